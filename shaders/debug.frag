@@ -0,0 +1,16 @@
+#version 150
+
+in vec4 Color;
+
+out vec4 out_color;
+
+uniform float gamma;
+uniform bool hdr_output;
+
+void main() {
+    if (hdr_output) {
+        out_color = Color;
+    } else {
+        out_color = vec4(pow(clamp(Color.rgb, 0.0, 1.0), vec3(1.0 / gamma)), Color.a);
+    }
+}
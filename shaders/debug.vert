@@ -0,0 +1,13 @@
+#version 150
+
+in vec3 position;
+in vec4 color;
+
+out vec4 Color;
+
+uniform mat4 view_proj;
+
+void main() {
+    Color = color;
+    gl_Position = view_proj * vec4(position, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 150
+
+#define CURVE_SAMPLES 16
+
+in vec3 position;
+in vec4 state;          // Age, lifetime, size, and rotation.
+
+out float Size;
+out float Rotation;
+out vec4 ParticleColor;
+out float Alive;
+
+uniform vec4 color;
+uniform float size_curve[CURVE_SAMPLES];
+uniform vec4 color_curve[CURVE_SAMPLES];
+
+void main() {
+    float progress = state.y > 0.0 ? clamp(state.x / state.y, 0.0, 1.0) : 1.0;
+    float x = progress * float(CURVE_SAMPLES - 1);
+    int i = min(int(x), CURVE_SAMPLES - 2);
+    float t = x - float(i);
+    Size = state.z * mix(size_curve[i], size_curve[i + 1], t);
+    Rotation = state.w;
+    ParticleColor = color * mix(color_curve[i], color_curve[i + 1], t);
+    Alive = state.x < state.y ? 1.0 : 0.0;
+    gl_Position = vec4(position, 1.0);
+}
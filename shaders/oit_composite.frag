@@ -0,0 +1,21 @@
+#version 150
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D accumulation;
+uniform sampler2D weight;
+
+// Resolves the weighted average of the transparent surfaces. The revealage (how much of the
+// opaque surface shows through) is stored in the accumulation alpha, so the result is blended on
+// top of the opaque scene with an alpha of 1 - revealage.
+void main() {
+    vec4 accum = texture(accumulation, TCoord);
+    float revealage = accum.a;
+    if (revealage >= 1.0) {
+        discard;
+    }
+    vec3 average = accum.rgb / max(texture(weight, TCoord).r, 0.00001);
+    out_color = vec4(average, 1.0 - revealage);
+}
@@ -0,0 +1,115 @@
+#version 150
+
+#define CURVE_SAMPLES 16
+#define PI 3.14159265359
+
+// Emitter shapes, matching GpuParticleSystem.
+#define SHAPE_POINT 0
+#define SHAPE_SPHERE 1
+#define SHAPE_CONE 2
+
+in vec3 position;
+in vec3 velocity;
+in vec4 state;          // Age, lifetime, size, and rotation.
+in float spin;
+
+out vec3 out_position;
+out vec3 out_velocity;
+out vec4 out_state;
+out float out_spin;
+
+uniform float dt;
+uniform uint seed;
+uniform int capacity;
+uniform int spawn_start;
+uniform int spawn_count;
+uniform mat4 emitter;
+uniform int shape;
+uniform float shape_radius;
+uniform float shape_angle;
+uniform vec2 lifetime_range;
+uniform vec2 speed_range;
+uniform vec2 size_range;
+uniform vec2 rotation_range;
+uniform vec2 spin_range;
+uniform vec3 gravity;
+uniform float drag;
+uniform float speed_curve[CURVE_SAMPLES];
+
+// Scrambles the bits of an integer.
+uint hash(uint x) {
+    x ^= x >> 16u;
+    x *= 0x7feb352du;
+    x ^= x >> 15u;
+    x *= 0x846ca68bu;
+    x ^= x >> 16u;
+    return x;
+}
+
+// Gets a random number in [0, 1) and advances the state.
+float random(inout uint rng) {
+    rng = hash(rng);
+    return float(rng >> 8u) / 16777216.0;
+}
+
+// Gets a random number in a (min, max) range.
+float random_range(inout uint rng, vec2 range) {
+    return mix(range.x, range.y, random(rng));
+}
+
+// Gets a direction around +Z within an angle of it, where an angle of PI points anywhere.
+vec3 random_direction(inout uint rng, float angle) {
+    float z = mix(cos(angle), 1.0, random(rng));
+    float phi = random(rng) * 2.0 * PI;
+    float ring = sqrt(max(1.0 - z * z, 0.0));
+    return vec3(ring * cos(phi), ring * sin(phi), z);
+}
+
+// Samples a curve over the lifetime of a particle with linear interpolation.
+float sample_curve(float curve[CURVE_SAMPLES], float t) {
+    float x = clamp(t, 0.0, 1.0) * float(CURVE_SAMPLES - 1);
+    int i = min(int(x), CURVE_SAMPLES - 2);
+    return mix(curve[i], curve[i + 1], x - float(i));
+}
+
+void main() {
+    // Particles in the run of slots starting at the spawn cursor are respawned, which recycles the
+    // oldest particles first when the buffer is full.
+    int slot = (gl_VertexID - spawn_start + capacity) % capacity;
+    if (slot < spawn_count) {
+        uint rng = hash(uint(gl_VertexID) ^ hash(seed));
+        vec3 offset = vec3(0.0);
+        vec3 dir;
+        if (shape == SHAPE_CONE) {
+            float r = shape_radius * sqrt(random(rng));
+            float theta = random(rng) * 2.0 * PI;
+            offset = vec3(r * cos(theta), r * sin(theta), 0.0);
+            dir = random_direction(rng, shape_angle);
+        } else {
+            dir = random_direction(rng, PI);
+            if (shape == SHAPE_SPHERE) {
+                offset = dir * shape_radius * pow(random(rng), 1.0 / 3.0);
+            }
+        }
+        out_position = (emitter * vec4(offset, 1.0)).xyz;
+        out_velocity = mat3(emitter) * dir * random_range(rng, speed_range);
+        out_state = vec4(0.0, random_range(rng, lifetime_range), random_range(rng, size_range),
+                random_range(rng, rotation_range));
+        out_spin = random_range(rng, spin_range);
+        return;
+    }
+
+    vec3 p = position;
+    vec3 v = velocity;
+    vec4 s = state;
+    s.x += dt;
+    if (s.x < s.y) {
+        v = (v + gravity * dt) * max(1.0 - drag * dt, 0.0);
+        p += v * sample_curve(speed_curve, s.x / s.y) * dt;
+        s.w += spin * dt;
+    }
+    out_position = p;
+    out_velocity = v;
+    out_state = s;
+    out_spin = spin;
+}
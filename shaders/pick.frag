@@ -0,0 +1,9 @@
+#version 150
+
+out uint out_id;
+
+uniform uint object_id;
+
+void main() {
+    out_id = object_id;
+}
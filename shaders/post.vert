@@ -0,0 +1,10 @@
+#version 150
+
+// Full screen triangle generated from the vertex ID so that no vertex buffer is needed.
+out vec2 TCoord;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexID & 1) << 2) - 1.0, float((gl_VertexID & 2) << 1) - 1.0);
+    TCoord = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
@@ -0,0 +1,14 @@
+#version 150
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform sampler2D bloom;
+uniform float intensity;
+
+void main() {
+    vec4 scene_color = texture(source, TCoord);
+    out_color = vec4(scene_color.rgb + texture(bloom, TCoord).rgb * intensity, scene_color.a);
+}
@@ -0,0 +1,33 @@
+#version 150
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+uniform bool prefilter;
+uniform float threshold;
+uniform float knee;
+
+// Removes everything below the bloom threshold with a quadratic falloff of width knee.
+vec3 apply_threshold(vec3 color) {
+    float brightness = max(color.r, max(color.g, color.b));
+    float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    return color * max(soft, brightness - threshold) / max(brightness, 0.00001);
+}
+
+// Dual filter downsample that weights the center twice as much as the four diagonal taps.
+void main() {
+    vec3 color = texture(source, TCoord).rgb * 4.0;
+    color += texture(source, TCoord + vec2(-1.0, -1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(1.0, -1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(-1.0, 1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(1.0, 1.0) * texel_size).rgb;
+    color /= 8.0;
+    if (prefilter) {
+        color = apply_threshold(color);
+    }
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,48 @@
+#version 150
+
+#define FXAA_SPAN_MAX 8.0
+#define FXAA_REDUCE_MUL (1.0 / 8.0)
+#define FXAA_REDUCE_MIN (1.0 / 128.0)
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// Perceptual luma of a color. The square root approximates gamma encoding since the source is in
+// linear space.
+float luma(vec3 color) {
+    return sqrt(dot(clamp(color, 0.0, 1.0), vec3(0.299, 0.587, 0.114)));
+}
+
+// Fast approximate anti-aliasing that blurs along the direction of the local luma gradient.
+void main() {
+    float luma_nw = luma(texture(source, TCoord + vec2(-1.0, -1.0) * texel_size).rgb);
+    float luma_ne = luma(texture(source, TCoord + vec2(1.0, -1.0) * texel_size).rgb);
+    float luma_sw = luma(texture(source, TCoord + vec2(-1.0, 1.0) * texel_size).rgb);
+    float luma_se = luma(texture(source, TCoord + vec2(1.0, 1.0) * texel_size).rgb);
+    vec4 center = texture(source, TCoord);
+    float luma_m = luma(center.rgb);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+            (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * FXAA_REDUCE_MUL),
+            FXAA_REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * texel_size;
+
+    vec3 near = 0.5 * (texture(source, TCoord + dir * (1.0 / 3.0 - 0.5)).rgb +
+            texture(source, TCoord + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 far = near * 0.5 + 0.25 * (texture(source, TCoord + dir * -0.5).rgb +
+            texture(source, TCoord + dir * 0.5).rgb);
+    float luma_far = luma(far);
+    if (luma_far < luma_min || luma_far > luma_max) {
+        out_color = vec4(near, center.a);
+    } else {
+        out_color = vec4(far, center.a);
+    }
+}
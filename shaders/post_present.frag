@@ -0,0 +1,13 @@
+#version 150
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform float gamma;
+
+void main() {
+    vec4 linear_color = clamp(texture(source, TCoord), 0.0, 1.0);
+    out_color = vec4(pow(linear_color.rgb, vec3(1.0 / gamma)), 1.0);
+}
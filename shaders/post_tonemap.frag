@@ -0,0 +1,28 @@
+#version 150
+
+#define REINHARD 0u
+#define ACES 1u
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform float exposure;
+uniform uint tonemapper;
+
+// Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    vec3 hdr_color = texture(source, TCoord).rgb * exposure;
+    vec3 ldr_color;
+    if (tonemapper == ACES) {
+        ldr_color = aces(hdr_color);
+    } else {
+        ldr_color = hdr_color / (1.0 + hdr_color);
+    }
+    out_color = vec4(ldr_color, 1.0);
+}
@@ -0,0 +1,22 @@
+#version 150
+
+in vec2 TCoord;
+
+out vec4 out_color;
+
+uniform sampler2D source;
+uniform vec2 texel_size;
+
+// 3x3 tent filter upsample. The result is additively blended into the larger level.
+void main() {
+    vec3 color = texture(source, TCoord).rgb * 4.0;
+    color += texture(source, TCoord + vec2(-1.0, 0.0) * texel_size).rgb * 2.0;
+    color += texture(source, TCoord + vec2(1.0, 0.0) * texel_size).rgb * 2.0;
+    color += texture(source, TCoord + vec2(0.0, -1.0) * texel_size).rgb * 2.0;
+    color += texture(source, TCoord + vec2(0.0, 1.0) * texel_size).rgb * 2.0;
+    color += texture(source, TCoord + vec2(-1.0, -1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(1.0, -1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(-1.0, 1.0) * texel_size).rgb;
+    color += texture(source, TCoord + vec2(1.0, 1.0) * texel_size).rgb;
+    out_color = vec4(color / 16.0, 1.0);
+}
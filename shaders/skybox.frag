@@ -0,0 +1,19 @@
+#version 150
+
+in vec3 Direction;
+
+out vec4 out_color;
+
+uniform samplerCube sky;
+uniform float exposure;
+uniform float gamma;
+uniform bool hdr_output;
+
+void main() {
+    vec3 sky_color = texture(sky, normalize(Direction)).rgb * exposure;
+    if (hdr_output) {
+        out_color = vec4(sky_color, 1.0);
+    } else {
+        out_color = vec4(pow(clamp(sky_color, 0.0, 1.0), vec3(1.0 / gamma)), 1.0);
+    }
+}
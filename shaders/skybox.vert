@@ -0,0 +1,14 @@
+#version 150
+
+// Full screen triangle at the far plane. The direction of each pixel is reconstructed with the
+// inverse of the camera's rotation and projection so that the sky appears infinitely far away.
+out vec3 Direction;
+
+uniform mat4 inv_view_proj;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexID & 1) << 2) - 1.0, float((gl_VertexID & 2) << 1) - 1.0);
+    vec4 world = inv_view_proj * vec4(position, 1.0, 1.0);
+    Direction = world.xyz / world.w;
+    gl_Position = vec4(position, 1.0, 1.0);
+}
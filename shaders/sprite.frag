@@ -0,0 +1,44 @@
+#version 150
+
+in vec2 TCoord;
+in vec4 Color;
+
+out vec4 out_color;
+
+uniform sampler2D sprite;
+uniform float gamma;
+uniform bool hdr_output;
+uniform bool use_soft_depth;
+uniform sampler2D depth_map;
+uniform vec2 viewport_size;
+uniform float near;
+uniform float far;
+uniform bool orthographic;
+uniform float soft_distance;
+
+// Converts a depth buffer value to the distance from the camera.
+float linear_depth(float depth) {
+    if (orthographic) {
+        return near + depth * (far - near);
+    }
+    float ndc = depth * 2.0 - 1.0;
+    return 2.0 * near * far / (far + near - ndc * (far - near));
+}
+
+void main() {
+    vec4 texel = texture(sprite, TCoord);
+    vec4 color = vec4(Color.rgb * texel.rgb, Color.a * texel.a);
+    if (use_soft_depth) {
+        float scene_depth = linear_depth(texture(depth_map, gl_FragCoord.xy / viewport_size).r);
+        float gap = scene_depth - linear_depth(gl_FragCoord.z);
+        color.a *= clamp(gap / soft_distance, 0.0, 1.0);
+    }
+    if (color.a <= 0.0) {
+        discard;
+    }
+    if (hdr_output) {
+        out_color = color;
+    } else {
+        out_color = vec4(pow(clamp(color.rgb, 0.0, 1.0), vec3(1.0 / gamma)), color.a);
+    }
+}
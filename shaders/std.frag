@@ -6,6 +6,11 @@
 #define DIRECTIONAL_LIGHT 2u
 #define SPOT_LIGHT 3u
 
+#define BLINN_PHONG 0u
+#define METALLIC_ROUGHNESS 1u
+
+#define PI 3.14159265359
+
 // TODO: Add materials so these can be a uniforms.
 #define AMBIENT_COEFF 0.03
 #define SPECULAR_COLOR 1.0
@@ -14,8 +19,12 @@ in vec3 Normal;
 in mat3 TBN;
 in vec3 Vert;
 in vec2 TCoord;
+in vec3 WorldPos;
+in vec3 WorldNormal;
+in vec4 InstanceColor;
 
 out vec4 out_color;
+out vec4 out_oit_weight;
 
 uniform struct Light {
     uint type;
@@ -27,6 +36,7 @@ uniform struct Light {
     float quad_attn;
     float cutoff;
     float dropoff;
+    uint culling_mask;
 } lights[MAX_LIGHTS];
 
 uniform vec3 camera;
@@ -39,10 +49,221 @@ uniform sampler2D diffuse_map;
 uniform sampler2D specular_map;
 uniform sampler2D normal_map;
 uniform bool use_normal_map;
+uniform uint shading_model;
+uniform sampler2D metallic_roughness_map;
+uniform sampler2D occlusion_map;
+uniform float metallic;
+uniform float roughness;
+uniform bool use_burley_diffuse;
+uniform bool use_ibl;
+uniform float ibl_intensity;
+uniform samplerCube prefiltered_map;
+uniform float prefiltered_max_lod;
+uniform sampler2D brdf_lut;
+uniform vec3 irradiance_sh[9];
+uniform bool hdr_output;
+uniform bool oit_output;
+uniform float lod_fade;
+uniform uint layers;
+uniform bool use_splat;
+uniform sampler2D splat_map;
+uniform sampler2D splat_layers[4];
+uniform float splat_tiling;
+
+// Thresholds of a 4x4 ordered dither pattern.
+const float DITHER[16] = float[16](
+        0.0 / 16.0, 8.0 / 16.0, 2.0 / 16.0, 10.0 / 16.0,
+        12.0 / 16.0, 4.0 / 16.0, 14.0 / 16.0, 6.0 / 16.0,
+        3.0 / 16.0, 11.0 / 16.0, 1.0 / 16.0, 9.0 / 16.0,
+        15.0 / 16.0, 7.0 / 16.0, 13.0 / 16.0, 5.0 / 16.0);
+
+// Returns true if a light is attached and lights the render layers being drawn.
+bool lights_layers(Light light) {
+    return light.type != EMPTY_LIGHT && (light.culling_mask & layers) != 0u;
+}
+
+// Samples the diffuse map, or blends the splat layers by the weights in the channels of the splat
+// map for a splat material.
+vec4 sample_diffuse() {
+    if (!use_splat) {
+        return texture(diffuse_map, TCoord);
+    }
+    vec4 weights = texture(splat_map, TCoord);
+    vec2 tiled = TCoord * splat_tiling;
+    vec4 blended = texture(splat_layers[0], tiled) * weights.r +
+        texture(splat_layers[1], tiled) * weights.g +
+        texture(splat_layers[2], tiled) * weights.b +
+        texture(splat_layers[3], tiled) * weights.a;
+    return blended / max(dot(weights, vec4(1.0)), 1e-4);
+}
+
+// Gets the radiance arriving at a surface position from a light and writes the normalized
+// direction from the surface to the light.
+vec3 light_radiance(Light light, vec3 position, out vec3 surface_to_light) {
+    if (light.type == DIRECTIONAL_LIGHT) {
+        surface_to_light = -normalize(light.direction);
+        return light.intensity;
+    }
+    surface_to_light = normalize(light.position - position);
+    float dist = distance(position, light.position);
+    vec3 intensity = light.intensity /
+        (light.const_attn + light.linear_attn * dist + light.quad_attn * (dist * dist));
+    if (light.type == SPOT_LIGHT) {
+        float cos_dv = dot(normalize(light.direction), -surface_to_light);
+        intensity *= cos_dv > cos(light.cutoff) ? pow(cos_dv, light.dropoff) : 0.0;
+    }
+    return intensity;
+}
+
+// GGX (Trowbridge-Reitz) normal distribution function with alpha = roughness^2.
+float distribution_ggx(float n_dot_h, float alpha) {
+    float alpha2 = alpha * alpha;
+    float denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denom * denom);
+}
+
+// Smith geometry term using the Schlick-GGX approximation remapped for analytic lights.
+float geometry_smith(float n_dot_v, float n_dot_l, float rough) {
+    float k = (rough + 1.0) * (rough + 1.0) / 8.0;
+    float g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+// Schlick's approximation of the Fresnel reflectance.
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Schlick's approximation of the Fresnel reflectance for image-based lighting, which accounts for
+// rough surfaces reflecting less at grazing angles.
+vec3 fresnel_schlick_roughness(float cos_theta, vec3 f0, float rough) {
+    return f0 + (max(vec3(1.0 - rough), f0) - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Evaluates the diffuse irradiance around a normal from the cosine convolved spherical harmonic
+// coefficients of the environment.
+vec3 sh_irradiance(vec3 n) {
+    return irradiance_sh[0] * 0.282095 +
+        irradiance_sh[1] * 0.488603 * n.y +
+        irradiance_sh[2] * 0.488603 * n.z +
+        irradiance_sh[3] * 0.488603 * n.x +
+        irradiance_sh[4] * 1.092548 * n.x * n.y +
+        irradiance_sh[5] * 1.092548 * n.y * n.z +
+        irradiance_sh[6] * 0.315392 * (3.0 * n.z * n.z - 1.0) +
+        irradiance_sh[7] * 1.092548 * n.x * n.z +
+        irradiance_sh[8] * 0.546274 * (n.x * n.x - n.y * n.y);
+}
+
+// Burley's (Disney) diffuse term, already divided by pi like the Lambertian term.
+float burley_diffuse(float n_dot_v, float n_dot_l, float l_dot_h, float rough) {
+    float f90 = 0.5 + 2.0 * rough * l_dot_h * l_dot_h;
+    float light_scatter = 1.0 + (f90 - 1.0) * pow(1.0 - n_dot_l, 5.0);
+    float view_scatter = 1.0 + (f90 - 1.0) * pow(1.0 - n_dot_v, 5.0);
+    return light_scatter * view_scatter / PI;
+}
+
+// Shades the fragment with the physically based metallic-roughness model. The diffuse term is
+// scaled by (1 - F) and by (1 - metallic) so that the sum of the reflected light never exceeds
+// the incoming light.
+vec4 shade_metallic_roughness(vec4 base_color, vec3 world_normal) {
+    vec4 albedo_sample = sample_diffuse();
+    vec3 albedo = base_color.rgb * albedo_sample.rgb;
+    vec4 mr_sample = texture(metallic_roughness_map, TCoord);
+    float rough = clamp(roughness * mr_sample.g, 0.04, 1.0);
+    float metal = clamp(metallic * mr_sample.b, 0.0, 1.0);
+    float occlusion = texture(occlusion_map, TCoord).r;
+
+    vec3 position = WorldPos;
+    vec3 surface_to_camera = normalize(camera - position);
+    float n_dot_v = max(dot(world_normal, surface_to_camera), 1e-4);
+    vec3 f0 = mix(vec3(0.04), albedo, metal);
+
+    vec3 total;
+    if (use_ibl) {
+        // Split-sum approximation of the environment lighting.
+        vec3 fresnel = fresnel_schlick_roughness(n_dot_v, f0, rough);
+        vec3 k_d = (1.0 - fresnel) * (1.0 - metal);
+        vec3 diffuse = k_d * albedo * max(sh_irradiance(world_normal), 0.0) / PI;
+        vec3 reflected = reflect(-surface_to_camera, world_normal);
+        vec3 prefiltered =
+            textureLod(prefiltered_map, reflected, rough * prefiltered_max_lod).rgb;
+        vec2 brdf = texture(brdf_lut, vec2(n_dot_v, rough)).rg;
+        vec3 specular = prefiltered * (f0 * brdf.x + brdf.y);
+        total = (diffuse + specular) * occlusion * ibl_intensity;
+    } else {
+        total = AMBIENT_COEFF * albedo * occlusion;
+    }
+    for (int i = 0; i < MAX_LIGHTS; i++) {
+        Light light = lights[i];
+        if (!lights_layers(light)) {
+            continue;
+        }
+        vec3 surface_to_light;
+        vec3 radiance = light_radiance(light, position, surface_to_light);
+        float n_dot_l = max(dot(world_normal, surface_to_light), 0.0);
+        if (n_dot_l <= 0.0) {
+            continue;
+        }
+        vec3 halfway = normalize(surface_to_light + surface_to_camera);
+        float n_dot_h = max(dot(world_normal, halfway), 0.0);
+        float l_dot_h = max(dot(surface_to_light, halfway), 0.0);
+
+        vec3 fresnel = fresnel_schlick(max(dot(halfway, surface_to_camera), 0.0), f0);
+        float d = distribution_ggx(n_dot_h, rough * rough);
+        float g = geometry_smith(n_dot_v, n_dot_l, rough);
+        vec3 specular = d * g * fresnel / (4.0 * n_dot_v * n_dot_l);
+
+        vec3 k_d = (1.0 - fresnel) * (1.0 - metal);
+        float diffuse_term = use_burley_diffuse ?
+            burley_diffuse(n_dot_v, n_dot_l, l_dot_h, rough) : 1.0 / PI;
+        total += (k_d * albedo * diffuse_term + specular) * radiance * n_dot_l;
+    }
+    return vec4(total, base_color.a * albedo_sample.a);
+}
+
+// Encodes a linear color for output. When rendering into a post-processing target the HDR values
+// are written as-is, otherwise they are clamped and gamma corrected for display.
+vec4 encode_output(vec4 linear_color) {
+    if (hdr_output) {
+        return vec4(max(linear_color.rgb, 0.0), clamp(linear_color.a, 0.0, 1.0));
+    }
+    vec4 display_color = clamp(linear_color, 0.0, 1.0);
+    return vec4(pow(display_color.rgb, vec3(1.0 / gamma)), display_color.a);
+}
+
+// Writes a linear color to the outputs. For weighted blended order-independent transparency, the
+// premultiplied color scaled by a depth based weight and the alpha are written to the first output
+// while the weighted alpha is written to the second. See McGuire and Bavoil 2013.
+void write_output(vec4 linear_color) {
+    if (oit_output) {
+        float alpha = clamp(linear_color.a, 0.0, 1.0);
+        float weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 *
+                pow(1.0 - gl_FragCoord.z * 0.9, 3.0), 1e-2, 3e3);
+        out_color = vec4(max(linear_color.rgb, 0.0) * alpha * weight, alpha);
+        out_oit_weight = vec4(alpha * weight);
+        return;
+    }
+    out_color = encode_output(linear_color);
+}
 
 void main() {
+    // Cross-fading levels of detail are drawn with complementary dither patterns. A positive fade
+    // keeps that fraction of the pixels and a negative fade keeps the pixels that the positive fade
+    // of the same amount would discard. A fade of 0.0 draws every pixel.
+    if (lod_fade != 0.0) {
+        ivec2 cell = ivec2(mod(gl_FragCoord.xy, 4.0));
+        float threshold = DITHER[cell.y * 4 + cell.x];
+        if (lod_fade > 0.0 ? threshold >= lod_fade : threshold < -lod_fade) {
+            discard;
+        }
+    }
+
+    // Instances can tint the material color.
+    vec4 base_color = color * InstanceColor;
+
     // Ambient light.
-    vec4 total_color = vec4(AMBIENT_COEFF * color.rgb * texture(diffuse_map, TCoord).rgb, 0.0);
+    vec4 total_color = vec4(AMBIENT_COEFF * base_color.rgb * sample_diffuse().rgb, 0.0);
 
     // Transform normal map to world space.
     vec3 world_normal;
@@ -51,13 +272,18 @@ void main() {
         world_normal = normalize(world_normal * 2.0 - 1.0);
         world_normal = normalize(TBN * world_normal);
     } else {
-        world_normal = normalize(mat3(normal_matrix) * Normal);
+        world_normal = normalize(WorldNormal);
     }
     // world_normal = normalize(mat3(normal_matrix) * (texture(normal_map, TCoord).rgb - 0.5) * 2);
 
+    if (shading_model == METALLIC_ROUGHNESS) {
+        write_output(shade_metallic_roughness(base_color, world_normal));
+        return;
+    }
+
     Light light = lights[7];
-    if (light.type != EMPTY_LIGHT) {
-        vec3 position = vec3(model * vec4(Vert, 1.0));
+    if (lights_layers(light)) {
+        vec3 position = WorldPos;
         vec3 surface_to_light;
         vec3 intensity;
 
@@ -83,8 +309,8 @@ void main() {
 
         // Get diffuse lighting.
         float cos_nl = max(dot(surface_to_light, world_normal), 0.0);
-        vec4 diffuse = vec4(cos_nl * intensity * color.rgb *
-                texture(diffuse_map, TCoord).rgb, color.a);
+        vec4 diffuse = vec4(cos_nl * intensity * base_color.rgb *
+                sample_diffuse().rgb, base_color.a);
 
         // Get specular lighting.
         vec4 specular = vec4(0, 0, 0, 0);
@@ -100,8 +326,8 @@ void main() {
     }
 
     light = lights[6];
-    if (light.type != EMPTY_LIGHT) {
-        vec3 position = vec3(model * vec4(Vert, 1.0));
+    if (lights_layers(light)) {
+        vec3 position = WorldPos;
         vec3 surface_to_light;
         vec3 intensity;
 
@@ -127,8 +353,8 @@ void main() {
 
         // Get diffuse lighting.
         float cos_nl = max(dot(surface_to_light, world_normal), 0.0);
-        vec4 diffuse = vec4(cos_nl * intensity * color.rgb *
-                texture(diffuse_map, TCoord).rgb, color.a);
+        vec4 diffuse = vec4(cos_nl * intensity * base_color.rgb *
+                sample_diffuse().rgb, base_color.a);
 
         // Get specular lighting.
         vec4 specular = vec4(0, 0, 0, 0);
@@ -143,6 +369,5 @@ void main() {
         total_color += diffuse + specular;
     }
 
-    vec4 final_color = clamp(total_color, 0.0, 1.0);
-    out_color = vec4(pow(final_color.rgb, vec3(1.0 / gamma)), final_color.a);
+    write_output(vec4(total_color.rgb, base_color.a * sample_diffuse().a));
 }
@@ -1,27 +1,64 @@
 #version 150
 
+#define MAX_JOINTS 64
+
 in vec3 position;
 in vec3 normal;
 in vec3 tangent;
 in vec3 bitangent;
 in vec2 tcoord;
 
+// Per-instance attributes used when drawing an InstanceBatch.
+in mat4 instance_model;
+in mat3 instance_normal;
+in vec4 instance_color;
+in vec4 instance_data;
+
+// Per-vertex joint indices and weights used when drawing a skinned mesh.
+in vec4 joints;
+in vec4 weights;
+
 out vec3 Normal;
 out mat3 TBN;
 out vec3 Vert;
 out vec2 TCoord;
+out vec3 WorldPos;
+out vec3 WorldNormal;
+out vec4 InstanceColor;
+out vec4 InstanceData;
 
+uniform mat4 model;
 uniform mat4 normal_matrix;
 uniform mat4 transform;
+uniform mat4 view_proj;
+uniform bool use_instancing;
+uniform bool use_skinning;
+uniform mat4 joint_matrices[MAX_JOINTS];
 
 void main() {
+    // Skinning moves the vertex within the model's space before the model matrix is applied.
+    mat4 skin = mat4(1.0);
+    if (use_skinning) {
+        skin = weights.x * joint_matrices[int(joints.x)] +
+            weights.y * joint_matrices[int(joints.y)] +
+            weights.z * joint_matrices[int(joints.z)] +
+            weights.w * joint_matrices[int(joints.w)];
+    }
+    mat4 model_mat = (use_instancing ? instance_model : model) * skin;
+    mat3 normal_mat = (use_instancing ? instance_normal : mat3(normal_matrix)) * mat3(skin);
+
     // TODO: Orthognalize TBN.
     Normal = normal;
-    vec3 T = normalize(vec3(normal_matrix * vec4(tangent, 0.0)));
-    vec3 B = normalize(vec3(normal_matrix * vec4(bitangent, 0.0)));
-    vec3 N = normalize(vec3(normal_matrix * vec4(normal, 0.0)));
+    vec3 T = normalize(normal_mat * tangent);
+    vec3 B = normalize(normal_mat * bitangent);
+    vec3 N = normalize(normal_mat * normal);
     TBN = mat3(T, B, N);
     TCoord = tcoord;
     Vert = position;
-    gl_Position = transform * vec4(position, 1.0);
+    WorldNormal = N;
+    vec4 world = model_mat * vec4(position, 1.0);
+    WorldPos = vec3(world);
+    InstanceColor = use_instancing ? instance_color : vec4(1.0);
+    InstanceData = use_instancing ? instance_data : vec4(0.0);
+    gl_Position = use_instancing ? view_proj * world : transform * skin * vec4(position, 1.0);
 }
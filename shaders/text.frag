@@ -0,0 +1,23 @@
+#version 150
+
+in vec2 TCoord;
+in vec4 Color;
+
+out vec4 out_color;
+
+uniform sampler2D glyphs;
+uniform float gamma;
+uniform bool hdr_output;
+
+void main() {
+    vec4 texel = texture(glyphs, TCoord);
+    vec4 color = vec4(Color.rgb * texel.rgb, Color.a * texel.a);
+    if (color.a <= 0.0) {
+        discard;
+    }
+    if (hdr_output) {
+        out_color = color;
+    } else {
+        out_color = vec4(pow(clamp(color.rgb, 0.0, 1.0), vec3(1.0 / gamma)), color.a);
+    }
+}
@@ -0,0 +1,16 @@
+#version 150
+
+in vec3 position;
+in vec2 tcoord;
+in vec4 color;
+
+out vec2 TCoord;
+out vec4 Color;
+
+uniform mat4 view_proj;
+
+void main() {
+    TCoord = tcoord;
+    Color = color;
+    gl_Position = view_proj * vec4(position, 1.0);
+}
@@ -0,0 +1,200 @@
+// Defines an AnimationClip, which is a set of keyframed channels that animate the joints of a
+// Skeleton over time. Like glTF, each channel keys the translation, rotation, and scale of a single
+// joint separately, and each of those has its own key times. Values between keys are linearly
+// interpolated, with rotations taking the shortest path. A clip can also key the weights of the
+// morph targets of a Morph, such as for facial animation.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use anim::skeleton::Pose;
+use gfx::types::*;
+use std::cmp;
+
+// A value that can be keyed and interpolated between keys.
+pub trait Keyframe: Copy {
+    // Interpolates from a to b, where t goes from 0.0 at a to 1.0 at b.
+    fn interpolate(a: Self, b: Self, t: GLfloat) -> Self;
+}
+
+impl Keyframe for GLfloat {
+    fn interpolate(a: GLfloat, b: GLfloat, t: GLfloat) -> GLfloat {
+        a + (b - a) * t
+    }
+}
+
+impl Keyframe for Vector3D {
+    fn interpolate(a: Vector3D, b: Vector3D, t: GLfloat) -> Vector3D {
+        a + (b - a) * t
+    }
+}
+
+impl Keyframe for Quaternion {
+    fn interpolate(a: Quaternion, b: Quaternion, t: GLfloat) -> Quaternion {
+        slerp(a, b, t)
+    }
+}
+
+// Spherically interpolates between two normalized rotations along the shortest path.
+pub fn slerp(a: Quaternion, b: Quaternion, t: GLfloat) -> Quaternion {
+    let mut dot = a.dot(b);
+    let b = if dot < 0.0 { dot = -dot; -b } else { b };
+    if dot > 0.9995 {
+        return (a * (1.0 - t) + b * t).normalize();
+    }
+    let theta = dot.min(1.0).acos();
+    let sin_theta = theta.sin();
+    (a * ((1.0 - t) * theta).sin() + b * (t * theta).sin()) * (1.0 / sin_theta)
+}
+
+// Values at increasing times.
+#[derive(Clone, Debug)]
+pub struct Keyframes<T: Keyframe> {
+    pub times: Vec<GLfloat>,
+    pub values: Vec<T>,
+}
+
+impl<T: Keyframe> Keyframes<T> {
+    // Creates keyframes from times and values of the same length. Returns an Err if the lengths
+    // differ, there are no keys, or the times aren't increasing.
+    pub fn new(times: Vec<GLfloat>, values: Vec<T>) -> Result<Keyframes<T>, String> {
+        if times.len() != values.len() {
+            return Err("Keyframes need a value for every time.".to_string());
+        }
+        if times.is_empty() {
+            return Err("Keyframes need at least one key.".to_string());
+        }
+        if times.windows(2).any(|w| w[1] < w[0]) {
+            return Err("Keyframe times must be increasing.".to_string());
+        }
+        Ok(Keyframes { times: times, values: values })
+    }
+
+    // Gets the time of the last key.
+    pub fn get_end_time(&self) -> GLfloat {
+        *self.times.last().unwrap()
+    }
+
+    // Finds the key at or before a time and how far the time is towards the next key from 0.0 to
+    // 1.0. Times outside of the keys are clamped to the first or last key.
+    pub fn locate(&self, time: GLfloat) -> (usize, GLfloat) {
+        let last = self.times.len() - 1;
+        // This also catches a NaN time.
+        if !(time > self.times[0]) { return (0, 0.0); }
+        if time >= self.times[last] { return (last, 0.0); }
+        // The index of the first key after the time, which is at least 1 here.
+        let search = self.times.binary_search_by(|t| {
+            t.partial_cmp(&time).unwrap_or(cmp::Ordering::Less)
+        });
+        let next = match search {
+            Ok(i) => { return (i, 0.0); },
+            Err(i) => i,
+        };
+        let span = self.times[next] - self.times[next - 1];
+        (next - 1, if span > 0.0 { (time - self.times[next - 1]) / span } else { 0.0 })
+    }
+
+    // Gets the interpolated value at a time.
+    pub fn sample(&self, time: GLfloat) -> T {
+        let (i, t) = self.locate(time);
+        if t == 0.0 { return self.values[i]; }
+        T::interpolate(self.values[i], self.values[i + 1], t)
+    }
+}
+
+// The keyframes that animate a single joint. Parts of the transform without keyframes are left as
+// they are in the pose being sampled into.
+#[derive(Clone, Debug)]
+pub struct JointChannel {
+    pub joint: usize,
+    pub translation: Option<Keyframes<Vector3D>>,
+    pub rotation: Option<Keyframes<Quaternion>>,
+    pub scale: Option<Keyframes<Vector3D>>,
+}
+
+impl JointChannel {
+    // Creates a channel for a joint that doesn't animate anything yet.
+    pub fn new(joint: usize) -> JointChannel {
+        JointChannel { joint: joint, translation: None, rotation: None, scale: None }
+    }
+
+    // Gets the time of the last key of any part of the channel.
+    pub fn get_end_time(&self) -> GLfloat {
+        let t = self.translation.as_ref().map_or(0.0, |k| k.get_end_time());
+        let r = self.rotation.as_ref().map_or(0.0, |k| k.get_end_time());
+        let s = self.scale.as_ref().map_or(0.0, |k| k.get_end_time());
+        t.max(r).max(s)
+    }
+}
+
+// The keyframes that animate the weight of a single morph target.
+#[derive(Clone, Debug)]
+pub struct MorphChannel {
+    pub target: usize,
+    pub weights: Keyframes<GLfloat>,
+}
+
+impl MorphChannel {
+    // Creates a channel for a morph target.
+    pub fn new(target: usize, weights: Keyframes<GLfloat>) -> MorphChannel {
+        MorphChannel { target: target, weights: weights }
+    }
+}
+
+// A named animation of a skeleton and morph target weights. The duration is the time of the last
+// key of any channel.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: GLfloat,
+    pub channels: Vec<JointChannel>,
+    pub morph_channels: Vec<MorphChannel>,
+}
+
+impl AnimationClip {
+    // Creates a clip from its joint channels.
+    pub fn new(name: &str, channels: Vec<JointChannel>) -> AnimationClip {
+        AnimationClip::with_morphs(name, channels, Vec::new())
+    }
+
+    // Creates a clip from its joint and morph target channels.
+    pub fn with_morphs(name: &str, channels: Vec<JointChannel>,
+            morph_channels: Vec<MorphChannel>) -> AnimationClip {
+        let duration = channels.iter().fold(0.0, |d: GLfloat, c| d.max(c.get_end_time()));
+        let duration = morph_channels.iter()
+                .fold(duration, |d: GLfloat, c| d.max(c.weights.get_end_time()));
+        AnimationClip { name: name.to_string(), duration: duration, channels: channels,
+                morph_channels: morph_channels }
+    }
+
+    // Writes the keyed parts of every joint at a time into a pose. Channels for joints that aren't
+    // in the pose are skipped.
+    pub fn sample(&self, time: GLfloat, pose: &mut Pose) {
+        for channel in &self.channels {
+            let local = match pose.locals.get_mut(channel.joint) {
+                Some(local) => local,
+                None => { continue; },
+            };
+            if let Some(ref keys) = channel.translation {
+                local.pos = keys.sample(time);
+            }
+            if let Some(ref keys) = channel.rotation {
+                local.rot = keys.sample(time);
+            }
+            if let Some(ref keys) = channel.scale {
+                local.scale = keys.sample(time);
+            }
+        }
+    }
+    // Writes the keyed morph target weights at a time into a list of weights. Channels for
+    // targets past the end of the list are skipped.
+    pub fn sample_weights(&self, time: GLfloat, weights: &mut [GLfloat]) {
+        for channel in &self.morph_channels {
+            if let Some(weight) = weights.get_mut(channel.target) {
+                *weight = channel.weights.sample(time);
+            }
+        }
+    }
+}
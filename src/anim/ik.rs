@@ -0,0 +1,236 @@
+// Defines inverse kinematics constraints, which adjust a sampled Pose so that joints reach targets
+// in the mesh's space, such as planting feet on uneven ground, reaching a hand towards a handle, or
+// turning a head towards a point of interest. Constraints run in order as a post-process after the
+// clips are blended, so each one sees the result of the ones before it.
+//
+// TwoBoneIk solves a three joint chain such as a hip, knee, and ankle analytically. The law of
+// cosines gives where the middle joint has to be for the end to land on the target, and the side
+// it bends to comes from a pole target, which says which way the knee or elbow points. The root
+// and middle joints are then turned by the smallest rotations that put the joints there. LookAt
+// rotates a single joint so that one of its local axes points at the target.
+//
+// Only joint rotations are changed, so chains keep their bone lengths, and targets out of reach
+// are reached for as far as the chain allows. Joints are assumed to have uniform scale.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use anim::clip;
+use anim::skeleton::{Pose, Skeleton};
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Rotation3, Vector};
+
+// How close vectors can get to zero or parallel before they are treated as degenerate.
+const EPSILON: GLfloat = 1e-5;
+
+// An analytic solver for a chain of three joints where the end should reach a target. The joints
+// must be a root, its child, and the child's child, and the target and pole are in the mesh's
+// space. Without a pole, the chain keeps bending in the plane it is already bent in.
+#[derive(Clone, Debug)]
+pub struct TwoBoneIk {
+    pub root: usize,
+    pub mid: usize,
+    pub end: usize,
+    pub target: Vector3D,
+    pub pole: Option<Vector3D>,
+    pub weight: GLfloat,
+}
+
+impl TwoBoneIk {
+    // Creates a solver for a chain with full weight and no pole.
+    pub fn new(root: usize, mid: usize, end: usize, target: Vector3D) -> TwoBoneIk {
+        TwoBoneIk { root: root, mid: mid, end: end, target: target, pole: None, weight: 1.0 }
+    }
+
+    // Bends the chain in a pose towards the target.
+    pub fn solve(&self, skeleton: &Skeleton, pose: &mut Pose) {
+        if self.weight <= 0.0 { return; }
+        let positions = world_positions(skeleton, pose);
+        let rotations = world_rotations(skeleton, pose);
+        let (a, b, c) = (positions[self.root], positions[self.mid], positions[self.end]);
+        let (len_ab, len_bc) = ((b - a).length(), (c - b).length());
+        if len_ab < EPSILON || len_bc < EPSILON { return; }
+        let dir = match normalize_checked(self.target - a) {
+            Some(dir) => dir,
+            None => { return; },
+        };
+        let len_at = (self.target - a).length()
+                .max((len_ab - len_bc).abs() + EPSILON).min(len_ab + len_bc - EPSILON);
+
+        // Find where the middle and end joints should be. The middle joint is moved off of the
+        // line to the target towards the pole, or the way the chain is already bent.
+        let hint = match self.pole {
+            Some(pole) => pole - a,
+            None => b - a,
+        };
+        let bend = normalize_checked(hint - dir * hint.dot(dir))
+                .or(normalize_checked(dir.cross(Vector3D::unit_x())))
+                .unwrap_or(Vector3D::unit_y());
+        let angle = law_of_cosines(len_ab, len_at, len_bc);
+        let new_b = a + (dir * angle.cos() + bend * angle.sin()) * len_ab;
+        let new_c = a + dir * len_at;
+
+        // Turn the root so the middle joint lands in place, and then the middle joint so the end
+        // does.
+        let turn_root = rotation_between(b - a, new_b - a);
+        let turn_mid = rotation_between(turn_root * (c - b), new_c - new_b);
+        let parent_rot = match skeleton.joints[self.root].parent {
+            Some(parent) => rotations[parent],
+            None => Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let root_world = turn_root * rotations[self.root];
+        let mid_world = turn_mid * turn_root * rotations[self.mid];
+        let root_local = (parent_rot.conjugate() * root_world).normalize();
+        let mid_local = (root_world.conjugate() * mid_world).normalize();
+        blend_rotation(pose, self.root, root_local, self.weight);
+        blend_rotation(pose, self.mid, mid_local, self.weight);
+    }
+}
+
+// Rotates a joint so that one of its local axes points at a target in the mesh's space. The
+// rotation can be limited to a maximum angle in degrees from the animated pose, such as to keep a
+// head from turning all the way around.
+#[derive(Clone, Debug)]
+pub struct LookAt {
+    pub joint: usize,
+    pub axis: Vector3D,
+    pub target: Vector3D,
+    pub max_angle: Option<GLfloat>,
+    pub weight: GLfloat,
+}
+
+impl LookAt {
+    // Creates a constraint with full weight and no angle limit that aims a local axis of a joint.
+    pub fn new(joint: usize, axis: Vector3D, target: Vector3D) -> LookAt {
+        LookAt { joint: joint, axis: axis, target: target, max_angle: None, weight: 1.0 }
+    }
+
+    // Turns the joint in a pose towards the target.
+    pub fn solve(&self, skeleton: &Skeleton, pose: &mut Pose) {
+        if self.weight <= 0.0 { return; }
+        let positions = world_positions(skeleton, pose);
+        let rotations = world_rotations(skeleton, pose);
+        let current = rotations[self.joint] * self.axis;
+        let desired = self.target - positions[self.joint];
+        let mut turn = rotation_between(current, desired);
+        if let Some(max_angle) = self.max_angle {
+            let angle = 2.0 * turn.s.max(-1.0).min(1.0).acos();
+            let limit = max_angle.to_radians();
+            if angle > limit {
+                turn = clip::slerp(Quaternion::new(1.0, 0.0, 0.0, 0.0), turn, limit / angle);
+            }
+        }
+        let parent_rot = match skeleton.joints[self.joint].parent {
+            Some(parent) => rotations[parent],
+            None => Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let local = (parent_rot.conjugate() * turn * rotations[self.joint]).normalize();
+        blend_rotation(pose, self.joint, local, self.weight);
+    }
+}
+
+// A constraint applied to a pose after the clips are blended.
+#[derive(Clone, Debug)]
+pub enum Constraint {
+    TwoBone(TwoBoneIk),
+    LookAt(LookAt),
+}
+
+impl Constraint {
+    // Applies the constraint to a pose. Constraints with joints outside of the skeleton are
+    // skipped.
+    pub fn solve(&self, skeleton: &Skeleton, pose: &mut Pose) {
+        let len = skeleton.len().min(pose.locals.len());
+        match *self {
+            Constraint::TwoBone(ref ik) => {
+                if ik.root < len && ik.mid < len && ik.end < len { ik.solve(skeleton, pose); }
+            },
+            Constraint::LookAt(ref look) => {
+                if look.joint < len { look.solve(skeleton, pose); }
+            },
+        }
+    }
+
+    // Gets the weight of the constraint.
+    pub fn get_weight(&self) -> GLfloat {
+        match *self {
+            Constraint::TwoBone(ref ik) => ik.weight,
+            Constraint::LookAt(ref look) => look.weight,
+        }
+    }
+
+    // Sets the weight of the constraint, such as to fade foot placement out while jumping.
+    pub fn set_weight(&mut self, weight: GLfloat) {
+        match *self {
+            Constraint::TwoBone(ref mut ik) => { ik.weight = weight; },
+            Constraint::LookAt(ref mut look) => { look.weight = weight; },
+        }
+    }
+}
+
+// Applies constraints to a pose in order.
+pub fn apply_constraints(skeleton: &Skeleton, pose: &mut Pose, constraints: &[Constraint]) {
+    for constraint in constraints {
+        constraint.solve(skeleton, pose);
+    }
+}
+
+// Gets the position of every joint in the mesh's space.
+fn world_positions(skeleton: &Skeleton, pose: &Pose) -> Vec<Vector3D> {
+    skeleton.compute_world(pose).iter().map(|m| m.w.truncate()).collect()
+}
+
+// Gets the rotation of every joint in the mesh's space.
+fn world_rotations(skeleton: &Skeleton, pose: &Pose) -> Vec<Quaternion> {
+    let mut rotations: Vec<Quaternion> = Vec::with_capacity(pose.locals.len());
+    for (joint, local) in skeleton.joints.iter().zip(pose.locals.iter()) {
+        let rot = match joint.parent {
+            Some(parent) => rotations[parent] * local.rot,
+            None => local.rot,
+        };
+        rotations.push(rot.normalize());
+    }
+    rotations
+}
+
+// Moves the local rotation of a joint towards a solved rotation by a weight.
+fn blend_rotation(pose: &mut Pose, joint: usize, rot: Quaternion, weight: GLfloat) {
+    let local = &mut pose.locals[joint];
+    local.rot = if weight >= 1.0 { rot } else { clip::slerp(local.rot, rot, weight) };
+}
+
+// Normalizes a vector unless it is too short to have a direction.
+fn normalize_checked(v: Vector3D) -> Option<Vector3D> {
+    if v.length2() < EPSILON * EPSILON { None } else { Some(v.normalize()) }
+}
+
+// Gets the angle in radians between sides a and b of a triangle with sides a, b, and c.
+fn law_of_cosines(a: GLfloat, b: GLfloat, c: GLfloat) -> GLfloat {
+    ((a * a + b * b - c * c) / (2.0 * a * b)).max(-1.0).min(1.0).acos()
+}
+
+// Creates a rotation of an angle in radians around a normalized axis.
+fn axis_angle(axis: Vector3D, angle: GLfloat) -> Quaternion {
+    Quaternion::from_axis_angle(axis, cgmath::rad(angle))
+}
+
+// Gets the shortest rotation that turns one direction into another.
+fn rotation_between(from: Vector3D, to: Vector3D) -> Quaternion {
+    let (from, to) = match (normalize_checked(from), normalize_checked(to)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => { return Quaternion::new(1.0, 0.0, 0.0, 0.0); },
+    };
+    let axis = from.cross(to);
+    match normalize_checked(axis) {
+        Some(axis) => axis_angle(axis, from.dot(to).max(-1.0).min(1.0).acos()),
+        None if from.dot(to) > 0.0 => Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        None => {
+            // Opposite directions turn half way around any perpendicular axis.
+            let perpendicular = normalize_checked(from.cross(Vector3D::unit_x()))
+                    .unwrap_or(Vector3D::unit_y());
+            axis_angle(perpendicular, ::std::f32::consts::PI)
+        },
+    }
+}
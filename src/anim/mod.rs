@@ -0,0 +1,6 @@
+pub mod clip;
+pub mod ik;
+pub mod player;
+pub mod skeleton;
+pub mod state_machine;
+pub mod track;
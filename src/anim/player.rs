@@ -0,0 +1,259 @@
+// Defines an AnimationPlayer, which plays any number of AnimationClips on a Skeleton at once and
+// blends them by weight. Each playing clip has its own time, speed, and weight, and weights can
+// fade to a target over time so that switching clips cross-fades instead of popping. If the
+// weights add up to less than 1.0, the rest of the pose comes from the skeleton's rest pose, and if
+// they add up to more, they are normalized. IK constraints are then applied to the blended pose.
+//
+// Usage of an AnimationPlayer:
+// - Create it with new() from the skeleton of a skinned mesh.
+// - Start clips with play() or switch to one with crossfade().
+// - Add IK constraints to constraints and move their targets as needed.
+// - Call update(dt) every frame to advance the clips and recompute the pose, or update_all() to
+//   update many players across the threads of a JobSystem.
+// - Draw the mesh with GameWindow::draw_skinned() and get_skinning_matrices().
+// - For meshes with morph targets, pass sample_weights() to Morph::set_weights() and call
+//   GameWindow::upload_morph() before drawing.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use anim::clip::AnimationClip;
+use anim::ik;
+use anim::skeleton::{Pose, Skeleton};
+use gfx::types::*;
+use std::rc::Rc;
+use util::jobs::JobSystem;
+
+// A clip being played by an AnimationPlayer.
+pub struct ClipState {
+    pub clip: Rc<AnimationClip>,
+    pub time: GLfloat,
+    pub speed: GLfloat,
+    pub looping: bool,
+    pub weight: GLfloat,
+    target_weight: GLfloat,
+    fade_rate: GLfloat,
+}
+
+impl ClipState {
+    // Gets the weight that the clip is fading towards.
+    pub fn get_target_weight(&self) -> GLfloat {
+        self.target_weight
+    }
+
+    // Returns true if the clip doesn't loop and has played to its end.
+    pub fn is_finished(&self) -> bool {
+        if self.looping { return false; }
+        if self.speed >= 0.0 { self.time >= self.clip.duration } else { self.time <= 0.0 }
+    }
+
+    // Starts fading the weight towards a target over a number of seconds, or sets it right away if
+    // the time is 0.0.
+    fn fade_to(&mut self, weight: GLfloat, fade_time: GLfloat) {
+        self.target_weight = weight;
+        if fade_time <= 0.0 {
+            self.weight = weight;
+            self.fade_rate = 0.0;
+        } else {
+            self.fade_rate = (weight - self.weight).abs() / fade_time;
+        }
+    }
+
+    // Advances the time and weight.
+    fn advance(&mut self, dt: GLfloat) {
+        let duration = self.clip.duration;
+        self.time += dt * self.speed;
+        if self.looping && duration > 0.0 {
+            self.time = self.time % duration;
+            if self.time < 0.0 { self.time += duration; }
+        } else {
+            self.time = self.time.max(0.0).min(duration);
+        }
+        let step = self.fade_rate * dt;
+        if (self.target_weight - self.weight).abs() <= step {
+            self.weight = self.target_weight;
+        } else if self.target_weight > self.weight {
+            self.weight += step;
+        } else {
+            self.weight -= step;
+        }
+    }
+}
+
+// Plays and blends clips on a skeleton.
+pub struct AnimationPlayer {
+    pub skeleton: Rc<Skeleton>,
+    pub constraints: Vec<ik::Constraint>,
+    states: Vec<ClipState>,
+    pose: Pose,
+    skinning: Vec<cgmath::Matrix4<GLfloat>>,
+}
+
+impl AnimationPlayer {
+    // Creates a player with no clips playing, which leaves the skeleton in its rest pose.
+    pub fn new(skeleton: Rc<Skeleton>) -> AnimationPlayer {
+        let pose = skeleton.rest_pose();
+        let skinning = skeleton.compute_skinning(&pose);
+        AnimationPlayer { skeleton: skeleton, constraints: Vec::new(), states: Vec::new(),
+                pose: pose, skinning: skinning }
+    }
+
+    // Starts playing a clip from the beginning and fades its weight in over a number of seconds.
+    // If a clip with the same name is already playing, only its weight is changed.
+    pub fn play(&mut self, clip: Rc<AnimationClip>, looping: bool, weight: GLfloat,
+            fade_time: GLfloat) {
+        if let Some(state) = self.get_state_mut(&clip.name) {
+            state.looping = looping;
+            state.fade_to(weight, fade_time);
+            return;
+        }
+        let mut state = ClipState { clip: clip, time: 0.0, speed: 1.0, looping: looping,
+                weight: 0.0, target_weight: 0.0, fade_rate: 0.0 };
+        state.fade_to(weight, fade_time);
+        self.states.push(state);
+    }
+
+    // Fades a clip in to a weight of 1.0 while fading every other clip out over the same time.
+    pub fn crossfade(&mut self, clip: Rc<AnimationClip>, looping: bool, fade_time: GLfloat) {
+        for state in self.states.iter_mut().filter(|s| s.clip.name != clip.name) {
+            state.fade_to(0.0, fade_time);
+        }
+        self.play(clip, looping, 1.0, fade_time);
+    }
+
+    // Fades the weight of a playing clip to a new weight. Returns an Err if the clip isn't playing.
+    pub fn set_weight(&mut self, name: &str, weight: GLfloat, fade_time: GLfloat)
+            -> Result<(), String> {
+        match self.get_state_mut(name) {
+            Some(state) => { state.fade_to(weight, fade_time); Ok(()) },
+            None => Err(format!("Clip {} is not playing.", name)),
+        }
+    }
+
+    // Fades a clip out, after which it is removed. Returns an Err if the clip isn't playing.
+    pub fn stop(&mut self, name: &str, fade_time: GLfloat) -> Result<(), String> {
+        self.set_weight(name, 0.0, fade_time)
+    }
+
+    // Fades every clip out.
+    pub fn stop_all(&mut self, fade_time: GLfloat) {
+        for state in &mut self.states {
+            state.fade_to(0.0, fade_time);
+        }
+    }
+
+    // Gets a playing clip by name.
+    pub fn get_state(&self, name: &str) -> Option<&ClipState> {
+        self.states.iter().find(|s| s.clip.name == name)
+    }
+
+    // Gets a mutable reference to a playing clip by name, such as to change its time or speed.
+    pub fn get_state_mut(&mut self, name: &str) -> Option<&mut ClipState> {
+        self.states.iter_mut().find(|s| s.clip.name == name)
+    }
+
+    // Gets every playing clip in the order they were started.
+    pub fn get_states(&self) -> &Vec<ClipState> {
+        &self.states
+    }
+
+    // Advances every clip by a number of seconds, removes the clips that have faded out, and
+    // recomputes the pose with the constraints applied and the skinning matrices.
+    pub fn update(&mut self, dt: GLfloat) {
+        profile_scope!("AnimationPlayer::update");
+        self.advance(dt);
+        self.pose = self.sample();
+        ik::apply_constraints(&self.skeleton, &mut self.pose, &self.constraints);
+        self.skinning = self.skeleton.compute_skinning(&self.pose);
+    }
+
+    // Blends the playing clips at their current times into a pose.
+    pub fn sample(&self) -> Pose {
+        blend_clips(&self.skeleton, &get_samples(&self.states))
+    }
+
+    // Blends the morph target weights keyed by the playing clips at their current times for a Morph
+    // with a number of targets. Like the pose, targets start at a weight of 0.0 and weights are
+    // normalized if the clips' weights add up to more than 1.0.
+    pub fn sample_weights(&self, count: usize) -> Vec<GLfloat> {
+        let total = self.states.iter().fold(0.0, |t: GLfloat, s| t + s.weight.max(0.0));
+        let scale = if total > 1.0 { 1.0 / total } else { 1.0 };
+        let mut weights = vec![0.0; count];
+        let mut sample = vec![0.0; count];
+        for state in self.states.iter().filter(|s| s.weight > 0.0) {
+            for w in sample.iter_mut() { *w = 0.0; }
+            state.clip.sample_weights(state.time, &mut sample);
+            for (w, s) in weights.iter_mut().zip(sample.iter()) {
+                *w += s * state.weight * scale;
+            }
+        }
+        weights
+    }
+
+    // Gets the pose as of the last update().
+    pub fn get_pose(&self) -> &Pose {
+        &self.pose
+    }
+
+    // Gets the skinning matrix of every joint as of the last update().
+    pub fn get_skinning_matrices(&self) -> &Vec<cgmath::Matrix4<GLfloat>> {
+        &self.skinning
+    }
+
+    // Advances every clip by a number of seconds and removes the clips that have faded out.
+    fn advance(&mut self, dt: GLfloat) {
+        for state in &mut self.states {
+            state.advance(dt);
+        }
+        self.states.retain(|s| s.weight > 0.0 || s.target_weight > 0.0);
+    }
+}
+
+// Updates many players like AnimationPlayer::update(). The clips are advanced on the calling
+// thread, and then the poses, constraints, and skinning matrices of the players are computed
+// across the threads of a JobSystem.
+pub fn update_all(jobs: &JobSystem, players: &mut [AnimationPlayer], dt: GLfloat) {
+    profile_scope!("anim::update_all");
+    for player in players.iter_mut() {
+        player.advance(dt);
+    }
+    // The skeletons and clips are shared through Rcs, which can't be touched by other threads, so
+    // the jobs only get references to what they point to.
+    let mut work = Vec::with_capacity(players.len());
+    for player in players.iter_mut() {
+        let AnimationPlayer { ref skeleton, ref constraints, ref states, ref mut pose,
+                ref mut skinning } = *player;
+        let skeleton: &Skeleton = skeleton;
+        work.push((skeleton, &constraints[..], get_samples(states), pose, skinning));
+    }
+    jobs.for_each_mut(&mut work, |_, item| {
+        let mut pose = blend_clips(item.0, &item.2);
+        ik::apply_constraints(item.0, &mut pose, item.1);
+        *item.4 = item.0.compute_skinning(&pose);
+        *item.3 = pose;
+    });
+}
+
+// Gets the clip, time, and weight of every playing clip with a weight above 0.0.
+fn get_samples<'a>(states: &'a [ClipState]) -> Vec<(&'a AnimationClip, GLfloat, GLfloat)> {
+    states.iter().filter(|s| s.weight > 0.0).map(|s| (&*s.clip, s.time, s.weight)).collect()
+}
+
+// Blends clips at times by weight into a pose of a skeleton.
+fn blend_clips(skeleton: &Skeleton, samples: &[(&AnimationClip, GLfloat, GLfloat)]) -> Pose {
+    let rest = skeleton.rest_pose();
+    let total = samples.iter().fold(0.0, |t: GLfloat, s| t + s.2);
+    // Poses are blended in one at a time with each weight relative to the weight so far, which
+    // gives the weighted average once every pose is in.
+    let mut accumulated = (1.0 - total).max(0.0);
+    let mut pose = rest.clone();
+    for &(clip, time, weight) in samples {
+        let mut sample = rest.clone();
+        clip.sample(time, &mut sample);
+        accumulated += weight;
+        pose = pose.blend(&sample, weight / accumulated);
+    }
+    pose
+}
@@ -0,0 +1,122 @@
+// Defines a Skeleton, which is the hierarchy of joints that a skinned mesh is bound to, and a Pose,
+// which is a transform for every joint of a Skeleton relative to its parent. Joints are stored so
+// that every parent comes before its children, which lets world transforms be computed in a single
+// pass. Each joint also has an inverse bind matrix that moves a vertex from the mesh's space into
+// the joint's space at the time the mesh was bound, so a joint's skinning matrix is its current
+// transform in the mesh's space multiplied by its inverse bind matrix.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use anim::clip;
+use gfx::scene::Transform;
+use gfx::types::*;
+use self::cgmath::SquareMatrix;
+
+// A single joint of a Skeleton. The rest transform is relative to the parent and is used for any
+// joint that an animation doesn't key.
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub rest: Transform,
+    pub inverse_bind: cgmath::Matrix4<GLfloat>,
+}
+
+impl Joint {
+    // Creates a joint with an identity inverse bind matrix. Use Skeleton::bind_to_rest_pose() to
+    // compute the inverse bind matrices if the mesh was bound in the rest pose.
+    pub fn new(name: &str, parent: Option<usize>, rest: Transform) -> Joint {
+        Joint { name: name.to_string(), parent: parent, rest: rest,
+                inverse_bind: cgmath::Matrix4::identity() }
+    }
+}
+
+// A transform relative to its parent for every joint of a Skeleton, in the same order.
+#[derive(Clone, Debug)]
+pub struct Pose {
+    pub locals: Vec<Transform>,
+}
+
+impl Pose {
+    // Interpolates every joint from this pose towards another pose of the same skeleton, where t
+    // goes from 0.0 at this pose to 1.0 at the other.
+    pub fn blend(&self, other: &Pose, t: GLfloat) -> Pose {
+        Pose { locals: self.locals.iter().zip(other.locals.iter()).map(|(a, b)| {
+            Transform::new(a.pos + (b.pos - a.pos) * t, clip::slerp(a.rot, b.rot, t),
+                    a.scale + (b.scale - a.scale) * t)
+        }).collect() }
+    }
+}
+
+// A hierarchy of joints with every parent before its children.
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Creates a Skeleton from its joints. Returns an Err if a joint's parent doesn't come before
+    // it.
+    pub fn new(joints: Vec<Joint>) -> Result<Skeleton, String> {
+        for (i, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                if parent >= i {
+                    return Err(format!("Joint {} must come after its parent.", joint.name));
+                }
+            }
+        }
+        Ok(Skeleton { joints: joints })
+    }
+
+    // Gets the number of joints.
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    // Returns true if the skeleton has no joints.
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    // Finds the index of a joint by its name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    // Gets the pose with every joint at its rest transform.
+    pub fn rest_pose(&self) -> Pose {
+        Pose { locals: self.joints.iter().map(|j| j.rest).collect() }
+    }
+
+    // Sets the inverse bind matrix of every joint from the rest pose, for meshes that were bound
+    // to the skeleton in its rest pose.
+    pub fn bind_to_rest_pose(&mut self) {
+        let world = self.compute_world(&self.rest_pose());
+        for (joint, m) in self.joints.iter_mut().zip(world.iter()) {
+            joint.inverse_bind = m.invert().unwrap_or(cgmath::Matrix4::identity());
+        }
+    }
+
+    // Computes the transform of every joint in the mesh's space from a pose.
+    pub fn compute_world(&self, pose: &Pose) -> Vec<cgmath::Matrix4<GLfloat>> {
+        let mut world: Vec<cgmath::Matrix4<GLfloat>> = Vec::with_capacity(self.joints.len());
+        for (joint, local) in self.joints.iter().zip(pose.locals.iter()) {
+            let local = local.to_matrix();
+            let m = match joint.parent {
+                Some(parent) => world[parent] * local,
+                None => local,
+            };
+            world.push(m);
+        }
+        world
+    }
+
+    // Computes the skinning matrix of every joint from a pose, which moves a vertex from where it
+    // was bound to where the pose puts it.
+    pub fn compute_skinning(&self, pose: &Pose) -> Vec<cgmath::Matrix4<GLfloat>> {
+        self.compute_world(pose).into_iter().zip(self.joints.iter())
+                .map(|(m, joint)| m * joint.inverse_bind).collect()
+    }
+}
@@ -0,0 +1,359 @@
+// Defines an AnimationStateMachine, which drives an AnimationPlayer from a graph of states. Each
+// state plays a Motion, which is either a single clip or a blend tree that mixes clips by a float
+// parameter (such as idle, walk, and run by speed). Transitions move between states when all of
+// their conditions on the parameters hold and cross-fade over their duration. Game code only sets
+// parameters, so logic such as idle to walk to run doesn't need hand-written switch statements.
+//
+// Parameters are bools, floats, or triggers. A trigger is a bool that is reset as soon as a
+// transition that checks it is taken, so it fires at most once per set_trigger().
+//
+// Usage of an AnimationStateMachine:
+// - Create it with new() from an AnimationPlayer.
+// - Add states with add_state() and transitions between them with add_transition(). The first
+//   state added is the one the machine starts in.
+// - Set parameters with set_bool(), set_float(), and set_trigger() as the game state changes.
+// - Call update(dt) every frame, which also updates the player.
+//
+// Brian Ho
+// brian@brkho.com
+
+use anim::clip::AnimationClip;
+use anim::player::AnimationPlayer;
+use gfx::types::*;
+use std::cmp;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// The value of a parameter.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Parameter {
+    Bool(bool),
+    Float(GLfloat),
+    Trigger(bool),
+}
+
+// A condition on the parameters or the current state for a transition to be taken.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    // The bool or trigger is set.
+    If(String),
+    // The bool or trigger isn't set.
+    IfNot(String),
+    // The float is greater than a value.
+    Greater(String, GLfloat),
+    // The float is less than a value.
+    Less(String, GLfloat),
+    // The current state has played at least this fraction of its duration. Values above 1.0 wait
+    // for more than one loop.
+    ExitTime(GLfloat),
+}
+
+// What a state plays.
+#[derive(Clone)]
+pub enum Motion {
+    Clip(Rc<AnimationClip>),
+    // Blends between clips placed along a float parameter. The weights of the two clips around the
+    // parameter's value are linearly interpolated, and values outside the thresholds use the
+    // nearest clip. The clips are kept in phase with each other, so a walk and a run of different
+    // lengths still plant their feet together.
+    BlendTree1D { parameter: String, children: Vec<(GLfloat, Rc<AnimationClip>)> },
+}
+
+// A single state of an AnimationStateMachine.
+pub struct State {
+    pub name: String,
+    pub motion: Motion,
+    pub speed: GLfloat,
+    pub looping: bool,
+}
+
+// A transition to a state. A transition without a source can be taken from any state.
+pub struct Transition {
+    pub from: Option<usize>,
+    pub to: usize,
+    pub duration: GLfloat,
+    pub conditions: Vec<Condition>,
+}
+
+// A graph of states that drives an AnimationPlayer.
+pub struct AnimationStateMachine {
+    pub player: AnimationPlayer,
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+    parameters: HashMap<String, Parameter>,
+    current: Option<usize>,
+    current_time: GLfloat,
+    previous: Option<usize>,
+    previous_time: GLfloat,
+    fade_elapsed: GLfloat,
+    fade_duration: GLfloat,
+}
+
+impl AnimationStateMachine {
+    // Creates a state machine with no states that drives a player.
+    pub fn new(player: AnimationPlayer) -> AnimationStateMachine {
+        AnimationStateMachine { player: player, states: Vec::new(), transitions: Vec::new(),
+                parameters: HashMap::new(), current: None, current_time: 0.0, previous: None,
+                previous_time: 0.0, fade_elapsed: 0.0, fade_duration: 0.0 }
+    }
+
+    // Adds a looping state that plays a motion at normal speed and returns its index. The first
+    // state added becomes the current state.
+    pub fn add_state(&mut self, name: &str, motion: Motion) -> usize {
+        self.states.push(State { name: name.to_string(), motion: motion, speed: 1.0,
+                looping: true });
+        let index = self.states.len() - 1;
+        if self.current.is_none() {
+            self.current = Some(index);
+        }
+        index
+    }
+
+    // Finds the index of a state by its name.
+    pub fn find_state(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+
+    // Adds a transition between two states by name, or from any state if from is None. Returns an
+    // Err if either state doesn't exist.
+    pub fn add_transition(&mut self, from: Option<&str>, to: &str, duration: GLfloat,
+            conditions: Vec<Condition>) -> Result<(), String> {
+        let from = match from {
+            Some(name) => Some(try!(self.find_state(name)
+                    .ok_or(format!("Unknown state: {}", name)))),
+            None => None,
+        };
+        let to = try!(self.find_state(to).ok_or(format!("Unknown state: {}", to)));
+        self.transitions.push(Transition { from: from, to: to, duration: duration,
+                conditions: conditions });
+        Ok(())
+    }
+
+    // Sets a bool parameter.
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.parameters.insert(name.to_string(), Parameter::Bool(value));
+    }
+
+    // Sets a float parameter.
+    pub fn set_float(&mut self, name: &str, value: GLfloat) {
+        self.parameters.insert(name.to_string(), Parameter::Float(value));
+    }
+
+    // Sets a trigger, which stays set until a transition that checks it is taken.
+    pub fn set_trigger(&mut self, name: &str) {
+        self.parameters.insert(name.to_string(), Parameter::Trigger(true));
+    }
+
+    // Resets a trigger without taking a transition.
+    pub fn reset_trigger(&mut self, name: &str) {
+        self.parameters.insert(name.to_string(), Parameter::Trigger(false));
+    }
+
+    // Gets a parameter.
+    pub fn get_parameter(&self, name: &str) -> Option<Parameter> {
+        self.parameters.get(name).cloned()
+    }
+
+    // Gets the value of a bool or trigger, which is false if it was never set.
+    pub fn get_bool(&self, name: &str) -> bool {
+        match self.parameters.get(name) {
+            Some(&Parameter::Bool(b)) | Some(&Parameter::Trigger(b)) => b,
+            _ => false,
+        }
+    }
+
+    // Gets the value of a float, which is 0.0 if it was never set.
+    pub fn get_float(&self, name: &str) -> GLfloat {
+        match self.parameters.get(name) {
+            Some(&Parameter::Float(f)) => f,
+            _ => 0.0,
+        }
+    }
+
+    // Gets the current state, which is the state being faded to during a transition.
+    pub fn get_current_state(&self) -> Option<&State> {
+        self.current.map(|i| &self.states[i])
+    }
+
+    // Gets the time in seconds since the current state was entered.
+    pub fn get_current_time(&self) -> GLfloat {
+        self.current_time
+    }
+
+    // Returns true if a transition is being faded.
+    pub fn is_transitioning(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    // Switches to a state right away without a transition. Returns an Err if the state doesn't
+    // exist.
+    pub fn jump_to(&mut self, name: &str) -> Result<(), String> {
+        let index = try!(self.find_state(name).ok_or(format!("Unknown state: {}", name)));
+        self.enter(index, 0.0);
+        Ok(())
+    }
+
+    // Gets the weight of every clip of a motion with the current parameters.
+    fn motion_weights(&self, motion: &Motion) -> Vec<(Rc<AnimationClip>, GLfloat)> {
+        match *motion {
+            Motion::Clip(ref clip) => vec![(clip.clone(), 1.0)],
+            Motion::BlendTree1D { ref parameter, ref children } => {
+                let mut sorted: Vec<&(GLfloat, Rc<AnimationClip>)> = children.iter().collect();
+                sorted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(cmp::Ordering::Equal));
+                let value = self.get_float(parameter);
+                let mut weights: Vec<(Rc<AnimationClip>, GLfloat)> =
+                        sorted.iter().map(|c| (c.1.clone(), 0.0)).collect();
+                if weights.is_empty() { return weights; }
+                let last = sorted.len() - 1;
+                if value <= sorted[0].0 {
+                    weights[0].1 = 1.0;
+                } else if value >= sorted[last].0 {
+                    weights[last].1 = 1.0;
+                } else {
+                    let i = sorted.iter().position(|c| c.0 > value).unwrap() - 1;
+                    let t = (value - sorted[i].0) / (sorted[i + 1].0 - sorted[i].0);
+                    weights[i].1 = 1.0 - t;
+                    weights[i + 1].1 = t;
+                }
+                weights
+            },
+        }
+    }
+
+    // Gets the duration of a state's motion, which for blend trees is the weighted average of the
+    // durations of its clips.
+    fn state_duration(&self, index: usize) -> GLfloat {
+        self.motion_weights(&self.states[index].motion).iter()
+                .fold(0.0, |d, &(ref clip, w)| d + clip.duration * w)
+    }
+
+    // Returns true if a condition holds.
+    fn check(&self, condition: &Condition) -> bool {
+        match *condition {
+            Condition::If(ref name) => self.get_bool(name),
+            Condition::IfNot(ref name) => !self.get_bool(name),
+            Condition::Greater(ref name, value) => self.get_float(name) > value,
+            Condition::Less(ref name, value) => self.get_float(name) < value,
+            Condition::ExitTime(fraction) => {
+                let duration = self.current.map_or(0.0, |i| self.state_duration(i));
+                let speed = self.current.map_or(1.0, |i| self.states[i].speed.abs());
+                duration <= 0.0 || self.current_time * speed >= fraction * duration
+            },
+        }
+    }
+
+    // Enters a state, fading from the current state over a duration. Clips of the new state start
+    // from the beginning unless they are still playing in the state being faded from.
+    fn enter(&mut self, index: usize, duration: GLfloat) {
+        self.previous = if duration > 0.0 { self.current } else { None };
+        self.previous_time = self.current_time;
+        self.current = Some(index);
+        self.current_time = 0.0;
+        self.fade_elapsed = 0.0;
+        self.fade_duration = duration;
+        if self.previous.is_none() {
+            self.player.stop_all(0.0);
+        }
+    }
+
+    // Takes the first transition whose conditions hold. Transitions from any state can interrupt
+    // a transition that is still fading, while transitions from the current state wait for it to
+    // finish.
+    fn evaluate_transitions(&mut self) {
+        let current = match self.current {
+            Some(c) => c,
+            None => { return; },
+        };
+        let transitioning = self.is_transitioning();
+        let taken = self.transitions.iter().find(|t| {
+            let source = match t.from {
+                Some(from) => from == current && !transitioning,
+                None => t.to != current,
+            };
+            source && t.conditions.iter().all(|c| self.check(c))
+        }).map(|t| (t.to, t.duration, t.conditions.clone()));
+        if let Some((to, duration, conditions)) = taken {
+            for condition in conditions {
+                if let Condition::If(name) = condition {
+                    if let Some(&Parameter::Trigger(_)) = self.parameters.get(&name) {
+                        self.parameters.insert(name, Parameter::Trigger(false));
+                    }
+                }
+            }
+            self.enter(to, duration);
+        }
+    }
+
+    // Advances the state machine by a number of seconds, takes any transition whose conditions
+    // hold, sets the weights of the clips in the player, and updates the player.
+    pub fn update(&mut self, dt: GLfloat) {
+        self.current_time += dt;
+        self.previous_time += dt;
+        if self.previous.is_some() {
+            self.fade_elapsed += dt;
+            if self.fade_elapsed >= self.fade_duration {
+                self.previous = None;
+            }
+        }
+        self.evaluate_transitions();
+
+        // The weight, speed, looping, and synced time of every clip of the current and previous
+        // states. Clips in a blend tree get their time from the phase of the state instead of
+        // advancing on their own.
+        let fade = match self.previous {
+            Some(_) => self.fade_elapsed / self.fade_duration,
+            None => 1.0,
+        };
+        let mut active = Vec::new();
+        if let Some(current) = self.current {
+            active.push((current, fade, self.current_time));
+        }
+        if let Some(previous) = self.previous {
+            active.push((previous, 1.0 - fade, self.previous_time));
+        }
+        let mut clips: Vec<(Rc<AnimationClip>, GLfloat, GLfloat, bool, Option<GLfloat>)> =
+                Vec::new();
+        for (index, state_weight, time) in active {
+            let state = &self.states[index];
+            let phase = match state.motion {
+                Motion::Clip(_) => None,
+                Motion::BlendTree1D { .. } => {
+                    let duration = self.state_duration(index);
+                    let phase = if duration > 0.0 { time * state.speed / duration } else { 0.0 };
+                    Some(if state.looping { phase - phase.floor() } else {
+                        phase.max(0.0).min(1.0)
+                    })
+                },
+            };
+            for (clip, weight) in self.motion_weights(&state.motion) {
+                let synced = phase.map(|p| p * clip.duration);
+                match clips.iter().position(|c| c.0.name == clip.name) {
+                    Some(i) => { clips[i].1 += weight * state_weight; },
+                    None => {
+                        clips.push((clip, weight * state_weight, state.speed, state.looping,
+                                synced));
+                    },
+                }
+            }
+        }
+
+        let names: Vec<String> = self.player.get_states().iter()
+                .map(|s| s.clip.name.clone()).collect();
+        for name in names {
+            if !clips.iter().any(|c| c.0.name == name) {
+                let _ = self.player.stop(&name, 0.0);
+            }
+        }
+        for (clip, weight, speed, looping, synced) in clips {
+            let name = clip.name.clone();
+            self.player.play(clip, looping, weight, 0.0);
+            if let Some(state) = self.player.get_state_mut(&name) {
+                match synced {
+                    Some(time) => { state.time = time; state.speed = 0.0; },
+                    None => { state.speed = speed; },
+                }
+            }
+        }
+        self.player.update(dt);
+    }
+}
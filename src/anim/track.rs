@@ -0,0 +1,354 @@
+// Defines property tracks, which key a single property of a scene node, material, light, or camera
+// over time, and a Timeline, which plays any number of tracks in sync. This covers animation that
+// isn't skeletal, such as doors, flickering lights, and camera zooms in cutscenes.
+//
+// Tracks step between keys, interpolate linearly, or follow a cubic curve through the keys with
+// Catmull-Rom tangents, which eases in and out of each key without overshooting as much as a
+// natural spline. Cubic rotations are interpolated per component and renormalized, so rotation
+// keys should be on the same side of the hypersphere as their neighbors, as most exporters write
+// them.
+//
+// Usage of a Timeline:
+// - Create it with new() and add tracks with add_track().
+// - Start it with play() and call update(dt) every frame.
+// - Call apply() to write the tracks' values at the current time into the scene and window, and
+//   then Scene::update() for node transforms to take effect.
+//
+// Brian Ho
+// brian@brkho.com
+
+use anim::clip::{Keyframe, Keyframes};
+use gfx::camera::Projection;
+use gfx::color::Color;
+use gfx::game_window::GameWindow;
+use gfx::material::Material;
+use gfx::scene::{NodeId, Scene};
+use gfx::types::*;
+
+// How a track gets values between its keys.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    Cubic,
+}
+
+// A value that a track can interpolate along a cubic curve.
+pub trait TrackValue: Keyframe {
+    // Gets a + b * s.
+    fn add_scaled(a: Self, b: Self, s: GLfloat) -> Self;
+
+    // Interpolates from p0 to p1 along a cubic Hermite curve, where m0 and m1 are the tangents at
+    // p0 and p1 scaled by the time between them.
+    fn hermite(p0: Self, m0: Self, p1: Self, m1: Self, t: GLfloat) -> Self {
+        let (t2, t3) = (t * t, t * t * t);
+        let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+        let h10 = t3 - 2.0 * t2 + t;
+        let h01 = -2.0 * t3 + 3.0 * t2;
+        let h11 = t3 - t2;
+        let value = Self::add_scaled(p0, p0, h00 - 1.0);
+        let value = Self::add_scaled(value, m0, h10);
+        let value = Self::add_scaled(value, p1, h01);
+        Self::add_scaled(value, m1, h11)
+    }
+}
+
+impl TrackValue for GLfloat {
+    fn add_scaled(a: GLfloat, b: GLfloat, s: GLfloat) -> GLfloat {
+        a + b * s
+    }
+}
+
+impl TrackValue for Vector3D {
+    fn add_scaled(a: Vector3D, b: Vector3D, s: GLfloat) -> Vector3D {
+        a + b * s
+    }
+}
+
+impl TrackValue for Quaternion {
+    fn add_scaled(a: Quaternion, b: Quaternion, s: GLfloat) -> Quaternion {
+        a + b * s
+    }
+
+    fn hermite(p0: Quaternion, m0: Quaternion, p1: Quaternion, m1: Quaternion, t: GLfloat)
+            -> Quaternion {
+        let (t2, t3) = (t * t, t * t * t);
+        let value = p0 * (2.0 * t3 - 3.0 * t2 + 1.0) + m0 * (t3 - 2.0 * t2 + t) +
+                p1 * (-2.0 * t3 + 3.0 * t2) + m1 * (t3 - t2);
+        value.normalize()
+    }
+}
+
+impl Keyframe for Color {
+    fn interpolate(a: Color, b: Color, t: GLfloat) -> Color {
+        Color::add_scaled(a, Color::add_scaled(b, a, -1.0), t)
+    }
+}
+
+impl TrackValue for Color {
+    fn add_scaled(a: Color, b: Color, s: GLfloat) -> Color {
+        Color::new(a.r + b.r * s, a.g + b.g * s, a.b + b.b * s, a.a + b.a * s)
+    }
+}
+
+// Keyframes of a value and how to interpolate between them.
+#[derive(Clone, Debug)]
+pub struct Track<T: TrackValue> {
+    pub keys: Keyframes<T>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: TrackValue> Track<T> {
+    // Creates a track from its keys.
+    pub fn new(keys: Keyframes<T>, interpolation: Interpolation) -> Track<T> {
+        Track { keys: keys, interpolation: interpolation }
+    }
+
+    // Gets the time of the last key.
+    pub fn get_end_time(&self) -> GLfloat {
+        self.keys.get_end_time()
+    }
+
+    // Gets the tangent at a key scaled to the span from the key to the next one. Tangents are the
+    // slope between the neighboring keys, or towards the only neighbor at either end.
+    fn tangent(&self, index: usize, span: GLfloat) -> T {
+        let (times, values) = (&self.keys.times, &self.keys.values);
+        let prev = if index > 0 { index - 1 } else { index };
+        let next = if index + 1 < times.len() { index + 1 } else { index };
+        let dt = times[next] - times[prev];
+        if dt <= 0.0 { return T::add_scaled(values[index], values[index], -1.0); }
+        let slope = T::add_scaled(values[next], values[prev], -1.0);
+        T::add_scaled(slope, slope, span / dt - 1.0)
+    }
+
+    // Gets the value at a time. Times outside of the keys are clamped to the first or last key.
+    pub fn sample(&self, time: GLfloat) -> T {
+        let (i, t) = self.keys.locate(time);
+        let values = &self.keys.values;
+        if t == 0.0 { return values[i]; }
+        match self.interpolation {
+            Interpolation::Step => values[i],
+            Interpolation::Linear => T::interpolate(values[i], values[i + 1], t),
+            Interpolation::Cubic => {
+                let span = self.keys.times[i + 1] - self.keys.times[i];
+                T::hermite(values[i], self.tangent(i, span), values[i + 1],
+                        self.tangent(i + 1, span), t)
+            },
+        }
+    }
+}
+
+// A float property. Material properties are written to the material override of the node's mesh,
+// which starts as a copy of the mesh's material. Cameras are GameWindow camera handles and only
+// perspective cameras have a field of view.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FloatTarget {
+    MaterialMetallic(NodeId),
+    MaterialRoughness(NodeId),
+    MaterialShininess(NodeId),
+    CameraFov(usize),
+}
+
+// A vector property of a node's local transform.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum VectorTarget {
+    NodePosition(NodeId),
+    NodeScale(NodeId),
+}
+
+// A rotation property of a node's local transform.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RotationTarget {
+    NodeRotation(NodeId),
+}
+
+// A color property. Lights are GameWindow light indices.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorTarget {
+    MaterialColor(NodeId),
+    PointLightIntensity(usize),
+    DirectionalLightIntensity(usize),
+    SpotLightIntensity(usize),
+}
+
+// A track along with the property it animates.
+#[derive(Clone, Debug)]
+pub enum PropertyTrack {
+    Float(FloatTarget, Track<GLfloat>),
+    Vector(VectorTarget, Track<Vector3D>),
+    Rotation(RotationTarget, Track<Quaternion>),
+    Color(ColorTarget, Track<Color>),
+}
+
+impl PropertyTrack {
+    // Gets the time of the last key.
+    pub fn get_end_time(&self) -> GLfloat {
+        match *self {
+            PropertyTrack::Float(_, ref track) => track.get_end_time(),
+            PropertyTrack::Vector(_, ref track) => track.get_end_time(),
+            PropertyTrack::Rotation(_, ref track) => track.get_end_time(),
+            PropertyTrack::Color(_, ref track) => track.get_end_time(),
+        }
+    }
+
+    // Writes the track's value at a time into the property. Properties of nodes, meshes, or
+    // cameras that don't exist are skipped, while lights must exist like with Scene::sync().
+    pub fn apply(&self, time: GLfloat, scene: &mut Scene, window: &mut GameWindow) {
+        match *self {
+            PropertyTrack::Float(target, ref track) => {
+                let value = track.sample(time);
+                match target {
+                    FloatTarget::MaterialMetallic(id) => {
+                        with_material(scene, id, |m| { m.metallic = value; });
+                    },
+                    FloatTarget::MaterialRoughness(id) => {
+                        with_material(scene, id, |m| { m.roughness = value; });
+                    },
+                    FloatTarget::MaterialShininess(id) => {
+                        with_material(scene, id, |m| { m.shininess = value; });
+                    },
+                    FloatTarget::CameraFov(handle) => {
+                        if let Ok(camera) = window.get_camera_mut(handle) {
+                            if let Projection::Perspective { ref mut fov, .. } =
+                                    camera.projection {
+                                *fov = value;
+                            }
+                        }
+                    },
+                }
+            },
+            PropertyTrack::Vector(target, ref track) => {
+                let value = track.sample(time);
+                match target {
+                    VectorTarget::NodePosition(id) => {
+                        if let Some(node) = scene.get_node_mut(id) {
+                            node.transform.pos = value;
+                        }
+                    },
+                    VectorTarget::NodeScale(id) => {
+                        if let Some(node) = scene.get_node_mut(id) {
+                            node.transform.scale = value;
+                        }
+                    },
+                }
+            },
+            PropertyTrack::Rotation(RotationTarget::NodeRotation(id), ref track) => {
+                if let Some(node) = scene.get_node_mut(id) {
+                    node.transform.rot = track.sample(time);
+                }
+            },
+            PropertyTrack::Color(target, ref track) => {
+                let value = track.sample(time);
+                match target {
+                    ColorTarget::MaterialColor(id) => {
+                        with_material(scene, id, |m| { m.color = value; });
+                    },
+                    ColorTarget::PointLightIntensity(index) => {
+                        window.get_point_light_mut(index).intensity = value;
+                        window.update_point_light(index);
+                    },
+                    ColorTarget::DirectionalLightIntensity(index) => {
+                        window.get_directional_light_mut(index).intensity = value;
+                        window.update_directional_light(index);
+                    },
+                    ColorTarget::SpotLightIntensity(index) => {
+                        window.get_spot_light_mut(index).intensity = value;
+                        window.update_spot_light(index);
+                    },
+                }
+            },
+        }
+    }
+}
+
+// Changes the material override of a node's mesh, creating it from the mesh's material if needed.
+fn with_material<F: FnOnce(&mut Material)>(scene: &mut Scene, id: NodeId, f: F) {
+    let instance = match scene.get_node_mut(id).and_then(|n| n.mesh.as_mut()) {
+        Some(instance) => instance,
+        None => { return; },
+    };
+    if instance.material.is_none() {
+        instance.material = Some(instance.info.mat);
+    }
+    f(instance.material.as_mut().unwrap());
+}
+
+// Plays a set of property tracks in sync. The duration is the time of the last key of any track.
+pub struct Timeline {
+    pub name: String,
+    pub tracks: Vec<PropertyTrack>,
+    pub duration: GLfloat,
+    pub time: GLfloat,
+    pub speed: GLfloat,
+    pub looping: bool,
+    playing: bool,
+}
+
+impl Timeline {
+    // Creates a paused timeline with no tracks that doesn't loop.
+    pub fn new(name: &str) -> Timeline {
+        Timeline { name: name.to_string(), tracks: Vec::new(), duration: 0.0, time: 0.0,
+                speed: 1.0, looping: false, playing: false }
+    }
+
+    // Adds a track, which extends the duration if it ends later than the other tracks.
+    pub fn add_track(&mut self, track: PropertyTrack) {
+        self.duration = self.duration.max(track.get_end_time());
+        self.tracks.push(track);
+    }
+
+    // Starts or resumes playing.
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    // Pauses at the current time.
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    // Pauses and rewinds to the start.
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.time = 0.0;
+    }
+
+    // Jumps to a time, which is clamped to the duration.
+    pub fn seek(&mut self, time: GLfloat) {
+        self.time = time.max(0.0).min(self.duration);
+    }
+
+    // Returns true if the timeline is playing.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // Returns true if the timeline doesn't loop and has played to its end.
+    pub fn is_finished(&self) -> bool {
+        if self.looping { return false; }
+        if self.speed >= 0.0 { self.time >= self.duration } else { self.time <= 0.0 }
+    }
+
+    // Advances the time by a number of seconds if the timeline is playing. A timeline that doesn't
+    // loop stops playing once it reaches its end.
+    pub fn update(&mut self, dt: GLfloat) {
+        if !self.playing { return; }
+        self.time += dt * self.speed;
+        if self.looping && self.duration > 0.0 {
+            self.time = self.time % self.duration;
+            if self.time < 0.0 { self.time += self.duration; }
+        } else {
+            self.time = self.time.max(0.0).min(self.duration);
+            if self.is_finished() { self.playing = false; }
+        }
+    }
+
+    // Writes the value of every track at the current time into the scene and window. Lights are
+    // updated on the GPU here, while cameras and node transforms take effect after
+    // GameWindow::update_camera() and Scene::update().
+    pub fn apply(&self, scene: &mut Scene, window: &mut GameWindow) {
+        for track in &self.tracks {
+            track.apply(self.time, scene, window);
+        }
+    }
+}
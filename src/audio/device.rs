@@ -0,0 +1,323 @@
+// Defines an AudioDevice, which plays the output of a Mixer through an AudioBackend on a thread of
+// its own. The thread mixes a short period of samples at a time and hands it to the backend, whose
+// write blocks until the device has room for it, so the thread runs at the pace of the device. The
+// Mixer is shared behind a mutex so that game code can start and change Voices at any time.
+//
+// On Linux, the backend is ALSA, which is loaded when the device is created so that the engine
+// still runs on machines without it. Other platforms have no backend yet. Without a backend, or if
+// the backend fails, a NullBackend takes over, which throws the samples away at the rate they
+// would have been played so that Voices still finish on time.
+//
+// Usage of an AudioDevice:
+// - Create it with new() for the platform's backend, or with_backend() for another one.
+// - Lock its Mixer with lock() to play() and change Voices.
+// - Drop it to stop the thread.
+//
+// Brian Ho
+// brian@brkho.com
+
+use audio::mixer::Mixer;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// The sample rate and number of channels that are asked of the platform's device.
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_CHANNELS: u16 = 2;
+
+// Number of frames that are mixed at a time. Smaller periods react to changes to the Mixer sooner
+// at the cost of more wakeups.
+const PERIOD_FRAMES: usize = 512;
+
+// Something that plays interleaved samples.
+pub trait AudioBackend: Send {
+    // Gets the sample rate that the backend plays at.
+    fn get_sample_rate(&self) -> u32;
+
+    // Gets the number of channels that the backend plays.
+    fn get_channels(&self) -> u16;
+
+    // Plays interleaved samples, blocking until the device has taken them.
+    fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+}
+
+// A backend that plays nothing, but takes as long as playing would.
+pub struct NullBackend {
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl NullBackend {
+    // Creates a backend for a sample rate and number of channels.
+    pub fn new(sample_rate: u32, channels: u16) -> NullBackend {
+        NullBackend { sample_rate: sample_rate, channels: channels }
+    }
+}
+
+impl AudioBackend for NullBackend {
+    fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn get_channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        let frames = samples.len() as u64 / self.channels as u64;
+        thread::sleep(Duration::new(0, (frames * 1000000000 / self.sample_rate as u64) as u32));
+        Ok(())
+    }
+}
+
+// The Mixer along with the thread that plays it.
+pub struct AudioDevice {
+    mixer: Arc<Mutex<Mixer>>,
+    running: Arc<AtomicBool>,
+    playing: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl AudioDevice {
+    // Creates a device for the platform's backend, or a NullBackend if there isn't one.
+    pub fn new() -> AudioDevice {
+        let backend = match default_backend() {
+            Some(backend) => backend,
+            None => Box::new(NullBackend::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS)),
+        };
+        // Both backends play the default format, which always has channels.
+        AudioDevice::with_backend(backend).unwrap()
+    }
+
+    // Creates a device for a backend. The Mixer gets the backend's format. Returns an Err if the
+    // backend plays no channels or has no sample rate, since periods are counted in frames.
+    pub fn with_backend(backend: Box<AudioBackend>) -> Result<AudioDevice, String> {
+        if backend.get_channels() == 0 || backend.get_sample_rate() == 0 {
+            return Err("Audio backends need at least one channel and a sample rate.".to_string());
+        }
+        let mixer = Arc::new(Mutex::new(
+                Mixer::new(backend.get_sample_rate(), backend.get_channels())));
+        let running = Arc::new(AtomicBool::new(true));
+        let playing = Arc::new(AtomicBool::new(true));
+        let (thread_mixer, thread_running, thread_playing) =
+                (mixer.clone(), running.clone(), playing.clone());
+        let thread = thread::spawn(move || {
+            play(backend, thread_mixer, thread_running, thread_playing);
+        });
+        Ok(AudioDevice { mixer: mixer, running: running, playing: playing, thread: Some(thread) })
+    }
+
+    // Locks the Mixer to change what is playing. The Mixer can't mix while it is locked, so it
+    // should only be held for a moment.
+    pub fn lock<'a>(&'a self) -> MutexGuard<'a, Mixer> {
+        self.mixer.lock().unwrap()
+    }
+
+    // Gets the shared Mixer, such as for a system on another thread.
+    pub fn get_mixer(&self) -> Arc<Mutex<Mixer>> {
+        self.mixer.clone()
+    }
+
+    // Returns false if the device fell back to playing nothing after its backend failed.
+    pub fn is_playing(&self) -> bool {
+        self.playing.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for AudioDevice {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Mixes and plays periods until the device is dropped. A backend that fails is replaced with a
+// NullBackend of the same format.
+fn play(mut backend: Box<AudioBackend>, mixer: Arc<Mutex<Mixer>>, running: Arc<AtomicBool>,
+        playing: Arc<AtomicBool>) {
+    let (sample_rate, channels) = (backend.get_sample_rate(), backend.get_channels());
+    let mut samples = vec![0.0; PERIOD_FRAMES * channels as usize];
+    while running.load(Ordering::SeqCst) {
+        mixer.lock().unwrap().mix(&mut samples);
+        if let Err(e) = backend.write(&samples) {
+            log_error!(Audio, "Audio device failed, continuing without sound: {}", e);
+            backend = Box::new(NullBackend::new(sample_rate, channels));
+            playing.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+// Gets the backend for the platform, if there is one.
+#[cfg(target_os = "linux")]
+fn default_backend() -> Option<Box<AudioBackend>> {
+    match linux::AlsaBackend::new(DEFAULT_SAMPLE_RATE, DEFAULT_CHANNELS) {
+        Ok(backend) => Some(Box::new(backend)),
+        Err(e) => {
+            log_warn!(Audio, "No audio device, continuing without sound: {}", e);
+            None
+        },
+    }
+}
+
+// Gets the backend for the platform, if there is one.
+#[cfg(not(target_os = "linux"))]
+fn default_backend() -> Option<Box<AudioBackend>> {
+    None
+}
+
+// A backend for ALSA's default playback device. libasound is opened with dlopen() instead of being
+// linked so that machines without it can still run the engine, just without sound.
+#[cfg(target_os = "linux")]
+pub mod linux {
+    use super::AudioBackend;
+    use std::ffi::CString;
+    use std::mem;
+    use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+    use std::ptr;
+
+    // Flags and enums from dlfcn.h and asoundlib.h.
+    const RTLD_NOW: c_int = 2;
+    const SND_PCM_STREAM_PLAYBACK: c_int = 0;
+    const SND_PCM_FORMAT_FLOAT_LE: c_int = 14;
+    const SND_PCM_ACCESS_RW_INTERLEAVED: c_int = 3;
+
+    // The latency that is asked of the device in microseconds.
+    const LATENCY: c_uint = 50000;
+
+    extern "C" {
+        fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlclose(handle: *mut c_void) -> c_int;
+    }
+
+    type PcmOpen = unsafe extern "C" fn(*mut *mut c_void, *const c_char, c_int, c_int) -> c_int;
+    type PcmSetParams =
+            unsafe extern "C" fn(*mut c_void, c_int, c_int, c_uint, c_uint, c_int, c_uint) -> c_int;
+    type PcmWritei = unsafe extern "C" fn(*mut c_void, *const c_void, c_ulong) -> c_long;
+    type PcmRecover = unsafe extern "C" fn(*mut c_void, c_int, c_int) -> c_int;
+    type PcmClose = unsafe extern "C" fn(*mut c_void) -> c_int;
+
+    // An open playback device along with the functions from libasound that drive it.
+    pub struct AlsaBackend {
+        library: *mut c_void,
+        pcm: *mut c_void,
+        sample_rate: u32,
+        channels: u16,
+        writei: PcmWritei,
+        recover: PcmRecover,
+        close: PcmClose,
+    }
+
+    // The device is only ever used by the thread that owns the backend.
+    unsafe impl Send for AlsaBackend {}
+
+    // Looks up a function in a library.
+    unsafe fn load_symbol(library: *mut c_void, name: &str) -> Result<*mut c_void, String> {
+        let symbol = dlsym(library, CString::new(name).unwrap().as_ptr());
+        if symbol.is_null() {
+            return Err(format!("libasound has no {}.", name));
+        }
+        Ok(symbol)
+    }
+
+    impl AlsaBackend {
+        // Opens the default playback device with a sample rate and number of channels, which
+        // ALSA converts to whatever the hardware plays. Returns an Err if libasound or the device
+        // isn't there, or if there are no channels.
+        pub fn new(sample_rate: u32, channels: u16) -> Result<AlsaBackend, String> { unsafe {
+            if channels == 0 {
+                return Err("An ALSA device needs at least one channel.".to_string());
+            }
+            let library = dlopen(CString::new("libasound.so.2").unwrap().as_ptr(), RTLD_NOW);
+            if library.is_null() {
+                return Err("Unable to load libasound.".to_string());
+            }
+            match AlsaBackend::open(library, sample_rate, channels) {
+                Ok(backend) => Ok(backend),
+                Err(e) => {
+                    dlclose(library);
+                    Err(e)
+                },
+            }
+        }}
+
+        // Opens and configures the device with a loaded library.
+        unsafe fn open(library: *mut c_void, sample_rate: u32, channels: u16)
+                -> Result<AlsaBackend, String> {
+            let pcm_open: PcmOpen = mem::transmute(try!(load_symbol(library, "snd_pcm_open")));
+            let set_params: PcmSetParams =
+                    mem::transmute(try!(load_symbol(library, "snd_pcm_set_params")));
+            let writei: PcmWritei = mem::transmute(try!(load_symbol(library, "snd_pcm_writei")));
+            let recover: PcmRecover =
+                    mem::transmute(try!(load_symbol(library, "snd_pcm_recover")));
+            let close: PcmClose = mem::transmute(try!(load_symbol(library, "snd_pcm_close")));
+
+            let mut pcm = ptr::null_mut();
+            let name = CString::new("default").unwrap();
+            if pcm_open(&mut pcm, name.as_ptr(), SND_PCM_STREAM_PLAYBACK, 0) < 0 {
+                return Err("Unable to open the ALSA device.".to_string());
+            }
+            if set_params(pcm, SND_PCM_FORMAT_FLOAT_LE, SND_PCM_ACCESS_RW_INTERLEAVED,
+                    channels as c_uint, sample_rate, 1, LATENCY) < 0 {
+                close(pcm);
+                return Err("Unable to configure the ALSA device.".to_string());
+            }
+            Ok(AlsaBackend { library: library, pcm: pcm, sample_rate: sample_rate,
+                    channels: channels, writei: writei, recover: recover, close: close })
+        }
+    }
+
+    impl AudioBackend for AlsaBackend {
+        fn get_sample_rate(&self) -> u32 {
+            self.sample_rate
+        }
+
+        fn get_channels(&self) -> u16 {
+            self.channels
+        }
+
+        fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+            let channels = self.channels as usize;
+            let mut written = 0;
+            while written < samples.len() {
+                let rest = &samples[written..];
+                let frames = unsafe { (self.writei)(self.pcm, rest.as_ptr() as *const c_void,
+                        (rest.len() / channels) as c_ulong) };
+                if frames < 0 {
+                    // An underrun or a suspend can be recovered from by restarting the device.
+                    if unsafe { (self.recover)(self.pcm, frames as c_int, 1) } < 0 {
+                        return Err(format!("ALSA write failed ({}).", frames));
+                    }
+                    continue;
+                }
+                written += frames as usize * channels;
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for AlsaBackend {
+        fn drop(&mut self) {
+            unsafe {
+                (self.close)(self.pcm);
+                dlclose(self.library);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_backends_without_channels() {
+        assert!(AudioDevice::with_backend(Box::new(NullBackend::new(44100, 0))).is_err());
+        assert!(AudioDevice::with_backend(Box::new(NullBackend::new(0, 2))).is_err());
+        let device = AudioDevice::with_backend(Box::new(NullBackend::new(44100, 2))).unwrap();
+        assert!(device.is_playing());
+    }
+}
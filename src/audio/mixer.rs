@@ -0,0 +1,344 @@
+// Defines a Mixer, which adds any number of playing Voices together into the stream of samples
+// that is sent to the audio device. Each Voice plays a decoded Sound with its own volume and pitch,
+// and can loop or be paused. Sounds are resampled to the Mixer's sample rate on the fly with
+// linear interpolation, which is also how pitch is applied, so a pitch of 2.0 plays a sound an
+// octave higher and twice as fast. Mono sounds play on every channel, and sounds with more
+// channels than the output are folded into it.
+//
+// Voices can be panned between the left and right channels of stereo output. A sound panned to the
+// center plays at full volume on both sides, so that Voices that aren't panned play as they would
+// without panning, and fades out of the far side as it is panned away.
+//
+// Sounds that are too long to decode up front, such as music, are played as StreamVoices instead,
+// which read their samples from an AudioStream a little at a time as they are mixed. Changes to
+// the volume of a StreamVoice are ramped over the next mix so that fades don't click.
+//
+// Voices and StreamVoices are given handles when they start playing, which stay valid until they
+// finish or are stopped. A finished Voice is removed by the next mix.
+//
+// Usage of a Mixer:
+// - Usually, lock the Mixer of an AudioDevice, which mixes on its own thread.
+// - Start a sound with play(Voice::new(sound)) and keep the handle to change it later through
+//   get_voice_mut() or to stop() it.
+// - Start a stream with play_stream(StreamVoice::new(stream)) and change it through
+//   get_stream_mut().
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::f32::consts::{FRAC_PI_4, SQRT_2};
+use std::sync::Arc;
+use util::common::Sound;
+
+// Number of frames read from an AudioStream at a time.
+const STREAM_READ_FRAMES: usize = 1024;
+
+// A sound being played along with how it is played.
+pub struct Voice {
+    pub volume: f32,
+    pub pitch: f32,
+    pub pan: f32,                       // From -1.0 for left to 1.0 for right.
+    pub looping: bool,
+    pub paused: bool,
+    sound: Arc<Sound>,
+    position: f64,                      // In frames of the sound.
+    finished: bool,
+}
+
+impl Voice {
+    // Creates a Voice that plays a sound once from the start at full volume and normal pitch.
+    pub fn new(sound: Arc<Sound>) -> Voice {
+        Voice { volume: 1.0, pitch: 1.0, pan: 0.0, looping: false, paused: false, sound: sound,
+                position: 0.0, finished: false }
+    }
+
+    // Gets the sound being played.
+    pub fn get_sound(&self) -> &Arc<Sound> {
+        &self.sound
+    }
+
+    // Gets how far into the sound the Voice is in seconds.
+    pub fn get_position(&self) -> f32 {
+        (self.position / self.sound.sample_rate as f64) as f32
+    }
+
+    // Moves to a time in the sound in seconds.
+    pub fn set_position(&mut self, seconds: f32) {
+        let frames = self.sound.get_frame_count() as f64;
+        self.position = (seconds.max(0.0) as f64 * self.sound.sample_rate as f64).min(frames);
+        self.finished = false;
+    }
+
+    // Returns true once a Voice that doesn't loop has played to the end of its sound.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Adds the Voice's next frames to interleaved output of a sample rate and channel count, and
+    // scales them by a gain on top of the Voice's volume.
+    fn mix_into(&mut self, output: &mut [f32], sample_rate: u32, channels: usize, gain: f32) {
+        if self.paused || self.finished { return; }
+        let frames = self.sound.get_frame_count();
+        if frames == 0 || self.sound.channels == 0 {
+            self.finished = true;
+            return;
+        }
+        let step = self.pitch.max(0.0) as f64 * self.sound.sample_rate as f64 / sample_rate as f64;
+        let gain = gain * self.volume;
+        let (left, right) = if channels >= 2 { get_pan_gains(self.pan) } else { (1.0, 1.0) };
+        for out in output.chunks_mut(channels) {
+            if self.position >= frames as f64 {
+                if !self.looping {
+                    self.finished = true;
+                    return;
+                }
+                self.position %= frames as f64;
+            }
+            let frame = self.position as usize;
+            let t = (self.position - frame as f64) as f32;
+            let last = if self.looping { 0 } else { frame };
+            let next = if frame + 1 < frames { frame + 1 } else { last };
+            for (channel, sample) in out.iter_mut().enumerate() {
+                let pan = match channel { 0 => left, 1 => right, _ => 1.0 };
+                let value = get_sample(&self.sound.samples, self.sound.channels as usize, frame,
+                        next, t, channel, channels);
+                *sample += value * gain * pan;
+            }
+            self.position += step;
+        }
+    }
+}
+
+// Gets a sample of an output channel between two frames of interleaved samples.
+fn get_sample(samples: &[f32], channels: usize, frame: usize, next: usize, t: f32,
+        output_channel: usize, output_channels: usize) -> f32 {
+    let read = |channel: usize| {
+        let (a, b) = (samples[frame * channels + channel], samples[next * channels + channel]);
+        a + (b - a) * t
+    };
+    if output_channels == 1 && channels > 1 {
+        (0..channels).fold(0.0, |sum, c| sum + read(c)) / channels as f32
+    } else {
+        read(cmp::min(output_channel, channels - 1))
+    }
+}
+
+// Gets the gains of the left and right channels for a pan. The gains follow a constant power curve
+// that is scaled up to reach 1.0 at the center, and are capped at 1.0 so that panning never makes
+// a sound louder.
+fn get_pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.max(-1.0).min(1.0) + 1.0) * FRAC_PI_4;
+    ((angle.cos() * SQRT_2).min(1.0), (angle.sin() * SQRT_2).min(1.0))
+}
+
+// Something that gives samples as they are needed instead of all at once, such as music that is
+// decoded from disk as it plays. Streams are read on the thread that mixes.
+pub trait AudioStream: Send {
+    // Gets the sample rate of the stream.
+    fn get_sample_rate(&self) -> u32;
+
+    // Gets the number of channels of the stream.
+    fn get_channels(&self) -> u16;
+
+    // Reads the next frames into interleaved samples and returns how many frames were read, which
+    // is only fewer than fit once the stream has ended.
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize, String>;
+}
+
+// A stream being played along with how it is played.
+pub struct StreamVoice {
+    pub volume: f32,
+    pub pitch: f32,
+    pub pan: f32,                       // From -1.0 for left to 1.0 for right.
+    pub paused: bool,
+    stream: Box<AudioStream>,
+    buffer: Vec<f32>,                   // Samples read from the stream that haven't played yet.
+    position: f64,                      // In frames of the buffer.
+    mixed_volume: Option<f32>,          // The volume at the end of the last mix.
+    ended: bool,
+    finished: bool,
+}
+
+impl StreamVoice {
+    // Creates a StreamVoice that plays a stream at full volume and normal pitch.
+    pub fn new(stream: Box<AudioStream>) -> StreamVoice {
+        StreamVoice { volume: 1.0, pitch: 1.0, pan: 0.0, paused: false, stream: stream,
+                buffer: Vec::new(), position: 0.0, mixed_volume: None, ended: false,
+                finished: false }
+    }
+
+    // Returns true once the stream has ended and every frame read from it has played. A stream
+    // that fails to read also ends.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Reads from the stream until a number of frames from the current one on are buffered,
+    // dropping the frames that have already played.
+    fn fill(&mut self, frames: usize, channels: usize) {
+        let current = self.position as usize;
+        if self.ended || self.buffer.len() / channels >= current + frames { return; }
+        self.buffer.drain(..(current * channels));
+        self.position -= current as f64;
+        let mut chunk = vec![0.0; STREAM_READ_FRAMES * channels];
+        while self.buffer.len() / channels < frames && !self.ended {
+            match self.stream.read(&mut chunk) {
+                Ok(read) => {
+                    self.buffer.extend_from_slice(&chunk[..(read * channels)]);
+                    self.ended = read < STREAM_READ_FRAMES;
+                },
+                Err(e) => {
+                    log_warn!(Audio, "Stopping a stream that failed to read: {}", e);
+                    self.ended = true;
+                },
+            }
+        }
+    }
+
+    // Adds the StreamVoice's next frames to interleaved output of a sample rate and channel count
+    // in the same way as a Voice. The volume is ramped from where the last mix left it.
+    fn mix_into(&mut self, output: &mut [f32], sample_rate: u32, channels: usize, gain: f32) {
+        if self.paused || self.finished { return; }
+        let stream_channels = self.stream.get_channels() as usize;
+        if stream_channels == 0 {
+            self.finished = true;
+            return;
+        }
+        let step = self.pitch.max(0.0) as f64 * self.stream.get_sample_rate() as f64 /
+                sample_rate as f64;
+        let (left, right) = if channels >= 2 { get_pan_gains(self.pan) } else { (1.0, 1.0) };
+        let frames = output.len() / channels;
+        let start_volume = self.mixed_volume.unwrap_or(self.volume);
+        self.mixed_volume = Some(self.volume);
+        for (i, out) in output.chunks_mut(channels).enumerate() {
+            self.fill(2, stream_channels);
+            let buffered = self.buffer.len() / stream_channels;
+            let frame = self.position as usize;
+            if frame >= buffered {
+                self.finished = true;
+                return;
+            }
+            let next = cmp::min(frame + 1, buffered - 1);
+            let t = (self.position - frame as f64) as f32;
+            let ramp = (i + 1) as f32 / frames as f32;
+            let volume = start_volume + (self.volume - start_volume) * ramp;
+            for (channel, sample) in out.iter_mut().enumerate() {
+                let pan = match channel { 0 => left, 1 => right, _ => 1.0 };
+                let value = get_sample(&self.buffer, stream_channels, frame, next, t, channel,
+                        channels);
+                *sample += value * gain * volume * pan;
+            }
+            self.position += step;
+        }
+    }
+}
+
+// The Voices being played and the format that they are mixed into.
+pub struct Mixer {
+    pub volume: f32,
+    sample_rate: u32,
+    channels: u16,
+    voices: Vec<(usize, Voice)>,
+    streams: Vec<(usize, StreamVoice)>,
+    next_id: usize,
+}
+
+impl Mixer {
+    // Creates a Mixer without any Voices that mixes to a sample rate and number of channels.
+    pub fn new(sample_rate: u32, channels: u16) -> Mixer {
+        Mixer { volume: 1.0, sample_rate: sample_rate, channels: cmp::max(channels, 1),
+                voices: Vec::new(), streams: Vec::new(), next_id: 0 }
+    }
+
+    // Gets the sample rate of the output.
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Gets the number of channels of the output.
+    pub fn get_channels(&self) -> u16 {
+        self.channels
+    }
+
+    // Starts playing a Voice and returns its handle.
+    pub fn play(&mut self, voice: Voice) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.voices.push((id, voice));
+        id
+    }
+
+    // Starts playing a StreamVoice and returns its handle.
+    pub fn play_stream(&mut self, stream: StreamVoice) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.streams.push((id, stream));
+        id
+    }
+
+    // Stops a Voice or StreamVoice. Returns false if it had already finished or been stopped.
+    pub fn stop(&mut self, id: usize) -> bool {
+        let len = self.len();
+        self.voices.retain(|&(i, _)| i != id);
+        self.streams.retain(|&(i, _)| i != id);
+        self.len() != len
+    }
+
+    // Stops every Voice and StreamVoice.
+    pub fn stop_all(&mut self) {
+        self.voices.clear();
+        self.streams.clear();
+    }
+
+    // Gets a Voice that is still playing.
+    pub fn get_voice(&self, id: usize) -> Option<&Voice> {
+        self.voices.iter().find(|&&(i, _)| i == id).map(|&(_, ref voice)| voice)
+    }
+
+    // Gets a Voice that is still playing to change it.
+    pub fn get_voice_mut(&mut self, id: usize) -> Option<&mut Voice> {
+        self.voices.iter_mut().find(|&&mut (i, _)| i == id).map(|&mut (_, ref mut voice)| voice)
+    }
+
+    // Gets a StreamVoice that is still playing.
+    pub fn get_stream(&self, id: usize) -> Option<&StreamVoice> {
+        self.streams.iter().find(|&&(i, _)| i == id).map(|&(_, ref stream)| stream)
+    }
+
+    // Gets a StreamVoice that is still playing to change it.
+    pub fn get_stream_mut(&mut self, id: usize) -> Option<&mut StreamVoice> {
+        self.streams.iter_mut().find(|&&mut (i, _)| i == id).map(|&mut (_, ref mut stream)| stream)
+    }
+
+    // Returns true if a Voice or StreamVoice hasn't finished or been stopped.
+    pub fn is_playing(&self, id: usize) -> bool {
+        self.get_voice(id).map_or(false, |voice| !voice.finished) ||
+                self.get_stream(id).map_or(false, |stream| !stream.finished)
+    }
+
+    // Gets the number of Voices and StreamVoices, including paused ones.
+    pub fn len(&self) -> usize {
+        self.voices.len() + self.streams.len()
+    }
+
+    // Fills interleaved output with the next samples of every Voice and StreamVoice and removes the
+    // ones that finished. The output is clipped to the range -1.0 to 1.0.
+    pub fn mix(&mut self, output: &mut [f32]) {
+        for sample in output.iter_mut() {
+            *sample = 0.0;
+        }
+        let (sample_rate, channels) = (self.sample_rate, self.channels as usize);
+        for &mut (_, ref mut voice) in &mut self.voices {
+            voice.mix_into(output, sample_rate, channels, self.volume);
+        }
+        for &mut (_, ref mut stream) in &mut self.streams {
+            stream.mix_into(output, sample_rate, channels, self.volume);
+        }
+        self.voices.retain(|&(_, ref voice)| !voice.finished);
+        self.streams.retain(|&(_, ref stream)| !stream.finished);
+        for sample in output.iter_mut() {
+            *sample = sample.max(-1.0).min(1.0);
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod device;
+pub mod mixer;
+pub mod music;
+pub mod sound;
+pub mod spatial;
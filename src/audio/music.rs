@@ -0,0 +1,218 @@
+// Defines music playback. A MusicTrack streams an Ogg Vorbis file from disk while it plays, so that
+// only a few packets of a long track are decoded at a time instead of the whole thing, and loops
+// seamlessly between two loop points, which lets a track play an intro once before looping its
+// body. The loop points come from the LOOPSTART and LOOPLENGTH or LOOPEND comments of the file in
+// frames, which many tools write, or cover the whole track otherwise. Tracks are read on the audio
+// thread as they are mixed.
+//
+// A MusicPlayer plays one track at a time and crossfades from one track to the next along equal
+// power curves, so the music stays at the same loudness through the fade. Tracks that have faded
+// out are stopped.
+//
+// Usage of a MusicPlayer:
+// - Create it with new().
+// - Open a track with MusicTrack::open(path) and play(&mut mixer, track, fade) it, which fades it
+//   in over some seconds while whatever was playing fades out.
+// - Call update(&mut mixer, dt) every frame to move the fades along.
+// - Call stop(&mut mixer, fade) to fade the music out.
+//
+// Brian Ho
+// brian@brkho.com
+
+use audio::mixer::{AudioStream, Mixer, StreamVoice};
+use std::cmp;
+use std::f32::consts::FRAC_PI_2;
+use util::ogg::VorbisStream;
+
+// A track of music streamed from an Ogg Vorbis file.
+pub struct MusicTrack {
+    pub looping: bool,
+    stream: VorbisStream,
+    loop_start: u64,
+    loop_end: u64,
+}
+
+impl MusicTrack {
+    // Opens an Ogg Vorbis file as a track that loops.
+    pub fn open(path: &str) -> Result<MusicTrack, String> {
+        let stream = try!(VorbisStream::open(path));
+        let (start, end) = {
+            let comment = |name: &str| {
+                stream.get_decoder().get_comment(name).and_then(|v| v.trim().parse::<u64>().ok())
+            };
+            let start = comment("LOOPSTART").unwrap_or(0);
+            match (comment("LOOPLENGTH"), comment("LOOPEND")) {
+                (Some(length), _) => (start, start + length),
+                (None, Some(end)) => (start, end),
+                (None, None) => (start, stream.get_frame_count()),
+            }
+        };
+        let mut track = MusicTrack { looping: true, stream: stream, loop_start: 0, loop_end: 0 };
+        track.set_loop(start, end);
+        Ok(track)
+    }
+
+    // Sets the frames that the track loops between, where the end is the first frame after the
+    // loop. Both are clamped to the length of the track.
+    pub fn set_loop(&mut self, start: u64, end: u64) {
+        self.loop_end = cmp::min(end, self.stream.get_frame_count());
+        self.loop_start = cmp::min(start, self.loop_end);
+    }
+
+    // Gets the frames that the track loops between.
+    pub fn get_loop(&self) -> (u64, u64) {
+        (self.loop_start, self.loop_end)
+    }
+
+    // Gets the length of the track in seconds.
+    pub fn get_duration(&self) -> f32 {
+        self.stream.get_frame_count() as f32 / self.stream.get_sample_rate() as f32
+    }
+
+    // Gets how far into the track the next read is in seconds.
+    pub fn get_position(&self) -> f32 {
+        self.stream.get_position() as f32 / self.stream.get_sample_rate() as f32
+    }
+
+    // Moves to a time in the track in seconds.
+    pub fn seek(&mut self, seconds: f32) -> Result<(), String> {
+        let frame = (seconds.max(0.0) as f64 * self.stream.get_sample_rate() as f64) as u64;
+        self.stream.seek(frame)
+    }
+}
+
+impl AudioStream for MusicTrack {
+    fn get_sample_rate(&self) -> u32 {
+        self.stream.get_sample_rate()
+    }
+
+    fn get_channels(&self) -> u16 {
+        self.stream.get_channels()
+    }
+
+    fn read(&mut self, samples: &mut [f32]) -> Result<usize, String> {
+        let channels = self.stream.get_channels() as usize;
+        let wanted = samples.len() / channels;
+        let looping = self.looping && self.loop_end > self.loop_start;
+        let mut frames = 0;
+        while frames < wanted {
+            // Reads stop at the end of the loop so that the loop start can follow right after it.
+            let position = self.stream.get_position();
+            let mut count = wanted - frames;
+            if looping && position < self.loop_end {
+                count = cmp::min(count, (self.loop_end - position) as usize);
+            }
+            let read = try!(self.stream.read(
+                    &mut samples[(frames * channels)..((frames + count) * channels)]));
+            frames += read;
+            if !looping {
+                if read < count { break; }
+            } else if read < count || self.stream.get_position() >= self.loop_end {
+                if read == 0 && position == self.loop_start { break; }
+                try!(self.stream.seek(self.loop_start));
+            }
+        }
+        Ok(frames)
+    }
+}
+
+// A track that the MusicPlayer is playing along with how far it is faded in from 0.0 to 1.0.
+struct PlayingTrack {
+    voice: usize,
+    level: f32,
+    target: f32,
+    speed: f32,                         // Change in level per second.
+}
+
+// Plays music a track at a time with crossfades between tracks.
+pub struct MusicPlayer {
+    pub volume: f32,
+    current: Option<usize>,
+    tracks: Vec<PlayingTrack>,
+}
+
+impl MusicPlayer {
+    // Creates a MusicPlayer at full volume that isn't playing anything.
+    pub fn new() -> MusicPlayer {
+        MusicPlayer { volume: 1.0, current: None, tracks: Vec::new() }
+    }
+
+    // Starts playing a track, fading it in over some seconds while the track that was playing
+    // fades out over the same time. Returns the handle of the track's StreamVoice.
+    pub fn play(&mut self, mixer: &mut Mixer, track: MusicTrack, fade: f32) -> usize {
+        self.fade_out(fade);
+        let level = if fade > 0.0 { 0.0 } else { 1.0 };
+        let mut voice = StreamVoice::new(Box::new(track));
+        voice.volume = self.volume * get_fade_gain(level);
+        let id = mixer.play_stream(voice);
+        self.tracks.push(PlayingTrack { voice: id, level: level, target: 1.0,
+                speed: get_fade_speed(fade) });
+        self.current = Some(id);
+        self.update(mixer, 0.0);
+        id
+    }
+
+    // Fades out the music over some seconds.
+    pub fn stop(&mut self, mixer: &mut Mixer, fade: f32) {
+        self.fade_out(fade);
+        self.current = None;
+        self.update(mixer, 0.0);
+    }
+
+    // Gets the handle of the StreamVoice of the track that is playing or fading in.
+    pub fn get_current(&self) -> Option<usize> {
+        self.current
+    }
+
+    // Returns true while a track is fading in or out.
+    pub fn is_fading(&self) -> bool {
+        self.tracks.iter().any(|track| track.level != track.target)
+    }
+
+    // Moves the fades along by some time in seconds, sets the volumes of the tracks, and stops the
+    // tracks that have faded out. Tracks that end on their own are forgotten.
+    pub fn update(&mut self, mixer: &mut Mixer, dt: f32) {
+        for track in &mut self.tracks {
+            let step = track.speed * dt;
+            track.level = if track.level < track.target {
+                (track.level + step).min(track.target)
+            } else {
+                (track.level - step).max(track.target)
+            };
+            if track.level == 0.0 && track.target == 0.0 {
+                mixer.stop(track.voice);
+            } else if let Some(voice) = mixer.get_stream_mut(track.voice) {
+                voice.volume = self.volume * get_fade_gain(track.level);
+            }
+        }
+        self.tracks.retain(|track| mixer.is_playing(track.voice));
+        if let Some(current) = self.current {
+            if !mixer.is_playing(current) {
+                self.current = None;
+            }
+        }
+    }
+
+    // Starts fading out every track over some seconds.
+    fn fade_out(&mut self, fade: f32) {
+        for track in &mut self.tracks {
+            track.target = 0.0;
+            track.speed = get_fade_speed(fade);
+            if fade <= 0.0 {
+                track.level = 0.0;
+            }
+        }
+    }
+}
+
+// Gets how fast the level of a fade changes for a fade that lasts some seconds. Fades without a
+// length happen at once.
+fn get_fade_speed(fade: f32) -> f32 {
+    if fade > 0.0 { 1.0 / fade } else { 0.0 }
+}
+
+// Gets the gain of a track at a fade level. The gains of two tracks crossfading at the same speed
+// add up to the same power throughout.
+fn get_fade_gain(level: f32) -> f32 {
+    (level * FRAC_PI_2).sin()
+}
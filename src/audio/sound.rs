@@ -0,0 +1,75 @@
+// Defines a SoundManager, which loads sounds from files and keeps them for as long as they are
+// needed, in the same way that the TextureManager does for textures. Sounds are cached by path so
+// that loading the same file twice gives back the same Sound, and are shared with Arcs so that
+// the audio thread can keep playing a sound that the manager has since forgotten.
+//
+// Usage of a SoundManager:
+// - Create it with new() and load() each sound by its path.
+// - Play the loaded sounds with Voice::new(sound) on an AudioDevice's Mixer.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::collections::HashMap;
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+use util::common::Sound;
+use util::ogg;
+use util::wav;
+
+// Keeps track of every sound loaded through it.
+pub struct SoundManager {
+    sounds: HashMap<String, Arc<Sound>>,
+}
+
+impl SoundManager {
+    // Default constructor for an empty manager.
+    pub fn new() -> SoundManager {
+        SoundManager { sounds: HashMap::new() }
+    }
+
+    // Loads a sound from a WAV or Ogg Vorbis file based on its extension, or gets it from the cache
+    // if it was already loaded. Long music should be streamed with a MusicTrack instead.
+    pub fn load(&mut self, path: &str) -> Result<Arc<Sound>, String> {
+        if let Some(sound) = self.sounds.get(path) {
+            return Ok(sound.clone());
+        }
+        let extension = Path::new(path).extension().and_then(|e| e.to_str())
+                .map(|e| e.to_lowercase());
+        let sound = match extension.as_ref().map(|e| &e[..]) {
+            Some("wav") => try!(wav::decode_wav(path)).sound,
+            Some("ogg") => try!(ogg::decode_ogg(path)).sound,
+            _ => return Err(format!("Unsupported sound file: {}.", path)),
+        };
+        Ok(self.insert(path, sound))
+    }
+
+    // Adds a sound that was decoded or generated elsewhere under a path, replacing any sound that
+    // was already there.
+    pub fn insert(&mut self, path: &str, sound: Sound) -> Arc<Sound> {
+        let sound = Arc::new(sound);
+        self.sounds.insert(path.to_string(), sound.clone());
+        sound
+    }
+
+    // Gets a loaded sound by its path.
+    pub fn get(&self, path: &str) -> Option<Arc<Sound>> {
+        self.sounds.get(path).cloned()
+    }
+
+    // Forgets a sound so that it is freed once nothing is playing it.
+    pub fn remove(&mut self, path: &str) -> Option<Arc<Sound>> {
+        self.sounds.remove(path)
+    }
+
+    // Gets the number of sounds currently loaded.
+    pub fn len(&self) -> usize {
+        self.sounds.len()
+    }
+
+    // Gets the total number of bytes used by the samples of the loaded sounds.
+    pub fn memory_usage(&self) -> usize {
+        self.sounds.values().map(|s| s.samples.len() * mem::size_of::<f32>()).fold(0, |a, b| a + b)
+    }
+}
@@ -0,0 +1,291 @@
+// Defines SpatialAudio, which places Voices in the world as Emitters and works out how each of them
+// sounds to a Listener, usually the camera. Once per frame, update() sets the volume, pan, and
+// pitch of every Emitter's Voice from where the Emitter is relative to the Listener:
+// - The volume falls off with distance along a Rolloff curve between the Emitter's min and max
+//   distances. Closer than the min distance, a sound plays at its full volume.
+// - The pan comes from how far to the Listener's right or left the Emitter is. Emitters within the
+//   min distance are panned less the closer they get, so that a sound doesn't jump from one ear
+//   to the other when it passes through the Listener.
+// - If Doppler is on, the pitch rises as the Emitter and Listener come together and falls as they
+//   move apart. Velocities are worked out from how far things moved since the last update.
+//
+// Emitters can follow a Scene node, in which case they take the node's world position as of the
+// last Scene::update() and are stopped along with their Voice when the node is removed. An Emitter
+// is forgotten once its Voice finishes or is stopped.
+//
+// Usage of SpatialAudio:
+// - Create it with new() and start sounds with play(&mut mixer, voice, emitter, scene), where the
+//   Mixer is usually locked from an AudioDevice.
+// - Each frame, move the Listener with follow_camera() or move_to(), move Emitters that don't
+//   follow nodes, and call update(&mut mixer, Some(&scene), dt).
+// - Change the volume and pitch of a playing sound through its Emitter, since update() overwrites
+//   the ones on its Voice.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use audio::mixer::{Mixer, Voice};
+use gfx::camera::GameCamera;
+use gfx::scene::{NodeId, Scene};
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Vector};
+
+// The default speed of sound in world units per second, which is meters in air.
+const DEFAULT_SPEED_OF_SOUND: f32 = 343.0;
+
+// The range that the Doppler shift of the pitch is kept within.
+const MIN_DOPPLER_SHIFT: f32 = 0.5;
+const MAX_DOPPLER_SHIFT: f32 = 2.0;
+
+// Distances shorter than this count as no distance at all.
+const DISTANCE_EPSILON: f32 = 1e-4;
+
+// How the volume of an Emitter falls off between its min and max distances. Linear reaches silence
+// at the max distance when the factor is 1.0, while the others keep the volume that they have at
+// the max distance from there on out. Inverse falls off like sound does in the real world when the
+// factor is 1.0, and Exponential falls off faster the larger its factor is.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Rolloff {
+    None,
+    Linear,
+    Inverse,
+    Exponential,
+}
+
+impl Rolloff {
+    // Gets the gain at a distance from 0.0 to 1.0 given the min and max distances and a factor.
+    pub fn get_gain(&self, distance: f32, min: f32, max: f32, factor: f32) -> f32 {
+        let min = min.max(DISTANCE_EPSILON);
+        let distance = distance.max(min).min(max.max(min));
+        let gain = match *self {
+            Rolloff::None => 1.0,
+            Rolloff::Linear => {
+                if max <= min { 1.0 } else { 1.0 - factor * (distance - min) / (max - min) }
+            },
+            Rolloff::Inverse => min / (min + factor * (distance - min)),
+            Rolloff::Exponential => (distance / min).powf(-factor),
+        };
+        gain.max(0.0).min(1.0)
+    }
+}
+
+// Where sounds are heard from. The forward and up vectors should be normalized.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Listener {
+    pub pos: Vector3D,
+    pub fwd: Vector3D,
+    pub up: Vector3D,
+    pub velocity: Vector3D,
+}
+
+impl Listener {
+    // Creates a Listener at the origin looking down +Y with +Z as up.
+    pub fn new() -> Listener {
+        Listener { pos: Vector3D::zero(), fwd: Vector3D::new(0.0, 1.0, 0.0),
+                up: Vector3D::new(0.0, 0.0, 1.0), velocity: Vector3D::zero() }
+    }
+
+    // Gets the vector to the Listener's right.
+    pub fn get_right(&self) -> Vector3D {
+        self.fwd.cross(self.up).normalize()
+    }
+
+    // Moves the Listener, working out its velocity from how far it moved over some time in seconds.
+    pub fn move_to(&mut self, pos: Vector3D, fwd: Vector3D, up: Vector3D, dt: f32) {
+        self.velocity = if dt > 0.0 { (pos - self.pos) / dt } else { Vector3D::zero() };
+        self.pos = pos;
+        self.fwd = fwd.normalize();
+        self.up = up.normalize();
+    }
+
+    // Moves the Listener to a camera.
+    pub fn follow_camera(&mut self, camera: &GameCamera, dt: f32) {
+        let fwd = camera.target - camera.pos;
+        if fwd.length() < DISTANCE_EPSILON { return; }
+        self.move_to(camera.pos, fwd, camera.up, dt);
+    }
+}
+
+// A Voice placed in the world, either at a position or following a Scene node.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Emitter {
+    pub pos: Vector3D,
+    pub node: Option<NodeId>,
+    pub volume: f32,
+    pub pitch: f32,
+    pub rolloff: Rolloff,
+    pub rolloff_factor: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub doppler: bool,
+    last_pos: Vector3D,
+    velocity: Vector3D,
+    voice: usize,
+}
+
+impl Emitter {
+    // Creates an Emitter at a position with inverse rolloff from 1 to 100 units and Doppler on.
+    pub fn new(pos: Vector3D) -> Emitter {
+        Emitter { pos: pos, node: None, volume: 1.0, pitch: 1.0, rolloff: Rolloff::Inverse,
+                rolloff_factor: 1.0, min_distance: 1.0, max_distance: 100.0, doppler: true,
+                last_pos: pos, velocity: Vector3D::zero(), voice: 0 }
+    }
+
+    // Creates an Emitter that follows a Scene node.
+    pub fn on_node(node: NodeId) -> Emitter {
+        let mut emitter = Emitter::new(Vector3D::zero());
+        emitter.node = Some(node);
+        emitter
+    }
+
+    // Gets the velocity as of the last update.
+    pub fn get_velocity(&self) -> Vector3D {
+        self.velocity
+    }
+
+    // Gets the handle of the Emitter's Voice in the Mixer.
+    pub fn get_voice(&self) -> usize {
+        self.voice
+    }
+}
+
+// The Listener along with every Emitter that is playing.
+pub struct SpatialAudio {
+    pub listener: Listener,
+    pub speed_of_sound: f32,
+    pub doppler_factor: f32,
+    emitters: Vec<Option<Emitter>>,
+}
+
+impl SpatialAudio {
+    // Creates SpatialAudio with a Listener at the origin and no Emitters.
+    pub fn new() -> SpatialAudio {
+        SpatialAudio { listener: Listener::new(), speed_of_sound: DEFAULT_SPEED_OF_SOUND,
+                doppler_factor: 1.0, emitters: Vec::new() }
+    }
+
+    // Starts playing a Voice from an Emitter and returns the Emitter's handle. The Voice starts
+    // with the volume, pan, and pitch for where the Emitter is now.
+    pub fn play(&mut self, mixer: &mut Mixer, voice: Voice, mut emitter: Emitter,
+            scene: Option<&Scene>) -> usize {
+        if let Some(pos) = emitter.node.and_then(|node| get_node_position(scene, node)) {
+            emitter.pos = pos;
+        }
+        emitter.last_pos = emitter.pos;
+        emitter.voice = mixer.play(voice);
+        if let Some(voice) = mixer.get_voice_mut(emitter.voice) {
+            self.apply(&emitter, voice);
+        }
+        match self.emitters.iter().position(|e| e.is_none()) {
+            Some(i) => {
+                self.emitters[i] = Some(emitter);
+                i
+            },
+            None => {
+                self.emitters.push(Some(emitter));
+                self.emitters.len() - 1
+            },
+        }
+    }
+
+    // Stops an Emitter along with its Voice. Returns false if it had already stopped.
+    pub fn stop(&mut self, mixer: &mut Mixer, handle: usize) -> bool {
+        match self.emitters.get_mut(handle).and_then(|e| e.take()) {
+            Some(emitter) => {
+                mixer.stop(emitter.voice);
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Gets an Emitter that is still playing.
+    pub fn get_emitter(&self, handle: usize) -> Option<&Emitter> {
+        self.emitters.get(handle).and_then(|e| e.as_ref())
+    }
+
+    // Gets an Emitter that is still playing to move or change it.
+    pub fn get_emitter_mut(&mut self, handle: usize) -> Option<&mut Emitter> {
+        self.emitters.get_mut(handle).and_then(|e| e.as_mut())
+    }
+
+    // Gets the number of Emitters that are playing.
+    pub fn len(&self) -> usize {
+        self.emitters.iter().filter(|e| e.is_some()).count()
+    }
+
+    // Moves the Emitters that follow nodes, works out the velocities of every Emitter from how far
+    // it moved over some time in seconds, and updates their Voices. Emitters whose Voices finished
+    // are forgotten, and Emitters whose nodes were removed are stopped.
+    pub fn update(&mut self, mixer: &mut Mixer, scene: Option<&Scene>, dt: f32) {
+        for i in 0..self.emitters.len() {
+            let mut emitter = match self.emitters[i] {
+                Some(emitter) => emitter,
+                None => continue,
+            };
+            if !mixer.is_playing(emitter.voice) {
+                self.emitters[i] = None;
+                continue;
+            }
+            let pos = match emitter.node {
+                Some(node) => match get_node_position(scene, node) {
+                    Some(pos) => pos,
+                    None => {
+                        mixer.stop(emitter.voice);
+                        self.emitters[i] = None;
+                        continue;
+                    },
+                },
+                None => emitter.pos,
+            };
+            let moved = pos - emitter.last_pos;
+            emitter.velocity = if dt > 0.0 { moved / dt } else { Vector3D::zero() };
+            emitter.pos = pos;
+            emitter.last_pos = pos;
+            if let Some(voice) = mixer.get_voice_mut(emitter.voice) {
+                self.apply(&emitter, voice);
+            }
+            self.emitters[i] = Some(emitter);
+        }
+    }
+
+    // Sets the volume, pan, and pitch of a Voice for an Emitter.
+    fn apply(&self, emitter: &Emitter, voice: &mut Voice) {
+        let offset = emitter.pos - self.listener.pos;
+        let distance = offset.length();
+        let gain = emitter.rolloff.get_gain(distance, emitter.min_distance,
+                emitter.max_distance, emitter.rolloff_factor);
+        voice.volume = emitter.volume * gain;
+        voice.pan = if distance < DISTANCE_EPSILON {
+            0.0
+        } else {
+            let closeness = (distance / emitter.min_distance.max(DISTANCE_EPSILON)).min(1.0);
+            offset.dot(self.listener.get_right()) / distance * closeness
+        };
+        voice.pitch = emitter.pitch;
+        if emitter.doppler && distance >= DISTANCE_EPSILON {
+            voice.pitch *= self.get_doppler_shift(offset / distance, emitter.velocity);
+        }
+    }
+
+    // Gets the factor that the pitch is shifted by given the direction from the Listener to an
+    // Emitter and the Emitter's velocity. Speeds along the direction are capped below the speed of
+    // sound so that the shift stays finite.
+    fn get_doppler_shift(&self, dir: Vector3D, velocity: Vector3D) -> f32 {
+        if self.doppler_factor <= 0.0 || self.speed_of_sound <= 0.0 { return 1.0; }
+        let limit = self.speed_of_sound / self.doppler_factor * 0.99;
+        // Both speeds are positive when moving away from the other.
+        let listener_speed = (-self.listener.velocity.dot(dir)).max(-limit).min(limit);
+        let emitter_speed = velocity.dot(dir).max(-limit).min(limit);
+        let shift = (self.speed_of_sound - self.doppler_factor * listener_speed) /
+                (self.speed_of_sound + self.doppler_factor * emitter_speed);
+        shift.max(MIN_DOPPLER_SHIFT).min(MAX_DOPPLER_SHIFT)
+    }
+}
+
+// Gets the world position of a node in a Scene, or None if there is no Scene or no such node.
+fn get_node_position(scene: Option<&Scene>, node: NodeId) -> Option<Vector3D> {
+    scene.and_then(|scene| scene.get_node(node)).map(|node| node.get_world_position())
+}
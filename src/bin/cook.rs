@@ -0,0 +1,66 @@
+// Command line tool that cooks the assets described by a manifest into the formats that the engine
+// loads directly. See util/cook.rs for the format of the manifest.
+//
+// Usage of cook:
+// - cargo run --bin cook -- [--force] [--jobs count] [manifest]
+// - The manifest defaults to assets/cook.json. --force cooks every asset even if it is up to date,
+//   and --jobs sets the number of worker threads.
+// - Exits with 1 if any asset failed to cook.
+//
+// Brian Ho
+// brian@brkho.com
+
+#[macro_use] extern crate mmo;
+
+use mmo::util::cook;
+use mmo::util::jobs::JobSystem;
+use mmo::util::log;
+use std::env;
+use std::process;
+
+// The manifest that is cooked if none is given.
+const DEFAULT_MANIFEST: &'static str = "assets/cook.json";
+
+// Prints how to use the tool and exits.
+fn usage() -> ! {
+    println!("Usage: cook [--force] [--jobs count] [manifest]");
+    process::exit(2);
+}
+
+fn main() {
+    let mut force = false;
+    let mut workers = None;
+    let mut manifest_path = DEFAULT_MANIFEST.to_string();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-f" | "--force" => force = true,
+            "-j" | "--jobs" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(count) => workers = Some(count),
+                None => usage(),
+            },
+            "-h" | "--help" => usage(),
+            _ if arg.starts_with("-") => usage(),
+            _ => manifest_path = arg.clone(),
+        }
+    }
+
+    let manifest = match cook::Manifest::load(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            log_error!(Assets, "Unable to read {}: {}", manifest_path, e);
+            process::exit(1);
+        },
+    };
+    let jobs = match workers {
+        Some(count) => JobSystem::with_workers(count),
+        None => JobSystem::new(),
+    };
+    let report = cook::cook(&manifest, &jobs, force);
+    log_info!(Assets, "{} cooked, {} up to date, {} failed.", report.cooked.len(),
+            report.skipped.len(), report.failed.len());
+    log::flush();
+    if !report.failed.is_empty() {
+        process::exit(1);
+    }
+}
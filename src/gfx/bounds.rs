@@ -0,0 +1,179 @@
+// Defines bounding volumes used for visibility and ray queries. An Aabb is an axis-aligned
+// bounding box and a Frustum is the volume visible through a camera described by six planes.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Vector};
+
+// An axis-aligned bounding box. An empty box has its min greater than its max so that adding any
+// point to it gives a box around just that point.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vector3D,
+    pub max: Vector3D,
+}
+
+impl Aabb {
+    // Default constructor for an Aabb given its corners.
+    pub fn new(min: Vector3D, max: Vector3D) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    // Creates a box that contains nothing.
+    pub fn empty() -> Aabb {
+        Aabb { min: Vector3D::new(GLfloat::INFINITY, GLfloat::INFINITY, GLfloat::INFINITY),
+                max: Vector3D::new(GLfloat::NEG_INFINITY, GLfloat::NEG_INFINITY,
+                GLfloat::NEG_INFINITY) }
+    }
+
+    // Creates the smallest box around a list of positions stored as [x, y, z, x, y, z, ...].
+    pub fn from_positions(positions: &[GLfloat]) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in positions.chunks(3).filter(|p| p.len() == 3) {
+            bounds.add_point(Vector3D::new(p[0], p[1], p[2]));
+        }
+        bounds
+    }
+
+    // Returns true if the box contains nothing.
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    // Grows the box to contain a point.
+    pub fn add_point(&mut self, p: Vector3D) {
+        self.min = Vector3D::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z));
+        self.max = Vector3D::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z));
+    }
+
+    // Gets the smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: Vector3D::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z)),
+                max: Vector3D::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z)) }
+    }
+
+    // Gets the box grown by a margin on every side.
+    pub fn expand(&self, margin: GLfloat) -> Aabb {
+        let m = Vector3D::new(margin, margin, margin);
+        Aabb { min: self.min - m, max: self.max + m }
+    }
+
+    // Gets the center of the box.
+    pub fn center(&self) -> Vector3D {
+        (self.min + self.max) * 0.5
+    }
+
+    // Gets half of the size of the box along each axis.
+    pub fn extents(&self) -> Vector3D {
+        (self.max - self.min) * 0.5
+    }
+
+    // Gets the surface area of the box, which is used as the cost of a node in a Bvh.
+    pub fn surface_area(&self) -> GLfloat {
+        if self.is_empty() { return 0.0; }
+        let d = self.max - self.min;
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    // Returns true if the box fully contains another box.
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.min.x <= other.min.x && self.min.y <= other.min.y && self.min.z <= other.min.z &&
+                self.max.x >= other.max.x && self.max.y >= other.max.y &&
+                self.max.z >= other.max.z
+    }
+
+    // Returns true if the box contains a point.
+    pub fn contains_point(&self, p: Vector3D) -> bool {
+        self.min.x <= p.x && self.min.y <= p.y && self.min.z <= p.z && self.max.x >= p.x &&
+                self.max.y >= p.y && self.max.z >= p.z
+    }
+
+    // Returns true if the boxes overlap.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && self.max.x >= other.min.x &&
+                self.min.y <= other.max.y && self.max.y >= other.min.y &&
+                self.min.z <= other.max.z && self.max.z >= other.min.z
+    }
+
+    // Gets the box around this box after it is transformed by a matrix.
+    pub fn transform(&self, m: &cgmath::Matrix4<GLfloat>) -> Aabb {
+        if self.is_empty() { return *self; }
+        let c = self.center();
+        let e = self.extents();
+        let center = *m * c.extend(1.0);
+        let extent = |row: usize| {
+            m.x[row].abs() * e.x + m.y[row].abs() * e.y + m.z[row].abs() * e.z
+        };
+        let half = Vector3D::new(extent(0), extent(1), extent(2));
+        let center = Vector3D::new(center.x, center.y, center.z);
+        Aabb { min: center - half, max: center + half }
+    }
+
+    // Intersects a ray with the box and returns the distance along the ray at which it enters the
+    // box, which is 0.0 if the origin is inside. The inverse direction is 1.0 divided by each
+    // component of the direction. Returns None if the ray misses or only hits beyond max_t.
+    pub fn intersect_ray(&self, origin: Vector3D, inv_dir: Vector3D, max_t: GLfloat)
+            -> Option<GLfloat> {
+        let mut t_min: GLfloat = 0.0;
+        let mut t_max = max_t;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            // NaN comes from a zero direction with the origin on a slab plane, which is a hit.
+            let (near, far) = if t1 <= t2 { (t1, t2) } else { (t2, t1) };
+            if !near.is_nan() { t_min = t_min.max(near); }
+            if !far.is_nan() { t_max = t_max.min(far); }
+            if t_min > t_max { return None; }
+        }
+        Some(t_min)
+    }
+}
+
+// The volume visible through a camera. Each plane is stored as (a, b, c, d) with its normal
+// pointing into the volume, so a point p is inside when a * p.x + b * p.y + c * p.z + d >= 0.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Frustum {
+    pub planes: [cgmath::Vector4<GLfloat>; 6],
+}
+
+impl Frustum {
+    // Extracts the frustum from a combined projection and view matrix.
+    pub fn from_matrix(m: &cgmath::Matrix4<GLfloat>) -> Frustum {
+        let row = |i: usize| cgmath::Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let normalize = |p: cgmath::Vector4<GLfloat>| {
+            let length = Vector3D::new(p.x, p.y, p.z).length();
+            if length > 0.0 { p / length } else { p }
+        };
+        Frustum { planes: [normalize(r3 + r0), normalize(r3 - r0), normalize(r3 + r1),
+                normalize(r3 - r1), normalize(r3 + r2), normalize(r3 - r2)] }
+    }
+
+    // Returns true if a point is inside the frustum.
+    pub fn contains_point(&self, p: Vector3D) -> bool {
+        self.planes.iter().all(|plane| plane.dot(p.extend(1.0)) >= 0.0)
+    }
+
+    // Returns true if a box is at least partly inside the frustum. Boxes that are near a corner of
+    // the frustum but outside of it can also return true, which is fine for culling.
+    pub fn intersects_aabb(&self, bounds: &Aabb) -> bool {
+        if bounds.is_empty() { return false; }
+        self.planes.iter().all(|plane| {
+            let p = Vector3D::new(if plane.x >= 0.0 { bounds.max.x } else { bounds.min.x },
+                    if plane.y >= 0.0 { bounds.max.y } else { bounds.min.y },
+                    if plane.z >= 0.0 { bounds.max.z } else { bounds.min.z });
+            plane.dot(p.extend(1.0)) >= 0.0
+        })
+    }
+
+    // Returns true if a sphere is at least partly inside the frustum.
+    pub fn intersects_sphere(&self, center: Vector3D, radius: GLfloat) -> bool {
+        self.planes.iter().all(|plane| plane.dot(center.extend(1.0)) >= -radius)
+    }
+}
@@ -0,0 +1,395 @@
+// Defines a Bvh, which is a dynamic bounding volume hierarchy over objects with axis-aligned
+// bounds. Objects are leaves of a binary tree where every internal node bounds its children, so
+// visibility and ray queries only visit the parts of the tree that they overlap instead of every
+// object.
+//
+// Leaves are stored with bounds that are fattened by a margin. When an object moves, its leaf only
+// has to be reinserted once the new bounds leave the fattened bounds, so small movements are
+// cheap. New leaves are placed where they increase the surface area of the tree the least, and the
+// tree is rebalanced with rotations on the way back up so that it stays shallow no matter what
+// order objects are added in.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::bounds::{Aabb, Frustum};
+use gfx::types::*;
+use std::cmp;
+use std::collections::VecDeque;
+use util::jobs::JobSystem;
+
+// Handle to an object in a Bvh.
+pub type ProxyId = usize;
+
+// The default margin that leaf bounds are grown by in world units.
+const DEFAULT_MARGIN: GLfloat = 0.1;
+
+// How many subtrees per thread query_parallel() splits the tree into.
+const SUBTREES_PER_THREAD: usize = 4;
+
+// A leaf holding an object or an internal node with two children.
+struct BvhNode<T> {
+    bounds: Aabb,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>,
+    height: i32,
+    data: Option<T>,
+}
+
+// A dynamic bounding volume hierarchy holding a value of type T for each object.
+pub struct Bvh<T> {
+    pub margin: GLfloat,
+    nodes: Vec<Option<BvhNode<T>>>,
+    free: Vec<usize>,
+    root: Option<usize>,
+    len: usize,
+}
+
+impl<T> Bvh<T> {
+    // Creates an empty Bvh with the default margin.
+    pub fn new() -> Bvh<T> {
+        Bvh { margin: DEFAULT_MARGIN, nodes: Vec::new(), free: Vec::new(), root: None, len: 0 }
+    }
+
+    // Gets the number of objects in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // Returns true if there are no objects in the tree.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Gets the height of the tree, which is 0 for a tree with at most one object.
+    pub fn get_height(&self) -> i32 {
+        self.root.map_or(0, |r| self.node(r).height)
+    }
+
+    fn node(&self, index: usize) -> &BvhNode<T> {
+        self.nodes[index].as_ref().unwrap()
+    }
+
+    fn node_mut(&mut self, index: usize) -> &mut BvhNode<T> {
+        self.nodes[index].as_mut().unwrap()
+    }
+
+    // Stores a node in an unused slot and returns its index.
+    fn allocate(&mut self, node: BvhNode<T>) -> usize {
+        match self.free.pop() {
+            Some(i) => { self.nodes[i] = Some(node); i },
+            None => { self.nodes.push(Some(node)); self.nodes.len() - 1 },
+        }
+    }
+
+    // Returns true if a handle refers to an object in the tree.
+    fn is_leaf(&self, id: ProxyId) -> bool {
+        match self.nodes.get(id) {
+            Some(&Some(ref node)) => node.data.is_some(),
+            _ => false,
+        }
+    }
+
+    // Adds an object with some bounds and returns a handle to it.
+    pub fn insert(&mut self, bounds: Aabb, data: T) -> ProxyId {
+        let leaf = BvhNode { bounds: bounds.expand(self.margin), parent: None, children: None,
+                height: 0, data: Some(data) };
+        let id = self.allocate(leaf);
+        self.insert_leaf(id);
+        self.len += 1;
+        id
+    }
+
+    // Removes an object and returns its value, or None if the handle isn't in the tree.
+    pub fn remove(&mut self, id: ProxyId) -> Option<T> {
+        if !self.is_leaf(id) { return None; }
+        self.remove_leaf(id);
+        self.len -= 1;
+        self.free.push(id);
+        self.nodes[id].take().and_then(|n| n.data)
+    }
+
+    // Moves an object to new bounds. The tree only changes if the bounds leave the fattened bounds
+    // of the object's leaf, in which case this returns true.
+    pub fn update(&mut self, id: ProxyId, bounds: Aabb) -> bool {
+        if !self.is_leaf(id) || self.node(id).bounds.contains(&bounds) { return false; }
+        self.remove_leaf(id);
+        self.node_mut(id).bounds = bounds.expand(self.margin);
+        self.insert_leaf(id);
+        true
+    }
+
+    // Gets the value of an object.
+    pub fn get(&self, id: ProxyId) -> Option<&T> {
+        self.nodes.get(id).and_then(|n| n.as_ref()).and_then(|n| n.data.as_ref())
+    }
+
+    // Gets the mutable value of an object.
+    pub fn get_mut(&mut self, id: ProxyId) -> Option<&mut T> {
+        self.nodes.get_mut(id).and_then(|n| n.as_mut()).and_then(|n| n.data.as_mut())
+    }
+
+    // Gets the fattened bounds of an object.
+    pub fn get_bounds(&self, id: ProxyId) -> Option<Aabb> {
+        if self.is_leaf(id) { Some(self.node(id).bounds) } else { None }
+    }
+
+    // Removes every object.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free.clear();
+        self.root = None;
+        self.len = 0;
+    }
+
+    // Finds the best sibling for a new leaf by the increase in surface area, links the leaf under
+    // a new parent with it, and fixes the bounds and balance of the ancestors.
+    fn insert_leaf(&mut self, leaf: usize) {
+        let mut index = match self.root {
+            Some(r) => r,
+            None => {
+                self.root = Some(leaf);
+                self.node_mut(leaf).parent = None;
+                return;
+            },
+        };
+        let leaf_bounds = self.node(leaf).bounds;
+        while let Some((c1, c2)) = self.node(index).children {
+            let area = self.node(index).bounds.surface_area();
+            let combined_area = self.node(index).bounds.union(&leaf_bounds).surface_area();
+            let cost = 2.0 * combined_area;
+            let inheritance = 2.0 * (combined_area - area);
+            let child_cost = |child: usize| {
+                let node = self.node(child);
+                let union_area = node.bounds.union(&leaf_bounds).surface_area();
+                if node.children.is_none() {
+                    union_area + inheritance
+                } else {
+                    union_area - node.bounds.surface_area() + inheritance
+                }
+            };
+            let (cost1, cost2) = (child_cost(c1), child_cost(c2));
+            if cost < cost1 && cost < cost2 { break; }
+            index = if cost1 < cost2 { c1 } else { c2 };
+        }
+
+        let sibling = index;
+        let old_parent = self.node(sibling).parent;
+        let bounds = self.node(sibling).bounds.union(&leaf_bounds);
+        let height = self.node(sibling).height + 1;
+        let new_parent = self.allocate(BvhNode { bounds: bounds, parent: old_parent,
+                children: Some((sibling, leaf)), height: height, data: None });
+        match old_parent {
+            Some(p) => self.replace_child(p, sibling, new_parent),
+            None => { self.root = Some(new_parent); },
+        }
+        self.node_mut(sibling).parent = Some(new_parent);
+        self.node_mut(leaf).parent = Some(new_parent);
+        self.refit(Some(new_parent));
+    }
+
+    // Unlinks a leaf and its parent from the tree, moving the leaf's sibling up into the parent's
+    // place. The leaf keeps its slot so that its handle stays valid.
+    fn remove_leaf(&mut self, leaf: usize) {
+        if self.root == Some(leaf) {
+            self.root = None;
+            return;
+        }
+        let parent = self.node(leaf).parent.unwrap();
+        let (c1, c2) = self.node(parent).children.unwrap();
+        let sibling = if c1 == leaf { c2 } else { c1 };
+        let grandparent = self.node(parent).parent;
+        match grandparent {
+            Some(g) => {
+                self.replace_child(g, parent, sibling);
+                self.node_mut(sibling).parent = Some(g);
+            },
+            None => {
+                self.root = Some(sibling);
+                self.node_mut(sibling).parent = None;
+            },
+        }
+        self.nodes[parent] = None;
+        self.free.push(parent);
+        self.node_mut(leaf).parent = None;
+        self.refit(grandparent);
+    }
+
+    // Swaps one child of a node for another.
+    fn replace_child(&mut self, parent: usize, old: usize, new: usize) {
+        let node = self.node_mut(parent);
+        if let Some((c1, c2)) = node.children {
+            node.children = Some(if c1 == old { (new, c2) } else { (c1, new) });
+        }
+    }
+
+    // Recomputes the bounds and height of a node from its children.
+    fn fix(&mut self, index: usize) {
+        let (c1, c2) = self.node(index).children.unwrap();
+        let bounds = self.node(c1).bounds.union(&self.node(c2).bounds);
+        let height = 1 + cmp::max(self.node(c1).height, self.node(c2).height);
+        let node = self.node_mut(index);
+        node.bounds = bounds;
+        node.height = height;
+    }
+
+    // Walks from a node up to the root, rebalancing and refitting each ancestor.
+    fn refit(&mut self, start: Option<usize>) {
+        let mut next = start;
+        while let Some(index) = next {
+            let index = self.balance(index);
+            self.fix(index);
+            next = self.node(index).parent;
+        }
+    }
+
+    // Rotates a node with one of its grandchildren if one side of it is more than one level
+    // deeper than the other. Returns the index of the node that is now in its place.
+    fn balance(&mut self, a: usize) -> usize {
+        let (b, c) = match self.node(a).children {
+            Some(children) if self.node(a).height >= 2 => children,
+            _ => { return a; },
+        };
+        let balance = self.node(c).height - self.node(b).height;
+        if balance > 1 {
+            self.rotate(a, c, b)
+        } else if balance < -1 {
+            self.rotate(a, b, c)
+        } else {
+            a
+        }
+    }
+
+    // Moves a deep child up to take the place of its parent, which takes the shallower of the
+    // deep child's children.
+    fn rotate(&mut self, a: usize, deep: usize, other: usize) -> usize {
+        let (f, g) = self.node(deep).children.unwrap();
+        let a_parent = self.node(a).parent;
+        self.node_mut(deep).parent = a_parent;
+        self.node_mut(a).parent = Some(deep);
+        match a_parent {
+            Some(p) => self.replace_child(p, a, deep),
+            None => { self.root = Some(deep); },
+        }
+        let (keep, give) = if self.node(f).height > self.node(g).height { (f, g) } else { (g, f) };
+        self.node_mut(deep).children = Some((a, keep));
+        self.node_mut(a).children = Some((other, give));
+        self.node_mut(give).parent = Some(a);
+        self.fix(a);
+        self.fix(deep);
+        deep
+    }
+
+    // Calls a function for every object whose fattened bounds pass a test, skipping the subtrees
+    // whose bounds fail it.
+    pub fn query<F, G>(&self, test: F, callback: G)
+            where F: FnMut(&Aabb) -> bool, G: FnMut(ProxyId, &T) {
+        self.query_from(self.root, test, callback);
+    }
+
+    // Like query() but spread across the threads of a JobSystem. Returns the objects that pass the
+    // test and that the filter accepts in no particular order. The top of the tree is split into
+    // subtrees on the calling thread and each subtree is walked by a job.
+    pub fn query_parallel<F, G>(&self, jobs: &JobSystem, test: F, filter: G) -> Vec<ProxyId>
+            where T: Sync, F: Fn(&Aabb) -> bool + Sync, G: Fn(ProxyId, &T) -> bool + Sync {
+        let mut found = Vec::new();
+        let mut subtrees: VecDeque<usize> = self.root.into_iter().collect();
+        let count = (jobs.get_worker_count() + 1) * SUBTREES_PER_THREAD;
+        while subtrees.len() < count {
+            let index = match subtrees.pop_front() {
+                Some(index) => index,
+                None => break,
+            };
+            let node = self.node(index);
+            if !test(&node.bounds) { continue; }
+            match node.children {
+                Some((c1, c2)) => { subtrees.push_back(c1); subtrees.push_back(c2); },
+                None => {
+                    if filter(index, node.data.as_ref().unwrap()) { found.push(index); }
+                },
+            }
+        }
+        let subtrees: Vec<usize> = subtrees.into_iter().collect();
+        for mut part in jobs.map(&subtrees, |_, start| {
+            let mut part = Vec::new();
+            self.query_from(Some(*start), |b| test(b), |id, data| {
+                if filter(id, data) { part.push(id); }
+            });
+            part
+        }) {
+            found.append(&mut part);
+        }
+        found
+    }
+
+    // Walks the subtree under a node like query().
+    fn query_from<F, G>(&self, start: Option<usize>, mut test: F, mut callback: G)
+            where F: FnMut(&Aabb) -> bool, G: FnMut(ProxyId, &T) {
+        let mut stack: Vec<usize> = start.into_iter().collect();
+        while let Some(index) = stack.pop() {
+            let node = self.node(index);
+            if !test(&node.bounds) { continue; }
+            match node.children {
+                Some((c1, c2)) => { stack.push(c1); stack.push(c2); },
+                None => callback(index, node.data.as_ref().unwrap()),
+            }
+        }
+    }
+
+    // Gets the objects whose fattened bounds overlap a box.
+    pub fn query_aabb(&self, bounds: &Aabb) -> Vec<ProxyId> {
+        let mut found = Vec::new();
+        self.query(|b| b.intersects(bounds), |id, _| found.push(id));
+        found
+    }
+
+    // Gets the objects whose fattened bounds are at least partly inside a frustum.
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<ProxyId> {
+        let mut found = Vec::new();
+        self.query(|b| frustum.intersects_aabb(b), |id, _| found.push(id));
+        found
+    }
+
+    // Casts a ray and finds the closest object it hits. The hit function is called for objects
+    // whose bounds the ray enters before the closest hit so far, and returns the distance along
+    // the ray at which it hits the object itself or None if it misses. Objects are visited from
+    // the nearest bounds first so that most of the tree can be skipped. The direction doesn't need
+    // to be normalized, in which case distances are in units of its length.
+    pub fn raycast<F>(&self, origin: Vector3D, dir: Vector3D, max_t: GLfloat, mut hit: F)
+            -> Option<(ProxyId, GLfloat)> where F: FnMut(ProxyId, &T) -> Option<GLfloat> {
+        let inv_dir = Vector3D::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let mut best: Option<(ProxyId, GLfloat)> = None;
+        let mut limit = max_t;
+        let mut stack: Vec<(usize, GLfloat)> = Vec::new();
+        if let Some(root) = self.root {
+            if let Some(t) = self.node(root).bounds.intersect_ray(origin, inv_dir, limit) {
+                stack.push((root, t));
+            }
+        }
+        while let Some((index, t_enter)) = stack.pop() {
+            if t_enter > limit { continue; }
+            let node = self.node(index);
+            match node.children {
+                Some((c1, c2)) => {
+                    let t1 = self.node(c1).bounds.intersect_ray(origin, inv_dir, limit);
+                    let t2 = self.node(c2).bounds.intersect_ray(origin, inv_dir, limit);
+                    // The nearer child is pushed last so that it is visited first.
+                    let mut hits: Vec<(usize, GLfloat)> = Vec::with_capacity(2);
+                    if let Some(t) = t1 { hits.push((c1, t)); }
+                    if let Some(t) = t2 { hits.push((c2, t)); }
+                    hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal));
+                    stack.extend(hits);
+                },
+                None => {
+                    if let Some(t) = hit(index, node.data.as_ref().unwrap()) {
+                        if t >= 0.0 && t <= limit {
+                            limit = t;
+                            best = Some((index, t));
+                        }
+                    }
+                },
+            }
+        }
+        best
+    }
+}
@@ -1,7 +1,12 @@
-// Defines various cameras for use in rendering. The base Camera trait specifies methods for
-// getting the matrices needed for rendering. This also defines a PerspectiveCamera with associated
-// methods. Future support is planned for adding an OrthographicCamera, but it is not currently
-// implemented.
+// Defines the cameras used for rendering. The base Camera trait specifies methods for getting the
+// matrices needed for rendering. A GameCamera implements it with a position, a target, an up
+// vector, and a Projection that is either a perspective projection given by a field of view or an
+// orthographic projection given by the extents of the view volume. GameCameras are owned by the
+// GameWindow, which can hold many of them and renders from whichever one is active.
+//
+// A GameCamera can also convert between the world and the screen, such as for placing UI over an
+// object or casting a ray under the mouse cursor. Screen coordinates are in pixels with the origin
+// at the top left of the viewport.
 //
 // Brian Ho
 // brian@brkho.com
@@ -11,8 +16,9 @@ extern crate gl;
 
 pub use self::cgmath::EuclideanVector;
 
+use gfx::layers;
 use gfx::types::*;
-use self::cgmath::SquareMatrix;
+use self::cgmath::{Point, SquareMatrix};
 
 // Specifies methods for getting the view and projection matrices.
 pub trait Camera {
@@ -22,18 +28,73 @@ pub trait Camera {
     fn get_right(&self) -> Vector3D;
 }
 
-// A representation of a camera with a perspective projection. This implements the Camera trait, so
-// it can be used as a camera for rendering the game.
-pub struct PerspectiveCamera {
+// The parameters of a projection. The field of view is vertical and in degrees, and the extents of
+// an orthographic projection are in view space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Projection {
+    Perspective { fov: f32, aspect: f32, near: f32, far: f32 },
+    Orthographic { left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32 },
+}
+
+impl Projection {
+    // Creates an orthographic projection centered on the view direction given the height of the
+    // view volume and the aspect ratio of its width to its height.
+    pub fn orthographic(height: f32, aspect: f32, near: f32, far: f32) -> Projection {
+        let (half_w, half_h) = (0.5 * height * aspect, 0.5 * height);
+        Projection::Orthographic { left: -half_w, right: half_w, bottom: -half_h, top: half_h,
+                near: near, far: far }
+    }
+
+    // Gets the projection matrix.
+    pub fn to_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        match *self {
+            Projection::Perspective { fov, aspect, near, far } => {
+                cgmath::Matrix4::from(cgmath::PerspectiveFov {
+                        fovy: cgmath::Rad::from(cgmath::deg(fov)), aspect: aspect, near: near,
+                        far: far })
+            },
+            Projection::Orthographic { left, right, bottom, top, near, far } => {
+                cgmath::ortho(left, right, bottom, top, near, far)
+            },
+        }
+    }
+
+    // Gets the near and far planes.
+    pub fn get_near_far(&self) -> (f32, f32) {
+        match *self {
+            Projection::Perspective { near, far, .. } => (near, far),
+            Projection::Orthographic { near, far, .. } => (near, far),
+        }
+    }
+
+    // Changes the aspect ratio, such as after the window is resized. An orthographic projection
+    // keeps its height and center and changes its width.
+    pub fn set_aspect(&mut self, new_aspect: f32) {
+        match *self {
+            Projection::Perspective { ref mut aspect, .. } => { *aspect = new_aspect; },
+            Projection::Orthographic { ref mut left, ref mut right, bottom, top, .. } => {
+                let center = 0.5 * (*left + *right);
+                let half_w = 0.5 * (top - bottom) * new_aspect;
+                *left = center - half_w;
+                *right = center + half_w;
+            },
+        }
+    }
+}
+
+// A camera that can be attached to the GameWindow for rendering. This implements the Camera trait.
+// Only renderables on layers in the culling mask are drawn from the camera.
+pub struct GameCamera {
     pub pos: Vector3D,
     pub target: Vector3D,
     pub view: cgmath::Matrix4<GLfloat>,
     pub up: Vector3D,
-    proj: cgmath::Matrix4<GLfloat>,
+    pub projection: Projection,
+    pub culling_mask: layers::LayerMask,
 }
 
-// Implementation of the Camera methods for PerspectiveCamera.
-impl Camera for PerspectiveCamera {
+// Implementation of the Camera methods for GameCamera.
+impl Camera for GameCamera {
     // Return the precomputed view matrix.
     fn get_view_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.view }
 
@@ -49,35 +110,111 @@ impl Camera for PerspectiveCamera {
         Vector3D::new(mat.x[0], mat.y[0], mat.z[0]).normalize()
     }
 
-    // Return the precomputed projection matrix.
-    fn get_projection_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.proj }
+    // Computes the projection matrix from the projection parameters.
+    fn get_projection_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.projection.to_matrix() }
 }
 
-// Implementation of PerspectiveCamera methods.
-impl PerspectiveCamera {
-    // Constructor to initialize the fields and set up the Projection matrix.
+// Implementation of GameCamera methods.
+impl GameCamera {
+    // Constructor for a camera with a perspective projection and +Z as up.
     pub fn new(pos: Vector3D, target: Vector3D, aspect: f32, fov: f32,
-            near: f32, far: f32) -> PerspectiveCamera {
+            near: f32, far: f32) -> GameCamera {
         let up = Vector3D::new(0.0, 0.0, 1.0);
-        PerspectiveCamera::new_with_up(pos, target, up, aspect, fov, near, far)
+        GameCamera::new_with_up(pos, target, up, aspect, fov, near, far)
     }
 
-    // Constructor to initialize the fields and set up the Projection matrix with a specified up
-    // vector.
-    // TODO: Right now, you must call update directly after. I should factor this new code into the
-    // GameWindow class like I do with lights.
+    // Constructor for a camera with a perspective projection and a specified up vector.
     pub fn new_with_up(pos: Vector3D, target: Vector3D, up: Vector3D, aspect: f32,
-            fov: f32, near: f32, far: f32) -> PerspectiveCamera {
-        let proj = cgmath::PerspectiveFov {
-                fovy: cgmath::Rad::from(cgmath::deg(fov)),
-                aspect: aspect,
-                near: near,
+            fov: f32, near: f32, far: f32) -> GameCamera {
+        let projection = Projection::Perspective { fov: fov, aspect: aspect, near: near,
                 far: far };
-        let dummy_view = cgmath::Matrix4::identity();
-        PerspectiveCamera { pos: pos, target: target,
-                up: up, proj: cgmath::Matrix4::from(proj), view: dummy_view }
+        GameCamera::with_projection(pos, target, up, projection)
+    }
+
+    // Constructor for a camera with an orthographic projection given the height of the view volume
+    // in world units.
+    pub fn new_orthographic(pos: Vector3D, target: Vector3D, up: Vector3D, aspect: f32,
+            height: f32, near: f32, far: f32) -> GameCamera {
+        GameCamera::with_projection(pos, target, up,
+                Projection::orthographic(height, aspect, near, far))
     }
-}
 
-// TODO: Write the OrthographicCamera.
-// pub struct OrthographicCamera { }
+    // Constructor for a camera with any projection. The view matrix is computed right away, but
+    // must be recomputed with update_view() or GameWindow::update_camera() after moving the camera.
+    pub fn with_projection(pos: Vector3D, target: Vector3D, up: Vector3D,
+            projection: Projection) -> GameCamera {
+        let mut camera = GameCamera { pos: pos, target: target, up: up, projection: projection,
+                view: cgmath::Matrix4::identity(), culling_mask: layers::ALL_LAYERS };
+        camera.update_view();
+        camera
+    }
+
+    // Recomputes the view matrix from the position, target, and up vector.
+    pub fn update_view(&mut self) {
+        self.view = cgmath::Matrix4::look_at(cgmath::Point3::from_vec(self.pos),
+                cgmath::Point3::from_vec(self.target), self.up);
+    }
+
+    // Returns true if the camera has an orthographic projection.
+    pub fn is_orthographic(&self) -> bool {
+        match self.projection {
+            Projection::Orthographic { .. } => true,
+            Projection::Perspective { .. } => false,
+        }
+    }
+
+    // Gets the combined view and projection matrix.
+    pub fn get_view_projection(&self) -> cgmath::Matrix4<GLfloat> {
+        self.get_projection_matrix() * self.view
+    }
+
+    // Projects a point in the world onto a viewport of a given size in pixels. Returns the screen
+    // position and the depth from 0.0 at the near plane to 1.0 at the far plane, or None if the
+    // point is behind the camera.
+    pub fn world_to_screen(&self, point: Vector3D, width: u32, height: u32)
+            -> Option<(f32, f32, f32)> {
+        let clip = self.get_view_projection() * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(((ndc.x + 1.0) * 0.5 * width as f32, (1.0 - ndc.y) * 0.5 * height as f32,
+                (ndc.z + 1.0) * 0.5))
+    }
+
+    // Unprojects a screen position on a viewport of a given size in pixels back into the world.
+    // The depth goes from 0.0 at the near plane to 1.0 at the far plane.
+    pub fn screen_to_world(&self, x: f32, y: f32, depth: f32, width: u32, height: u32)
+            -> Vector3D {
+        let ndc = cgmath::Vector4::new(2.0 * x / width as f32 - 1.0,
+                1.0 - 2.0 * y / height as f32, 2.0 * depth - 1.0, 1.0);
+        let inverse = self.get_view_projection().invert()
+                .unwrap_or(cgmath::Matrix4::identity());
+        let world = inverse * ndc;
+        world.truncate() / world.w
+    }
+
+    // Gets the ray through a screen position on a viewport of a given size in pixels as an origin
+    // on the near plane and a normalized direction.
+    pub fn screen_to_ray(&self, x: f32, y: f32, width: u32, height: u32)
+            -> (Vector3D, Vector3D) {
+        let near = self.screen_to_world(x, y, 0.0, width, height);
+        let far = self.screen_to_world(x, y, 1.0, width, height);
+        (near, (far - near).normalize())
+    }
+
+    // Gets the eight corners of the view frustum in the world, near plane first, each in the order
+    // bottom left, bottom right, top right, top left.
+    pub fn get_frustum_corners(&self) -> Vec<Vector3D> {
+        let inverse = self.get_view_projection().invert()
+                .unwrap_or(cgmath::Matrix4::identity());
+        let mut corners = Vec::with_capacity(8);
+        for &z in &[-1.0, 1.0] {
+            for &(x, y) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let corner = inverse * cgmath::Vector4::new(x, y, z, 1.0);
+                corners.push(corner.truncate() / corner.w);
+            }
+        }
+        corners
+    }
+}
@@ -0,0 +1,330 @@
+// Defines ready-made controllers that move a GameCamera in response to window events. Each
+// controller keeps track of the keys and mouse buttons it cares about from the events passed to
+// handle_event() and moves the camera by the elapsed time in update().
+//
+// - An OrbitController tumbles around a target with the left mouse button, pans the target with
+//   the middle mouse button (or shift and the left mouse button), and zooms with the scroll wheel.
+// - A FlyController moves freely with WASD, rises and sinks with E and Q, and looks around while
+//   the right mouse button is held. Shift speeds it up and control slows it down.
+// - A FirstPersonController walks along the ground plane with WASD and always looks with the
+//   mouse, with the pitch clamped so that the view can't flip over.
+//
+// Usage of a controller:
+// - Create it with from_camera() so that it starts from the camera's current view.
+// - Pass every event from window.poll_events() to handle_event().
+// - Call update(&mut camera, dt) once per frame and then window.update_camera(handle).
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate glutin;
+
+use gfx::camera::{EuclideanVector, GameCamera};
+use gfx::types::*;
+use self::cgmath::Vector;
+use self::glutin::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode};
+use std::collections::HashSet;
+use std::f32::consts::PI;
+
+// Number of scroll lines a pixel delta from a touchpad counts as.
+const LINES_PER_PIXEL: f32 = 1.0 / 20.0;
+
+// Common interface for controllers so that demos can switch between them.
+pub trait CameraController {
+    fn handle_event(&mut self, event: &Event);
+    fn update(&mut self, camera: &mut GameCamera, dt: f32);
+}
+
+// The state of the keyboard and mouse as seen through events. Mouse movement and scrolling are
+// accumulated until they are taken by a controller's update.
+struct InputTracker {
+    keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    cursor: Option<(i32, i32)>,
+    mouse_delta: (f32, f32),
+    scroll: f32,
+}
+
+impl InputTracker {
+    fn new() -> InputTracker {
+        InputTracker { keys: HashSet::new(), buttons: HashSet::new(), cursor: None,
+                mouse_delta: (0.0, 0.0), scroll: 0.0 }
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyboardInput(state, _, Some(key)) => {
+                if state == ElementState::Pressed {
+                    self.keys.insert(key);
+                } else {
+                    self.keys.remove(&key);
+                }
+            },
+            Event::MouseInput(state, button) => {
+                if state == ElementState::Pressed {
+                    self.buttons.insert(button);
+                } else {
+                    self.buttons.remove(&button);
+                }
+            },
+            Event::MouseMoved((x, y)) => {
+                if let Some((last_x, last_y)) = self.cursor {
+                    self.mouse_delta.0 += (x - last_x) as f32;
+                    self.mouse_delta.1 += (y - last_y) as f32;
+                }
+                self.cursor = Some((x, y));
+            },
+            Event::MouseWheel(MouseScrollDelta::LineDelta(_, lines)) => { self.scroll += lines; },
+            Event::MouseWheel(MouseScrollDelta::PixelDelta(_, pixels)) => {
+                self.scroll += pixels * LINES_PER_PIXEL;
+            },
+            Event::Focused(false) => {
+                self.keys.clear();
+                self.buttons.clear();
+                self.cursor = None;
+            },
+            _ => {},
+        }
+    }
+
+    fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    // Gets a value from -1.0 to 1.0 from a pair of opposing keys.
+    fn axis(&self, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        let mut value = 0.0;
+        if self.is_key_down(negative) { value -= 1.0; }
+        if self.is_key_down(positive) { value += 1.0; }
+        value
+    }
+
+    // Returns and resets the accumulated mouse movement and scrolling.
+    fn take_motion(&mut self) -> ((f32, f32), f32) {
+        let motion = (self.mouse_delta, self.scroll);
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll = 0.0;
+        motion
+    }
+}
+
+// Two axes that are perpendicular to an up vector and to each other, which are the directions of
+// a yaw of 0 and of a quarter turn. For the default +Z up these are +X and +Y.
+fn ground_axes(up: Vector3D) -> (Vector3D, Vector3D) {
+    let reference = if up.x.abs() < 0.9 {
+        Vector3D::new(1.0, 0.0, 0.0)
+    } else {
+        Vector3D::new(0.0, 1.0, 0.0)
+    };
+    let first = (reference - up * reference.dot(up)).normalize();
+    (first, up.cross(first))
+}
+
+// Gets the direction given by a yaw around the up vector and a pitch above the ground plane.
+fn direction(up: Vector3D, yaw: f32, pitch: f32) -> Vector3D {
+    let (first, second) = ground_axes(up);
+    (first * yaw.cos() + second * yaw.sin()) * pitch.cos() + up * pitch.sin()
+}
+
+// Gets the yaw and pitch of a direction, which is the inverse of direction().
+fn yaw_pitch(up: Vector3D, dir: Vector3D) -> (f32, f32) {
+    let (first, second) = ground_axes(up);
+    let dir = dir.normalize();
+    let pitch = dir.dot(up).max(-1.0).min(1.0).asin();
+    (dir.dot(second).atan2(dir.dot(first)), pitch)
+}
+
+// Tumbles, pans, and zooms a camera around a target point. Angles are in radians.
+pub struct OrbitController {
+    pub target: Vector3D,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub up: Vector3D,
+    pub rotate_speed: f32,    // Radians per pixel.
+    pub pan_speed: f32,       // Fraction of the distance per pixel.
+    pub zoom_speed: f32,      // Fraction of the distance per scroll line.
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub max_pitch: f32,
+    input: InputTracker,
+}
+
+impl OrbitController {
+    // Creates a controller orbiting a target from a distance with +Z as up.
+    pub fn new(target: Vector3D, distance: f32, yaw: f32, pitch: f32) -> OrbitController {
+        OrbitController { target: target, distance: distance, yaw: yaw, pitch: pitch,
+                up: Vector3D::new(0.0, 0.0, 1.0), rotate_speed: 0.005, pan_speed: 0.002,
+                zoom_speed: 0.1, min_distance: 0.1, max_distance: 1000.0,
+                max_pitch: 89.0 * PI / 180.0, input: InputTracker::new() }
+    }
+
+    // Creates a controller that orbits a camera's target from the camera's current position.
+    pub fn from_camera(camera: &GameCamera) -> OrbitController {
+        let offset = camera.pos - camera.target;
+        let mut controller = OrbitController::new(camera.target, offset.length(), 0.0, 0.0);
+        controller.up = camera.up.normalize();
+        let (yaw, pitch) = yaw_pitch(controller.up, offset);
+        controller.yaw = yaw;
+        controller.pitch = pitch;
+        controller
+    }
+}
+
+impl CameraController for OrbitController {
+    fn handle_event(&mut self, event: &Event) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, camera: &mut GameCamera, _: f32) {
+        let ((dx, dy), scroll) = self.input.take_motion();
+        let shift = self.input.is_key_down(VirtualKeyCode::LShift) ||
+                self.input.is_key_down(VirtualKeyCode::RShift);
+        let left = self.input.is_button_down(MouseButton::Left);
+        if self.input.is_button_down(MouseButton::Middle) || (left && shift) {
+            let offset = direction(self.up, self.yaw, self.pitch);
+            let right = self.up.cross(offset).normalize();
+            let screen_up = offset.cross(right);
+            let scale = self.pan_speed * self.distance;
+            self.target = self.target - right * (dx * scale) + screen_up * (dy * scale);
+        } else if left {
+            self.yaw -= dx * self.rotate_speed;
+            self.pitch = (self.pitch + dy * self.rotate_speed).max(-self.max_pitch)
+                    .min(self.max_pitch);
+        }
+        self.distance = (self.distance * (1.0 - self.zoom_speed).powf(scroll))
+                .max(self.min_distance).min(self.max_distance);
+        camera.target = self.target;
+        camera.pos = self.target + direction(self.up, self.yaw, self.pitch) * self.distance;
+        camera.up = self.up;
+        camera.update_view();
+    }
+}
+
+// Moves a camera freely in the direction it is looking. Angles are in radians.
+pub struct FlyController {
+    pub pos: Vector3D,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub up: Vector3D,
+    pub speed: f32,             // World units per second.
+    pub fast_multiplier: f32,   // Applied while shift is held.
+    pub slow_multiplier: f32,   // Applied while control is held.
+    pub look_speed: f32,        // Radians per pixel.
+    pub max_pitch: f32,
+    input: InputTracker,
+}
+
+impl FlyController {
+    // Creates a controller at a position with +Z as up.
+    pub fn new(pos: Vector3D, yaw: f32, pitch: f32) -> FlyController {
+        FlyController { pos: pos, yaw: yaw, pitch: pitch, up: Vector3D::new(0.0, 0.0, 1.0),
+                speed: 5.0, fast_multiplier: 4.0, slow_multiplier: 0.25, look_speed: 0.003,
+                max_pitch: 89.0 * PI / 180.0, input: InputTracker::new() }
+    }
+
+    // Creates a controller that starts from a camera's current position and view direction.
+    pub fn from_camera(camera: &GameCamera) -> FlyController {
+        let mut controller = FlyController::new(camera.pos, 0.0, 0.0);
+        controller.up = camera.up.normalize();
+        let (yaw, pitch) = yaw_pitch(controller.up, camera.target - camera.pos);
+        controller.yaw = yaw;
+        controller.pitch = pitch;
+        controller
+    }
+}
+
+impl CameraController for FlyController {
+    fn handle_event(&mut self, event: &Event) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, camera: &mut GameCamera, dt: f32) {
+        let ((dx, dy), _) = self.input.take_motion();
+        if self.input.is_button_down(MouseButton::Right) {
+            self.yaw -= dx * self.look_speed;
+            self.pitch = (self.pitch - dy * self.look_speed).max(-self.max_pitch)
+                    .min(self.max_pitch);
+        }
+        let fwd = direction(self.up, self.yaw, self.pitch);
+        let right = fwd.cross(self.up).normalize();
+        let mut speed = self.speed;
+        if self.input.is_key_down(VirtualKeyCode::LShift) { speed *= self.fast_multiplier; }
+        if self.input.is_key_down(VirtualKeyCode::LControl) { speed *= self.slow_multiplier; }
+        let input = &self.input;
+        let motion = fwd * input.axis(VirtualKeyCode::S, VirtualKeyCode::W) +
+                right * input.axis(VirtualKeyCode::A, VirtualKeyCode::D) +
+                self.up * input.axis(VirtualKeyCode::Q, VirtualKeyCode::E);
+        if motion.length2() > 0.0 {
+            self.pos = self.pos + motion.normalize() * (speed * dt);
+        }
+        camera.pos = self.pos;
+        camera.target = self.pos + fwd;
+        camera.up = self.up;
+        camera.update_view();
+    }
+}
+
+// Walks a camera along the ground plane while looking around with the mouse. Angles are in
+// radians.
+pub struct FirstPersonController {
+    pub pos: Vector3D,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub up: Vector3D,
+    pub speed: f32,             // World units per second.
+    pub run_multiplier: f32,    // Applied while shift is held.
+    pub look_speed: f32,        // Radians per pixel.
+    pub max_pitch: f32,
+    input: InputTracker,
+}
+
+impl FirstPersonController {
+    // Creates a controller at a position with +Z as up.
+    pub fn new(pos: Vector3D, yaw: f32, pitch: f32) -> FirstPersonController {
+        FirstPersonController { pos: pos, yaw: yaw, pitch: pitch,
+                up: Vector3D::new(0.0, 0.0, 1.0), speed: 4.0, run_multiplier: 2.0,
+                look_speed: 0.003, max_pitch: 85.0 * PI / 180.0, input: InputTracker::new() }
+    }
+
+    // Creates a controller that starts from a camera's current position and view direction.
+    pub fn from_camera(camera: &GameCamera) -> FirstPersonController {
+        let mut controller = FirstPersonController::new(camera.pos, 0.0, 0.0);
+        controller.up = camera.up.normalize();
+        let (yaw, pitch) = yaw_pitch(controller.up, camera.target - camera.pos);
+        controller.yaw = yaw;
+        controller.pitch = pitch.max(-controller.max_pitch).min(controller.max_pitch);
+        controller
+    }
+}
+
+impl CameraController for FirstPersonController {
+    fn handle_event(&mut self, event: &Event) {
+        self.input.handle_event(event);
+    }
+
+    fn update(&mut self, camera: &mut GameCamera, dt: f32) {
+        let ((dx, dy), _) = self.input.take_motion();
+        self.yaw -= dx * self.look_speed;
+        self.pitch = (self.pitch - dy * self.look_speed).max(-self.max_pitch).min(self.max_pitch);
+        let walk = direction(self.up, self.yaw, 0.0);
+        let right = walk.cross(self.up).normalize();
+        let mut speed = self.speed;
+        if self.input.is_key_down(VirtualKeyCode::LShift) { speed *= self.run_multiplier; }
+        let input = &self.input;
+        let motion = walk * input.axis(VirtualKeyCode::S, VirtualKeyCode::W) +
+                right * input.axis(VirtualKeyCode::A, VirtualKeyCode::D);
+        if motion.length2() > 0.0 {
+            self.pos = self.pos + motion.normalize() * (speed * dt);
+        }
+        camera.pos = self.pos;
+        camera.target = self.pos + direction(self.up, self.yaw, self.pitch);
+        camera.up = self.up;
+        camera.update_view();
+    }
+}
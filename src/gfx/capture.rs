@@ -0,0 +1,160 @@
+// Defines helpers for saving what the window shows. GameWindow::capture_screenshot() saves a single
+// frame, and a SequenceCapture saves numbered frames for as long as it is recording so that repro
+// cases can be turned into videos, such as with ffmpeg -i captures/frame_%05d.bmp repro.mp4.
+//
+// Frames are read back from the back buffer, so they must be captured after the last draw of the
+// frame (including post-processing) and before the buffers are swapped. Reading back waits for the
+// GPU to finish the frame, and encoding is slow enough that a SequenceCapture can hand it to a
+// JobSystem so that recording doesn't slow the game down much more than the readback does.
+//
+// Usage of a SequenceCapture:
+// - let mut capture = SequenceCapture::new("captures", "frame", ImageFormat::BMP);
+// - try!(capture.start()) to begin recording, such as from a key binding.
+// - try!(capture.capture(&window, Some(&jobs))) every frame before window.swap_buffers().
+// - try!(capture.stop(Some(&jobs))) to stop recording and wait for the frames to be written.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::game_window::GameWindow;
+use util::common;
+use util::jobs::{JobHandle, JobSystem};
+use util::{bmp, png};
+use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// The formats that captured frames can be written in. BMPs are much faster to write and PNGs are
+// much smaller.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ImageFormat {
+    BMP,
+    PNG,
+}
+
+impl ImageFormat {
+    // Gets the format of a path from its extension.
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str())
+                .map(|e| e.to_lowercase());
+        match extension.as_ref().map(|e| &e[..]) {
+            Some("bmp") => Some(ImageFormat::BMP),
+            Some("png") => Some(ImageFormat::PNG),
+            _ => None,
+        }
+    }
+
+    // Gets the extension of files in the format.
+    pub fn get_extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::BMP => "bmp",
+            ImageFormat::PNG => "png",
+        }
+    }
+}
+
+// Writes an opaque image to a file in a format. The alpha channel is dropped.
+pub fn write_image(path: &str, image: &common::Image, format: ImageFormat)
+        -> Result<(), String> {
+    match format {
+        ImageFormat::BMP => bmp::encode_bmp(path, image),
+        ImageFormat::PNG => png::encode_png(path, image, false),
+    }
+}
+
+// Records frames of a window to numbered files in a directory while it is recording. Only every
+// interval-th frame is kept, and recording stops by itself after max_frames frames if it is set.
+// Numbering continues across recordings so that an earlier recording is never overwritten.
+pub struct SequenceCapture {
+    pub directory: String,
+    pub prefix: String,
+    pub format: ImageFormat,
+    pub interval: usize,
+    pub max_frames: Option<usize>,
+    recording: bool,
+    frame: usize,
+    next_index: usize,
+    pending: Vec<JobHandle>,
+}
+
+impl SequenceCapture {
+    // Creates a SequenceCapture that writes frames named prefix_00000 and onwards to a directory.
+    // It keeps every frame and isn't recording yet.
+    pub fn new(directory: &str, prefix: &str, format: ImageFormat) -> SequenceCapture {
+        SequenceCapture { directory: directory.to_string(), prefix: prefix.to_string(),
+                format: format, interval: 1, max_frames: None, recording: false, frame: 0,
+                next_index: 0, pending: Vec::new() }
+    }
+
+    // Starts recording, creating the directory if it doesn't exist.
+    pub fn start(&mut self) -> Result<(), String> {
+        try!(fs::create_dir_all(&self.directory).map_err(|e| e.to_string()));
+        self.recording = true;
+        self.frame = 0;
+        Ok(())
+    }
+
+    // Returns true if frames are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    // Gets the number of frames that have been captured.
+    pub fn get_frame_count(&self) -> usize {
+        self.next_index
+    }
+
+    // Gets the path of the file that a frame is written to.
+    pub fn get_path(&self, index: usize) -> String {
+        let mut path = PathBuf::from(&self.directory);
+        path.push(format!("{}_{:05}.{}", self.prefix, index, self.format.get_extension()));
+        path.to_string_lossy().into_owned()
+    }
+
+    // Captures the window's frame if recording and returns the path that it is written to. With a
+    // JobSystem the frame is written on a worker and failures are logged, and otherwise it is
+    // written before this returns. This must be called before the window's buffers are swapped.
+    pub fn capture(&mut self, window: &GameWindow, jobs: Option<&JobSystem>)
+            -> Result<Option<String>, String> {
+        if !self.recording { return Ok(None); }
+        let frame = self.frame;
+        let interval = cmp::max(self.interval, 1);
+        self.frame += 1;
+        if frame % interval != 0 { return Ok(None); }
+        if let Some(max_frames) = self.max_frames {
+            if frame / interval >= max_frames {
+                self.recording = false;
+                return Ok(None);
+            }
+        }
+
+        let path = self.get_path(self.next_index);
+        let image = window.read_screen();
+        let format = self.format;
+        match jobs {
+            Some(jobs) => {
+                let job_path = path.clone();
+                self.pending.retain(|handle| !handle.is_done());
+                self.pending.push(jobs.spawn(move || {
+                    if let Err(e) = write_image(&job_path, &image, format) {
+                        log_error!(Renderer, "Unable to write {}: {}", job_path, e);
+                    }
+                }));
+            },
+            None => try!(write_image(&path, &image, format)),
+        }
+        self.next_index += 1;
+        Ok(Some(path))
+    }
+
+    // Stops recording and waits for the frames that are still being written by a JobSystem,
+    // which must be the one that they were given to.
+    pub fn stop(&mut self, jobs: Option<&JobSystem>) -> Result<(), String> {
+        self.recording = false;
+        let pending = self.pending.drain(..).collect::<Vec<JobHandle>>();
+        match jobs {
+            Some(jobs) => jobs.wait_all(&pending),
+            None => Ok(()),
+        }
+    }
+}
@@ -6,6 +6,7 @@
 use gfx::types::*;
 
 // Represents a color in RGBA with intensity values from 0.0 to 1.0.
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Color {
     pub r: GLfloat,
     pub g: GLfloat,
@@ -0,0 +1,392 @@
+// Defines DebugDraw, an immediate mode facility for visualizing things in world space such as
+// lines, bounding boxes, spheres, camera frustums, transform axes, and text labels. Shapes are
+// added throughout a frame, batched into a single vertex buffer, and drawn with flush() which
+// issues at most two draw calls: one for shapes that are depth tested against the scene and one for
+// shapes drawn on top of everything.
+//
+// Labels are drawn with a small built-in stroke font made of line segments and always face the
+// camera. Only digits, letters (lowercase is drawn as uppercase), and common punctuation are
+// supported, and any other character is drawn as a box. The text of labels is kept in Strings
+// from a Pool so that labels added every frame reuse the same memory.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::{EuclideanVector, Matrix, SquareMatrix};
+use gfx::camera::Camera;
+use gfx::color;
+use gfx::game_window::GameWindow;
+use gfx::model;
+use gfx::types::*;
+use std::ffi::CString;
+use std::f32::consts::PI;
+use std::mem;
+use std::ptr;
+use util::alloc::Pool;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "debug.vert";
+const FRAGMENT_SHADER_NAME: &'static str = "debug.frag";
+
+// Contents of a single vertex in the debug vertex buffer.
+// [P_x  P_y  P_z  C_r  C_g  C_b  C_a]
+const DEBUG_POS_SIZE: usize = 3;
+const DEBUG_COLOR_SIZE: usize = 4;
+const DEBUG_VERTEX_SIZE: usize = DEBUG_POS_SIZE + DEBUG_COLOR_SIZE;
+
+// Number of line segments used to approximate each circle of a sphere.
+const SPHERE_SEGMENTS: usize = 32;
+
+// Size of a glyph in the stroke font relative to the label height. Glyphs are defined on a grid
+// that is 3 points wide and 5 points tall.
+const GLYPH_WIDTH: GLfloat = 0.6;
+const GLYPH_ADVANCE: GLfloat = 0.9;
+const GLYPH_GRID_X: GLfloat = 2.0;
+const GLYPH_GRID_Y: GLfloat = 4.0;
+
+// A text label waiting to be expanded into lines once the camera orientation is known.
+struct Label {
+    pos: Vector3D,
+    text: String,
+    color: [GLfloat; 4],
+    depth_test: bool,
+}
+
+// A batch of debug shapes for a frame. The label size is the height of a label's characters in
+// world units. This can only be created after the window context is set up.
+pub struct DebugDraw {
+    pub label_size: GLfloat,
+    depth_lines: Vec<GLfloat>,
+    overlay_lines: Vec<GLfloat>,
+    labels: Vec<Label>,
+    strings: Pool<String>,
+    program: GLuint,
+    vao: GLuint,
+    vbo: GLuint,
+    capacity: usize,
+}
+
+impl DebugDraw {
+    // Creates an empty DebugDraw along with its program and vertex buffer.
+    pub fn new() -> DebugDraw { unsafe {
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+        let mut vao = 0;
+        let mut vbo = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let pos_attr = gl::GetAttribLocation(program, gl_str!("position"));
+        gl::EnableVertexAttribArray(pos_attr as GLuint);
+        gl::VertexAttribPointer(
+                pos_attr as GLuint, DEBUG_POS_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(DEBUG_VERTEX_SIZE, GLsizei), ptr::null());
+        let color_attr = gl::GetAttribLocation(program, gl_str!("color"));
+        gl::EnableVertexAttribArray(color_attr as GLuint);
+        gl::VertexAttribPointer(
+                color_attr as GLuint, DEBUG_COLOR_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(DEBUG_VERTEX_SIZE, GLsizei), float_size!(DEBUG_POS_SIZE, CVoid));
+        gl::BindVertexArray(0);
+        DebugDraw { label_size: 0.25, depth_lines: Vec::new(), overlay_lines: Vec::new(),
+                labels: Vec::new(), strings: Pool::new(), program: program, vao: vao, vbo: vbo,
+                capacity: 0 }
+    }}
+
+    // Adds a line between two points. If depth_test is false, the line is drawn over the scene.
+    pub fn line(&mut self, start: Vector3D, end: Vector3D, color: &color::Color,
+            depth_test: bool) {
+        let lines = if depth_test { &mut self.depth_lines } else { &mut self.overlay_lines };
+        for point in &[start, end] {
+            lines.extend_from_slice(&[point.x, point.y, point.z, color.r, color.g, color.b,
+                    color.a]);
+        }
+    }
+
+    // Adds the outline of an axis aligned bounding box from its minimum and maximum corners.
+    pub fn aabb(&mut self, min: Vector3D, max: Vector3D, color: &color::Color, depth_test: bool) {
+        let corners = [
+                Vector3D::new(min.x, min.y, min.z), Vector3D::new(max.x, min.y, min.z),
+                Vector3D::new(max.x, max.y, min.z), Vector3D::new(min.x, max.y, min.z),
+                Vector3D::new(min.x, min.y, max.z), Vector3D::new(max.x, min.y, max.z),
+                Vector3D::new(max.x, max.y, max.z), Vector3D::new(min.x, max.y, max.z)];
+        self.box_edges(&corners, color, depth_test);
+    }
+
+    // Adds the outline of a sphere as three circles around its x, y, and z axes.
+    pub fn sphere(&mut self, center: Vector3D, radius: GLfloat, color: &color::Color,
+            depth_test: bool) {
+        let x = Vector3D::new(radius, 0.0, 0.0);
+        let y = Vector3D::new(0.0, radius, 0.0);
+        let z = Vector3D::new(0.0, 0.0, radius);
+        self.circle(center, x, y, color, depth_test);
+        self.circle(center, y, z, color, depth_test);
+        self.circle(center, z, x, color, depth_test);
+    }
+
+    // Adds the outline of the volume visible through a combined projection and view matrix. This
+    // does nothing if the matrix cannot be inverted.
+    pub fn frustum(&mut self, view_proj: &cgmath::Matrix4<GLfloat>, color: &color::Color,
+            depth_test: bool) {
+        let inv_view_proj = match view_proj.invert() {
+            Some(m) => m,
+            None => { return; },
+        };
+        let mut corners = [Vector3D::new(0.0, 0.0, 0.0); 8];
+        for (corner, &(x, y, z)) in corners.iter_mut().zip([(-1.0, -1.0, -1.0),
+                (1.0, -1.0, -1.0), (1.0, 1.0, -1.0), (-1.0, 1.0, -1.0), (-1.0, -1.0, 1.0),
+                (1.0, -1.0, 1.0), (1.0, 1.0, 1.0), (-1.0, 1.0, 1.0)].iter()) {
+            let world = inv_view_proj * cgmath::Vector4::new(x, y, z, 1.0);
+            *corner = Vector3D::new(world.x / world.w, world.y / world.w, world.z / world.w);
+        }
+        self.box_edges(&corners, color, depth_test);
+    }
+
+    // Adds the x, y, and z axes of a transform as red, green, and blue lines of a given length.
+    pub fn axes(&mut self, transform: &cgmath::Matrix4<GLfloat>, size: GLfloat, depth_test: bool) {
+        let origin = Vector3D::new(transform.w.x, transform.w.y, transform.w.z);
+        let colors = [color::Color::new_rgb(1.0, 0.0, 0.0), color::Color::new_rgb(0.0, 1.0, 0.0),
+                color::Color::new_rgb(0.0, 0.0, 1.0)];
+        let columns = [transform.x, transform.y, transform.z];
+        for (column, color) in columns.iter().zip(colors.iter()) {
+            let axis = Vector3D::new(column.x, column.y, column.z);
+            if axis.length2() == 0.0 { continue; }
+            self.line(origin, origin + axis.normalize() * size, color, depth_test);
+        }
+    }
+
+    // Adds every triangle edge of a ModelInstance transformed by its model matrix.
+    pub fn wireframe(&mut self, instance: &model::ModelInstance, color: &color::Color,
+            depth_test: bool) {
+        let vertices = &instance.info.vertices;
+        let point = |i: GLuint| {
+            let i = i as usize * 3;
+            let world = instance.model *
+                    cgmath::Vector4::new(vertices[i], vertices[i + 1], vertices[i + 2], 1.0);
+            Vector3D::new(world.x, world.y, world.z)
+        };
+        for triangle in instance.info.elements.chunks(3) {
+            if triangle.len() < 3 { break; }
+            let (a, b, c) = (point(triangle[0]), point(triangle[1]), point(triangle[2]));
+            self.line(a, b, color, depth_test);
+            self.line(b, c, color, depth_test);
+            self.line(c, a, color, depth_test);
+        }
+    }
+
+    // Adds a text label centered above a world position. The label always faces the camera.
+    pub fn label(&mut self, pos: Vector3D, text: &str, color: &color::Color, depth_test: bool) {
+        let mut string = self.strings.take();
+        string.push_str(text);
+        self.labels.push(Label { pos: pos, text: string,
+                color: [color.r, color.g, color.b, color.a], depth_test: depth_test });
+    }
+
+    // Gets the number of lines waiting to be drawn, not counting labels.
+    pub fn len(&self) -> usize {
+        (self.depth_lines.len() + self.overlay_lines.len()) / (DEBUG_VERTEX_SIZE * 2)
+    }
+
+    // Returns true if nothing is waiting to be drawn.
+    pub fn is_empty(&self) -> bool {
+        self.depth_lines.is_empty() && self.overlay_lines.is_empty() && self.labels.is_empty()
+    }
+
+    // Removes every shape without drawing it.
+    pub fn clear(&mut self) {
+        self.depth_lines.clear();
+        self.overlay_lines.clear();
+        for label in self.labels.drain(..) {
+            self.strings.give(label.text);
+        }
+    }
+
+    // Draws every shape from the active camera to the window's current render target and clears
+    // the batch for the next frame. Nothing is drawn if there is no active camera.
+    pub fn flush(&mut self, window: &mut GameWindow) {
+        let (view_proj, right, up) = match window.get_active_camera() {
+            Err(_) => { self.clear(); return; },
+            Ok(camera) => {
+                let view = camera.get_view_matrix();
+                (camera.get_projection_matrix() * view,
+                        Vector3D::new(view.x.x, view.y.x, view.z.x),
+                        Vector3D::new(view.x.y, view.y.y, view.z.y))
+            },
+        };
+        // The labels are swapped out while they are expanded since that adds to the lines, and
+        // their list is put back empty afterwards so that it keeps its memory.
+        let mut labels = mem::replace(&mut self.labels, Vec::new());
+        for label in labels.drain(..) {
+            self.label_lines(&label, right, up);
+            self.strings.give(label.text);
+        }
+        self.labels = labels;
+        if self.depth_lines.is_empty() && self.overlay_lines.is_empty() { return; }
+
+        let depth_count = self.depth_lines.len() / DEBUG_VERTEX_SIZE;
+        let overlay_count = self.overlay_lines.len() / DEBUG_VERTEX_SIZE;
+        self.depth_lines.extend_from_slice(&self.overlay_lines);
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            let count = depth_count + overlay_count;
+            if count > self.capacity {
+                self.capacity = count;
+                gl::BufferData(
+                        gl::ARRAY_BUFFER, float_size!(self.depth_lines.len(), GLsizeiptr),
+                        vec_to_addr!(self.depth_lines), gl::STREAM_DRAW);
+            } else {
+                gl::BufferSubData(
+                        gl::ARRAY_BUFFER, 0, float_size!(self.depth_lines.len(), GLsizeiptr),
+                        vec_to_addr!(self.depth_lines));
+            }
+
+            gl::UseProgram(self.program);
+            uniform_mat4!(self.program, "view_proj", view_proj);
+            uniform_float!(self.program, "gamma", window.get_gamma());
+            uniform_int!(self.program, "hdr_output", window.is_hdr_output() as GLint);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+            if depth_count > 0 {
+                gl::DrawArrays(gl::LINES, 0, depth_count as GLsizei);
+            }
+            if overlay_count > 0 {
+                gl::Disable(gl::DEPTH_TEST);
+                gl::DrawArrays(gl::LINES, depth_count as GLint, overlay_count as GLsizei);
+                gl::Enable(gl::DEPTH_TEST);
+            }
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+        for &count in &[depth_count, overlay_count] {
+            if count > 0 { window.record_draw(0, 0); }
+        }
+        self.depth_lines.clear();
+        self.overlay_lines.clear();
+        window.restore_state();
+    }
+
+    // Deletes the program and vertex buffer from the GPU.
+    pub fn delete(self) { unsafe {
+        gl::DeleteProgram(self.program);
+        gl::DeleteBuffers(1, &self.vbo);
+        gl::DeleteVertexArrays(1, &self.vao);
+    }}
+
+    // Adds the twelve edges of a box given its near face followed by its far face, with each face
+    // in winding order.
+    fn box_edges(&mut self, corners: &[Vector3D], color: &color::Color, depth_test: bool) {
+        for i in 0..4 {
+            let next = (i + 1) % 4;
+            self.line(corners[i], corners[next], color, depth_test);
+            self.line(corners[i + 4], corners[next + 4], color, depth_test);
+            self.line(corners[i], corners[i + 4], color, depth_test);
+        }
+    }
+
+    // Adds a circle spanned by two perpendicular vectors whose lengths are the radius.
+    fn circle(&mut self, center: Vector3D, u: Vector3D, v: Vector3D, color: &color::Color,
+            depth_test: bool) {
+        let point = |i: usize| {
+            let angle = 2.0 * PI * i as GLfloat / SPHERE_SEGMENTS as GLfloat;
+            center + u * angle.cos() + v * angle.sin()
+        };
+        for i in 0..SPHERE_SEGMENTS {
+            self.line(point(i), point(i + 1), color, depth_test);
+        }
+    }
+
+    // Expands a label into lines in the plane spanned by the camera's right and up vectors.
+    fn label_lines(&mut self, label: &Label, right: Vector3D, up: Vector3D) {
+        let size = self.label_size;
+        let color = color::Color::new(label.color[0], label.color[1], label.color[2],
+                label.color[3]);
+        let count = label.text.chars().count() as GLfloat;
+        let width = (count - 1.0) * GLYPH_ADVANCE + GLYPH_WIDTH;
+        let origin = label.pos + right * (-0.5 * width * size) + up * (0.5 * size);
+        for (i, c) in label.text.chars().enumerate() {
+            let glyph_origin = origin + right * (i as GLfloat * GLYPH_ADVANCE * size);
+            let point = |x: u8, y: u8| {
+                let x = (x - b'0') as GLfloat / GLYPH_GRID_X * GLYPH_WIDTH * size;
+                let y = (y - b'0') as GLfloat / GLYPH_GRID_Y * size;
+                glyph_origin + right * x + up * y
+            };
+            for stroke in glyph_strokes(c).split(' ') {
+                let s = stroke.as_bytes();
+                if s.len() != 4 { continue; }
+                self.line(point(s[0], s[1]), point(s[2], s[3]), &color, label.depth_test);
+            }
+        }
+    }
+}
+
+// Gets the strokes of a glyph in the built-in font. Each stroke is a line written as four digits
+// "x0y0x1y1" on a grid where x goes from 0 to 2 and y goes from 0 (bottom) to 4 (top).
+fn glyph_strokes(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        ' ' => "",
+        '0' => "0004 0424 2420 2000 0024",
+        '1' => "1014 1403 0020",
+        '2' => "0424 2422 2202 0200 0020",
+        '3' => "0424 2420 2000 0222",
+        '4' => "0402 0222 2420",
+        '5' | 'S' => "2404 0402 0222 2220 2000",
+        '6' => "2404 0400 0020 2022 2202",
+        '7' => "0424 2410",
+        '8' => "0004 0424 2420 2000 0222",
+        '9' => "2000 2024 2404 0402 0222",
+        'A' => "0004 0424 2420 0222",
+        'B' => "0004 0414 1423 2321 2110 1000 0222",
+        'C' => "2404 0400 0020",
+        'D' => "0004 0414 1423 2321 2110 1000",
+        'E' => "2404 0400 0020 0212",
+        'F' => "2404 0400 0212",
+        'G' => "2404 0400 0020 2022 2212",
+        'H' => "0004 2420 0222",
+        'I' => "0424 1014 0020",
+        'J' => "1424 2420 2000 0001",
+        'K' => "0004 0224 0220",
+        'L' => "0400 0020",
+        'M' => "0004 0412 1224 2420",
+        'N' => "0004 0420 2024",
+        'O' => "0004 0424 2420 2000",
+        'P' => "0004 0424 2422 2202",
+        'Q' => "0004 0424 2420 2000 1120",
+        'R' => "0004 0424 2422 2202 0220",
+        'T' => "0424 1410",
+        'U' => "0400 0020 2024",
+        'V' => "0410 1024",
+        'W' => "0400 0012 1220 2024",
+        'X' => "0024 0420",
+        'Y' => "0412 1224 1210",
+        'Z' => "0424 2400 0020",
+        '.' => "1011",
+        ',' => "1100",
+        ':' => "1011 1314",
+        ';' => "1314 1100",
+        '-' => "0222",
+        '+' => "0222 1113",
+        '=' => "0121 0323",
+        '*' => "0123 0321 1113",
+        '/' => "0024",
+        '\\' => "0420",
+        '(' => "1403 0301 0110",
+        ')' => "1423 2321 2110",
+        '[' => "1404 0400 0010",
+        ']' => "1424 2420 2010",
+        '<' => "2402 0220",
+        '>' => "0422 2200",
+        '_' => "0020",
+        '|' => "1014",
+        '!' => "1412 1011",
+        '?' => "0424 2423 2312 1211",
+        '\'' => "1413",
+        '"' => "0403 2423",
+        _ => "0004 0424 2420 2000",
+    }
+}
@@ -0,0 +1,288 @@
+// Defines ready made DebugUi windows for the parts of the engine that are most often tweaked or
+// watched while it runs: a graph of recent frame times along with the GPU time of each profiled
+// pass, the lights attached to the GameWindow, the passes of a PostProcessStack, and the nodes of
+// a Scene with their transforms and components. Components are shown as JSON through a
+// ComponentRegistry, so only registered components and ones that were loaded as raw JSON can be
+// inspected.
+//
+// Every panel is its own window, which starts at a different place along the top of the screen and
+// can then be dragged anywhere.
+//
+// Usage of the panels:
+// - Create a FrameTimeGraph and push() the length of every frame in milliseconds to it.
+// - Between ui.begin_frame() and ui.end_frame(), call the panels that should be shown, such as
+//   frame_panel(&mut ui, &graph, profiler.get_stats()) and light_panel(&mut ui, &mut window).
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::debug_ui::DebugUi;
+use gfx::game_window::GameWindow;
+use gfx::postprocess::{PostProcessStack, Tonemapper};
+use gfx::scene::{NodeId, Scene};
+use gfx::scene_io::ComponentRegistry;
+use gfx::stats::FrameStats;
+use gfx::types::*;
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use util::json::Value;
+
+// The default number of frames that a FrameTimeGraph shows.
+pub const DEFAULT_GRAPH_SAMPLES: usize = 240;
+
+// The width of every panel in pixels.
+const PANEL_WIDTH: GLfloat = 280.0;
+
+// Where the panels start, in pixels from the top left of the screen.
+const FRAME_PANEL_POS: (GLfloat, GLfloat) = (10.0, 10.0);
+const POST_PANEL_POS: (GLfloat, GLfloat) = (300.0, 10.0);
+const LIGHT_PANEL_POS: (GLfloat, GLfloat) = (590.0, 10.0);
+const SCENE_PANEL_POS: (GLfloat, GLfloat) = (880.0, 10.0);
+
+// The height in pixels of the frame time graph, and the least time in milliseconds that its top
+// stands for, which is two frames at 60 Hz.
+const GRAPH_HEIGHT: GLfloat = 60.0;
+const MIN_GRAPH_TOP: GLfloat = 33.3;
+
+// The brightest that a light's color can be set to.
+const MAX_INTENSITY: GLfloat = 10.0;
+
+// How much values change for every pixel that they are dragged.
+const POSITION_SPEED: GLfloat = 0.05;
+const DIRECTION_SPEED: GLfloat = 0.01;
+const SCALE_SPEED: GLfloat = 0.01;
+const ATTENUATION_SPEED: GLfloat = 0.001;
+
+// The lengths of the most recent frames in milliseconds, oldest first.
+pub struct FrameTimeGraph {
+    pub capacity: usize,
+    samples: VecDeque<GLfloat>,
+}
+
+impl FrameTimeGraph {
+    // Creates an empty graph that keeps a number of frames.
+    pub fn new(capacity: usize) -> FrameTimeGraph {
+        FrameTimeGraph { capacity: capacity, samples: VecDeque::new() }
+    }
+
+    // Adds the length of a frame, dropping the oldest ones that no longer fit.
+    pub fn push(&mut self, milliseconds: GLfloat) {
+        self.samples.push_back(milliseconds);
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    // Gets the frames in order from oldest to newest.
+    pub fn get_samples(&self) -> Vec<GLfloat> {
+        self.samples.iter().cloned().collect()
+    }
+
+    // Gets the average length of the frames, or 0 if there are none.
+    pub fn get_average(&self) -> GLfloat {
+        if self.samples.is_empty() { return 0.0; }
+        self.samples.iter().fold(0.0, |sum, s| sum + s) / self.samples.len() as GLfloat
+    }
+
+    // Gets the length of the longest frame, or 0 if there are none.
+    pub fn get_max(&self) -> GLfloat {
+        self.samples.iter().fold(0.0, |max: GLfloat, &s| max.max(s))
+    }
+}
+
+// Shows drags for the three components of a vector. Returns true if any of them changed.
+pub fn vector_drag(ui: &mut DebugUi, label: &str, vector: &mut Vector3D, speed: GLfloat) -> bool {
+    ui.label(label);
+    ui.push_id(label);
+    let mut changed = ui.drag("X", &mut vector.x, speed);
+    changed |= ui.drag("Y", &mut vector.y, speed);
+    changed |= ui.drag("Z", &mut vector.z, speed);
+    ui.pop_id();
+    changed
+}
+
+// Shows a JSON value as a tree, where objects and arrays are nodes that can be opened.
+pub fn json_tree(ui: &mut DebugUi, label: &str, value: &Value) {
+    match *value {
+        Value::Object(ref fields) => {
+            if ui.tree_node(label) {
+                for &(ref key, ref field) in fields {
+                    json_tree(ui, key, field);
+                }
+                ui.tree_pop();
+            }
+        },
+        Value::Array(ref items) => {
+            if ui.tree_node(label) {
+                for (i, item) in items.iter().enumerate() {
+                    json_tree(ui, &format!("[{}]", i), item);
+                }
+                ui.tree_pop();
+            }
+        },
+        Value::Null => ui.value(label, "null"),
+        Value::Bool(b) => ui.value(label, &b.to_string()),
+        Value::Number(n) => ui.value(label, &n.to_string()),
+        Value::String(ref s) => ui.value(label, s),
+    }
+}
+
+// Shows the frame time graph along with the CPU and GPU times and render counters of the last
+// profiled frame. Passes are listed by their GPU time and indented by how deeply they are nested.
+pub fn frame_panel(ui: &mut DebugUi, graph: &FrameTimeGraph, stats: &FrameStats) {
+    if ui.begin_window("Frame", FRAME_PANEL_POS.0, FRAME_PANEL_POS.1, PANEL_WIDTH) {
+        let max = graph.get_max();
+        let label = format!("{:.2} ms avg, {:.2} ms max", graph.get_average(), max);
+        ui.plot(&label, &graph.get_samples(), 0.0, max.max(MIN_GRAPH_TOP), GRAPH_HEIGHT);
+        ui.value("CPU", &format!("{:.2} ms", stats.cpu_time));
+        ui.value("GPU", &format!("{:.2} ms", stats.gpu_time));
+        ui.value("Draw calls", &stats.counters.draw_calls.to_string());
+        ui.value("Instances", &stats.counters.instances.to_string());
+        ui.value("Triangles", &stats.counters.triangles.to_string());
+        if !stats.passes.is_empty() && ui.tree_node("Passes") {
+            for (i, pass) in stats.passes.iter().enumerate() {
+                let mut name = String::new();
+                for _ in 0..pass.depth { name.push_str("  "); }
+                name.push_str(&format!("{}##{}", pass.name, i));
+                ui.value(&name, &format!("{:.2} ms", pass.gpu_time));
+            }
+            ui.tree_pop();
+        }
+    }
+    ui.end_window();
+}
+
+// Shows the switches and settings of every pass of a post-processing stack.
+pub fn post_panel(ui: &mut DebugUi, stack: &mut PostProcessStack) {
+    if ui.begin_window("Post-processing", POST_PANEL_POS.0, POST_PANEL_POS.1, PANEL_WIDTH) {
+        ui.checkbox("Bloom", &mut stack.bloom_enabled);
+        if stack.bloom_enabled {
+            ui.slider("Threshold", &mut stack.bloom_threshold, 0.0, 4.0);
+            ui.slider("Knee", &mut stack.bloom_knee, 0.0, 1.0);
+            ui.slider("Intensity", &mut stack.bloom_intensity, 0.0, 2.0);
+        }
+        ui.separator();
+        ui.checkbox("Tonemapping", &mut stack.tonemap_enabled);
+        if stack.tonemap_enabled {
+            ui.slider("Exposure", &mut stack.exposure, 0.0, 8.0);
+            let name = match stack.tonemapper {
+                Tonemapper::Reinhard => "Tonemapper: Reinhard##tonemapper",
+                Tonemapper::ACES => "Tonemapper: ACES##tonemapper",
+            };
+            if ui.button(name) {
+                stack.tonemapper = match stack.tonemapper {
+                    Tonemapper::Reinhard => Tonemapper::ACES,
+                    Tonemapper::ACES => Tonemapper::Reinhard,
+                };
+            }
+        }
+        ui.separator();
+        ui.checkbox("FXAA", &mut stack.fxaa_enabled);
+    }
+    ui.end_window();
+}
+
+// Shows every light attached to a window in a tree node of its own, and updates the lights that
+// are changed.
+pub fn light_panel(ui: &mut DebugUi, window: &mut GameWindow) {
+    if ui.begin_window("Lights", LIGHT_PANEL_POS.0, LIGHT_PANEL_POS.1, PANEL_WIDTH) {
+        let points = window.get_point_light_handles();
+        let directionals = window.get_directional_light_handles();
+        let spots = window.get_spot_light_handles();
+        if points.is_empty() && directionals.is_empty() && spots.is_empty() {
+            ui.label("No lights are attached.");
+        }
+        for handle in points {
+            if !ui.tree_node(&format!("Point light {}", handle)) { continue; }
+            let changed = {
+                let light = window.get_point_light_mut(handle);
+                let mut changed = ui.color_edit("Intensity", &mut light.intensity, MAX_INTENSITY);
+                changed |= vector_drag(ui, "Position", &mut light.position, POSITION_SPEED);
+                changed |= ui.drag("Linear attenuation", &mut light.linear_attn,
+                        ATTENUATION_SPEED);
+                changed |= ui.drag("Quadratic attenuation", &mut light.quad_attn,
+                        ATTENUATION_SPEED);
+                changed
+            };
+            if changed { window.update_point_light(handle); }
+            ui.tree_pop();
+        }
+        for handle in directionals {
+            if !ui.tree_node(&format!("Directional light {}", handle)) { continue; }
+            let changed = {
+                let light = window.get_directional_light_mut(handle);
+                let mut changed = ui.color_edit("Intensity", &mut light.intensity, MAX_INTENSITY);
+                changed |= vector_drag(ui, "Direction", &mut light.direction, DIRECTION_SPEED);
+                changed
+            };
+            if changed { window.update_directional_light(handle); }
+            ui.tree_pop();
+        }
+        for handle in spots {
+            if !ui.tree_node(&format!("Spot light {}", handle)) { continue; }
+            let changed = {
+                let light = window.get_spot_light_mut(handle);
+                let mut changed = ui.color_edit("Intensity", &mut light.intensity, MAX_INTENSITY);
+                changed |= vector_drag(ui, "Position", &mut light.position, POSITION_SPEED);
+                changed |= vector_drag(ui, "Direction", &mut light.direction, DIRECTION_SPEED);
+                changed |= ui.slider("Cutoff", &mut light.cutoff, 0.0, PI * 0.5);
+                changed |= ui.slider("Dropoff", &mut light.dropoff, 0.0, 64.0);
+                changed
+            };
+            if changed { window.update_spot_light(handle); }
+            ui.tree_pop();
+        }
+    }
+    ui.end_window();
+}
+
+// Shows the nodes of a scene as a tree. Each node can be opened to edit its transform and inspect
+// its components and children.
+pub fn scene_panel(ui: &mut DebugUi, scene: &mut Scene, registry: &ComponentRegistry) {
+    if ui.begin_window("Scene", SCENE_PANEL_POS.0, SCENE_PANEL_POS.1, PANEL_WIDTH) {
+        ui.value("Nodes", &scene.len().to_string());
+        let roots = scene.get_roots().clone();
+        for id in roots {
+            node_tree(ui, scene, registry, id);
+        }
+    }
+    ui.end_window();
+}
+
+// Shows a node and, if it is open, its transform, components, and children.
+fn node_tree(ui: &mut DebugUi, scene: &mut Scene, registry: &ComponentRegistry, id: NodeId) {
+    let (label, children) = match scene.get_node(id) {
+        Some(node) => (format!("{}##{}", node.name, id), node.get_children().clone()),
+        None => return,
+    };
+    if !ui.tree_node(&label) { return; }
+    if ui.tree_node("Transform") {
+        let transform = &mut scene.get_node_mut(id).unwrap().transform;
+        vector_drag(ui, "Position", &mut transform.pos, POSITION_SPEED);
+        vector_drag(ui, "Scale", &mut transform.scale, SCALE_SPEED);
+        let rot = transform.rot;
+        ui.value("Rotation", &format!("{:.2} {:.2} {:.2} {:.2}", rot.s, rot.v.x, rot.v.y,
+                rot.v.z));
+        ui.tree_pop();
+    }
+    {
+        let node = scene.get_node(id).unwrap();
+        if let Some(ref path) = node.mesh_path {
+            ui.value("Mesh", path);
+        } else if node.mesh.is_some() {
+            ui.value("Mesh", "(not from a file)");
+        }
+        let mut names: Vec<&String> = node.components.keys().collect();
+        names.sort();
+        for name in names {
+            match registry.save(name, &*node.components[name]) {
+                Some(value) => json_tree(ui, name, &value),
+                None => ui.value(name, "(not registered)"),
+            }
+        }
+    }
+    for child in children {
+        node_tree(ui, scene, registry, child);
+    }
+    ui.tree_pop();
+}
@@ -0,0 +1,566 @@
+// Defines DebugUi, an immediate mode GUI for tweaking and inspecting the engine while it runs.
+// Widgets are functions that are called every frame with the values they edit, such as
+// slider("Exposure", &mut stack.exposure, 0.0, 8.0), and return whether the user changed them, so
+// there is no state to keep in sync with the game. Widgets are laid out top to bottom in windows
+// that can be dragged by their title bars and collapsed, and the window that is clicked is brought
+// to the front.
+//
+// Every widget is identified by its label along with the window and open tree nodes that it is in.
+// Labels that would otherwise repeat can be told apart with a suffix after "##", which isn't shown,
+// or by wrapping them in push_id() and pop_id(). Everything is drawn with a SpriteBatch for the
+// boxes and a TextRenderer for the text, with two draw calls per window so that windows in front
+// cover the text of the ones behind them.
+//
+// Usage of a DebugUi:
+// - let mut ui = DebugUi::new(try!(Font::from_ttf("fonts/mono.ttf", 14.0))) once the window is
+//   set up.
+// - ui.begin_frame(&input) every frame after the input is updated.
+// - if ui.begin_window("Lights", 10.0, 10.0, 260.0) { ui.slider(...); ... } then ui.end_window().
+// - Skip game input that uses the mouse when ui.wants_mouse() is true.
+// - ui.end_frame(&mut window) after the scene and any post-processing are drawn.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::color::Color;
+use gfx::game_window::GameWindow;
+use gfx::sprite::{Sprite, SpriteBatch, TextureRegion};
+use gfx::text::{Font, TextRenderer};
+use gfx::types::*;
+use platform::input::Input;
+use platform::window::MouseButton;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+// Space in pixels around the contents of a window, between widgets, and inside a widget's row.
+const WINDOW_PADDING: GLfloat = 6.0;
+const WIDGET_SPACING: GLfloat = 4.0;
+const ROW_PADDING: GLfloat = 2.0;
+
+// How far the contents of an open tree node are indented in pixels.
+const TREE_INDENT: GLfloat = 12.0;
+
+// The smallest width in pixels that a widget's contents are squeezed to by indentation.
+const MIN_CONTENT_WIDTH: GLfloat = 40.0;
+
+// The colors of the parts of the UI. Colors are linear and are gamma corrected when drawn.
+const WINDOW_COLOR: Color = Color { r: 0.01, g: 0.01, b: 0.012, a: 0.88 };
+const TITLE_COLOR: Color = Color { r: 0.03, g: 0.06, b: 0.15, a: 0.95 };
+const FRAME_COLOR: Color = Color { r: 0.04, g: 0.04, b: 0.05, a: 1.0 };
+const HOVERED_COLOR: Color = Color { r: 0.08, g: 0.08, b: 0.1, a: 1.0 };
+const FILL_COLOR: Color = Color { r: 0.1, g: 0.25, b: 0.6, a: 1.0 };
+const ACTIVE_COLOR: Color = Color { r: 0.15, g: 0.35, b: 0.8, a: 1.0 };
+const TEXT_COLOR: Color = Color { r: 0.9, g: 0.9, b: 0.9, a: 1.0 };
+const DIM_TEXT_COLOR: Color = Color { r: 0.4, g: 0.4, b: 0.4, a: 1.0 };
+
+// A rectangle in pixels from the top left of the render target.
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Rect {
+    x: GLfloat,
+    y: GLfloat,
+    width: GLfloat,
+    height: GLfloat,
+}
+
+impl Rect {
+    // Returns true if a point is inside the rectangle.
+    fn contains(&self, x: GLfloat, y: GLfloat) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+// A window along with what was added to it this frame, which is kept until end_frame() so that
+// windows are drawn in order from back to front.
+struct Window {
+    x: GLfloat,
+    y: GLfloat,
+    width: GLfloat,
+    height: GLfloat,
+    collapsed: bool,
+    used: bool,
+    visible: bool,
+    rects: Vec<(Rect, Color)>,
+    texts: Vec<(String, GLfloat, GLfloat, Color)>,
+}
+
+// How a widget's rectangle was used by the mouse this frame.
+struct Interaction {
+    hovered: bool,
+    held: bool,
+    clicked: bool,
+}
+
+// An immediate mode GUI that is drawn over the scene. The text scale multiplies the size of the
+// font. This can only be created after the window context is set up.
+pub struct DebugUi {
+    pub font: Font,
+    pub text_scale: GLfloat,
+    sprites: SpriteBatch,
+    text: TextRenderer,
+    white_texture: GLuint,
+    windows: HashMap<String, Window>,
+    order: Vec<String>,
+    current: Option<(String, Window)>,
+    cursor_y: GLfloat,
+    indent: GLfloat,
+    ids: Vec<String>,
+    open: HashSet<String>,
+    active: Option<String>,
+    hovered_window: Option<String>,
+    mouse: Option<(GLfloat, GLfloat)>,
+    mouse_delta: (GLfloat, GLfloat),
+    mouse_down: bool,
+    mouse_pressed: bool,
+    mouse_released: bool,
+}
+
+// Gets the part of a label that is shown, which is everything before a "##".
+fn display_label(label: &str) -> &str {
+    label.split("##").next().unwrap_or("")
+}
+
+// Clamps a value to a range.
+fn clamp(value: GLfloat, min: GLfloat, max: GLfloat) -> GLfloat {
+    if value < min { min } else if value > max { max } else { value }
+}
+
+impl DebugUi {
+    // Creates a DebugUi that draws its text with a font, along with the texture that its boxes
+    // are drawn with.
+    pub fn new(font: Font) -> DebugUi {
+        let mut white_texture = 0;
+        unsafe {
+            let pixel: Vec<u8> = vec![255, 255, 255, 255];
+            gl::GenTextures(1, &mut white_texture);
+            gl::BindTexture(gl::TEXTURE_2D, white_texture);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, 1, 1, 0, gl::RGBA, gl::UNSIGNED_BYTE,
+                vec_to_addr!(pixel));
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        DebugUi { font: font, text_scale: 1.0, sprites: SpriteBatch::new(),
+                text: TextRenderer::new(), white_texture: white_texture, windows: HashMap::new(),
+                order: Vec::new(), current: None, cursor_y: 0.0, indent: 0.0, ids: Vec::new(),
+                open: HashSet::new(), active: None, hovered_window: None, mouse: None,
+                mouse_delta: (0.0, 0.0), mouse_down: false, mouse_pressed: false,
+                mouse_released: false }
+    }
+
+    // Starts a frame with the state of the mouse. The window under the cursor is found from where
+    // the windows were last frame, and is brought to the front if it was clicked.
+    pub fn begin_frame(&mut self, input: &Input) {
+        let mouse = input.get_cursor().map(|(x, y)| (x as GLfloat, y as GLfloat));
+        self.mouse_delta = match (self.mouse, mouse) {
+            (Some(last), Some(now)) => (now.0 - last.0, now.1 - last.1),
+            _ => (0.0, 0.0),
+        };
+        self.mouse = mouse;
+        self.mouse_down = input.is_button_down(MouseButton::Left);
+        self.mouse_pressed = input.was_button_pressed(MouseButton::Left);
+        self.mouse_released = input.was_button_released(MouseButton::Left);
+
+        self.hovered_window = None;
+        if let Some((mx, my)) = self.mouse {
+            for title in self.order.iter().rev() {
+                let window = match self.windows.get(title) {
+                    Some(window) => window,
+                    None => { continue; },
+                };
+                let rect = Rect { x: window.x, y: window.y, width: window.width,
+                        height: window.height };
+                if window.visible && rect.contains(mx, my) {
+                    self.hovered_window = Some(title.clone());
+                    break;
+                }
+            }
+        }
+        if self.mouse_pressed {
+            if let Some(ref title) = self.hovered_window {
+                self.order.retain(|t| t != title);
+                self.order.push(title.clone());
+            }
+        }
+    }
+
+    // Returns true if the mouse is over the UI or dragging one of its widgets, in which case the
+    // game should ignore the mouse.
+    pub fn wants_mouse(&self) -> bool {
+        self.hovered_window.is_some() || self.active.is_some()
+    }
+
+    // Starts a window with a title, which also identifies it. The position is only used the first
+    // time that the window is shown, after which it stays wherever it is dragged. Returns false if
+    // the window is collapsed, in which case no widgets should be added. end_window() must be
+    // called either way.
+    pub fn begin_window(&mut self, title: &str, x: GLfloat, y: GLfloat, width: GLfloat) -> bool {
+        if self.current.is_some() {
+            log_warn!(Renderer, "Window {} was started before the last one was ended.", title);
+            self.end_window();
+        }
+        let mut window = self.windows.remove(title).unwrap_or_else(|| {
+            Window { x: x, y: y, width: width, height: 0.0, collapsed: false, used: false,
+                    visible: false, rects: Vec::new(), texts: Vec::new() }
+        });
+        if window.used {
+            log_warn!(Renderer, "Window {} was shown more than once in a frame.", title);
+        }
+        window.width = width;
+        window.used = true;
+        window.rects.clear();
+        window.texts.clear();
+        if !self.order.iter().any(|t| t == title) {
+            self.order.push(title.to_string());
+        }
+        let (window_x, window_y) = (window.x, window.y);
+        self.ids = vec![title.to_string()];
+        self.indent = 0.0;
+        self.current = Some((title.to_string(), window));
+
+        // The title bar moves the window when dragged and the box at its left collapses it.
+        let row = self.get_row_height();
+        let (mut wx, mut wy) = (window_x, window_y);
+        let toggle = self.interact("##collapse", Rect { x: wx, y: wy, width: row, height: row });
+        let collapsed = {
+            let window = &mut self.current.as_mut().unwrap().1;
+            if toggle.clicked { window.collapsed = !window.collapsed; }
+            window.collapsed
+        };
+        let bar = self.interact("##title", Rect { x: wx + row, y: wy, width: width - row,
+                height: row });
+        if bar.held {
+            wx += self.mouse_delta.0;
+            wy += self.mouse_delta.1;
+            let window = &mut self.current.as_mut().unwrap().1;
+            window.x = wx;
+            window.y = wy;
+        }
+        self.push_rect(Rect { x: wx, y: wy, width: width, height: row }, TITLE_COLOR);
+        self.push_text(if collapsed { "+" } else { "-" }, wx + WINDOW_PADDING, wy + ROW_PADDING,
+                if toggle.hovered { TEXT_COLOR } else { DIM_TEXT_COLOR });
+        self.push_text(display_label(title), wx + row, wy + ROW_PADDING, TEXT_COLOR);
+        self.cursor_y = wy + row + WINDOW_PADDING;
+        !collapsed
+    }
+
+    // Ends the current window, which is then sized to fit its widgets.
+    pub fn end_window(&mut self) {
+        let (title, mut window) = match self.current.take() {
+            Some(current) => current,
+            None => {
+                log_warn!(Renderer, "A window was ended without being started.");
+                return;
+            },
+        };
+        let row = self.get_row_height();
+        window.height = if window.collapsed {
+            row
+        } else {
+            self.cursor_y - window.y - WIDGET_SPACING + WINDOW_PADDING
+        };
+        let background = Rect { x: window.x, y: window.y, width: window.width,
+                height: window.height };
+        window.rects.insert(0, (background, WINDOW_COLOR));
+        self.windows.insert(title, window);
+        self.ids.clear();
+    }
+
+    // Draws every window that was shown this frame over the window's current render target, from
+    // back to front.
+    pub fn end_frame(&mut self, window: &mut GameWindow) {
+        if self.current.is_some() {
+            log_warn!(Renderer, "The last window of the frame was never ended.");
+            self.end_window();
+        }
+        let region = TextureRegion::new(self.white_texture);
+        for title in &self.order {
+            let ui_window = match self.windows.get_mut(title) {
+                Some(ui_window) => ui_window,
+                None => { continue; },
+            };
+            ui_window.visible = ui_window.used;
+            ui_window.used = false;
+            if !ui_window.visible { continue; }
+            for &(rect, color) in &ui_window.rects {
+                let mut sprite = Sprite::new(region, rect.x, rect.y, rect.width, rect.height);
+                sprite.pivot = (0.0, 0.0);
+                sprite.tint = color;
+                self.sprites.draw(&sprite);
+            }
+            self.sprites.flush(window);
+            for &(ref text, x, y, ref color) in &ui_window.texts {
+                self.text.draw_text(&mut self.font, text, x, y, self.text_scale, color);
+            }
+            self.text.flush(window);
+        }
+        if !self.mouse_down {
+            self.active = None;
+        }
+    }
+
+    // Adds a segment to the ids of the widgets that follow until pop_id(), so that widgets with
+    // the same labels can be shown for different things, such as one per light.
+    pub fn push_id(&mut self, id: &str) {
+        self.ids.push(id.to_string());
+    }
+
+    // Removes the segment added by the last push_id().
+    pub fn pop_id(&mut self) {
+        if self.ids.len() > 1 {
+            self.ids.pop();
+        }
+    }
+
+    // Shows a line of text.
+    pub fn label(&mut self, text: &str) {
+        let (_, height) = self.font.measure(text);
+        let height = height * self.text_scale + 2.0 * ROW_PADDING;
+        if let Some(rect) = self.next_row(height) {
+            self.push_text(text, rect.x, rect.y + ROW_PADDING, TEXT_COLOR);
+        }
+    }
+
+    // Shows a label on the left and a value on the right of the same line.
+    pub fn value(&mut self, label: &str, value: &str) {
+        let row = self.get_row_height();
+        if let Some(rect) = self.next_row(row) {
+            let width = self.font.measure(value).0 * self.text_scale;
+            self.push_text(display_label(label), rect.x, rect.y + ROW_PADDING, DIM_TEXT_COLOR);
+            self.push_text(value, rect.x + rect.width - width, rect.y + ROW_PADDING, TEXT_COLOR);
+        }
+    }
+
+    // Shows a button that fits its label. Returns true if it was clicked.
+    pub fn button(&mut self, label: &str) -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let text = display_label(label);
+        let width = self.font.measure(text).0 * self.text_scale + 2.0 * WINDOW_PADDING;
+        let button = Rect { x: rect.x, y: rect.y, width: width.min(rect.width), height: row };
+        let state = self.interact(label, button);
+        self.push_rect(button, if state.held {
+            ACTIVE_COLOR
+        } else if state.hovered {
+            FILL_COLOR
+        } else {
+            HOVERED_COLOR
+        });
+        self.push_text(text, rect.x + WINDOW_PADDING, rect.y + ROW_PADDING, TEXT_COLOR);
+        state.clicked
+    }
+
+    // Shows a box that toggles a value when it or its label is clicked. Returns true if the value
+    // changed.
+    pub fn checkbox(&mut self, label: &str, value: &mut bool) -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let state = self.interact(label, rect);
+        if state.clicked { *value = !*value; }
+        let size = row - 2.0 * ROW_PADDING;
+        let check = Rect { x: rect.x, y: rect.y + ROW_PADDING, width: size, height: size };
+        self.push_rect(check, if state.hovered { HOVERED_COLOR } else { FRAME_COLOR });
+        if *value {
+            let inset = (size * 0.25).floor();
+            self.push_rect(Rect { x: check.x + inset, y: check.y + inset,
+                    width: size - 2.0 * inset, height: size - 2.0 * inset }, ACTIVE_COLOR);
+        }
+        self.push_text(display_label(label), rect.x + row, rect.y + ROW_PADDING, TEXT_COLOR);
+        state.clicked
+    }
+
+    // Shows a slider that sets a value between a minimum and maximum to where it is clicked or
+    // dragged. Returns true if the value changed.
+    pub fn slider(&mut self, label: &str, value: &mut GLfloat, min: GLfloat, max: GLfloat)
+            -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let state = self.interact(label, rect);
+        let old = *value;
+        if state.held && max > min {
+            if let Some((mx, _)) = self.mouse {
+                *value = min + clamp((mx - rect.x) / rect.width, 0.0, 1.0) * (max - min);
+            }
+        }
+        let fraction = if max > min { clamp((*value - min) / (max - min), 0.0, 1.0) } else { 0.0 };
+        self.push_rect(rect, if state.hovered { HOVERED_COLOR } else { FRAME_COLOR });
+        self.push_rect(Rect { x: rect.x, y: rect.y, width: rect.width * fraction, height: row },
+                if state.held { ACTIVE_COLOR } else { FILL_COLOR });
+        let text = format!("{}: {:.3}", display_label(label), *value);
+        self.push_text(&text, rect.x + WINDOW_PADDING, rect.y + ROW_PADDING, TEXT_COLOR);
+        *value != old
+    }
+
+    // Shows a value that changes by a speed for every pixel that the mouse is dragged to the right,
+    // for values without a range such as positions. Returns true if the value changed.
+    pub fn drag(&mut self, label: &str, value: &mut GLfloat, speed: GLfloat) -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let state = self.interact(label, rect);
+        let old = *value;
+        if state.held {
+            *value += self.mouse_delta.0 * speed;
+        }
+        self.push_rect(rect, if state.held {
+            FILL_COLOR
+        } else if state.hovered {
+            HOVERED_COLOR
+        } else {
+            FRAME_COLOR
+        });
+        let text = format!("{}: {:.3}", display_label(label), *value);
+        self.push_text(&text, rect.x + WINDOW_PADDING, rect.y + ROW_PADDING, TEXT_COLOR);
+        *value != old
+    }
+
+    // Shows a swatch of a color along with sliders for its red, green, and blue up to a maximum,
+    // which can be above 1 for light intensities. Returns true if the color changed.
+    pub fn color_edit(&mut self, label: &str, color: &mut Color, max: GLfloat) -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let brightest = color.r.max(color.g).max(color.b);
+        let scale = if brightest > 1.0 { 1.0 / brightest } else { 1.0 };
+        let swatch = Color::new(color.r * scale, color.g * scale, color.b * scale, 1.0);
+        self.push_text(display_label(label), rect.x, rect.y + ROW_PADDING, TEXT_COLOR);
+        self.push_rect(Rect { x: rect.x + rect.width - 2.0 * row, y: rect.y + ROW_PADDING,
+                width: 2.0 * row, height: row - 2.0 * ROW_PADDING }, swatch);
+        self.push_id(label);
+        let mut changed = self.slider("R", &mut color.r, 0.0, max);
+        changed |= self.slider("G", &mut color.g, 0.0, max);
+        changed |= self.slider("B", &mut color.b, 0.0, max);
+        self.pop_id();
+        changed
+    }
+
+    // Shows values as a bar graph of a height in pixels, with the label in its top left. Values
+    // outside of the range are clamped, and values are squeezed to fit the width.
+    pub fn plot(&mut self, label: &str, values: &[GLfloat], min: GLfloat, max: GLfloat,
+            height: GLfloat) {
+        let rect = match self.next_row(height) { Some(rect) => rect, None => return };
+        self.push_rect(rect, FRAME_COLOR);
+        if !values.is_empty() && max > min {
+            let bar_width = rect.width / values.len() as GLfloat;
+            for (i, &value) in values.iter().enumerate() {
+                let fraction = clamp((value - min) / (max - min), 0.0, 1.0);
+                if fraction <= 0.0 { continue; }
+                let bar_height = fraction * height;
+                self.push_rect(Rect { x: rect.x + i as GLfloat * bar_width,
+                        y: rect.y + height - bar_height, width: bar_width, height: bar_height },
+                        FILL_COLOR);
+            }
+        }
+        self.push_text(display_label(label), rect.x + ROW_PADDING, rect.y + ROW_PADDING,
+                TEXT_COLOR);
+    }
+
+    // Shows a node of a tree that opens and closes when clicked. Returns true if it is open, in
+    // which case its contents should be added, indented, followed by tree_pop().
+    pub fn tree_node(&mut self, label: &str) -> bool {
+        let row = self.get_row_height();
+        let rect = match self.next_row(row) { Some(rect) => rect, None => return false };
+        let id = self.get_id(label);
+        let state = self.interact(label, rect);
+        if state.clicked && !self.open.remove(&id) {
+            self.open.insert(id.clone());
+        }
+        let open = self.open.contains(&id);
+        if state.hovered {
+            self.push_rect(rect, HOVERED_COLOR);
+        }
+        self.push_text(if open { "-" } else { "+" }, rect.x, rect.y + ROW_PADDING,
+                DIM_TEXT_COLOR);
+        self.push_text(display_label(label), rect.x + TREE_INDENT, rect.y + ROW_PADDING,
+                TEXT_COLOR);
+        if open {
+            self.push_id(label);
+            self.indent += TREE_INDENT;
+        }
+        open
+    }
+
+    // Ends the contents of a tree node that tree_node() opened.
+    pub fn tree_pop(&mut self) {
+        self.pop_id();
+        self.indent = (self.indent - TREE_INDENT).max(0.0);
+    }
+
+    // Shows a horizontal line between groups of widgets.
+    pub fn separator(&mut self) {
+        if let Some(rect) = self.next_row(1.0) {
+            self.push_rect(rect, DIM_TEXT_COLOR);
+        }
+    }
+
+    // Deletes the texture and the renderers' programs and buffers from the GPU.
+    pub fn delete(self) {
+        unsafe { gl::DeleteTextures(1, &self.white_texture); }
+        self.sprites.delete();
+        self.text.delete();
+        self.font.delete();
+    }
+
+    // Gets the height of a row of a single line of text.
+    fn get_row_height(&self) -> GLfloat {
+        (self.font.line_height * self.text_scale).ceil() + 2.0 * ROW_PADDING
+    }
+
+    // Gets the id of a widget from its label and where it is.
+    fn get_id(&self, label: &str) -> String {
+        let mut id = self.ids.join("/");
+        id.push('/');
+        id.push_str(label);
+        id
+    }
+
+    // Takes the space for the next widget in the current window and returns it. Returns None if
+    // there isn't a window.
+    fn next_row(&mut self, height: GLfloat) -> Option<Rect> {
+        let (x, width) = match self.current {
+            Some((_, ref window)) => (window.x, window.width),
+            None => {
+                log_warn!(Renderer, "A widget was added outside of a window.");
+                return None;
+            },
+        };
+        let content = (width - 2.0 * WINDOW_PADDING - self.indent).max(MIN_CONTENT_WIDTH);
+        let rect = Rect { x: x + WINDOW_PADDING + self.indent, y: self.cursor_y, width: content,
+                height: height };
+        self.cursor_y += height + WIDGET_SPACING;
+        Some(rect)
+    }
+
+    // Handles the mouse for a widget in the current window. A widget becomes active when it is
+    // pressed, and stays active while the button is held even if the mouse leaves it, and it is
+    // clicked if the button is released over it while it is active.
+    fn interact(&mut self, label: &str, rect: Rect) -> Interaction {
+        let id = self.get_id(label);
+        let in_window = match (&self.current, &self.hovered_window) {
+            (&Some((ref title, _)), &Some(ref hovered)) => title == hovered,
+            _ => false,
+        };
+        let over = in_window && self.mouse.map_or(false, |(x, y)| rect.contains(x, y));
+        let hovered = over && self.active.as_ref().map_or(true, |active| *active == id);
+        if hovered && self.mouse_pressed {
+            self.active = Some(id.clone());
+        }
+        let is_active = self.active.as_ref().map_or(false, |active| *active == id);
+        Interaction { hovered: hovered, held: is_active && self.mouse_down,
+                clicked: is_active && self.mouse_released && over }
+    }
+
+    // Adds a rectangle to the current window.
+    fn push_rect(&mut self, rect: Rect, color: Color) {
+        if let Some((_, ref mut window)) = self.current {
+            window.rects.push((rect, color));
+        }
+    }
+
+    // Adds text to the current window with its top left at a position.
+    fn push_text(&mut self, text: &str, x: GLfloat, y: GLfloat, color: Color) {
+        if let Some((_, ref mut window)) = self.current {
+            window.texts.push((text.to_string(), x.round(), y.round(), color));
+        }
+    }
+}
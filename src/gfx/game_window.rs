@@ -1,31 +1,43 @@
 // Defines the data structures and implementations for the GameWindow which is essentially the
-// main component of the overall game engine. This uses glutin under the hood to create the OpenGL
-// context. This might be moved to a "core" crate in the future if the GameWindow must also handle
-// sophisitcated input, physics, or sound.
+// main component of the overall game engine. The OS window and its OpenGL context come from a
+// PlatformWindow, which is a glutin window unless another one is given to from_platform(), or an
+// offscreen context if from_config() is given an offscreen WindowConfig.
 //
 // Brian Ho
 // brian@brkho.com
 
 extern crate cgmath;
 extern crate gl;
-extern crate glutin;
 
-use self::cgmath::{Matrix, Point};
-pub use self::glutin::{ElementState, Event, VirtualKeyCode};
+use self::cgmath::{Matrix, SquareMatrix};
+pub use platform::window::{ElementState, Event, VirtualKeyCode};
 
 use gfx::camera;
 use gfx::camera::Camera;
+use gfx::capture;
 use gfx::color;
+use gfx::ibl;
+use gfx::instancing;
+use gfx::layers;
 use gfx::light;
+use gfx::material;
 use gfx::model;
+use gfx::morph;
+use gfx::skin;
+use gfx::render_target;
+use gfx::skybox;
+use gfx::stats;
+use gfx::texture;
+use gfx::transparency;
 use gfx::types::*;
+use gfx::viewport;
+use platform::window::{create_window, NativeWindow, PlatformWindow, WindowConfig};
+use util::common;
 use util::shader;
-use self::glutin::{Window, WindowBuilder};
 use std::cmp;
 use std::ffi::CString;
 use std::mem;
 use std::path;
-use std::ptr;
 use std::rc::Rc;
 
 // Number of elements in a VBO or EBO.
@@ -41,6 +53,8 @@ const DEFAULT_GAMMA: GLfloat = 2.2;
 const SHADER_DIR: &'static str = "shaders";
 const VERTEX_SHADER_NAME: &'static str = "std.vert";
 const FRAGMENT_SHADER_NAME: &'static str = "std.frag";
+const SKYBOX_VERTEX_SHADER_NAME: &'static str = "skybox.vert";
+const SKYBOX_FRAGMENT_SHADER_NAME: &'static str = "skybox.frag";
 
 // Contents of a VBO.
 // [P_x  P_y  P_z  N_x  N_y  N_z  T_u  T_v]
@@ -53,22 +67,31 @@ const VERTEX_SIZE: usize = VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT
         VERTEX_BITANGENT_SIZE + VERTEX_TCOORD_SIZE;
 
 // A window for graphics drawing that is managed by the graphics module. This is a thin wrapper
-// around the glutin Window class and will manage draws to the glutin window.
+// around a PlatformWindow and will manage draws to it.
 pub struct GameWindow {
     pub bg_color: color::Color,
-    pub cameras: Vec<Option<camera::PerspectiveCamera>>,
+    pub cameras: Vec<Option<camera::GameCamera>>,
     pub program: GLuint,
     active_camera: Option<usize>,
-    gl_window: Window,
+    gl_window: Box<PlatformWindow>,
     point_lights: Vec<Option<light::PointLight>>,               // (light_index, light)
     directional_lights: Vec<Option<light::DirectionalLight>>,   // (light_index, light)
     spot_lights: Vec<Option<light::SpotLight>>,                 // (light_index, light)
     light_indices: Vec<usize>,
     gen: usize,
     working_vao: GLuint,
+    skinned_vao: GLuint,
     bound_vao: Option<GLuint>,
     default_texture: GLuint,
+    textures: texture::TextureManager,
     gamma: GLfloat,
+    hdr_output: bool,
+    oit_output: bool,
+    environment: Option<ibl::EnvironmentLighting>,
+    skybox: Option<skybox::Skybox>,
+    skybox_program: GLuint,
+    fullscreen_vao: GLuint,
+    counters: stats::RenderCounters,
     vaos: Vec<Vec<Option<GLuint>>>,
     vbos: Vec<(GLuint, usize, usize)>, // (vbo_id, size, max_size)
     ebos: Vec<(GLuint, usize, usize)>, // (ebo_id, size, max_size)
@@ -78,17 +101,23 @@ impl GameWindow {
     // Initializes a GameWindow with a black background and no camera. Note that the GameWindow
     // creation can fail suchas unsupported OpenGL, so it returns a Result.
     pub fn new(width: u32, height: u32, title: String) -> Result<GameWindow, String> {
+        let gl_window = try!(NativeWindow::new(&WindowConfig::new(width, height, &title)));
+        GameWindow::from_platform(Box::new(gl_window))
+    }
+
+    // Initializes a GameWindow with a window or offscreen context created from a WindowConfig.
+    pub fn from_config(config: &WindowConfig) -> Result<GameWindow, String> {
+        GameWindow::from_platform(try!(create_window(config)))
+    }
+
+    // Initializes a GameWindow that draws to a PlatformWindow, which must have an OpenGL context.
+    pub fn from_platform(gl_window: Box<PlatformWindow>) -> Result<GameWindow, String> {
         let bg_color = color::Color::new_rgb(0.0, 0.0, 0.0);
         let pl: Vec<Option<light::PointLight>> = Vec::new();
         let dl: Vec<Option<light::DirectionalLight>> = Vec::new();
         let sl: Vec<Option<light::SpotLight>> = Vec::new();
 
-        // TODO: Handle the actual error reporting of glutin and make this code less ugly.
-        let creation_err = "Unable to create GameWindow.";
-        let gl_window_builder = WindowBuilder::new().with_vsync().with_srgb(Some(true));
-        let gl_window = try!(gl_window_builder.build().map_err(|_| creation_err.to_string()));
-        unsafe { try!(gl_window.make_current().map_err(|_| creation_err.to_string())) }
-        gl_window.set_title(&title);
+        try!(gl_window.make_current());
         gl::load_with(|symbol| gl_window.get_proc_address(symbol) as *const _);
         let lights:Vec<usize> = (0..MAX_LIGHTS).collect();
 
@@ -96,8 +125,11 @@ impl GameWindow {
                 bg_color: bg_color, cameras: Vec::new(), gl_window: gl_window,
                 program: 0, point_lights: pl, directional_lights: dl, spot_lights: sl,
                 active_camera: None, gen: 0, bound_vao: None, vbos: Vec::new(), ebos: Vec::new(),
-                vaos: Vec::new(), working_vao: 0, light_indices: lights, default_texture: 0,
-                gamma: 0.0 };
+                vaos: Vec::new(), working_vao: 0, skinned_vao: 0, light_indices: lights,
+                default_texture: 0, textures: texture::TextureManager::new(), gamma: 0.0,
+                hdr_output: false, oit_output: false,
+                environment: None, skybox: None, skybox_program: 0, fullscreen_vao: 0,
+                counters: stats::RenderCounters::default() };
 
         // Begin unsafe OpenGL shenanigans. Here, we compile and link the shaders, set up the VAO
         // and VBO, and set some texture parameters.
@@ -110,11 +142,19 @@ impl GameWindow {
             let fs = shader::compile_shader(fpath.to_str().unwrap(), gl::FRAGMENT_SHADER);
             window.program = shader::link_program(vs, fs);
             gl::GenVertexArrays(1, &mut window.working_vao);
+            gl::GenVertexArrays(1, &mut window.skinned_vao);
+            window.skybox_program = shader::load_program(
+                    SHADER_DIR, SKYBOX_VERTEX_SHADER_NAME, SKYBOX_FRAGMENT_SHADER_NAME);
+            gl::GenVertexArrays(1, &mut window.fullscreen_vao);
             window.initialize_vbo(0);
             window.initialize_ebo(0);
             gl::Enable(gl::DEPTH_TEST);
-            gl::UseProgram(window.program);
+            // Fragment outputs are bound after linking, so the program is relinked for them to
+            // take effect.
             gl::BindFragDataLocation(window.program, 0, gl_str!("out_color"));
+            gl::BindFragDataLocation(window.program, 1, gl_str!("out_oit_weight"));
+            gl::LinkProgram(window.program);
+            gl::UseProgram(window.program);
             window.set_gamma(DEFAULT_GAMMA);
 
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
@@ -124,17 +164,13 @@ impl GameWindow {
             gl::TexParameteri(
                     gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR_MIPMAP_NEAREST as GLint);
             // Set up the default white texture.
-            let white_tex: Vec<u8> = vec![255, 255, 255];
-            gl::GenTextures(1, &mut window.default_texture);
-            gl::BindTexture(gl::TEXTURE_2D, window.default_texture);
-            gl::TexImage2D(
-                gl::TEXTURE_2D, 0, gl::SRGB as GLsizei, 1, 1, 0, gl::RGB as GLuint,
-                gl::UNSIGNED_BYTE, vec_to_addr!(white_tex));
-            gl::GenerateMipmap(gl::TEXTURE_2D);
+            let white = common::Image { width: 1, height: 1,
+                    data: vec![common::Pixel { red: 255, green: 255, blue: 255, alpha: 255 }] };
+            window.default_texture =
+                    window.textures.upload_image(&white, texture::ColorSpace::SRGB, false);
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
 
-        window.set_size(width, height);
         window.clear();
         window.swap_buffers();
         Ok(window)
@@ -187,6 +223,46 @@ impl GameWindow {
         }
     }}
 
+    // Points the vertex attributes of the bound VAO at the bound VBO starting from a base vertex.
+    unsafe fn bind_vertex_attributes(&self, base_vertex: usize) {
+        let offset = base_vertex * VERTEX_SIZE;
+        let pos_attr = gl::GetAttribLocation(self.program, gl_str!("position"));
+        gl::EnableVertexAttribArray(pos_attr as GLuint);
+        gl::VertexAttribPointer(
+                pos_attr as GLuint, VERTEX_POS_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
+                float_size!(offset, CVoid));
+        let normal_attr = gl::GetAttribLocation(self.program, gl_str!("normal"));
+        gl::EnableVertexAttribArray(normal_attr as GLuint);
+        gl::VertexAttribPointer(
+                normal_attr as GLuint, VERTEX_NORMAL_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
+                float_size!(offset + VERTEX_POS_SIZE, CVoid));
+
+        let tangent_attr = gl::GetAttribLocation(self.program, gl_str!("tangent"));
+        gl::EnableVertexAttribArray(tangent_attr as GLuint);
+        gl::VertexAttribPointer(
+                tangent_attr as GLuint, VERTEX_TANGENT_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
+                float_size!(offset + VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE, CVoid));
+
+        let bitangent_attr = gl::GetAttribLocation(self.program, gl_str!("bitangent"));
+        gl::EnableVertexAttribArray(bitangent_attr as GLuint);
+        gl::VertexAttribPointer(
+                bitangent_attr as GLuint, VERTEX_BITANGENT_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
+                float_size!(offset + VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE,
+                CVoid));
+
+        let tcoord_attr = gl::GetAttribLocation(self.program, gl_str!("tcoord"));
+        gl::EnableVertexAttribArray(tcoord_attr as GLuint);
+        gl::VertexAttribPointer(
+                tcoord_attr as GLuint, VERTEX_TCOORD_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
+                float_size!(offset + VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE
+                + VERTEX_BITANGENT_SIZE, CVoid));
+    }
+
     // Initializes a VAO if there is not already an existing one for the EBO/VBO combination and
     // returns the corresponding VAO ID.
     fn initialize_vao(&mut self, vbo: usize, ebo: usize) -> GLuint { unsafe {
@@ -199,40 +275,7 @@ impl GameWindow {
                 self.vaos[vbo][ebo] = Some(vao);
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.vbos[vbo].0);
                 gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebos[ebo].0);
-                let pos_attr = gl::GetAttribLocation(self.program, gl_str!("position"));
-                gl::EnableVertexAttribArray(pos_attr as GLuint);
-                gl::VertexAttribPointer(
-                        pos_attr as GLuint, VERTEX_POS_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei), ptr::null());
-                let normal_attr = gl::GetAttribLocation(self.program, gl_str!("normal"));
-                gl::EnableVertexAttribArray(normal_attr as GLuint);
-                gl::VertexAttribPointer(
-                        normal_attr as GLuint, VERTEX_NORMAL_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE, CVoid));
-
-                let tangent_attr = gl::GetAttribLocation(self.program, gl_str!("tangent"));
-                gl::EnableVertexAttribArray(tangent_attr as GLuint);
-                gl::VertexAttribPointer(
-                        tangent_attr as GLuint, VERTEX_TANGENT_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE, CVoid));
-
-                let bitangent_attr = gl::GetAttribLocation(self.program, gl_str!("bitangent"));
-                gl::EnableVertexAttribArray(bitangent_attr as GLuint);
-                gl::VertexAttribPointer(
-                        bitangent_attr as GLuint, VERTEX_BITANGENT_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE,
-                        CVoid));
-
-                let tcoord_attr = gl::GetAttribLocation(self.program, gl_str!("tcoord"));
-                gl::EnableVertexAttribArray(tcoord_attr as GLuint);
-                gl::VertexAttribPointer(
-                        tcoord_attr as GLuint, VERTEX_TCOORD_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE
-                        + VERTEX_BITANGENT_SIZE, CVoid));
+                self.bind_vertex_attributes(0);
                 vao
             },
         }
@@ -244,9 +287,45 @@ impl GameWindow {
         uniform_float!(self.program, "gamma", gamma);
     }}
 
+    // Gets the gamma of the context.
+    pub fn get_gamma(&self) -> GLfloat {
+        self.gamma
+    }
+
+    // Sets whether draws output linear HDR colors instead of clamped and gamma corrected ones.
+    // This is used when drawing into a floating point RenderTarget that is post-processed.
+    pub fn set_hdr_output(&mut self, hdr_output: bool) { unsafe {
+        self.hdr_output = hdr_output;
+        uniform_int!(self.program, "hdr_output", hdr_output as GLint);
+    }}
+
+    // Returns true if draws currently output linear HDR colors.
+    pub fn is_hdr_output(&self) -> bool {
+        self.hdr_output
+    }
+
+    // Sets whether draws output to the accumulation buffers of an OITBuffer instead of color. This
+    // is managed by OITBuffer::begin() and OITBuffer::end().
+    pub fn set_oit_output(&mut self, oit_output: bool) { unsafe {
+        self.oit_output = oit_output;
+        uniform_int!(self.program, "oit_output", oit_output as GLint);
+    }}
+
+    // Returns true if draws currently output to the accumulation buffers of an OITBuffer.
+    pub fn is_oit_output(&self) -> bool {
+        self.oit_output
+    }
+
+    // Rebinds the program and forgets the bound VAO. This must be called after code outside of the
+    // GameWindow (such as post-processing) changes the OpenGL program or vertex array bindings.
+    pub fn restore_state(&mut self) { unsafe {
+        gl::UseProgram(self.program);
+        self.bound_vao = None;
+    }}
+
     // Adds a Camera to the engine and returns an integer handle to that camera that can be used
     // with get_camera() and detach_camera().
-    pub fn attach_camera(&mut self, camera: camera::PerspectiveCamera) -> usize {
+    pub fn attach_camera(&mut self, camera: camera::GameCamera) -> usize {
         let mut index = None;
         for (i, elem) in self.cameras.iter().enumerate() {
             match elem {
@@ -273,10 +352,7 @@ impl GameWindow {
     pub fn update_camera(&mut self, handle: usize) {
         let program = self.program.clone();
         let camera = self.get_camera_mut(handle).unwrap();
-        camera.view = cgmath::Matrix4::look_at(
-                cgmath::Point3::from_vec(camera.pos),
-                cgmath::Point3::from_vec(camera.target),
-                camera.up);
+        camera.update_view();
         unsafe { uniform_vec3!(program, "camera", v3d_to_vec!(camera.pos)) };
     }
 
@@ -302,20 +378,20 @@ impl GameWindow {
     // Takes in a handle and returns a mutable reference to the corresponding camera if it is
     // within range. Otherwise, return an Err.
     pub fn get_camera_mut(&mut self, handle: usize)
-            -> Result<&mut camera::PerspectiveCamera, String> {
+            -> Result<&mut camera::GameCamera, String> {
         if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
         Ok(self.cameras[handle].as_mut().unwrap())
     }
 
     // Takes in a handle and returns an immutable reference to the corresponding camera if it is
     // within range. Otherwise, return an Err.
-    pub fn get_camera(&self, handle: usize) -> Result<&camera::PerspectiveCamera, String> {
+    pub fn get_camera(&self, handle: usize) -> Result<&camera::GameCamera, String> {
         if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
         Ok(self.cameras[handle].as_ref().unwrap())
     }
 
     // Gets a mutable reference to the active camera. Returns Err if no current active camera.
-    pub fn get_active_camera_mut(&mut self) -> Result<&mut camera::PerspectiveCamera, String> {
+    pub fn get_active_camera_mut(&mut self) -> Result<&mut camera::GameCamera, String> {
         match self.active_camera {
             None => Err("No currently active camera.".to_string()),
             Some(c) => self.get_camera_mut(c)
@@ -323,13 +399,18 @@ impl GameWindow {
     }
 
     // Gets an immutable reference to the active camera. Returns Err if no current active camera.
-    pub fn get_active_camera(&self) -> Result<&camera::PerspectiveCamera, String> {
+    pub fn get_active_camera(&self) -> Result<&camera::GameCamera, String> {
         match self.active_camera {
             None => Err("No currently active camera.".to_string()),
             Some(c) => self.get_camera(c)
         }
     }
 
+    // Gets the handle of the active camera if there is one.
+    pub fn get_active_camera_handle(&self) -> Option<usize> {
+        self.active_camera
+    }
+
     // Sets the active camera used for rendering given a handle.
     pub fn set_active_camera(&mut self, handle: usize) -> Result<(), String> {
         if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
@@ -353,6 +434,7 @@ impl GameWindow {
         let light = self.get_point_light(index);
         let li = light.light_index.unwrap();
         uniform_uint!(self.program, lights![li, "type"], 1);
+        uniform_uint!(self.program, lights![li, "culling_mask"], light.culling_mask);
         let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
         uniform_vec3!(self.program, lights![li, "intensity"], color);
         uniform_vec3!(self.program, lights![li, "position"], v3d_to_vec!(light.position));
@@ -381,6 +463,11 @@ impl GameWindow {
         (&self.point_lights[index]).as_ref().unwrap()
     }
 
+    // Gets the handles of every attached PointLight.
+    pub fn get_point_light_handles(&self) -> Vec<usize> {
+        GameWindow::get_handles(&self.point_lights)
+    }
+
     // Attaches and transfers ownership of a directional light to the window. This then returns a
     // handle (internally representing the index in the array) that can be used with the getter to
     // modify light attrs.
@@ -397,6 +484,7 @@ impl GameWindow {
         let light = self.get_directional_light(index);
         let li = light.light_index.unwrap();
         uniform_uint!(self.program, lights![li, "type"], 2);
+        uniform_uint!(self.program, lights![li, "culling_mask"], light.culling_mask);
         let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
         uniform_vec3!(self.program, lights![li, "intensity"], color);
         uniform_vec3!(self.program, lights![li, "direction"], v3d_to_vec!(light.direction));
@@ -423,6 +511,11 @@ impl GameWindow {
         (&self.directional_lights[index]).as_ref().unwrap()
     }
 
+    // Gets the handles of every attached DirectionalLight.
+    pub fn get_directional_light_handles(&self) -> Vec<usize> {
+        GameWindow::get_handles(&self.directional_lights)
+    }
+
     // Attaches and transfers ownership of a spot light to the window. This then returns a handle
     // (internally representing the index in the array) that can be used with the getter to modify
     // light attrs.
@@ -439,6 +532,7 @@ impl GameWindow {
         let light = self.get_spot_light(index);
         let li = light.light_index.unwrap();
         uniform_uint!(self.program, lights![li, "type"], 3);
+        uniform_uint!(self.program, lights![li, "culling_mask"], light.culling_mask);
         let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
         uniform_vec3!(self.program, lights![li, "intensity"], color);
         uniform_vec3!(self.program, lights![li, "position"], v3d_to_vec!(light.position));
@@ -470,6 +564,16 @@ impl GameWindow {
         (&self.spot_lights[index]).as_ref().unwrap()
     }
 
+    // Gets the handles of every attached SpotLight.
+    pub fn get_spot_light_handles(&self) -> Vec<usize> {
+        GameWindow::get_handles(&self.spot_lights)
+    }
+
+    // Gets the indices of the filled slots in a vector of lights.
+    fn get_handles<T>(vector: &Vec<Option<T>>) -> Vec<usize> {
+        vector.iter().enumerate().filter(|&(_, l)| l.is_some()).map(|(i, _)| i).collect()
+    }
+
     // Helper function that adds a light to a specified vector of lights. This keeps track of
     // "holes" in the array and returns a handle to the first unused location in the array. If
     // there are no holes, then it adds the light to the end and returns the corresponding handle.
@@ -493,16 +597,200 @@ impl GameWindow {
         }
     }
 
+    // Sets the image-based lighting used by physically based materials and returns the previous
+    // environment (if any) to transfer ownership back to the caller. Passing None falls back to
+    // the constant ambient term.
+    pub fn set_environment(&mut self, environment: Option<ibl::EnvironmentLighting>)
+            -> Option<ibl::EnvironmentLighting> {
+        let previous = mem::replace(&mut self.environment, environment);
+        self.update_environment();
+        previous
+    }
+
+    // Updates the image-based lighting uniforms. This must be called after any sequence of struct
+    // field changes to the environment for the changes to appear in-world.
+    pub fn update_environment(&self) { unsafe {
+        match self.environment {
+            Some(ref env) => {
+                uniform_int!(self.program, "use_ibl", 1);
+                uniform_float!(self.program, "ibl_intensity", env.intensity);
+                uniform_float!(self.program, "prefiltered_max_lod",
+                        env.specular_levels.saturating_sub(1) as GLfloat);
+                gl::Uniform3fv(gl::GetUniformLocation(self.program, gl_str!("irradiance_sh")),
+                        ibl::SH_COEFFICIENTS as GLsizei, env.irradiance_sh.as_ptr());
+            },
+            None => { uniform_int!(self.program, "use_ibl", 0); },
+        }
+    }}
+
+    // Gets a mutable reference to the current image-based lighting if there is one.
+    pub fn get_environment_mut(&mut self) -> Option<&mut ibl::EnvironmentLighting> {
+        self.environment.as_mut()
+    }
+
+    // Gets an immutable reference to the current image-based lighting if there is one.
+    pub fn get_environment(&self) -> Option<&ibl::EnvironmentLighting> {
+        self.environment.as_ref()
+    }
+
+    // Gets the TextureManager that materials should load their textures through so that they
+    // share textures and their memory is accounted for.
+    pub fn get_textures_mut(&mut self) -> &mut texture::TextureManager {
+        &mut self.textures
+    }
+
+    // Gets the TextureManager to query the textures that have been loaded.
+    pub fn get_textures(&self) -> &texture::TextureManager {
+        &self.textures
+    }
+
+    // Redirects every following draw and clear to a RenderTarget, or back to the window if None.
+    // The viewport is set to the size of the target. A RenderTarget must not be drawn to while
+    // one of its textures is used by a Material in the same draw.
+    pub fn set_render_target(&self, target: Option<&render_target::RenderTarget>) { unsafe {
+        gl::Disable(gl::SCISSOR_TEST);
+        match target {
+            Some(t) => {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, t.framebuffer);
+                gl::Viewport(0, 0, t.width as GLsizei, t.height as GLsizei);
+            },
+            None => {
+                let (width, height) = self.get_size();
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+            },
+        }
+    }}
+
+    // Redirects every following draw and clear to a Viewport of a RenderTarget, or of the window if
+    // None, and makes the Viewport's camera active with the aspect ratio of the Viewport. Draws
+    // and clears stay inside the Viewport until the next call to this or set_render_target().
+    // Returns an Err if the Viewport's camera isn't attached.
+    pub fn set_viewport(&mut self, viewport: &viewport::Viewport,
+            target: Option<&render_target::RenderTarget>) -> Result<(), String> {
+        if viewport.camera >= self.cameras.len() || self.cameras[viewport.camera].is_none() {
+            return Err("Viewport camera is not attached.".to_string());
+        }
+        let (width, height) = match target {
+            Some(t) => (t.width, t.height),
+            None => self.get_size(),
+        };
+        let (x, y, rect_width, rect_height) = viewport.get_rect(width, height);
+        self.set_render_target(target);
+        unsafe {
+            // OpenGL puts the origin at the bottom left.
+            let bottom = height as GLint - y - rect_height as GLint;
+            gl::Viewport(x, bottom, rect_width as GLsizei, rect_height as GLsizei);
+            gl::Scissor(x, bottom, rect_width as GLsizei, rect_height as GLsizei);
+            gl::Enable(gl::SCISSOR_TEST);
+            if let Some(color) = viewport.bg_color {
+                gl::ClearColor(color.r, color.g, color.b, color.a);
+                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            }
+        }
+        self.cameras[viewport.camera].as_mut().unwrap().projection
+                .set_aspect(viewport.get_aspect_ratio(width, height));
+        self.active_camera = Some(viewport.camera);
+        self.update_camera(viewport.camera);
+        Ok(())
+    }
+
+    // Sets the Skybox drawn by draw_skybox() and returns the previous one (if any) to transfer
+    // ownership back to the caller. This can be used to swap skies at runtime.
+    pub fn set_skybox(&mut self, skybox: Option<skybox::Skybox>) -> Option<skybox::Skybox> {
+        mem::replace(&mut self.skybox, skybox)
+    }
+
+    // Gets a mutable reference to the current Skybox if there is one.
+    pub fn get_skybox_mut(&mut self) -> Option<&mut skybox::Skybox> {
+        self.skybox.as_mut()
+    }
+
+    // Gets an immutable reference to the current Skybox if there is one.
+    pub fn get_skybox(&self) -> Option<&skybox::Skybox> {
+        self.skybox.as_ref()
+    }
+
+    // Draws the Skybox from the active camera at the far plane so that it only covers pixels that
+    // nothing else was drawn to. This should be called after the opaque instances are drawn to
+    // avoid shading pixels that end up hidden.
+    pub fn draw_skybox(&mut self) {
+        let (texture, exposure) = match self.skybox {
+            Some(ref sky) => (sky.texture, sky.exposure),
+            None => { return; },
+        };
+        let inv_view_proj = {
+            let camera = match self.active_camera {
+                None => { return; },
+                Some(c) => self.cameras[c].as_ref().unwrap(),
+            };
+            // Only the rotation of the view matters since the sky is infinitely far away.
+            let mut view = camera.get_view_matrix();
+            view.w = cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+            match (camera.get_projection_matrix() * view).invert() {
+                Some(m) => m,
+                None => { return; },
+            }
+        };
+
+        unsafe {
+            gl::UseProgram(self.skybox_program);
+            uniform_mat4!(self.skybox_program, "inv_view_proj", inv_view_proj);
+            uniform_float!(self.skybox_program, "exposure", exposure);
+            uniform_float!(self.skybox_program, "gamma", self.gamma);
+            uniform_int!(self.skybox_program, "hdr_output", self.hdr_output as GLint);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture);
+            uniform_int!(self.skybox_program, "sky", 0);
+            gl::DepthFunc(gl::LEQUAL);
+            gl::DepthMask(gl::FALSE);
+            gl::BindVertexArray(self.fullscreen_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::DepthMask(gl::TRUE);
+            gl::DepthFunc(gl::LESS);
+        }
+        self.record_draw(1, 1);
+        self.restore_state();
+    }
+
+    // Adds a draw call to the window's counters. This is called by the window's own draws and
+    // should be called by anything else that draws through the window to be counted.
+    pub fn record_draw(&mut self, instances: usize, triangles: usize) {
+        self.counters.draw_calls += 1;
+        self.counters.instances += instances;
+        self.counters.triangles += triangles;
+    }
+
+    // Gets the counts of the work submitted since the counters were last reset.
+    pub fn get_counters(&self) -> stats::RenderCounters {
+        self.counters
+    }
+
+    // Resets the counts of submitted work to zero.
+    pub fn reset_counters(&mut self) {
+        self.counters = stats::RenderCounters::default();
+    }
+
     // Sets the size of the window.
-    pub fn set_size(&self, width: u32, height: u32) {
-        self.gl_window.set_inner_size(width, height);
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.gl_window.set_size(width, height);
     }
 
-    // Gets the gl_window.
-    pub fn poll_events(&self) -> glutin::PollEventsIterator {
+    // Gets every event that happened since the last poll.
+    pub fn poll_events(&mut self) -> Vec<Event> {
         self.gl_window.poll_events()
     }
 
+    // Gets the PlatformWindow for things like the cursor mode.
+    pub fn get_platform(&self) -> &PlatformWindow {
+        &*self.gl_window
+    }
+
+    // Gets the PlatformWindow to change it.
+    pub fn get_platform_mut(&mut self) -> &mut PlatformWindow {
+        &mut *self.gl_window
+    }
+
     // Clears the screen and buffers.
     pub fn clear(&self) {
         unsafe {
@@ -516,17 +804,50 @@ impl GameWindow {
         self.gl_window.swap_buffers().unwrap();
     }
 
-    // Gets the size of the window in pixels. Again, just a poorly named wrapper. Sorry tomaka. :(
+    // Reads back what has been drawn to the window this frame. This must be called before the
+    // buffers are swapped and waits for the GPU to finish drawing.
+    pub fn read_screen(&self) -> common::Image {
+        let (width, height) = self.get_size();
+        render_target::read_pixels(0, gl::BACK, width, height)
+    }
+
+    // Saves what has been drawn to the window this frame to a BMP or PNG file, which is picked by
+    // the extension of the path. This must be called before the buffers are swapped.
+    pub fn capture_screenshot(&self, path: &str) -> Result<(), String> {
+        let format = match capture::ImageFormat::from_path(path) {
+            Some(format) => format,
+            None => return Err(format!("Unsupported screenshot file: {}.", path)),
+        };
+        try!(capture::write_image(path, &self.read_screen(), format));
+        log_info!(Renderer, "Saved a screenshot to {}.", path);
+        Ok(())
+    }
+
+    // Gets the size of the window in pixels.
     pub fn get_size(&self) -> (u32, u32) {
-        self.gl_window.get_inner_size_pixels().unwrap()
+        self.gl_window.get_size()
     }
 
-    // Gets the aspect ratio of the window.
+    // Gets the aspect ratio of the window. A minimized window has no size, so its aspect ratio is
+    // 1.0 to keep projections finite.
     pub fn get_aspect_ratio(&self) -> f32 {
         let (width, height) = self.get_size();
+        if width == 0 || height == 0 { return 1.0; }
         (width as f32) / (height as f32)
     }
 
+    // Gets the number of pixels per logical point of the screen that the window is on.
+    pub fn get_scale_factor(&self) -> f32 {
+        self.gl_window.get_scale_factor()
+    }
+
+    // Sets the aspect ratio of every attached camera, such as after the window is resized.
+    pub fn set_camera_aspects(&mut self, aspect: f32) {
+        for camera in self.cameras.iter_mut().filter_map(|c| c.as_mut()) {
+            camera.projection.set_aspect(aspect);
+        }
+    }
+
     // Maps/remaps a given Rc<ModelInfo> to VBO and EBO locations in the engine's managed buffers.
     pub fn map_vbo(&mut self, info: Rc<model::ModelInfo>) {
         let vertices = info.get_vbo_format();
@@ -570,16 +891,20 @@ impl GameWindow {
         let vbo_pair = self.vbos[vbo_index];
         let ebo_pair = self.ebos[ebo_index];
 
+        let base_vertex = vbo_pair.1 / VERTEX_SIZE;
         let mut elements: Vec<GLuint> = Vec::new();
         for elem in &info.elements {
-            elements.push(elem.clone() + (vbo_pair.1 as GLuint / VERTEX_SIZE as GLuint));
+            elements.push(elem.clone() + base_vertex as GLuint);
         }
         let buffer_info = model::BufferInfo {
-                start: ebo_pair.1, size: elements.len(), gen: self.gen, vao: vao };
+                start: ebo_pair.1, size: elements.len(), gen: self.gen, vao: vao,
+                vbo: vbo_pair.0, ebo: ebo_pair.0, base_vertex: base_vertex };
         info.buffer_info.set(Some(buffer_info));
         unsafe {
             let working_vao = self.working_vao.clone();
             self.bind_vao_checked(working_vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_pair.0);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo_pair.0);
             gl::BufferSubData(
                     gl::ELEMENT_ARRAY_BUFFER, uint_size!(ebo_pair.1, GLintptr),
                     uint_size!(elements.len(), GLsizeiptr), vec_to_addr!(elements));
@@ -614,6 +939,100 @@ impl GameWindow {
         }
     }
 
+    // Maps a ModelInfo to the engine's VBO space if it does not have an up to date BufferInfo.
+    fn map_vbo_checked(&mut self, info: &Rc<model::ModelInfo>) {
+        match info.buffer_info.get() {
+            None => { self.map_vbo(info.clone()); },
+            Some(i) => { if i.gen != self.gen { self.map_vbo(info.clone()) }; },
+        }
+    }
+
+    // Gets the combined projection and view matrix of the active camera if there is one.
+    fn get_view_projection(&self) -> Option<cgmath::Matrix4<GLfloat>> {
+        let camera = match self.active_camera {
+            None => { return None; },
+            Some(c) => self.cameras[c].as_ref().unwrap(),
+        };
+        Some(camera.get_projection_matrix() * camera.get_view_matrix())
+    }
+
+    // Returns true if renderables on some layers are seen by the active camera's culling mask.
+    // Nothing is visible if there is no active camera.
+    pub fn is_layer_visible(&self, layers: layers::LayerMask) -> bool {
+        match self.get_active_camera() {
+            Ok(camera) => layers::is_visible(layers, camera.culling_mask),
+            Err(_) => false,
+        }
+    }
+
+    // Binds the textures and sets the uniforms for a Material.
+    fn bind_material(&self, mat: &material::Material) { unsafe {
+        gl::ActiveTexture(gl::TEXTURE0);
+        let diffuse_id = if mat.diffuse == 0 { self.default_texture } else { mat.diffuse };
+        gl::BindTexture(gl::TEXTURE_2D, diffuse_id);
+        uniform_int!(self.program, "diffuse_map", 0);
+        gl::ActiveTexture(gl::TEXTURE1);
+        let spec_id = if mat.specular == 0 { self.default_texture } else { mat.specular };
+        gl::BindTexture(gl::TEXTURE_2D, spec_id);
+        uniform_int!(self.program, "specular_map", 1);
+        match mat.normal {
+            Some(normal_id) => {
+                gl::ActiveTexture(gl::TEXTURE2);
+                gl::BindTexture(gl::TEXTURE_2D, normal_id);
+                uniform_int!(self.program, "normal_map", 2);
+                uniform_int!(self.program, "use_normal_map", 1); },
+            None => { uniform_int!(self.program, "use_normal_map", 0); },
+        };
+
+        // Splat materials blend their layers by the weights map in place of the diffuse map.
+        match mat.splat {
+            Some(ref splat) => {
+                gl::ActiveTexture(gl::TEXTURE7);
+                gl::BindTexture(gl::TEXTURE_2D, splat.weights);
+                uniform_int!(self.program, "splat_map", 7);
+                for (i, &layer) in splat.layers.iter().enumerate() {
+                    gl::ActiveTexture(gl::TEXTURE8 + i as GLuint);
+                    let layer_id = if layer == 0 { self.default_texture } else { layer };
+                    gl::BindTexture(gl::TEXTURE_2D, layer_id);
+                    uniform_int!(self.program, format!("splat_layers[{}]", i), 8 + i as GLint);
+                }
+                uniform_float!(self.program, "splat_tiling", splat.tiling);
+                uniform_int!(self.program, "use_splat", 1); },
+            None => { uniform_int!(self.program, "use_splat", 0); },
+        };
+        uniform_float!(self.program, "specular_coeff", mat.shininess);
+        uniform_vec4!(self.program, "color", color_to_vec!(mat.color));
+
+        // Physically based materials sample the metallic-roughness and occlusion maps, which
+        // default to white so that the metallic and roughness factors are used as-is.
+        let shading_model = match mat.shading_model {
+            material::ShadingModel::BlinnPhong => 0,
+            material::ShadingModel::MetallicRoughness => 1,
+        };
+        uniform_uint!(self.program, "shading_model", shading_model);
+        if mat.shading_model == material::ShadingModel::MetallicRoughness {
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_2D,
+                    mat.metallic_roughness.unwrap_or(self.default_texture));
+            uniform_int!(self.program, "metallic_roughness_map", 3);
+            gl::ActiveTexture(gl::TEXTURE4);
+            gl::BindTexture(gl::TEXTURE_2D, mat.occlusion.unwrap_or(self.default_texture));
+            uniform_int!(self.program, "occlusion_map", 4);
+            uniform_float!(self.program, "metallic", mat.metallic);
+            uniform_float!(self.program, "roughness", mat.roughness);
+            let burley = mat.diffuse_model == material::DiffuseModel::Burley;
+            uniform_int!(self.program, "use_burley_diffuse", burley as GLint);
+            if let Some(ref env) = self.environment {
+                gl::ActiveTexture(gl::TEXTURE5);
+                gl::BindTexture(gl::TEXTURE_CUBE_MAP, env.specular_map);
+                uniform_int!(self.program, "prefiltered_map", 5);
+                gl::ActiveTexture(gl::TEXTURE6);
+                gl::BindTexture(gl::TEXTURE_2D, env.brdf_lut);
+                uniform_int!(self.program, "brdf_lut", 6);
+            }
+        }
+    }}
+
     // Draw a ModelInstance to the window using a camera, position, vertices, and materials.
     // This method also manages the engine's VBO space and updates the BufferInfo of the instance's
     // ModelInfo. If there is no associated BufferInfo for a ModelInfo, then we find an empty space
@@ -623,49 +1042,218 @@ impl GameWindow {
     // increment this generation count in the engine. If the generation count on the ModelInfo does
     // not match the count of the Engine, we remap.
     pub fn draw_instance(&mut self, instance: &model::ModelInstance) {
-        match instance.info.buffer_info.get() {
-            None => { self.map_vbo(instance.info.clone()); },
-            Some(i) => { if i.gen != self.gen { self.map_vbo(instance.info.clone()) }; },
-        }
-
-        let transform = {
-            let camera = match self.active_camera {
-                None => { return; },
-                Some(c) => self.cameras[c].as_ref().unwrap(),
-            };
-            let view = camera.get_view_matrix();
-            let proj = camera.get_projection_matrix();
-            proj * view * instance.model
+        if !self.is_layer_visible(instance.layers) { return; }
+        self.map_vbo_checked(&instance.info);
+        let transform = match self.get_view_projection() {
+            None => { return; },
+            Some(view_proj) => view_proj * instance.model,
         };
 
         unsafe {
-            let mat = &instance.info.mat;
             let info = instance.info.buffer_info.get().unwrap();
             self.bind_vao_checked(info.vao);
+            uniform_int!(self.program, "use_instancing", 0);
+            uniform_uint!(self.program, "layers", instance.layers);
             uniform_mat4!(self.program, "transform", transform);
             uniform_mat4!(self.program, "model", instance.model);
             uniform_mat4!(self.program, "normal_matrix", instance.normal);
-            gl::ActiveTexture(gl::TEXTURE0);
-            let diffuse_id = if mat.diffuse == 0 { self.default_texture } else { mat.diffuse };
-            gl::BindTexture(gl::TEXTURE_2D, diffuse_id);
-            uniform_int!(self.program, "diffuse_map", 0);
-            gl::ActiveTexture(gl::TEXTURE1);
-            let spec_id = if mat.specular == 0 { self.default_texture } else { mat.specular };
-            gl::BindTexture(gl::TEXTURE_2D, spec_id);
-            uniform_int!(self.program, "specular_map", 1);
-            match mat.normal {
-                Some(normal_id) => {
-                    gl::ActiveTexture(gl::TEXTURE2);
-                    gl::BindTexture(gl::TEXTURE_2D, normal_id);
-                    uniform_int!(self.program, "normal_map", 2);
-                    uniform_int!(self.program, "use_normal_map", 1); },
-                None => { uniform_int!(self.program, "use_normal_map", 0); },
-            };
-            uniform_float!(self.program, "specular_coeff", mat.shininess);
-            uniform_vec4!(self.program, "color", color_to_vec!(mat.color));
+            self.bind_material(instance.get_material());
+            gl::DrawElements(gl::TRIANGLES, info.size as i32,
+                    gl::UNSIGNED_INT, uint_size!(info.start, CVoid));
+            self.record_draw(1, info.size / 3);
+        }
+    }
+
+    // Draws an instance with only part of its pixels so that it can be cross-faded with another
+    // instance without blending. A fade from 0.0 to 1.0 keeps that fraction of the pixels and a
+    // fade from -1.0 to 0.0 keeps the pixels discarded by the opposite positive fade, so drawing
+    // one instance with f and another with -f covers every pixel exactly once.
+    pub fn draw_instance_faded(&mut self, instance: &model::ModelInstance, fade: GLfloat) {
+        unsafe { uniform_float!(self.program, "lod_fade", fade); }
+        self.draw_instance(instance);
+        unsafe { uniform_float!(self.program, "lod_fade", 0.0); }
+    }
 
+    // Draws an instance with a program other than the engine's, such as the one used for ID
+    // picking. The program must already be in use, must take its position attribute at the same
+    // location as the engine's program, and only gets its transform uniform set here. Call
+    // restore_state() once done drawing with other programs.
+    pub fn draw_instance_with_program(&mut self, instance: &model::ModelInstance,
+            program: GLuint) {
+        if !self.is_layer_visible(instance.layers) { return; }
+        self.map_vbo_checked(&instance.info);
+        let transform = match self.get_view_projection() {
+            None => { return; },
+            Some(view_proj) => view_proj * instance.model,
+        };
+
+        unsafe {
+            let info = instance.info.buffer_info.get().unwrap();
+            self.bind_vao_checked(info.vao);
+            uniform_mat4!(program, "transform", transform);
             gl::DrawElements(gl::TRIANGLES, info.size as i32,
                     gl::UNSIGNED_INT, uint_size!(info.start, CVoid));
         }
     }
-}
\ No newline at end of file
+
+    // Uploads the vertices of a morphed model blended with its current weights over the model's
+    // vertices in the engine's VBO space. This only uploads if the weights changed or the model was
+    // remapped since the last upload, and should be called before drawing the model each frame.
+    pub fn upload_morph(&mut self, morph: &mut morph::Morph) {
+        self.map_vbo_checked(&morph.info);
+        let info = morph.info.buffer_info.get().unwrap();
+        if !morph.needs_upload(&info) { return; }
+        let vertices = morph.blend();
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, info.vbo);
+            gl::BufferSubData(
+                    gl::ARRAY_BUFFER, float_size!(info.base_vertex * VERTEX_SIZE, GLintptr),
+                    float_size!(vertices.len(), GLsizeiptr), vec_to_addr!(vertices));
+        }
+        morph.mark_uploaded(&info);
+    }
+
+    // Draws an instance deformed by the skinning matrices of its skeleton's joints, such as from
+    // AnimationPlayer::get_skinning_matrices(). The skin must have an entry for every vertex of the
+    // instance's ModelInfo and only the first skin::MAX_JOINTS matrices are used.
+    //
+    // The skin buffer is indexed from the model's first vertex while the shared VBOs are indexed
+    // from the start of the buffer, so skinned draws use their own VAO with the shared attributes
+    // offset to the model's first vertex and subtract the base vertex back out of the elements.
+    pub fn draw_skinned(&mut self, instance: &model::ModelInstance, skin: &skin::Skin,
+            matrices: &[cgmath::Matrix4<GLfloat>]) {
+        if !self.is_layer_visible(instance.layers) { return; }
+        self.map_vbo_checked(&instance.info);
+        let transform = match self.get_view_projection() {
+            None => { return; },
+            Some(view_proj) => view_proj * instance.model,
+        };
+
+        unsafe {
+            let info = instance.info.buffer_info.get().unwrap();
+            let skinned_vao = self.skinned_vao;
+            self.bind_vao_checked(skinned_vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, info.vbo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, info.ebo);
+            self.bind_vertex_attributes(info.base_vertex);
+            gl::BindBuffer(gl::ARRAY_BUFFER, skin.get_buffer());
+            let stride = float_size!(skin::SKIN_VERTEX_SIZE, GLsizei);
+            let mut attributes = Vec::new();
+            for &(name, offset) in &[("joints", 0), ("weights", skin::JOINTS_PER_VERTEX)] {
+                let location = gl::GetAttribLocation(self.program, gl_str!(name));
+                if location >= 0 {
+                    gl::EnableVertexAttribArray(location as GLuint);
+                    gl::VertexAttribPointer(
+                            location as GLuint, skin::JOINTS_PER_VERTEX as GLint, gl::FLOAT,
+                            gl::FALSE as GLboolean, stride, float_size!(offset, CVoid));
+                    attributes.push(location as GLuint);
+                }
+            }
+
+            let count = cmp::min(matrices.len(), skin::MAX_JOINTS);
+            let mut packed: Vec<GLfloat> = Vec::with_capacity(count * 16);
+            for m in &matrices[..count] {
+                for col in &[m.x, m.y, m.z, m.w] {
+                    packed.extend_from_slice(&[col.x, col.y, col.z, col.w]);
+                }
+            }
+            if count > 0 {
+                gl::UniformMatrix4fv(
+                        gl::GetUniformLocation(self.program, gl_str!("joint_matrices")),
+                        count as GLsizei, gl::FALSE as GLboolean, packed.as_ptr());
+            }
+            uniform_int!(self.program, "use_instancing", 0);
+            uniform_int!(self.program, "use_skinning", 1);
+            uniform_uint!(self.program, "layers", instance.layers);
+            uniform_mat4!(self.program, "transform", transform);
+            uniform_mat4!(self.program, "model", instance.model);
+            uniform_mat4!(self.program, "normal_matrix", instance.normal);
+            self.bind_material(instance.get_material());
+            gl::DrawElementsBaseVertex(gl::TRIANGLES, info.size as i32, gl::UNSIGNED_INT,
+                    uint_size!(info.start, CVoid), -(info.base_vertex as GLint));
+            uniform_int!(self.program, "use_skinning", 0);
+            self.record_draw(1, info.size / 3);
+
+            for attr in attributes {
+                gl::DisableVertexAttribArray(attr);
+            }
+        }
+    }
+
+    // Draws every instance in an InstanceBatch with a single instanced draw call. The VAOs are
+    // shared between ModelInfos, so the per-instance attributes are pointed at the batch's instance
+    // buffer for the duration of the draw and disabled afterwards.
+    pub fn draw_instanced(&mut self, batch: &mut instancing::InstanceBatch) {
+        if batch.is_empty() || !self.is_layer_visible(batch.layers) { return; }
+        self.map_vbo_checked(&batch.info);
+        let view_proj = match self.get_view_projection() {
+            None => { return; },
+            Some(view_proj) => view_proj,
+        };
+
+        unsafe {
+            let info = batch.info.buffer_info.get().unwrap();
+            self.bind_vao_checked(info.vao);
+            let instance_buffer = batch.upload();
+            gl::BindBuffer(gl::ARRAY_BUFFER, instance_buffer);
+
+            // Matrices take up one attribute location per column.
+            let stride = float_size!(instancing::INSTANCE_SIZE, GLsizei);
+            let mut attributes = Vec::new();
+            let mut offset = 0;
+            for &(name, columns, rows) in &[
+                    ("instance_model", 4, 4), ("instance_normal", 3, 3), ("instance_color", 1, 4),
+                    ("instance_data", 1, 4)] {
+                let location = gl::GetAttribLocation(self.program, gl_str!(name));
+                for column in 0..columns {
+                    if location >= 0 {
+                        let attr = (location + column) as GLuint;
+                        gl::EnableVertexAttribArray(attr);
+                        gl::VertexAttribPointer(
+                                attr, rows, gl::FLOAT, gl::FALSE as GLboolean, stride,
+                                float_size!(offset, CVoid));
+                        gl::VertexAttribDivisor(attr, 1);
+                        attributes.push(attr);
+                    }
+                    offset += rows as usize;
+                }
+            }
+
+            uniform_int!(self.program, "use_instancing", 1);
+            uniform_uint!(self.program, "layers", batch.layers);
+            uniform_mat4!(self.program, "view_proj", view_proj);
+            self.bind_material(&batch.info.mat);
+            gl::DrawElementsInstanced(gl::TRIANGLES, info.size as i32, gl::UNSIGNED_INT,
+                    uint_size!(info.start, CVoid), batch.len() as GLsizei);
+            self.record_draw(batch.len(), info.size / 3 * batch.len());
+
+            for attr in attributes {
+                gl::VertexAttribDivisor(attr, 0);
+                gl::DisableVertexAttribArray(attr);
+            }
+        }
+    }
+
+    // Draws every instance in a TransparentQueue with alpha blending. The instances are sorted
+    // back-to-front from the active camera first and don't write to the depth buffer so that they
+    // don't hide each other. This should be called after every opaque instance has been drawn.
+    pub fn draw_transparent(&mut self, queue: &mut transparency::TransparentQueue) {
+        let camera_pos = match self.get_active_camera() {
+            Ok(camera) => camera.pos,
+            Err(_) => { return; },
+        };
+        queue.sort(camera_pos);
+        unsafe {
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+        }
+        for instance in queue.get_instances() {
+            self.draw_instance(instance);
+        }
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+    }
+}
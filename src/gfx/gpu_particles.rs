@@ -0,0 +1,391 @@
+// Defines a GpuParticleSystem, which simulates the particles of an emitter on the GPU for effects
+// with far more particles than a ParticleSystem can update on the CPU every frame. The particles
+// live in a pair of vertex buffers, and each update runs a vertex shader over one buffer and
+// captures its output into the other with transform feedback, so the particles are never read back
+// to the CPU. Dead particles are respawned in the shader by the emitter's rate and bursts, and the
+// oldest particles are recycled first once every slot is in use. The particles are then drawn
+// straight from the buffer as points that a geometry shader expands into camera-facing billboards.
+//
+// The emitter and particle settings are a ParticleSystem whose own particles are unused, so an
+// effect can switch between the two without being set up twice. The GPU path folds the modifiers
+// into a single gravity and drag and doesn't sort the particles, so it suits additive effects or
+// ones where the order of the particles can't be seen. ParticleBackend picks the GPU path when the
+// context supports it and otherwise keeps simulating on the CPU.
+//
+// Usage of a GpuParticleSystem:
+// - Create a ParticleSystem with the settings and pass it to new(), either directly or through
+//   ParticleBackend::new(). The max particles of the settings is the fixed size of the buffers.
+// - Call update(&mut window, dt) every frame, along with burst() for one-off effects.
+// - Call draw(&mut window, &camera) after the opaque scene with its depth buffer still bound.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use anim::track::Track;
+use gfx::camera::GameCamera;
+use gfx::color::Color;
+use gfx::game_window::GameWindow;
+use gfx::particles::{EmitterShape, ParticleModifier, ParticleSystem};
+use gfx::sprite::{SoftDepth, SpriteBatch};
+use gfx::types::*;
+use self::cgmath::Matrix;
+use std::ffi::CString;
+use std::mem;
+use util::random::Random;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const UPDATE_SHADER_NAME: &'static str = "particle_update.vert";
+const VERTEX_SHADER_NAME: &'static str = "gpu_particle.vert";
+const GEOMETRY_SHADER_NAME: &'static str = "gpu_particle.geom";
+const FRAGMENT_SHADER_NAME: &'static str = "sprite.frag";
+
+// Contents of a single particle in the particle buffers. The state holds the age, lifetime, size,
+// and rotation.
+// [P_x  P_y  P_z  V_x  V_y  V_z  S_age  S_life  S_size  S_rot  spin]
+const PARTICLE_POS_SIZE: usize = 3;
+const PARTICLE_VELOCITY_SIZE: usize = 3;
+const PARTICLE_STATE_SIZE: usize = 4;
+const PARTICLE_SPIN_SIZE: usize = 1;
+const PARTICLE_SIZE: usize = PARTICLE_POS_SIZE + PARTICLE_VELOCITY_SIZE + PARTICLE_STATE_SIZE +
+        PARTICLE_SPIN_SIZE;
+
+// The outputs of the update shader that are captured, in the order of the buffer layout.
+const VARYINGS: [&'static str; 4] = ["out_position", "out_velocity", "out_state", "out_spin"];
+
+// Number of samples that the curves over a particle's lifetime are baked into. This must match
+// CURVE_SAMPLES in the shaders.
+const CURVE_SAMPLES: usize = 16;
+
+// The emitter shapes as numbered in the update shader.
+const SHAPE_POINT: GLint = 0;
+const SHAPE_SPHERE: GLint = 1;
+const SHAPE_CONE: GLint = 2;
+
+// An emitter whose particles are simulated and drawn on the GPU. Particles blend additively if
+// additive is set, and otherwise blend by their alpha in no particular order.
+pub struct GpuParticleSystem {
+    pub settings: ParticleSystem,
+    pub additive: bool,
+    pub soft_depth: Option<SoftDepth>,
+    capacity: usize,
+    update_program: GLuint,
+    draw_program: GLuint,
+    buffers: [GLuint; 2],
+    update_vaos: [GLuint; 2],
+    draw_vaos: [GLuint; 2],
+    current: usize,
+    cursor: usize,
+    pending: usize,
+    random: Random,
+}
+
+impl GpuParticleSystem {
+    // Creates the particle buffers and programs for an emitter with a seed for its random numbers.
+    // The buffers hold the max particles of the settings, which starts out dead. This can only be
+    // called after the window context is set up.
+    pub fn new(settings: ParticleSystem, seed: u64) -> GpuParticleSystem { unsafe {
+        let capacity = settings.max_particles.max(1);
+        let update_program = shader::load_feedback_program(
+                SHADER_DIR, UPDATE_SHADER_NAME, &VARYINGS);
+        let draw_program = shader::load_geometry_program(
+                SHADER_DIR, VERTEX_SHADER_NAME, GEOMETRY_SHADER_NAME, FRAGMENT_SHADER_NAME);
+
+        // A lifetime of zero marks every particle as dead until it is spawned.
+        let data = vec![0.0 as GLfloat; capacity * PARTICLE_SIZE];
+        let mut buffers = [0; 2];
+        gl::GenBuffers(2, buffers.as_mut_ptr());
+        for &buffer in &buffers {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferData(
+                    gl::ARRAY_BUFFER, float_size!(data.len(), GLsizeiptr), vec_to_addr!(data),
+                    gl::DYNAMIC_COPY);
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        let update_vaos = [create_vao(update_program, buffers[0]),
+                create_vao(update_program, buffers[1])];
+        let draw_vaos = [create_vao(draw_program, buffers[0]),
+                create_vao(draw_program, buffers[1])];
+        GpuParticleSystem { settings: settings, additive: false, soft_depth: None,
+                capacity: capacity, update_program: update_program, draw_program: draw_program,
+                buffers: buffers, update_vaos: update_vaos, draw_vaos: draw_vaos, current: 0,
+                cursor: 0, pending: 0, random: Random::new(seed) }
+    }}
+
+    // Gets the number of particles that the buffers hold, which is the most that can be alive.
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Kills every particle and restarts the emitter's cycle.
+    pub fn clear(&mut self) { unsafe {
+        self.settings.clear();
+        self.pending = 0;
+        self.cursor = 0;
+        let data = vec![0.0 as GLfloat; self.capacity * PARTICLE_SIZE];
+        for &buffer in &self.buffers {
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            gl::BufferSubData(
+                    gl::ARRAY_BUFFER, 0, float_size!(data.len(), GLsizeiptr), vec_to_addr!(data));
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    }}
+
+    // Spawns a number of particles on the next update, replacing the oldest particles if there
+    // aren't enough dead ones.
+    pub fn burst(&mut self, count: usize) {
+        self.pending += count;
+    }
+
+    // Ages and moves the particles by a timestep in seconds and spawns new ones from the rate,
+    // bursts, and pending burst() calls.
+    pub fn update(&mut self, window: &mut GameWindow, dt: GLfloat) { unsafe {
+        let spawn = (self.settings.advance_emitter(dt) + self.pending).min(self.capacity);
+        self.pending = 0;
+        let settings = &self.settings;
+        let program = self.update_program;
+        gl::UseProgram(program);
+        uniform_float!(program, "dt", dt);
+        uniform_uint!(program, "seed", self.random.next_u32());
+        uniform_int!(program, "capacity", self.capacity as GLint);
+        uniform_int!(program, "spawn_start", self.cursor as GLint);
+        uniform_int!(program, "spawn_count", spawn as GLint);
+        let emitter = cgmath::Matrix4::from(cgmath::Decomposed {
+                scale: 1.0, rot: settings.rot, disp: settings.pos });
+        uniform_mat4!(program, "emitter", emitter);
+        let (shape, radius, angle) = match settings.shape {
+            EmitterShape::Point => (SHAPE_POINT, 0.0, 0.0),
+            EmitterShape::Sphere { radius } => (SHAPE_SPHERE, radius, 0.0),
+            EmitterShape::Cone { angle, radius } => (SHAPE_CONE, radius, angle),
+        };
+        uniform_int!(program, "shape", shape);
+        uniform_float!(program, "shape_radius", radius);
+        uniform_float!(program, "shape_angle", angle);
+        uniform_vec2!(program, "lifetime_range", vec![settings.lifetime.0, settings.lifetime.1]);
+        uniform_vec2!(program, "speed_range", vec![settings.speed.0, settings.speed.1]);
+        uniform_vec2!(program, "size_range", vec![settings.size.0, settings.size.1]);
+        uniform_vec2!(program, "rotation_range", vec![settings.rotation.0, settings.rotation.1]);
+        uniform_vec2!(program, "spin_range", vec![settings.spin.0, settings.spin.1]);
+
+        // The modifiers are folded into a single gravity and drag.
+        let mut gravity = Vector3D::new(0.0, 0.0, 0.0);
+        let mut drag = 0.0;
+        for modifier in &settings.modifiers {
+            match *modifier {
+                ParticleModifier::Gravity(g) => { gravity = gravity + g; },
+                ParticleModifier::Drag(d) => { drag += d; },
+            }
+        }
+        uniform_vec3!(program, "gravity", v3d_to_vec!(gravity));
+        uniform_float!(program, "drag", drag);
+        let speed_curve = bake_curve(settings.speed_curve.as_ref());
+        gl::Uniform1fv(gl::GetUniformLocation(program, gl_str!("speed_curve")),
+                CURVE_SAMPLES as GLsizei, speed_curve.as_ptr());
+
+        gl::Enable(gl::RASTERIZER_DISCARD);
+        gl::BindVertexArray(self.update_vaos[self.current]);
+        gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.buffers[1 - self.current]);
+        gl::BeginTransformFeedback(gl::POINTS);
+        gl::DrawArrays(gl::POINTS, 0, self.capacity as GLsizei);
+        gl::EndTransformFeedback();
+        gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, 0);
+        gl::BindVertexArray(0);
+        gl::Disable(gl::RASTERIZER_DISCARD);
+        self.current = 1 - self.current;
+        self.cursor = (self.cursor + spawn) % self.capacity;
+        window.restore_state();
+    }}
+
+    // Draws the particles over the window's current render target as billboards facing a camera.
+    // The particles are tested against the depth buffer without writing to it.
+    pub fn draw(&self, window: &mut GameWindow, camera: &GameCamera) { unsafe {
+        let settings = &self.settings;
+        let program = self.draw_program;
+        let view = camera.view;
+        let right = Vector3D::new(view.x[0], view.y[0], view.z[0]);
+        let up = Vector3D::new(view.x[1], view.y[1], view.z[1]);
+        gl::UseProgram(program);
+        uniform_mat4!(program, "view_proj", camera.get_view_projection());
+        uniform_vec3!(program, "right", v3d_to_vec!(right));
+        uniform_vec3!(program, "up", v3d_to_vec!(up));
+        uniform_vec4!(program, "region", settings.region.uv);
+        uniform_vec4!(program, "color", color_to_vec!(settings.color));
+        let size_curve = bake_curve(settings.size_curve.as_ref());
+        gl::Uniform1fv(gl::GetUniformLocation(program, gl_str!("size_curve")),
+                CURVE_SAMPLES as GLsizei, size_curve.as_ptr());
+        let mut color_curve = Vec::with_capacity(CURVE_SAMPLES * 4);
+        for i in 0..CURVE_SAMPLES {
+            let t = i as GLfloat / (CURVE_SAMPLES - 1) as GLfloat;
+            let c = settings.color_curve.as_ref().map_or(Color::new(1.0, 1.0, 1.0, 1.0),
+                    |c| c.sample(t));
+            color_curve.extend_from_slice(&[c.r, c.g, c.b, c.a]);
+        }
+        gl::Uniform4fv(gl::GetUniformLocation(program, gl_str!("color_curve")),
+                CURVE_SAMPLES as GLsizei, color_curve.as_ptr());
+        uniform_float!(program, "gamma", window.get_gamma());
+        uniform_int!(program, "hdr_output", window.is_hdr_output() as GLint);
+        match self.soft_depth {
+            Some(soft) => {
+                let mut viewport: [GLint; 4] = [0; 4];
+                gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, soft.texture);
+                uniform_int!(program, "depth_map", 1);
+                uniform_vec2!(program, "viewport_size",
+                        vec![viewport[2] as GLfloat, viewport[3] as GLfloat]);
+                uniform_float!(program, "near", soft.near);
+                uniform_float!(program, "far", soft.far);
+                uniform_int!(program, "orthographic", soft.orthographic as GLint);
+                uniform_float!(program, "soft_distance", soft.distance);
+                uniform_int!(program, "use_soft_depth", 1); },
+            None => { uniform_int!(program, "use_soft_depth", 0); },
+        };
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, settings.region.texture);
+        uniform_int!(program, "sprite", 0);
+
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, if self.additive { gl::ONE } else { gl::ONE_MINUS_SRC_ALPHA });
+        gl::DepthMask(gl::FALSE);
+        gl::BindVertexArray(self.draw_vaos[self.current]);
+        gl::DrawArrays(gl::POINTS, 0, self.capacity as GLsizei);
+        gl::BindVertexArray(0);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+        window.record_draw(self.capacity, self.capacity * 2);
+        window.restore_state();
+    }}
+
+    // Deletes the programs and particle buffers from the GPU and gives back the settings.
+    pub fn delete(self) -> ParticleSystem { unsafe {
+        gl::DeleteProgram(self.update_program);
+        gl::DeleteProgram(self.draw_program);
+        gl::DeleteVertexArrays(2, self.update_vaos.as_ptr());
+        gl::DeleteVertexArrays(2, self.draw_vaos.as_ptr());
+        gl::DeleteBuffers(2, self.buffers.as_ptr());
+        self.settings
+    }}
+}
+
+// The simulation backend of an emitter. The GPU backend is used where it is supported, and the
+// CPU backend is the fallback that works everywhere and sorts its particles.
+pub enum ParticleBackend {
+    Cpu(ParticleSystem),
+    Gpu(GpuParticleSystem),
+}
+
+impl ParticleBackend {
+    // Creates the GPU backend for an emitter if the context supports it, and otherwise keeps
+    // simulating the emitter on the CPU. This can only be called after the window context is set
+    // up.
+    pub fn new(settings: ParticleSystem, seed: u64) -> ParticleBackend {
+        if is_gpu_supported() {
+            ParticleBackend::Gpu(GpuParticleSystem::new(settings, seed))
+        } else {
+            ParticleBackend::Cpu(settings)
+        }
+    }
+
+    // Returns true if the particles are simulated on the GPU.
+    pub fn is_gpu(&self) -> bool {
+        match *self {
+            ParticleBackend::Cpu(_) => false,
+            ParticleBackend::Gpu(_) => true,
+        }
+    }
+
+    // Gets the emitter and particle settings.
+    pub fn get_settings(&self) -> &ParticleSystem {
+        match *self {
+            ParticleBackend::Cpu(ref system) => system,
+            ParticleBackend::Gpu(ref system) => &system.settings,
+        }
+    }
+
+    // Gets the emitter and particle settings to change them.
+    pub fn get_settings_mut(&mut self) -> &mut ParticleSystem {
+        match *self {
+            ParticleBackend::Cpu(ref mut system) => system,
+            ParticleBackend::Gpu(ref mut system) => &mut system.settings,
+        }
+    }
+
+    // Spawns a number of particles at once.
+    pub fn burst(&mut self, count: usize) {
+        match *self {
+            ParticleBackend::Cpu(ref mut system) => system.burst(count),
+            ParticleBackend::Gpu(ref mut system) => system.burst(count),
+        }
+    }
+
+    // Ages and moves the particles by a timestep in seconds and spawns new ones.
+    pub fn update(&mut self, window: &mut GameWindow, dt: GLfloat) {
+        match *self {
+            ParticleBackend::Cpu(ref mut system) => system.update(dt),
+            ParticleBackend::Gpu(ref mut system) => system.update(window, dt),
+        }
+    }
+
+    // Draws the particles as billboards facing a camera. The CPU backend queues them in the
+    // SpriteBatch, which still has to be flushed, while the GPU backend draws them right away with
+    // the batch's soft depth.
+    pub fn draw(&mut self, window: &mut GameWindow, batch: &mut SpriteBatch, camera: &GameCamera) {
+        match *self {
+            ParticleBackend::Cpu(ref system) => system.draw(batch, camera),
+            ParticleBackend::Gpu(ref mut system) => {
+                system.soft_depth = batch.soft_depth;
+                system.draw(window, camera);
+            },
+        }
+    }
+}
+
+// Returns true if the current context supports simulating particles on the GPU, which needs
+// transform feedback and geometry shaders from OpenGL 3.2.
+pub fn is_gpu_supported() -> bool { unsafe {
+    let (mut major, mut minor) = (0, 0);
+    gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+    gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    (major, minor) >= (3, 2)
+}}
+
+// Creates a vertex array that reads the particles in a buffer for a program. Attributes that the
+// program doesn't use are skipped.
+unsafe fn create_vao(program: GLuint, buffer: GLuint) -> GLuint {
+    let mut vao = 0;
+    gl::GenVertexArrays(1, &mut vao);
+    gl::BindVertexArray(vao);
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+    let attributes = [("position", PARTICLE_POS_SIZE, 0),
+            ("velocity", PARTICLE_VELOCITY_SIZE, PARTICLE_POS_SIZE),
+            ("state", PARTICLE_STATE_SIZE, PARTICLE_POS_SIZE + PARTICLE_VELOCITY_SIZE),
+            ("spin", PARTICLE_SPIN_SIZE,
+                    PARTICLE_POS_SIZE + PARTICLE_VELOCITY_SIZE + PARTICLE_STATE_SIZE)];
+    for &(name, size, offset) in &attributes {
+        let attr = gl::GetAttribLocation(program, gl_str!(name));
+        if attr < 0 { continue; }
+        gl::EnableVertexAttribArray(attr as GLuint);
+        gl::VertexAttribPointer(
+                attr as GLuint, size as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(PARTICLE_SIZE, GLsizei), float_size!(offset, CVoid));
+    }
+    gl::BindVertexArray(0);
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    vao
+}
+
+// Samples a curve over a particle's lifetime from 0.0 to 1.0 at evenly spaced times, or gives a
+// constant 1.0 if there is no curve.
+fn bake_curve(curve: Option<&Track<GLfloat>>) -> [GLfloat; CURVE_SAMPLES] {
+    let mut samples = [1.0; CURVE_SAMPLES];
+    if let Some(curve) = curve {
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = curve.sample(i as GLfloat / (CURVE_SAMPLES - 1) as GLfloat);
+        }
+    }
+    samples
+}
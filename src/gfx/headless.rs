@@ -0,0 +1,76 @@
+// Defines a HeadlessRenderer which draws scenes with an OpenGL context that has no window and reads
+// back what was drawn, so that rendering can be checked by automated tests and on CI servers
+// without a display. Frames are drawn into an RGBA8 RenderTarget instead of the context's default
+// framebuffer since offscreen contexts don't promise a usable one, and the fragment shader
+// already gamma corrects its output so the pixels read back are the ones a window would show.
+//
+// Usage of a HeadlessRenderer:
+// - let mut renderer = try!(HeadlessRenderer::new(256, 256));
+// - Attach cameras and lights to renderer.window like any other GameWindow.
+// - let image = try!(renderer.render(|window| window.draw_instance(&instance)));
+// - Or check a frame against a reference image with render_and_compare(), which uses imgdiff.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::game_window::GameWindow;
+use gfx::render_target::{ColorFormat, DepthFormat, RenderTarget};
+use platform::window::WindowConfig;
+use util::common;
+use util::imgdiff;
+
+// A GameWindow with an offscreen context along with the RenderTarget that it draws frames into.
+pub struct HeadlessRenderer {
+    pub window: GameWindow,
+    pub target: RenderTarget,
+}
+
+impl HeadlessRenderer {
+    // Creates an offscreen context and a target of a size in pixels. Returns an Err if the
+    // platform can't create an offscreen context, such as when no software rasterizer is
+    // installed.
+    pub fn new(width: u32, height: u32) -> Result<HeadlessRenderer, String> {
+        let window = try!(GameWindow::from_config(&WindowConfig::new_offscreen(width, height)));
+        let target = try!(RenderTarget::new(
+                width, height, vec![ColorFormat::RGBA8], Some(DepthFormat::Depth24)));
+        Ok(HeadlessRenderer { window: window, target: target })
+    }
+
+    // Gets the size of the frames in pixels.
+    pub fn get_size(&self) -> (u32, u32) {
+        (self.target.width, self.target.height)
+    }
+
+    // Changes the size of the frames in pixels along with the aspect ratio of every camera.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.target.resize(width, height);
+        self.window.set_size(width, height);
+        let aspect = self.target.get_aspect_ratio();
+        self.window.set_camera_aspects(aspect);
+    }
+
+    // Clears the target to the window's background color, lets a closure draw the frame, and reads
+    // the frame back once the GPU has finished drawing it. The closure may switch render targets
+    // as long as it switches back to this one before it returns.
+    pub fn render<F>(&mut self, draw: F) -> Result<common::Image, String>
+            where F: FnOnce(&mut GameWindow) {
+        self.window.set_render_target(Some(&self.target));
+        self.window.clear();
+        draw(&mut self.window);
+        unsafe { gl::Finish(); }
+        let image = self.target.read_pixels(0);
+        self.window.set_render_target(None);
+        self.window.swap_buffers();
+        image
+    }
+
+    // Renders a frame like render() and compares it to a reference image in a BMP, PNG, or TGA
+    // file. Each channel may be off by the tolerance.
+    pub fn render_and_compare<F>(&mut self, draw: F, reference: &str, tolerance: u8)
+            -> Result<imgdiff::ImageDiff, String> where F: FnOnce(&mut GameWindow) {
+        let image = try!(self.render(draw));
+        imgdiff::compare_to_reference(&image, reference, tolerance)
+    }
+}
@@ -0,0 +1,496 @@
+// Defines image-based lighting (IBL) for the physically based shading model. An Environment is
+// loaded from an equirectangular HDR or six cubemap faces and is then precomputed at load time
+// into the three pieces the shader needs: spherical harmonic coefficients for diffuse irradiance,
+// a mip chain of GGX-prefiltered specular cubemaps (one mip per roughness level), and a BRDF lookup
+// table for the split-sum approximation. The precomputation is done on the CPU so that it can run
+// offline without a context, and the results are then uploaded as EnvironmentLighting which can be
+// attached to the GameWindow.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::{EuclideanVector, Vector, Vector3};
+use gfx::types::*;
+use std::f32::consts::PI;
+use std::mem;
+use util::{common, hdr};
+
+// Number of faces in a cubemap. Faces are stored in the OpenGL order (+X, -X, +Y, -Y, +Z, -Z).
+pub const CUBE_FACES: usize = 6;
+
+// Default size of the most detailed specular mip and the number of roughness levels.
+pub const DEFAULT_SPECULAR_SIZE: usize = 128;
+pub const DEFAULT_SPECULAR_LEVELS: usize = 6;
+
+// Default width and height of the BRDF lookup table.
+pub const DEFAULT_BRDF_LUT_SIZE: usize = 64;
+
+// Number of spherical harmonic coefficients used for diffuse irradiance (bands 0 to 2).
+pub const SH_COEFFICIENTS: usize = 9;
+
+// The face size used when projecting the environment onto spherical harmonics.
+const SH_SAMPLE_SIZE: usize = 32;
+
+// Number of importance samples per texel for the specular prefilter and BRDF lookup table.
+const SPECULAR_SAMPLES: u32 = 64;
+const BRDF_SAMPLES: u32 = 256;
+
+// A cubemap of linear RGB values stored face by face in row-major order where the first row of a
+// face maps to the t = 0 texture coordinate.
+pub struct CubeMap {
+    pub size: usize,
+    pub faces: Vec<Vec<Vector3<GLfloat>>>,
+}
+
+impl CubeMap {
+    // Creates a cubemap of a given size by evaluating a function for the direction of each texel.
+    pub fn from_fn<F>(size: usize, f: F) -> CubeMap
+            where F: Fn(Vector3<GLfloat>) -> Vector3<GLfloat> {
+        let mut faces = Vec::new();
+        for face in 0..CUBE_FACES {
+            let mut texels = Vec::with_capacity(size * size);
+            for y in 0..size {
+                for x in 0..size {
+                    texels.push(f(texel_direction(face, x, y, size)));
+                }
+            }
+            faces.push(texels);
+        }
+        CubeMap { size: size, faces: faces }
+    }
+
+    // Creates a cubemap from six square sRGB images in the OpenGL face order. The colors are
+    // converted to linear space.
+    pub fn from_images(faces: &[common::Image]) -> Result<CubeMap, String> {
+        if faces.len() != CUBE_FACES {
+            return Err("A cubemap requires exactly six faces.".to_string());
+        }
+        let size = faces[0].width as usize;
+        let mut data = Vec::new();
+        for face in faces {
+            if face.width as usize != size || face.height as usize != size {
+                return Err("Cubemap faces must be square and of equal size.".to_string());
+            }
+            data.push(face.data.iter().map(|p| Vector3::new(
+                    srgb_to_linear(p.red), srgb_to_linear(p.green), srgb_to_linear(p.blue)))
+                    .collect());
+        }
+        Ok(CubeMap { size: size, faces: data })
+    }
+
+    // Gets the texel nearest to a direction.
+    pub fn sample(&self, dir: Vector3<GLfloat>) -> Vector3<GLfloat> {
+        let (face, s, t) = direction_to_face(dir);
+        let x = clamp_index((s * self.size as f32) as isize, self.size);
+        let y = clamp_index((t * self.size as f32) as isize, self.size);
+        self.faces[face][y * self.size + x]
+    }
+
+    // Creates a cubemap at half the resolution by averaging 2x2 blocks of texels.
+    fn downsample(&self) -> CubeMap {
+        let size = if self.size > 1 { self.size / 2 } else { 1 };
+        let mut faces = Vec::new();
+        for face in &self.faces {
+            let mut texels = Vec::with_capacity(size * size);
+            for y in 0..size {
+                for x in 0..size {
+                    let mut sum = Vector3::new(0.0, 0.0, 0.0);
+                    for &(dx, dy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = cmp_min(x * 2 + dx, self.size - 1);
+                        let sy = cmp_min(y * 2 + dy, self.size - 1);
+                        sum = sum + face[sy * self.size + sx];
+                    }
+                    texels.push(sum * 0.25);
+                }
+            }
+            faces.push(texels);
+        }
+        CubeMap { size: size, faces: faces }
+    }
+}
+
+// The source radiance for image-based lighting.
+pub enum Environment {
+    Equirectangular(common::HDRImage),
+    CubeMap(CubeMap),
+}
+
+impl Environment {
+    // Loads an environment from an equirectangular Radiance HDR file.
+    pub fn from_hdr(fpath: &str) -> Result<Environment, String> {
+        let decoded = try!(hdr::decode_hdr(fpath));
+        Ok(Environment::Equirectangular(decoded.image))
+    }
+
+    // Gets the radiance arriving from a direction. The equirectangular mapping treats +Z as up to
+    // match the rest of the engine, with the top row of the image looking straight up.
+    pub fn sample(&self, dir: Vector3<GLfloat>) -> Vector3<GLfloat> {
+        match self {
+            &Environment::CubeMap(ref cube) => cube.sample(dir),
+            &Environment::Equirectangular(ref image) => {
+                let dir = dir.normalize();
+                let u = 0.5 + dir.y.atan2(dir.x) / (2.0 * PI);
+                let v = dir.z.max(-1.0).min(1.0).acos() / PI;
+                sample_bilinear(image, u, v)
+            },
+        }
+    }
+}
+
+// Precomputed image-based lighting data that can be uploaded to the GPU. The specular vector
+// holds the prefiltered mip chain where level i corresponds to a roughness of i / (levels - 1).
+// The irradiance coefficients are already convolved with the cosine lobe, so the irradiance is
+// evaluated by summing the coefficients weighted by the SH basis functions.
+pub struct PrecomputedIBL {
+    pub irradiance_sh: Vec<Vector3<GLfloat>>,
+    pub specular: Vec<CubeMap>,
+    pub brdf_lut_size: usize,
+    pub brdf_lut: Vec<(GLfloat, GLfloat)>,
+}
+
+impl PrecomputedIBL {
+    // Precomputes the lighting data for an environment with the default sizes.
+    pub fn new(env: &Environment) -> PrecomputedIBL {
+        PrecomputedIBL::with_sizes(env, DEFAULT_SPECULAR_SIZE, DEFAULT_SPECULAR_LEVELS,
+                DEFAULT_BRDF_LUT_SIZE)
+    }
+
+    // Precomputes the lighting data for an environment given the size of the most detailed
+    // specular mip, the number of specular mips, and the size of the BRDF lookup table.
+    pub fn with_sizes(env: &Environment, specular_size: usize, specular_levels: usize,
+            brdf_lut_size: usize) -> PrecomputedIBL {
+        PrecomputedIBL {
+                irradiance_sh: project_irradiance_sh(env),
+                specular: prefilter_specular(env, specular_size, specular_levels),
+                brdf_lut_size: brdf_lut_size,
+                brdf_lut: integrate_brdf_lut(brdf_lut_size) }
+    }
+}
+
+// Image-based lighting uploaded to the GPU for use with the MetallicRoughness shading model. The
+// intensity scales the contribution of the environment and must be pushed to the shader with
+// GameWindow::update_environment() after being changed.
+pub struct EnvironmentLighting {
+    pub intensity: GLfloat,
+    pub specular_map: GLuint,
+    pub specular_levels: usize,
+    pub brdf_lut: GLuint,
+    pub irradiance_sh: Vec<GLfloat>,
+}
+
+impl EnvironmentLighting {
+    // Uploads precomputed lighting data. This can only be called after the window context is set
+    // up. Returns an Err if there are no specular mips.
+    pub fn new(ibl: &PrecomputedIBL) -> Result<EnvironmentLighting, String> {
+        if ibl.specular.is_empty() {
+            return Err("Image-based lighting needs at least one specular level.".to_string());
+        }
+        let mut sh = Vec::new();
+        for coeff in &ibl.irradiance_sh {
+            sh.push(coeff.x);
+            sh.push(coeff.y);
+            sh.push(coeff.z);
+        }
+        Ok(EnvironmentLighting { intensity: 1.0, specular_map: upload_cubemap_mips(&ibl.specular),
+                specular_levels: ibl.specular.len(),
+                brdf_lut: upload_brdf_lut(&ibl.brdf_lut, ibl.brdf_lut_size), irradiance_sh: sh })
+    }
+
+    // Convenience constructor that loads, precomputes, and uploads an equirectangular HDR.
+    pub fn from_hdr(fpath: &str) -> Result<EnvironmentLighting, String> {
+        let env = try!(Environment::from_hdr(fpath));
+        EnvironmentLighting::new(&PrecomputedIBL::new(&env))
+    }
+}
+
+// Converts an sRGB encoded byte to linear space using the engine's gamma approximation.
+fn srgb_to_linear(value: u8) -> GLfloat {
+    (value as f32 / 255.0).powf(2.2)
+}
+
+// Clamps a possibly negative index to [0, size).
+fn clamp_index(index: isize, size: usize) -> usize {
+    if index < 0 { 0 } else if index as usize >= size { size - 1 } else { index as usize }
+}
+
+// Returns the smaller of two indices.
+fn cmp_min(a: usize, b: usize) -> usize {
+    if a < b { a } else { b }
+}
+
+// Bilinearly samples an HDR image given texture coordinates in [0, 1] where u wraps around.
+fn sample_bilinear(image: &common::HDRImage, u: f32, v: f32) -> Vector3<GLfloat> {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let x = u * width as f32 - 0.5;
+    let y = v * height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let wrap = |i: isize| (((i % width as isize) + width as isize) % width as isize) as usize;
+    let (xa, xb) = (wrap(x0 as isize), wrap(x0 as isize + 1));
+    let (ya, yb) = (clamp_index(y0 as isize, height), clamp_index(y0 as isize + 1, height));
+    let top = image.data[ya * width + xa] * (1.0 - fx) + image.data[ya * width + xb] * fx;
+    let bottom = image.data[yb * width + xa] * (1.0 - fx) + image.data[yb * width + xb] * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+// Gets the normalized direction through the center of a cubemap texel. This is the inverse of the
+// major axis selection table in the OpenGL specification.
+pub fn texel_direction(face: usize, x: usize, y: usize, size: usize) -> Vector3<GLfloat> {
+    let sc = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+    let tc = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+    let dir = match face {
+        0 => Vector3::new(1.0, -tc, -sc),
+        1 => Vector3::new(-1.0, -tc, sc),
+        2 => Vector3::new(sc, 1.0, tc),
+        3 => Vector3::new(sc, -1.0, -tc),
+        4 => Vector3::new(sc, -tc, 1.0),
+        _ => Vector3::new(-sc, -tc, -1.0),
+    };
+    dir.normalize()
+}
+
+// Gets the cubemap face and the (s, t) coordinates in [0, 1] that a direction maps to.
+pub fn direction_to_face(dir: Vector3<GLfloat>) -> (usize, f32, f32) {
+    let (ax, ay, az) = (dir.x.abs(), dir.y.abs(), dir.z.abs());
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if dir.x > 0.0 { (0, -dir.z, -dir.y, ax) } else { (1, dir.z, -dir.y, ax) }
+    } else if ay >= az {
+        if dir.y > 0.0 { (2, dir.x, dir.z, ay) } else { (3, dir.x, -dir.z, ay) }
+    } else {
+        if dir.z > 0.0 { (4, dir.x, -dir.y, az) } else { (5, -dir.x, -dir.y, az) }
+    };
+    (face, (sc / ma + 1.0) * 0.5, (tc / ma + 1.0) * 0.5)
+}
+
+// Gets the solid angle subtended by a cubemap texel.
+fn texel_solid_angle(x: usize, y: usize, size: usize) -> f32 {
+    let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+    let texel = 2.0 / size as f32;
+    texel * texel / (1.0 + u * u + v * v).powf(1.5)
+}
+
+// Evaluates the nine real spherical harmonic basis functions for bands 0 to 2.
+pub fn sh_basis(dir: Vector3<GLfloat>) -> [f32; SH_COEFFICIENTS] {
+    let (x, y, z) = (dir.x, dir.y, dir.z);
+    [0.282095,
+     0.488603 * y, 0.488603 * z, 0.488603 * x,
+     1.092548 * x * y, 1.092548 * y * z, 0.315392 * (3.0 * z * z - 1.0),
+     1.092548 * x * z, 0.546274 * (x * x - y * y)]
+}
+
+// Projects the environment onto spherical harmonics and convolves the result with the clamped
+// cosine lobe so that the coefficients directly give the irradiance around a normal.
+fn project_irradiance_sh(env: &Environment) -> Vec<Vector3<GLfloat>> {
+    let mut coeffs = vec![Vector3::new(0.0, 0.0, 0.0); SH_COEFFICIENTS];
+    for face in 0..CUBE_FACES {
+        for y in 0..SH_SAMPLE_SIZE {
+            for x in 0..SH_SAMPLE_SIZE {
+                let dir = texel_direction(face, x, y, SH_SAMPLE_SIZE);
+                let weight = texel_solid_angle(x, y, SH_SAMPLE_SIZE);
+                let radiance = env.sample(dir) * weight;
+                let basis = sh_basis(dir);
+                for i in 0..SH_COEFFICIENTS {
+                    coeffs[i] = coeffs[i] + radiance * basis[i];
+                }
+            }
+        }
+    }
+    // Convolution with the cosine lobe scales each band by a constant.
+    let band_scale = [PI, 2.0 * PI / 3.0, 2.0 * PI / 3.0, 2.0 * PI / 3.0,
+            PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0, PI / 4.0];
+    for i in 0..SH_COEFFICIENTS {
+        coeffs[i] = coeffs[i] * band_scale[i];
+    }
+    coeffs
+}
+
+// Gets the i-th point of an n point Hammersley sequence in [0, 1)^2.
+fn hammersley(i: u32, n: u32) -> (f32, f32) {
+    let mut bits = i;
+    bits = (bits << 16) | (bits >> 16);
+    bits = ((bits & 0x55555555) << 1) | ((bits & 0xAAAAAAAA) >> 1);
+    bits = ((bits & 0x33333333) << 2) | ((bits & 0xCCCCCCCC) >> 2);
+    bits = ((bits & 0x0F0F0F0F) << 4) | ((bits & 0xF0F0F0F0) >> 4);
+    bits = ((bits & 0x00FF00FF) << 8) | ((bits & 0xFF00FF00) >> 8);
+    (i as f32 / n as f32, bits as f32 * 2.3283064365386963e-10)
+}
+
+// Importance samples a GGX lobe around +Z and returns the tangent space halfway vector.
+fn importance_sample_ggx(xi: (f32, f32), roughness: f32) -> Vector3<GLfloat> {
+    let alpha = roughness * roughness;
+    let phi = 2.0 * PI * xi.0;
+    let cos_theta = ((1.0 - xi.1) / (1.0 + (alpha * alpha - 1.0) * xi.1)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+// Transforms a tangent space vector into world space around a normal.
+fn tangent_to_world(v: Vector3<GLfloat>, normal: Vector3<GLfloat>) -> Vector3<GLfloat> {
+    let up = if normal.z.abs() < 0.999 {
+        Vector3::new(0.0, 0.0, 1.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    tangent * v.x + bitangent * v.y + normal * v.z
+}
+
+// The GGX normal distribution function.
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha2 = roughness * roughness * roughness * roughness;
+    let denom = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (PI * denom * denom)
+}
+
+// Prefilters the environment with the GGX lobe for each roughness level. Following Karis, the
+// view and reflection directions are assumed to equal the normal, and samples are read from a
+// box filtered mip chain of the environment based on their pdf to reduce aliasing.
+fn prefilter_specular(env: &Environment, size: usize, levels: usize) -> Vec<CubeMap> {
+    let mut source = vec![CubeMap::from_fn(size, |dir| env.sample(dir))];
+    while source[source.len() - 1].size > 1 {
+        let next = source[source.len() - 1].downsample();
+        source.push(next);
+    }
+    let texel_angle = 4.0 * PI / (CUBE_FACES * size * size) as f32;
+    let mut mips = Vec::new();
+    for level in 0..levels {
+        let level_size = if size >> level > 0 { size >> level } else { 1 };
+        if level == 0 || levels == 1 {
+            mips.push(CubeMap::from_fn(level_size, |dir| source[0].sample(dir)));
+            continue;
+        }
+        let roughness = level as f32 / (levels - 1) as f32;
+        mips.push(CubeMap::from_fn(level_size, |normal| {
+            let mut total = Vector3::new(0.0, 0.0, 0.0);
+            let mut weight = 0.0;
+            for i in 0..SPECULAR_SAMPLES {
+                let halfway = tangent_to_world(
+                        importance_sample_ggx(hammersley(i, SPECULAR_SAMPLES), roughness), normal);
+                let light = halfway * (2.0 * normal.dot(halfway)) - normal;
+                let n_dot_l = normal.dot(light);
+                if n_dot_l <= 0.0 { continue; }
+                let pdf = distribution_ggx(normal.dot(halfway).max(0.0), roughness) / 4.0;
+                let sample_angle = 1.0 / (SPECULAR_SAMPLES as f32 * pdf + 1e-4);
+                let lod = (0.5 * (sample_angle / texel_angle).log2() + 1.0).max(0.0);
+                let mip = cmp_min(lod.round() as usize, source.len() - 1);
+                total = total + source[mip].sample(light) * n_dot_l;
+                weight += n_dot_l;
+            }
+            if weight > 0.0 { total / weight } else { total }
+        }));
+    }
+    mips
+}
+
+// Integrates the split-sum BRDF scale and bias terms for every (n_dot_v, roughness) pair. The
+// geometry term uses the k = roughness^2 / 2 remapping for image-based lighting.
+fn integrate_brdf_lut(size: usize) -> Vec<(GLfloat, GLfloat)> {
+    let mut lut = Vec::with_capacity(size * size);
+    for y in 0..size {
+        let roughness = (y as f32 + 0.5) / size as f32;
+        let k = roughness * roughness / 2.0;
+        for x in 0..size {
+            let n_dot_v = (x as f32 + 0.5) / size as f32;
+            let view = Vector3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+            let (mut scale, mut bias) = (0.0, 0.0);
+            for i in 0..BRDF_SAMPLES {
+                let halfway = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), roughness);
+                let light = halfway * (2.0 * view.dot(halfway)) - view;
+                let n_dot_l = light.z;
+                if n_dot_l <= 0.0 { continue; }
+                let n_dot_h = halfway.z.max(0.0);
+                let v_dot_h = view.dot(halfway).max(0.0);
+                let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+                let g = g_v * n_dot_l / (n_dot_l * (1.0 - k) + k);
+                let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+                let fresnel = (1.0 - v_dot_h).powi(5);
+                scale += (1.0 - fresnel) * g_vis;
+                bias += fresnel * g_vis;
+            }
+            lut.push((scale / BRDF_SAMPLES as f32, bias / BRDF_SAMPLES as f32));
+        }
+    }
+    lut
+}
+
+// Uploads the faces of a cubemap as a single mip level of the currently bound cubemap texture.
+unsafe fn upload_cubemap_level(cube: &CubeMap, level: usize) {
+    for (face, texels) in cube.faces.iter().enumerate() {
+        let mut data: Vec<GLfloat> = Vec::with_capacity(texels.len() * 3);
+        for texel in texels {
+            data.push(texel.x);
+            data.push(texel.y);
+            data.push(texel.z);
+        }
+        gl::TexImage2D(
+                gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum, level as GLint,
+                gl::RGB16F as GLint, cube.size as GLsizei, cube.size as GLsizei, 0, gl::RGB,
+                gl::FLOAT, vec_to_addr!(data));
+    }
+}
+
+// Sets the sampling parameters of the currently bound cubemap texture given its number of mips.
+unsafe fn set_cubemap_sampling(levels: usize) {
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_BASE_LEVEL, 0);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAX_LEVEL, levels as GLint - 1);
+    gl::TexParameteri(
+            gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+    gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+}
+
+// Uploads a cubemap mip chain as a floating point cubemap texture and returns its ID.
+fn upload_cubemap_mips(mips: &Vec<CubeMap>) -> GLuint { unsafe {
+    let mut texture_id = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture_id);
+    for (level, mip) in mips.iter().enumerate() {
+        upload_cubemap_level(mip, level);
+    }
+    set_cubemap_sampling(mips.len());
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+    texture_id
+}}
+
+// Uploads a cubemap as a floating point cubemap texture with a generated mip chain and returns its
+// ID. This can only be called after the window context is set up.
+pub fn upload_cubemap(cube: &CubeMap) -> GLuint { unsafe {
+    let mut texture_id = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, texture_id);
+    upload_cubemap_level(cube, 0);
+    gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+    let mut levels = 1;
+    while cube.size >> levels > 0 {
+        levels += 1;
+    }
+    set_cubemap_sampling(levels);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+    texture_id
+}}
+
+// Uploads the BRDF lookup table as a two channel floating point texture and returns its ID.
+fn upload_brdf_lut(lut: &Vec<(GLfloat, GLfloat)>, size: usize) -> GLuint { unsafe {
+    let mut data: Vec<GLfloat> = Vec::with_capacity(lut.len() * 2);
+    for &(scale, bias) in lut {
+        data.push(scale);
+        data.push(bias);
+    }
+    let mut texture_id = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+    gl::TexImage2D(
+            gl::TEXTURE_2D, 0, gl::RG16F as GLint, size as GLsizei, size as GLsizei, 0, gl::RG,
+            gl::FLOAT, vec_to_addr!(data));
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    texture_id
+}}
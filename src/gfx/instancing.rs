@@ -0,0 +1,127 @@
+// Defines an InstanceBatch which allows for drawing many copies of the same ModelInfo with a single
+// instanced draw call. Since a ModelInfo holds both the mesh and the material, a batch corresponds
+// to exactly one mesh/material pair. Per-instance model and normal matrices along with an optional
+// color tint and a free-form vec4 of custom data are packed into a GPU buffer that is read by the
+// vertex shader with an attribute divisor of 1.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use gfx::color;
+use gfx::layers;
+use gfx::model;
+use gfx::types::*;
+use std::mem;
+use std::rc::Rc;
+
+// Contents of a single instance in the instance buffer.
+// [M_00 ... M_33  N_00 ... N_22  C_r  C_g  C_b  C_a  D_x  D_y  D_z  D_w]
+pub const INSTANCE_MODEL_SIZE: usize = 16;
+pub const INSTANCE_NORMAL_SIZE: usize = 9;
+pub const INSTANCE_COLOR_SIZE: usize = 4;
+pub const INSTANCE_DATA_SIZE: usize = 4;
+pub const INSTANCE_SIZE: usize = INSTANCE_MODEL_SIZE + INSTANCE_NORMAL_SIZE + INSTANCE_COLOR_SIZE +
+        INSTANCE_DATA_SIZE;
+
+// A collection of instances of a single ModelInfo that are drawn together with
+// GameWindow::draw_instanced(). Instances are addressed by the index returned when they are pushed.
+// Changes are kept on the CPU until the next upload, which happens automatically on draw. Every
+// instance in a batch is on the batch's render layers.
+pub struct InstanceBatch {
+    pub info: Rc<model::ModelInfo>,
+    pub layers: layers::LayerMask,
+    instances: Vec<GLfloat>,
+    buffer: GLuint,
+    capacity: usize,
+    dirty: bool,
+}
+
+impl InstanceBatch {
+    // Creates an empty batch for a ModelInfo. This can only be created after the window context
+    // is set up.
+    pub fn new(info: Rc<model::ModelInfo>) -> InstanceBatch {
+        let mut buffer = 0;
+        unsafe { gl::GenBuffers(1, &mut buffer); }
+        InstanceBatch { info: info, layers: layers::DEFAULT_LAYER, instances: Vec::new(),
+                buffer: buffer, capacity: 0, dirty: false }
+    }
+
+    // Gets the number of instances in the batch.
+    pub fn len(&self) -> usize {
+        self.instances.len() / INSTANCE_SIZE
+    }
+
+    // Returns true if there are no instances in the batch.
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    // Removes every instance from the batch.
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.dirty = true;
+    }
+
+    // Adds an instance with the transform of a ModelInstance, no tint, and zeroed custom data and
+    // returns its index. The ModelInstance must be up to date (see ModelInstance::update()).
+    pub fn push(&mut self, instance: &model::ModelInstance) -> usize {
+        let white = color::Color::new_rgb(1.0, 1.0, 1.0);
+        self.push_with_data(instance, &white, [0.0; INSTANCE_DATA_SIZE])
+    }
+
+    // Adds an instance with the transform of a ModelInstance, a color that tints the material
+    // color, and custom data that is passed through to the shader and returns its index.
+    pub fn push_with_data(&mut self, instance: &model::ModelInstance, tint: &color::Color,
+            data: [GLfloat; INSTANCE_DATA_SIZE]) -> usize {
+        let index = self.len();
+        self.instances.extend(vec![0.0; INSTANCE_SIZE]);
+        self.set(index, instance, tint, data);
+        index
+    }
+
+    // Overwrites the instance at an index.
+    pub fn set(&mut self, index: usize, instance: &model::ModelInstance, tint: &color::Color,
+            data: [GLfloat; INSTANCE_DATA_SIZE]) {
+        let mut packed: Vec<GLfloat> = Vec::with_capacity(INSTANCE_SIZE);
+        let m = instance.model;
+        for col in &[m.x, m.y, m.z, m.w] {
+            packed.extend(vec![col.x, col.y, col.z, col.w]);
+        }
+        let n = instance.normal;
+        for col in &[n.x, n.y, n.z] {
+            packed.extend(vec![col.x, col.y, col.z]);
+        }
+        packed.extend(color_to_vec!(tint));
+        packed.extend(data.iter().cloned());
+        let start = index * INSTANCE_SIZE;
+        for (i, value) in packed.into_iter().enumerate() {
+            self.instances[start + i] = value;
+        }
+        self.dirty = true;
+    }
+
+    // Uploads the instances to the GPU if they have changed since the last upload and returns the
+    // ID of the instance buffer. The buffer is reallocated when the batch outgrows it.
+    pub fn upload(&mut self) -> GLuint { unsafe {
+        if self.dirty {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer);
+            let count = self.len();
+            if count > self.capacity {
+                self.capacity = count;
+                gl::BufferData(
+                        gl::ARRAY_BUFFER, float_size!(count * INSTANCE_SIZE, GLsizeiptr),
+                        0 as CVoid, gl::DYNAMIC_DRAW);
+            }
+            if count > 0 {
+                gl::BufferSubData(
+                        gl::ARRAY_BUFFER, 0, float_size!(self.instances.len(), GLsizeiptr),
+                        vec_to_addr!(self.instances));
+            }
+            self.dirty = false;
+        }
+        self.buffer
+    }}
+}
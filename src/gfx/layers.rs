@@ -0,0 +1,78 @@
+// Defines render layers, which let cameras and lights pick out which renderables they see. Every
+// ModelInstance and InstanceBatch has a 32-bit mask of the layers it is on, and every camera and
+// light has a culling mask of the layers it affects. A camera only draws renderables whose layers
+// overlap its culling mask and a light only lights them under the same rule. For example, a
+// minimap camera can see only a "map" layer, and a muzzle flash light can leave the world alone
+// and only light the first-person weapon.
+//
+// Layers are plain bits, so a LayerNames is only needed to give them readable names such as in
+// scene files. Bit 0 is the default layer that renderables start on, and cameras and lights start
+// out affecting every layer.
+//
+// Brian Ho
+// brian@brkho.com
+
+// A set of layers with one bit per layer.
+pub type LayerMask = u32;
+
+// The layer that renderables are on when created.
+pub const DEFAULT_LAYER: LayerMask = 1;
+
+// Masks that contain every layer and no layers.
+pub const ALL_LAYERS: LayerMask = !0;
+pub const NO_LAYERS: LayerMask = 0;
+
+// The number of layers that fit in a LayerMask.
+pub const MAX_LAYERS: usize = 32;
+
+// Names for up to MAX_LAYERS layers. The default layer is named "default".
+pub struct LayerNames {
+    names: Vec<String>,
+}
+
+impl LayerNames {
+    // Creates a set of names with only the default layer named.
+    pub fn new() -> LayerNames {
+        LayerNames { names: vec!["default".to_string()] }
+    }
+
+    // Names the next unused layer and returns its mask, or returns the existing mask if the name
+    // is already taken. Returns an Err if every layer is named.
+    pub fn add(&mut self, name: &str) -> Result<LayerMask, String> {
+        if let Some(mask) = self.get(name) {
+            return Ok(mask);
+        }
+        if self.names.len() >= MAX_LAYERS {
+            return Err(format!("Cannot add layer {}: all {} layers are named.", name,
+                    MAX_LAYERS));
+        }
+        self.names.push(name.to_string());
+        Ok(1 << (self.names.len() - 1))
+    }
+
+    // Gets the mask of a named layer.
+    pub fn get(&self, name: &str) -> Option<LayerMask> {
+        self.names.iter().position(|n| n == name).map(|i| 1 << i)
+    }
+
+    // Gets the mask containing every layer in a list of names. Returns an Err if any name isn't
+    // a layer.
+    pub fn mask(&self, names: &[&str]) -> Result<LayerMask, String> {
+        let mut mask = NO_LAYERS;
+        for name in names {
+            mask |= try!(self.get(name).ok_or(format!("Unknown layer: {}", name)));
+        }
+        Ok(mask)
+    }
+
+    // Gets the names of the named layers in a mask, in bit order.
+    pub fn names<'a>(&'a self, mask: LayerMask) -> Vec<&'a str> {
+        self.names.iter().enumerate().filter(|&(i, _)| mask & (1 << i) != 0)
+                .map(|(_, n)| &n[..]).collect()
+    }
+}
+
+// Returns true if a renderable on some layers is seen by a camera or light with a culling mask.
+pub fn is_visible(layers: LayerMask, culling_mask: LayerMask) -> bool {
+    layers & culling_mask != 0
+}
@@ -4,12 +4,16 @@
 // with no attenuation). SpotLight is like a PointLight except it has a cutoff angle with a dropoff
 // factor.
 //
+// Every light has a culling mask of the render layers it lights, so renderables on no layers in
+// the mask are left unlit by it.
+//
 // Brian Ho
 // brian@brkho.com
 
 extern crate gl;
 
 use gfx::color;
+use gfx::layers;
 use gfx::types::*;
 
 // Light source that emanates from a fixed point with specified intensity and attenuation.
@@ -19,6 +23,7 @@ pub struct PointLight {
     pub const_attn: f32,
     pub linear_attn: f32,
     pub quad_attn: f32,
+    pub culling_mask: layers::LayerMask,
     pub light_index: Option<usize>,
 }
 
@@ -27,7 +32,8 @@ impl PointLight {
     pub fn new(intensity: color::Color, position: Vector3D, const_attn: f32, linear_attn: f32,
             quad_attn: f32) -> PointLight {
         PointLight { intensity: intensity, position: position, const_attn: const_attn,
-                linear_attn: linear_attn, quad_attn: quad_attn, light_index: None }
+                linear_attn: linear_attn, quad_attn: quad_attn,
+                culling_mask: layers::ALL_LAYERS, light_index: None }
     }
 }
 
@@ -35,13 +41,15 @@ impl PointLight {
 pub struct DirectionalLight {
     pub intensity: color::Color,
     pub direction: Vector3D,
+    pub culling_mask: layers::LayerMask,
     pub light_index: Option<usize>,
 }
 
 impl DirectionalLight {
     // Default constructor for a DirectionalLight.
     pub fn new(intensity: color::Color, direction: Vector3D) -> DirectionalLight {
-        DirectionalLight { intensity: intensity, direction: direction, light_index: None }
+        DirectionalLight { intensity: intensity, direction: direction,
+                culling_mask: layers::ALL_LAYERS, light_index: None }
     }
 }
 
@@ -56,6 +64,7 @@ pub struct SpotLight {
     pub quad_attn: f32,
     pub cutoff: f32,
     pub dropoff: f32,
+    pub culling_mask: layers::LayerMask,
     pub light_index: Option<usize>,
 }
 
@@ -65,7 +74,8 @@ impl SpotLight {
             linear_attn: f32, quad_attn: f32, cutoff: f32, dropoff: f32) -> SpotLight {
         SpotLight { intensity: intensity, position: position, const_attn: const_attn,
                 direction: direction, linear_attn: linear_attn, quad_attn: quad_attn,
-                cutoff: cutoff, dropoff: dropoff, light_index: None }
+                cutoff: cutoff, dropoff: dropoff, culling_mask: layers::ALL_LAYERS,
+                light_index: None }
 
     }
 }
\ No newline at end of file
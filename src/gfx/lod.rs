@@ -0,0 +1,180 @@
+// Defines a LodGroup, which is a renderable made of several meshes of decreasing detail. Each
+// time the group is drawn, the level is picked from the active camera either by the distance to
+// the camera or by how much of the screen the group's bounds cover, and nothing is drawn once the
+// group is past its cull threshold. Since the choice depends on the camera, every camera keeps its
+// own current level.
+//
+// Switching levels can cross-fade over a short time to hide popping. Both levels are drawn during
+// the fade with complementary dither patterns, so the fade doesn't need blending or sorting.
+//
+// Usage of a LodGroup:
+// - Create it with new() from meshes ordered from most to least detailed.
+// - Set the transform with set_model(), or attach it to a Scene node which does so on update().
+// - Call draw(&mut window) instead of drawing the meshes directly.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate time;
+
+use gfx::bounds;
+use gfx::camera::{GameCamera, Projection};
+use gfx::game_window::GameWindow;
+use gfx::layers;
+use gfx::model;
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Matrix, SquareMatrix};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// How the level of a LodGroup is chosen.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LodMetric {
+    // Thresholds are distances from the camera in world units, with each level used from its
+    // threshold out to the next level's threshold.
+    Distance,
+    // Thresholds are fractions of the screen height covered by the bounding sphere, with each
+    // level used while the coverage is at least its threshold.
+    ScreenCoverage,
+}
+
+// A single mesh of a LodGroup along with the threshold at which it is used.
+pub struct LodLevel {
+    pub instance: model::ModelInstance,
+    pub threshold: GLfloat,
+}
+
+// The level that a camera currently sees and the level it is fading from.
+#[derive(Copy, Clone, Debug)]
+struct LodState {
+    current: Option<usize>,
+    previous: Option<usize>,
+    fade_start: f64,
+}
+
+// A renderable that switches between meshes of different detail.
+pub struct LodGroup {
+    pub levels: Vec<LodLevel>,
+    pub metric: LodMetric,
+    pub cull_threshold: Option<GLfloat>,    // Distance past or coverage below which it is hidden.
+    pub bias: GLfloat,                      // Scales distances, so larger values lower detail.
+    pub fade_time: GLfloat,                 // Seconds to cross-fade, or 0.0 to switch instantly.
+    states: RefCell<HashMap<usize, LodState>>,
+}
+
+impl LodGroup {
+    // Creates a group from meshes ordered from most to least detailed, each with its threshold.
+    // Levels switch instantly by default.
+    pub fn new(levels: Vec<(Rc<model::ModelInfo>, GLfloat)>, metric: LodMetric) -> LodGroup {
+        let levels = levels.into_iter().map(|(info, threshold)| {
+            LodLevel { instance: model::ModelInstance::from(info), threshold: threshold }
+        }).collect();
+        LodGroup { levels: levels, metric: metric, cull_threshold: None, bias: 1.0,
+                fade_time: 0.0, states: RefCell::new(HashMap::new()) }
+    }
+
+    // Sets the model matrix of every level and recomputes their normal matrices.
+    pub fn set_model(&mut self, model: cgmath::Matrix4<GLfloat>) {
+        let normal = model.invert().unwrap_or(cgmath::Matrix4::identity()).transpose();
+        for level in &mut self.levels {
+            level.instance.model = model;
+            level.instance.normal = normal;
+        }
+    }
+
+    // Puts every level on a set of render layers.
+    pub fn set_layers(&mut self, layers: layers::LayerMask) {
+        for level in &mut self.levels {
+            level.instance.layers = layers;
+        }
+    }
+
+    // Gets the bounds of the most detailed level in the world.
+    pub fn get_world_bounds(&self) -> bounds::Aabb {
+        match self.levels.first() {
+            Some(level) => level.instance.get_world_bounds(),
+            None => bounds::Aabb::empty(),
+        }
+    }
+
+    // Gets the fraction of the screen height that a sphere covers from a camera.
+    fn coverage(camera: &GameCamera, center: Vector3D, radius: GLfloat) -> GLfloat {
+        match camera.projection {
+            Projection::Perspective { fov, .. } => {
+                let dist = (center - camera.pos).length().max(1e-4);
+                radius / (dist * (0.5 * fov.to_radians()).tan())
+            },
+            Projection::Orthographic { bottom, top, .. } => 2.0 * radius / (top - bottom).abs(),
+        }
+    }
+
+    // Picks the level to draw from a camera, or None if the group should be hidden.
+    pub fn select_level(&self, camera: &GameCamera) -> Option<usize> {
+        if self.levels.is_empty() { return None; }
+        let bounds = self.get_world_bounds();
+        let center = bounds.center();
+        match self.metric {
+            LodMetric::Distance => {
+                let dist = (center - camera.pos).length() * self.bias;
+                if self.cull_threshold.map_or(false, |t| dist > t) { return None; }
+                Some(self.levels.iter().rposition(|l| dist >= l.threshold).unwrap_or(0))
+            },
+            LodMetric::ScreenCoverage => {
+                let radius = bounds.extents().length();
+                let coverage = LodGroup::coverage(camera, center, radius) / self.bias;
+                if self.cull_threshold.map_or(false, |t| coverage < t) { return None; }
+                Some(self.levels.iter().position(|l| coverage >= l.threshold)
+                        .unwrap_or(self.levels.len() - 1))
+            },
+        }
+    }
+
+    // Gets the level that a camera saw the last time the group was drawn from it.
+    pub fn get_current_level(&self, camera_handle: usize) -> Option<usize> {
+        self.states.borrow().get(&camera_handle).and_then(|s| s.current)
+    }
+
+    // Draws the level picked from the active camera, cross-fading from the previous level if a
+    // switch is in progress. Nothing is drawn if there is no active camera.
+    pub fn draw(&self, window: &mut GameWindow) {
+        let (handle, level) = match (window.get_active_camera_handle(),
+                window.get_active_camera()) {
+            (Some(handle), Ok(camera)) => (handle, self.select_level(camera)),
+            _ => { return; },
+        };
+        let now = time::precise_time_s();
+        let state = {
+            let mut states = self.states.borrow_mut();
+            let state = states.entry(handle).or_insert(LodState { current: level,
+                    previous: None, fade_start: now });
+            if state.current != level {
+                state.previous = if self.fade_time > 0.0 { state.current } else { None };
+                state.current = level;
+                state.fade_start = now;
+            }
+            let progress = if self.fade_time > 0.0 {
+                ((now - state.fade_start) as GLfloat / self.fade_time).min(1.0)
+            } else { 1.0 };
+            if progress >= 1.0 {
+                state.previous = None;
+            }
+            (*state, progress)
+        };
+        match state {
+            (LodState { current, previous: Some(previous), .. }, progress) => {
+                window.draw_instance_faded(&self.levels[previous].instance, -progress);
+                if let Some(current) = current {
+                    if progress > 0.0 {
+                        window.draw_instance_faded(&self.levels[current].instance, progress);
+                    }
+                }
+            },
+            (LodState { current: Some(current), .. }, _) => {
+                window.draw_instance(&self.levels[current].instance);
+            },
+            _ => {},
+        }
+    }
+}
@@ -29,6 +29,12 @@ macro_rules! uniform_mat4 { ($p:expr, $s:expr, $l: expr) =>
             gl::GetUniformLocation($p, gl_str!($s)), 1,
             gl::FALSE as GLboolean, ($l).as_ptr())) }
 
+#[macro_export]
+// Macro for updating a vec2 uniform.
+macro_rules! uniform_vec2 { ($p:expr, $s:expr, $l: expr) =>
+        (gl::Uniform2fv(
+            gl::GetUniformLocation($p, gl_str!($s)), 1, ($l).as_ptr())) }
+
 #[macro_export]
 // Macro for updating a vec3 uniform.
 macro_rules! uniform_vec3 { ($p:expr, $s:expr, $l: expr) =>
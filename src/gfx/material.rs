@@ -6,26 +6,67 @@
 extern crate gl;
 
 use gfx::color;
+use gfx::texture::{ColorSpace, TextureManager};
 use gfx::types::*;
 use std::mem;
-use util::{common, bmp};
+use util::common;
+
+// The lighting model used by the shader to render a Material. BlinnPhong uses the diffuse and
+// specular maps with a shininess factor. MetallicRoughness is a physically based model that uses
+// a Cook-Torrance GGX specular term and consumes albedo, metallic-roughness, and occlusion maps
+// laid out the same way as glTF 2.0 materials.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ShadingModel {
+    BlinnPhong,
+    MetallicRoughness,
+}
+
+// The diffuse term used by the MetallicRoughness shading model. Burley is a bit more expensive but
+// accounts for retroreflection at grazing angles on rough surfaces.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DiffuseModel {
+    Lambert,
+    Burley,
+}
+
+// The textures that a splat Material blends in place of its diffuse map. The weights map holds the
+// weight of each of up to four layers in its channels, and the layers are repeated across the
+// surface a number of times given by the tiling.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SplatMap {
+    pub weights: GLuint,
+    pub layers: [GLuint; 4],
+    pub tiling: GLfloat,
+}
 
 // Describes a material for a model that contains a color, diffuse map, specular map, and a
 // shininess factor for specular. This can only be created after the window context is set up.
+// Physically based materials store their albedo in the diffuse map and ignore the specular map and
+// shininess in favor of the metallic and roughness factors.
+#[derive(Copy, Clone)]
 pub struct Material {
     pub color: color::Color,
     pub diffuse: GLuint,
     pub specular: GLuint,
     pub normal: Option<GLuint>,
     pub shininess: GLfloat,
+    pub shading_model: ShadingModel,
+    pub diffuse_model: DiffuseModel,
+    pub metallic_roughness: Option<GLuint>,
+    pub occlusion: Option<GLuint>,
+    pub metallic: GLfloat,
+    pub roughness: GLfloat,
+    pub splat: Option<SplatMap>,
 }
 
 impl Material {
     // Default constructor that automatically assigns a white color given a shininess and paths to
-    // the diffuse and specular maps as BMPs.
-    pub fn new(diffuse_name: Option<&str>, specular_name: Option<&str>,
-            normal_name: Option<&str>, shininess: GLfloat) -> Material {
-        Material::new_with_color(diffuse_name, specular_name, normal_name,
+    // the diffuse and specular maps. The maps are loaded through a TextureManager, so they can be
+    // any format it supports and materials that share a file share one texture.
+    pub fn new(textures: &mut TextureManager, diffuse_name: Option<&str>,
+            specular_name: Option<&str>, normal_name: Option<&str>, shininess: GLfloat)
+            -> Result<Material, String> {
+        Material::new_with_color(textures, diffuse_name, specular_name, normal_name,
                 color::Color::new_rgb(1.0, 1.0, 1.0), shininess)
     }
 
@@ -45,13 +86,74 @@ impl Material {
         texture_id
     }}
 
-    // Reads and binds a BMP texture given a name and returns the corresponding texture ID. This
-    // method also lets the caller specify if the texture should be in sRGB space or not.
-    fn read_and_bind_bmp(texture_name: Option<&str>, srgb: bool) -> GLuint {
-        if let Some(name) = texture_name {
-            let texture = bmp::decode_bmp(name).unwrap().image;
-            Material::bind_image(&texture, srgb)
-        } else { 0 }
+    // Loads a texture given a name through a TextureManager and returns the corresponding texture
+    // ID, or 0 if there is no name. This method also lets the caller specify the color space.
+    fn load_texture(textures: &mut TextureManager, texture_name: Option<&str>,
+            color_space: ColorSpace) -> Result<GLuint, String> {
+        match texture_name {
+            Some(name) => textures.load(name, color_space),
+            None => Ok(0),
+        }
+    }
+
+    // Same as load_texture() but returns None instead of 0 for a missing texture.
+    fn load_texture_opt(textures: &mut TextureManager, texture_name: Option<&str>,
+            color_space: ColorSpace) -> Result<Option<GLuint>, String> {
+        match texture_name {
+            Some(name) => Ok(Some(try!(textures.load(name, color_space)))),
+            None => Ok(None),
+        }
+    }
+
+    // Binds an optional Image and returns the texture ID if there was one.
+    fn bind_image_opt(texture: &Option<common::Image>, srgb: bool) -> Option<GLuint> {
+        match texture {
+            &Some(ref i) => Some(Material::bind_image(i, srgb)),
+            &None => None,
+        }
+    }
+
+    // Helper constructor for a BlinnPhong material given already bound textures.
+    fn blinn_phong(diffuse: GLuint, specular: GLuint, normal: Option<GLuint>,
+            color: color::Color, shininess: GLfloat) -> Material {
+        Material { color: color, diffuse: diffuse, specular: specular, normal: normal,
+                shininess: shininess, shading_model: ShadingModel::BlinnPhong,
+                diffuse_model: DiffuseModel::Lambert, metallic_roughness: None, occlusion: None,
+                metallic: 0.0, roughness: 1.0, splat: None }
+    }
+
+    // Helper constructor for a MetallicRoughness material given already bound textures.
+    fn metallic_roughness(albedo: GLuint, normal: Option<GLuint>, mr: Option<GLuint>,
+            occlusion: Option<GLuint>, color: color::Color, metallic: GLfloat,
+            roughness: GLfloat) -> Material {
+        Material { color: color, diffuse: albedo, specular: 0, normal: normal, shininess: 0.0,
+                shading_model: ShadingModel::MetallicRoughness,
+                diffuse_model: DiffuseModel::Lambert, metallic_roughness: mr,
+                occlusion: occlusion, metallic: metallic, roughness: roughness, splat: None }
+    }
+
+    // Creates a BlinnPhong Material from textures that are already on the GPU such as ones from a
+    // TextureManager or the color attachment of a RenderTarget. A texture ID of 0 uses the default
+    // white texture.
+    pub fn from_textures(diffuse: GLuint, specular: GLuint, normal: Option<GLuint>,
+            color: color::Color, shininess: GLfloat) -> Material {
+        Material::blinn_phong(diffuse, specular, normal, color, shininess)
+    }
+
+    // Creates a BlinnPhong Material that blends up to four textures that are already on the GPU by
+    // the weights in the channels of a splat map Image, which is used for terrain. A texture ID of
+    // 0 uses the default white texture.
+    pub fn from_splat(weights: &common::Image, layers: &[GLuint], tiling: GLfloat,
+            color: color::Color, shininess: GLfloat) -> Material {
+        let mut splat_layers = [0; 4];
+        for (layer, &id) in splat_layers.iter_mut().zip(layers) {
+            *layer = id;
+        }
+        let weights_handle = Material::bind_image(weights, false);
+        let mut mat = Material::blinn_phong(0, 0, None, color, shininess);
+        mat.splat = Some(SplatMap { weights: weights_handle, layers: splat_layers,
+                tiling: tiling });
+        mat
     }
 
     pub fn from_images(diffuse: &Option<common::Image>, specular: &Option<common::Image>,
@@ -62,24 +164,52 @@ impl Material {
         let specular_handle = match specular {
             &Some(ref i) => Material::bind_image(i, false),
             &None => 0 };
-        let normal_handle = match normal {
-            &Some(ref i) => Some(Material::bind_image(i, false)),
-            &None => None };
-        Material { color: color, diffuse: diffuse_handle, specular: specular_handle,
-                normal: normal_handle, shininess: shininess }
+        let normal_handle = Material::bind_image_opt(normal, false);
+        Material::blinn_phong(diffuse_handle, specular_handle, normal_handle, color, shininess)
     }
 
-    // Creates a Material with paths to diffuse and specular maps, shiniess, and color.
-    pub fn new_with_color(diffuse_name: Option<&str>, specular_name: Option<&str>,
-            normal_name: Option<&str>, color: color::Color, shininess: GLfloat) -> Material {
-        let diffuse = Material::read_and_bind_bmp(diffuse_name, true);
-        let specular = Material::read_and_bind_bmp(specular_name, false);
-        // TODO: Just use the rgb vec.
-        let normal = match normal_name {
-            Some(_) => Some(Material::read_and_bind_bmp(normal_name, false)),
-            None => None,
-        };
-        Material { color: color, diffuse: diffuse, specular: specular, normal: normal,
-                shininess: shininess }
+    // Creates a Material with paths to diffuse and specular maps, shiniess, and color. Returns an
+    // Err if one of the maps can't be loaded.
+    pub fn new_with_color(textures: &mut TextureManager, diffuse_name: Option<&str>,
+            specular_name: Option<&str>, normal_name: Option<&str>, color: color::Color,
+            shininess: GLfloat) -> Result<Material, String> {
+        let diffuse = try!(Material::load_texture(textures, diffuse_name, ColorSpace::SRGB));
+        let specular = try!(Material::load_texture(textures, specular_name, ColorSpace::Linear));
+        let normal = try!(Material::load_texture_opt(textures, normal_name, ColorSpace::Linear));
+        Ok(Material::blinn_phong(diffuse, specular, normal, color, shininess))
     }
-}
\ No newline at end of file
+
+    // Creates a physically based Material from paths to albedo, normal, metallic-roughness, and
+    // ambient occlusion maps, which are loaded through a TextureManager. Like glTF, roughness is
+    // read from the green channel and metallic from the blue channel of the metallic-roughness map
+    // while occlusion is read from the red channel. The color, metallic, and roughness factors are
+    // multiplied with the sampled values. Returns an Err if one of the maps can't be loaded.
+    pub fn new_pbr(textures: &mut TextureManager, albedo_name: Option<&str>,
+            normal_name: Option<&str>, metallic_roughness_name: Option<&str>,
+            occlusion_name: Option<&str>, color: color::Color, metallic: GLfloat,
+            roughness: GLfloat) -> Result<Material, String> {
+        let albedo = try!(Material::load_texture(textures, albedo_name, ColorSpace::SRGB));
+        let normal = try!(Material::load_texture_opt(textures, normal_name, ColorSpace::Linear));
+        let mr = try!(Material::load_texture_opt(textures, metallic_roughness_name,
+                ColorSpace::Linear));
+        let occlusion = try!(Material::load_texture_opt(textures, occlusion_name,
+                ColorSpace::Linear));
+        Ok(Material::metallic_roughness(albedo, normal, mr, occlusion, color, metallic,
+                roughness))
+    }
+
+    // Creates a physically based Material from already decoded albedo, normal, metallic-roughness,
+    // and ambient occlusion Images. See new_pbr() for the channel layout.
+    pub fn from_pbr_images(albedo: &Option<common::Image>, normal: &Option<common::Image>,
+            metallic_roughness: &Option<common::Image>, occlusion: &Option<common::Image>,
+            color: color::Color, metallic: GLfloat, roughness: GLfloat) -> Material {
+        let albedo_handle = match albedo {
+            &Some(ref i) => Material::bind_image(i, true),
+            &None => 0 };
+        let normal_handle = Material::bind_image_opt(normal, false);
+        let mr_handle = Material::bind_image_opt(metallic_roughness, false);
+        let occlusion_handle = Material::bind_image_opt(occlusion, false);
+        Material::metallic_roughness(albedo_handle, normal_handle, mr_handle, occlusion_handle,
+                color, metallic, roughness)
+    }
+}
@@ -1,10 +1,42 @@
 #[macro_use]
 mod macros;
 
+pub mod bounds;
+pub mod bvh;
 pub mod camera;
+pub mod camera_controller;
+pub mod capture;
 pub mod color;
+pub mod debug_draw;
+pub mod debug_panels;
+pub mod debug_ui;
 pub mod game_window;
+pub mod gpu_particles;
+pub mod headless;
+pub mod ibl;
+pub mod instancing;
+pub mod layers;
 pub mod light;
+pub mod lod;
 pub mod material;
 pub mod model;
-pub mod types;
\ No newline at end of file
+pub mod morph;
+pub mod particles;
+pub mod picking;
+pub mod postprocess;
+pub mod prefab;
+pub mod render_target;
+pub mod scatter;
+pub mod scene;
+pub mod scene_io;
+pub mod skin;
+pub mod skybox;
+pub mod sprite;
+pub mod stats;
+pub mod surface;
+pub mod terrain;
+pub mod text;
+pub mod texture;
+pub mod transparency;
+pub mod types;
+pub mod viewport;
\ No newline at end of file
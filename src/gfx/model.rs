@@ -10,19 +10,26 @@
 extern crate cgmath;
 
 use self::cgmath::{Matrix, SquareMatrix};
+use gfx::bounds;
 use gfx::color;
+use gfx::layers;
 use gfx::material;
 use gfx::types::*;
 use std::cell::Cell;
 use std::rc::Rc;
 use util::{common, obj, rmod};
 
+// Where a ModelInfo's elements are in the engine's managed buffers. The base vertex is the index of
+// the model's first vertex in the VBO, which has already been added to its elements.
 #[derive(Copy, Clone)]
 pub struct BufferInfo {
     pub gen: usize,
     pub start: usize,
     pub size: usize,
     pub vao: GLuint,
+    pub vbo: GLuint,
+    pub ebo: GLuint,
+    pub base_vertex: usize,
 }
 
 // Stores information about the model which can be instantiated to create a ModelInstance. 
@@ -34,6 +41,7 @@ pub struct ModelInfo {
     pub elements: Vec<GLuint>,
     pub tcoords: Vec<GLfloat>,
     pub mat: material::Material,
+    pub bounds: bounds::Aabb,
     pub buffer_info: Cell<Option<BufferInfo>>,
 }
 
@@ -42,9 +50,10 @@ impl ModelInfo {
     pub fn new(vertices: Vec<GLfloat>, elems: Vec<GLuint>, normals: Vec<GLfloat>,
             tangents: Vec<GLfloat>, bitangents: Vec<GLfloat>, tcoords: Vec<GLfloat>,
             mat: material::Material) -> ModelInfo {
+        let bounds = bounds::Aabb::from_positions(&vertices);
         ModelInfo { vertices: vertices, normals: normals, tangents: tangents,
                 bitangents: bitangents, elements: elems, tcoords: tcoords, mat: mat,
-                buffer_info: Cell::new(None) }
+                bounds: bounds, buffer_info: Cell::new(None) }
     }
 
     // Creates a box with specified size and color.
@@ -133,34 +142,42 @@ impl ModelInfo {
 
     // Gets a single vector representing the the ModelInfo in VBO format.
     pub fn get_vbo_format(&self) -> Vec<GLfloat> {
-        let mut vertices: Vec<GLfloat> = Vec::new();
-        let mut y = 0;
-        for x in 0..self.vertices.len() {
-            if x % 3 != 0 {
-                continue;
-            }
-            vertices.push(self.vertices[x]);
-            vertices.push(self.vertices[x + 1]);
-            vertices.push(self.vertices[x + 2]);
-            vertices.push(self.normals[x]);
-            vertices.push(self.normals[x + 1]);
-            vertices.push(self.normals[x + 2]);
-            vertices.push(self.tangents[x]);
-            vertices.push(self.tangents[x + 1]);
-            vertices.push(self.tangents[x + 2]);
-            vertices.push(self.bitangents[x]);
-            vertices.push(self.bitangents[x + 1]);
-            vertices.push(self.bitangents[x + 2]);
-            vertices.push(self.tcoords[y]);
-            vertices.push(self.tcoords[y + 1]);
-            y += 2;
+        pack_vbo_format(&self.vertices, &self.normals, &self.tangents, &self.bitangents,
+                &self.tcoords)
+    }
+}
+
+// Interleaves separate vertex attribute lists into a single vector in VBO format.
+pub fn pack_vbo_format(vertices: &[GLfloat], normals: &[GLfloat], tangents: &[GLfloat],
+        bitangents: &[GLfloat], tcoords: &[GLfloat]) -> Vec<GLfloat> {
+    let mut packed: Vec<GLfloat> = Vec::new();
+    let mut y = 0;
+    for x in 0..vertices.len() {
+        if x % 3 != 0 {
+            continue;
         }
-        vertices
+        packed.push(vertices[x]);
+        packed.push(vertices[x + 1]);
+        packed.push(vertices[x + 2]);
+        packed.push(normals[x]);
+        packed.push(normals[x + 1]);
+        packed.push(normals[x + 2]);
+        packed.push(tangents[x]);
+        packed.push(tangents[x + 1]);
+        packed.push(tangents[x + 2]);
+        packed.push(bitangents[x]);
+        packed.push(bitangents[x + 1]);
+        packed.push(bitangents[x + 2]);
+        packed.push(tcoords[y]);
+        packed.push(tcoords[y + 1]);
+        y += 2;
     }
+    packed
 }
 
 // An instantiazation of a ModelInfo that represents a model in-game. This has a variety of
-// positional attributes used to render the instance.
+// positional attributes used to render the instance along with the render layers it is on. The
+// material replaces the ModelInfo's material for this instance only if it is set.
 pub struct ModelInstance {
     pub info: Rc<ModelInfo>,
     pub pos: Vector3D,
@@ -168,6 +185,8 @@ pub struct ModelInstance {
     pub scale: f32,
     pub model: cgmath::Matrix4<GLfloat>,
     pub normal: cgmath::Matrix4<GLfloat>,
+    pub layers: layers::LayerMask,
+    pub material: Option<material::Material>,
 }
 
 impl ModelInstance {
@@ -179,7 +198,8 @@ impl ModelInstance {
         let model = cgmath::Matrix4::from(cgmath::Decomposed {
                 scale: scale, rot: rot, disp: pos });
         let norm = model.clone().invert().unwrap().transpose();
-        ModelInstance { info: info, pos: pos, scale: scale, rot: rot, model: model, normal: norm }
+        ModelInstance { info: info, pos: pos, scale: scale, rot: rot, model: model, normal: norm,
+                layers: layers::DEFAULT_LAYER, material: None }
     }
 
     // Updates the model and normal matrices. This must be called after any sequence of struct
@@ -191,4 +211,14 @@ impl ModelInstance {
         self.model = model;
         self.normal = normal;
     }
+
+    // Gets the material the instance is drawn with.
+    pub fn get_material(&self) -> &material::Material {
+        self.material.as_ref().unwrap_or(&self.info.mat)
+    }
+
+    // Gets the bounds of the instance in the world from its model matrix.
+    pub fn get_world_bounds(&self) -> bounds::Aabb {
+        self.info.bounds.transform(&self.model)
+    }
 }
@@ -0,0 +1,159 @@
+// Defines a Morph, which deforms a ModelInfo by a weighted sum of morph targets (also known as
+// blend shapes). Like glTF, each target holds the offset of every vertex's position, normal, and
+// tangent from the base mesh, so a weight of 1.0 moves the mesh fully into the target's shape.
+// Facial animation usually has a target per expression or viseme and animates their weights.
+//
+// Morphs are blended on the CPU and GameWindow::upload_morph() writes the blended vertices over
+// the model's vertices in the engine's VBO space, so the model is then drawn as usual, including
+// with GameWindow::draw_skinned(). Since the vertices are replaced, every instance of the same
+// ModelInfo shares the morph, so meshes that morph independently need their own ModelInfo. The
+// model's bounds aren't updated either, so targets that move vertices far past them can be culled
+// early.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::model;
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Vector};
+use std::rc::Rc;
+
+// The offsets of every vertex of a mesh from its base shape. Normal and tangent offsets can be
+// empty if the target only moves positions.
+#[derive(Clone, Debug)]
+pub struct MorphTarget {
+    pub name: String,
+    pub positions: Vec<GLfloat>,
+    pub normals: Vec<GLfloat>,
+    pub tangents: Vec<GLfloat>,
+}
+
+impl MorphTarget {
+    // Creates a target from its position, normal, and tangent offsets.
+    pub fn new(name: &str, positions: Vec<GLfloat>, normals: Vec<GLfloat>,
+            tangents: Vec<GLfloat>) -> MorphTarget {
+        MorphTarget { name: name.to_string(), positions: positions, normals: normals,
+                tangents: tangents }
+    }
+}
+
+// The morph targets of a model and their current weights.
+pub struct Morph {
+    pub info: Rc<model::ModelInfo>,
+    pub targets: Vec<MorphTarget>,
+    weights: Vec<GLfloat>,
+    // The generation of the BufferInfo that the current weights were uploaded to, if any.
+    uploaded_gen: Option<usize>,
+}
+
+impl Morph {
+    // Creates a Morph of a model with every weight at 0.0. Returns an Err if a target doesn't have
+    // an offset for every vertex.
+    pub fn new(info: Rc<model::ModelInfo>, targets: Vec<MorphTarget>) -> Result<Morph, String> {
+        let size = info.vertices.len();
+        for target in &targets {
+            let valid = |v: &Vec<GLfloat>| v.is_empty() || v.len() == size;
+            if target.positions.len() != size || !valid(&target.normals) ||
+                    !valid(&target.tangents) {
+                return Err(format!("Morph target {} needs an offset for every vertex.",
+                        target.name));
+            }
+        }
+        let weights = vec![0.0; targets.len()];
+        Ok(Morph { info: info, targets: targets, weights: weights, uploaded_gen: None })
+    }
+
+    // Gets the number of targets.
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    // Returns true if there are no targets.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    // Finds the index of a target by its name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.targets.iter().position(|t| t.name == name)
+    }
+
+    // Gets the weight of every target.
+    pub fn get_weights(&self) -> &Vec<GLfloat> {
+        &self.weights
+    }
+
+    // Sets the weight of a target. Out of range indices are ignored.
+    pub fn set_weight(&mut self, index: usize, weight: GLfloat) {
+        if let Some(w) = self.weights.get_mut(index) {
+            if *w != weight {
+                *w = weight;
+                self.uploaded_gen = None;
+            }
+        }
+    }
+
+    // Sets the weights of the targets in order, such as from AnimationPlayer::sample_weights().
+    // Extra weights are ignored and missing ones are left as they are.
+    pub fn set_weights(&mut self, weights: &[GLfloat]) {
+        for (i, w) in weights.iter().enumerate() {
+            self.set_weight(i, *w);
+        }
+    }
+
+    // Returns true if the blended vertices need to be uploaded to a BufferInfo.
+    pub fn needs_upload(&self, info: &model::BufferInfo) -> bool {
+        self.uploaded_gen != Some(info.gen)
+    }
+
+    // Marks the blended vertices as uploaded to a BufferInfo.
+    pub fn mark_uploaded(&mut self, info: &model::BufferInfo) {
+        self.uploaded_gen = Some(info.gen);
+    }
+
+    // Blends the targets into the model's vertices with the current weights and returns them in
+    // VBO format. Blended normals and tangents are renormalized and the bitangents are rebuilt from
+    // them with the handedness of the base mesh.
+    pub fn blend(&self) -> Vec<GLfloat> {
+        let info = &self.info;
+        let mut positions = info.vertices.clone();
+        let mut normals = info.normals.clone();
+        let mut tangents = info.tangents.clone();
+        let mut bitangents = info.bitangents.clone();
+        let mut shaded = false;
+        for (target, &weight) in self.targets.iter().zip(self.weights.iter()) {
+            if weight == 0.0 { continue; }
+            for (p, d) in positions.iter_mut().zip(target.positions.iter()) {
+                *p += d * weight;
+            }
+            for (n, d) in normals.iter_mut().zip(target.normals.iter()) {
+                *n += d * weight;
+            }
+            for (t, d) in tangents.iter_mut().zip(target.tangents.iter()) {
+                *t += d * weight;
+            }
+            shaded = shaded || !target.normals.is_empty() || !target.tangents.is_empty();
+        }
+
+        if shaded {
+            let vector = |v: &[GLfloat], i: usize| Vector3D::new(v[i], v[i + 1], v[i + 2]);
+            for i in (0..positions.len() / 3).map(|i| i * 3) {
+                let normal = vector(&normals, i);
+                let tangent = vector(&tangents, i);
+                if normal.length2() == 0.0 || tangent.length2() == 0.0 { continue; }
+                let (normal, tangent) = (normal.normalize(), tangent.normalize());
+                let base = vector(&info.normals, i).cross(vector(&info.tangents, i));
+                let handedness = if base.dot(vector(&info.bitangents, i)) < 0.0 { -1.0 } else {
+                    1.0
+                };
+                let bitangent = normal.cross(tangent) * handedness;
+                normals[i..i + 3].copy_from_slice(&[normal.x, normal.y, normal.z]);
+                tangents[i..i + 3].copy_from_slice(&[tangent.x, tangent.y, tangent.z]);
+                bitangents[i..i + 3].copy_from_slice(&[bitangent.x, bitangent.y, bitangent.z]);
+            }
+        }
+        model::pack_vbo_format(&positions, &normals, &tangents, &bitangents, &info.tcoords)
+    }
+}
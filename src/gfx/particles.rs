@@ -0,0 +1,289 @@
+// Defines a ParticleSystem, which simulates many small short-lived particles on the CPU for effects
+// such as smoke, sparks, and dust. Particles are spawned by an emitter at a steady rate and in
+// bursts, move under a list of modifiers such as gravity and drag, and die when they reach the end
+// of their lifetime. Their size, color, and speed can change over their lifetime by following
+// Tracks that are keyed from 0.0 at birth to 1.0 at death.
+//
+// Particles are drawn as billboards facing the camera through a SpriteBatch, sorted from back to
+// front so that they blend correctly with each other.
+//
+// Usage of a ParticleSystem:
+// - Create it with new() and set the emitter and particle settings.
+// - Call update(dt) every frame, along with burst() for one-off effects like explosions.
+// - Set the view of a SpriteBatch to the camera's view projection with depth test on, and
+//   optionally soft depth, then call draw(&mut batch, &camera) and flush the batch. With a
+//   FrameArena, draw_in() sorts the particles in it instead of a new list.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use anim::track::Track;
+use gfx::camera::GameCamera;
+use gfx::color::Color;
+use gfx::sprite::{Billboard, SpriteBatch, TextureRegion};
+use gfx::types::*;
+use self::cgmath::EuclideanVector;
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+use std::slice;
+use util::alloc::FrameArena;
+use util::random::Random;
+
+// The shape that an emitter spawns particles from in its local space. Points and spheres send
+// particles out in every direction, and spheres spawn them anywhere inside of the radius. Cones
+// spawn particles on a disc of a radius in the XY plane and send them toward +Z, spreading out by
+// up to an angle in radians.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EmitterShape {
+    Point,
+    Sphere { radius: GLfloat },
+    Cone { angle: GLfloat, radius: GLfloat },
+}
+
+// A change to the velocity of every particle over time. Gravity is an acceleration and drag takes
+// away a fraction of the velocity every second.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParticleModifier {
+    Gravity(Vector3D),
+    Drag(GLfloat),
+}
+
+// A number of particles spawned at once a time after the start of each cycle of the emitter.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Burst {
+    pub time: GLfloat,
+    pub count: usize,
+}
+
+// A single particle in the world. The size and color are the particle's own before the curves over
+// its lifetime are applied.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Particle {
+    pub pos: Vector3D,
+    pub velocity: Vector3D,
+    pub age: GLfloat,
+    pub lifetime: GLfloat,
+    pub size: GLfloat,
+    pub rotation: GLfloat,
+    pub spin: GLfloat,
+    pub color: Color,
+}
+
+impl Particle {
+    // Gets how far the particle is through its lifetime from 0.0 to 1.0.
+    pub fn get_progress(&self) -> GLfloat {
+        if self.lifetime > 0.0 { (self.age / self.lifetime).min(1.0) } else { 1.0 }
+    }
+}
+
+// An emitter along with the particles that it has spawned. The emitter is placed in the world by
+// its position and rotation, and its particles move freely in the world once they are spawned. The
+// ranges of the new particles' settings are (min, max) and rotations are in radians. The emitter
+// runs in cycles of its duration, and stops emitting after the first one unless it loops.
+pub struct ParticleSystem {
+    pub pos: Vector3D,
+    pub rot: Quaternion,
+    pub shape: EmitterShape,
+    pub rate: GLfloat,                      // Particles spawned per second.
+    pub bursts: Vec<Burst>,
+    pub duration: GLfloat,
+    pub looping: bool,
+    pub emitting: bool,
+    pub max_particles: usize,
+    pub lifetime: (GLfloat, GLfloat),
+    pub speed: (GLfloat, GLfloat),
+    pub size: (GLfloat, GLfloat),
+    pub rotation: (GLfloat, GLfloat),
+    pub spin: (GLfloat, GLfloat),
+    pub color: Color,
+    pub size_curve: Option<Track<GLfloat>>,
+    pub color_curve: Option<Track<Color>>,
+    pub speed_curve: Option<Track<GLfloat>>,
+    pub modifiers: Vec<ParticleModifier>,
+    pub region: TextureRegion,
+    pub z: i32,
+    particles: Vec<Particle>,
+    random: Random,
+    time: GLfloat,
+    spawn_debt: GLfloat,
+}
+
+impl ParticleSystem {
+    // Creates a looping emitter at the origin that spawns white particles with a texture from a
+    // point at a rate, with a seed for its random numbers.
+    pub fn new(region: TextureRegion, rate: GLfloat, seed: u64) -> ParticleSystem {
+        ParticleSystem { pos: Vector3D::new(0.0, 0.0, 0.0),
+                rot: Quaternion::new(1.0, 0.0, 0.0, 0.0), shape: EmitterShape::Point, rate: rate,
+                bursts: Vec::new(), duration: 1.0, looping: true, emitting: true,
+                max_particles: 1000, lifetime: (1.0, 1.0), speed: (1.0, 1.0), size: (1.0, 1.0),
+                rotation: (0.0, 0.0), spin: (0.0, 0.0), color: Color::new_rgb(1.0, 1.0, 1.0),
+                size_curve: None, color_curve: None, speed_curve: None, modifiers: Vec::new(),
+                region: region, z: 0, particles: Vec::new(), random: Random::new(seed),
+                time: 0.0, spawn_debt: 0.0 }
+    }
+
+    // Gets the number of live particles.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    // Returns true if there are no live particles.
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    // Gets an iterator over the live particles.
+    pub fn iter<'a>(&'a self) -> slice::Iter<'a, Particle> {
+        self.particles.iter()
+    }
+
+    // Removes every particle and restarts the emitter's cycle.
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.time = 0.0;
+        self.spawn_debt = 0.0;
+    }
+
+    // Spawns a number of particles at once, up to the max particles.
+    pub fn burst(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.particles.len() >= self.max_particles { break; }
+            let particle = self.spawn();
+            self.particles.push(particle);
+        }
+    }
+
+    // Ages and moves the particles by a timestep in seconds, removes the ones that died, and then
+    // spawns new ones from the rate and bursts.
+    pub fn update(&mut self, dt: GLfloat) {
+        profile_scope!("ParticleSystem::update");
+        for particle in &mut self.particles {
+            particle.age += dt;
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+        for particle in &mut self.particles {
+            for modifier in &self.modifiers {
+                match *modifier {
+                    ParticleModifier::Gravity(g) => particle.velocity = particle.velocity + g * dt,
+                    ParticleModifier::Drag(drag) => {
+                        particle.velocity = particle.velocity * (1.0 - drag * dt).max(0.0);
+                    },
+                }
+            }
+            let progress = particle.get_progress();
+            let scale = self.speed_curve.as_ref().map_or(1.0, |c| c.sample(progress));
+            particle.pos = particle.pos + particle.velocity * (scale * dt);
+            particle.rotation += particle.spin * dt;
+        }
+        let count = self.advance_emitter(dt);
+        self.burst(count);
+    }
+
+    // Advances the emitter's cycle by a timestep in seconds and gets the number of particles that
+    // the rate and bursts spawn over it. This is done by update(), and is only needed to drive
+    // another backend such as a GpuParticleSystem from the emitter's settings.
+    pub fn advance_emitter(&mut self, dt: GLfloat) -> usize {
+        if !self.emitting { return 0; }
+        self.spawn_debt += self.rate * dt;
+        let count = self.spawn_debt.floor();
+        self.spawn_debt -= count;
+        let mut count = count as usize;
+
+        // Count the bursts that the cycle passed this step, including the ones at the start of the
+        // next cycle if it wrapped around.
+        let (start, end) = (self.time, self.time + dt);
+        for burst in &self.bursts {
+            if burst.time >= start && burst.time < end { count += burst.count; }
+            if self.looping && end >= self.duration && burst.time < end - self.duration {
+                count += burst.count;
+            }
+        }
+        self.time = end;
+        if self.time >= self.duration {
+            if self.looping && self.duration > 0.0 {
+                self.time %= self.duration;
+            } else {
+                self.emitting = false;
+            }
+        }
+        count
+    }
+
+    // Queues the particles in a SpriteBatch as billboards facing a camera, from back to front.
+    pub fn draw(&self, batch: &mut SpriteBatch, camera: &GameCamera) {
+        let mut order: Vec<(GLfloat, usize)> = self.particles.iter().enumerate()
+                .map(|(i, p)| ((p.pos - camera.pos).length2(), i)).collect();
+        self.draw_sorted(batch, camera, &mut order);
+    }
+
+    // Like draw() but sorts the particles in a FrameArena.
+    pub fn draw_in(&self, batch: &mut SpriteBatch, camera: &GameCamera, arena: &FrameArena) {
+        let mut order = arena.alloc_slice(self.particles.len(), (0.0, 0));
+        for (i, particle) in self.particles.iter().enumerate() {
+            order[i] = ((particle.pos - camera.pos).length2(), i);
+        }
+        self.draw_sorted(batch, camera, &mut order);
+    }
+
+    // Sorts the distances from the camera and indices of the particles from back to front and
+    // queues the particles in that order.
+    fn draw_sorted(&self, batch: &mut SpriteBatch, camera: &GameCamera,
+            order: &mut [(GLfloat, usize)]) {
+        let view = camera.view;
+        let right = Vector3D::new(view.x[0], view.y[0], view.z[0]);
+        let up = Vector3D::new(view.x[1], view.y[1], view.z[1]);
+        order.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+        for &(_, i) in order.iter() {
+            let particle = &self.particles[i];
+            let progress = particle.get_progress();
+            let size = particle.size *
+                    self.size_curve.as_ref().map_or(1.0, |c| c.sample(progress));
+            let mut tint = particle.color;
+            if let Some(ref curve) = self.color_curve {
+                let c = curve.sample(progress);
+                tint = Color::new(tint.r * c.r, tint.g * c.g, tint.b * c.b, tint.a * c.a);
+            }
+            let mut billboard = Billboard::new(self.region, particle.pos, size, size);
+            billboard.rotation = particle.rotation;
+            billboard.tint = tint;
+            billboard.z = self.z;
+            batch.draw_billboard(&billboard, right, up);
+        }
+    }
+
+    // Creates a new particle from the emitter's shape and settings.
+    fn spawn(&mut self) -> Particle {
+        let (offset, dir) = match self.shape {
+            EmitterShape::Point => (Vector3D::new(0.0, 0.0, 0.0), self.random_direction()),
+            EmitterShape::Sphere { radius } => {
+                let dir = self.random_direction();
+                (dir * (radius * self.random.next_float().cbrt()), dir)
+            },
+            EmitterShape::Cone { angle, radius } => {
+                let r = radius * self.random.next_float().sqrt();
+                let theta = self.random.range(0.0, 2.0 * PI);
+                let offset = Vector3D::new(r * theta.cos(), r * theta.sin(), 0.0);
+                let z = self.random.range(angle.cos(), 1.0);
+                let phi = self.random.range(0.0, 2.0 * PI);
+                let ring = (1.0 - z * z).max(0.0).sqrt();
+                (offset, Vector3D::new(ring * phi.cos(), ring * phi.sin(), z))
+            },
+        };
+        let speed = self.random.range(self.speed.0, self.speed.1);
+        Particle { pos: self.pos + self.rot * offset, velocity: self.rot * dir * speed, age: 0.0,
+                lifetime: self.random.range(self.lifetime.0, self.lifetime.1),
+                size: self.random.range(self.size.0, self.size.1),
+                rotation: self.random.range(self.rotation.0, self.rotation.1),
+                spin: self.random.range(self.spin.0, self.spin.1), color: self.color }
+    }
+
+    // Gets a random direction that is equally likely to point anywhere.
+    fn random_direction(&mut self) -> Vector3D {
+        let z = self.random.range(-1.0, 1.0);
+        let phi = self.random.range(0.0, 2.0 * PI);
+        let ring = (1.0 - z * z).max(0.0).sqrt();
+        Vector3D::new(ring * phi.cos(), ring * phi.sin(), z)
+    }
+}
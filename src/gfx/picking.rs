@@ -0,0 +1,224 @@
+// Defines object picking, which finds the Scene node under a position on the screen. There are two
+// ways to pick:
+// - pick() casts a ray from the camera through the screen position and walks the Scene's Bvh,
+//   testing the triangles of each mesh whose bounds the ray passes through. This is done entirely
+//   on the CPU and also gives the distance to and point of the hit.
+// - IdPicker draws the ID of every visible node to an offscreen RenderTarget and reads back the
+//   pixel under the screen position. This is exact to the pixel for any mesh regardless of its
+//   triangle count, but it stalls until the GPU has finished drawing.
+//
+// Both only consider nodes with meshes or level of detail groups as of the last Scene::update().
+// The CPU path tests the most detailed level of a level of detail group while the GPU path draws
+// the level that the active camera currently sees.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use gfx::bounds;
+use gfx::camera::GameCamera;
+use gfx::game_window::GameWindow;
+use gfx::model;
+use gfx::render_target::{ColorFormat, DepthFormat, RenderTarget};
+use gfx::scene::{NodeId, Scene};
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, SquareMatrix, Vector};
+use std::ffi::CString;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "pick.vert";
+const FRAGMENT_SHADER_NAME: &'static str = "pick.frag";
+
+// Triangles whose determinant is smaller than this are treated as parallel to the ray.
+const TRIANGLE_EPSILON: GLfloat = 1e-8;
+
+// The result of a CPU pick. The distance is along the ray from the near plane and the point is
+// where the ray hits the node's mesh in the world.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PickHit {
+    pub node: NodeId,
+    pub distance: GLfloat,
+    pub point: Vector3D,
+}
+
+// Intersects a ray with a triangle from either side and returns the distance along the ray to the
+// hit, in units of the direction's length.
+pub fn intersect_triangle(origin: Vector3D, dir: Vector3D, a: Vector3D, b: Vector3D,
+        c: Vector3D) -> Option<GLfloat> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = dir.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < TRIANGLE_EPSILON { return None; }
+    let inv_det = 1.0 / det;
+    let s = origin - a;
+    let u = s.dot(p) * inv_det;
+    if u < 0.0 || u > 1.0 { return None; }
+    let q = s.cross(edge1);
+    let v = dir.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 { return None; }
+    let t = edge2.dot(q) * inv_det;
+    if t >= 0.0 { Some(t) } else { None }
+}
+
+// Intersects a ray in the world with the triangles of a ModelInstance and returns the distance to
+// the closest hit no further than max_dist. The ray is moved into the model's space instead of
+// moving every vertex into the world, which keeps the distance in world units as long as the
+// direction is normalized.
+pub fn raycast_instance(instance: &model::ModelInstance, origin: Vector3D, dir: Vector3D,
+        max_dist: GLfloat) -> Option<GLfloat> {
+    let inverse = match instance.model.invert() {
+        Some(m) => m,
+        None => { return None; },
+    };
+    let local_origin = (inverse * origin.extend(1.0)).truncate();
+    let local_dir = (inverse * dir.extend(0.0)).truncate();
+    let vertices = &instance.info.vertices;
+    let point = |i: GLuint| {
+        let i = i as usize * 3;
+        Vector3D::new(vertices[i], vertices[i + 1], vertices[i + 2])
+    };
+    let mut closest: Option<GLfloat> = None;
+    for triangle in instance.info.elements.chunks(3) {
+        if triangle.len() < 3 { break; }
+        let limit = closest.unwrap_or(max_dist);
+        if let Some(t) = intersect_triangle(local_origin, local_dir, point(triangle[0]),
+                point(triangle[1]), point(triangle[2])) {
+            if t <= limit { closest = Some(t); }
+        }
+    }
+    closest
+}
+
+// Casts a ray in the world against the meshes of a Scene and returns the closest node hit. The
+// direction should be normalized for the distance to be in world units.
+pub fn pick_ray(scene: &Scene, origin: Vector3D, dir: Vector3D, max_dist: GLfloat)
+        -> Option<PickHit> {
+    scene.get_bvh().raycast(origin, dir, max_dist, |_, id| {
+        let node = scene.get_node(*id).unwrap();
+        let mesh = node.mesh.as_ref().and_then(|m| raycast_instance(m, origin, dir, max_dist));
+        let lod = node.lod.as_ref().and_then(|l| l.levels.first())
+                .and_then(|l| raycast_instance(&l.instance, origin, dir, max_dist));
+        match (mesh, lod) {
+            (Some(m), Some(l)) => Some(m.min(l)),
+            (m, l) => m.or(l),
+        }
+    }).map(|(proxy, t)| {
+        PickHit { node: *scene.get_bvh().get(proxy).unwrap(), distance: t,
+                point: origin + dir * t }
+    })
+}
+
+// Picks the node under a position in pixels on a viewport of a given size viewed by a camera.
+pub fn pick(scene: &Scene, camera: &GameCamera, x: f32, y: f32, width: u32, height: u32)
+        -> Option<PickHit> {
+    let (origin, dir) = camera.screen_to_ray(x, y, width, height);
+    let max_dist = (camera.screen_to_world(x, y, 1.0, width, height) - origin).length();
+    pick_ray(scene, origin, dir, max_dist)
+}
+
+// Picks nodes by drawing their IDs to an offscreen RenderTarget. The target can be smaller than
+// the window to save on fill rate at the cost of precision. This can only be created after the
+// window context is set up.
+pub struct IdPicker {
+    pub target: RenderTarget,
+    program: GLuint,
+}
+
+impl IdPicker {
+    // Creates an IdPicker with a target of a given size. The program's position attribute is
+    // bound to the same location as the engine's so that it can draw with the engine's VAOs.
+    pub fn new(window: &GameWindow, width: u32, height: u32) -> Result<IdPicker, String> {
+        let target = try!(RenderTarget::new(width, height, vec![ColorFormat::R32UI],
+                Some(DepthFormat::Depth24)));
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+        unsafe {
+            let location = gl::GetAttribLocation(window.program, gl_str!("position"));
+            gl::BindAttribLocation(program, location as GLuint, gl_str!("position"));
+            gl::BindFragDataLocation(program, 0, gl_str!("out_id"));
+            gl::LinkProgram(program);
+        }
+        Ok(IdPicker { target: target, program: program })
+    }
+
+    // Resizes the target, such as when the window is resized.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.target.resize(width, height);
+    }
+
+    // Draws the ID of every node visible from the active camera to the target. Nothing is drawn if
+    // there is no active camera. The window is set back to drawing to itself afterwards.
+    pub fn render(&self, scene: &Scene, window: &mut GameWindow) {
+        // The level of each level of detail group is picked up front since the camera is
+        // borrowed from the window.
+        let visible: Vec<(NodeId, Option<usize>)> = match window.get_active_camera() {
+            Ok(camera) => {
+                let frustum = bounds::Frustum::from_matrix(&camera.get_view_projection());
+                scene.query_frustum(&frustum).into_iter().map(|id| {
+                    let node = scene.get_node(id).unwrap();
+                    (id, node.lod.as_ref().and_then(|l| l.select_level(camera)))
+                }).collect()
+            },
+            Err(_) => { return; },
+        };
+        window.set_render_target(Some(&self.target));
+        unsafe {
+            let background: [GLuint; 4] = [0, 0, 0, 0];
+            gl::ClearBufferuiv(gl::COLOR, 0, background.as_ptr());
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            gl::UseProgram(self.program);
+        }
+        for (id, level) in visible {
+            let node = scene.get_node(id).unwrap();
+            // IDs are offset by one so that 0 is left for the background.
+            unsafe { uniform_uint!(self.program, "object_id", id as GLuint + 1); }
+            if let Some(ref instance) = node.mesh {
+                window.draw_instance_with_program(instance, self.program);
+            }
+            if let (Some(group), Some(level)) = (node.lod.as_ref(), level) {
+                window.draw_instance_with_program(&group.levels[level].instance, self.program);
+            }
+        }
+        window.set_render_target(None);
+        window.restore_state();
+    }
+
+    // Reads the node drawn at a position in pixels on a viewport of a given size by the last
+    // render(). The position is scaled to the size of the target.
+    pub fn read(&self, x: f32, y: f32, width: u32, height: u32) -> Option<NodeId> {
+        let tx = (x / width as f32 * self.target.width as f32).floor();
+        let ty = (y / height as f32 * self.target.height as f32).floor();
+        if tx < 0.0 || ty < 0.0 || tx >= self.target.width as f32 ||
+                ty >= self.target.height as f32 {
+            return None;
+        }
+        let mut id: GLuint = 0;
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.target.framebuffer);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            // OpenGL puts the origin at the bottom left while screen positions start at the top.
+            gl::ReadPixels(tx as GLint, (self.target.height as f32 - 1.0 - ty) as GLint, 1, 1,
+                    gl::RED_INTEGER, gl::UNSIGNED_INT, &mut id as *mut GLuint as *mut _);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        if id == 0 { None } else { Some(id as NodeId - 1) }
+    }
+
+    // Draws the IDs and reads the node under a position in pixels on the window.
+    pub fn pick(&self, scene: &Scene, window: &mut GameWindow, x: f32, y: f32)
+            -> Option<NodeId> {
+        let (width, height) = window.get_size();
+        self.render(scene, window);
+        self.read(x, y, width, height)
+    }
+
+    // Deletes the program and target from the GPU.
+    pub fn delete(self) {
+        unsafe { gl::DeleteProgram(self.program); }
+        self.target.delete();
+    }
+}
@@ -0,0 +1,324 @@
+// Defines a PostProcessStack which renders the scene into an HDR RenderTarget and then runs a
+// chain of full screen passes over it before presenting the result. The available passes are
+// thresholded bloom using a downsample/upsample blur pyramid, exposure and tonemapping with either
+// the Reinhard or ACES curve, and FXAA. Every pass can be toggled and the order they are run in
+// is configurable, although bloom should come before tonemapping and FXAA after it. The scene can
+// be drawn with MSAA, in which case it is automatically resolved before the passes run.
+//
+// Usage:
+// - stack.begin(&mut window) before clearing and drawing the scene.
+// - stack.resolve() if a pass such as SSAO needs to read back the scene's depth before end().
+// - stack.end(&mut window, None) to run the passes and present to the window.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::game_window::GameWindow;
+use gfx::render_target::{ColorFormat, DepthFormat, RenderTarget};
+use gfx::types::*;
+use std::ffi::CString;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "post.vert";
+const PRESENT_SHADER_NAME: &'static str = "post_present.frag";
+const TONEMAP_SHADER_NAME: &'static str = "post_tonemap.frag";
+const DOWNSAMPLE_SHADER_NAME: &'static str = "post_downsample.frag";
+const UPSAMPLE_SHADER_NAME: &'static str = "post_upsample.frag";
+const BLOOM_SHADER_NAME: &'static str = "post_bloom.frag";
+const FXAA_SHADER_NAME: &'static str = "post_fxaa.frag";
+
+// The default number of levels in the bloom blur pyramid. The first level is half the size of
+// the scene and each following level is half the size of the previous one.
+const DEFAULT_BLOOM_LEVELS: usize = 5;
+
+// The curve used to map HDR colors into displayable colors.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tonemapper {
+    Reinhard,
+    ACES,
+}
+
+// A single full screen pass in the stack.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PostPass {
+    Bloom,
+    Tonemap,
+    FXAA,
+}
+
+// The chain of post-processing passes along with the targets they render into. The scene target
+// is an RGBA16F RenderTarget with a depth buffer that the scene is drawn into, and the passes
+// ping-pong between two more RGBA16F targets of the same size. If the scene target is
+// multisampled, it is resolved into a single sampled target with the same formats which is used
+// as the input for the passes. This can only be created after the window context is set up.
+pub struct PostProcessStack {
+    pub passes: Vec<PostPass>,
+    pub bloom_enabled: bool,
+    pub bloom_threshold: GLfloat,
+    pub bloom_knee: GLfloat,
+    pub bloom_intensity: GLfloat,
+    pub tonemap_enabled: bool,
+    pub tonemapper: Tonemapper,
+    pub exposure: GLfloat,
+    pub fxaa_enabled: bool,
+    pub scene: RenderTarget,
+    resolved: Option<RenderTarget>,
+    is_resolved: bool,
+    ping: RenderTarget,
+    pong: RenderTarget,
+    bloom_pyramid: Vec<RenderTarget>,
+    bloom_levels: usize,
+    vao: GLuint,
+    present_program: GLuint,
+    tonemap_program: GLuint,
+    downsample_program: GLuint,
+    upsample_program: GLuint,
+    bloom_program: GLuint,
+    fxaa_program: GLuint,
+}
+
+// Compiles and links a full screen pass given the name of its fragment shader.
+fn load_program(fragment_name: &str) -> GLuint {
+    shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, fragment_name)
+}
+
+// Creates a single color RenderTarget for intermediate results.
+fn create_target(width: u32, height: u32) -> Result<RenderTarget, String> {
+    RenderTarget::new(width, height, vec![ColorFormat::RGBA16F], None)
+}
+
+// Creates the bloom pyramid for a scene of a given size. Levels that would be smaller than a
+// single pixel are skipped.
+fn create_pyramid(width: u32, height: u32, levels: usize) -> Result<Vec<RenderTarget>, String> {
+    let mut pyramid = Vec::new();
+    let (mut level_width, mut level_height) = (width / 2, height / 2);
+    while pyramid.len() < levels && level_width > 0 && level_height > 0 {
+        pyramid.push(try!(create_target(level_width, level_height)));
+        level_width /= 2;
+        level_height /= 2;
+    }
+    Ok(pyramid)
+}
+
+// Binds a texture to a texture unit and points a sampler uniform at it.
+unsafe fn bind_source(program: GLuint, name: &str, unit: GLuint, texture_id: GLuint) {
+    gl::ActiveTexture(gl::TEXTURE0 + unit);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+    uniform_int!(program, name, unit as GLint);
+}
+
+// Binds a framebuffer with a viewport of the given size and draws a full screen triangle with the
+// currently bound program.
+unsafe fn draw_fullscreen(framebuffer: GLuint, width: u32, height: u32) {
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+}
+
+impl PostProcessStack {
+    // Creates a stack with every pass enabled in the order bloom, tonemap, FXAA using the ACES
+    // tonemapper. Returns an Err if the render targets could not be created.
+    pub fn new(width: u32, height: u32) -> Result<PostProcessStack, String> {
+        PostProcessStack::new_multisampled(width, height, 1)
+    }
+
+    // Same as new() but the scene is drawn with a number of samples per pixel for MSAA.
+    pub fn new_multisampled(width: u32, height: u32, samples: u32)
+            -> Result<PostProcessStack, String> {
+        let scene = try!(RenderTarget::new_multisampled(
+                width, height, vec![ColorFormat::RGBA16F], Some(DepthFormat::Depth24), samples));
+        let resolved = if scene.samples > 1 {
+            Some(try!(RenderTarget::new(
+                    width, height, vec![ColorFormat::RGBA16F], Some(DepthFormat::Depth24))))
+        } else { None };
+        let ping = try!(create_target(width, height));
+        let pong = try!(create_target(width, height));
+        let pyramid = try!(create_pyramid(width, height, DEFAULT_BLOOM_LEVELS));
+        let mut vao = 0;
+        unsafe { gl::GenVertexArrays(1, &mut vao); }
+        Ok(PostProcessStack {
+                passes: vec![PostPass::Bloom, PostPass::Tonemap, PostPass::FXAA],
+                bloom_enabled: true, bloom_threshold: 1.0, bloom_knee: 0.5, bloom_intensity: 0.5,
+                tonemap_enabled: true, tonemapper: Tonemapper::ACES, exposure: 1.0,
+                fxaa_enabled: true, scene: scene, resolved: resolved,
+                is_resolved: false, ping: ping, pong: pong, bloom_pyramid: pyramid,
+                bloom_levels: DEFAULT_BLOOM_LEVELS, vao: vao,
+                present_program: load_program(PRESENT_SHADER_NAME),
+                tonemap_program: load_program(TONEMAP_SHADER_NAME),
+                downsample_program: load_program(DOWNSAMPLE_SHADER_NAME),
+                upsample_program: load_program(UPSAMPLE_SHADER_NAME),
+                bloom_program: load_program(BLOOM_SHADER_NAME),
+                fxaa_program: load_program(FXAA_SHADER_NAME) })
+    }
+
+    // Resizes every target in the stack. This should be called whenever the window is resized.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        self.scene.resize(width, height);
+        if let Some(ref mut resolved) = self.resolved {
+            resolved.resize(width, height);
+        }
+        self.ping.resize(width, height);
+        self.pong.resize(width, height);
+        let pyramid = try!(create_pyramid(width, height, self.bloom_levels));
+        for target in self.bloom_pyramid.drain(..) {
+            target.delete();
+        }
+        self.bloom_pyramid = pyramid;
+        Ok(())
+    }
+
+    // Sets the number of levels in the bloom pyramid. More levels give a wider bloom.
+    pub fn set_bloom_levels(&mut self, levels: usize) -> Result<(), String> {
+        self.bloom_levels = levels;
+        let (width, height) = (self.scene.width, self.scene.height);
+        self.resize(width, height)
+    }
+
+    // Returns true if a pass is enabled.
+    pub fn is_enabled(&self, pass: PostPass) -> bool {
+        match pass {
+            PostPass::Bloom => self.bloom_enabled && !self.bloom_pyramid.is_empty(),
+            PostPass::Tonemap => self.tonemap_enabled,
+            PostPass::FXAA => self.fxaa_enabled,
+        }
+    }
+
+    // Redirects the window's draws into the scene target with HDR output. The scene target still
+    // needs to be cleared with GameWindow::clear().
+    pub fn begin(&mut self, window: &mut GameWindow) {
+        self.is_resolved = false;
+        window.set_hdr_output(true);
+        window.set_render_target(Some(&self.scene));
+    }
+
+    // Resolves the scene target's color and depth if it is multisampled. After this, the
+    // single sampled scene can be read from get_input() by depth readback passes. Calling this is
+    // optional since end() resolves the scene if it hasn't been already.
+    pub fn resolve(&mut self) {
+        if let Some(ref resolved) = self.resolved {
+            if !self.is_resolved {
+                self.scene.resolve(resolved, true).unwrap();
+            }
+        }
+        self.is_resolved = true;
+    }
+
+    // Gets the single sampled scene that is used as the input of the passes. For a multisampled
+    // stack this is only up to date after resolve().
+    pub fn get_input(&self) -> &RenderTarget {
+        match self.resolved {
+            Some(ref resolved) => resolved,
+            None => &self.scene,
+        }
+    }
+
+    // Runs the enabled passes over the scene target and presents the gamma corrected result to
+    // the output RenderTarget, or the window if None. The window is restored to drawing regular
+    // output to the window afterwards.
+    pub fn end(&mut self, window: &mut GameWindow, output: Option<&RenderTarget>) { unsafe {
+        window.set_hdr_output(false);
+        self.resolve();
+        gl::Disable(gl::SCISSOR_TEST);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::BindVertexArray(self.vao);
+
+        let mut source = self.get_input().color[0];
+        let mut use_ping = true;
+        for pass in self.passes.clone() {
+            if !self.is_enabled(pass) { continue; }
+            let (framebuffer, texture_id) = if use_ping {
+                (self.ping.framebuffer, self.ping.color[0])
+            } else {
+                (self.pong.framebuffer, self.pong.color[0])
+            };
+            match pass {
+                PostPass::Bloom => self.bloom(source, framebuffer),
+                PostPass::Tonemap => self.tonemap(source, framebuffer),
+                PostPass::FXAA => self.fxaa(source, framebuffer),
+            }
+            source = texture_id;
+            use_ping = !use_ping;
+        }
+
+        let (output_framebuffer, output_width, output_height) = match output {
+            Some(target) => (target.framebuffer, target.width, target.height),
+            None => {
+                let (window_width, window_height) = window.get_size();
+                (0, window_width, window_height)
+            },
+        };
+        gl::UseProgram(self.present_program);
+        bind_source(self.present_program, "source", 0, source);
+        uniform_float!(self.present_program, "gamma", window.get_gamma());
+        draw_fullscreen(output_framebuffer, output_width, output_height);
+
+        window.set_render_target(output);
+        gl::Enable(gl::DEPTH_TEST);
+        window.restore_state();
+    }}
+
+    // Extracts the bright parts of the source, blurs them through the pyramid, and adds the result
+    // back onto the source.
+    unsafe fn bloom(&self, source: GLuint, framebuffer: GLuint) {
+        gl::UseProgram(self.downsample_program);
+        uniform_float!(self.downsample_program, "threshold", self.bloom_threshold);
+        uniform_float!(self.downsample_program, "knee", self.bloom_knee);
+        let (mut level_source, mut source_width, mut source_height) =
+                (source, self.scene.width, self.scene.height);
+        for (i, level) in self.bloom_pyramid.iter().enumerate() {
+            bind_source(self.downsample_program, "source", 0, level_source);
+            uniform_int!(self.downsample_program, "prefilter", (i == 0) as GLint);
+            uniform_vec2!(self.downsample_program, "texel_size",
+                    vec![1.0 / source_width as GLfloat, 1.0 / source_height as GLfloat]);
+            draw_fullscreen(level.framebuffer, level.width, level.height);
+            level_source = level.color[0];
+            source_width = level.width;
+            source_height = level.height;
+        }
+
+        // Walk back up the pyramid and add each blurred level onto the next largest one.
+        gl::UseProgram(self.upsample_program);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE);
+        for i in (1..self.bloom_pyramid.len()).rev() {
+            let (smaller, larger) = (&self.bloom_pyramid[i], &self.bloom_pyramid[i - 1]);
+            bind_source(self.upsample_program, "source", 0, smaller.color[0]);
+            uniform_vec2!(self.upsample_program, "texel_size",
+                    vec![1.0 / smaller.width as GLfloat, 1.0 / smaller.height as GLfloat]);
+            draw_fullscreen(larger.framebuffer, larger.width, larger.height);
+        }
+        gl::Disable(gl::BLEND);
+
+        gl::UseProgram(self.bloom_program);
+        bind_source(self.bloom_program, "source", 0, source);
+        bind_source(self.bloom_program, "bloom", 1, self.bloom_pyramid[0].color[0]);
+        uniform_float!(self.bloom_program, "intensity", self.bloom_intensity);
+        draw_fullscreen(framebuffer, self.scene.width, self.scene.height);
+    }
+
+    // Applies the exposure and maps the source into the displayable range.
+    unsafe fn tonemap(&self, source: GLuint, framebuffer: GLuint) {
+        gl::UseProgram(self.tonemap_program);
+        bind_source(self.tonemap_program, "source", 0, source);
+        uniform_float!(self.tonemap_program, "exposure", self.exposure);
+        let tonemapper = match self.tonemapper {
+            Tonemapper::Reinhard => 0,
+            Tonemapper::ACES => 1,
+        };
+        uniform_uint!(self.tonemap_program, "tonemapper", tonemapper);
+        draw_fullscreen(framebuffer, self.scene.width, self.scene.height);
+    }
+
+    // Smooths out aliased edges in the source.
+    unsafe fn fxaa(&self, source: GLuint, framebuffer: GLuint) {
+        gl::UseProgram(self.fxaa_program);
+        bind_source(self.fxaa_program, "source", 0, source);
+        uniform_vec2!(self.fxaa_program, "texel_size",
+                vec![1.0 / self.scene.width as GLfloat, 1.0 / self.scene.height as GLfloat]);
+        draw_fullscreen(framebuffer, self.scene.width, self.scene.height);
+    }
+}
@@ -0,0 +1,327 @@
+// Defines prefabs, which are saved subtrees of a Scene that can be placed many times. Each placed
+// copy is a PrefabInstance that remembers which prefab it came from along with a list of
+// overrides, so that when the prefab is edited every instance is rebuilt from the new version with
+// its own overrides applied on top.
+//
+// Prefabs are stored in the same JSON node format as saved scenes. An override replaces a single
+// property of one node in that format, where the node is given as a path of names relative to the
+// instance root ("" for the root itself, "body/wheel" for a descendant) and the property is a path
+// of keys separated by '.' such as "transform.pos", "light.intensity", or "components.health". The
+// root transform and name always belong to the instance so that each copy keeps its placement.
+//
+// Usage of prefabs:
+// - Create a Prefab from a node with Prefab::from_node() or load one with Prefab::load().
+// - Add it to a PrefabManager with add_prefab() and place copies with instantiate().
+// - Change individual copies with set_override() and edit the prefab itself with update_prefab(),
+//   which rebuilds every instance.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::game_window::GameWindow;
+use gfx::scene::{LightHandle, NodeId, Scene};
+use gfx::scene_io::{self, ComponentRegistry, MeshCache};
+use std::collections::HashMap;
+use util::json::{self, Value};
+
+// Identifies a file as a saved prefab.
+pub const PREFAB_FORMAT: &'static str = "mmo-prefab";
+
+// The version of the format written by Prefab::save().
+pub const PREFAB_VERSION: u32 = 1;
+
+// Handle to a PrefabInstance in a PrefabManager.
+pub type InstanceId = usize;
+
+// A saved subtree of nodes.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    pub root: Value,
+}
+
+impl Prefab {
+    // Creates a prefab from a node and its descendants. Returns an Err if the node doesn't exist.
+    pub fn from_node(scene: &Scene, id: NodeId, window: &GameWindow,
+            registry: &ComponentRegistry) -> Result<Prefab, String> {
+        match scene_io::node_to_json(scene, id, window, registry) {
+            Some(root) => Ok(Prefab { root: root }),
+            None => Err(format!("Node {} does not exist.", id)),
+        }
+    }
+
+    // Loads a prefab from a JSON file written by save().
+    pub fn load(fpath: &str) -> Result<Prefab, String> {
+        let mut doc = try!(json::decode_json(fpath));
+        if doc.get("format").and_then(|f| f.as_str()) != Some(PREFAB_FORMAT) {
+            return Err("Not a prefab file.".to_string());
+        }
+        match doc.remove("root") {
+            Some(root) => Ok(Prefab { root: root }),
+            None => Err("Prefab file has no root node.".to_string()),
+        }
+    }
+
+    // Saves the prefab to a JSON file.
+    pub fn save(&self, fpath: &str) -> Result<(), String> {
+        let mut doc = Value::new_object();
+        doc.set("format", Value::String(PREFAB_FORMAT.to_string()));
+        doc.set("version", Value::Number(PREFAB_VERSION as f64));
+        doc.set("root", self.root.clone());
+        json::encode_json(fpath, &doc)
+    }
+}
+
+// Replaces a single property of one node of a placed prefab.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Override {
+    pub node: String,
+    pub property: String,
+    pub value: Value,
+}
+
+impl Override {
+    // Applies the override to the JSON of a prefab's root node. Returns false if the node doesn't
+    // exist in the prefab, which can happen after the prefab is edited.
+    fn apply(&self, root: &mut Value) -> bool {
+        let mut node = root;
+        for name in self.node.split('/').filter(|s| !s.is_empty()) {
+            let found = node.get_mut("children").and_then(|children| match *children {
+                Value::Array(ref mut items) => {
+                    items.iter_mut().find(|c| c.get("name").and_then(|n| n.as_str()) == Some(name))
+                },
+                _ => None,
+            });
+            node = match found {
+                Some(child) => child,
+                None => { return false; },
+            };
+        }
+        let keys: Vec<&str> = self.property.split('.').collect();
+        let (last, parents) = match keys.split_last() {
+            Some(split) => split,
+            None => { return false; },
+        };
+        let mut target = node;
+        for key in parents {
+            if target.get(key).map_or(true, |v| v.as_object().is_none()) {
+                target.set(key, Value::new_object());
+            }
+            target = target.get_mut(key).unwrap();
+        }
+        target.set(last, self.value.clone());
+        true
+    }
+}
+
+// A copy of a prefab placed in a scene.
+pub struct PrefabInstance {
+    pub prefab: String,
+    pub root: NodeId,
+    pub overrides: Vec<Override>,
+}
+
+// Holds named prefabs along with every instance of them so that instances can be rebuilt when
+// their prefab changes.
+pub struct PrefabManager {
+    prefabs: HashMap<String, Prefab>,
+    instances: Vec<Option<PrefabInstance>>,
+}
+
+impl PrefabManager {
+    // Default constructor for a PrefabManager with no prefabs.
+    pub fn new() -> PrefabManager {
+        PrefabManager { prefabs: HashMap::new(), instances: Vec::new() }
+    }
+
+    // Adds a prefab under a name, replacing any prefab with the same name without rebuilding its
+    // instances. Use update_prefab() to replace a prefab that has been placed.
+    pub fn add_prefab(&mut self, name: &str, prefab: Prefab) {
+        self.prefabs.insert(name.to_string(), prefab);
+    }
+
+    // Gets a prefab by name.
+    pub fn get_prefab(&self, name: &str) -> Option<&Prefab> {
+        self.prefabs.get(name)
+    }
+
+    // Gets a placed instance given its handle.
+    pub fn get_instance(&self, id: InstanceId) -> Option<&PrefabInstance> {
+        self.instances.get(id).and_then(|i| i.as_ref())
+    }
+
+    // Finds the instance whose root is a given node.
+    pub fn find_instance(&self, root: NodeId) -> Option<InstanceId> {
+        self.instances.iter().position(|i| i.as_ref().map_or(false, |i| i.root == root))
+    }
+
+    // Places a copy of a prefab as the last child of a parent, or as a root if the parent is None,
+    // and returns a handle to the instance. Cameras and lights in the prefab are attached to the
+    // window.
+    pub fn instantiate(&mut self, name: &str, scene: &mut Scene, parent: Option<NodeId>,
+            window: &mut GameWindow, registry: &ComponentRegistry, meshes: &mut MeshCache)
+            -> Result<InstanceId, String> {
+        let root = match self.prefabs.get(name) {
+            Some(prefab) => {
+                try!(scene_io::validate_node(&prefab.root, registry, meshes));
+                try!(scene_io::load_node(&prefab.root, parent, scene, window, registry, meshes))
+            },
+            None => { return Err(format!("Prefab {} does not exist.", name)); },
+        };
+        scene.update();
+        scene.sync(window);
+        let instance = PrefabInstance { prefab: name.to_string(), root: root,
+                overrides: Vec::new() };
+        Ok(match self.instances.iter().position(|i| i.is_none()) {
+            Some(i) => { self.instances[i] = Some(instance); i },
+            None => { self.instances.push(Some(instance)); self.instances.len() - 1 },
+        })
+    }
+
+    // Overrides a property of one node of an instance and rebuilds the instance. Setting the same
+    // property again replaces the earlier override. If the instance can't be rebuilt with the new
+    // override, the override is dropped and the instance is left as it was.
+    pub fn set_override(&mut self, id: InstanceId, node: &str, property: &str, value: Value,
+            scene: &mut Scene, window: &mut GameWindow, registry: &ComponentRegistry,
+            meshes: &mut MeshCache) -> Result<(), String> {
+        let previous = {
+            let instance = try!(self.get_instance_mut(id));
+            let previous = instance.overrides.clone();
+            instance.overrides.retain(|o| o.node != node || o.property != property);
+            instance.overrides.push(Override { node: node.to_string(),
+                    property: property.to_string(), value: value });
+            previous
+        };
+        self.rebuild_or_restore(id, previous, scene, window, registry, meshes)
+    }
+
+    // Removes an override so the property follows the prefab again, and rebuilds the instance. If
+    // the instance can't be rebuilt without the override, the override is kept.
+    pub fn clear_override(&mut self, id: InstanceId, node: &str, property: &str,
+            scene: &mut Scene, window: &mut GameWindow, registry: &ComponentRegistry,
+            meshes: &mut MeshCache) -> Result<(), String> {
+        let previous = {
+            let instance = try!(self.get_instance_mut(id));
+            let previous = instance.overrides.clone();
+            instance.overrides.retain(|o| o.node != node || o.property != property);
+            previous
+        };
+        self.rebuild_or_restore(id, previous, scene, window, registry, meshes)
+    }
+
+    // Replaces a prefab and rebuilds every instance of it so that they pick up the changes. The
+    // overrides of each instance are applied again on top of the new version. Every instance is
+    // rebuilt even if some of them fail, and the instances that failed are left as they were and
+    // reported together in the Err.
+    pub fn update_prefab(&mut self, name: &str, prefab: Prefab, scene: &mut Scene,
+            window: &mut GameWindow, registry: &ComponentRegistry, meshes: &mut MeshCache)
+            -> Result<(), String> {
+        self.prefabs.insert(name.to_string(), prefab);
+        let ids: Vec<InstanceId> = (0..self.instances.len()).filter(|i| {
+            self.instances[*i].as_ref().map_or(false, |inst| inst.prefab == name)
+        }).collect();
+        let mut errors = Vec::new();
+        for id in ids {
+            if let Err(e) = self.rebuild(id, scene, window, registry, meshes) {
+                errors.push(format!("Prefab instance {}: {}", id, e));
+            }
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors.join("\n")) }
+    }
+
+    // Forgets an instance so that its nodes become ordinary nodes that no longer follow the prefab.
+    pub fn unlink(&mut self, id: InstanceId) -> Result<PrefabInstance, String> {
+        match self.instances.get_mut(id).and_then(|i| i.take()) {
+            Some(instance) => Ok(instance),
+            None => Err(format!("Prefab instance {} does not exist.", id)),
+        }
+    }
+
+    // Removes an instance along with its nodes and the cameras and lights attached to them.
+    pub fn remove_instance(&mut self, id: InstanceId, scene: &mut Scene, window: &mut GameWindow)
+            -> Result<(), String> {
+        let instance = try!(self.unlink(id));
+        release_attachments(scene, instance.root, window);
+        try!(scene.remove_node(instance.root));
+        Ok(())
+    }
+
+    // Gets a mutable instance or returns an Err if it doesn't exist.
+    fn get_instance_mut(&mut self, id: InstanceId) -> Result<&mut PrefabInstance, String> {
+        match self.instances.get_mut(id).and_then(|i| i.as_mut()) {
+            Some(instance) => Ok(instance),
+            None => Err(format!("Prefab instance {} does not exist.", id)),
+        }
+    }
+
+    // Rebuilds an instance and puts back its previous overrides if that fails.
+    fn rebuild_or_restore(&mut self, id: InstanceId, previous: Vec<Override>, scene: &mut Scene,
+            window: &mut GameWindow, registry: &ComponentRegistry, meshes: &mut MeshCache)
+            -> Result<(), String> {
+        let result = self.rebuild(id, scene, window, registry, meshes);
+        if result.is_err() {
+            try!(self.get_instance_mut(id)).overrides = previous;
+        }
+        result
+    }
+
+    // Rebuilds the nodes of an instance from its prefab and overrides. The root node keeps its
+    // NodeId, name, and transform, while its descendants are replaced with new nodes. The new
+    // nodes are checked before the old ones are removed, so an Err leaves the instance unchanged.
+    fn rebuild(&mut self, id: InstanceId, scene: &mut Scene, window: &mut GameWindow,
+            registry: &ComponentRegistry, meshes: &mut MeshCache) -> Result<(), String> {
+        let instance = match self.get_instance(id) {
+            Some(instance) => instance,
+            None => { return Err(format!("Prefab instance {} does not exist.", id)); },
+        };
+        let mut doc = match self.prefabs.get(&instance.prefab) {
+            Some(prefab) => prefab.root.clone(),
+            None => { return Err(format!("Prefab {} does not exist.", instance.prefab)); },
+        };
+        {
+            let root = match scene.get_node(instance.root) {
+                Some(root) => root,
+                None => { return Err(format!("Node {} does not exist.", instance.root)); },
+            };
+            doc.set("name", Value::String(root.name.clone()));
+            doc.set("transform", scene_io::transform_to_json(&root.transform));
+        }
+        for o in &instance.overrides {
+            o.apply(&mut doc);
+        }
+        try!(scene_io::validate_node(&doc, registry, meshes));
+        release_attachments(scene, instance.root, window);
+        let children = scene.get_node(instance.root).unwrap().get_children().clone();
+        for child in children {
+            try!(scene.remove_node(child));
+        }
+        {
+            let root = scene.get_node_mut(instance.root).unwrap();
+            root.mesh = None;
+            root.mesh_path = None;
+            root.camera = None;
+            root.light = None;
+            root.components.clear();
+        }
+        try!(scene_io::load_node_into(&doc, instance.root, scene, window, registry, meshes));
+        scene.update();
+        scene.sync(window);
+        Ok(())
+    }
+}
+
+// Detaches the cameras and lights of a node and its descendants from the window.
+fn release_attachments(scene: &Scene, id: NodeId, window: &mut GameWindow) {
+    let attachments: Vec<(Option<usize>, Option<LightHandle>)> =
+            scene.iter_from(id).map(|(_, n)| (n.camera, n.light)).collect();
+    for (camera, light) in attachments {
+        if let Some(handle) = camera {
+            let _ = window.detach_camera(handle);
+        }
+        match light {
+            Some(LightHandle::Point(index)) => { window.remove_point_light(index); },
+            Some(LightHandle::Directional(index)) => { window.remove_directional_light(index); },
+            Some(LightHandle::Spot(index)) => { window.remove_spot_light(index); },
+            None => {},
+        }
+    }
+}
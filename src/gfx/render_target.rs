@@ -0,0 +1,382 @@
+// Defines a RenderTarget which wraps an OpenGL framebuffer with texture attachments so that scenes
+// can be drawn offscreen instead of to the window. Each RenderTarget has any number of color
+// attachments and an optional depth attachment of configurable formats, all backed by textures
+// that can be sampled afterwards. This allows for effects such as reflections, portals, and
+// minimaps by drawing to a RenderTarget with GameWindow::set_render_target() and using the color
+// texture in a Material. Multisampled RenderTargets can't be sampled like regular textures and
+// must first be resolved into a single sampled RenderTarget of the same size. The color
+// attachments can be read back to the CPU with read_pixels(), which is how headless rendering
+// tests check what was drawn.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::types::*;
+use util::common;
+use std::ptr;
+
+// The format of a color attachment. Since the fragment shader already gamma corrects its output,
+// SRGB8Alpha8 should be used for targets that are later sampled as a color texture so that they
+// are linearized again on read. The floating point formats hold values outside of [0, 1] and are
+// used for HDR rendering. R32UI holds unsigned integers such as object IDs, which must be written
+// by a uint fragment output and can be read back but not filtered.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorFormat {
+    RGBA8,
+    SRGB8Alpha8,
+    RGBA16F,
+    RGBA32F,
+    R8,
+    R16F,
+    RG16F,
+    R32UI,
+}
+
+// The format of a depth attachment.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DepthFormat {
+    Depth16,
+    Depth24,
+    Depth32F,
+    Depth24Stencil8,
+}
+
+impl ColorFormat {
+    // Gets the internal format, pixel format, and pixel type used to allocate the texture.
+    fn gl_formats(&self) -> (GLenum, GLenum, GLenum) {
+        match *self {
+            ColorFormat::RGBA8 => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+            ColorFormat::SRGB8Alpha8 => (gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
+            ColorFormat::RGBA16F => (gl::RGBA16F, gl::RGBA, gl::FLOAT),
+            ColorFormat::RGBA32F => (gl::RGBA32F, gl::RGBA, gl::FLOAT),
+            ColorFormat::R8 => (gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+            ColorFormat::R16F => (gl::R16F, gl::RED, gl::FLOAT),
+            ColorFormat::RG16F => (gl::RG16F, gl::RG, gl::FLOAT),
+            ColorFormat::R32UI => (gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT),
+        }
+    }
+}
+
+impl DepthFormat {
+    // Gets the internal format, pixel format, pixel type, and attachment point of the texture.
+    fn gl_formats(&self) -> (GLenum, GLenum, GLenum, GLenum) {
+        match *self {
+            DepthFormat::Depth16 => (gl::DEPTH_COMPONENT16, gl::DEPTH_COMPONENT,
+                    gl::UNSIGNED_SHORT, gl::DEPTH_ATTACHMENT),
+            DepthFormat::Depth24 => (gl::DEPTH_COMPONENT24, gl::DEPTH_COMPONENT,
+                    gl::UNSIGNED_INT, gl::DEPTH_ATTACHMENT),
+            DepthFormat::Depth32F => (gl::DEPTH_COMPONENT32F, gl::DEPTH_COMPONENT, gl::FLOAT,
+                    gl::DEPTH_ATTACHMENT),
+            DepthFormat::Depth24Stencil8 => (gl::DEPTH24_STENCIL8, gl::DEPTH_STENCIL,
+                    gl::UNSIGNED_INT_24_8, gl::DEPTH_STENCIL_ATTACHMENT),
+        }
+    }
+}
+
+// An offscreen framebuffer along with the textures attached to it. The color textures are in the
+// same order as the formats passed in on creation, so color[i] is written to by the fragment
+// shader's output at location i. When samples is greater than 1, the textures are
+// TEXTURE_2D_MULTISAMPLE textures instead of TEXTURE_2D. This can only be created after the
+// window context is set up.
+pub struct RenderTarget {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub framebuffer: GLuint,
+    pub color: Vec<GLuint>,
+    pub depth: Option<GLuint>,
+    color_formats: Vec<ColorFormat>,
+    depth_format: Option<DepthFormat>,
+}
+
+// Gets the texture target used for attachments with a given sample count.
+fn texture_target(samples: u32) -> GLenum {
+    if samples > 1 { gl::TEXTURE_2D_MULTISAMPLE } else { gl::TEXTURE_2D }
+}
+
+// Gets the largest number of samples supported by the driver.
+pub fn get_max_samples() -> u32 {
+    let mut max_samples = 0;
+    unsafe { gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples); }
+    max_samples as u32
+}
+
+// Reads back a rectangle of pixels at the bottom left of a framebuffer as 8-bit RGBA. The buffer is
+// COLOR_ATTACHMENTi for a RenderTarget's framebuffer or BACK for the window's, and the formats of
+// the buffer are converted and clamped to [0, 255]. OpenGL gives the bottom row first, so the rows
+// are flipped to store the top row first like every other Image.
+pub fn read_pixels(framebuffer: GLuint, buffer: GLenum, width: u32, height: u32)
+        -> common::Image { unsafe {
+    let row_size = width as usize * 4;
+    let mut raw = vec![0u8; row_size * height as usize];
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    gl::ReadBuffer(buffer);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(0, 0, width as GLsizei, height as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE,
+            raw.as_mut_ptr() as *mut GLvoid);
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for row in raw.chunks(row_size).rev() {
+        for texel in row.chunks(4) {
+            data.push(common::Pixel { red: texel[0], green: texel[1], blue: texel[2],
+                    alpha: texel[3] });
+        }
+    }
+    common::Image { width: width, height: height, data: data }
+}}
+
+// Allocates storage for the currently bound 2D or multisampled 2D texture.
+unsafe fn allocate_texture(width: u32, height: u32, samples: u32,
+        formats: (GLenum, GLenum, GLenum)) {
+    let (internal, format, pixel_type) = formats;
+    if samples > 1 {
+        gl::TexImage2DMultisample(
+                gl::TEXTURE_2D_MULTISAMPLE, samples as GLsizei, internal, width as GLsizei,
+                height as GLsizei, gl::TRUE);
+    } else {
+        gl::TexImage2D(
+                gl::TEXTURE_2D, 0, internal as GLint, width as GLsizei, height as GLsizei, 0,
+                format, pixel_type, ptr::null());
+    }
+}
+
+// Sets the filtering and wrapping of the currently bound 2D texture. Multisampled textures can
+// only be fetched from directly, so they have no sampling state.
+unsafe fn set_sampling(samples: u32, filter: GLenum) {
+    if samples > 1 { return; }
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+}
+
+impl RenderTarget {
+    // Creates a RenderTarget with a color attachment per format and an optional depth attachment.
+    // Returns an Err if the driver doesn't support the combination of formats.
+    pub fn new(width: u32, height: u32, color_formats: Vec<ColorFormat>,
+            depth_format: Option<DepthFormat>) -> Result<RenderTarget, String> {
+        RenderTarget::new_multisampled(width, height, color_formats, depth_format, 1)
+    }
+
+    // Same as new() but with a number of samples per pixel for MSAA. A sample count of 1 creates
+    // a regular RenderTarget. Returns an Err if the driver supports fewer samples.
+    pub fn new_multisampled(width: u32, height: u32, color_formats: Vec<ColorFormat>,
+            depth_format: Option<DepthFormat>, samples: u32)
+            -> Result<RenderTarget, String> { unsafe {
+        let samples = if samples == 0 { 1 } else { samples };
+        if samples > 1 && samples > get_max_samples() {
+            return Err(format!("{} samples are not supported.", samples));
+        }
+        let target_type = texture_target(samples);
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+
+        let mut color = Vec::new();
+        let mut draw_buffers = Vec::new();
+        for (i, format) in color_formats.iter().enumerate() {
+            let mut texture_id = 0;
+            gl::GenTextures(1, &mut texture_id);
+            gl::BindTexture(target_type, texture_id);
+            allocate_texture(width, height, samples, format.gl_formats());
+            set_sampling(samples, gl::LINEAR);
+            let attachment = gl::COLOR_ATTACHMENT0 + i as GLenum;
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, target_type, texture_id, 0);
+            color.push(texture_id);
+            draw_buffers.push(attachment);
+        }
+        if draw_buffers.is_empty() {
+            gl::DrawBuffer(gl::NONE);
+            gl::ReadBuffer(gl::NONE);
+        } else {
+            gl::DrawBuffers(draw_buffers.len() as GLsizei, draw_buffers.as_ptr());
+        }
+
+        let depth = match depth_format {
+            Some(format) => {
+                let (internal, pixel_format, pixel_type, attachment) = format.gl_formats();
+                let mut texture_id = 0;
+                gl::GenTextures(1, &mut texture_id);
+                gl::BindTexture(target_type, texture_id);
+                allocate_texture(width, height, samples, (internal, pixel_format, pixel_type));
+                set_sampling(samples, gl::NEAREST);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, attachment, target_type, texture_id, 0);
+                Some(texture_id)
+            },
+            None => None,
+        };
+        gl::BindTexture(target_type, 0);
+
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        let target = RenderTarget { width: width, height: height, samples: samples,
+                framebuffer: framebuffer,
+                color: color, depth: depth, color_formats: color_formats,
+                depth_format: depth_format };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            target.delete();
+            return Err(format!("Incomplete framebuffer (status 0x{:X}).", status));
+        }
+        Ok(target)
+    }}
+
+    // Helper constructor for the common case of a single color attachment with a depth buffer.
+    pub fn new_simple(width: u32, height: u32, color_format: ColorFormat)
+            -> Result<RenderTarget, String> {
+        RenderTarget::new(width, height, vec![color_format], Some(DepthFormat::Depth24))
+    }
+
+    // Gets the format of a color attachment.
+    pub fn get_color_format(&self, index: usize) -> ColorFormat {
+        self.color_formats[index]
+    }
+
+    // Gets the format of the depth attachment if there is one.
+    pub fn get_depth_format(&self) -> Option<DepthFormat> {
+        self.depth_format
+    }
+
+    // Gets the aspect ratio of the target.
+    pub fn get_aspect_ratio(&self) -> f32 {
+        if self.width == 0 || self.height == 0 { return 1.0; }
+        (self.width as f32) / (self.height as f32)
+    }
+
+    // Reads back a color attachment as 8-bit RGBA. Channels that the format doesn't have are read
+    // as 0, except for alpha which is read as 255. Returns an Err for multisampled targets, which
+    // must be resolved first, and for integer formats.
+    pub fn read_pixels(&self, index: usize) -> Result<common::Image, String> {
+        if index >= self.color.len() {
+            return Err(format!("RenderTarget has no color attachment {}.", index));
+        }
+        if self.samples > 1 {
+            return Err("Multisampled RenderTargets must be resolved to be read.".to_string());
+        }
+        if self.color_formats[index] == ColorFormat::R32UI {
+            return Err("Integer attachments can't be read as colors.".to_string());
+        }
+        let attachment = gl::COLOR_ATTACHMENT0 + index as GLenum;
+        let image = read_pixels(self.framebuffer, attachment, self.width, self.height);
+        unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        }
+        Ok(image)
+    }
+
+    // Reallocates every attachment with a new size. The contents are undefined afterwards, and
+    // mipmaps of the color attachments are dropped until generate_mipmaps() is called again.
+    pub fn resize(&mut self, width: u32, height: u32) { unsafe {
+        self.width = width;
+        self.height = height;
+        let target_type = texture_target(self.samples);
+        for (texture_id, format) in self.color.iter().zip(self.color_formats.iter()) {
+            gl::BindTexture(target_type, *texture_id);
+            allocate_texture(width, height, self.samples, format.gl_formats());
+            // The old mipmaps are the wrong size now, so stop sampling them until they are
+            // generated again.
+            set_sampling(self.samples, gl::LINEAR);
+        }
+        if let (Some(texture_id), Some(format)) = (self.depth, self.depth_format) {
+            let (internal, pixel_format, pixel_type, _) = format.gl_formats();
+            gl::BindTexture(target_type, texture_id);
+            allocate_texture(width, height, self.samples, (internal, pixel_format, pixel_type));
+        }
+        gl::BindTexture(target_type, 0);
+    }}
+
+    // Generates mipmaps for every color attachment and enables trilinear filtering on them. This
+    // should be called after drawing to the target if it is used as a texture on distant or
+    // minified surfaces. Multisampled targets have no mipmaps and are left as-is.
+    pub fn generate_mipmaps(&self) { unsafe {
+        if self.samples > 1 { return; }
+        for texture_id in &self.color {
+            gl::BindTexture(gl::TEXTURE_2D, *texture_id);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl::TexParameteri(
+                    gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as GLint);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }}
+
+    // Resolves the samples of a multisampled target into a single sampled target of the same size
+    // by averaging the color samples. Depth can't be averaged, so when depth is true the depth
+    // attachment is resolved by taking one of the samples per pixel instead, which is what depth
+    // readback passes such as SSAO expect. Returns an Err if the targets don't match, including
+    // when depth is resolved between different depth formats.
+    pub fn resolve(&self, dest: &RenderTarget, depth: bool) -> Result<(), String> { unsafe {
+        if self.width != dest.width || self.height != dest.height {
+            return Err("Resolved RenderTargets must be the same size.".to_string());
+        }
+        if dest.samples > 1 {
+            return Err("Cannot resolve into a multisampled RenderTarget.".to_string());
+        }
+        if self.color.len() > dest.color.len() || (depth && dest.depth.is_none()) {
+            return Err("Resolved RenderTarget is missing attachments.".to_string());
+        }
+        if depth && self.depth.is_some() && self.depth_format != dest.depth_format {
+            return Err("Resolved RenderTargets must have matching depth formats.".to_string());
+        }
+        let (width, height) = (self.width as GLint, self.height as GLint);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, dest.framebuffer);
+        for i in 0..self.color.len() {
+            let attachment = gl::COLOR_ATTACHMENT0 + i as GLenum;
+            gl::ReadBuffer(attachment);
+            gl::DrawBuffers(1, &attachment);
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::COLOR_BUFFER_BIT,
+                    gl::NEAREST);
+        }
+        if depth && self.depth.is_some() {
+            gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT,
+                    gl::NEAREST);
+        }
+
+        // Restore the draw buffers of both framebuffers.
+        let draw_buffers: Vec<GLenum> =
+                (0..dest.color.len()).map(|i| gl::COLOR_ATTACHMENT0 + i as GLenum).collect();
+        if !draw_buffers.is_empty() {
+            gl::DrawBuffers(draw_buffers.len() as GLsizei, draw_buffers.as_ptr());
+        }
+        if !self.color.is_empty() {
+            gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        Ok(())
+    }}
+
+    // Copies the depth attachment into another target of the same size and depth format. The
+    // destination can either have the same number of samples or be single sampled, in which case
+    // the depth is resolved.
+    pub fn copy_depth(&self, dest: &RenderTarget) -> Result<(), String> { unsafe {
+        if self.width != dest.width || self.height != dest.height {
+            return Err("Copied RenderTargets must be the same size.".to_string());
+        }
+        if self.depth.is_none() || self.depth_format != dest.depth_format {
+            return Err("Copied RenderTargets must have matching depth formats.".to_string());
+        }
+        if dest.samples > 1 && dest.samples != self.samples {
+            return Err("Copied RenderTargets must have matching samples.".to_string());
+        }
+        let (width, height) = (self.width as GLint, self.height as GLint);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, dest.framebuffer);
+        gl::BlitFramebuffer(0, 0, width, height, 0, 0, width, height, gl::DEPTH_BUFFER_BIT,
+                gl::NEAREST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        Ok(())
+    }}
+
+    // Deletes the framebuffer and its textures from the GPU.
+    pub fn delete(self) { unsafe {
+        gl::DeleteFramebuffers(1, &self.framebuffer);
+        gl::DeleteTextures(self.color.len() as GLsizei, self.color.as_ptr());
+        if let Some(texture_id) = self.depth {
+            gl::DeleteTextures(1, &texture_id);
+        }
+    }}
+}
@@ -0,0 +1,277 @@
+// Defines a Scatter, which places instances of vegetation and props over a Terrain or over the
+// triangles of a mesh. Candidate points are picked at random across the surface, and each one is
+// kept based on a density map, a rule on the height and slope of the surface, and its distance to
+// the points that were already kept. Throwing away candidates that land too close to another point
+// gives a Poisson-disk distribution, which looks natural without the clumps of purely random
+// points. The same seed always places the same points.
+//
+// Usage of a Scatter:
+// - Create it with new() and set any of the rules.
+// - Call scatter() with a surface to get the points, or create_batch() to get an InstanceBatch of
+//   a ModelInfo at the points that can be drawn with GameWindow::draw_instanced().
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::instancing::InstanceBatch;
+use gfx::model::{ModelInfo, ModelInstance};
+use gfx::terrain::{SplatRule, Terrain};
+use gfx::types::*;
+use self::cgmath::{EuclideanVector, Matrix, Rotation, Rotation3, SquareMatrix};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::rc::Rc;
+use util::common::Image;
+use util::random::Random;
+
+// A surface that instances are scattered over in its local space.
+pub enum ScatterSurface<'a> {
+    // Points are spread evenly over the terrain as seen from above, and the density map is
+    // stretched over it with its first row at +Y like the heightmap.
+    Terrain(&'a Terrain),
+    // Points are spread evenly over the area of the triangles, and the density map is looked up by
+    // the texture coordinates of the mesh.
+    Mesh(&'a ModelInfo),
+}
+
+// A candidate point on a surface along with the texture coordinate to look up the density at.
+struct SurfacePoint {
+    pos: Vector3D,
+    normal: Vector3D,
+    tcoord: Option<(GLfloat, GLfloat)>,
+}
+
+// An instance placed by a Scatter in the local space of the surface.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ScatterPoint {
+    pub pos: Vector3D,
+    pub normal: Vector3D,
+    pub rot: Quaternion,
+    pub scale: GLfloat,
+}
+
+// The settings that a Scatter places instances with. Up to count instances are placed, with up to
+// attempts candidates tried for each of them before giving up. The density map gives the chance of
+// keeping a candidate by its brightness, and the rule gives the chance by the height and slope of
+// the surface. Instances tilt from upright toward the surface normal by the align factor.
+pub struct Scatter {
+    pub seed: u64,
+    pub count: usize,
+    pub spacing: GLfloat,                   // Minimum distance between instances, or 0.0 for none.
+    pub attempts: usize,
+    pub density: Option<Image>,
+    pub rule: Option<SplatRule>,
+    pub scale: (GLfloat, GLfloat),          // Range that the scale of each instance is picked from.
+    pub align: GLfloat,
+    pub random_yaw: bool,
+}
+
+impl Scatter {
+    // Creates a scatter of up to a number of upright instances at least a distance apart with a
+    // seed. Every instance has a scale of 1 and is turned randomly about the up axis.
+    pub fn new(count: usize, spacing: GLfloat, seed: u64) -> Scatter {
+        Scatter { seed: seed, count: count, spacing: spacing, attempts: 30, density: None,
+                rule: None, scale: (1.0, 1.0), align: 0.0, random_yaw: true }
+    }
+
+    // Places instances over a surface. Fewer than count instances are placed if the rules and
+    // spacing throw away too many candidates.
+    pub fn scatter(&self, surface: &ScatterSurface) -> Vec<ScatterPoint> {
+        let mut random = Random::new(self.seed);
+        let areas = match *surface {
+            ScatterSurface::Mesh(info) => compute_areas(info),
+            ScatterSurface::Terrain(_) => Vec::new(),
+        };
+        let mut grid: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+        let mut points: Vec<ScatterPoint> = Vec::new();
+        for _ in 0..(self.count * self.attempts) {
+            if points.len() >= self.count { break; }
+            let candidate = match sample_surface(surface, &areas, &mut random) {
+                Some(candidate) => candidate,
+                None => { continue; },
+            };
+            if random.next_float() >= self.get_chance(&candidate) { continue; }
+            if self.spacing > 0.0 {
+                if self.is_crowded(&grid, &points, candidate.pos) { continue; }
+                grid.entry(self.get_cell(candidate.pos)).or_insert(Vec::new()).push(points.len());
+            }
+            let rot = self.get_rotation(candidate.normal, &mut random);
+            let scale = random.range(self.scale.0, self.scale.1);
+            points.push(ScatterPoint { pos: candidate.pos, normal: candidate.normal, rot: rot,
+                    scale: scale });
+        }
+        points
+    }
+
+    // Places instances over a surface and creates an InstanceBatch of a ModelInfo at them. The
+    // model matrix places the surface in the world. This can only be called after the window
+    // context is set up.
+    pub fn create_batch(&self, surface: &ScatterSurface, info: Rc<ModelInfo>,
+            model: cgmath::Matrix4<GLfloat>) -> InstanceBatch {
+        let mut batch = InstanceBatch::new(info);
+        fill_batch(&self.scatter(surface), &mut batch, model);
+        batch
+    }
+
+    // Gets the chance of keeping a candidate from the density map and the rule.
+    fn get_chance(&self, candidate: &SurfacePoint) -> GLfloat {
+        let density = match (&self.density, candidate.tcoord) {
+            (&Some(ref image), Some((u, v))) => sample_density(image, u, v),
+            _ => 1.0,
+        };
+        let slope = candidate.normal.z.max(-1.0).min(1.0).acos();
+        density * self.rule.map_or(1.0, |r| r.get_weight(candidate.pos.z, slope))
+    }
+
+    // Gets the cell of the spacing grid that a point is in.
+    fn get_cell(&self, p: Vector3D) -> (i32, i32, i32) {
+        ((p.x / self.spacing).floor() as i32, (p.y / self.spacing).floor() as i32,
+                (p.z / self.spacing).floor() as i32)
+    }
+
+    // Returns true if a point is closer than the spacing to any point that was already kept, which
+    // only has to check the points in the cells around it.
+    fn is_crowded(&self, grid: &HashMap<(i32, i32, i32), Vec<usize>>, points: &[ScatterPoint],
+            pos: Vector3D) -> bool {
+        let cell = self.get_cell(pos);
+        for i in -1..2 {
+            for j in -1..2 {
+                for k in -1..2 {
+                    if let Some(neighbors) = grid.get(&(cell.0 + i, cell.1 + j, cell.2 + k)) {
+                        if neighbors.iter().any(|&n| {
+                            (points[n].pos - pos).length2() < self.spacing * self.spacing
+                        }) { return true; }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Gets the rotation of an instance on a surface with a normal, which is tilted toward the
+    // normal by the align factor after turning about the up axis.
+    fn get_rotation(&self, normal: Vector3D, random: &mut Random) -> Quaternion {
+        let up = Vector3D::new(0.0, 0.0, 1.0);
+        let axis = up * (1.0 - self.align) + normal * self.align;
+        let tilt = if axis.length2() < 1e-8 {
+            Quaternion::new(1.0, 0.0, 0.0, 0.0)
+        } else if axis.normalize().z < -0.9999 {
+            Quaternion::from_axis_angle(Vector3D::new(1.0, 0.0, 0.0), cgmath::rad(PI))
+        } else {
+            Quaternion::between_vectors(up, axis.normalize())
+        };
+        if self.random_yaw {
+            tilt * Quaternion::from_axis_angle(up, cgmath::rad(random.range(0.0, 2.0 * PI)))
+        } else {
+            tilt
+        }
+    }
+}
+
+// Adds an instance of a batch's ModelInfo at each of the points. The model matrix places the
+// surface that the points were scattered over in the world.
+pub fn fill_batch(points: &[ScatterPoint], batch: &mut InstanceBatch,
+        model: cgmath::Matrix4<GLfloat>) {
+    for point in points {
+        let mut instance = ModelInstance::from(batch.info.clone());
+        instance.pos = point.pos;
+        instance.rot = point.rot;
+        instance.scale = point.scale;
+        instance.update();
+        instance.model = model * instance.model;
+        instance.normal = instance.model.invert().unwrap_or(cgmath::Matrix4::identity())
+                .transpose();
+        batch.push(&instance);
+    }
+}
+
+// Gets the running total of the areas of the triangles of a mesh, which is used to pick triangles
+// in proportion to their area.
+fn compute_areas(info: &ModelInfo) -> Vec<GLfloat> {
+    let mut total = 0.0;
+    info.elements.chunks(3).filter(|t| t.len() == 3).map(|t| {
+        let (a, b, c) = (get_vertex(info, t[0]), get_vertex(info, t[1]), get_vertex(info, t[2]));
+        total += (b - a).cross(c - a).length() * 0.5;
+        total
+    }).collect()
+}
+
+// Gets the position of a vertex of a mesh.
+fn get_vertex(info: &ModelInfo, index: GLuint) -> Vector3D {
+    let i = index as usize * 3;
+    Vector3D::new(info.vertices[i], info.vertices[i + 1], info.vertices[i + 2])
+}
+
+// Picks a random point on a surface, or None if the point missed the surface.
+fn sample_surface(surface: &ScatterSurface, areas: &[GLfloat], random: &mut Random)
+        -> Option<SurfacePoint> {
+    match *surface {
+        ScatterSurface::Terrain(terrain) => {
+            let bounds = terrain.get_bounds();
+            let (u, v) = (random.next_float(), random.next_float());
+            let x = bounds.min.x + (bounds.max.x - bounds.min.x) * u;
+            let y = bounds.max.y - (bounds.max.y - bounds.min.y) * v;
+            terrain.get_surface(x, y).map(|(height, normal)| {
+                SurfacePoint { pos: Vector3D::new(x, y, height), normal: normal,
+                        tcoord: Some((u, v)) }
+            })
+        },
+        ScatterSurface::Mesh(info) => {
+            let total = match areas.last() {
+                Some(&total) if total > 0.0 => total,
+                _ => { return None; },
+            };
+            let target = random.next_float() * total;
+            let triangle = match areas.binary_search_by(|a| a.partial_cmp(&target).unwrap()) {
+                Ok(i) | Err(i) => i.min(areas.len() - 1),
+            };
+            let t = &info.elements[triangle * 3..triangle * 3 + 3];
+
+            // Fold points outside of the triangle back in so they are spread evenly.
+            let (mut s, mut r) = (random.next_float(), random.next_float());
+            if s + r > 1.0 {
+                s = 1.0 - s;
+                r = 1.0 - r;
+            }
+            let (a, b, c) = (get_vertex(info, t[0]), get_vertex(info, t[1]),
+                    get_vertex(info, t[2]));
+
+            // Blend the vertex normals if the mesh has them, since the winding of the triangles
+            // isn't always consistent.
+            let mut normal = Vector3D::new(0.0, 0.0, 0.0);
+            if info.normals.len() >= info.vertices.len() {
+                let get = |i: GLuint| {
+                    let i = i as usize * 3;
+                    Vector3D::new(info.normals[i], info.normals[i + 1], info.normals[i + 2])
+                };
+                normal = get(t[0]) * (1.0 - s - r) + get(t[1]) * s + get(t[2]) * r;
+            }
+            if normal.length2() < 1e-8 {
+                normal = (b - a).cross(c - a);
+            }
+            if normal.length2() <= 0.0 { return None; }
+            let tcoord = if info.tcoords.len() >= info.vertices.len() / 3 * 2 {
+                let get = |i: GLuint| {
+                    (info.tcoords[i as usize * 2], info.tcoords[i as usize * 2 + 1])
+                };
+                let (ta, tb, tc) = (get(t[0]), get(t[1]), get(t[2]));
+                Some((ta.0 + (tb.0 - ta.0) * s + (tc.0 - ta.0) * r,
+                        ta.1 + (tb.1 - ta.1) * s + (tc.1 - ta.1) * r))
+            } else { None };
+            Some(SurfacePoint { pos: a + (b - a) * s + (c - a) * r, normal: normal.normalize(),
+                    tcoord: tcoord })
+        },
+    }
+}
+
+// Gets the brightness of the pixel of an image at a texture coordinate, which is clamped to the
+// edges of the image.
+fn sample_density(image: &Image, u: GLfloat, v: GLfloat) -> GLfloat {
+    if image.data.is_empty() { return 1.0; }
+    let x = ((u.max(0.0) * image.width as GLfloat) as usize).min(image.width as usize - 1);
+    let y = ((v.max(0.0) * image.height as GLfloat) as usize).min(image.height as usize - 1);
+    let p = &image.data[y * image.width as usize + x];
+    (p.red as GLfloat + p.green as GLfloat + p.blue as GLfloat) / (3.0 * 255.0)
+}
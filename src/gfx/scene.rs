@@ -0,0 +1,563 @@
+// Defines a Scene which is a hierarchy of named nodes. Every node has a transform relative to its
+// parent and can optionally have a mesh, camera, or light attached to it, so moving a node moves
+// everything attached to it and to its children. Nodes are addressed by the NodeId handle returned
+// when they are added, and can also be found by name or by a path of names such as "car/wheel_fl".
+//
+// Meshes are stored as ModelInstances since a ModelInfo holds both the geometry and the material.
+// Cameras and lights are owned by the GameWindow, so nodes only hold their handles and sync()
+// copies the world transforms of the nodes to them. Following glTF, attached cameras and lights
+// point down the node's local -Z axis and cameras use the local +Y axis as up.
+//
+// Usage of a Scene:
+// - Build the hierarchy with add_node() and attach meshes, cameras, and lights.
+// - After changing transforms, call update() to recompute the world transforms.
+// - Call sync(&mut window) to move the attached cameras and lights and draw(&mut window) to draw
+//   every mesh that is visible from the active camera.
+// - For scenes with many meshes, draw_parallel(&mut window, &jobs) culls them across the threads
+//   of a JobSystem instead, and draw_in(&mut window, &arena) keeps the list of visible nodes in a
+//   FrameArena.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::{EuclideanVector, Matrix, SquareMatrix, Vector};
+use gfx::bounds;
+use gfx::bvh;
+use gfx::game_window::GameWindow;
+use gfx::lod;
+use gfx::model;
+use gfx::types::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::rc::Rc;
+use util::alloc::{ArenaVec, FrameArena};
+use util::jobs::JobSystem;
+
+// Handle to a node in a Scene.
+pub type NodeId = usize;
+
+// A position, rotation, and non-uniform scale relative to a parent.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Transform {
+    pub pos: Vector3D,
+    pub rot: Quaternion,
+    pub scale: Vector3D,
+}
+
+impl Transform {
+    // Creates a transform that doesn't move, rotate, or scale anything.
+    pub fn identity() -> Transform {
+        Transform { pos: Vector3D::new(0.0, 0.0, 0.0), rot: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+                scale: Vector3D::new(1.0, 1.0, 1.0) }
+    }
+
+    // Default constructor for a Transform.
+    pub fn new(pos: Vector3D, rot: Quaternion, scale: Vector3D) -> Transform {
+        Transform { pos: pos, rot: rot, scale: scale }
+    }
+
+    // Gets the matrix that scales, then rotates, then translates.
+    pub fn to_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        cgmath::Matrix4::from_translation(self.pos) * cgmath::Matrix4::from(self.rot) *
+                cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Decomposes a matrix made of a translation, rotation, and scale back into a Transform. Any
+    // shear in the matrix is lost.
+    pub fn from_matrix(m: &cgmath::Matrix4<GLfloat>) -> Transform {
+        let x = Vector3D::new(m.x.x, m.x.y, m.x.z);
+        let y = Vector3D::new(m.y.x, m.y.y, m.y.z);
+        let z = Vector3D::new(m.z.x, m.z.y, m.z.z);
+        let mut scale = Vector3D::new(x.length(), y.length(), z.length());
+        if x.cross(y).dot(z) < 0.0 {
+            scale.x = -scale.x;
+        }
+        let axis = |v: Vector3D, s: GLfloat| if s == 0.0 { v } else { v / s };
+        let rotation = cgmath::Matrix3::from_cols(axis(x, scale.x), axis(y, scale.y),
+                axis(z, scale.z));
+        Transform { pos: Vector3D::new(m.w.x, m.w.y, m.w.z),
+                rot: Quaternion::from(rotation).normalize(), scale: scale }
+    }
+}
+
+// Handle to a light owned by the GameWindow.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LightHandle {
+    Point(usize),
+    Directional(usize),
+    Spot(usize),
+}
+
+// A single node of a Scene. The camera is a handle from GameWindow::attach_camera() and the light
+// is a handle from one of the GameWindow::attach_*_light() methods. The mesh path is the asset the
+// mesh was loaded from, which is what gets saved in place of the mesh. Components hold any other
+// data that game code wants to attach to a node, keyed by a name.
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    pub mesh: Option<model::ModelInstance>,
+    pub mesh_path: Option<String>,
+    pub lod: Option<lod::LodGroup>,
+    pub camera: Option<usize>,
+    pub light: Option<LightHandle>,
+    pub components: HashMap<String, Box<Any>>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: cgmath::Matrix4<GLfloat>,
+    proxy: Option<bvh::ProxyId>,
+}
+
+impl Node {
+    // Gets the parent of the node if it isn't a root.
+    pub fn get_parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    // Gets the children of the node in order.
+    pub fn get_children(&self) -> &Vec<NodeId> {
+        &self.children
+    }
+
+    // Gets the transform of the node relative to the world as of the last Scene::update().
+    pub fn get_world_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        self.world
+    }
+
+    // Gets the position of the node in the world as of the last Scene::update().
+    pub fn get_world_position(&self) -> Vector3D {
+        Vector3D::new(self.world.w.x, self.world.w.y, self.world.w.z)
+    }
+
+    // Attaches a component to the node, replacing any component with the same name.
+    pub fn set_component<T: Any>(&mut self, name: &str, component: T) {
+        self.components.insert(name.to_string(), Box::new(component));
+    }
+
+    // Gets a component by name if it exists and has the type T.
+    pub fn get_component<T: Any>(&self, name: &str) -> Option<&T> {
+        self.components.get(name).and_then(|c| c.downcast_ref::<T>())
+    }
+
+    // Gets a mutable component by name if it exists and has the type T.
+    pub fn get_component_mut<T: Any>(&mut self, name: &str) -> Option<&mut T> {
+        self.components.get_mut(name).and_then(|c| c.downcast_mut::<T>())
+    }
+
+    // Gets the bounds in the world of the node's mesh and level of detail group as of the last
+    // Scene::update(), or None if it has neither.
+    pub fn get_world_bounds(&self) -> Option<bounds::Aabb> {
+        let mesh = self.mesh.as_ref().map(|m| m.get_world_bounds());
+        let lod = self.lod.as_ref().map(|l| l.get_world_bounds());
+        match (mesh, lod) {
+            (Some(m), Some(l)) => Some(m.union(&l)),
+            (m, l) => m.or(l),
+        }
+    }
+
+    // Transforms a direction in the node's space into a normalized direction in the world.
+    fn world_direction(&self, x: GLfloat, y: GLfloat, z: GLfloat) -> Vector3D {
+        let v = self.world * cgmath::Vector4::new(x, y, z, 0.0);
+        Vector3D::new(v.x, v.y, v.z).normalize()
+    }
+}
+
+// A depth-first, pre-order iterator over the nodes of a Scene or a subtree.
+pub struct Traversal<'a> {
+    scene: &'a Scene,
+    stack: Vec<NodeId>,
+}
+
+impl<'a> Iterator for Traversal<'a> {
+    type Item = (NodeId, &'a Node);
+
+    fn next(&mut self) -> Option<(NodeId, &'a Node)> {
+        let id = match self.stack.pop() {
+            Some(id) => id,
+            None => { return None; },
+        };
+        let node = self.scene.nodes[id].as_ref().unwrap();
+        self.stack.extend(node.children.iter().rev());
+        Some((id, node))
+    }
+}
+
+// An iterator over the ancestors of a node from its parent up to the root.
+pub struct Ancestors<'a> {
+    scene: &'a Scene,
+    next: Option<NodeId>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = (NodeId, &'a Node);
+
+    fn next(&mut self) -> Option<(NodeId, &'a Node)> {
+        let id = match self.next {
+            Some(id) => id,
+            None => { return None; },
+        };
+        let node = self.scene.nodes[id].as_ref().unwrap();
+        self.next = node.parent;
+        Some((id, node))
+    }
+}
+
+// A hierarchy of nodes. Removed nodes leave a hole that is reused by the next added node, so a
+// NodeId is only valid until its node is removed. The world bounds of every mesh are kept in a Bvh
+// that is refit by update() and used to cull meshes outside of the camera and to cast rays.
+pub struct Scene {
+    nodes: Vec<Option<Node>>,
+    roots: Vec<NodeId>,
+    bvh: bvh::Bvh<NodeId>,
+    world_bounds: Vec<Option<bounds::Aabb>>,   // By NodeId as of the last update().
+}
+
+impl Scene {
+    // Default constructor for an empty Scene.
+    pub fn new() -> Scene {
+        Scene { nodes: Vec::new(), roots: Vec::new(), bvh: bvh::Bvh::new(),
+                world_bounds: Vec::new() }
+    }
+
+    // Adds an empty node with an identity transform as the last child of a parent, or as a root if
+    // the parent is None. Returns an Err if the parent doesn't exist.
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>) -> Result<NodeId, String> {
+        if let Some(p) = parent {
+            try!(self.check(p));
+        }
+        let node = Node { name: name.to_string(), transform: Transform::identity(), mesh: None,
+                mesh_path: None, lod: None, camera: None, light: None, components: HashMap::new(),
+                parent: parent, children: Vec::new(), world: cgmath::Matrix4::identity(),
+                proxy: None };
+        let id = match self.nodes.iter().position(|n| n.is_none()) {
+            Some(i) => { self.nodes[i] = Some(node); i },
+            None => { self.nodes.push(Some(node)); self.nodes.len() - 1 },
+        };
+        match parent {
+            Some(p) => self.nodes[p].as_mut().unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        Ok(id)
+    }
+
+    // Adds a node with a mesh made from a ModelInfo. The node's transform positions the mesh.
+    pub fn add_mesh_node(&mut self, name: &str, parent: Option<NodeId>,
+            info: Rc<model::ModelInfo>) -> Result<NodeId, String> {
+        let id = try!(self.add_node(name, parent));
+        self.nodes[id].as_mut().unwrap().mesh = Some(model::ModelInstance::from(info));
+        Ok(id)
+    }
+
+    // Removes a node along with all of its descendants and returns the removed nodes in
+    // depth-first order. The cameras and lights of the removed nodes are left in the GameWindow.
+    pub fn remove_node(&mut self, id: NodeId) -> Result<Vec<Node>, String> {
+        try!(self.check(id));
+        self.detach(id);
+        let ids: Vec<NodeId> = self.iter_from(id).map(|(i, _)| i).collect();
+        let mut removed = Vec::with_capacity(ids.len());
+        for i in ids {
+            let mut node = self.nodes[i].take().unwrap();
+            if let Some(proxy) = node.proxy.take() {
+                self.bvh.remove(proxy);
+            }
+            removed.push(node);
+        }
+        Ok(removed)
+    }
+
+    // Returns an Err if a NodeId doesn't refer to a node.
+    fn check(&self, id: NodeId) -> Result<(), String> {
+        match self.nodes.get(id) {
+            Some(&Some(_)) => Ok(()),
+            _ => Err(format!("Node {} does not exist.", id)),
+        }
+    }
+
+    // Removes a node from its parent's children or from the roots.
+    fn detach(&mut self, id: NodeId) {
+        let siblings = match self.nodes[id].as_ref().unwrap().parent {
+            Some(p) => &mut self.nodes[p].as_mut().unwrap().children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&c| c != id);
+    }
+
+    // Gets a node given its handle.
+    pub fn get_node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id).and_then(|n| n.as_ref())
+    }
+
+    // Gets a mutable node given its handle.
+    pub fn get_node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id).and_then(|n| n.as_mut())
+    }
+
+    // Gets the root nodes in order.
+    pub fn get_roots(&self) -> &Vec<NodeId> {
+        &self.roots
+    }
+
+    // Gets the number of nodes in the scene.
+    pub fn len(&self) -> usize {
+        self.nodes.iter().filter(|n| n.is_some()).count()
+    }
+
+    // Moves a node and its descendants under a new parent, or to the roots if the parent is None.
+    // If keep_world is true, the node's transform is changed so that it stays in the same place in
+    // the world as of the last update(). Returns an Err if either node doesn't exist or if the new
+    // parent is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>, keep_world: bool)
+            -> Result<(), String> {
+        try!(self.check(id));
+        if let Some(p) = parent {
+            try!(self.check(p));
+            if p == id || self.ancestors(p).any(|(a, _)| a == id) {
+                return Err("A node cannot be parented to itself or its descendants.".to_string());
+            }
+        }
+        self.detach(id);
+        match parent {
+            Some(p) => self.nodes[p].as_mut().unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        let parent_world = parent.map(|p| self.nodes[p].as_ref().unwrap().world);
+        let node = self.nodes[id].as_mut().unwrap();
+        node.parent = parent;
+        if keep_world {
+            let local = match parent_world {
+                Some(pw) => pw.invert().unwrap_or(cgmath::Matrix4::identity()) * node.world,
+                None => node.world,
+            };
+            node.transform = Transform::from_matrix(&local);
+        }
+        Ok(())
+    }
+
+    // Iterates over every node in depth-first, pre-order.
+    pub fn iter<'a>(&'a self) -> Traversal<'a> {
+        Traversal { scene: self, stack: self.roots.iter().rev().map(|r| *r).collect() }
+    }
+
+    // Iterates over a node and its descendants in depth-first, pre-order.
+    pub fn iter_from<'a>(&'a self, id: NodeId) -> Traversal<'a> {
+        let stack = if self.get_node(id).is_some() { vec![id] } else { Vec::new() };
+        Traversal { scene: self, stack: stack }
+    }
+
+    // Iterates over the ancestors of a node starting with its parent.
+    pub fn ancestors<'a>(&'a self, id: NodeId) -> Ancestors<'a> {
+        Ancestors { scene: self, next: self.get_node(id).and_then(|n| n.parent) }
+    }
+
+    // Finds the first node with a given name in depth-first order.
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.iter().find(|&(_, n)| n.name == name).map(|(i, _)| i)
+    }
+
+    // Finds a node given a path of names separated by '/' starting from a root such as
+    // "car/body/wheel". The first matching child is taken at each level.
+    pub fn find_path(&self, path: &str) -> Option<NodeId> {
+        let mut candidates = &self.roots;
+        let mut found = None;
+        for name in path.split('/').filter(|s| !s.is_empty()) {
+            let child = candidates.iter().find(|&&c| self.nodes[c].as_ref().unwrap().name == name);
+            let id = match child {
+                Some(id) => *id,
+                None => { return None; },
+            };
+            found = Some(id);
+            candidates = &self.nodes[id].as_ref().unwrap().children;
+        }
+        found
+    }
+
+    // Gets the path of names from a root to a node.
+    pub fn get_path(&self, id: NodeId) -> Option<String> {
+        let node = match self.get_node(id) {
+            Some(n) => n,
+            None => { return None; },
+        };
+        let mut names: Vec<&str> = self.ancestors(id).map(|(_, n)| n.name.as_ref()).collect();
+        names.reverse();
+        names.push(&node.name);
+        Some(names.join("/"))
+    }
+
+    // Recomputes the world transform of every node along with the model matrices and bounds of the
+    // meshes. This must be called after any sequence of transform or mesh changes for them to
+    // appear in-world.
+    pub fn update(&mut self) {
+        profile_scope!("Scene::update");
+        self.world_bounds.resize(self.nodes.len(), None);
+        let mut stack: Vec<(NodeId, cgmath::Matrix4<GLfloat>)> =
+                self.roots.iter().map(|r| (*r, cgmath::Matrix4::identity())).collect();
+        while let Some((id, parent_world)) = stack.pop() {
+            let node = self.nodes[id].as_mut().unwrap();
+            node.world = parent_world * node.transform.to_matrix();
+            if let Some(ref mut instance) = node.mesh {
+                instance.model = node.world;
+                instance.normal = node.world.invert().unwrap_or(cgmath::Matrix4::identity())
+                        .transpose();
+            }
+            if let Some(ref mut group) = node.lod {
+                group.set_model(node.world);
+            }
+            let world_bounds = node.get_world_bounds();
+            self.world_bounds[id] = world_bounds;
+            match (world_bounds, node.proxy) {
+                (Some(world_bounds), Some(proxy)) => { self.bvh.update(proxy, world_bounds); },
+                (Some(world_bounds), None) => {
+                    node.proxy = Some(self.bvh.insert(world_bounds, id));
+                },
+                (None, Some(proxy)) => {
+                    self.bvh.remove(proxy);
+                    node.proxy = None;
+                },
+                (None, None) => {},
+            }
+            for child in &node.children {
+                stack.push((*child, node.world));
+            }
+        }
+    }
+
+    // Moves the cameras and lights attached to nodes to the world transforms of their nodes.
+    pub fn sync(&self, window: &mut GameWindow) {
+        for (_, node) in self.iter() {
+            let pos = node.get_world_position();
+            let fwd = node.world_direction(0.0, 0.0, -1.0);
+            if let Some(handle) = node.camera {
+                if let Ok(camera) = window.get_camera_mut(handle) {
+                    camera.pos = pos;
+                    camera.target = pos + fwd;
+                    camera.up = node.world_direction(0.0, 1.0, 0.0);
+                }
+                window.update_camera(handle);
+            }
+            match node.light {
+                Some(LightHandle::Point(index)) => {
+                    window.get_point_light_mut(index).position = pos;
+                    window.update_point_light(index);
+                },
+                Some(LightHandle::Directional(index)) => {
+                    window.get_directional_light_mut(index).direction = fwd;
+                    window.update_directional_light(index);
+                },
+                Some(LightHandle::Spot(index)) => {
+                    {
+                        let light = window.get_spot_light_mut(index);
+                        light.position = pos;
+                        light.direction = fwd;
+                    }
+                    window.update_spot_light(index);
+                },
+                None => {},
+            }
+        }
+    }
+
+    // Gets the hierarchy of mesh bounds as of the last update().
+    pub fn get_bvh(&self) -> &bvh::Bvh<NodeId> {
+        &self.bvh
+    }
+
+    // Gets the nodes with meshes or level of detail groups that are at least partly inside a
+    // frustum as of the last update(), ordered by NodeId.
+    pub fn query_frustum(&self, frustum: &bounds::Frustum) -> Vec<NodeId> {
+        profile_scope!("Scene::cull");
+        let mut visible: Vec<NodeId> = Vec::new();
+        self.bvh.query(|b| frustum.intersects_aabb(b), |_, id| {
+            let node = self.nodes[*id].as_ref().unwrap();
+            if node.get_world_bounds().map_or(false, |b| frustum.intersects_aabb(&b)) {
+                visible.push(*id);
+            }
+        });
+        visible.sort();
+        visible
+    }
+
+    // Like query_frustum() but keeps the list of nodes in a FrameArena.
+    pub fn query_frustum_in<'a>(&self, arena: &'a FrameArena, frustum: &bounds::Frustum)
+            -> ArenaVec<'a, NodeId> {
+        profile_scope!("Scene::cull");
+        let mut visible = arena.new_vec();
+        self.bvh.query(|b| frustum.intersects_aabb(b), |_, id| {
+            if self.world_bounds[*id].map_or(false, |b| frustum.intersects_aabb(&b)) {
+                visible.push(*id);
+            }
+        });
+        visible.sort();
+        visible
+    }
+
+    // Like query_frustum() but spread across the threads of a JobSystem.
+    pub fn query_frustum_parallel(&self, jobs: &JobSystem, frustum: &bounds::Frustum)
+            -> Vec<NodeId> {
+        profile_scope!("Scene::cull");
+        let world_bounds = &self.world_bounds;
+        let mut visible: Vec<NodeId> = self.bvh.query_parallel(jobs,
+                |b| frustum.intersects_aabb(b),
+                |_, id| world_bounds[*id].map_or(false, |b| frustum.intersects_aabb(&b)))
+                .into_iter().map(|proxy| *self.bvh.get(proxy).unwrap()).collect();
+        visible.sort();
+        visible
+    }
+
+    // Casts a ray against the world bounds of the meshes as of the last update() and returns the
+    // closest node hit along with the distance to its bounds. The direction should be normalized
+    // for the distance to be in world units.
+    pub fn raycast_bounds(&self, origin: Vector3D, dir: Vector3D, max_dist: GLfloat)
+            -> Option<(NodeId, GLfloat)> {
+        let inv_dir = Vector3D::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        self.bvh.raycast(origin, dir, max_dist, |_, id| {
+            self.nodes[*id].as_ref().unwrap().get_world_bounds()
+                    .and_then(|b| b.intersect_ray(origin, inv_dir, max_dist))
+        }).map(|(proxy, t)| (*self.bvh.get(proxy).unwrap(), t))
+    }
+
+    // Draws the mesh and level of detail group of every node that is visible from the active
+    // camera. Nothing is drawn if there is no active camera.
+    pub fn draw(&self, window: &mut GameWindow) {
+        let view_proj = match window.get_active_camera() {
+            Ok(camera) => camera.get_view_projection(),
+            Err(_) => { return; },
+        };
+        let visible = self.query_frustum(&bounds::Frustum::from_matrix(&view_proj));
+        self.draw_nodes(window, &visible);
+    }
+
+    // Like draw() but culls across the threads of a JobSystem.
+    pub fn draw_parallel(&self, window: &mut GameWindow, jobs: &JobSystem) {
+        let view_proj = match window.get_active_camera() {
+            Ok(camera) => camera.get_view_projection(),
+            Err(_) => { return; },
+        };
+        let visible = self.query_frustum_parallel(jobs, &bounds::Frustum::from_matrix(&view_proj));
+        self.draw_nodes(window, &visible);
+    }
+
+    // Like draw() but keeps the list of visible nodes in a FrameArena.
+    pub fn draw_in(&self, window: &mut GameWindow, arena: &FrameArena) {
+        let view_proj = match window.get_active_camera() {
+            Ok(camera) => camera.get_view_projection(),
+            Err(_) => { return; },
+        };
+        let visible = self.query_frustum_in(arena, &bounds::Frustum::from_matrix(&view_proj));
+        self.draw_nodes(window, &visible);
+    }
+
+    // Draws the mesh and level of detail group of some nodes.
+    fn draw_nodes(&self, window: &mut GameWindow, ids: &[NodeId]) {
+        profile_scope!("Scene::draw");
+        for &id in ids {
+            let node = self.nodes[id].as_ref().unwrap();
+            if let Some(ref instance) = node.mesh {
+                window.draw_instance(instance);
+            }
+            if let Some(ref group) = node.lod {
+                group.draw(window);
+            }
+        }
+    }
+}
@@ -0,0 +1,541 @@
+// Saves a Scene to a JSON file and loads it back. The file stores the hierarchy of nodes with
+// their transforms, the paths of the assets their meshes were loaded from, the parameters of their
+// cameras and lights, and any components that have been registered with a ComponentRegistry.
+//
+// Every file records the version of the format it was written with. Loading is forwards
+// compatible: fields that this version doesn't know about are ignored, components that aren't
+// registered are kept as raw JSON so they survive being saved again, and new fields are always
+// optional so that older files keep loading.
+//
+// Usage of scene serialization:
+// - Register the component types that should be saved with registry.register().
+// - Call save_scene(&scene, &window, &registry, path) to write a scene.
+// - Call load_scene(path, &mut window, &registry, &mut meshes) to read it back. Meshes are loaded
+//   through the MeshCache so that nodes sharing an asset share one ModelInfo. Cameras and lights
+//   are attached to the window and moved to their nodes.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::camera::{self, Projection};
+use gfx::color;
+use gfx::game_window::GameWindow;
+use gfx::layers;
+use gfx::light;
+use gfx::material;
+use gfx::model;
+use gfx::scene::{LightHandle, NodeId, Scene, Transform};
+use gfx::types::*;
+use std::any::Any;
+use std::collections::HashMap;
+use std::rc::Rc;
+use util::json::{self, Value};
+use util::{obj, rmod};
+
+// Identifies a file as a saved scene.
+pub const SCENE_FORMAT: &'static str = "mmo-scene";
+
+// The version of the format written by save_scene().
+pub const SCENE_VERSION: u32 = 1;
+
+// Converts a component to JSON, or returns None if it isn't the registered type.
+type SaveComponent = Box<Fn(&Any) -> Option<Value>>;
+
+// Creates a component from JSON.
+type LoadComponent = Box<Fn(&Value) -> Result<Box<Any>, String>>;
+
+// Maps the names of component types to functions that save and load them.
+pub struct ComponentRegistry {
+    types: HashMap<String, (SaveComponent, LoadComponent)>,
+}
+
+impl ComponentRegistry {
+    // Default constructor for an empty ComponentRegistry.
+    pub fn new() -> ComponentRegistry {
+        ComponentRegistry { types: HashMap::new() }
+    }
+
+    // Registers the components stored under a name as having the type T, along with functions to
+    // convert them to and from JSON.
+    pub fn register<T, S, L>(&mut self, name: &str, save: S, load: L)
+            where T: Any, S: Fn(&T) -> Value + 'static,
+            L: Fn(&Value) -> Result<T, String> + 'static {
+        let save_any: SaveComponent = Box::new(move |c: &Any| c.downcast_ref::<T>().map(&save));
+        let load_any: LoadComponent = Box::new(move |v: &Value| {
+            load(v).map(|c| Box::new(c) as Box<Any>)
+        });
+        self.types.insert(name.to_string(), (save_any, load_any));
+    }
+
+    // Returns true if a component name has been registered.
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.types.contains_key(name)
+    }
+
+    // Converts a component to JSON. Components that were kept as raw JSON when loaded are saved
+    // as they are. Returns None for components that can't be saved.
+    pub fn save(&self, name: &str, component: &Any) -> Option<Value> {
+        if let Some(&(ref save, _)) = self.types.get(name) {
+            if let Some(value) = save(component) {
+                return Some(value);
+            }
+        }
+        component.downcast_ref::<Value>().cloned()
+    }
+
+    // Creates a component from JSON, keeping the raw JSON if the name isn't registered.
+    fn load(&self, name: &str, value: &Value) -> Result<Box<Any>, String> {
+        match self.types.get(name) {
+            Some(&(_, ref load)) => load(value).map_err(|e| format!("Component {}: {}", name, e)),
+            None => Ok(Box::new(value.clone())),
+        }
+    }
+}
+
+// Loads meshes given their asset paths and shares them between the nodes that use them. RMOD
+// files bring their own material while OBJ files get a plain white one. Meshes with other formats
+// or custom materials can be added to the cache with insert() before loading a scene.
+pub struct MeshCache {
+    meshes: HashMap<String, Rc<model::ModelInfo>>,
+}
+
+impl MeshCache {
+    // Default constructor for an empty MeshCache.
+    pub fn new() -> MeshCache {
+        MeshCache { meshes: HashMap::new() }
+    }
+
+    // Adds a mesh under an asset path, replacing any mesh already there.
+    pub fn insert(&mut self, path: &str, info: Rc<model::ModelInfo>) {
+        self.meshes.insert(path.to_string(), info);
+    }
+
+    // Gets the mesh for an asset path, loading it if it isn't in the cache.
+    pub fn load(&mut self, path: &str) -> Result<Rc<model::ModelInfo>, String> {
+        if let Some(info) = self.meshes.get(path) {
+            return Ok(info.clone());
+        }
+        let info = if path.ends_with(".rmod") {
+            model::ModelInfo::from_rmod(&try!(rmod::decode_rmod(path)))
+        } else if path.ends_with(".obj") {
+            let mat = material::Material::from_textures(0, 0, None,
+                    color::Color::new_rgb(1.0, 1.0, 1.0), 10.0);
+            model::ModelInfo::from_obj(&try!(obj::decode_obj(path)), mat)
+        } else {
+            return Err(format!("Unsupported mesh format: {}", path));
+        };
+        let info = Rc::new(info);
+        self.meshes.insert(path.to_string(), info.clone());
+        Ok(info)
+    }
+}
+
+// Helpers to convert values to JSON.
+fn number(n: GLfloat) -> Value {
+    Value::Number(n as f64)
+}
+
+fn vec3(v: Vector3D) -> Value {
+    Value::Array(vec![number(v.x), number(v.y), number(v.z)])
+}
+
+fn rgba(c: &color::Color) -> Value {
+    Value::Array(vec![number(c.r), number(c.g), number(c.b), number(c.a)])
+}
+
+// Helpers to read values from a JSON object. Missing fields take a default value while fields
+// with the wrong type are an Err.
+fn get_f32(object: &Value, key: &str, default: GLfloat) -> Result<GLfloat, String> {
+    match object.get(key) {
+        None => Ok(default),
+        Some(v) => v.as_f64().map(|n| n as GLfloat).ok_or(format!("{} must be a number.", key)),
+    }
+}
+
+fn get_mask(object: &Value, key: &str, default: layers::LayerMask)
+        -> Result<layers::LayerMask, String> {
+    match object.get(key) {
+        None => Ok(default),
+        Some(v) => match v.as_f64() {
+            Some(n) if n >= 0.0 && n <= layers::ALL_LAYERS as f64 => Ok(n as layers::LayerMask),
+            _ => Err(format!("{} must be a layer mask.", key)),
+        },
+    }
+}
+
+fn get_floats(object: &Value, key: &str, default: &[GLfloat]) -> Result<Vec<GLfloat>, String> {
+    let items = match object.get(key) {
+        None => { return Ok(default.to_vec()); },
+        Some(v) => try!(v.as_array().ok_or(format!("{} must be an array.", key))),
+    };
+    let floats: Vec<GLfloat> = items.iter().filter_map(|i| i.as_f64()).map(|n| n as GLfloat)
+            .collect();
+    if floats.len() != default.len() || items.len() != default.len() {
+        return Err(format!("{} must have {} numbers.", key, default.len()));
+    }
+    Ok(floats)
+}
+
+fn get_vec3(object: &Value, key: &str, default: [GLfloat; 3]) -> Result<Vector3D, String> {
+    let v = try!(get_floats(object, key, &default));
+    Ok(Vector3D::new(v[0], v[1], v[2]))
+}
+
+fn get_color(object: &Value, key: &str) -> Result<color::Color, String> {
+    let c = try!(get_floats(object, key, &[1.0, 1.0, 1.0, 1.0]));
+    Ok(color::Color::new(c[0], c[1], c[2], c[3]))
+}
+
+// Converts the camera attached to a node to JSON. The position and orientation come from the node.
+fn save_camera(window: &GameWindow, handle: usize) -> Option<Value> {
+    let camera = match window.get_camera(handle) {
+        Ok(c) => c,
+        Err(_) => { return None; },
+    };
+    let mut value = Value::new_object();
+    match camera.projection {
+        Projection::Perspective { fov, aspect, near, far } => {
+            value.set("projection", Value::String("perspective".to_string()));
+            value.set("fov", number(fov));
+            value.set("aspect", number(aspect));
+            value.set("near", number(near));
+            value.set("far", number(far));
+        },
+        Projection::Orthographic { left, right, bottom, top, near, far } => {
+            value.set("projection", Value::String("orthographic".to_string()));
+            value.set("left", number(left));
+            value.set("right", number(right));
+            value.set("bottom", number(bottom));
+            value.set("top", number(top));
+            value.set("near", number(near));
+            value.set("far", number(far));
+        },
+    }
+    value.set("culling_mask", Value::Number(camera.culling_mask as f64));
+    value.set("active", Value::Bool(window.get_active_camera_handle() == Some(handle)));
+    Some(value)
+}
+
+// Converts the light attached to a node to JSON. The position and direction come from the node.
+fn save_light(window: &GameWindow, handle: LightHandle) -> Value {
+    let mut value = Value::new_object();
+    let attn = |value: &mut Value, c: GLfloat, l: GLfloat, q: GLfloat| {
+        value.set("const_attn", number(c));
+        value.set("linear_attn", number(l));
+        value.set("quad_attn", number(q));
+    };
+    let culling_mask = match handle {
+        LightHandle::Point(index) => window.get_point_light(index).culling_mask,
+        LightHandle::Directional(index) => window.get_directional_light(index).culling_mask,
+        LightHandle::Spot(index) => window.get_spot_light(index).culling_mask,
+    };
+    value.set("culling_mask", Value::Number(culling_mask as f64));
+    match handle {
+        LightHandle::Point(index) => {
+            let light = window.get_point_light(index);
+            value.set("type", Value::String("point".to_string()));
+            value.set("intensity", rgba(&light.intensity));
+            attn(&mut value, light.const_attn, light.linear_attn, light.quad_attn);
+        },
+        LightHandle::Directional(index) => {
+            let light = window.get_directional_light(index);
+            value.set("type", Value::String("directional".to_string()));
+            value.set("intensity", rgba(&light.intensity));
+        },
+        LightHandle::Spot(index) => {
+            let light = window.get_spot_light(index);
+            value.set("type", Value::String("spot".to_string()));
+            value.set("intensity", rgba(&light.intensity));
+            attn(&mut value, light.const_attn, light.linear_attn, light.quad_attn);
+            value.set("cutoff", number(light.cutoff));
+            value.set("dropoff", number(light.dropoff));
+        },
+    }
+    value
+}
+
+// Converts a transform to JSON with the rotation stored as [w, x, y, z].
+pub fn transform_to_json(t: &Transform) -> Value {
+    let mut value = Value::new_object();
+    value.set("pos", vec3(t.pos));
+    value.set("rot", Value::Array(vec![number(t.rot.s), number(t.rot.v.x), number(t.rot.v.y),
+            number(t.rot.v.z)]));
+    value.set("scale", vec3(t.scale));
+    value
+}
+
+// Converts a node and its descendants to JSON. Returns None if the node doesn't exist.
+pub fn node_to_json(scene: &Scene, id: NodeId, window: &GameWindow, registry: &ComponentRegistry)
+        -> Option<Value> {
+    scene.get_node(id).map(|_| save_node(scene, id, window, registry))
+}
+
+// Converts a node that exists and its descendants to JSON.
+fn save_node(scene: &Scene, id: NodeId, window: &GameWindow, registry: &ComponentRegistry)
+        -> Value {
+    let node = scene.get_node(id).unwrap();
+    let mut value = Value::new_object();
+    value.set("name", Value::String(node.name.clone()));
+    value.set("transform", transform_to_json(&node.transform));
+    if let (&Some(ref mesh), &Some(ref path)) = (&node.mesh, &node.mesh_path) {
+        value.set("mesh", Value::String(path.clone()));
+        value.set("layers", Value::Number(mesh.layers as f64));
+    }
+    if let Some(camera) = node.camera.and_then(|h| save_camera(window, h)) {
+        value.set("camera", camera);
+    }
+    if let Some(handle) = node.light {
+        value.set("light", save_light(window, handle));
+    }
+    let mut names: Vec<&String> = node.components.keys().collect();
+    names.sort();
+    let mut components = Value::new_object();
+    for name in names {
+        if let Some(component) = registry.save(name, &*node.components[name]) {
+            components.set(name, component);
+        }
+    }
+    if components.as_object().map_or(false, |c| !c.is_empty()) {
+        value.set("components", components);
+    }
+    let children: Vec<Value> = node.get_children().iter()
+            .map(|c| save_node(scene, *c, window, registry)).collect();
+    if !children.is_empty() {
+        value.set("children", Value::Array(children));
+    }
+    value
+}
+
+// Converts a scene to a JSON document.
+pub fn scene_to_json(scene: &Scene, window: &GameWindow, registry: &ComponentRegistry) -> Value {
+    let mut doc = Value::new_object();
+    doc.set("format", Value::String(SCENE_FORMAT.to_string()));
+    doc.set("version", Value::Number(SCENE_VERSION as f64));
+    doc.set("nodes", Value::Array(scene.get_roots().iter()
+            .map(|r| save_node(scene, *r, window, registry)).collect()));
+    doc
+}
+
+// Saves a scene to a JSON file. Meshes are saved as the asset paths in their nodes' mesh_path, so
+// meshes without a path are left out. Components that aren't registered are left out unless they
+// were kept as raw JSON when the scene was loaded.
+pub fn save_scene(scene: &Scene, window: &GameWindow, registry: &ComponentRegistry,
+        fpath: &str) -> Result<(), String> {
+    json::encode_json(fpath, &scene_to_json(scene, window, registry))
+}
+
+// Creates a camera from JSON. The aspect ratio is the default for the projection.
+fn parse_camera(value: &Value, aspect: GLfloat) -> Result<camera::GameCamera, String> {
+    let near = try!(get_f32(value, "near", 0.1));
+    let far = try!(get_f32(value, "far", 1000.0));
+    let projection = match value.get("projection").and_then(|p| p.as_str()) {
+        None | Some("perspective") => Projection::Perspective {
+                fov: try!(get_f32(value, "fov", 45.0)),
+                aspect: try!(get_f32(value, "aspect", aspect)), near: near, far: far },
+        Some("orthographic") => Projection::Orthographic {
+                left: try!(get_f32(value, "left", -aspect)),
+                right: try!(get_f32(value, "right", aspect)),
+                bottom: try!(get_f32(value, "bottom", -1.0)),
+                top: try!(get_f32(value, "top", 1.0)), near: near, far: far },
+        Some(other) => { return Err(format!("Unknown camera projection: {}", other)); },
+    };
+    let mut camera = camera::GameCamera::with_projection(Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(0.0, 0.0, -1.0), Vector3D::new(0.0, 1.0, 0.0), projection);
+    camera.culling_mask = try!(get_mask(value, "culling_mask", layers::ALL_LAYERS));
+    Ok(camera)
+}
+
+// Creates a camera from JSON and attaches it to the window.
+fn load_camera(value: &Value, window: &mut GameWindow) -> Result<usize, String> {
+    let camera = try!(parse_camera(value, window.get_aspect_ratio()));
+    let handle = window.attach_camera(camera);
+    if value.get("active").and_then(|a| a.as_bool()).unwrap_or(false) {
+        try!(window.set_active_camera(handle));
+    }
+    Ok(handle)
+}
+
+// A light created from JSON that hasn't been attached to a window yet.
+enum ParsedLight {
+    Point(light::PointLight),
+    Directional(light::DirectionalLight),
+    Spot(light::SpotLight),
+}
+
+// Creates a light from JSON. The position and direction are set when the scene is synced.
+fn parse_light(value: &Value) -> Result<ParsedLight, String> {
+    let origin = Vector3D::new(0.0, 0.0, 0.0);
+    let down = Vector3D::new(0.0, 0.0, -1.0);
+    let intensity = try!(get_color(value, "intensity"));
+    let const_attn = try!(get_f32(value, "const_attn", 1.0));
+    let linear_attn = try!(get_f32(value, "linear_attn", 0.0));
+    let quad_attn = try!(get_f32(value, "quad_attn", 0.0));
+    let culling_mask = try!(get_mask(value, "culling_mask", layers::ALL_LAYERS));
+    match value.get("type").and_then(|t| t.as_str()) {
+        Some("point") => {
+            let mut light = light::PointLight::new(intensity, origin, const_attn, linear_attn,
+                    quad_attn);
+            light.culling_mask = culling_mask;
+            Ok(ParsedLight::Point(light))
+        },
+        Some("directional") => {
+            let mut light = light::DirectionalLight::new(intensity, down);
+            light.culling_mask = culling_mask;
+            Ok(ParsedLight::Directional(light))
+        },
+        Some("spot") => {
+            let mut light = light::SpotLight::new(intensity, origin, down, const_attn,
+                    linear_attn, quad_attn, try!(get_f32(value, "cutoff", 0.9)),
+                    try!(get_f32(value, "dropoff", 0.8)));
+            light.culling_mask = culling_mask;
+            Ok(ParsedLight::Spot(light))
+        },
+        Some(other) => Err(format!("Unknown light type: {}", other)),
+        None => Err("Light is missing its type.".to_string()),
+    }
+}
+
+// Creates a light from JSON and attaches it to the window.
+fn load_light(value: &Value, window: &mut GameWindow) -> Result<LightHandle, String> {
+    Ok(match try!(parse_light(value)) {
+        ParsedLight::Point(light) => LightHandle::Point(window.attach_point_light(light)),
+        ParsedLight::Directional(light) => {
+            LightHandle::Directional(window.attach_directional_light(light))
+        },
+        ParsedLight::Spot(light) => LightHandle::Spot(window.attach_spot_light(light)),
+    })
+}
+
+// Checks that a node and its descendants can be loaded without adding anything to a scene or
+// attaching anything to a window. Meshes are still loaded into the cache. Loading a node that
+// passes this check only fails if the node it's loaded into doesn't exist, so callers check first
+// to avoid leaving half of a subtree behind.
+pub fn validate_node(value: &Value, registry: &ComponentRegistry, meshes: &mut MeshCache)
+        -> Result<(), String> {
+    if value.as_object().is_none() {
+        return Err("Nodes must be objects.".to_string());
+    }
+    if let Some(t) = value.get("transform") {
+        try!(get_floats(t, "rot", &[1.0, 0.0, 0.0, 0.0]));
+        try!(get_vec3(t, "pos", [0.0, 0.0, 0.0]));
+        try!(get_vec3(t, "scale", [1.0, 1.0, 1.0]));
+    }
+    if let Some(path) = value.get("mesh") {
+        let path = try!(path.as_str().ok_or("mesh must be a path.".to_string()));
+        try!(meshes.load(path));
+        try!(get_mask(value, "layers", layers::DEFAULT_LAYER));
+    }
+    if let Some(camera) = value.get("camera") {
+        try!(parse_camera(camera, 1.0));
+    }
+    if let Some(light) = value.get("light") {
+        try!(parse_light(light));
+    }
+    if let Some(components) = value.get("components").and_then(|c| c.as_object()) {
+        for &(ref name, ref component) in components {
+            try!(registry.load(name, component));
+        }
+    }
+    if let Some(children) = value.get("children") {
+        let children = try!(children.as_array().ok_or("children must be an array.".to_string()));
+        for child in children {
+            try!(validate_node(child, registry, meshes));
+        }
+    }
+    Ok(())
+}
+
+// Adds a node and its descendants from JSON to a scene.
+pub fn load_node(value: &Value, parent: Option<NodeId>, scene: &mut Scene,
+        window: &mut GameWindow, registry: &ComponentRegistry, meshes: &mut MeshCache)
+        -> Result<NodeId, String> {
+    if value.as_object().is_none() {
+        return Err("Nodes must be objects.".to_string());
+    }
+    let name = value.get("name").and_then(|n| n.as_str()).unwrap_or("");
+    let id = try!(scene.add_node(name, parent));
+    try!(load_node_into(value, id, scene, window, registry, meshes));
+    Ok(id)
+}
+
+// Sets the transform, mesh, camera, light, and components of an existing node from JSON and adds
+// the children in the JSON after any children the node already has. The name is left as is.
+pub fn load_node_into(value: &Value, id: NodeId, scene: &mut Scene, window: &mut GameWindow,
+        registry: &ComponentRegistry, meshes: &mut MeshCache) -> Result<(), String> {
+    if scene.get_node(id).is_none() {
+        return Err(format!("Node {} does not exist.", id));
+    }
+    if let Some(t) = value.get("transform") {
+        let rot = try!(get_floats(t, "rot", &[1.0, 0.0, 0.0, 0.0]));
+        scene.get_node_mut(id).unwrap().transform = Transform::new(
+                try!(get_vec3(t, "pos", [0.0, 0.0, 0.0])),
+                Quaternion::new(rot[0], rot[1], rot[2], rot[3]),
+                try!(get_vec3(t, "scale", [1.0, 1.0, 1.0])));
+    }
+    if let Some(path) = value.get("mesh") {
+        let path = try!(path.as_str().ok_or("mesh must be a path.".to_string()));
+        let info = try!(meshes.load(path));
+        let mut instance = model::ModelInstance::from(info);
+        instance.layers = try!(get_mask(value, "layers", layers::DEFAULT_LAYER));
+        let node = scene.get_node_mut(id).unwrap();
+        node.mesh = Some(instance);
+        node.mesh_path = Some(path.to_string());
+    }
+    if let Some(camera) = value.get("camera") {
+        scene.get_node_mut(id).unwrap().camera = Some(try!(load_camera(camera, window)));
+    }
+    if let Some(light) = value.get("light") {
+        scene.get_node_mut(id).unwrap().light = Some(try!(load_light(light, window)));
+    }
+    if let Some(components) = value.get("components").and_then(|c| c.as_object()) {
+        for &(ref name, ref component) in components {
+            let component = try!(registry.load(name, component));
+            scene.get_node_mut(id).unwrap().components.insert(name.clone(), component);
+        }
+    }
+    if let Some(children) = value.get("children") {
+        let children = try!(children.as_array().ok_or("children must be an array.".to_string()));
+        for child in children {
+            try!(load_node(child, Some(id), scene, window, registry, meshes));
+        }
+    }
+    Ok(())
+}
+
+// Creates a scene from a JSON document. Cameras and lights in the document are attached to the
+// window, and the scene is updated and synced so that everything starts in place.
+pub fn scene_from_json(doc: &Value, window: &mut GameWindow, registry: &ComponentRegistry,
+        meshes: &mut MeshCache) -> Result<Scene, String> {
+    if doc.get("format").and_then(|f| f.as_str()) != Some(SCENE_FORMAT) {
+        return Err("Not a scene file.".to_string());
+    }
+    match doc.get("version").and_then(|v| v.as_f64()) {
+        Some(v) if v >= 1.0 => {},
+        _ => { return Err("Scene file has an invalid version.".to_string()); },
+    }
+    let nodes: &[Value] = match doc.get("nodes") {
+        Some(nodes) => try!(nodes.as_array().ok_or("nodes must be an array.".to_string())),
+        None => &[],
+    };
+    // The whole document is checked first so that an error partway through doesn't leave the
+    // cameras and lights of the nodes before it attached to the window.
+    for node in nodes {
+        try!(validate_node(node, registry, meshes));
+    }
+    let mut scene = Scene::new();
+    for node in nodes {
+        try!(load_node(node, None, &mut scene, window, registry, meshes));
+    }
+    scene.update();
+    scene.sync(window);
+    Ok(scene)
+}
+
+// Loads a scene from a JSON file written by save_scene().
+pub fn load_scene(fpath: &str, window: &mut GameWindow, registry: &ComponentRegistry,
+        meshes: &mut MeshCache) -> Result<Scene, String> {
+    let doc = try!(json::decode_json(fpath));
+    scene_from_json(&doc, window, registry, meshes)
+}
@@ -0,0 +1,91 @@
+// Defines a Skin, which binds the vertices of a ModelInfo to the joints of a Skeleton. Every vertex
+// is influenced by up to four joints with weights that add up to 1.0. The joint indices and weights
+// are kept in their own GPU buffer since the engine's shared vertex buffers only hold the static
+// vertex attributes, and GameWindow::draw_skinned() points the skinning attributes at it for the
+// duration of the draw. The vertex shader then moves each vertex by the weighted sum of the
+// skinning matrices of its joints.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::types::*;
+use std::mem;
+
+// The most joints that a single draw can be skinned with. This matches the size of the
+// joint_matrices array in the vertex shader.
+pub const MAX_JOINTS: usize = 64;
+
+// The number of joints that can influence a single vertex.
+pub const JOINTS_PER_VERTEX: usize = 4;
+
+// Contents of a single vertex in the skin buffer.
+// [J_0  J_1  J_2  J_3  W_0  W_1  W_2  W_3]
+pub const SKIN_VERTEX_SIZE: usize = JOINTS_PER_VERTEX * 2;
+
+// The joint influences of every vertex of a mesh. This can only be created after the window
+// context is set up.
+pub struct Skin {
+    pub joints: Vec<[u16; JOINTS_PER_VERTEX]>,
+    pub weights: Vec<[GLfloat; JOINTS_PER_VERTEX]>,
+    buffer: GLuint,
+}
+
+impl Skin {
+    // Creates a Skin from the joint indices and weights of every vertex in the same order as the
+    // mesh's vertices, and uploads it. Weights are normalized so that they add up to 1.0. Returns
+    // an Err if the lengths differ or a joint index is past MAX_JOINTS.
+    pub fn new(joints: Vec<[u16; JOINTS_PER_VERTEX]>, weights: Vec<[GLfloat; JOINTS_PER_VERTEX]>)
+            -> Result<Skin, String> {
+        if joints.len() != weights.len() {
+            return Err("A Skin needs weights for every vertex.".to_string());
+        }
+        if joints.iter().any(|j| j.iter().any(|i| *i as usize >= MAX_JOINTS)) {
+            return Err(format!("A Skin can only use the first {} joints.", MAX_JOINTS));
+        }
+        let weights: Vec<[GLfloat; JOINTS_PER_VERTEX]> = weights.into_iter().map(|w| {
+            let total = w.iter().fold(0.0, |t, x| t + x);
+            if total > 0.0 { [w[0] / total, w[1] / total, w[2] / total, w[3] / total] } else {
+                [1.0, 0.0, 0.0, 0.0]
+            }
+        }).collect();
+
+        let mut packed: Vec<GLfloat> = Vec::with_capacity(joints.len() * SKIN_VERTEX_SIZE);
+        for (j, w) in joints.iter().zip(weights.iter()) {
+            packed.extend(j.iter().map(|i| *i as GLfloat));
+            packed.extend(w.iter().cloned());
+        }
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer);
+            if !packed.is_empty() {
+                gl::BufferData(
+                        gl::ARRAY_BUFFER, float_size!(packed.len(), GLsizeiptr),
+                        vec_to_addr!(packed), gl::STATIC_DRAW);
+            }
+        }
+        Ok(Skin { joints: joints, weights: weights, buffer: buffer })
+    }
+
+    // Gets the number of vertices in the skin.
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    // Returns true if the skin has no vertices.
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    // Gets the ID of the skin buffer.
+    pub fn get_buffer(&self) -> GLuint {
+        self.buffer
+    }
+
+    // Deletes the skin buffer from the GPU.
+    pub fn delete(self) { unsafe {
+        gl::DeleteBuffers(1, &self.buffer);
+    }}
+}
@@ -0,0 +1,61 @@
+// Defines a Skybox which is a cubemap drawn behind everything else in the scene. A Skybox can be
+// created from six cubemap face images or from an equirectangular HDR which is resampled into a
+// cubemap on load. Once created, it can be attached to the GameWindow with set_skybox() and drawn
+// with draw_skybox() after the opaque instances.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::ibl;
+use gfx::types::*;
+use util::bmp;
+
+// Default size of each face when converting an equirectangular environment into a cubemap.
+pub const DEFAULT_SKYBOX_SIZE: usize = 512;
+
+// A cubemap texture along with how it is displayed. The exposure scales the sky's colors, which
+// is mostly useful for HDR skies. This can only be created after the window context is set up.
+pub struct Skybox {
+    pub texture: GLuint,
+    pub size: usize,
+    pub exposure: GLfloat,
+}
+
+impl Skybox {
+    // Creates a Skybox from a cubemap with linear colors.
+    pub fn from_cubemap(cube: &ibl::CubeMap) -> Skybox {
+        Skybox { texture: ibl::upload_cubemap(cube), size: cube.size, exposure: 1.0 }
+    }
+
+    // Creates a Skybox by resampling an Environment into a cubemap with faces of a given size.
+    pub fn from_environment(env: &ibl::Environment, size: usize) -> Skybox {
+        let cube = match env {
+            &ibl::Environment::CubeMap(ref cube) => return Skybox::from_cubemap(cube),
+            &ibl::Environment::Equirectangular(_) => ibl::CubeMap::from_fn(size, |d| env.sample(d)),
+        };
+        Skybox::from_cubemap(&cube)
+    }
+
+    // Loads a Skybox from an equirectangular Radiance HDR file.
+    pub fn from_hdr(fpath: &str) -> Result<Skybox, String> {
+        let env = try!(ibl::Environment::from_hdr(fpath));
+        Ok(Skybox::from_environment(&env, DEFAULT_SKYBOX_SIZE))
+    }
+
+    // Loads a Skybox from six square BMP images in the OpenGL face order (+X, -X, +Y, -Y, +Z, -Z).
+    pub fn from_bmps(fpaths: &[&str]) -> Result<Skybox, String> {
+        let mut faces = Vec::new();
+        for fpath in fpaths {
+            faces.push(try!(bmp::decode_bmp(fpath)).image);
+        }
+        let cube = try!(ibl::CubeMap::from_images(&faces));
+        Ok(Skybox::from_cubemap(&cube))
+    }
+
+    // Deletes the cubemap texture from the GPU.
+    pub fn delete(self) {
+        unsafe { gl::DeleteTextures(1, &self.texture); }
+    }
+}
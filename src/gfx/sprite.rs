@@ -0,0 +1,366 @@
+// Defines a SpriteBatch for drawing textured 2D quads on top of the scene for HUDs and 2D games.
+// Sprites reference a TextureRegion, which is either a whole texture or a rectangle of a larger
+// atlas texture, so that many different images can share a texture and be drawn together. Sprites
+// are sorted by their z order and grouped by texture so that each run of sprites sharing a texture
+// is drawn with a single draw call.
+//
+// A batch can also draw Billboards, which are quads in the world such as particles, by setting its
+// view to a camera's view projection. Billboards can be depth tested against the scene and faded
+// out where they meet it with soft depth, which hides the hard line where a quad cuts into a wall.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::Matrix;
+use gfx::camera::GameCamera;
+use gfx::color;
+use gfx::game_window::GameWindow;
+use gfx::types::*;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use util::shader;
+
+// The default shader directory and names. Sprites share the vertex layout of text.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "text.vert";
+const FRAGMENT_SHADER_NAME: &'static str = "sprite.frag";
+
+// Contents of a single vertex in the sprite vertex buffer.
+// [P_x  P_y  P_z  T_u  T_v  C_r  C_g  C_b  C_a]
+const SPRITE_POS_SIZE: usize = 3;
+const SPRITE_TCOORD_SIZE: usize = 2;
+const SPRITE_COLOR_SIZE: usize = 4;
+const SPRITE_VERTEX_SIZE: usize = SPRITE_POS_SIZE + SPRITE_TCOORD_SIZE + SPRITE_COLOR_SIZE;
+
+// Number of vertices for the two triangles of a sprite.
+const SPRITE_VERTICES: usize = 6;
+
+// A rectangle of a texture given by its texture coordinates.
+#[derive(Copy, Clone, Debug)]
+pub struct TextureRegion {
+    pub texture: GLuint,
+    pub uv: [GLfloat; 4],    // [u_min, v_min, u_max, v_max]
+}
+
+impl TextureRegion {
+    // Creates a region covering a whole texture.
+    pub fn new(texture: GLuint) -> TextureRegion {
+        TextureRegion { texture: texture, uv: [0.0, 0.0, 1.0, 1.0] }
+    }
+
+    // Creates a region from a rectangle in pixels of a texture of a given size, measured from the
+    // top left of the image.
+    pub fn from_pixels(texture: GLuint, texture_width: u32, texture_height: u32, x: u32, y: u32,
+            width: u32, height: u32) -> TextureRegion {
+        let (tw, th) = (texture_width as GLfloat, texture_height as GLfloat);
+        TextureRegion { texture: texture, uv: [x as GLfloat / tw, y as GLfloat / th,
+                (x + width) as GLfloat / tw, (y + height) as GLfloat / th] }
+    }
+
+    // Splits a texture into a grid of equally sized regions, row by row from the top left. This is
+    // useful for sprite sheets and animation strips.
+    pub fn grid(texture: GLuint, columns: u32, rows: u32) -> Vec<TextureRegion> {
+        let mut regions = Vec::with_capacity((columns * rows) as usize);
+        for row in 0..rows {
+            for column in 0..columns {
+                regions.push(TextureRegion { texture: texture, uv: [
+                        column as GLfloat / columns as GLfloat, row as GLfloat / rows as GLfloat,
+                        (column + 1) as GLfloat / columns as GLfloat,
+                        (row + 1) as GLfloat / rows as GLfloat] });
+            }
+        }
+        regions
+    }
+}
+
+// A single sprite. The position is where the pivot ends up and the pivot is given as a fraction of
+// the size from the top left, so the default pivot of (0.5, 0.5) centers the sprite on its
+// position. Sprites rotate clockwise around the pivot by an angle in radians, and sprites with a
+// higher z are drawn over sprites with a lower z.
+pub struct Sprite {
+    pub region: TextureRegion,
+    pub x: GLfloat,
+    pub y: GLfloat,
+    pub width: GLfloat,
+    pub height: GLfloat,
+    pub rotation: GLfloat,
+    pub pivot: (GLfloat, GLfloat),
+    pub tint: color::Color,
+    pub z: i32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Sprite {
+    // Creates an untinted and unrotated sprite centered on a position.
+    pub fn new(region: TextureRegion, x: GLfloat, y: GLfloat, width: GLfloat, height: GLfloat)
+            -> Sprite {
+        Sprite { region: region, x: x, y: y, width: width, height: height, rotation: 0.0,
+                pivot: (0.5, 0.5), tint: color::Color::new_rgb(1.0, 1.0, 1.0), z: 0,
+                flip_x: false, flip_y: false }
+    }
+}
+
+// A quad in the world centered on its position. It lies in the plane of the two axes that it's
+// drawn with, such as the right and up axes of a camera to face it, and rotates clockwise in that
+// plane by an angle in radians.
+pub struct Billboard {
+    pub region: TextureRegion,
+    pub pos: Vector3D,
+    pub width: GLfloat,
+    pub height: GLfloat,
+    pub rotation: GLfloat,
+    pub tint: color::Color,
+    pub z: i32,
+}
+
+impl Billboard {
+    // Creates an untinted and unrotated billboard at a position.
+    pub fn new(region: TextureRegion, pos: Vector3D, width: GLfloat, height: GLfloat)
+            -> Billboard {
+        Billboard { region: region, pos: pos, width: width, height: height, rotation: 0.0,
+                tint: color::Color::new_rgb(1.0, 1.0, 1.0), z: 0 }
+    }
+}
+
+// The depth of the scene that billboards fade out against. The texture must be a copy of the depth
+// of the scene (see RenderTarget::copy_depth()) with the size of the viewport, since the depth
+// buffer that is being drawn to can't be read. Billboards fade out over the distance in front of
+// the scene.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SoftDepth {
+    pub texture: GLuint,
+    pub distance: GLfloat,
+    pub near: GLfloat,
+    pub far: GLfloat,
+    pub orthographic: bool,
+}
+
+impl SoftDepth {
+    // Creates soft depth from a copy of the scene depth as seen from a camera.
+    pub fn from_camera(texture: GLuint, camera: &GameCamera, distance: GLfloat) -> SoftDepth {
+        let (near, far) = camera.projection.get_near_far();
+        SoftDepth { texture: texture, distance: distance, near: near, far: far,
+                orthographic: camera.is_orthographic() }
+    }
+}
+
+// Batches sprites for a frame. If the view is None, sprites are positioned in pixels from the top
+// left of the current viewport. Otherwise, the view is used as the full transform from sprite
+// coordinates to clip space, which allows for a scrolling or zooming 2D camera or for billboards in
+// the world. Sprites are tested against the depth buffer without writing to it if depth test is
+// set. This can only be created after the window context is set up.
+pub struct SpriteBatch {
+    pub view: Option<cgmath::Matrix4<GLfloat>>,
+    pub depth_test: bool,
+    pub soft_depth: Option<SoftDepth>,
+    sprites: Vec<(i32, GLuint, [GLfloat; SPRITE_VERTEX_SIZE * SPRITE_VERTICES])>,
+    vertices: Vec<GLfloat>,                 // Kept between flushes to reuse their memory.
+    batches: Vec<(GLuint, usize, usize)>,   // (texture, first_vertex, vertex_count)
+    program: GLuint,
+    vao: GLuint,
+    vbo: GLuint,
+    capacity: usize,
+    draw_calls: usize,
+}
+
+impl SpriteBatch {
+    // Creates an empty SpriteBatch along with its program and vertex buffer.
+    pub fn new() -> SpriteBatch { unsafe {
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+        let mut vao = 0;
+        let mut vbo = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let pos_attr = gl::GetAttribLocation(program, gl_str!("position"));
+        gl::EnableVertexAttribArray(pos_attr as GLuint);
+        gl::VertexAttribPointer(
+                pos_attr as GLuint, SPRITE_POS_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(SPRITE_VERTEX_SIZE, GLsizei), ptr::null());
+        let tcoord_attr = gl::GetAttribLocation(program, gl_str!("tcoord"));
+        gl::EnableVertexAttribArray(tcoord_attr as GLuint);
+        gl::VertexAttribPointer(
+                tcoord_attr as GLuint, SPRITE_TCOORD_SIZE as i32, gl::FLOAT,
+                gl::FALSE as GLboolean, float_size!(SPRITE_VERTEX_SIZE, GLsizei),
+                float_size!(SPRITE_POS_SIZE, CVoid));
+        let color_attr = gl::GetAttribLocation(program, gl_str!("color"));
+        gl::EnableVertexAttribArray(color_attr as GLuint);
+        gl::VertexAttribPointer(
+                color_attr as GLuint, SPRITE_COLOR_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(SPRITE_VERTEX_SIZE, GLsizei),
+                float_size!(SPRITE_POS_SIZE + SPRITE_TCOORD_SIZE, CVoid));
+        gl::BindVertexArray(0);
+        SpriteBatch { view: None, depth_test: false, soft_depth: None, sprites: Vec::new(),
+                vertices: Vec::new(), batches: Vec::new(), program: program, vao: vao, vbo: vbo,
+                capacity: 0, draw_calls: 0 }
+    }}
+
+    // Queues a sprite to be drawn on the next flush.
+    pub fn draw(&mut self, sprite: &Sprite) {
+        let (sin, cos) = sprite.rotation.sin_cos();
+        let (px, py) = (sprite.pivot.0 * sprite.width, sprite.pivot.1 * sprite.height);
+        let (mut u0, mut v0, mut u1, mut v1) = (sprite.region.uv[0], sprite.region.uv[1],
+                sprite.region.uv[2], sprite.region.uv[3]);
+        if sprite.flip_x { let u = u0; u0 = u1; u1 = u; }
+        if sprite.flip_y { let v = v0; v0 = v1; v1 = v; }
+        let tint = &sprite.tint;
+
+        // Corners are in order top left, top right, bottom right, and bottom left.
+        let corners = [(0.0, 0.0, u0, v0), (sprite.width, 0.0, u1, v0),
+                (sprite.width, sprite.height, u1, v1), (0.0, sprite.height, u0, v1)];
+        let mut vertices = [0.0; SPRITE_VERTEX_SIZE * SPRITE_VERTICES];
+        for (n, &i) in [0, 3, 2, 0, 2, 1].iter().enumerate() {
+            let (x, y, u, v) = corners[i];
+            let (x, y) = (x - px, y - py);
+            let vertex = [sprite.x + x * cos - y * sin, sprite.y + x * sin + y * cos, 0.0, u, v,
+                    tint.r, tint.g, tint.b, tint.a];
+            vertices[(n * SPRITE_VERTEX_SIZE)..((n + 1) * SPRITE_VERTEX_SIZE)]
+                    .copy_from_slice(&vertex);
+        }
+        self.sprites.push((sprite.z, sprite.region.texture, vertices));
+    }
+
+    // Queues a billboard to be drawn on the next flush in the plane of a right and an up axis.
+    // Billboards with the same z and texture are drawn in the order they are queued, so they
+    // should be queued from back to front.
+    pub fn draw_billboard(&mut self, billboard: &Billboard, right: Vector3D, up: Vector3D) {
+        let (sin, cos) = billboard.rotation.sin_cos();
+        let (right, up) = ((right * cos - up * sin) * (billboard.width * 0.5),
+                (up * cos + right * sin) * (billboard.height * 0.5));
+        let uv = billboard.region.uv;
+        let tint = &billboard.tint;
+
+        // Corners are in order top left, top right, bottom right, and bottom left.
+        let corners = [(-right + up, uv[0], uv[1]), (right + up, uv[2], uv[1]),
+                (right - up, uv[2], uv[3]), (-right - up, uv[0], uv[3])];
+        let mut vertices = [0.0; SPRITE_VERTEX_SIZE * SPRITE_VERTICES];
+        for (n, &i) in [0, 3, 2, 0, 2, 1].iter().enumerate() {
+            let (offset, u, v) = corners[i];
+            let p = billboard.pos + offset;
+            let vertex = [p.x, p.y, p.z, u, v, tint.r, tint.g, tint.b, tint.a];
+            vertices[(n * SPRITE_VERTEX_SIZE)..((n + 1) * SPRITE_VERTEX_SIZE)]
+                    .copy_from_slice(&vertex);
+        }
+        self.sprites.push((billboard.z, billboard.region.texture, vertices));
+    }
+
+    // Gets the number of sprites waiting to be drawn.
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    // Returns true if no sprites are waiting to be drawn.
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    // Gets the number of draw calls issued by the last flush.
+    pub fn get_draw_calls(&self) -> usize {
+        self.draw_calls
+    }
+
+    // Removes every sprite without drawing it.
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    // Draws the queued sprites over the window's current render target and clears the queue.
+    // Sprites with the same z are grouped by texture, so their relative order is not preserved.
+    pub fn flush(&mut self, window: &mut GameWindow) {
+        self.draw_calls = 0;
+        if self.sprites.is_empty() { return; }
+        self.sprites.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        let mut vertices = mem::replace(&mut self.vertices, Vec::new());
+        let mut batches = mem::replace(&mut self.batches, Vec::new());
+        vertices.clear();
+        batches.clear();
+        for &(_, texture, ref sprite) in &self.sprites {
+            let extend = match batches.last_mut() {
+                Some(batch) if batch.0 == texture => { batch.2 += SPRITE_VERTICES; true },
+                _ => false,
+            };
+            if !extend {
+                batches.push((texture, vertices.len() / SPRITE_VERTEX_SIZE, SPRITE_VERTICES));
+            }
+            vertices.extend_from_slice(sprite);
+        }
+        self.sprites.clear();
+
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            let count = vertices.len() / SPRITE_VERTEX_SIZE;
+            if count > self.capacity {
+                self.capacity = count;
+                gl::BufferData(
+                        gl::ARRAY_BUFFER, float_size!(vertices.len(), GLsizeiptr),
+                        vec_to_addr!(vertices), gl::STREAM_DRAW);
+            } else {
+                gl::BufferSubData(
+                        gl::ARRAY_BUFFER, 0, float_size!(vertices.len(), GLsizeiptr),
+                        vec_to_addr!(vertices));
+            }
+
+            let mut viewport: [GLint; 4] = [0; 4];
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            let (width, height) = (viewport[2] as GLfloat, viewport[3] as GLfloat);
+            let view = match self.view {
+                Some(view) => view,
+                None => cgmath::ortho(0.0, width, height, 0.0, -1.0, 1.0),
+            };
+            gl::UseProgram(self.program);
+            uniform_mat4!(self.program, "view_proj", view);
+            uniform_float!(self.program, "gamma", window.get_gamma());
+            uniform_int!(self.program, "hdr_output", window.is_hdr_output() as GLint);
+            match self.soft_depth {
+                Some(soft) => {
+                    gl::ActiveTexture(gl::TEXTURE1);
+                    gl::BindTexture(gl::TEXTURE_2D, soft.texture);
+                    uniform_int!(self.program, "depth_map", 1);
+                    uniform_vec2!(self.program, "viewport_size", vec![width, height]);
+                    uniform_float!(self.program, "near", soft.near);
+                    uniform_float!(self.program, "far", soft.far);
+                    uniform_int!(self.program, "orthographic", soft.orthographic as GLint);
+                    uniform_float!(self.program, "soft_distance", soft.distance);
+                    uniform_int!(self.program, "use_soft_depth", 1); },
+                None => { uniform_int!(self.program, "use_soft_depth", 0); },
+            };
+            gl::ActiveTexture(gl::TEXTURE0);
+            uniform_int!(self.program, "sprite", 0);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            if self.depth_test {
+                gl::DepthMask(gl::FALSE);
+            } else {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+            for &(texture, first, count) in &batches {
+                gl::BindTexture(gl::TEXTURE_2D, texture);
+                gl::DrawArrays(gl::TRIANGLES, first as GLint, count as GLsizei);
+            }
+            gl::DepthMask(gl::TRUE);
+            gl::Enable(gl::DEPTH_TEST);
+            gl::Disable(gl::BLEND);
+        }
+        self.draw_calls = batches.len();
+        for &(_, _, count) in &batches {
+            window.record_draw(count / SPRITE_VERTICES, count / 3);
+        }
+        self.vertices = vertices;
+        self.batches = batches;
+        window.restore_state();
+    }
+
+    // Deletes the program and vertex buffer from the GPU.
+    pub fn delete(self) { unsafe {
+        gl::DeleteProgram(self.program);
+        gl::DeleteBuffers(1, &self.vbo);
+        gl::DeleteVertexArrays(1, &self.vao);
+    }}
+}
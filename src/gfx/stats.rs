@@ -0,0 +1,228 @@
+// Defines a FrameProfiler for measuring where the time of a frame goes on the GPU along with how
+// much work was submitted. GPU time is measured with timestamp queries around the whole frame and
+// around named passes, which can be nested. Query results are only read back a few frames later so
+// that the CPU never waits on the GPU, which means the FrameStats always describe a frame slightly
+// in the past.
+//
+// Usage of a FrameProfiler:
+// - profiler.begin_frame(&mut window) at the start of the frame.
+// - profiler.begin_pass("shadows") and profiler.end_pass() around each pass of interest.
+// - profiler.end_frame(&mut window) after the last draw and before swapping buffers.
+// - profiler.get_stats() to inspect the most recently completed frame.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+extern crate time;
+
+use gfx::game_window::GameWindow;
+use gfx::types::*;
+
+// Number of frames that can be in flight before their query results are read back.
+const FRAME_LATENCY: usize = 3;
+
+// Counts of the work submitted through the GameWindow and the batchers built on top of it. These
+// are accumulated by the GameWindow and reset at the start of every profiled frame.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderCounters {
+    pub draw_calls: usize,
+    pub instances: usize,
+    pub triangles: usize,
+}
+
+// The GPU time of a named pass in milliseconds. The depth is how many passes it is nested in, and
+// the start is how long after the start of the frame on the GPU the pass started.
+#[derive(Clone, Debug)]
+pub struct PassTime {
+    pub name: String,
+    pub depth: usize,
+    pub gpu_start: f64,
+    pub gpu_time: f64,
+}
+
+// Statistics for a single completed frame. Times are in milliseconds. The primitive count comes
+// from the GPU and includes everything drawn in the frame, such as full screen passes, while the
+// counters only include draws made through the engine's renderers.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub frame: u64,
+    pub cpu_time: f64,
+    pub gpu_time: f64,
+    pub passes: Vec<PassTime>,
+    pub counters: RenderCounters,
+    pub primitives: u64,
+}
+
+// The queries and measurements of a frame that has been submitted but not read back yet.
+struct PendingFrame {
+    frame: u64,
+    in_flight: bool,
+    cpu_time: f64,
+    counters: RenderCounters,
+    frame_queries: (GLuint, GLuint),
+    primitives_query: GLuint,
+    passes: Vec<(String, usize, GLuint, GLuint)>,    // (name, depth, begin, end)
+    pool: Vec<GLuint>,
+    pool_used: usize,
+}
+
+impl PendingFrame {
+    // Creates a frame with its whole-frame queries.
+    fn new() -> PendingFrame {
+        let mut queries = [0; 3];
+        unsafe { gl::GenQueries(3, queries.as_mut_ptr()); }
+        PendingFrame { frame: 0, in_flight: false, cpu_time: 0.0,
+                counters: RenderCounters::default(), frame_queries: (queries[0], queries[1]),
+                primitives_query: queries[2], passes: Vec::new(), pool: Vec::new(),
+                pool_used: 0 }
+    }
+
+    // Gets an unused timestamp query from the frame's pool, creating one if needed.
+    fn next_query(&mut self) -> GLuint {
+        if self.pool_used == self.pool.len() {
+            let mut query = 0;
+            unsafe { gl::GenQueries(1, &mut query); }
+            self.pool.push(query);
+        }
+        self.pool_used += 1;
+        self.pool[self.pool_used - 1]
+    }
+
+    // Deletes every query of the frame from the GPU.
+    fn delete(&self) { unsafe {
+        let queries = [self.frame_queries.0, self.frame_queries.1, self.primitives_query];
+        gl::DeleteQueries(3, queries.as_ptr());
+        gl::DeleteQueries(self.pool.len() as GLsizei, self.pool.as_ptr());
+    }}
+}
+
+// Reads back the result of a timestamp query in nanoseconds, waiting for it if needed.
+unsafe fn get_query_result(query: GLuint) -> u64 {
+    let mut result = 0;
+    gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut result);
+    result
+}
+
+// Converts the difference of two timestamps in nanoseconds to milliseconds.
+fn elapsed_ms(begin: u64, end: u64) -> f64 {
+    if end > begin { (end - begin) as f64 / 1000000.0 } else { 0.0 }
+}
+
+// Measures GPU timings and submission counts of frames. This can only be created after the window
+// context is set up.
+pub struct FrameProfiler {
+    frames: Vec<PendingFrame>,
+    current: usize,
+    frame: u64,
+    frame_start: f64,
+    pass_stack: Vec<usize>,
+    in_frame: bool,
+    stats: FrameStats,
+}
+
+impl FrameProfiler {
+    // Creates a profiler with no completed frames.
+    pub fn new() -> FrameProfiler {
+        FrameProfiler { frames: (0..FRAME_LATENCY).map(|_| PendingFrame::new()).collect(),
+                current: 0, frame: 0, frame_start: 0.0, pass_stack: Vec::new(), in_frame: false,
+                stats: FrameStats::default() }
+    }
+
+    // Starts measuring a frame and resets the window's counters. If the queries of the frame that
+    // used the same slot are still in flight, their results are read back first.
+    pub fn begin_frame(&mut self, window: &mut GameWindow) {
+        if self.in_frame { return; }
+        self.collect(self.current);
+        let pending = &mut self.frames[self.current];
+        pending.frame = self.frame;
+        pending.passes.clear();
+        pending.pool_used = 0;
+        window.reset_counters();
+        self.frame_start = time::precise_time_s();
+        self.pass_stack.clear();
+        self.in_frame = true;
+        unsafe {
+            gl::QueryCounter(pending.frame_queries.0, gl::TIMESTAMP);
+            gl::BeginQuery(gl::PRIMITIVES_GENERATED, pending.primitives_query);
+        }
+    }
+
+    // Starts timing a named pass. Passes can be nested by beginning a pass inside another one.
+    pub fn begin_pass(&mut self, name: &str) {
+        if !self.in_frame { return; }
+        let pending = &mut self.frames[self.current];
+        let (begin, end) = (pending.next_query(), pending.next_query());
+        unsafe { gl::QueryCounter(begin, gl::TIMESTAMP); }
+        self.pass_stack.push(pending.passes.len());
+        pending.passes.push((name.to_string(), self.pass_stack.len() - 1, begin, end));
+    }
+
+    // Stops timing the most recently begun pass.
+    pub fn end_pass(&mut self) {
+        if let Some(index) = self.pass_stack.pop() {
+            let end = self.frames[self.current].passes[index].3;
+            unsafe { gl::QueryCounter(end, gl::TIMESTAMP); }
+        }
+    }
+
+    // Stops measuring the frame and records the window's counters. Any passes that are still open
+    // are ended first.
+    pub fn end_frame(&mut self, window: &mut GameWindow) {
+        if !self.in_frame { return; }
+        while !self.pass_stack.is_empty() {
+            self.end_pass();
+        }
+        let pending = &mut self.frames[self.current];
+        unsafe {
+            gl::EndQuery(gl::PRIMITIVES_GENERATED);
+            gl::QueryCounter(pending.frame_queries.1, gl::TIMESTAMP);
+        }
+        pending.cpu_time = (time::precise_time_s() - self.frame_start) * 1000.0;
+        pending.counters = window.get_counters();
+        pending.in_flight = true;
+        self.in_frame = false;
+        self.frame += 1;
+        self.current = (self.current + 1) % FRAME_LATENCY;
+    }
+
+    // Reads back the results of a frame if it is in flight and makes it the latest stats.
+    fn collect(&mut self, slot: usize) {
+        let pending = &mut self.frames[slot];
+        if !pending.in_flight { return; }
+        pending.in_flight = false;
+        unsafe {
+            let begin = get_query_result(pending.frame_queries.0);
+            let end = get_query_result(pending.frame_queries.1);
+            let mut primitives = 0;
+            gl::GetQueryObjectui64v(pending.primitives_query, gl::QUERY_RESULT, &mut primitives);
+            let passes = pending.passes.iter().map(|&(ref name, depth, pass_begin, pass_end)| {
+                let pass_begin = get_query_result(pass_begin);
+                PassTime { name: name.clone(), depth: depth,
+                        gpu_start: elapsed_ms(begin, pass_begin),
+                        gpu_time: elapsed_ms(pass_begin, get_query_result(pass_end)) }
+            }).collect();
+            self.stats = FrameStats { frame: pending.frame, cpu_time: pending.cpu_time,
+                    gpu_time: elapsed_ms(begin, end), passes: passes,
+                    counters: pending.counters, primitives: primitives };
+        }
+    }
+
+    // Gets the stats of the most recently completed frame.
+    pub fn get_stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    // Gets the GPU time in milliseconds of the first pass with a given name in the most recently
+    // completed frame.
+    pub fn get_pass_time(&self, name: &str) -> Option<f64> {
+        self.stats.passes.iter().find(|p| p.name == name).map(|p| p.gpu_time)
+    }
+
+    // Deletes every query from the GPU.
+    pub fn delete(self) {
+        for pending in &self.frames {
+            pending.delete();
+        }
+    }
+}
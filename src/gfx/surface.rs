@@ -0,0 +1,195 @@
+// Defines a Surface, which keeps everything that depends on the size of a GameWindow in step with
+// it. Once per frame, update() compares the window's size and scale factor with the last ones it
+// saw and reports what changed: a resize, the window being minimized to nothing or restored, or
+// the window moving to a screen with another DPI. Checking once per frame instead of reacting to
+// each Resized event means that a window being dragged to a new size only recreates its targets
+// once per frame instead of once per event.
+//
+// On a resize, every attached camera is given the new aspect ratio, the window's viewport is reset
+// to cover it, and every tracked Resizable (such as a PostProcessStack, an OITBuffer, or a plain
+// RenderTarget used as a G-buffer) is resized to the new target size. The default framebuffer
+// follows the window on its own. While minimized, the window has no size, so nothing is resized
+// and nothing should be drawn until it is restored.
+//
+// The target size is the window's size in pixels times the render scale, so that size-dependent
+// targets can be drawn at a lower resolution than the window and scaled up when presented.
+//
+// Usage of a Surface:
+// - Create it with new(&window) and create the size-dependent targets at get_target_size().
+// - Wrap those in Rc<RefCell<_>> and track() them, or resize them by hand when update() says so.
+// - Call update(&mut window) once per frame after polling events, and skip drawing while
+//   is_minimized() is true.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::game_window::GameWindow;
+use gfx::picking::IdPicker;
+use gfx::postprocess::PostProcessStack;
+use gfx::render_target::RenderTarget;
+use gfx::transparency::OITBuffer;
+use std::cell::RefCell;
+use std::cmp;
+use std::rc::{Rc, Weak};
+
+// Something whose size has to match the window's.
+pub trait Resizable {
+    // Resizes to a new size in pixels. Returns an Err if it couldn't be recreated at that size.
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String>;
+}
+
+impl Resizable for RenderTarget {
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        RenderTarget::resize(self, width, height);
+        Ok(())
+    }
+}
+
+impl Resizable for PostProcessStack {
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        PostProcessStack::resize(self, width, height)
+    }
+}
+
+impl Resizable for OITBuffer {
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        OITBuffer::resize(self, width, height);
+        Ok(())
+    }
+}
+
+impl Resizable for IdPicker {
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        IdPicker::resize(self, width, height);
+        Ok(())
+    }
+}
+
+// A change to a window found by Surface::update(). Restored is given along with Resized if the
+// window came back at another size.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SurfaceChange {
+    Resized(u32, u32),
+    Minimized,
+    Restored(u32, u32),
+    ScaleChanged(f32),
+}
+
+// The size and scale of a window as of the last update along with the targets that follow it.
+// Tracked targets are held weakly and are forgotten once they are dropped.
+pub struct Surface {
+    pub render_scale: f32,
+    width: u32,
+    height: u32,
+    scale_factor: f32,
+    minimized: bool,
+    target_size: (u32, u32),
+    tracked: Vec<Weak<RefCell<Resizable>>>,
+}
+
+impl Surface {
+    // Creates a Surface with the window's current size and scale and a render scale of 1.0.
+    pub fn new(window: &GameWindow) -> Surface {
+        let (width, height) = window.get_size();
+        let mut surface = Surface { render_scale: 1.0, width: width, height: height,
+                scale_factor: window.get_scale_factor(), minimized: width == 0 || height == 0,
+                target_size: (0, 0), tracked: Vec::new() };
+        surface.target_size = surface.get_target_size();
+        surface
+    }
+
+    // Tracks something to be resized whenever the target size changes. It should already have the
+    // current target size.
+    pub fn track<T: Resizable + 'static>(&mut self, resizable: &Rc<RefCell<T>>) {
+        let resizable: Rc<RefCell<Resizable>> = resizable.clone();
+        self.tracked.push(Rc::downgrade(&resizable));
+    }
+
+    // Gets the number of tracked targets that haven't been dropped.
+    pub fn get_tracked_count(&self) -> usize {
+        self.tracked.iter().filter(|r| r.upgrade().is_some()).count()
+    }
+
+    // Gets the size of the window in pixels as of the last update. While minimized, this is the
+    // size from before the window was minimized.
+    pub fn get_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    // Gets the size in pixels that size-dependent targets should have, which is the window's size
+    // times the render scale and at least a pixel in each direction.
+    pub fn get_target_size(&self) -> (u32, u32) {
+        let scale = |size: u32| cmp::max((size as f32 * self.render_scale).round() as u32, 1);
+        (scale(self.width), scale(self.height))
+    }
+
+    // Gets the size of the window in logical points, which is what UI should be laid out in so that
+    // it looks the same size on every screen.
+    pub fn get_logical_size(&self) -> (f32, f32) {
+        (self.width as f32 / self.scale_factor, self.height as f32 / self.scale_factor)
+    }
+
+    // Gets the number of pixels per logical point as of the last update.
+    pub fn get_scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    // Returns true if the window was minimized as of the last update.
+    pub fn is_minimized(&self) -> bool {
+        self.minimized
+    }
+
+    // Checks the window for changes and handles them, returning what changed. Every tracked target
+    // is resized even if one fails, and the first Err is returned. The tracked targets must not be
+    // borrowed while this is called.
+    pub fn update(&mut self, window: &mut GameWindow) -> Result<Vec<SurfaceChange>, String> {
+        let mut changes = Vec::new();
+        let scale_factor = window.get_scale_factor();
+        if scale_factor != self.scale_factor {
+            self.scale_factor = scale_factor;
+            changes.push(SurfaceChange::ScaleChanged(scale_factor));
+        }
+
+        let (width, height) = window.get_size();
+        if width == 0 || height == 0 {
+            if !self.minimized {
+                self.minimized = true;
+                changes.push(SurfaceChange::Minimized);
+            }
+            return Ok(changes);
+        }
+        if self.minimized {
+            self.minimized = false;
+            changes.push(SurfaceChange::Restored(width, height));
+        }
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            changes.push(SurfaceChange::Resized(width, height));
+            window.set_camera_aspects(width as f32 / height as f32);
+            window.set_render_target(None);
+        }
+
+        // The render scale can change without the window changing, so the target size is checked
+        // on its own.
+        let target_size = self.get_target_size();
+        if target_size == self.target_size {
+            return Ok(changes);
+        }
+        self.target_size = target_size;
+        let mut result = Ok(());
+        self.tracked.retain(|resizable| {
+            match resizable.upgrade() {
+                Some(resizable) => {
+                    let resized = resizable.borrow_mut().resize(target_size.0, target_size.1);
+                    if let (&Ok(_), Err(e)) = (&result, resized) {
+                        result = Err(e);
+                    }
+                    true
+                },
+                None => false,
+            }
+        });
+        result.map(|_| changes)
+    }
+}
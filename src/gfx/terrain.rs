@@ -0,0 +1,484 @@
+// Defines a Terrain, which is a renderable grid mesh built from a heightmap such as a grayscale
+// BMP. The grid is split into square chunks that are drawn on their own, so chunks outside of the
+// view are culled and every chunk picks its level of detail from its distance to the camera. Each
+// level keeps every other height of the level before it.
+//
+// Neighboring chunks at different levels would leave cracks where the finer chunk has heights on
+// their shared edge that the coarser chunk skips. To stitch them, the finer chunk moves those edge
+// vertices onto the coarser chunk's edge. The mesh for a combination of a chunk's level and the
+// levels of its neighbors is built the first time it's drawn and kept after that.
+//
+// The surface can be textured by up to four layers that are blended by a splat map, which gets
+// the weight of each layer from rules on the height and slope of the terrain.
+//
+// Usage of a Terrain:
+// - Create it with from_bmp() or from_image(). The grid is laid out the same way as a Heightfield,
+//   so to_heightfield() gives a collision shape that matches it.
+// - Optionally set a material, such as one from create_splat_material().
+// - Set the transform with set_model().
+// - Call draw(&mut window) every frame.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::bounds::{Aabb, Frustum};
+use gfx::color;
+use gfx::game_window::GameWindow;
+use gfx::layers;
+use gfx::material::Material;
+use gfx::model::{ModelInfo, ModelInstance};
+use gfx::types::*;
+use physics::heightfield::Heightfield;
+use self::cgmath::{EuclideanVector, Matrix, SquareMatrix};
+use std::collections::HashMap;
+use std::rc::Rc;
+use util::bmp;
+use util::common::{Image, Pixel};
+
+// The most layers that a splat map can blend, one for each of its channels.
+pub const MAX_SPLAT_LAYERS: usize = 4;
+
+// A rule for where a splat layer covers the terrain. Heights are in the terrain's local units and
+// slopes are angles from flat in radians. The weight of the layer fades out over the blend
+// distances past the ends of its ranges.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SplatRule {
+    pub min_height: GLfloat,
+    pub max_height: GLfloat,
+    pub min_slope: GLfloat,
+    pub max_slope: GLfloat,
+    pub height_blend: GLfloat,
+    pub slope_blend: GLfloat,
+}
+
+impl SplatRule {
+    // Creates a rule from its height and slope ranges and how far past them it fades out.
+    pub fn new(height: (GLfloat, GLfloat), slope: (GLfloat, GLfloat), height_blend: GLfloat,
+            slope_blend: GLfloat) -> SplatRule {
+        SplatRule { min_height: height.0, max_height: height.1, min_slope: slope.0,
+                max_slope: slope.1, height_blend: height_blend, slope_blend: slope_blend }
+    }
+
+    // Gets the weight of the rule's layer at a height and slope before it is normalized against
+    // the other layers.
+    pub fn get_weight(&self, height: GLfloat, slope: GLfloat) -> GLfloat {
+        ramp(height, self.min_height, self.max_height, self.height_blend) *
+                ramp(slope, self.min_slope, self.max_slope, self.slope_blend)
+    }
+}
+
+// Gets 1.0 inside of a range that fades to 0.0 over a distance past either end.
+fn ramp(x: GLfloat, min: GLfloat, max: GLfloat, blend: GLfloat) -> GLfloat {
+    if x >= min && x <= max { return 1.0; }
+    if blend <= 0.0 { return 0.0; }
+    let outside = if x < min { min - x } else { x - max };
+    (1.0 - outside / blend).max(0.0)
+}
+
+// The level of a chunk and the levels of its west, east, north, and south neighbors that its mesh
+// is stitched to. Neighbors that are finer than the chunk are given as the chunk's own level.
+type ChunkKey = (usize, [usize; 4]);
+
+// A piece of the grid that is drawn on its own. It starts at a column and row and covers a number
+// of cells along each axis, which is less than the chunk size for chunks on the far edges.
+struct TerrainChunk {
+    column: usize,
+    row: usize,
+    columns: usize,
+    rows: usize,
+    bounds: Aabb,
+    level: usize,
+    meshes: HashMap<ChunkKey, ModelInstance>,
+}
+
+// A heightmap terrain made of chunks. The lod distance is how far from the camera chunks drop to
+// the first coarser level, and it doubles for every level after that.
+pub struct Terrain {
+    pub lod_distance: GLfloat,
+    pub max_level: usize,
+    pub layers: layers::LayerMask,
+    columns: usize,
+    rows: usize,
+    heights: Vec<GLfloat>,
+    normals: Vec<Vector3D>,
+    cell_size: GLfloat,
+    chunk_size: usize,
+    chunk_columns: usize,
+    chunks: Vec<TerrainChunk>,
+    material: Material,
+    model: cgmath::Matrix4<GLfloat>,
+    normal: cgmath::Matrix4<GLfloat>,
+}
+
+impl Terrain {
+    // Creates a terrain from its heights, one row after another, with the distance between
+    // neighboring heights and the number of cells along each side of a chunk. Returns an Err if
+    // the grid is smaller than 2 by 2, the number of heights doesn't match it, or the chunk size
+    // isn't a power of two.
+    pub fn new(columns: usize, rows: usize, heights: Vec<GLfloat>, cell_size: GLfloat,
+            chunk_size: usize) -> Result<Terrain, String> {
+        if columns < 2 || rows < 2 {
+            return Err(format!("A Terrain needs at least 2x2 heights, but got {}x{}.", columns,
+                    rows));
+        }
+        if heights.len() != columns * rows {
+            return Err(format!("A {}x{} Terrain needs {} heights, but got {}.", columns, rows,
+                    columns * rows, heights.len()));
+        }
+        if !chunk_size.is_power_of_two() {
+            return Err(format!("The Terrain chunk size must be a power of two, but got {}.",
+                    chunk_size));
+        }
+        let white = color::Color::new_rgb(1.0, 1.0, 1.0);
+        let mut terrain = Terrain { lod_distance: 2.0 * chunk_size as GLfloat * cell_size,
+                max_level: chunk_size.trailing_zeros() as usize, layers: layers::DEFAULT_LAYER,
+                columns: columns, rows: rows, heights: heights, normals: Vec::new(),
+                cell_size: cell_size, chunk_size: chunk_size, chunk_columns: 0,
+                chunks: Vec::new(), material: Material::from_textures(0, 0, None, white, 16.0),
+                model: cgmath::Matrix4::identity(), normal: cgmath::Matrix4::identity() };
+        terrain.normals = terrain.compute_normals();
+        terrain.chunks = terrain.create_chunks();
+        Ok(terrain)
+    }
+
+    // Creates a terrain from the brightness of an image, where black is a height of 0 and white
+    // is the height scale.
+    pub fn from_image(image: &Image, height_scale: GLfloat, cell_size: GLfloat, chunk_size: usize)
+            -> Result<Terrain, String> {
+        let heights = image.data.iter().map(|p| {
+            (p.red as GLfloat + p.green as GLfloat + p.blue as GLfloat) / (3.0 * 255.0) *
+                    height_scale
+        }).collect();
+        Terrain::new(image.width as usize, image.height as usize, heights, cell_size, chunk_size)
+    }
+
+    // Creates a terrain from a grayscale BMP heightmap.
+    pub fn from_bmp(fpath: &str, height_scale: GLfloat, cell_size: GLfloat, chunk_size: usize)
+            -> Result<Terrain, String> {
+        let decoded = try!(bmp::decode_bmp(fpath));
+        Terrain::from_image(&decoded.image, height_scale, cell_size, chunk_size)
+    }
+
+    // Gets the number of columns and rows of heights.
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    // Gets the distance between neighboring heights.
+    pub fn get_cell_size(&self) -> GLfloat {
+        self.cell_size
+    }
+
+    // Gets the height at a column and row.
+    pub fn get(&self, column: usize, row: usize) -> GLfloat {
+        self.heights[row * self.columns + column]
+    }
+
+    // Gets the surface normal at a column and row in local space.
+    pub fn get_normal(&self, column: usize, row: usize) -> Vector3D {
+        self.normals[row * self.columns + column]
+    }
+
+    // Gets the position of the height at a column and row in local space.
+    pub fn get_point(&self, column: usize, row: usize) -> Vector3D {
+        let (x0, y0) = self.get_origin();
+        Vector3D::new(x0 + column as GLfloat * self.cell_size,
+                y0 - row as GLfloat * self.cell_size, self.get(column, row))
+    }
+
+    // Gets the height and normal of the most detailed surface at a point in the XY plane in local
+    // space, or None if the point is off of the grid. The normal is blended from the normals at
+    // the corners of the cell.
+    pub fn get_surface(&self, x: GLfloat, y: GLfloat) -> Option<(GLfloat, Vector3D)> {
+        let (x0, y0) = self.get_origin();
+        let (u, v) = ((x - x0) / self.cell_size, (y0 - y) / self.cell_size);
+        let (max_u, max_v) = ((self.columns - 1) as GLfloat, (self.rows - 1) as GLfloat);
+        if !(u >= 0.0 && v >= 0.0 && u <= max_u && v <= max_v) { return None; }
+        let (column, row) = ((u as usize).min(self.columns - 2), (v as usize).min(self.rows - 2));
+        let (fu, fv) = (u - column as GLfloat, v - row as GLfloat);
+
+        // Match the triangles that the cell is split into.
+        let (h00, h10) = (self.get(column, row), self.get(column + 1, row));
+        let (h01, h11) = (self.get(column, row + 1), self.get(column + 1, row + 1));
+        let height = if fu >= fv {
+            h00 + (h10 - h00) * fu + (h11 - h10) * fv
+        } else {
+            h00 + (h11 - h01) * fu + (h01 - h00) * fv
+        };
+        let normal = self.get_normal(column, row) * ((1.0 - fu) * (1.0 - fv)) +
+                self.get_normal(column + 1, row) * (fu * (1.0 - fv)) +
+                self.get_normal(column, row + 1) * ((1.0 - fu) * fv) +
+                self.get_normal(column + 1, row + 1) * (fu * fv);
+        Some((height, normal.normalize()))
+    }
+
+    // Gets the bounds of the terrain in local space.
+    pub fn get_bounds(&self) -> Aabb {
+        self.chunks.iter().fold(Aabb::empty(), |b, c| b.union(&c.bounds))
+    }
+
+    // Gets the bounds of the terrain in the world.
+    pub fn get_world_bounds(&self) -> Aabb {
+        self.get_bounds().transform(&self.model)
+    }
+
+    // Gets the number of chunks.
+    pub fn get_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Gets the level that a chunk was last drawn at, where 0 is the most detailed.
+    pub fn get_chunk_level(&self, index: usize) -> usize {
+        self.chunks[index].level
+    }
+
+    // Creates a collision shape that matches the surface of the terrain in local space.
+    pub fn to_heightfield(&self) -> Result<Heightfield, String> {
+        Heightfield::new(self.columns, self.rows, self.heights.clone(),
+                (self.cell_size, self.cell_size))
+    }
+
+    // Sets the model matrix of the terrain and recomputes the normal matrix.
+    pub fn set_model(&mut self, model: cgmath::Matrix4<GLfloat>) {
+        self.model = model;
+        self.normal = model.invert().unwrap_or(cgmath::Matrix4::identity()).transpose();
+        for chunk in &mut self.chunks {
+            for instance in chunk.meshes.values_mut() {
+                instance.model = self.model;
+                instance.normal = self.normal;
+            }
+        }
+    }
+
+    // Sets the material that the terrain is drawn with.
+    pub fn set_material(&mut self, material: Material) {
+        self.material = material;
+        for chunk in &mut self.chunks {
+            for instance in chunk.meshes.values_mut() {
+                instance.material = Some(material);
+            }
+        }
+    }
+
+    // Computes a splat map with the weights of up to four layers in its channels from a rule for
+    // each layer. The weights of every pixel add up to 1, and pixels that no rule covers use the
+    // first layer. The map has a pixel for every height, so it lines up with the terrain's texture
+    // coordinates.
+    pub fn compute_splat_map(&self, rules: &[SplatRule]) -> Result<Image, String> {
+        if rules.is_empty() || rules.len() > MAX_SPLAT_LAYERS {
+            return Err(format!("A splat map needs 1 to {} rules, but got {}.", MAX_SPLAT_LAYERS,
+                    rules.len()));
+        }
+        let mut data = Vec::with_capacity(self.heights.len());
+        for (height, normal) in self.heights.iter().zip(&self.normals) {
+            let slope = normal.z.max(-1.0).min(1.0).acos();
+            let mut weights = [0.0; MAX_SPLAT_LAYERS];
+            for (weight, rule) in weights.iter_mut().zip(rules) {
+                *weight = rule.get_weight(*height, slope);
+            }
+            let mut total = weights.iter().fold(0.0, |t, w| t + w);
+            if total <= 0.0 {
+                weights[0] = 1.0;
+                total = 1.0;
+            }
+            let channel = |w: GLfloat| (w / total * 255.0).round() as u8;
+            data.push(Pixel { red: channel(weights[0]), green: channel(weights[1]),
+                    blue: channel(weights[2]), alpha: channel(weights[3]) });
+        }
+        Ok(Image { width: self.columns as u32, height: self.rows as u32, data: data })
+    }
+
+    // Creates a material that blends textures by a splat map computed from a rule for each of
+    // them, with the textures repeated across the terrain a number of times. Returns an Err if the
+    // number of textures doesn't match the number of rules.
+    pub fn create_splat_material(&self, rules: &[SplatRule], textures: &[GLuint],
+            tiling: GLfloat) -> Result<Material, String> {
+        if textures.len() != rules.len() {
+            return Err(format!("A splat material needs a texture for each of its {} rules, but \
+                    got {}.", rules.len(), textures.len()));
+        }
+        let weights = try!(self.compute_splat_map(rules));
+        Ok(Material::from_splat(&weights, textures, tiling, color::Color::new_rgb(1.0, 1.0, 1.0),
+                16.0))
+    }
+
+    // Picks the level of every chunk from the position of the camera in the world.
+    pub fn select_levels(&mut self, eye: Vector3D) {
+        let inverse = self.model.invert().unwrap_or(cgmath::Matrix4::identity());
+        let eye = (inverse * eye.extend(1.0)).truncate();
+        for chunk in &mut self.chunks {
+            let closest = Vector3D::new(eye.x.max(chunk.bounds.min.x).min(chunk.bounds.max.x),
+                    eye.y.max(chunk.bounds.min.y).min(chunk.bounds.max.y),
+                    eye.z.max(chunk.bounds.min.z).min(chunk.bounds.max.z));
+            let dist = (eye - closest).length();
+            let mut level = 0;
+            while level < self.max_level &&
+                    dist >= self.lod_distance * (1 << level) as GLfloat {
+                level += 1;
+            }
+            chunk.level = level;
+        }
+    }
+
+    // Picks the level of every chunk from the active camera and draws the chunks that it can see.
+    // Nothing is drawn if there is no active camera.
+    pub fn draw(&mut self, window: &mut GameWindow) {
+        if !window.is_layer_visible(self.layers) { return; }
+        let (eye, frustum) = match window.get_active_camera() {
+            Ok(camera) => {
+                (camera.pos, Frustum::from_matrix(&(camera.get_view_projection() * self.model)))
+            },
+            Err(_) => { return; },
+        };
+        self.select_levels(eye);
+        for index in 0..self.chunks.len() {
+            if !frustum.intersects_aabb(&self.chunks[index].bounds) { continue; }
+            let key = self.get_chunk_key(index);
+            if !self.chunks[index].meshes.contains_key(&key) {
+                let mut instance = ModelInstance::from(Rc::new(self.build_mesh(index, key)));
+                instance.model = self.model;
+                instance.normal = self.normal;
+                instance.layers = self.layers;
+                self.chunks[index].meshes.insert(key, instance);
+            }
+            let instance = &self.chunks[index].meshes[&key];
+            window.draw_instance(instance);
+        }
+    }
+
+    // Gets the position of the first column and row in the XY plane.
+    fn get_origin(&self) -> (GLfloat, GLfloat) {
+        ((self.columns - 1) as GLfloat * self.cell_size * -0.5,
+                (self.rows - 1) as GLfloat * self.cell_size * 0.5)
+    }
+
+    // Computes the normal at every height from the differences to its neighbors, or to itself on
+    // the edges of the grid.
+    fn compute_normals(&self) -> Vec<Vector3D> {
+        let mut normals = Vec::with_capacity(self.heights.len());
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let (c0, c1) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+                let (r0, r1) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+                let dx = (self.get(c1, row) - self.get(c0, row)) /
+                        ((c1 - c0) as GLfloat * self.cell_size);
+                // Rows go toward -Y.
+                let dy = (self.get(column, r0) - self.get(column, r1)) /
+                        ((r1 - r0) as GLfloat * self.cell_size);
+                normals.push(Vector3D::new(-dx, -dy, 1.0).normalize());
+            }
+        }
+        normals
+    }
+
+    // Splits the grid into chunks and finds their bounds.
+    fn create_chunks(&mut self) -> Vec<TerrainChunk> {
+        let (cells_x, cells_y) = (self.columns - 1, self.rows - 1);
+        self.chunk_columns = (cells_x + self.chunk_size - 1) / self.chunk_size;
+        let chunk_rows = (cells_y + self.chunk_size - 1) / self.chunk_size;
+        let mut chunks = Vec::with_capacity(self.chunk_columns * chunk_rows);
+        for j in 0..chunk_rows {
+            for i in 0..self.chunk_columns {
+                let (column, row) = (i * self.chunk_size, j * self.chunk_size);
+                let columns = self.chunk_size.min(cells_x - column);
+                let rows = self.chunk_size.min(cells_y - row);
+                let mut bounds = Aabb::empty();
+                for r in row..(row + rows + 1) {
+                    for c in column..(column + columns + 1) {
+                        bounds.add_point(self.get_point(c, r));
+                    }
+                }
+                chunks.push(TerrainChunk { column: column, row: row, columns: columns,
+                        rows: rows, bounds: bounds, level: 0, meshes: HashMap::new() });
+            }
+        }
+        chunks
+    }
+
+    // Gets the level of a chunk and the levels of its neighbors that it has to be stitched to.
+    fn get_chunk_key(&self, index: usize) -> ChunkKey {
+        let level = self.chunks[index].level;
+        let (i, j) = (index % self.chunk_columns, index / self.chunk_columns);
+        let neighbor = |offset: Option<usize>| {
+            offset.and_then(|o| self.chunks.get(o)).map_or(level, |c| c.level.max(level))
+        };
+        let west = if i > 0 { Some(index - 1) } else { None };
+        let east = if i + 1 < self.chunk_columns { Some(index + 1) } else { None };
+        let north = if j > 0 { Some(index - self.chunk_columns) } else { None };
+        let south = Some(index + self.chunk_columns);
+        (level, [neighbor(west), neighbor(east), neighbor(north), neighbor(south)])
+    }
+
+    // Builds the mesh of a chunk at a level, with the heights on its edges moved onto the edges of
+    // coarser neighbors.
+    fn build_mesh(&self, index: usize, key: ChunkKey) -> ModelInfo {
+        let chunk = &self.chunks[index];
+        let (level, neighbors) = key;
+        let columns = get_samples(chunk.columns, 1 << level);
+        let rows = get_samples(chunk.rows, 1 << level);
+        let mut vertices = Vec::new();
+        let mut normals = Vec::new();
+        let mut tangents = Vec::new();
+        let mut bitangents = Vec::new();
+        let mut tcoords = Vec::new();
+        for &v in &rows {
+            for &u in &columns {
+                let (column, row) = (chunk.column + u, chunk.row + v);
+                let mut p = self.get_point(column, row);
+                if u == 0 || u == chunk.columns {
+                    let level = neighbors[if u == 0 { 0 } else { 1 }];
+                    p.z = get_edge_height(chunk.rows, v, level,
+                            |r| self.get(column, chunk.row + r));
+                }
+                if v == 0 || v == chunk.rows {
+                    let level = neighbors[if v == 0 { 2 } else { 3 }];
+                    p.z = get_edge_height(chunk.columns, u, level,
+                            |c| self.get(chunk.column + c, row));
+                }
+                let n = self.get_normal(column, row);
+                let t = Vector3D::new(n.z, 0.0, -n.x).normalize();
+                let b = t.cross(n);
+                vertices.extend_from_slice(&[p.x, p.y, p.z]);
+                normals.extend_from_slice(&[n.x, n.y, n.z]);
+                tangents.extend_from_slice(&[t.x, t.y, t.z]);
+                bitangents.extend_from_slice(&[b.x, b.y, b.z]);
+                tcoords.push((column as GLfloat + 0.5) / self.columns as GLfloat);
+                tcoords.push((row as GLfloat + 0.5) / self.rows as GLfloat);
+            }
+        }
+
+        // Split every cell into the same triangles as a Heightfield.
+        let stride = columns.len();
+        let mut elements = Vec::new();
+        for j in 0..(rows.len() - 1) {
+            for i in 0..(columns.len() - 1) {
+                let i00 = (j * stride + i) as GLuint;
+                let (i10, i01, i11) = (i00 + 1, i00 + stride as GLuint, i00 + stride as GLuint + 1);
+                elements.extend_from_slice(&[i00, i01, i11, i00, i11, i10]);
+            }
+        }
+        ModelInfo::new(vertices, elements, normals, tangents, bitangents, tcoords, self.material)
+    }
+}
+
+// Gets the offsets of the heights along a side of a chunk that a level keeps, which are every step
+// cells and the far end.
+fn get_samples(cells: usize, step: usize) -> Vec<usize> {
+    let mut samples: Vec<usize> = (0..cells).filter(|i| i % step == 0).collect();
+    samples.push(cells);
+    samples
+}
+
+// Gets the height at an offset along an edge of a chunk as a neighbor at a level sees it, which is
+// interpolated between the heights that the neighbor keeps on either side.
+fn get_edge_height<F>(cells: usize, offset: usize, level: usize, height: F) -> GLfloat
+        where F: Fn(usize) -> GLfloat {
+    let step = 1 << level;
+    let start = offset / step * step;
+    if start == offset { return height(offset); }
+    let end = (start + step).min(cells);
+    let t = (offset - start) as GLfloat / (end - start) as GLfloat;
+    height(start) * (1.0 - t) + height(end) * t
+}
@@ -0,0 +1,584 @@
+// Defines fonts and a TextRenderer for drawing strings either in screen space for UI or as labels
+// in the world that always face the camera. A Font can be loaded from an AngelCode BMFont
+// description with BMP pages or rasterized from a TrueType font into a GlyphAtlas on demand. Text
+// is laid out per character with kerning, so it supports any script without complex shaping such as
+// Latin, Greek, and Cyrillic.
+//
+// Usage of a TextRenderer:
+// - Queue strings with draw_text() and draw_label() throughout the frame.
+// - Call flush() once after the scene is drawn to draw everything with one call per atlas page.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::Matrix;
+use gfx::camera::Camera;
+use gfx::color;
+use gfx::game_window::GameWindow;
+use gfx::types::*;
+use std::cmp;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use util::{bmp, fnt, shader, ttf};
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "text.vert";
+const FRAGMENT_SHADER_NAME: &'static str = "text.frag";
+
+// Contents of a single vertex in the text vertex buffer.
+// [P_x  P_y  P_z  T_u  T_v  C_r  C_g  C_b  C_a]
+const TEXT_POS_SIZE: usize = 3;
+const TEXT_TCOORD_SIZE: usize = 2;
+const TEXT_COLOR_SIZE: usize = 4;
+const TEXT_VERTEX_SIZE: usize = TEXT_POS_SIZE + TEXT_TCOORD_SIZE + TEXT_COLOR_SIZE;
+
+// Default size of each page of a GlyphAtlas.
+pub const DEFAULT_ATLAS_SIZE: u32 = 512;
+
+// Number of spaces a tab advances by.
+const TAB_WIDTH: GLfloat = 4.0;
+
+// Empty pixels left between glyphs in an atlas so that filtering does not bleed between them.
+const ATLAS_GUTTER: u32 = 1;
+
+// The placement of a single character in a font's atlas pages. The offsets are measured from the
+// pen position at the top left of the line and all sizes are in pixels.
+#[derive(Copy, Clone, Debug)]
+pub struct Glyph {
+    pub page: usize,
+    pub uv: [GLfloat; 4],    // [u_min, v_min, u_max, v_max]
+    pub width: GLfloat,
+    pub height: GLfloat,
+    pub x_offset: GLfloat,
+    pub y_offset: GLfloat,
+    pub advance: GLfloat,
+}
+
+// A glyph positioned by Font::layout(). The position is the top left of the glyph's quad in pixels
+// relative to the top left of the text.
+#[derive(Copy, Clone, Debug)]
+pub struct PlacedGlyph {
+    pub glyph: Glyph,
+    pub x: GLfloat,
+    pub y: GLfloat,
+}
+
+// Packs glyph bitmaps into one or more square texture pages using rows of glyphs (shelves). A new
+// page is added when a glyph no longer fits on the current one.
+pub struct GlyphAtlas {
+    pub size: u32,
+    pub pages: Vec<GLuint>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+}
+
+impl GlyphAtlas {
+    // Creates an atlas with a single empty page of a given size. This can only be created after the
+    // window context is set up.
+    pub fn new(size: u32) -> GlyphAtlas {
+        let mut atlas = GlyphAtlas { size: size, pages: Vec::new(), shelf_x: 0, shelf_y: 0,
+                shelf_height: 0 };
+        atlas.add_page();
+        atlas
+    }
+
+    // Adds an empty page and starts packing onto it.
+    fn add_page(&mut self) { unsafe {
+        let mut texture_id = 0;
+        let clear = vec![0u8; (self.size * self.size * 4) as usize];
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::RGBA8 as GLint, self.size as GLsizei,
+                self.size as GLsizei, 0, gl::RGBA, gl::UNSIGNED_BYTE, vec_to_addr!(clear));
+        set_glyph_sampling();
+        self.pages.push(texture_id);
+        self.shelf_x = 0;
+        self.shelf_y = 0;
+        self.shelf_height = 0;
+    }}
+
+    // Copies a coverage bitmap into the atlas as white texels with the coverage as alpha. Returns
+    // the page along with the top left of the glyph in texels, or an Err if the glyph is larger
+    // than a page.
+    pub fn insert(&mut self, width: u32, height: u32, coverage: &[u8])
+            -> Result<(usize, u32, u32), String> {
+        if width + ATLAS_GUTTER > self.size || height + ATLAS_GUTTER > self.size {
+            return Err(format!("Glyph of size {}x{} does not fit in the atlas.", width, height));
+        }
+        if self.shelf_x + width + ATLAS_GUTTER > self.size {
+            self.shelf_x = 0;
+            self.shelf_y += self.shelf_height;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y + height + ATLAS_GUTTER > self.size {
+            self.add_page();
+        }
+        let (x, y) = (self.shelf_x + ATLAS_GUTTER, self.shelf_y + ATLAS_GUTTER);
+        self.shelf_x += width + ATLAS_GUTTER;
+        self.shelf_height = cmp::max(self.shelf_height, height + ATLAS_GUTTER);
+        if width > 0 && height > 0 {
+            let mut texels = Vec::with_capacity(coverage.len() * 4);
+            for alpha in coverage {
+                texels.extend_from_slice(&[255, 255, 255, *alpha]);
+            }
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, self.pages[self.pages.len() - 1]);
+                gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+                gl::TexSubImage2D(
+                        gl::TEXTURE_2D, 0, x as GLint, y as GLint, width as GLsizei,
+                        height as GLsizei, gl::RGBA, gl::UNSIGNED_BYTE, vec_to_addr!(texels));
+                gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            }
+        }
+        Ok((self.pages.len() - 1, x, y))
+    }
+
+    // Deletes every page from the GPU.
+    pub fn delete(self) {
+        unsafe { gl::DeleteTextures(self.pages.len() as GLsizei, self.pages.as_ptr()); }
+    }
+}
+
+// Sets the sampling parameters of the currently bound glyph texture.
+unsafe fn set_glyph_sampling() {
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+}
+
+// Where the glyphs of a Font come from. Bitmap fonts have every glyph up front in their own pages
+// while TrueType fonts rasterize glyphs into an atlas the first time they are used.
+enum FontSource {
+    Bitmap(Vec<GLuint>),
+    TrueType(ttf::TrueTypeFont, GlyphAtlas),
+}
+
+// A font at a single size. The line height is the distance between lines and the base is the
+// distance from the top of a line to the baseline, both in pixels. This can only be created after
+// the window context is set up.
+pub struct Font {
+    pub size: GLfloat,
+    pub line_height: GLfloat,
+    pub base: GLfloat,
+    glyphs: HashMap<char, Glyph>,
+    kernings: HashMap<(char, char), GLfloat>,
+    source: FontSource,
+}
+
+impl Font {
+    // Loads a bitmap font from a text BMFont file and its BMP pages.
+    pub fn from_fnt(fpath: &str) -> Result<Font, String> {
+        let decoded = try!(fnt::decode_fnt(fpath));
+        let mut pages = Vec::new();
+        let mut page_sizes = Vec::new();
+        for page in &decoded.pages {
+            let image = match bmp::decode_bmp(page) {
+                Ok(decoded) => decoded.image,
+                Err(e) => {
+                    unsafe { gl::DeleteTextures(pages.len() as GLsizei, pages.as_ptr()); }
+                    return Err(e);
+                },
+            };
+            let data = image.get_rgba_vec();
+            let mut texture_id = 0;
+            unsafe {
+                gl::GenTextures(1, &mut texture_id);
+                gl::BindTexture(gl::TEXTURE_2D, texture_id);
+                gl::TexImage2D(
+                        gl::TEXTURE_2D, 0, gl::SRGB8_ALPHA8 as GLint, image.width as GLsizei,
+                        image.height as GLsizei, 0, gl::RGBA, gl::UNSIGNED_BYTE,
+                        vec_to_addr!(data));
+                set_glyph_sampling();
+            }
+            pages.push(texture_id);
+            page_sizes.push((image.width as GLfloat, image.height as GLfloat));
+        }
+
+        let mut glyphs = HashMap::new();
+        for (c, info) in &decoded.chars {
+            let (page_width, page_height) = page_sizes[info.page];
+            glyphs.insert(*c, Glyph {
+                    page: info.page,
+                    uv: [info.x as GLfloat / page_width, info.y as GLfloat / page_height,
+                            (info.x + info.width) as GLfloat / page_width,
+                            (info.y + info.height) as GLfloat / page_height],
+                    width: info.width as GLfloat, height: info.height as GLfloat,
+                    x_offset: info.x_offset as GLfloat, y_offset: info.y_offset as GLfloat,
+                    advance: info.x_advance as GLfloat });
+        }
+        let kernings = decoded.kernings.iter().map(|(k, v)| (*k, *v as GLfloat)).collect();
+        Ok(Font { size: decoded.size as GLfloat, line_height: decoded.line_height as GLfloat,
+                base: decoded.base as GLfloat, glyphs: glyphs, kernings: kernings,
+                source: FontSource::Bitmap(pages) })
+    }
+
+    // Loads a TrueType font at an em size in pixels. The printable Latin-1 characters are
+    // rasterized up front and any other character is rasterized when it is first laid out.
+    pub fn from_ttf(fpath: &str, pixel_size: GLfloat) -> Result<Font, String> {
+        let ttf = try!(ttf::decode_ttf(fpath));
+        let scale = ttf.get_scale(pixel_size);
+        let ascent = (ttf.ascender as GLfloat * scale).ceil();
+        let descent = (ttf.descender as GLfloat * scale).floor();
+        let line_height = ascent - descent + (ttf.line_gap as GLfloat * scale).round();
+        let mut font = Font { size: pixel_size, line_height: line_height, base: ascent,
+                glyphs: HashMap::new(), kernings: HashMap::new(),
+                source: FontSource::TrueType(ttf, GlyphAtlas::new(DEFAULT_ATLAS_SIZE)) };
+        let latin = (0x20..0x7F).chain(0xA0..0x100);
+        for c in latin.filter_map(|code| ::std::char::from_u32(code)) {
+            try!(font.load_glyph(c));
+        }
+        Ok(font)
+    }
+
+    // Rasterizes a character of a TrueType font into the atlas if it isn't there already. Returns
+    // false if the font does not have the character.
+    fn load_glyph(&mut self, c: char) -> Result<bool, String> {
+        if self.glyphs.contains_key(&c) {
+            return Ok(true);
+        }
+        let glyph = match self.source {
+            FontSource::Bitmap(_) => { return Ok(false); },
+            FontSource::TrueType(ref ttf, ref mut atlas) => {
+                let raster = match try!(ttf.rasterize(c, self.size)) {
+                    Some(r) => r,
+                    None => { return Ok(false); },
+                };
+                let (page, x, y) = try!(atlas.insert(raster.width, raster.height,
+                        &raster.coverage));
+                let size = atlas.size as GLfloat;
+                Glyph { page: page,
+                        uv: [x as GLfloat / size, y as GLfloat / size,
+                                (x + raster.width) as GLfloat / size,
+                                (y + raster.height) as GLfloat / size],
+                        width: raster.width as GLfloat, height: raster.height as GLfloat,
+                        x_offset: raster.left as GLfloat,
+                        y_offset: self.base - raster.top as GLfloat, advance: raster.advance }
+            },
+        };
+        self.glyphs.insert(c, glyph);
+        Ok(true)
+    }
+
+    // Gets the kerning adjustment in pixels between two characters.
+    pub fn get_kerning(&self, left: char, right: char) -> GLfloat {
+        match self.source {
+            FontSource::Bitmap(_) => *self.kernings.get(&(left, right)).unwrap_or(&0.0),
+            FontSource::TrueType(ref ttf, _) =>
+                    (ttf.get_kerning(left, right) as GLfloat * ttf.get_scale(self.size)).round(),
+        }
+    }
+
+    // Gets the glyph for a character, loading it first if needed.
+    pub fn get_glyph(&mut self, c: char) -> Option<Glyph> {
+        match self.load_glyph(c) {
+            Ok(true) => self.glyphs.get(&c).map(|g| *g),
+            _ => None,
+        }
+    }
+
+    // Gets the texture ID of an atlas page.
+    pub fn get_page(&self, page: usize) -> GLuint {
+        match self.source {
+            FontSource::Bitmap(ref pages) => pages[page],
+            FontSource::TrueType(_, ref atlas) => atlas.pages[page],
+        }
+    }
+
+    // Positions every character of a string. Newlines start a new line, tabs advance by a few
+    // spaces, and characters missing from the font are drawn as '?' if the font has it.
+    pub fn layout(&mut self, text: &str) -> Vec<PlacedGlyph> {
+        let mut placed = Vec::new();
+        let (mut x, mut y) = (0.0, 0.0);
+        let mut previous = None;
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    x = 0.0;
+                    y += self.line_height;
+                    previous = None;
+                    continue;
+                },
+                '\r' => { continue; },
+                '\t' => {
+                    x += self.get_glyph(' ').map_or(0.0, |g| g.advance) * TAB_WIDTH;
+                    previous = None;
+                    continue;
+                },
+                _ => {},
+            }
+            let (c, glyph) = match self.get_glyph(c) {
+                Some(g) => (c, g),
+                None => match self.get_glyph('?') {
+                    Some(g) => ('?', g),
+                    None => { continue; },
+                },
+            };
+            if let Some(p) = previous {
+                x += self.get_kerning(p, c);
+            }
+            if glyph.width > 0.0 && glyph.height > 0.0 {
+                placed.push(PlacedGlyph { glyph: glyph, x: x + glyph.x_offset,
+                        y: y + glyph.y_offset });
+            }
+            x += glyph.advance;
+            previous = Some(c);
+        }
+        placed
+    }
+
+    // Gets the width and height in pixels of a string once it is laid out.
+    pub fn measure(&mut self, text: &str) -> (GLfloat, GLfloat) {
+        let mut width: GLfloat = 0.0;
+        let lines: Vec<&str> = text.split('\n').collect();
+        for line in &lines {
+            let mut x = 0.0;
+            let mut previous = None;
+            for c in line.chars() {
+                let advance = match c {
+                    '\r' => { continue; },
+                    '\t' => self.get_glyph(' ').map_or(0.0, |g| g.advance) * TAB_WIDTH,
+                    _ => match self.get_glyph(c).or_else(|| self.get_glyph('?')) {
+                        Some(g) => g.advance,
+                        None => { continue; },
+                    },
+                };
+                if let Some(p) = previous {
+                    x += self.get_kerning(p, c);
+                }
+                x += advance;
+                previous = if c == '\t' { None } else { Some(c) };
+            }
+            width = width.max(x);
+        }
+        (width, lines.len() as GLfloat * self.line_height)
+    }
+
+    // Deletes the font's pages from the GPU.
+    pub fn delete(self) {
+        match self.source {
+            FontSource::Bitmap(pages) => unsafe {
+                gl::DeleteTextures(pages.len() as GLsizei, pages.as_ptr());
+            },
+            FontSource::TrueType(_, atlas) => atlas.delete(),
+        }
+    }
+}
+
+// A string queued to be drawn in the world. The quads are laid out in pixels relative to the
+// label's anchor and are turned to face the camera when the label is flushed.
+struct WorldLabel {
+    pos: Vector3D,
+    scale: GLfloat,
+    color: [GLfloat; 4],
+    quads: Vec<(GLuint, PlacedGlyph)>,
+}
+
+// Batches text for a frame and draws it with as few draw calls as possible. This can only be
+// created after the window context is set up.
+pub struct TextRenderer {
+    screen: HashMap<GLuint, Vec<GLfloat>>,
+    labels: Vec<WorldLabel>,
+    program: GLuint,
+    vao: GLuint,
+    vbo: GLuint,
+    capacity: usize,
+}
+
+// Adds the two triangles of a glyph quad given its corners in order top left, top right, bottom
+// right, and bottom left.
+fn push_quad(vertices: &mut Vec<GLfloat>, corners: [[GLfloat; 3]; 4], uv: [GLfloat; 4],
+        color: [GLfloat; 4]) {
+    let tcoords = [[uv[0], uv[1]], [uv[2], uv[1]], [uv[2], uv[3]], [uv[0], uv[3]]];
+    for &i in &[0, 3, 2, 0, 2, 1] {
+        vertices.extend_from_slice(&corners[i]);
+        vertices.extend_from_slice(&tcoords[i]);
+        vertices.extend_from_slice(&color);
+    }
+}
+
+impl TextRenderer {
+    // Creates an empty TextRenderer along with its program and vertex buffer.
+    pub fn new() -> TextRenderer { unsafe {
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+        let mut vao = 0;
+        let mut vbo = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let pos_attr = gl::GetAttribLocation(program, gl_str!("position"));
+        gl::EnableVertexAttribArray(pos_attr as GLuint);
+        gl::VertexAttribPointer(
+                pos_attr as GLuint, TEXT_POS_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(TEXT_VERTEX_SIZE, GLsizei), ptr::null());
+        let tcoord_attr = gl::GetAttribLocation(program, gl_str!("tcoord"));
+        gl::EnableVertexAttribArray(tcoord_attr as GLuint);
+        gl::VertexAttribPointer(
+                tcoord_attr as GLuint, TEXT_TCOORD_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(TEXT_VERTEX_SIZE, GLsizei), float_size!(TEXT_POS_SIZE, CVoid));
+        let color_attr = gl::GetAttribLocation(program, gl_str!("color"));
+        gl::EnableVertexAttribArray(color_attr as GLuint);
+        gl::VertexAttribPointer(
+                color_attr as GLuint, TEXT_COLOR_SIZE as i32, gl::FLOAT, gl::FALSE as GLboolean,
+                float_size!(TEXT_VERTEX_SIZE, GLsizei),
+                float_size!(TEXT_POS_SIZE + TEXT_TCOORD_SIZE, CVoid));
+        gl::BindVertexArray(0);
+        TextRenderer { screen: HashMap::new(), labels: Vec::new(), program: program, vao: vao,
+                vbo: vbo, capacity: 0 }
+    }}
+
+    // Queues a string in screen space with its top left at a position in pixels from the top left
+    // of the render target. The scale multiplies the font's size.
+    pub fn draw_text(&mut self, font: &mut Font, text: &str, x: GLfloat, y: GLfloat,
+            scale: GLfloat, color: &color::Color) {
+        let color = [color.r, color.g, color.b, color.a];
+        for placed in font.layout(text) {
+            let vertices = self.screen.entry(font.get_page(placed.glyph.page))
+                    .or_insert_with(Vec::new);
+            let (x0, y0) = (x + placed.x * scale, y + placed.y * scale);
+            let (x1, y1) = (x0 + placed.glyph.width * scale, y0 + placed.glyph.height * scale);
+            push_quad(vertices, [[x0, y0, 0.0], [x1, y0, 0.0], [x1, y1, 0.0], [x0, y1, 0.0]],
+                    placed.glyph.uv, color);
+        }
+    }
+
+    // Queues a label in the world that is centered above a position and always faces the camera.
+    // The height is the height of a line of text in world units. Labels are depth tested against
+    // the scene.
+    pub fn draw_label(&mut self, font: &mut Font, text: &str, pos: Vector3D, height: GLfloat,
+            color: &color::Color) {
+        let (width, text_height) = font.measure(text);
+        let quads = font.layout(text).into_iter().map(|mut placed| {
+            placed.x -= width * 0.5;
+            placed.y -= text_height;
+            (font.get_page(placed.glyph.page), placed)
+        }).collect();
+        self.labels.push(WorldLabel { pos: pos, scale: height / font.line_height,
+                color: [color.r, color.g, color.b, color.a], quads: quads });
+    }
+
+    // Removes all queued text without drawing it.
+    pub fn clear(&mut self) {
+        self.screen.clear();
+        self.labels.clear();
+    }
+
+    // Draws the queued text to the window's current render target and clears the queue. Labels
+    // are drawn from the active camera and are skipped if there isn't one.
+    pub fn flush(&mut self, window: &mut GameWindow) {
+        // Expand the labels into world space quads grouped by page.
+        let mut world: HashMap<GLuint, Vec<GLfloat>> = HashMap::new();
+        let mut view_proj = None;
+        if let Ok(camera) = window.get_active_camera() {
+            let view = camera.get_view_matrix();
+            let right = Vector3D::new(view.x.x, view.y.x, view.z.x);
+            let up = Vector3D::new(view.x.y, view.y.y, view.z.y);
+            for label in &self.labels {
+                for &(page, ref placed) in &label.quads {
+                    let corner = |x: GLfloat, y: GLfloat| {
+                        let p = label.pos + right * (x * label.scale) - up * (y * label.scale);
+                        [p.x, p.y, p.z]
+                    };
+                    let (x0, y0) = (placed.x, placed.y);
+                    let (x1, y1) = (x0 + placed.glyph.width, y0 + placed.glyph.height);
+                    push_quad(world.entry(page).or_insert_with(Vec::new),
+                            [corner(x0, y0), corner(x1, y0), corner(x1, y1), corner(x0, y1)],
+                            placed.glyph.uv, label.color);
+                }
+            }
+            view_proj = Some(camera.get_projection_matrix() * view);
+        }
+        self.labels.clear();
+
+        // Pack every batch into the vertex buffer, world labels first.
+        let mut vertices = Vec::new();
+        let mut world_batches = Vec::new();
+        let mut screen_batches = Vec::new();
+        for (batches, source) in vec![(&mut world_batches, &world), (&mut screen_batches,
+                &self.screen)] {
+            for (page, batch) in source {
+                batches.push((*page, vertices.len() / TEXT_VERTEX_SIZE,
+                        batch.len() / TEXT_VERTEX_SIZE));
+                vertices.extend_from_slice(batch);
+            }
+        }
+        self.screen.clear();
+        if vertices.is_empty() { return; }
+
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            let count = vertices.len() / TEXT_VERTEX_SIZE;
+            if count > self.capacity {
+                self.capacity = count;
+                gl::BufferData(
+                        gl::ARRAY_BUFFER, float_size!(vertices.len(), GLsizeiptr),
+                        vec_to_addr!(vertices), gl::STREAM_DRAW);
+            } else {
+                gl::BufferSubData(
+                        gl::ARRAY_BUFFER, 0, float_size!(vertices.len(), GLsizeiptr),
+                        vec_to_addr!(vertices));
+            }
+
+            gl::UseProgram(self.program);
+            uniform_float!(self.program, "gamma", window.get_gamma());
+            uniform_int!(self.program, "hdr_output", window.is_hdr_output() as GLint);
+            gl::ActiveTexture(gl::TEXTURE0);
+            uniform_int!(self.program, "glyphs", 0);
+            gl::Enable(gl::BLEND);
+            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl::DepthMask(gl::FALSE);
+
+            if let Some(view_proj) = view_proj {
+                uniform_mat4!(self.program, "view_proj", view_proj);
+                TextRenderer::draw_batches(&world_batches);
+            }
+
+            // Screen space text uses pixels from the top left of the current viewport.
+            let mut viewport: [GLint; 4] = [0; 4];
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            let ortho = cgmath::ortho(0.0, viewport[2] as GLfloat, viewport[3] as GLfloat, 0.0,
+                    -1.0, 1.0);
+            uniform_mat4!(self.program, "view_proj", ortho);
+            gl::Disable(gl::DEPTH_TEST);
+            TextRenderer::draw_batches(&screen_batches);
+            gl::Enable(gl::DEPTH_TEST);
+
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+        if view_proj.is_some() {
+            for &(_, _, count) in &world_batches {
+                window.record_draw(0, count / 3);
+            }
+        }
+        for &(_, _, count) in &screen_batches {
+            window.record_draw(0, count / 3);
+        }
+        window.restore_state();
+    }
+
+    // Draws ranges of the vertex buffer with their atlas pages.
+    unsafe fn draw_batches(batches: &Vec<(GLuint, usize, usize)>) {
+        for &(page, first, count) in batches {
+            gl::BindTexture(gl::TEXTURE_2D, page);
+            gl::DrawArrays(gl::TRIANGLES, first as GLint, count as GLsizei);
+        }
+    }
+
+    // Deletes the program and vertex buffer from the GPU.
+    pub fn delete(self) { unsafe {
+        gl::DeleteProgram(self.program);
+        gl::DeleteBuffers(1, &self.vbo);
+        gl::DeleteVertexArrays(1, &self.vao);
+    }}
+}
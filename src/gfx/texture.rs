@@ -0,0 +1,337 @@
+// Defines a TextureManager which is responsible for uploading decoded images to the GPU and
+// keeping track of the resulting textures. Uncompressed Images can either have their mip chain
+// generated by the driver or provide their own, and block compressed images from DDS and KTX2
+// files are uploaded directly without being decoded. Every texture created through the manager is
+// accounted for so that the GPU memory used by textures can be queried at any time. Many files
+// can be loaded at once with load_all(), which decodes them across the threads of a JobSystem and
+// then uploads them on the calling thread since only it has the context.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::types::*;
+use std::collections::HashMap;
+use std::mem;
+use std::path::Path;
+use util::{bmp, common, dds, ktx2, png, tga};
+use util::jobs::JobSystem;
+
+// S3TC formats are only exposed through EXT_texture_compression_s3tc and
+// EXT_texture_sRGB which are not part of the core profile bindings, although every desktop driver
+// supports them.
+const COMPRESSED_RGB_S3TC_DXT1_EXT: GLenum = 0x83F0;
+const COMPRESSED_RGBA_S3TC_DXT1_EXT: GLenum = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3_EXT: GLenum = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5_EXT: GLenum = 0x83F3;
+const COMPRESSED_SRGB_S3TC_DXT1_EXT: GLenum = 0x8C4C;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT: GLenum = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT: GLenum = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT: GLenum = 0x8C4F;
+
+// Number of bytes used by a single texel of an uncompressed RGBA image.
+const RGBA_TEXEL_SIZE: usize = 4;
+
+// The color space that the data of a texture is stored in. Color data such as diffuse and albedo
+// maps should be SRGB so that it is linearized when sampled, while data like normal, specular, and
+// metallic-roughness maps should be Linear.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ColorSpace {
+    SRGB,
+    Linear,
+}
+
+// An image decoded from a file that is ready to upload.
+enum DecodedTexture {
+    Image(common::Image),
+    Compressed(common::CompressedImage),
+}
+
+// Decodes a BMP, PNG, TGA, DDS, or KTX2 file based on its extension.
+fn decode_texture(path: &str, color_space: ColorSpace) -> Result<DecodedTexture, String> {
+    profile_scope!("decode_texture");
+    let extension = Path::new(path).extension().and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| &e[..]) {
+        Some("bmp") => Ok(DecodedTexture::Image(try!(bmp::decode_bmp(path)).image)),
+        Some("png") => Ok(DecodedTexture::Image(try!(png::decode_png(path)).image)),
+        Some("tga") => Ok(DecodedTexture::Image(try!(tga::decode_tga(path)).image)),
+        Some("dds") => {
+            let mut image = try!(dds::decode_dds(path)).image;
+            image.srgb = image.srgb || color_space == ColorSpace::SRGB;
+            Ok(DecodedTexture::Compressed(image))
+        },
+        Some("ktx2") => {
+            let mut image = try!(ktx2::decode_ktx2(path)).image;
+            image.srgb = image.srgb || color_space == ColorSpace::SRGB;
+            Ok(DecodedTexture::Compressed(image))
+        },
+        _ => Err(format!("Unsupported texture file: {}.", path)),
+    }
+}
+
+// Information about a texture that was uploaded by the TextureManager.
+#[derive(Copy, Clone, Debug)]
+pub struct TextureInfo {
+    pub width: u32,
+    pub height: u32,
+    pub levels: u32,
+    pub color_space: ColorSpace,
+    pub compressed: bool,
+    pub bytes: usize,
+}
+
+// Keeps track of every texture uploaded to the GPU through it. Textures loaded from files are
+// cached by path and color space so that loading the same file twice will return the same texture,
+// while loading it as both sRGB and linear data gives two textures. This can only be used after
+// the window context is set up.
+pub struct TextureManager {
+    textures: HashMap<GLuint, TextureInfo>,
+    paths: HashMap<(String, ColorSpace), GLuint>,
+    memory: usize,
+}
+
+// Gets the number of levels in a full mip chain for an image of the given size.
+pub fn mip_count(width: u32, height: u32) -> u32 {
+    let mut size = if width > height { width } else { height };
+    let mut count = 1;
+    while size > 1 {
+        size /= 2;
+        count += 1;
+    }
+    count
+}
+
+// Gets the size of the next smallest mip level given the current size.
+fn next_mip_size(size: u32) -> u32 {
+    if size > 1 { size / 2 } else { 1 }
+}
+
+// Gets the OpenGL internal format for a compressed format.
+fn compressed_gl_format(format: common::CompressedFormat, srgb: bool) -> GLenum {
+    match (format, srgb) {
+        (common::CompressedFormat::BC1, false) => COMPRESSED_RGB_S3TC_DXT1_EXT,
+        (common::CompressedFormat::BC1, true) => COMPRESSED_SRGB_S3TC_DXT1_EXT,
+        (common::CompressedFormat::BC1Alpha, false) => COMPRESSED_RGBA_S3TC_DXT1_EXT,
+        (common::CompressedFormat::BC1Alpha, true) => COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT,
+        (common::CompressedFormat::BC2, false) => COMPRESSED_RGBA_S3TC_DXT3_EXT,
+        (common::CompressedFormat::BC2, true) => COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT,
+        (common::CompressedFormat::BC3, false) => COMPRESSED_RGBA_S3TC_DXT5_EXT,
+        (common::CompressedFormat::BC3, true) => COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+        (common::CompressedFormat::BC4, _) => gl::COMPRESSED_RED_RGTC1,
+        (common::CompressedFormat::BC5, _) => gl::COMPRESSED_RG_RGTC2,
+        (common::CompressedFormat::BC6HUnsigned, _) => gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT,
+        (common::CompressedFormat::BC6HSigned, _) => gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT,
+        (common::CompressedFormat::BC7, false) => gl::COMPRESSED_RGBA_BPTC_UNORM,
+        (common::CompressedFormat::BC7, true) => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+    }
+}
+
+// Sets the sampling parameters of the currently bound 2D texture given its number of mip levels.
+unsafe fn set_sampling(levels: u32) {
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_BASE_LEVEL, 0);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, levels as GLint - 1);
+    let min_filter = if levels > 1 { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as GLint);
+}
+
+impl TextureManager {
+    // Default constructor for an empty manager.
+    pub fn new() -> TextureManager {
+        TextureManager { textures: HashMap::new(), paths: HashMap::new(), memory: 0 }
+    }
+
+    // Records a newly created texture.
+    fn track(&mut self, texture_id: GLuint, info: TextureInfo) {
+        self.memory += info.bytes;
+        self.textures.insert(texture_id, info);
+    }
+
+    // Uploads a single mip level of an uncompressed image to the currently bound texture.
+    unsafe fn upload_level(image: &common::Image, level: GLint, color_space: ColorSpace) {
+        let data = image.get_rgba_vec();
+        let internal = match color_space {
+            ColorSpace::SRGB => gl::SRGB8_ALPHA8,
+            ColorSpace::Linear => gl::RGBA8,
+        };
+        gl::TexImage2D(
+                gl::TEXTURE_2D, level, internal as GLint, image.width as GLsizei,
+                image.height as GLsizei, 0, gl::RGBA, gl::UNSIGNED_BYTE, vec_to_addr!(data));
+    }
+
+    // Uploads an Image and returns the texture ID. If mipmaps is true, the rest of the mip chain is
+    // generated from the image.
+    pub fn upload_image(&mut self, image: &common::Image, color_space: ColorSpace,
+            mipmaps: bool) -> GLuint { unsafe {
+        let mut texture_id = 0;
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        TextureManager::upload_level(image, 0, color_space);
+        let levels = if mipmaps { mip_count(image.width, image.height) } else { 1 };
+        if mipmaps {
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+        set_sampling(levels);
+        let (mut width, mut height, mut bytes) = (image.width, image.height, 0);
+        for _ in 0..levels {
+            bytes += width as usize * height as usize * RGBA_TEXEL_SIZE;
+            width = next_mip_size(width);
+            height = next_mip_size(height);
+        }
+        self.track(texture_id, TextureInfo { width: image.width, height: image.height,
+                levels: levels, color_space: color_space, compressed: false, bytes: bytes });
+        texture_id
+    }}
+
+    // Uploads a precomputed mip chain starting with the full size image and returns the texture
+    // ID. Each level must be half the size of the previous one, but the chain doesn't have to go
+    // all the way down to 1x1.
+    pub fn upload_image_mips(&mut self, levels: &Vec<common::Image>, color_space: ColorSpace)
+            -> Result<GLuint, String> { unsafe {
+        if levels.is_empty() {
+            return Err("A mip chain needs at least one level.".to_string());
+        }
+        let (mut width, mut height) = (levels[0].width, levels[0].height);
+        for level in levels {
+            if level.width != width || level.height != height {
+                return Err("Mip level has an incorrect size.".to_string());
+            }
+            width = next_mip_size(width);
+            height = next_mip_size(height);
+        }
+        let mut texture_id = 0;
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        let mut bytes = 0;
+        for (i, level) in levels.iter().enumerate() {
+            TextureManager::upload_level(level, i as GLint, color_space);
+            bytes += level.data.len() * RGBA_TEXEL_SIZE;
+        }
+        set_sampling(levels.len() as u32);
+        self.track(texture_id, TextureInfo { width: levels[0].width, height: levels[0].height,
+                levels: levels.len() as u32, color_space: color_space, compressed: false,
+                bytes: bytes });
+        Ok(texture_id)
+    }}
+
+    // Uploads the blocks of a CompressedImage without decoding them and returns the texture ID.
+    // The color space is taken from the image.
+    pub fn upload_compressed(&mut self, image: &common::CompressedImage) -> GLuint { unsafe {
+        let format = compressed_gl_format(image.format, image.srgb);
+        let mut texture_id = 0;
+        gl::GenTextures(1, &mut texture_id);
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        let (mut width, mut height, mut bytes) = (image.width, image.height, 0);
+        for (i, level) in image.levels.iter().enumerate() {
+            gl::CompressedTexImage2D(
+                    gl::TEXTURE_2D, i as GLint, format, width as GLsizei, height as GLsizei, 0,
+                    level.len() as GLsizei, vec_to_addr!(level));
+            bytes += level.len();
+            width = next_mip_size(width);
+            height = next_mip_size(height);
+        }
+        set_sampling(image.levels.len() as u32);
+        let color_space = if image.srgb { ColorSpace::SRGB } else { ColorSpace::Linear };
+        self.track(texture_id, TextureInfo { width: image.width, height: image.height,
+                levels: image.levels.len() as u32, color_space: color_space, compressed: true,
+                bytes: bytes });
+        texture_id
+    }}
+
+    // Loads a texture from a BMP, PNG, TGA, DDS, or KTX2 file based on its extension and returns
+    // the texture ID. Uncompressed images get a generated mip chain while compressed files use the
+    // levels stored in the file.
+    // Since DDS files without a DX10 header can't mark themselves as sRGB, a compressed file is
+    // treated as sRGB if either the file or the requested color space says so.
+    pub fn load(&mut self, path: &str, color_space: ColorSpace) -> Result<GLuint, String> {
+        if let Some(texture_id) = self.paths.get(&(path.to_string(), color_space)) {
+            return Ok(*texture_id);
+        }
+        let decoded = try!(decode_texture(path, color_space));
+        Ok(self.upload_decoded(path, decoded, color_space))
+    }
+
+    // Loads many textures like load() and returns their texture IDs in the same order. The files
+    // are decoded at the same time across the threads of a JobSystem. Files that were already
+    // loaded or that come up more than once with the same color space are only decoded once.
+    pub fn load_all(&mut self, jobs: &JobSystem, textures: &[(&str, ColorSpace)])
+            -> Vec<Result<GLuint, String>> {
+        profile_scope!("TextureManager::load_all");
+        let mut pending: Vec<(&str, ColorSpace)> = Vec::new();
+        for &(path, color_space) in textures {
+            if !self.paths.contains_key(&(path.to_string(), color_space)) &&
+                    !pending.contains(&(path, color_space)) {
+                pending.push((path, color_space));
+            }
+        }
+        let decoded = jobs.map(&pending, |_, &(path, color_space)| {
+            decode_texture(path, color_space)
+        });
+        let mut errors: HashMap<(&str, ColorSpace), String> = HashMap::new();
+        for (&(path, color_space), result) in pending.iter().zip(decoded.into_iter()) {
+            match result {
+                Ok(decoded) => { self.upload_decoded(path, decoded, color_space); },
+                Err(e) => {
+                    log_warn!(Assets, "{}", e);
+                    errors.insert((path, color_space), e);
+                },
+            }
+        }
+        textures.iter().map(|&(path, color_space)| {
+            match self.paths.get(&(path.to_string(), color_space)) {
+                Some(texture_id) => Ok(*texture_id),
+                None => Err(errors.get(&(path, color_space)).cloned()
+                        .unwrap_or_else(|| format!("Failed to load texture: {}.", path))),
+            }
+        }).collect()
+    }
+
+    // Uploads a decoded file and caches its texture ID by path and color space. Uncompressed
+    // images get a generated mip chain.
+    fn upload_decoded(&mut self, path: &str, decoded: DecodedTexture, color_space: ColorSpace)
+            -> GLuint {
+        let texture_id = match decoded {
+            DecodedTexture::Image(image) => self.upload_image(&image, color_space, true),
+            DecodedTexture::Compressed(image) => self.upload_compressed(&image),
+        };
+        self.paths.insert((path.to_string(), color_space), texture_id);
+        log_debug!(Assets, "Loaded texture {} as {}.", path, texture_id);
+        texture_id
+    }
+
+    // Gets information about a texture uploaded through the manager.
+    pub fn get_info(&self, texture_id: GLuint) -> Option<&TextureInfo> {
+        self.textures.get(&texture_id)
+    }
+
+    // Gets the number of textures currently managed.
+    pub fn len(&self) -> usize {
+        self.textures.len()
+    }
+
+    // Gets the total number of bytes of GPU memory used by the managed textures. This is an
+    // estimate based on the size of the data uploaded and does not account for driver padding.
+    pub fn memory_usage(&self) -> usize {
+        self.memory
+    }
+
+    // Deletes a texture from the GPU. Returns the information about the texture if it was managed.
+    pub fn delete(&mut self, texture_id: GLuint) -> Option<TextureInfo> {
+        let info = match self.textures.remove(&texture_id) {
+            Some(info) => info,
+            None => return None,
+        };
+        self.memory -= info.bytes;
+        let keys: Vec<(String, ColorSpace)> = self.paths.iter()
+                .filter(|&(_, id)| *id == texture_id).map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            self.paths.remove(&key);
+        }
+        unsafe { gl::DeleteTextures(1, &texture_id); }
+        Some(info)
+    }
+}
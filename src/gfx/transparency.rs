@@ -0,0 +1,151 @@
+// Defines the two ways of drawing transparent ModelInstances. A TransparentQueue collects the
+// transparent instances of a frame so that GameWindow::draw_transparent() can sort them
+// back-to-front and alpha blend them over the opaque scene. Sorting per instance breaks down for
+// intersecting or interleaved geometry such as foliage and particles, so an OITBuffer can be used
+// instead to draw them in any order with weighted blended order-independent transparency.
+//
+// Usage of an OITBuffer:
+// - Draw the opaque scene into a RenderTarget (such as the scene of a PostProcessStack).
+// - oit.begin(&mut window, &opaque) and draw the transparent instances with draw_instance().
+// - oit.end(&mut window, &opaque) to blend the transparent surfaces onto the opaque target.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::EuclideanVector;
+use gfx::game_window::GameWindow;
+use gfx::model;
+use gfx::render_target::{ColorFormat, DepthFormat, RenderTarget};
+use gfx::types::*;
+use std::cmp::Ordering;
+use std::ffi::CString;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "post.vert";
+const COMPOSITE_SHADER_NAME: &'static str = "oit_composite.frag";
+
+// A list of transparent instances to be drawn in a single frame. The queue only borrows the
+// instances, so it is meant to be filled and drawn every frame.
+pub struct TransparentQueue<'a> {
+    instances: Vec<&'a model::ModelInstance>,
+}
+
+impl<'a> TransparentQueue<'a> {
+    // Default constructor for an empty queue.
+    pub fn new() -> TransparentQueue<'a> {
+        TransparentQueue { instances: Vec::new() }
+    }
+
+    // Adds an instance to the queue.
+    pub fn push(&mut self, instance: &'a model::ModelInstance) {
+        self.instances.push(instance);
+    }
+
+    // Gets the number of instances in the queue.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    // Returns true if there are no instances in the queue.
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    // Removes every instance from the queue.
+    pub fn clear(&mut self) {
+        self.instances.clear();
+    }
+
+    // Sorts the instances from farthest to closest to a camera position based on the position of
+    // each instance's origin.
+    pub fn sort(&mut self, camera_pos: Vector3D) {
+        self.instances.sort_by(|a, b| {
+            let dist_a = (a.pos - camera_pos).length2();
+            let dist_b = (b.pos - camera_pos).length2();
+            dist_b.partial_cmp(&dist_a).unwrap_or(Ordering::Equal)
+        });
+    }
+
+    // Gets the instances in their current order.
+    pub fn get_instances(&self) -> &Vec<&'a model::ModelInstance> {
+        &self.instances
+    }
+}
+
+// The accumulation buffers for weighted blended order-independent transparency. The first color
+// attachment holds the sum of the weighted premultiplied colors along with the product of
+// (1 - alpha) in its alpha channel, and the second holds the sum of the weighted alphas. This can
+// only be created after the window context is set up.
+pub struct OITBuffer {
+    pub target: RenderTarget,
+    program: GLuint,
+    vao: GLuint,
+}
+
+impl OITBuffer {
+    // Creates the accumulation buffers. The size must match the opaque RenderTarget and the depth
+    // format is Depth24 to match the PostProcessStack scene.
+    pub fn new(width: u32, height: u32) -> Result<OITBuffer, String> {
+        let target = try!(RenderTarget::new(width, height,
+                vec![ColorFormat::RGBA16F, ColorFormat::R16F], Some(DepthFormat::Depth24)));
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, COMPOSITE_SHADER_NAME);
+        let mut vao = 0;
+        unsafe { gl::GenVertexArrays(1, &mut vao); }
+        Ok(OITBuffer { target: target, program: program, vao: vao })
+    }
+
+    // Resizes the accumulation buffers. This should be called whenever the opaque target resizes.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.target.resize(width, height);
+    }
+
+    // Copies the depth of the opaque scene so that transparent surfaces behind opaque ones are
+    // hidden, clears the accumulation buffers, and redirects the window's draws into them.
+    // Returns an Err if the opaque target doesn't have a matching depth buffer.
+    pub fn begin(&self, window: &mut GameWindow, opaque: &RenderTarget)
+            -> Result<(), String> { unsafe {
+        try!(opaque.copy_depth(&self.target));
+        window.set_render_target(Some(&self.target));
+        let accumulation_clear: [GLfloat; 4] = [0.0, 0.0, 0.0, 1.0];
+        let weight_clear: [GLfloat; 4] = [0.0, 0.0, 0.0, 0.0];
+        gl::ClearBufferfv(gl::COLOR, 0, accumulation_clear.as_ptr());
+        gl::ClearBufferfv(gl::COLOR, 1, weight_clear.as_ptr());
+
+        // Colors and weights are summed while the alpha of the first attachment becomes the
+        // product of (1 - alpha).
+        gl::Enable(gl::BLEND);
+        gl::BlendFuncSeparate(gl::ONE, gl::ONE, gl::ZERO, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+        window.set_oit_output(true);
+        Ok(())
+    }}
+
+    // Blends the weighted average of the transparent surfaces onto the opaque target and leaves
+    // the window drawing to it.
+    pub fn end(&self, window: &mut GameWindow, opaque: &RenderTarget) { unsafe {
+        window.set_oit_output(false);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        window.set_render_target(Some(opaque));
+
+        gl::UseProgram(self.program);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.target.color[0]);
+        uniform_int!(self.program, "accumulation", 0);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.target.color[1]);
+        uniform_int!(self.program, "weight", 1);
+        gl::BindVertexArray(self.vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+        gl::Disable(gl::BLEND);
+        gl::Enable(gl::DEPTH_TEST);
+        window.restore_state();
+    }}
+}
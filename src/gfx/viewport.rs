@@ -0,0 +1,240 @@
+// Defines Viewports, which split what is drawn to into regions that are each drawn from their own
+// camera, and SharedWindows, which are extra OS windows that share the GameWindow's textures,
+// buffers, and programs. Together they cover editor-style layouts with several views of the same
+// scene and local split-screen, without loading anything more than once.
+//
+// A Viewport's rectangle is given in fractions of the window or RenderTarget with the origin at
+// the top left, so layouts keep their proportions when the window is resized. Setting a Viewport
+// with GameWindow::set_viewport() restricts drawing and clearing to its rectangle, makes its
+// camera active, and matches the camera's aspect ratio to the rectangle.
+//
+// A SharedWindow can't draw through the GameWindow directly, since each context has its own vertex
+// arrays, so the scene is drawn to a RenderTarget that the SharedWindow owns and is copied to the
+// SharedWindow's own surface by present(), which swaps its buffers independently of the GameWindow.
+//
+// Usage of Viewports:
+// - Attach a camera per view and create a Viewport for each, by hand or with split_screen().
+// - For each Viewport, call window.set_viewport(&viewport, None) and draw the scene.
+// - Use find_viewport() and to_local() to send the cursor to the view under it.
+//
+// Usage of a SharedWindow:
+// - Create it with new(&window, &config) after the GameWindow.
+// - Each frame, call begin(&mut window), or window.set_viewport(&viewport, Some(&shared.target))
+//   for each of its Viewports, then draw the scene and call present(&mut window).
+// - Poll its events with poll_events() like any other window, and delete() it when it's closed.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use gfx::color::Color;
+use gfx::game_window::GameWindow;
+use gfx::render_target::{ColorFormat, RenderTarget};
+use gfx::types::*;
+use platform::window::{Event, PlatformWindow, WindowConfig};
+use std::cmp;
+use std::ffi::CString;
+use util::shader;
+
+// The default shader directory and names.
+const SHADER_DIR: &'static str = "shaders";
+const VERTEX_SHADER_NAME: &'static str = "post.vert";
+const FRAGMENT_SHADER_NAME: &'static str = "post_present.frag";
+
+// A region that is drawn from a camera, given by the camera's handle in the GameWindow. If the
+// background color is set, the region is cleared to it when the Viewport is set.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub camera: usize,
+    pub bg_color: Option<Color>,
+}
+
+impl Viewport {
+    // Creates a Viewport from its top left corner and size in fractions of what is drawn to.
+    pub fn new(x: f32, y: f32, width: f32, height: f32, camera: usize) -> Viewport {
+        Viewport { x: x, y: y, width: width, height: height, camera: camera, bg_color: None }
+    }
+
+    // Creates a Viewport that covers everything.
+    pub fn new_full(camera: usize) -> Viewport {
+        Viewport::new(0.0, 0.0, 1.0, 1.0, camera)
+    }
+
+    // Gets the rectangle in pixels as (x, y, width, height) with the origin at the top left, given
+    // the size of what is drawn to. The edges are rounded so that Viewports that meet don't leave
+    // gaps or overlap.
+    pub fn get_rect(&self, width: u32, height: u32) -> (i32, i32, u32, u32) {
+        let left = (self.x * width as f32).round() as i32;
+        let top = (self.y * height as f32).round() as i32;
+        let right = ((self.x + self.width) * width as f32).round() as i32;
+        let bottom = ((self.y + self.height) * height as f32).round() as i32;
+        (left, top, cmp::max(right - left, 0) as u32, cmp::max(bottom - top, 0) as u32)
+    }
+
+    // Gets the aspect ratio of the rectangle given the size of what is drawn to.
+    pub fn get_aspect_ratio(&self, width: u32, height: u32) -> f32 {
+        let (_, _, rect_width, rect_height) = self.get_rect(width, height);
+        rect_width as f32 / cmp::max(rect_height, 1) as f32
+    }
+
+    // Returns true if a position in pixels from the top left of what is drawn to is inside the
+    // rectangle.
+    pub fn contains(&self, x: i32, y: i32, width: u32, height: u32) -> bool {
+        let (left, top, rect_width, rect_height) = self.get_rect(width, height);
+        x >= left && y >= top && x < left + rect_width as i32 && y < top + rect_height as i32
+    }
+
+    // Converts a position in pixels from the top left of what is drawn to into one from the top
+    // left of the rectangle, along with the size of the rectangle, which is what the screen
+    // conversions of a GameCamera take.
+    pub fn to_local(&self, x: i32, y: i32, width: u32, height: u32) -> (i32, i32, u32, u32) {
+        let (left, top, rect_width, rect_height) = self.get_rect(width, height);
+        (x - left, y - top, rect_width, rect_height)
+    }
+}
+
+// Creates the Viewports for split-screen with a camera per player. One player gets everything, two
+// are stacked on top of each other, three have the first player across the top, and four or more
+// are laid out in a grid that fills rows from the left.
+pub fn split_screen(cameras: &[usize]) -> Vec<Viewport> {
+    match cameras.len() {
+        0 => Vec::new(),
+        1 => vec![Viewport::new_full(cameras[0])],
+        2 => vec![Viewport::new(0.0, 0.0, 1.0, 0.5, cameras[0]),
+                Viewport::new(0.0, 0.5, 1.0, 0.5, cameras[1])],
+        3 => vec![Viewport::new(0.0, 0.0, 1.0, 0.5, cameras[0]),
+                Viewport::new(0.0, 0.5, 0.5, 0.5, cameras[1]),
+                Viewport::new(0.5, 0.5, 0.5, 0.5, cameras[2])],
+        count => {
+            let columns = (count as f32).sqrt().ceil() as usize;
+            let rows = (count + columns - 1) / columns;
+            let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+            cameras.iter().enumerate().map(|(i, &camera)| {
+                let (column, row) = (i % columns, i / columns);
+                Viewport::new(column as f32 * width, row as f32 * height, width, height, camera)
+            }).collect()
+        },
+    }
+}
+
+// Finds the index of the Viewport under a position in pixels from the top left of what is drawn
+// to. Later Viewports are drawn over earlier ones, so they are checked first.
+pub fn find_viewport(viewports: &[Viewport], x: i32, y: i32, width: u32, height: u32)
+        -> Option<usize> {
+    (0..viewports.len()).rev().find(|&i| viewports[i].contains(x, y, width, height))
+}
+
+// An extra window that shows a RenderTarget drawn by a GameWindow. The target always has the size
+// of the window, and holds sRGB color so that it is presented with the GameWindow's gamma like
+// any other draw.
+pub struct SharedWindow {
+    pub target: RenderTarget,
+    window: Box<PlatformWindow>,
+    vao: GLuint,
+    program: GLuint,
+}
+
+impl SharedWindow {
+    // Opens a window whose context shares the objects of a GameWindow's context. Returns an Err if
+    // the window or its RenderTarget couldn't be created.
+    pub fn new(game_window: &GameWindow, config: &WindowConfig)
+            -> Result<SharedWindow, String> {
+        let window = try!(game_window.get_platform().create_shared(config));
+        let (width, height) = window.get_size();
+        let target = try!(RenderTarget::new_simple(
+                cmp::max(width, 1), cmp::max(height, 1), ColorFormat::SRGB8Alpha8));
+        let program = shader::load_program(SHADER_DIR, VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+        let mut vao = 0;
+        try!(window.make_current());
+        unsafe { gl::GenVertexArrays(1, &mut vao); }
+        try!(game_window.get_platform().make_current());
+        Ok(SharedWindow { target: target, window: window, vao: vao, program: program })
+    }
+
+    // Gets the PlatformWindow for things like its title and cursor mode.
+    pub fn get_platform(&self) -> &PlatformWindow {
+        &*self.window
+    }
+
+    // Gets the PlatformWindow to change it.
+    pub fn get_platform_mut(&mut self) -> &mut PlatformWindow {
+        &mut *self.window
+    }
+
+    // Gets every event that happened to the window since the last poll.
+    pub fn poll_events(&mut self) -> Vec<Event> {
+        self.window.poll_events()
+    }
+
+    // Gets the size of the window in pixels.
+    pub fn get_size(&self) -> (u32, u32) {
+        self.window.get_size()
+    }
+
+    // Gets the aspect ratio of the window.
+    pub fn get_aspect_ratio(&self) -> f32 {
+        self.target.get_aspect_ratio()
+    }
+
+    // Returns true once the user has asked to close the window.
+    pub fn is_closed(&self) -> bool {
+        self.window.is_closed()
+    }
+
+    // Resizes the target to match the window if the window has changed size. This is done by
+    // begin(), and only needs to be called before drawing to the target through set_viewport().
+    pub fn update_size(&mut self) {
+        let (width, height) = self.window.get_size();
+        if width == 0 || height == 0 { return; }
+        if (width, height) != (self.target.width, self.target.height) {
+            self.target.resize(width, height);
+        }
+    }
+
+    // Redirects the GameWindow's draws to the whole target. The target still needs to be cleared
+    // with GameWindow::clear().
+    pub fn begin(&mut self, game_window: &mut GameWindow) {
+        self.update_size();
+        game_window.set_render_target(Some(&self.target));
+    }
+
+    // Copies the target to the window and swaps the window's buffers. The GameWindow is made
+    // current again and draws to its own window afterwards.
+    pub fn present(&mut self, game_window: &mut GameWindow) -> Result<(), String> {
+        // The draws to the target have to be submitted before another context reads it.
+        unsafe { gl::Flush(); }
+        try!(self.window.make_current());
+        let (width, height) = self.window.get_size();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+            gl::UseProgram(self.program);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, self.target.color[0]);
+            uniform_int!(self.program, "source", 0);
+            uniform_float!(self.program, "gamma", game_window.get_gamma());
+            gl::BindVertexArray(self.vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+        let result = self.window.swap_buffers();
+        try!(game_window.get_platform().make_current());
+        game_window.set_render_target(None);
+        game_window.restore_state();
+        result
+    }
+
+    // Deletes the window's OpenGL objects and closes it.
+    pub fn delete(self, game_window: &GameWindow) { unsafe {
+        if self.window.make_current().is_ok() {
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+        let _ = game_window.get_platform().make_current();
+        gl::DeleteProgram(self.program);
+        self.target.delete();
+    }}
+}
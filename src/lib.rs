@@ -1,3 +1,9 @@
+#[macro_use] pub mod util;
+pub mod anim;
+pub mod audio;
 pub mod ecs;
 pub mod gfx;
-pub mod util;
+pub mod net;
+pub mod physics;
+pub mod platform;
+pub mod script;
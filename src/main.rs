@@ -38,19 +38,20 @@ fn main() {
     let mut window = GameWindow::new(800, 600, "Engine Test".to_string()).unwrap();
     window.bg_color = color::Color::new_rgb(0.2, 0.2, 0.2);
 
-    let camera1 = camera::PerspectiveCamera::new(
+    let camera1 = camera::GameCamera::new(
             Vector3D::new(17.0, 17.0, 17.0), Vector3D::new(0.0, 0.0, 0.0),
             window.get_aspect_ratio(), 45.0, 0.1, 100.0);
-    let camera2 = camera::PerspectiveCamera::new(
+    let camera2 = camera::GameCamera::new(
             Vector3D::new(0.0001, 0.0, 30.0), Vector3D::new(0.0, 0.0, 0.0),
             window.get_aspect_ratio(), 45.0, 0.1, 100.0);
     let main_camera = window.attach_camera(camera1);
     let secondary_camera = window.attach_camera(camera2);
     window.set_active_camera(main_camera).unwrap();
 
-    // let bunny_mat = material::Material::new_with_color(Some(asset!("stone_diffuse.bmp")),
-    //         Some(asset!("stone_specular.bmp")), Some(asset!("stone_normal.bmp")),
-    //         color::Color::new_rgb(1.0, 1.0, 1.0), 75.0);
+    // let bunny_mat = material::Material::new_with_color(window.get_textures_mut(),
+    //         Some(asset!("stone_diffuse.bmp")), Some(asset!("stone_specular.bmp")),
+    //         Some(asset!("stone_normal.bmp")),
+    //         color::Color::new_rgb(1.0, 1.0, 1.0), 75.0).unwrap();
     let bunny = rmod::decode_rmod(asset!("bunny.rmod")).unwrap();
     let bunny_info = Rc::new(model::ModelInfo::from_rmod(&bunny));
     let mut bunny_inst = model::ModelInstance::from(bunny_info.clone());
@@ -64,26 +65,26 @@ fn main() {
     ground_inst.scale = 20.0;
     ground_inst.update();
 
-    // let dragon_mat = material::Material::new_with_color(Some(asset!("uvs.bmp")),
-    //     None, None,
-    //     color::Color::new_rgb(1.0, 1.0, 1.0), 175.0);
+    // let dragon_mat = material::Material::new_with_color(window.get_textures_mut(),
+    //     Some(asset!("uvs.bmp")), None, None,
+    //     color::Color::new_rgb(1.0, 1.0, 1.0), 175.0).unwrap();
     // let dragon_info = Rc::new(model::ModelInfo::from_obj(&dragon, dragon_mat));
     // let mut dragon_inst = model::ModelInstance::from(dragon_info.clone());
     // dragon_inst.scale = 0.6;
     // dragon_inst.pos = Vector3D::new(4.0, -4.0, 0.0);
     // dragon_inst.update();
 
-    // let budda_mat = material::Material::new_with_color(Some(asset!("brian.bmp")),
-    //         None, None,
-    //         color::Color::new_rgb(1.0, 1.0, 1.0), 175.0);
+    // let budda_mat = material::Material::new_with_color(window.get_textures_mut(),
+    //         Some(asset!("brian.bmp")), None, None,
+    //         color::Color::new_rgb(1.0, 1.0, 1.0), 175.0).unwrap();
     // let budda_info = Rc::new(model::ModelInfo::from_obj(&budda, budda_mat));
     // let mut budda_inst = model::ModelInstance::from(budda_info.clone());
     // budda_inst.pos = Vector3D::new(3.5, 3.5, 1.0);
     // budda_inst.update();
 
-    let lb_mat = material::Material::new_with_color(None,
+    let lb_mat = material::Material::new_with_color(window.get_textures_mut(), None,
             None, None,
-            color::Color::new_rgb(0.0, 0.0, 0.0), 75.0);
+            color::Color::new_rgb(0.0, 0.0, 0.0), 75.0).unwrap();
     let lb = Rc::new(model::ModelInfo::new_box(1.0, 1.0, 1.0, lb_mat));
     let mut lb1_inst = model::ModelInstance::from(lb.clone());
     lb1_inst.update();
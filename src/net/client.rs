@@ -0,0 +1,343 @@
+// Defines a Client, which connects to a Server and shows the entities that it replicates. Snapshots
+// arrive at the server's tick rate with some jitter, so the client draws the entities a little in
+// the past, by the interpolation delay, and blends between the two snapshots on either side of
+// that time. This hides late and lost packets as long as one newer snapshot has arrived. When none
+// has, the entities are extrapolated along their last velocity for a short while and then held.
+//
+// The client keeps a clock of the server time that it is drawing, which runs at the frame rate and
+// is nudged toward the newest snapshot's time as snapshots arrive so that it doesn't drift. If it
+// falls too far behind or ahead it jumps instead.
+//
+// Usage of a Client:
+// - let mut client = try!(Client::connect("127.0.0.1:27015"));
+// - client.update(dt) every frame to send and receive packets.
+// - for event in client.apply(&mut scene) to place the replicated nodes, attaching meshes to the
+//   nodes that were spawned based on their kind.
+// - client.disconnect() when leaving.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::scene::{NodeId, Scene, Transform};
+use gfx::types::*;
+use net::packet::{Packet, MAX_PACKET_SIZE, NO_BASELINE};
+use net::snapshot::{NetworkId, Snapshot};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+// The default number of seconds in the past that entities are drawn.
+pub const DEFAULT_INTERPOLATION_DELAY: f64 = 0.1;
+
+// The default number of seconds that entities are extrapolated before they are held in place.
+pub const DEFAULT_MAX_EXTRAPOLATION: f64 = 0.25;
+
+// The default number of seconds without hearing from the server before giving up.
+pub const DEFAULT_TIMEOUT: f64 = 5.0;
+
+// Seconds between connect requests while waiting to be accepted.
+const CONNECT_INTERVAL: f64 = 0.5;
+
+// The number of received snapshots kept for interpolation and as baselines. This is more than
+// the server keeps so that any baseline it uses is still here.
+const HISTORY_SIZE: usize = 64;
+
+// How far the clock can be from where it should be before it jumps there.
+const MAX_CLOCK_ERROR: f64 = 0.25;
+
+// How much of the clock's error is corrected each frame.
+const CLOCK_CORRECTION: f64 = 0.1;
+
+// Where a Client is in connecting to its server.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Refused,
+    Disconnected,
+}
+
+// A change to the replicated nodes of a scene made by Client::apply().
+#[derive(Clone, PartialEq, Debug)]
+pub enum ReplicationEvent {
+    Spawned { id: NetworkId, kind: u16, node: NodeId },
+    Removed { id: NetworkId, node: NodeId },
+}
+
+// Blends between two transforms, taking the shorter way between their rotations.
+pub fn interpolate(a: &Transform, b: &Transform, t: f32) -> Transform {
+    let (ra, mut rb) = (a.rot, b.rot);
+    if ra.s * rb.s + ra.v.x * rb.v.x + ra.v.y * rb.v.y + ra.v.z * rb.v.z < 0.0 {
+        rb = Quaternion::new(-rb.s, -rb.v.x, -rb.v.y, -rb.v.z);
+    }
+    let blend = |x: f32, y: f32| x + (y - x) * t;
+    let (s, x, y, z) = (blend(ra.s, rb.s), blend(ra.v.x, rb.v.x), blend(ra.v.y, rb.v.y),
+            blend(ra.v.z, rb.v.z));
+    let length = (s * s + x * x + y * y + z * z).sqrt();
+    let rot = if length > 0.0 {
+        Quaternion::new(s / length, x / length, y / length, z / length)
+    } else {
+        ra
+    };
+    Transform::new(a.pos + (b.pos - a.pos) * t, rot, a.scale + (b.scale - a.scale) * t)
+}
+
+// Receives snapshots from a Server and works out where its entities are.
+pub struct Client {
+    pub interpolation_delay: f64,
+    pub max_extrapolation: f64,
+    pub timeout: f64,
+    socket: UdpSocket,
+    server: SocketAddr,
+    state: ConnectionState,
+    id: Option<u16>,
+    tick_rate: f64,
+    received: VecDeque<Snapshot>,
+    clock: f64,
+    since_connect: f64,
+    since_received: f64,
+    nodes: HashMap<NetworkId, NodeId>,
+}
+
+impl Client {
+    // Creates a Client that starts connecting to a server at an address such as
+    // "127.0.0.1:27015". The connection is made by update().
+    pub fn connect(addr: &str) -> Result<Client, String> {
+        let server = match addr.to_socket_addrs().ok().and_then(|mut a| a.next()) {
+            Some(server) => server,
+            None => return Err(format!("Unable to resolve {}.", addr)),
+        };
+        let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = try!(UdpSocket::bind(local).map_err(|e| e.to_string()));
+        try!(socket.set_nonblocking(true).map_err(|e| e.to_string()));
+        Ok(Client { interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
+                max_extrapolation: DEFAULT_MAX_EXTRAPOLATION, timeout: DEFAULT_TIMEOUT,
+                socket: socket, server: server, state: ConnectionState::Connecting, id: None,
+                tick_rate: 1.0, received: VecDeque::new(), clock: 0.0,
+                since_connect: CONNECT_INTERVAL, since_received: 0.0, nodes: HashMap::new() })
+    }
+
+    // Gets where the client is in connecting.
+    pub fn get_state(&self) -> ConnectionState {
+        self.state
+    }
+
+    // Gets the id that the server gave the client once it has connected.
+    pub fn get_id(&self) -> Option<u16> {
+        self.id
+    }
+
+    // Gets the server time in seconds that the entities are drawn at.
+    pub fn get_clock(&self) -> f64 {
+        self.clock
+    }
+
+    // Gets the newest snapshot that has been received.
+    pub fn get_latest(&self) -> Option<&Snapshot> {
+        self.received.back()
+    }
+
+    // Leaves the server.
+    pub fn disconnect(&mut self) {
+        if self.state == ConnectionState::Connected || self.state == ConnectionState::Connecting {
+            let _ = self.socket.send_to(&Packet::Disconnect.encode(), self.server);
+        }
+        self.state = ConnectionState::Disconnected;
+    }
+
+    // Gets the server time of a tick.
+    fn get_time(&self, tick: u32) -> f64 {
+        tick as f64 / self.tick_rate
+    }
+
+    // Sends connect requests until the server answers, handles the packets that have arrived, and
+    // advances the clock by the frame time in seconds.
+    pub fn update(&mut self, dt: f64) {
+        match self.state {
+            ConnectionState::Refused | ConnectionState::Disconnected => return,
+            ConnectionState::Connecting => {
+                self.since_connect += dt;
+                if self.since_connect >= CONNECT_INTERVAL {
+                    self.since_connect = 0.0;
+                    let _ = self.socket.send_to(&Packet::Connect.encode(), self.server);
+                }
+            },
+            ConnectionState::Connected => {},
+        }
+
+        self.since_received += dt;
+        self.clock += dt;
+        self.receive();
+        if self.since_received > self.timeout {
+            log_info!(Network, "Lost the connection to {}.", self.server);
+            self.state = ConnectionState::Disconnected;
+            return;
+        }
+
+        let latest = match self.received.back() {
+            Some(latest) => self.get_time(latest.tick),
+            None => return,
+        };
+        let target = latest + self.since_received - self.interpolation_delay;
+        let error = target - self.clock;
+        if error.abs() > MAX_CLOCK_ERROR {
+            self.clock = target;
+        } else {
+            self.clock += error * CLOCK_CORRECTION;
+        }
+    }
+
+    // Handles every packet from the server that is waiting on the socket.
+    fn receive(&mut self) {
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        loop {
+            let (size, addr) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    log_debug!(Network, "Unable to receive: {}", e);
+                    return;
+                },
+            };
+            if addr != self.server { continue; }
+            match Packet::decode(&buffer[..size]) {
+                Ok(packet) => self.handle(packet),
+                Err(e) => log_debug!(Network, "Ignoring a packet from the server: {}", e),
+            }
+        }
+    }
+
+    // Handles a packet from the server.
+    fn handle(&mut self, packet: Packet) {
+        match packet {
+            Packet::Accept { client, tick_rate } => {
+                self.since_received = 0.0;
+                if self.state == ConnectionState::Connecting {
+                    log_info!(Network, "Connected to {} as client {}.", self.server, client);
+                    self.state = ConnectionState::Connected;
+                    self.id = Some(client);
+                    self.tick_rate = if tick_rate == 0 { 1.0 } else { tick_rate as f64 };
+                }
+            },
+            Packet::Refuse => {
+                log_info!(Network, "{} refused the connection.", self.server);
+                self.state = ConnectionState::Refused;
+            },
+            Packet::Disconnect => {
+                log_info!(Network, "{} ended the connection.", self.server);
+                self.state = ConnectionState::Disconnected;
+            },
+            Packet::Snapshot { tick, baseline, data } => {
+                if self.state != ConnectionState::Connected { return; }
+                self.since_received = 0.0;
+                if self.received.back().map_or(false, |s| s.tick >= tick) { return; }
+                let base = if baseline == NO_BASELINE {
+                    None
+                } else {
+                    match self.received.iter().find(|s| s.tick == baseline) {
+                        Some(base) => Some(base),
+                        None => {
+                            log_debug!(Network, "Dropping snapshot {} since its baseline {} is \
+                                    gone.", tick, baseline);
+                            return;
+                        },
+                    }
+                };
+                let snapshot = match Snapshot::decode(tick, &data, base) {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        log_warn!(Network, "Unable to decode snapshot {}: {}", tick, e);
+                        return;
+                    },
+                };
+                if self.received.is_empty() {
+                    self.clock = self.get_time(tick) - self.interpolation_delay;
+                }
+                self.received.push_back(snapshot);
+                while self.received.len() > HISTORY_SIZE {
+                    self.received.pop_front();
+                }
+                let _ = self.socket.send_to(&Packet::Ack { tick: tick }.encode(), self.server);
+            },
+            packet => log_debug!(Network, "Ignoring {:?} from the server.", packet),
+        }
+    }
+
+    // Gets the kind and transform of every entity at the clock's time.
+    pub fn get_entities(&self) -> Vec<(NetworkId, u16, Transform)> {
+        // Find the newest snapshot at or before the clock.
+        let index = match self.received.iter().rposition(|s| self.get_time(s.tick) <= self.clock) {
+            Some(index) => index,
+            None => {
+                return self.received.front().map_or(Vec::new(), |s| s.entities.iter()
+                        .map(|e| (e.id, e.kind, e.get_transform())).collect());
+            },
+        };
+        let before = &self.received[index];
+        let time = self.get_time(before.tick);
+
+        if let Some(after) = self.received.get(index + 1) {
+            let t = ((self.clock - time) / (self.get_time(after.tick) - time)) as f32;
+            return after.entities.iter().map(|e| {
+                let transform = match before.get(e.id) {
+                    Some(b) if b.kind == e.kind => {
+                        interpolate(&b.get_transform(), &e.get_transform(), t)
+                    },
+                    _ => e.get_transform(),
+                };
+                (e.id, e.kind, transform)
+            }).collect();
+        }
+
+        // Past the newest snapshot, so extrapolate from the velocity between the last two.
+        let extra = (self.clock - time).min(self.max_extrapolation);
+        let previous = if index > 0 { self.received.get(index - 1) } else { None };
+        before.entities.iter().map(|e| {
+            let mut transform = e.get_transform();
+            if let Some(p) = previous.and_then(|p| p.get(e.id)) {
+                let elapsed = time - self.get_time(previous.unwrap().tick);
+                let velocity = (transform.pos - p.get_transform().pos) / elapsed as f32;
+                transform.pos = transform.pos + velocity * extra as f32;
+            }
+            (e.id, e.kind, transform)
+        }).collect()
+    }
+
+    // Places the replicated nodes of a scene at the clock's time. Nodes are spawned as roots named
+    // after their network id for entities that are new, and removed for entities that are gone.
+    // Returns the nodes that were spawned and removed.
+    pub fn apply(&mut self, scene: &mut Scene) -> Vec<ReplicationEvent> {
+        let mut events = Vec::new();
+        let entities = self.get_entities();
+        for &(id, kind, ref transform) in entities.iter() {
+            let existing = self.nodes.get(&id).cloned().and_then(|n| scene.get_node(n).map(|_| n));
+            let node = match existing {
+                Some(node) => node,
+                None => {
+                    let node = match scene.add_node(&format!("net_{}", id), None) {
+                        Ok(node) => node,
+                        Err(e) => {
+                            log_error!(Network, "Unable to spawn entity {}: {}", id, e);
+                            continue;
+                        },
+                    };
+                    self.nodes.insert(id, node);
+                    events.push(ReplicationEvent::Spawned { id: id, kind: kind, node: node });
+                    node
+                },
+            };
+            scene.get_node_mut(node).unwrap().transform = *transform;
+        }
+
+        let removed: Vec<NetworkId> = self.nodes.keys().cloned()
+                .filter(|id| !entities.iter().any(|e| e.0 == *id)).collect();
+        for id in removed {
+            let node = self.nodes.remove(&id).unwrap();
+            if scene.get_node(node).is_some() {
+                let _ = scene.remove_node(node);
+            }
+            events.push(ReplicationEvent::Removed { id: id, node: node });
+        }
+        events
+    }
+}
@@ -0,0 +1,4 @@
+pub mod client;
+pub mod packet;
+pub mod server;
+pub mod snapshot;
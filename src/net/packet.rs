@@ -0,0 +1,263 @@
+// Defines the packets that a Server and its Clients send each other over UDP, along with the
+// readers and writers of their bytes. Every packet starts with the protocol id, so that stray
+// datagrams from other programs are ignored, followed by its type. Numbers are little endian, and
+// counts and ids are written as variable length integers with 7 bits per byte so that small ones
+// take a single byte.
+//
+// Brian Ho
+// brian@brkho.com
+
+// Identifies the engine's packets. This changes whenever the format of a packet does.
+pub const PROTOCOL_ID: u32 = 0x4D4D4F01;
+
+// The largest packet that is sent, which keeps packets under the usual MTU so that they aren't
+// fragmented.
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+// The tick of a snapshot's baseline when it isn't encoded against one.
+pub const NO_BASELINE: u32 = 0xFFFFFFFF;
+
+// Writes numbers to a byte vector.
+pub struct ByteWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    // Creates an empty ByteWriter.
+    pub fn new() -> ByteWriter {
+        ByteWriter { bytes: Vec::new() }
+    }
+
+    // Writes a byte.
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    // Writes a 16 bit number.
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+    }
+
+    // Writes a 32 bit number.
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8,
+                (value >> 24) as u8]);
+    }
+
+    // Writes a number with 7 bits per byte, where the high bit of a byte means that more follow.
+    pub fn write_varint(&mut self, mut value: u32) {
+        while value >= 0x80 {
+            self.bytes.push((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    // Writes a signed number as a varint with the sign in the lowest bit, so that small negative
+    // numbers are small too.
+    pub fn write_signed(&mut self, value: i32) {
+        self.write_varint(((value << 1) ^ (value >> 31)) as u32);
+    }
+}
+
+// Reads numbers from bytes, returning an Err if the bytes run out.
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    // Creates a reader at the start of some bytes.
+    pub fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes: bytes, position: 0 }
+    }
+
+    // Gets the bytes that haven't been read yet.
+    pub fn get_remaining(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    // Reads a byte.
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        match self.bytes.get(self.position) {
+            Some(&b) => { self.position += 1; Ok(b) },
+            None => Err("Packet ended early.".to_string()),
+        }
+    }
+
+    // Reads a 16 bit number.
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let low = try!(self.read_u8()) as u16;
+        Ok(low | (try!(self.read_u8()) as u16) << 8)
+    }
+
+    // Reads a 32 bit number.
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let low = try!(self.read_u16()) as u32;
+        Ok(low | (try!(self.read_u16()) as u32) << 16)
+    }
+
+    // Reads a number written by ByteWriter::write_varint().
+    pub fn read_varint(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let b = try!(self.read_u8());
+            value |= ((b & 0x7F) as u32) << (7 * i);
+            if b & 0x80 == 0 { return Ok(value); }
+        }
+        Err("Varint is too long.".to_string())
+    }
+
+    // Reads a number written by ByteWriter::write_signed().
+    pub fn read_signed(&mut self) -> Result<i32, String> {
+        let value = try!(self.read_varint());
+        Ok((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+}
+
+// The packets of the protocol.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Packet {
+    // Asks a server to join. Clients send this until they are accepted.
+    Connect,
+    // Accepts a client and tells it its id and how many snapshots the server takes a second.
+    Accept { client: u16, tick_rate: u16 },
+    // Refuses a client, such as when the server is full.
+    Refuse,
+    // The transforms of the replicated entities at a tick, encoded against the snapshot at the
+    // baseline tick, which the client has already acknowledged.
+    Snapshot { tick: u32, baseline: u32, data: Vec<u8> },
+    // Acknowledges the newest snapshot that a client has received, which the server then uses as
+    // the baseline for the snapshots it sends. This also keeps the connection alive.
+    Ack { tick: u32 },
+    // Leaves the server, or tells a client that it was removed.
+    Disconnect,
+}
+
+impl Packet {
+    // Encodes the packet to bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        writer.write_u32(PROTOCOL_ID);
+        match *self {
+            Packet::Connect => writer.write_u8(0),
+            Packet::Accept { client, tick_rate } => {
+                writer.write_u8(1);
+                writer.write_u16(client);
+                writer.write_u16(tick_rate);
+            },
+            Packet::Refuse => writer.write_u8(2),
+            Packet::Snapshot { tick, baseline, ref data } => {
+                writer.write_u8(3);
+                writer.write_u32(tick);
+                writer.write_u32(baseline);
+                writer.bytes.extend_from_slice(data);
+            },
+            Packet::Ack { tick } => {
+                writer.write_u8(4);
+                writer.write_u32(tick);
+            },
+            Packet::Disconnect => writer.write_u8(5),
+        }
+        writer.bytes
+    }
+
+    // Decodes a packet from bytes. Returns an Err if the bytes aren't one of the engine's packets.
+    pub fn decode(bytes: &[u8]) -> Result<Packet, String> {
+        let mut reader = ByteReader::new(bytes);
+        if try!(reader.read_u32()) != PROTOCOL_ID {
+            return Err("Packet has the wrong protocol id.".to_string());
+        }
+        let packet = match try!(reader.read_u8()) {
+            0 => Packet::Connect,
+            1 => Packet::Accept { client: try!(reader.read_u16()),
+                    tick_rate: try!(reader.read_u16()) },
+            2 => Packet::Refuse,
+            3 => {
+                let tick = try!(reader.read_u32());
+                let baseline = try!(reader.read_u32());
+                return Ok(Packet::Snapshot { tick: tick, baseline: baseline,
+                        data: reader.get_remaining().to_vec() });
+            },
+            4 => Packet::Ack { tick: try!(reader.read_u32()) },
+            5 => Packet::Disconnect,
+            t => return Err(format!("Unknown packet type {}.", t)),
+        };
+        if !reader.get_remaining().is_empty() {
+            return Err("Packet has extra bytes.".to_string());
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_numbers() {
+        let mut writer = ByteWriter::new();
+        writer.write_u8(7);
+        writer.write_u16(0xBEEF);
+        writer.write_u32(0xDEADBEEF);
+        for &value in [0, 127, 128, 0xFFFFFFFF].iter() {
+            writer.write_varint(value);
+        }
+        for &value in [0, -1, 1, -64, i32::min_value(), i32::max_value()].iter() {
+            writer.write_signed(value);
+        }
+        // Small varints and small signed numbers of either sign take a single byte.
+        assert_eq!(writer.bytes.len(), 1 + 2 + 4 + (1 + 1 + 2 + 5) + (1 + 1 + 1 + 1 + 5 + 5));
+        let mut reader = ByteReader::new(&writer.bytes);
+        assert_eq!(reader.read_u8(), Ok(7));
+        assert_eq!(reader.read_u16(), Ok(0xBEEF));
+        assert_eq!(reader.read_u32(), Ok(0xDEADBEEF));
+        for &value in [0, 127, 128, 0xFFFFFFFF].iter() {
+            assert_eq!(reader.read_varint(), Ok(value));
+        }
+        for &value in [0, -1, 1, -64, i32::min_value(), i32::max_value()].iter() {
+            assert_eq!(reader.read_signed(), Ok(value));
+        }
+        assert!(reader.get_remaining().is_empty());
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_varints() {
+        assert!(ByteReader::new(&[0x80; 6]).read_varint().is_err());
+        assert!(ByteReader::new(&[0x80, 0x80]).read_varint().is_err());
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let packets = vec![Packet::Connect, Packet::Accept { client: 3, tick_rate: 20 },
+                Packet::Refuse, Packet::Snapshot { tick: 9, baseline: NO_BASELINE,
+                data: vec![1, 2, 3] }, Packet::Ack { tick: 8 }, Packet::Disconnect];
+        for packet in packets {
+            assert_eq!(Packet::decode(&packet.encode()), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let bytes = Packet::Accept { client: 3, tick_rate: 20 }.encode();
+        for length in 0..bytes.len() {
+            assert!(Packet::decode(&bytes[..length]).is_err());
+        }
+        let bytes = Packet::Snapshot { tick: 9, baseline: 8, data: Vec::new() }.encode();
+        assert!(Packet::decode(&bytes[..(bytes.len() - 1)]).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_packets() {
+        let mut bytes = Packet::Connect.encode();
+        bytes[0] ^= 1;
+        assert!(Packet::decode(&bytes).is_err());
+        let mut bytes = Packet::Connect.encode();
+        bytes[4] = 99;
+        assert!(Packet::decode(&bytes).is_err());
+        let mut bytes = Packet::Ack { tick: 8 }.encode();
+        bytes.push(0);
+        assert!(Packet::decode(&bytes).is_err());
+    }
+}
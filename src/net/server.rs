@@ -0,0 +1,279 @@
+// Defines a Server, which replicates the transforms of scene nodes to Clients over UDP. The server
+// takes a snapshot of every replicated node at a fixed tick rate and sends it to each client,
+// encoded against the newest snapshot that the client has acknowledged. Snapshots are sent
+// unreliably and never resent, since a newer one is always on its way, and a lost packet only
+// means that the next one is encoded against an older baseline.
+//
+// Nodes are replicated with their local transforms, so they should be roots or children of nodes
+// that are placed the same way on every client. Clients that haven't been heard from within the
+// timeout are dropped. A snapshot has to fit in a single packet, which leaves room for about
+// seventy entities in a full snapshot and a few hundred moving ones in a delta.
+//
+// Usage of a Server:
+// - let mut server = try!(Server::bind("0.0.0.0:27015", 8));
+// - let id = server.replicate(node, PLAYER_KIND) for each node that clients should see.
+// - for event in server.update(dt, &scene) every frame, after the scene has been simulated.
+// - server.stop_replicating(id) before the node is removed from the scene.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::scene::{NodeId, Scene};
+use net::packet::{ByteWriter, Packet, MAX_PACKET_SIZE, NO_BASELINE};
+use net::snapshot::{EntityState, NetworkId, Snapshot};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+// The default number of snapshots taken a second.
+pub const DEFAULT_TICK_RATE: u16 = 20;
+
+// The default number of seconds without hearing from a client before it is dropped.
+pub const DEFAULT_TIMEOUT: f64 = 5.0;
+
+// The number of past snapshots kept as baselines. A client that hasn't acknowledged any of them
+// is sent full snapshots.
+const HISTORY_SIZE: usize = 32;
+
+// Most ticks that are caught up in one update() after a long frame.
+const MAX_TICKS_PER_UPDATE: usize = 5;
+
+// Something that happened to the server's clients during an update().
+#[derive(Clone, PartialEq, Debug)]
+pub enum ServerEvent {
+    Connected(u16, SocketAddr),
+    Disconnected(u16),
+}
+
+// A client that has joined the server.
+struct Connection {
+    id: u16,
+    addr: SocketAddr,
+    acked: Option<u32>,
+    idle: f64,
+}
+
+// A node that is replicated along with its network id and the kind given to it by the game.
+struct Replicated {
+    id: NetworkId,
+    node: NodeId,
+    kind: u16,
+}
+
+// Sends snapshots of replicated nodes to the clients that have connected to it.
+pub struct Server {
+    pub timeout: f64,
+    socket: UdpSocket,
+    tick_rate: u16,
+    max_clients: usize,
+    clients: Vec<Option<Connection>>,
+    replicated: Vec<Replicated>,
+    history: VecDeque<Snapshot>,
+    tick: u32,
+    accumulator: f64,
+    next_id: NetworkId,
+}
+
+impl Server {
+    // Creates a Server that listens on an address such as "0.0.0.0:27015" and accepts up to a
+    // number of clients. It takes DEFAULT_TICK_RATE snapshots a second.
+    pub fn bind(addr: &str, max_clients: usize) -> Result<Server, String> {
+        let socket = try!(UdpSocket::bind(addr)
+                .map_err(|e| format!("Unable to bind to {}: {}", addr, e)));
+        try!(socket.set_nonblocking(true).map_err(|e| e.to_string()));
+        Ok(Server { timeout: DEFAULT_TIMEOUT, socket: socket, tick_rate: DEFAULT_TICK_RATE,
+                max_clients: max_clients, clients: Vec::new(), replicated: Vec::new(),
+                history: VecDeque::new(), tick: 0, accumulator: 0.0, next_id: 0 })
+    }
+
+    // Gets the address that the server is listening on.
+    pub fn get_local_addr(&self) -> Result<SocketAddr, String> {
+        self.socket.local_addr().map_err(|e| e.to_string())
+    }
+
+    // Changes the number of snapshots taken a second. Clients that have already connected keep
+    // the old rate, so this should be set before any connect.
+    pub fn set_tick_rate(&mut self, tick_rate: u16) {
+        self.tick_rate = if tick_rate == 0 { 1 } else { tick_rate };
+    }
+
+    // Gets the number of snapshots taken a second.
+    pub fn get_tick_rate(&self) -> u16 {
+        self.tick_rate
+    }
+
+    // Gets the tick of the next snapshot.
+    pub fn get_tick(&self) -> u32 {
+        self.tick
+    }
+
+    // Gets the number of connected clients.
+    pub fn get_client_count(&self) -> usize {
+        self.clients.iter().filter(|c| c.is_some()).count()
+    }
+
+    // Starts replicating a node and returns its network id. The kind is sent to clients along
+    // with the node's first state so that they know what to show for it.
+    pub fn replicate(&mut self, node: NodeId, kind: u16) -> NetworkId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.replicated.push(Replicated { id: id, node: node, kind: kind });
+        id
+    }
+
+    // Stops replicating a node, which clients then remove. Returns false if the id isn't
+    // replicated.
+    pub fn stop_replicating(&mut self, id: NetworkId) -> bool {
+        let count = self.replicated.len();
+        self.replicated.retain(|r| r.id != id);
+        self.replicated.len() < count
+    }
+
+    // Gets the network id of a replicated node.
+    pub fn get_network_id(&self, node: NodeId) -> Option<NetworkId> {
+        self.replicated.iter().find(|r| r.node == node).map(|r| r.id)
+    }
+
+    // Disconnects every client.
+    pub fn shutdown(&mut self) {
+        let packet = Packet::Disconnect.encode();
+        for client in self.clients.iter().filter_map(|c| c.as_ref()) {
+            let _ = self.socket.send_to(&packet, client.addr);
+        }
+        self.clients.clear();
+    }
+
+    // Handles the packets that have arrived, drops clients that have timed out, and takes and
+    // sends a snapshot of the scene for each tick that has passed. Returns the clients that
+    // connected and disconnected.
+    pub fn update(&mut self, dt: f64, scene: &Scene) -> Vec<ServerEvent> {
+        let mut events = Vec::new();
+        for client in self.clients.iter_mut().filter_map(|c| c.as_mut()) {
+            client.idle += dt;
+        }
+        self.receive(&mut events);
+        let timeout = self.timeout;
+        for slot in self.clients.iter_mut() {
+            let timed_out = slot.as_ref().map_or(false, |c| c.idle > timeout);
+            if timed_out {
+                let client = slot.take().unwrap();
+                log_info!(Network, "Client {} at {} timed out.", client.id, client.addr);
+                events.push(ServerEvent::Disconnected(client.id));
+            }
+        }
+
+        let step = 1.0 / self.tick_rate as f64;
+        self.accumulator += dt;
+        let mut ticks = 0;
+        while self.accumulator >= step {
+            self.accumulator -= step;
+            ticks += 1;
+            if ticks > MAX_TICKS_PER_UPDATE {
+                self.accumulator = 0.0;
+                break;
+            }
+            self.send_snapshot(scene);
+        }
+        events
+    }
+
+    // Handles every packet that is waiting on the socket.
+    fn receive(&mut self, events: &mut Vec<ServerEvent>) {
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        loop {
+            let (size, addr) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    // Windows reports an unreachable client on the next receive, which can be
+                    // ignored since the client will time out. The rest wait for the next frame.
+                    log_debug!(Network, "Unable to receive: {}", e);
+                    return;
+                },
+            };
+            let packet = match Packet::decode(&buffer[..size]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    log_debug!(Network, "Ignoring a packet from {}: {}", addr, e);
+                    continue;
+                },
+            };
+            self.handle(packet, addr, events);
+        }
+    }
+
+    // Handles a packet from an address.
+    fn handle(&mut self, packet: Packet, addr: SocketAddr, events: &mut Vec<ServerEvent>) {
+        let index = self.clients.iter().position(|c| c.as_ref().map_or(false, |c| c.addr == addr));
+        match (packet, index) {
+            (Packet::Connect, Some(i)) => {
+                // The accept was lost, so send it again.
+                let client = self.clients[i].as_mut().unwrap();
+                client.idle = 0.0;
+                let accept = Packet::Accept { client: client.id, tick_rate: self.tick_rate };
+                let _ = self.socket.send_to(&accept.encode(), addr);
+            },
+            (Packet::Connect, None) => {
+                if self.get_client_count() >= self.max_clients {
+                    log_info!(Network, "Refusing {} since the server is full.", addr);
+                    let _ = self.socket.send_to(&Packet::Refuse.encode(), addr);
+                    return;
+                }
+                let slot = match self.clients.iter().position(|c| c.is_none()) {
+                    Some(slot) => slot,
+                    None => { self.clients.push(None); self.clients.len() - 1 },
+                };
+                let id = slot as u16;
+                self.clients[slot] = Some(Connection { id: id, addr: addr, acked: None,
+                        idle: 0.0 });
+                log_info!(Network, "Client {} connected from {}.", id, addr);
+                let accept = Packet::Accept { client: id, tick_rate: self.tick_rate };
+                let _ = self.socket.send_to(&accept.encode(), addr);
+                events.push(ServerEvent::Connected(id, addr));
+            },
+            (Packet::Ack { tick }, Some(i)) => {
+                let client = self.clients[i].as_mut().unwrap();
+                client.idle = 0.0;
+                if tick < self.tick && client.acked.map_or(true, |acked| tick > acked) {
+                    client.acked = Some(tick);
+                }
+            },
+            (Packet::Disconnect, Some(i)) => {
+                let client = self.clients[i].take().unwrap();
+                log_info!(Network, "Client {} disconnected.", client.id);
+                events.push(ServerEvent::Disconnected(client.id));
+            },
+            (packet, _) => log_debug!(Network, "Ignoring {:?} from {}.", packet, addr),
+        }
+    }
+
+    // Takes a snapshot of the replicated nodes and sends it to every client.
+    fn send_snapshot(&mut self, scene: &Scene) {
+        let states = self.replicated.iter().filter_map(|r| {
+            scene.get_node(r.node).map(|n| EntityState::from_transform(r.id, r.kind, &n.transform))
+        }).collect();
+        let snapshot = Snapshot::new(self.tick, states);
+        for client in self.clients.iter().filter_map(|c| c.as_ref()) {
+            let history = &self.history;
+            let baseline = client.acked.and_then(|tick| history.iter().find(|s| s.tick == tick));
+            let mut writer = ByteWriter::new();
+            snapshot.encode(baseline, &mut writer);
+            let packet = Packet::Snapshot { tick: snapshot.tick,
+                    baseline: baseline.map_or(NO_BASELINE, |b| b.tick), data: writer.bytes };
+            let bytes = packet.encode();
+            if bytes.len() > MAX_PACKET_SIZE {
+                log_warn!(Network, "Snapshot {} for client {} is {} bytes, which is too large to \
+                        send.", snapshot.tick, client.id, bytes.len());
+                continue;
+            }
+            if let Err(e) = self.socket.send_to(&bytes, client.addr) {
+                log_debug!(Network, "Unable to send to client {}: {}", client.id, e);
+            }
+        }
+        self.history.push_back(snapshot);
+        while self.history.len() > HISTORY_SIZE {
+            self.history.pop_front();
+        }
+        self.tick += 1;
+    }
+}
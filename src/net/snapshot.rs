@@ -0,0 +1,326 @@
+// Defines the snapshots that a Server takes of its replicated entities, and how they are delta
+// compressed against a baseline snapshot that the client already has. States are quantized when a
+// snapshot is taken so that both ends hold exactly the same numbers: positions are stored in
+// 1/512ths of a unit, rotations with the smallest three method in 32 bits (the largest component
+// of the quaternion is dropped, since it follows from the other three, and the rest are stored
+// with 10 bits each), and scales in 1/4096ths.
+//
+// An encoded snapshot lists the entities of the baseline that were removed, followed by the
+// entities that are new or have changed. Unchanged entities aren't written at all, and changed
+// positions are written as the difference from the baseline, which is small for anything that
+// moves smoothly. Without a baseline every entity is written as new.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::scene::Transform;
+use gfx::types::*;
+use net::packet::{ByteReader, ByteWriter};
+use std::f32;
+
+// Handle to an entity that is replicated by a Server, which is the same on every client.
+pub type NetworkId = u32;
+
+// The number of position units in one unit of the scene.
+const POSITION_SCALE: f32 = 512.0;
+
+// The number of scale units in a scale of 1.
+const SCALE_SCALE: f32 = 4096.0;
+
+// Bits for each stored component of a rotation.
+const ROTATION_BITS: u32 = 10;
+
+// Flags for what an encoded entity holds.
+const FLAG_NEW: u8 = 1;
+const FLAG_POSITION: u8 = 2;
+const FLAG_ROTATION: u8 = 4;
+const FLAG_SCALE: u8 = 8;
+
+// Quantizes a quaternion with the smallest three method.
+fn encode_rotation(rot: Quaternion) -> u32 {
+    let length = (rot.s * rot.s + rot.v.x * rot.v.x + rot.v.y * rot.v.y + rot.v.z * rot.v.z)
+            .sqrt();
+    let components = if length > 0.0 {
+        [rot.s / length, rot.v.x / length, rot.v.y / length, rot.v.z / length]
+    } else {
+        [1.0, 0.0, 0.0, 0.0]
+    };
+    let mut largest = 0;
+    for i in 1..4 {
+        if components[i].abs() > components[largest].abs() { largest = i; }
+    }
+    let sign = if components[largest] < 0.0 { -1.0 } else { 1.0 };
+    let max = ((1 << ROTATION_BITS) - 1) as f32;
+    let mut packed = (largest as u32) << (3 * ROTATION_BITS);
+    let mut shift = 2 * ROTATION_BITS;
+    for i in 0..4 {
+        if i == largest { continue; }
+        let normalized = (components[i] * sign * f32::consts::SQRT_2 + 1.0) * 0.5;
+        let q = (normalized * max).round();
+        let q = if q < 0.0 { 0.0 } else if q > max { max } else { q };
+        packed |= (q as u32) << shift;
+        if shift > 0 { shift -= ROTATION_BITS; }
+    }
+    packed
+}
+
+// Unpacks a quaternion that was quantized by encode_rotation().
+fn decode_rotation(packed: u32) -> Quaternion {
+    let largest = (packed >> (3 * ROTATION_BITS)) as usize & 3;
+    let max = ((1 << ROTATION_BITS) - 1) as f32;
+    let mut components = [0.0f32; 4];
+    let mut shift = 2 * ROTATION_BITS;
+    let mut sum = 0.0;
+    for i in 0..4 {
+        if i == largest { continue; }
+        let q = ((packed >> shift) & ((1 << ROTATION_BITS) - 1)) as f32;
+        components[i] = (q / max * 2.0 - 1.0) / f32::consts::SQRT_2;
+        sum += components[i] * components[i];
+        if shift > 0 { shift -= ROTATION_BITS; }
+    }
+    components[largest] = if sum < 1.0 { (1.0 - sum).sqrt() } else { 0.0 };
+    Quaternion::new(components[0], components[1], components[2], components[3])
+}
+
+// The quantized state of a replicated entity. The kind is chosen by the game, such as which
+// prefab a client should spawn for the entity.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EntityState {
+    pub id: NetworkId,
+    pub kind: u16,
+    pub pos: [i32; 3],
+    pub rot: u32,
+    pub scale: [i32; 3],
+}
+
+impl EntityState {
+    // Quantizes a transform.
+    pub fn from_transform(id: NetworkId, kind: u16, transform: &Transform) -> EntityState {
+        let p = transform.pos;
+        let s = transform.scale;
+        EntityState { id: id, kind: kind,
+                pos: [(p.x * POSITION_SCALE).round() as i32, (p.y * POSITION_SCALE).round() as i32,
+                        (p.z * POSITION_SCALE).round() as i32],
+                rot: encode_rotation(transform.rot),
+                scale: [(s.x * SCALE_SCALE).round() as i32, (s.y * SCALE_SCALE).round() as i32,
+                        (s.z * SCALE_SCALE).round() as i32] }
+    }
+
+    // Gets the transform that the state was quantized from, to within the precision it keeps.
+    pub fn get_transform(&self) -> Transform {
+        Transform::new(
+                Vector3D::new(self.pos[0] as f32 / POSITION_SCALE,
+                        self.pos[1] as f32 / POSITION_SCALE, self.pos[2] as f32 / POSITION_SCALE),
+                decode_rotation(self.rot),
+                Vector3D::new(self.scale[0] as f32 / SCALE_SCALE,
+                        self.scale[1] as f32 / SCALE_SCALE, self.scale[2] as f32 / SCALE_SCALE))
+    }
+
+    // Writes the state in full.
+    fn write_full(&self, writer: &mut ByteWriter) {
+        writer.write_varint(self.id);
+        writer.write_u8(FLAG_NEW);
+        writer.write_varint(self.kind as u32);
+        for i in 0..3 { writer.write_signed(self.pos[i]); }
+        writer.write_u32(self.rot);
+        for i in 0..3 { writer.write_signed(self.scale[i]); }
+    }
+
+    // Writes the parts of the state that differ from an earlier state of the same entity. Returns
+    // false without writing anything if nothing has changed.
+    fn write_delta(&self, base: &EntityState, writer: &mut ByteWriter) -> bool {
+        if self.kind != base.kind { self.write_full(writer); return true; }
+        let mut flags = 0;
+        if self.pos != base.pos { flags |= FLAG_POSITION; }
+        if self.rot != base.rot { flags |= FLAG_ROTATION; }
+        if self.scale != base.scale { flags |= FLAG_SCALE; }
+        if flags == 0 { return false; }
+        writer.write_varint(self.id);
+        writer.write_u8(flags);
+        if flags & FLAG_POSITION != 0 {
+            for i in 0..3 { writer.write_signed(self.pos[i].wrapping_sub(base.pos[i])); }
+        }
+        if flags & FLAG_ROTATION != 0 { writer.write_u32(self.rot); }
+        if flags & FLAG_SCALE != 0 {
+            for i in 0..3 { writer.write_signed(self.scale[i]); }
+        }
+        true
+    }
+
+    // Reads a state written by write_full() or write_delta(). A delta needs the earlier state that
+    // it was written against.
+    fn read(reader: &mut ByteReader, base: Option<&EntityState>) -> Result<EntityState, String> {
+        let id = try!(reader.read_varint());
+        let flags = try!(reader.read_u8());
+        if flags & FLAG_NEW != 0 {
+            let kind = try!(reader.read_varint());
+            if kind > 0xFFFF { return Err(format!("Entity {} has an invalid kind.", id)); }
+            let mut state = EntityState { id: id, kind: kind as u16, pos: [0; 3], rot: 0,
+                    scale: [0; 3] };
+            for i in 0..3 { state.pos[i] = try!(reader.read_signed()); }
+            state.rot = try!(reader.read_u32());
+            for i in 0..3 { state.scale[i] = try!(reader.read_signed()); }
+            return Ok(state);
+        }
+        let mut state = match base {
+            Some(base) if base.id == id => *base,
+            _ => return Err(format!("Entity {} changed without being in the baseline.", id)),
+        };
+        if flags & FLAG_POSITION != 0 {
+            for i in 0..3 { state.pos[i] = state.pos[i].wrapping_add(try!(reader.read_signed())); }
+        }
+        if flags & FLAG_ROTATION != 0 { state.rot = try!(reader.read_u32()); }
+        if flags & FLAG_SCALE != 0 {
+            for i in 0..3 { state.scale[i] = try!(reader.read_signed()); }
+        }
+        Ok(state)
+    }
+}
+
+// The states of every replicated entity at a tick, sorted by id.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub entities: Vec<EntityState>,
+}
+
+impl Snapshot {
+    // Creates a snapshot from states, which are sorted by id.
+    pub fn new(tick: u32, mut entities: Vec<EntityState>) -> Snapshot {
+        entities.sort_by(|a, b| a.id.cmp(&b.id));
+        Snapshot { tick: tick, entities: entities }
+    }
+
+    // Gets the state of an entity.
+    pub fn get(&self, id: NetworkId) -> Option<&EntityState> {
+        self.entities.binary_search_by(|e| e.id.cmp(&id)).ok().map(|i| &self.entities[i])
+    }
+
+    // Writes the snapshot as changes from a baseline, or in full without one.
+    pub fn encode(&self, baseline: Option<&Snapshot>, writer: &mut ByteWriter) {
+        let empty = Vec::new();
+        let base = baseline.map_or(&empty, |b| &b.entities);
+        let removed: Vec<NetworkId> = base.iter().map(|e| e.id)
+                .filter(|&id| self.get(id).is_none()).collect();
+        writer.write_varint(removed.len() as u32);
+        for id in removed {
+            writer.write_varint(id);
+        }
+
+        let mut changes = ByteWriter::new();
+        let mut count = 0;
+        for state in self.entities.iter() {
+            let written = match baseline.and_then(|b| b.get(state.id)) {
+                Some(previous) => state.write_delta(previous, &mut changes),
+                None => { state.write_full(&mut changes); true },
+            };
+            if written { count += 1; }
+        }
+        writer.write_varint(count);
+        writer.bytes.extend(changes.bytes);
+    }
+
+    // Reads a snapshot at a tick that was written by encode() against a baseline, which must be
+    // the same one that it was written against.
+    pub fn decode(tick: u32, bytes: &[u8], baseline: Option<&Snapshot>)
+            -> Result<Snapshot, String> {
+        let mut reader = ByteReader::new(bytes);
+        let mut entities = baseline.map_or(Vec::new(), |b| b.entities.clone());
+        let removed = try!(reader.read_varint());
+        for _ in 0..removed {
+            let id = try!(reader.read_varint());
+            entities.retain(|e| e.id != id);
+        }
+        let count = try!(reader.read_varint());
+        for _ in 0..count {
+            let state = {
+                let mut peek = ByteReader::new(reader.get_remaining());
+                let id = try!(peek.read_varint());
+                let base = baseline.and_then(|b| b.get(id));
+                try!(EntityState::read(&mut reader, base))
+            };
+            match entities.binary_search_by(|e| e.id.cmp(&state.id)) {
+                Ok(i) => entities[i] = state,
+                Err(i) => entities.insert(i, state),
+            }
+        }
+        if !reader.get_remaining().is_empty() {
+            return Err("Snapshot has extra bytes.".to_string());
+        }
+        Ok(Snapshot { tick: tick, entities: entities })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Creates the state of an entity at a position that isn't rotated or scaled.
+    fn state(id: NetworkId, x: f32) -> EntityState {
+        let transform = Transform::new(Vector3D::new(x, 2.0, -3.0),
+                Quaternion::new(1.0, 0.0, 0.0, 0.0), Vector3D::new(1.0, 1.0, 1.0));
+        EntityState::from_transform(id, 1, &transform)
+    }
+
+    // Encodes a snapshot against a baseline.
+    fn encode(snapshot: &Snapshot, baseline: Option<&Snapshot>) -> Vec<u8> {
+        let mut writer = ByteWriter::new();
+        snapshot.encode(baseline, &mut writer);
+        writer.bytes
+    }
+
+    #[test]
+    fn quantizes_transforms() {
+        let rot = Quaternion::new(0.8, 0.0, 0.6, 0.0);
+        let transform = Transform::new(Vector3D::new(1.25, -4.0, 100.5), rot,
+                Vector3D::new(2.0, 0.5, 1.0));
+        let decoded = EntityState::from_transform(4, 2, &transform).get_transform();
+        assert_eq!(decoded.pos, transform.pos);
+        assert_eq!(decoded.scale, transform.scale);
+        assert!((decoded.rot.s - rot.s).abs() < 0.002 && (decoded.rot.v.y - rot.v.y).abs() < 0.002);
+    }
+
+    #[test]
+    fn round_trips_full_snapshots() {
+        let snapshot = Snapshot::new(10, vec![state(7, 1.0), state(3, 2.0)]);
+        let bytes = encode(&snapshot, None);
+        assert_eq!(Snapshot::decode(10, &bytes, None), Ok(snapshot));
+    }
+
+    #[test]
+    fn round_trips_deltas() {
+        let baseline = Snapshot::new(10, vec![state(1, 1.0), state(2, 2.0), state(3, 3.0)]);
+        let snapshot = Snapshot::new(11, vec![state(1, 1.0), state(3, 3.5), state(4, 4.0)]);
+        let bytes = encode(&snapshot, Some(&baseline));
+        // Entity 2 is removed, 3 moved, 4 is new, and 1 isn't written at all.
+        assert!(bytes.len() < encode(&snapshot, None).len());
+        assert_eq!(Snapshot::decode(11, &bytes, Some(&baseline)), Ok(snapshot.clone()));
+        // Decoding against the wrong baseline fails rather than making up the moved entity.
+        assert!(Snapshot::decode(11, &bytes, None).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_snapshots() {
+        let baseline = Snapshot::new(10, vec![state(1, 1.0), state(2, 2.0)]);
+        let snapshot = Snapshot::new(11, vec![state(1, 1.5), state(5, 5.0)]);
+        let bytes = encode(&snapshot, Some(&baseline));
+        for length in 0..bytes.len() {
+            assert!(Snapshot::decode(11, &bytes[..length], Some(&baseline)).is_err());
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(Snapshot::decode(11, &extra, Some(&baseline)).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_counts() {
+        // Counts that claim far more entities than the bytes hold run out of bytes.
+        let mut writer = ByteWriter::new();
+        writer.write_varint(0);
+        writer.write_varint(0xFFFFFFFF);
+        assert!(Snapshot::decode(1, &writer.bytes, None).is_err());
+        let mut writer = ByteWriter::new();
+        writer.write_varint(0xFFFFFFFF);
+        assert!(Snapshot::decode(1, &writer.bytes, None).is_err());
+    }
+}
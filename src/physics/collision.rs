@@ -0,0 +1,542 @@
+// Defines the narrowphase, which tests a pair of placed shapes for contact. Pairs of spheres and
+// capsules are tested directly from the closest points of their cores. Every other pair goes
+// through GJK, which finds the closest points between the cores of two convex shapes from their
+// support functions, and the contact comes from those points and the shapes' margins. If the cores
+// themselves overlap, EPA expands the simplex that GJK ended with into a polytope until it finds
+// the face of the Minkowski difference closest to the origin, which gives the normal and depth.
+//
+// Contacts are a single point, which is enough for overlap queries and triggers. Resting contact
+// between flat faces needs more than one point, which the physics world builds up over several
+// frames. Casts move a shape along a direction until it first touches another, which is how rays
+// and sphere casts find what they hit.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::bounds::Aabb;
+use gfx::types::*;
+use physics::shape::{Isometry, Shape};
+use self::cgmath::{EuclideanVector, Vector};
+
+// Distances below this are treated as touching.
+const EPSILON: GLfloat = 1e-5;
+
+// The most iterations GJK and EPA run for before taking the best answer so far.
+const MAX_GJK_ITERATIONS: usize = 64;
+const MAX_EPA_ITERATIONS: usize = 64;
+
+// How close a cast has to get before it counts as a hit, and the most steps it takes to get there.
+const CAST_TOLERANCE: GLfloat = 1e-4;
+const MAX_CAST_ITERATIONS: usize = 32;
+
+// A contact between two shapes. The normal points from the first shape towards the second, the
+// depth is how far the second shape would have to move along the normal to stop touching, and the
+// point is halfway between the two surfaces, all in world space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Contact {
+    pub point: Vector3D,
+    pub normal: Vector3D,
+    pub depth: GLfloat,
+}
+
+impl Contact {
+    // Gets the same contact from the point of view of the other shape.
+    pub fn flipped(&self) -> Contact {
+        Contact { point: self.point, normal: -self.normal, depth: self.depth }
+    }
+}
+
+// Where a shape moving along a direction first touched another. The distance is how far it moved,
+// and the point and normal are on the surface of the shape that was hit, in world space.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct CastHit {
+    pub distance: GLfloat,
+    pub point: Vector3D,
+    pub normal: Vector3D,
+}
+
+// Tests two placed shapes for contact, returning the contact if they touch. Two concave shapes
+// never touch, since neither can be moved by the other.
+pub fn collide(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry) -> Option<Contact> {
+    match (a.is_concave(), b.is_concave()) {
+        (true, true) => { return None; },
+        (true, false) => { return collide_concave(a, iso_a, b, iso_b); },
+        (false, true) => { return collide_concave(b, iso_b, a, iso_a).map(|c| c.flipped()); },
+        _ => (),
+    }
+    match (core_segment(a, iso_a), core_segment(b, iso_b)) {
+        (Some((a0, a1)), Some((b0, b1))) => {
+            let (pa, pb) = closest_points_segments(a0, a1, b0, b1);
+            let dir = iso_b.pos - iso_a.pos;
+            contact_from_closest(pa, pb, a.margin(), b.margin(), dir)
+        },
+        _ => collide_convex(a, iso_a, b, iso_b),
+    }
+}
+
+// Returns true if two placed shapes overlap.
+pub fn intersects(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry) -> bool {
+    collide(a, iso_a, b, iso_b).is_some()
+}
+
+// Moves shape a along a normalized direction until it touches shape b, returning the hit if it does
+// within a distance. This is conservative advancement: each step moves shape a forward by the gap
+// between the shapes over how fast the gap is closing, which can't carry it through shape b. A
+// ray is a cast of a sphere with no radius. Shapes that overlap to begin with hit at a distance of
+// 0 with the normal facing back along the direction.
+pub fn cast(a: &Shape, iso_a: &Isometry, dir: Vector3D, max_dist: GLfloat, b: &Shape,
+        iso_b: &Isometry) -> Option<CastHit> {
+    if a.is_concave() { return None; }
+    if b.is_concave() { return cast_concave(a, iso_a, dir, max_dist, b, iso_b); }
+    let margin = a.margin() + b.margin();
+    let mut normal = -dir;
+    let mut t = 0.0;
+    for _ in 0..MAX_CAST_ITERATIONS {
+        let moved = Isometry::new(iso_a.pos + dir * t, iso_a.rot);
+        let (pa, pb) = match gjk(a, &moved, b, iso_b) {
+            Gjk::Separated(pa, pb) => (pa, pb),
+            Gjk::Overlapping(_) => {
+                // Cores with no margin only overlap once the last step lands exactly on the
+                // surface.
+                if t == 0.0 {
+                    return Some(CastHit { distance: 0.0, point: iso_a.pos, normal: -dir });
+                }
+                let local = a.support(moved.inverse_transform_vector(-normal));
+                let point = moved.transform_point(local);
+                return Some(CastHit { distance: t, point: point, normal: normal });
+            },
+        };
+        let delta = pa - pb;
+        let length = delta.length();
+        // Keep the last normal once the cores are too close for the gap to have a direction.
+        if length > CAST_TOLERANCE { normal = delta / length; }
+        let gap = length - margin;
+        if gap <= CAST_TOLERANCE {
+            if t == 0.0 && gap < 0.0 {
+                return Some(CastHit { distance: 0.0, point: iso_a.pos, normal: -dir });
+            }
+            return Some(CastHit { distance: t, point: pb + normal * b.margin(), normal: normal });
+        }
+        let closing = -dir.dot(normal);
+        if closing <= EPSILON { return None; }
+        t += gap / closing;
+        if t > max_dist { return None; }
+    }
+    None
+}
+
+// Tests a convex shape against the triangles of a concave shape near it, returning the deepest
+// contact with the normal pointing from the concave shape to the convex one.
+fn collide_concave(concave: &Shape, iso_c: &Isometry, convex: &Shape, iso: &Isometry)
+        -> Option<Contact> {
+    // Work in the concave shape's local space, where its triangles are.
+    let local = Isometry::new(iso_c.inverse_transform_point(iso.pos),
+            iso_c.rot.conjugate() * iso.rot);
+    let identity = Isometry::identity();
+    let mut deepest: Option<Contact> = None;
+    concave.query_triangles(&convex.compute_bounds(&local), |points| {
+        let triangle = Shape::Triangle { points: points };
+        if let Some(contact) = collide(&triangle, &identity, convex, &local) {
+            if deepest.map_or(true, |d| contact.depth > d.depth) { deepest = Some(contact); }
+        }
+    });
+    deepest.map(|c| Contact { point: iso_c.transform_point(c.point),
+            normal: iso_c.transform_vector(c.normal), depth: c.depth })
+}
+
+// Casts a convex shape against the triangles of a concave shape along its path, returning the
+// nearest hit.
+fn cast_concave(a: &Shape, iso_a: &Isometry, dir: Vector3D, max_dist: GLfloat, concave: &Shape,
+        iso_c: &Isometry) -> Option<CastHit> {
+    let local = Isometry::new(iso_c.inverse_transform_point(iso_a.pos),
+            iso_c.rot.conjugate() * iso_a.rot);
+    let local_dir = iso_c.inverse_transform_vector(dir);
+    let start = a.compute_bounds(&local);
+    let end = Aabb::new(start.min + local_dir * max_dist, start.max + local_dir * max_dist);
+    let identity = Isometry::identity();
+    let mut nearest: Option<CastHit> = None;
+    concave.query_triangles(&start.union(&end), |points| {
+        let limit = nearest.map_or(max_dist, |n| n.distance);
+        let triangle = Shape::Triangle { points: points };
+        if let Some(hit) = cast(a, &local, local_dir, limit, &triangle, &identity) {
+            if nearest.map_or(true, |n| hit.distance < n.distance) { nearest = Some(hit); }
+        }
+    });
+    nearest.map(|h| CastHit { distance: h.distance, point: iso_c.transform_point(h.point),
+            normal: iso_c.transform_vector(h.normal) })
+}
+
+// Gets the core of a sphere or capsule in world space as a segment, which is a single point for a
+// sphere.
+fn core_segment(shape: &Shape, iso: &Isometry) -> Option<(Vector3D, Vector3D)> {
+    match *shape {
+        Shape::Sphere { .. } => Some((iso.pos, iso.pos)),
+        Shape::Capsule { half_height, .. } => {
+            let axis = iso.transform_vector(Vector3D::new(0.0, 0.0, half_height));
+            Some((iso.pos - axis, iso.pos + axis))
+        },
+        _ => None,
+    }
+}
+
+// Builds the contact between two cores rounded by margins from their closest points. If the cores
+// touch, the normal falls back to a direction between the shapes.
+fn contact_from_closest(pa: Vector3D, pb: Vector3D, margin_a: GLfloat, margin_b: GLfloat,
+        fallback: Vector3D) -> Option<Contact> {
+    let delta = pb - pa;
+    let dist = delta.length();
+    let radius = margin_a + margin_b;
+    if dist > radius { return None; }
+    let normal = if dist > EPSILON { delta / dist } else {
+        if fallback.length2() > EPSILON * EPSILON { fallback.normalize() } else {
+            Vector3D::unit_z()
+        }
+    };
+    let surface_a = pa + normal * margin_a;
+    let surface_b = pb - normal * margin_b;
+    Some(Contact { point: (surface_a + surface_b) * 0.5, normal: normal, depth: radius - dist })
+}
+
+// Gets the closest points between segments p0-p1 and q0-q1, from Real-Time Collision Detection.
+pub fn closest_points_segments(p0: Vector3D, p1: Vector3D, q0: Vector3D, q1: Vector3D)
+        -> (Vector3D, Vector3D) {
+    let (d1, d2, r) = (p1 - p0, q1 - q0, p0 - q0);
+    let (a, e, f) = (d1.length2(), d2.length2(), d2.dot(r));
+    let clamp = |x: GLfloat| x.max(0.0).min(1.0);
+    let (s, t);
+    if a <= EPSILON && e <= EPSILON {
+        return (p0, q0);
+    } else if a <= EPSILON {
+        s = 0.0;
+        t = clamp(f / e);
+    } else {
+        let c = d1.dot(r);
+        if e <= EPSILON {
+            t = 0.0;
+            s = clamp(-c / a);
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            let s0 = if denom > EPSILON { clamp((b * f - c * e) / denom) } else { 0.0 };
+            let t0 = (b * s0 + f) / e;
+            if t0 < 0.0 {
+                t = 0.0;
+                s = clamp(-c / a);
+            } else if t0 > 1.0 {
+                t = 1.0;
+                s = clamp((b - c) / a);
+            } else {
+                t = t0;
+                s = s0;
+            }
+        }
+    }
+    (p0 + d1 * s, q0 + d2 * t)
+}
+
+// A point of the Minkowski difference of two cores along with the points of each core it came
+// from.
+#[derive(Copy, Clone, Debug)]
+struct SupportPoint {
+    w: Vector3D,
+    a: Vector3D,
+    b: Vector3D,
+}
+
+// Gets the support point of the Minkowski difference of the cores of two placed shapes.
+fn support(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry, dir: Vector3D)
+        -> SupportPoint {
+    let pa = iso_a.transform_point(a.core_support(iso_a.inverse_transform_vector(dir)));
+    let pb = iso_b.transform_point(b.core_support(iso_b.inverse_transform_vector(-dir)));
+    SupportPoint { w: pa - pb, a: pa, b: pb }
+}
+
+// The result of running GJK on two cores.
+enum Gjk {
+    // The closest points of the two cores.
+    Separated(Vector3D, Vector3D),
+    // The cores overlap, along with the last simplex.
+    Overlapping(Vec<SupportPoint>),
+}
+
+// Tests any two convex shapes through GJK and EPA.
+fn collide_convex(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry) -> Option<Contact> {
+    let (margin_a, margin_b) = (a.margin(), b.margin());
+    let center_dir = iso_b.pos - iso_a.pos;
+    match gjk(a, iso_a, b, iso_b) {
+        Gjk::Separated(pa, pb) => {
+            if (pb - pa).length2() > EPSILON * EPSILON {
+                return contact_from_closest(pa, pb, margin_a, margin_b, center_dir);
+            }
+            let simplex = vec![support(a, iso_a, b, iso_b, -center_dir)];
+            epa(a, iso_a, b, iso_b, simplex, margin_a, margin_b)
+        },
+        Gjk::Overlapping(simplex) => epa(a, iso_a, b, iso_b, simplex, margin_a, margin_b),
+    }
+}
+
+// Finds the closest points between the cores of two shapes or reports that they overlap.
+fn gjk(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry) -> Gjk {
+    let mut dir = iso_a.pos - iso_b.pos;
+    if dir.length2() < EPSILON * EPSILON { dir = Vector3D::unit_x(); }
+    let mut simplex: Vec<(SupportPoint, GLfloat)> = vec![(support(a, iso_a, b, iso_b, -dir), 1.0)];
+    let mut closest = simplex[0].0.w;
+    for _ in 0..MAX_GJK_ITERATIONS {
+        let dist2 = closest.length2();
+        if dist2 < EPSILON * EPSILON {
+            return Gjk::Overlapping(simplex.into_iter().map(|s| s.0).collect());
+        }
+        let p = support(a, iso_a, b, iso_b, -closest);
+        // Stop once the new support point can't get any closer to the origin.
+        if dist2 - closest.dot(p.w) <= 1e-6 * dist2.max(1.0) { break; }
+        let mut points: Vec<SupportPoint> = simplex.iter().map(|s| s.0).collect();
+        points.push(p);
+        let reduced = closest_on_simplex(&points);
+        let next = reduced.iter().fold(Vector3D::new(0.0, 0.0, 0.0), |v, s| v + s.0.w * s.1);
+        if reduced.len() == 4 {
+            return Gjk::Overlapping(reduced.into_iter().map(|s| s.0).collect());
+        }
+        let improved = next.length2() < dist2;
+        simplex = reduced;
+        closest = next;
+        if !improved { break; }
+    }
+    let pa = simplex.iter().fold(Vector3D::new(0.0, 0.0, 0.0), |v, s| v + s.0.a * s.1);
+    let pb = simplex.iter().fold(Vector3D::new(0.0, 0.0, 0.0), |v, s| v + s.0.b * s.1);
+    Gjk::Separated(pa, pb)
+}
+
+// Finds the point of a simplex of up to four points closest to the origin. Returns the smallest
+// part of the simplex that contains it along with the barycentric weight of each of its points.
+fn closest_on_simplex(points: &[SupportPoint]) -> Vec<(SupportPoint, GLfloat)> {
+    match points.len() {
+        1 => vec![(points[0], 1.0)],
+        2 => closest_on_segment(points[0], points[1]),
+        3 => closest_on_triangle(points[0], points[1], points[2]),
+        _ => closest_on_tetrahedron(points[0], points[1], points[2], points[3]),
+    }
+}
+
+fn closest_on_segment(a: SupportPoint, b: SupportPoint) -> Vec<(SupportPoint, GLfloat)> {
+    let ab = b.w - a.w;
+    let len2 = ab.length2();
+    if len2 < EPSILON * EPSILON { return vec![(a, 1.0)]; }
+    let t = -a.w.dot(ab) / len2;
+    if t <= 0.0 {
+        vec![(a, 1.0)]
+    } else if t >= 1.0 {
+        vec![(b, 1.0)]
+    } else {
+        vec![(a, 1.0 - t), (b, t)]
+    }
+}
+
+// Finds the closest point of a triangle to the origin by its Voronoi regions, from Real-Time
+// Collision Detection.
+fn closest_on_triangle(a: SupportPoint, b: SupportPoint, c: SupportPoint)
+        -> Vec<(SupportPoint, GLfloat)> {
+    let (ab, ac, ap) = (b.w - a.w, c.w - a.w, -a.w);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 { return vec![(a, 1.0)]; }
+    let bp = -b.w;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 { return vec![(b, 1.0)]; }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return vec![(a, 1.0 - v), (b, v)];
+    }
+    let cp = -c.w;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 { return vec![(c, 1.0)]; }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return vec![(a, 1.0 - w), (c, w)];
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return vec![(b, 1.0 - w), (c, w)];
+    }
+    let denom = va + vb + vc;
+    if denom.abs() < EPSILON * EPSILON { return closest_on_segment(a, b); }
+    let (v, w) = (vb / denom, vc / denom);
+    vec![(a, 1.0 - v - w), (b, v), (c, w)]
+}
+
+// Finds the closest point of a tetrahedron to the origin, which is the whole tetrahedron if the
+// origin is inside of it.
+fn closest_on_tetrahedron(a: SupportPoint, b: SupportPoint, c: SupportPoint, d: SupportPoint)
+        -> Vec<(SupportPoint, GLfloat)> {
+    let faces = [(a, b, c, d), (a, c, d, b), (a, d, b, c), (b, d, c, a)];
+    let mut best: Option<(Vec<(SupportPoint, GLfloat)>, GLfloat)> = None;
+    let mut inside = true;
+    for &(p, q, r, opposite) in &faces {
+        let normal = (q.w - p.w).cross(r.w - p.w);
+        let origin_side = -p.w.dot(normal);
+        let opposite_side = (opposite.w - p.w).dot(normal);
+        // The origin is outside of the face if it is on the other side from the opposite point.
+        if origin_side * opposite_side >= 0.0 && opposite_side.abs() > EPSILON * EPSILON {
+            continue;
+        }
+        inside = false;
+        let found = closest_on_triangle(p, q, r);
+        let point = found.iter().fold(Vector3D::new(0.0, 0.0, 0.0), |v, s| v + s.0.w * s.1);
+        let dist2 = point.length2();
+        if best.as_ref().map_or(true, |b| dist2 < b.1) {
+            best = Some((found, dist2));
+        }
+    }
+    if inside {
+        return vec![(a, 0.25), (b, 0.25), (c, 0.25), (d, 0.25)];
+    }
+    best.unwrap().0
+}
+
+// A face of the EPA polytope with its outward normal and distance from the origin.
+struct Face {
+    indices: [usize; 3],
+    normal: Vector3D,
+    dist: GLfloat,
+}
+
+// Builds a face of the polytope from three of its points, or None if they are degenerate.
+fn make_face(points: &[SupportPoint], i: usize, j: usize, k: usize) -> Option<Face> {
+    let normal = (points[j].w - points[i].w).cross(points[k].w - points[i].w);
+    if normal.length2() < EPSILON * EPSILON * EPSILON { return None; }
+    let normal = normal.normalize();
+    Some(Face { indices: [i, j, k], normal: normal, dist: normal.dot(points[i].w) })
+}
+
+// Finds the contact between two shapes whose cores overlap by expanding the simplex from GJK into
+// a polytope around the origin.
+fn epa(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry, simplex: Vec<SupportPoint>,
+        margin_a: GLfloat, margin_b: GLfloat) -> Option<Contact> {
+    let mut points = simplex;
+    let fallback = iso_b.pos - iso_a.pos;
+    if !complete_tetrahedron(a, iso_a, b, iso_b, &mut points) {
+        // The difference is flat, so the cores are only touching.
+        return contact_from_closest(points[0].a, points[0].b, margin_a, margin_b, fallback);
+    }
+
+    // Orient the starting faces outward from the center of the tetrahedron.
+    let center = (points[0].w + points[1].w + points[2].w + points[3].w) * 0.25;
+    let mut faces: Vec<Face> = Vec::new();
+    for &(i, j, k) in &[(0, 1, 2), (0, 3, 1), (0, 2, 3), (1, 3, 2)] {
+        let face = match make_face(&points, i, j, k) {
+            Some(face) => face,
+            None => { continue; },
+        };
+        if face.normal.dot(points[i].w - center) < 0.0 {
+            faces.extend(make_face(&points, i, k, j));
+        } else {
+            faces.push(face);
+        }
+    }
+    if faces.is_empty() { return None; }
+
+    let mut closest = 0;
+    for _ in 0..MAX_EPA_ITERATIONS {
+        closest = (0..faces.len()).fold(0, |best, i| {
+            if faces[i].dist < faces[best].dist { i } else { best }
+        });
+        let normal = faces[closest].normal;
+        let p = support(a, iso_a, b, iso_b, normal);
+        if p.w.dot(normal) - faces[closest].dist < 1e-4 { break; }
+
+        // Remove every face that can see the new point and stitch the hole closed with faces from
+        // its boundary to the new point.
+        let index = points.len();
+        points.push(p);
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        let mut kept: Vec<Face> = Vec::with_capacity(faces.len());
+        for face in faces {
+            if face.normal.dot(p.w - points[face.indices[0]].w) > 0.0 {
+                for &(i, j) in &[(0, 1), (1, 2), (2, 0)] {
+                    let edge = (face.indices[i], face.indices[j]);
+                    match edges.iter().position(|e| *e == (edge.1, edge.0)) {
+                        Some(shared) => { edges.swap_remove(shared); },
+                        None => { edges.push(edge); },
+                    }
+                }
+            } else {
+                kept.push(face);
+            }
+        }
+        faces = kept;
+        for (i, j) in edges {
+            faces.extend(make_face(&points, i, j, index));
+        }
+        if faces.is_empty() { return None; }
+    }
+
+    // The contact comes from where the origin projects onto the closest face.
+    let face = &faces[closest];
+    let (i, j, k) = (face.indices[0], face.indices[1], face.indices[2]);
+    let (u, v, w) = barycentric(face.normal * face.dist, points[i].w, points[j].w, points[k].w);
+    let pa = points[i].a * u + points[j].a * v + points[k].a * w;
+    let pb = points[i].b * u + points[j].b * v + points[k].b * w;
+    let normal = face.normal;
+    let surface_a = pa + normal * margin_a;
+    let surface_b = pb - normal * margin_b;
+    Some(Contact { point: (surface_a + surface_b) * 0.5, normal: normal,
+            depth: face.dist + margin_a + margin_b })
+}
+
+// Adds support points to a simplex until it is a tetrahedron with volume. Returns false if the
+// Minkowski difference is too flat to have one.
+fn complete_tetrahedron(a: &Shape, iso_a: &Isometry, b: &Shape, iso_b: &Isometry,
+        points: &mut Vec<SupportPoint>) -> bool {
+    let axes = [Vector3D::unit_x(), Vector3D::unit_y(), Vector3D::unit_z()];
+    if points.len() == 1 {
+        for axis in axes.iter().flat_map(|a| vec![*a, -*a]) {
+            let p = support(a, iso_a, b, iso_b, axis);
+            if (p.w - points[0].w).length2() > EPSILON {
+                points.push(p);
+                break;
+            }
+        }
+    }
+    if points.len() == 2 {
+        let line = points[1].w - points[0].w;
+        for axis in &axes {
+            let perpendicular = line.cross(*axis);
+            if perpendicular.length2() < EPSILON { continue; }
+            let p = support(a, iso_a, b, iso_b, perpendicular);
+            let q = support(a, iso_a, b, iso_b, -perpendicular);
+            let p = if line.cross(p.w - points[0].w).length2() >
+                    line.cross(q.w - points[0].w).length2() { p } else { q };
+            if line.cross(p.w - points[0].w).length2() > EPSILON {
+                points.push(p);
+                break;
+            }
+        }
+    }
+    if points.len() == 3 {
+        let normal = (points[1].w - points[0].w).cross(points[2].w - points[0].w);
+        let p = support(a, iso_a, b, iso_b, normal);
+        let q = support(a, iso_a, b, iso_b, -normal);
+        let p = if (p.w - points[0].w).dot(normal).abs() >
+                (q.w - points[0].w).dot(normal).abs() { p } else { q };
+        points.push(p);
+    }
+    if points.len() < 4 { return false; }
+    let volume = (points[1].w - points[0].w).cross(points[2].w - points[0].w)
+            .dot(points[3].w - points[0].w);
+    volume.abs() > EPSILON * EPSILON
+}
+
+// Gets the barycentric coordinates of a point in a triangle.
+fn barycentric(p: Vector3D, a: Vector3D, b: Vector3D, c: Vector3D) -> (GLfloat, GLfloat, GLfloat) {
+    let (v0, v1, v2) = (b - a, c - a, p - a);
+    let (d00, d01, d11) = (v0.dot(v0), v0.dot(v1), v1.dot(v1));
+    let (d20, d21) = (v2.dot(v0), v2.dot(v1));
+    let denom = d00 * d11 - d01 * d01;
+    if denom.abs() < EPSILON * EPSILON { return (1.0, 0.0, 0.0); }
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    (1.0 - v - w, v, w)
+}
@@ -0,0 +1,195 @@
+// Defines a CollisionWorld, which holds placed shapes called colliders and finds the ones that
+// touch. The broadphase keeps the bounds of every collider in a Bvh, so finding the pairs that
+// might touch only compares colliders whose bounds overlap instead of every pair, and only those
+// pairs are tested exactly by the narrowphase. Each collider carries a value of type T, such as the
+// scene node or rigid body that it belongs to.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::bounds::Aabb;
+use gfx::bvh::{Bvh, ProxyId};
+use gfx::types::*;
+use physics::collision::{self, CastHit, Contact};
+use physics::shape::{Isometry, Shape};
+use std::cell::Cell;
+use std::cmp;
+
+// Handle to a collider in a CollisionWorld.
+pub type ColliderId = usize;
+
+// A shape placed in the world along with its data.
+pub struct Collider<T> {
+    pub shape: Shape,
+    pub iso: Isometry,
+    pub data: T,
+    proxy: ProxyId,
+}
+
+impl<T> Collider<T> {
+    // Gets the bounds of the collider in the world.
+    pub fn get_bounds(&self) -> Aabb {
+        self.shape.compute_bounds(&self.iso)
+    }
+}
+
+// A set of colliders with a broadphase over their bounds.
+pub struct CollisionWorld<T> {
+    colliders: Vec<Option<Collider<T>>>,
+    free: Vec<ColliderId>,
+    bvh: Bvh<ColliderId>,
+}
+
+impl<T> CollisionWorld<T> {
+    // Creates an empty world.
+    pub fn new() -> CollisionWorld<T> {
+        CollisionWorld { colliders: Vec::new(), free: Vec::new(), bvh: Bvh::new() }
+    }
+
+    // Adds a collider and returns its handle.
+    pub fn add(&mut self, shape: Shape, iso: Isometry, data: T) -> ColliderId {
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => { self.colliders.push(None); self.colliders.len() - 1 },
+        };
+        let proxy = self.bvh.insert(shape.compute_bounds(&iso), id);
+        self.colliders[id] = Some(Collider { shape: shape, iso: iso, data: data, proxy: proxy });
+        id
+    }
+
+    // Removes a collider and returns it, or None if the handle isn't in the world.
+    pub fn remove(&mut self, id: ColliderId) -> Option<Collider<T>> {
+        let collider = match self.colliders.get_mut(id).and_then(|c| c.take()) {
+            Some(collider) => collider,
+            None => { return None; },
+        };
+        self.bvh.remove(collider.proxy);
+        self.free.push(id);
+        Some(collider)
+    }
+
+    // Gets a collider.
+    pub fn get(&self, id: ColliderId) -> Option<&Collider<T>> {
+        self.colliders.get(id).and_then(|c| c.as_ref())
+    }
+
+    // Gets a mutable reference to a collider's data. Use set_pose() and set_shape() to change
+    // where it is and what it is.
+    pub fn get_data_mut(&mut self, id: ColliderId) -> Option<&mut T> {
+        self.colliders.get_mut(id).and_then(|c| c.as_mut()).map(|c| &mut c.data)
+    }
+
+    // Moves a collider. Returns an Err if the handle isn't in the world.
+    pub fn set_pose(&mut self, id: ColliderId, iso: Isometry) -> Result<(), String> {
+        let collider = try!(self.colliders.get_mut(id).and_then(|c| c.as_mut())
+                .ok_or(format!("Collider {} doesn't exist.", id)));
+        collider.iso = iso;
+        self.bvh.update(collider.proxy, collider.shape.compute_bounds(&iso));
+        Ok(())
+    }
+
+    // Changes the shape of a collider. Returns an Err if the handle isn't in the world.
+    pub fn set_shape(&mut self, id: ColliderId, shape: Shape) -> Result<(), String> {
+        let collider = try!(self.colliders.get_mut(id).and_then(|c| c.as_mut())
+                .ok_or(format!("Collider {} doesn't exist.", id)));
+        collider.shape = shape;
+        self.bvh.update(collider.proxy, collider.shape.compute_bounds(&collider.iso));
+        Ok(())
+    }
+
+    // Gets the number of colliders.
+    pub fn len(&self) -> usize {
+        self.bvh.len()
+    }
+
+    // Returns true if there are no colliders.
+    pub fn is_empty(&self) -> bool {
+        self.bvh.is_empty()
+    }
+
+    // Iterates over every collider and its handle.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(ColliderId, &'a Collider<T>)> + 'a> {
+        Box::new(self.colliders.iter().enumerate()
+                .filter_map(|(id, c)| c.as_ref().map(|c| (id, c))))
+    }
+
+    // Gets the hierarchy of collider bounds.
+    pub fn get_bvh(&self) -> &Bvh<ColliderId> {
+        &self.bvh
+    }
+
+    // Gets every pair of colliders whose fattened bounds overlap, with the smaller handle first.
+    pub fn find_pairs(&self) -> Vec<(ColliderId, ColliderId)> {
+        let mut pairs = Vec::new();
+        for (id, collider) in self.iter() {
+            let bounds = match self.bvh.get_bounds(collider.proxy) {
+                Some(bounds) => bounds,
+                None => { continue; },
+            };
+            self.bvh.query(|b| b.intersects(&bounds), |_, other| {
+                if *other > id { pairs.push((id, *other)); }
+            });
+        }
+        pairs
+    }
+
+    // Gets every pair of colliders that touch along with their contact, whose normal points from
+    // the first collider to the second.
+    pub fn find_contacts(&self) -> Vec<(ColliderId, ColliderId, Contact)> {
+        self.find_pairs().into_iter().filter_map(|(a, b)| {
+            let (ca, cb) = (self.get(a).unwrap(), self.get(b).unwrap());
+            collision::collide(&ca.shape, &ca.iso, &cb.shape, &cb.iso).map(|c| (a, b, c))
+        }).collect()
+    }
+
+    // Gets the colliders that a placed shape overlaps.
+    pub fn query_overlaps(&self, shape: &Shape, iso: &Isometry) -> Vec<ColliderId> {
+        let bounds = shape.compute_bounds(iso);
+        let mut found = Vec::new();
+        self.bvh.query(|b| b.intersects(&bounds), |_, id| {
+            let collider = self.get(*id).unwrap();
+            if collider.get_bounds().intersects(&bounds) &&
+                    collision::intersects(shape, iso, &collider.shape, &collider.iso) {
+                found.push(*id);
+            }
+        });
+        found.sort();
+        found
+    }
+
+    // Moves a shape along a normalized direction and gets the colliders it hits within a distance
+    // that pass a filter, nearest first. If all is false, only the nearest hit is returned.
+    pub fn cast<F>(&self, shape: &Shape, iso: &Isometry, dir: Vector3D, max_dist: GLfloat,
+            all: bool, mut filter: F) -> Vec<(ColliderId, CastHit)>
+            where F: FnMut(ColliderId, &T) -> bool {
+        // The bounds of the colliders are grown by the size of the shape so that a ray through
+        // them finds every collider that the shape could touch.
+        let start = shape.compute_bounds(iso);
+        let (origin, radius) = (start.center(), (start.max - start.min) * 0.5);
+        let inv_dir = Vector3D::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let limit = Cell::new(max_dist);
+        let mut hits: Vec<(ColliderId, CastHit)> = Vec::new();
+        self.bvh.query(|b| {
+            let grown = Aabb::new(b.min - radius, b.max + radius);
+            grown.intersect_ray(origin, inv_dir, limit.get()).is_some()
+        }, |_, id| {
+            let collider = self.get(*id).unwrap();
+            if !filter(*id, &collider.data) { return; }
+            let hit = match collision::cast(shape, iso, dir, limit.get(), &collider.shape,
+                    &collider.iso) {
+                Some(hit) => hit,
+                None => { return; },
+            };
+            if all {
+                hits.push((*id, hit));
+            } else {
+                limit.set(hit.distance);
+                hits = vec![(*id, hit)];
+            }
+        });
+        hits.sort_by(|a, b| {
+            a.1.distance.partial_cmp(&b.1.distance).unwrap_or(cmp::Ordering::Equal)
+        });
+        hits
+    }
+}
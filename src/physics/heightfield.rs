@@ -0,0 +1,135 @@
+// Defines a Heightfield, which is a collision shape for terrain made of a regular grid of heights.
+// Each cell of the grid is split into two triangles, and since the cells that a shape covers come
+// straight from its bounds, nothing has to be precomputed to find the triangles near it.
+//
+// The grid lies in the XY plane centered on the local origin with the heights along Z, which is up
+// in the engine. The first row of heights is at the most positive Y, so a heightmap image is laid
+// out the way it looks when viewed from above with +Y at the top.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::bounds::Aabb;
+use gfx::types::*;
+use util::bmp;
+use util::common::Image;
+
+// A grid of heights with a number of columns along X and rows along Y. The spacing is the distance
+// between neighboring heights along X and Y.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    pub columns: usize,
+    pub rows: usize,
+    pub heights: Vec<GLfloat>,
+    pub spacing: (GLfloat, GLfloat),
+    min_height: GLfloat,
+    max_height: GLfloat,
+}
+
+impl Heightfield {
+    // Creates a heightfield from its heights, one row after another. Returns an Err if the grid is
+    // smaller than 2 by 2 or the number of heights doesn't match it.
+    pub fn new(columns: usize, rows: usize, heights: Vec<GLfloat>, spacing: (GLfloat, GLfloat))
+            -> Result<Heightfield, String> {
+        if columns < 2 || rows < 2 {
+            return Err(format!("A Heightfield needs at least 2x2 heights, but got {}x{}.",
+                    columns, rows));
+        }
+        if heights.len() != columns * rows {
+            return Err(format!("A {}x{} Heightfield needs {} heights, but got {}.", columns, rows,
+                    columns * rows, heights.len()));
+        }
+        let min_height = heights.iter().fold(::std::f32::MAX, |m, &h| m.min(h));
+        let max_height = heights.iter().fold(::std::f32::MIN, |m, &h| m.max(h));
+        Ok(Heightfield { columns: columns, rows: rows, heights: heights, spacing: spacing,
+                min_height: min_height, max_height: max_height })
+    }
+
+    // Creates a heightfield from the brightness of an image, where black is a height of 0 and
+    // white is the max height.
+    pub fn from_image(image: &Image, spacing: (GLfloat, GLfloat), max_height: GLfloat)
+            -> Result<Heightfield, String> {
+        let heights = image.data.iter().map(|p| {
+            (p.red as GLfloat + p.green as GLfloat + p.blue as GLfloat) / (3.0 * 255.0) * max_height
+        }).collect();
+        Heightfield::new(image.width as usize, image.height as usize, heights, spacing)
+    }
+
+    // Creates a heightfield from a BMP heightmap.
+    pub fn from_bmp(fpath: &str, spacing: (GLfloat, GLfloat), max_height: GLfloat)
+            -> Result<Heightfield, String> {
+        let decoded = try!(bmp::decode_bmp(fpath));
+        Heightfield::from_image(&decoded.image, spacing, max_height)
+    }
+
+    // Gets the height at a column and row.
+    pub fn get(&self, column: usize, row: usize) -> GLfloat {
+        self.heights[row * self.columns + column]
+    }
+
+    // Gets the position of the height at a column and row in local space.
+    pub fn get_point(&self, column: usize, row: usize) -> Vector3D {
+        let (x0, y0) = self.get_origin();
+        Vector3D::new(x0 + column as GLfloat * self.spacing.0,
+                y0 - row as GLfloat * self.spacing.1, self.get(column, row))
+    }
+
+    // Gets the bounds of the heightfield in local space.
+    pub fn get_bounds(&self) -> Aabb {
+        let (x0, y0) = self.get_origin();
+        Aabb::new(Vector3D::new(x0, -y0, self.min_height),
+                Vector3D::new(-x0, y0, self.max_height))
+    }
+
+    // Gets the height of the surface at a point in the XY plane in local space, or None if the
+    // point is off of the grid.
+    pub fn get_height(&self, x: GLfloat, y: GLfloat) -> Option<GLfloat> {
+        let (x0, y0) = self.get_origin();
+        let (u, v) = ((x - x0) / self.spacing.0, (y0 - y) / self.spacing.1);
+        let (max_u, max_v) = ((self.columns - 1) as GLfloat, (self.rows - 1) as GLfloat);
+        if !(u >= 0.0 && v >= 0.0 && u <= max_u && v <= max_v) { return None; }
+        let (column, row) = ((u as usize).min(self.columns - 2), (v as usize).min(self.rows - 2));
+        let (fu, fv) = (u - column as GLfloat, v - row as GLfloat);
+
+        // Match the triangles that the cell is split into.
+        let (h00, h10) = (self.get(column, row), self.get(column + 1, row));
+        let (h01, h11) = (self.get(column, row + 1), self.get(column + 1, row + 1));
+        Some(if fu >= fv {
+            h00 + (h10 - h00) * fu + (h11 - h10) * fv
+        } else {
+            h00 + (h11 - h01) * fu + (h01 - h00) * fv
+        })
+    }
+
+    // Calls a function with the corners of every triangle in the cells that a box in local space
+    // covers.
+    pub fn query<F>(&self, bounds: &Aabb, mut callback: F) where F: FnMut([Vector3D; 3]) {
+        if bounds.max.z < self.min_height || bounds.min.z > self.max_height { return; }
+        let (x0, y0) = self.get_origin();
+        let max_column = (self.columns - 2) as GLfloat;
+        let max_row = (self.rows - 2) as GLfloat;
+        let (u0, u1) = ((bounds.min.x - x0) / self.spacing.0, (bounds.max.x - x0) / self.spacing.0);
+        let (v0, v1) = ((y0 - bounds.max.y) / self.spacing.1, (y0 - bounds.min.y) / self.spacing.1);
+        if u1 < 0.0 || v1 < 0.0 || u0 > max_column + 1.0 || v0 > max_row + 1.0 { return; }
+        let first_column = u0.max(0.0).min(max_column) as usize;
+        let last_column = u1.max(0.0).min(max_column) as usize;
+        let first_row = v0.max(0.0).min(max_row) as usize;
+        let last_row = v1.max(0.0).min(max_row) as usize;
+        for row in first_row..(last_row + 1) {
+            for column in first_column..(last_column + 1) {
+                let p00 = self.get_point(column, row);
+                let p10 = self.get_point(column + 1, row);
+                let p01 = self.get_point(column, row + 1);
+                let p11 = self.get_point(column + 1, row + 1);
+                callback([p00, p01, p11]);
+                callback([p00, p11, p10]);
+            }
+        }
+    }
+
+    // Gets the position of the first column and row in the XY plane.
+    fn get_origin(&self) -> (GLfloat, GLfloat) {
+        ((self.columns - 1) as GLfloat * self.spacing.0 * -0.5,
+                (self.rows - 1) as GLfloat * self.spacing.1 * 0.5)
+    }
+}
@@ -0,0 +1,308 @@
+// Defines joints, which hold two rigid bodies together. A ball-socket joint pins a point on each
+// body together and lets them swing freely around it, such as a shoulder or a lamp on a chain. A
+// hinge joint also keeps an axis of each body lined up so that they only turn around it, such as
+// a door or a knee, and a fixed joint keeps the bodies from moving relative to each other at all.
+//
+// Joints are solved by sequential impulses alongside the contacts. Each part of a joint is a row
+// that removes the relative velocity that would break it, biased to pull back any drift, and the
+// impulses are kept between steps to warm start the solver the way contacts are. Hinges can limit
+// how far they turn and drive the bodies with a motor, and ball-socket joints can limit how far
+// they swing from an axis. Angles are in radians and motor speeds are in radians per second.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::types::*;
+use physics::world::{BodyId, RigidBody, SolverBody};
+use self::cgmath::{EuclideanVector, SquareMatrix, Vector};
+
+// The fraction of the drift that joints pull back each step.
+const BAUMGARTE: GLfloat = 0.2;
+
+// Handle to a joint in a PhysicsWorld.
+pub type JointId = usize;
+
+// Drives a hinge towards a speed using at most a torque.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Motor {
+    pub speed: GLfloat,
+    pub max_torque: GLfloat,
+}
+
+// The kind of a joint along with its limits. A ball-socket's swing limit is the most it can turn
+// away from its axis, and a hinge's limits are the lowest and highest angles it can turn to.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum JointKind {
+    BallSocket { swing_limit: Option<GLfloat> },
+    Hinge { limits: Option<(GLfloat, GLfloat)>, motor: Option<Motor> },
+    Fixed,
+}
+
+// A row of a joint that removes relative angular velocity around an axis. The impulse is the
+// total applied this step, which is clamped to a range.
+#[derive(Copy, Clone, Debug)]
+struct AngularRow {
+    axis: Vector3D,
+    mass: GLfloat,
+    bias: GLfloat,
+    impulse: GLfloat,
+    min: GLfloat,
+    max: GLfloat,
+}
+
+impl AngularRow {
+    // Creates a row around an axis for two bodies.
+    fn new(axis: Vector3D, sa: &SolverBody, sb: &SolverBody, bias: GLfloat, impulse: GLfloat,
+            min: GLfloat, max: GLfloat) -> AngularRow {
+        let k = sa.apply_inv_inertia(axis).dot(axis) + sb.apply_inv_inertia(axis).dot(axis);
+        AngularRow { axis: axis, mass: if k > 0.0 { 1.0 / k } else { 0.0 }, bias: bias,
+                impulse: impulse.max(min).min(max), min: min, max: max }
+    }
+
+    // Creates a row that keeps an angle from passing a limit, where the error is how far past the
+    // limit the angle is. Angles short of the limit are allowed to close the gap this step.
+    fn limit(axis: Vector3D, sa: &SolverBody, sb: &SolverBody, error: GLfloat, impulse: GLfloat,
+            dt: GLfloat) -> AngularRow {
+        let bias = if error > 0.0 { BAUMGARTE / dt * error } else { error / dt };
+        AngularRow::new(axis, sa, sb, bias, impulse, ::std::f32::MIN, 0.0)
+    }
+
+    // Applies the impulse that drives the relative velocity around the axis towards the bias.
+    fn solve(&mut self, sa: &mut SolverBody, sb: &mut SolverBody) {
+        let speed = (sb.angular_velocity - sa.angular_velocity).dot(self.axis);
+        let total = (self.impulse - self.mass * (speed + self.bias)).max(self.min).min(self.max);
+        let impulse = self.axis * (total - self.impulse);
+        self.impulse = total;
+        sa.apply_angular_impulse(-impulse);
+        sb.apply_angular_impulse(impulse);
+    }
+
+    // Applies the impulse from the last step.
+    fn warm_start(&self, sa: &mut SolverBody, sb: &mut SolverBody) {
+        sa.apply_angular_impulse(-self.axis * self.impulse);
+        sb.apply_angular_impulse(self.axis * self.impulse);
+    }
+}
+
+// A joint between bodies a and b. The anchors are the point that the joint holds together in each
+// body's local space, and the axes are the hinge or swing axis in each body's local space. By
+// default, the bodies that a joint connects don't collide with each other.
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub kind: JointKind,
+    pub a: BodyId,
+    pub b: BodyId,
+    pub local_anchor_a: Vector3D,
+    pub local_anchor_b: Vector3D,
+    pub local_axis_a: Vector3D,
+    pub local_axis_b: Vector3D,
+    pub collide_connected: bool,
+    reference: Quaternion,
+    point_impulse: Vector3D,
+    row_impulses: [GLfloat; 3],
+    limit_impulses: [GLfloat; 2],
+    motor_impulse: GLfloat,
+    rows: Vec<AngularRow>,
+    r_a: Vector3D,
+    r_b: Vector3D,
+    point_mass: cgmath::Matrix3<GLfloat>,
+    point_bias: Vector3D,
+    angular_mass: cgmath::Matrix3<GLfloat>,
+    angular_bias: Vector3D,
+}
+
+impl Joint {
+    // Creates a joint between two bodies in their current poses, holding them together at an
+    // anchor and around an axis in world space. Their current poses are the rest pose of the
+    // joint, where a hinge has an angle of 0.
+    pub fn new(kind: JointKind, a: BodyId, body_a: &RigidBody, b: BodyId, body_b: &RigidBody,
+            anchor: Vector3D, axis: Vector3D) -> Joint {
+        let axis = if axis.length2() > 0.0 { axis.normalize() } else { Vector3D::unit_z() };
+        let zero = Vector3D::new(0.0, 0.0, 0.0);
+        let zero_matrix = cgmath::Matrix3::from_value(0.0);
+        Joint {
+            kind: kind, a: a, b: b, local_anchor_a: body_a.iso.inverse_transform_point(anchor),
+            local_anchor_b: body_b.iso.inverse_transform_point(anchor),
+            local_axis_a: body_a.iso.inverse_transform_vector(axis),
+            local_axis_b: body_b.iso.inverse_transform_vector(axis), collide_connected: false,
+            reference: body_a.iso.rot.conjugate() * body_b.iso.rot, point_impulse: zero,
+            row_impulses: [0.0; 3], limit_impulses: [0.0; 2], motor_impulse: 0.0,
+            rows: Vec::new(), r_a: zero, r_b: zero, point_mass: zero_matrix, point_bias: zero,
+            angular_mass: zero_matrix, angular_bias: zero,
+        }
+    }
+
+    // Sets the lowest and highest angles of a hinge. Returns an Err if the joint isn't a hinge.
+    pub fn set_limits(&mut self, lower: GLfloat, upper: GLfloat) -> Result<(), String> {
+        match self.kind {
+            JointKind::Hinge { ref mut limits, .. } => { *limits = Some((lower, upper)); Ok(()) },
+            _ => Err("Only hinge joints have angle limits.".to_string()),
+        }
+    }
+
+    // Sets or clears the motor of a hinge. Returns an Err if the joint isn't a hinge.
+    pub fn set_motor(&mut self, motor: Option<Motor>) -> Result<(), String> {
+        match self.kind {
+            JointKind::Hinge { motor: ref mut m, .. } => { *m = motor; Ok(()) },
+            _ => Err("Only hinge joints have motors.".to_string()),
+        }
+    }
+
+    // Sets the most a ball-socket joint can swing away from its axis. Returns an Err if the joint
+    // isn't a ball-socket.
+    pub fn set_swing_limit(&mut self, angle: GLfloat) -> Result<(), String> {
+        match self.kind {
+            JointKind::BallSocket { ref mut swing_limit } => { *swing_limit = Some(angle); Ok(()) },
+            _ => Err("Only ball-socket joints have swing limits.".to_string()),
+        }
+    }
+
+    // Returns true if the joint has a motor that is trying to turn it.
+    pub fn has_running_motor(&self) -> bool {
+        match self.kind {
+            JointKind::Hinge { motor: Some(motor), .. } => motor.speed != 0.0,
+            _ => false,
+        }
+    }
+
+    // Gets how far the joint has turned around its axis from its rest pose given the rotations of
+    // its bodies, from -pi to pi.
+    pub fn get_angle(&self, rot_a: Quaternion, rot_b: Quaternion) -> GLfloat {
+        let delta = rot_a.conjugate() * rot_b * self.reference.conjugate();
+        let angle = 2.0 * delta.v.dot(self.local_axis_a).atan2(delta.s);
+        let pi = ::std::f32::consts::PI;
+        if angle > pi { angle - 2.0 * pi } else if angle < -pi { angle + 2.0 * pi } else { angle }
+    }
+
+    // Prepares the joint for solving a step and applies the impulses from the last step. This is
+    // called by the PhysicsWorld.
+    pub fn prepare(&mut self, sa: &mut SolverBody, sb: &mut SolverBody, dt: GLfloat) {
+        let beta = BAUMGARTE / dt;
+        self.r_a = sa.rot * self.local_anchor_a;
+        self.r_b = sb.rot * self.local_anchor_b;
+        let point_k = point_matrix(sa, self.r_a) + point_matrix(sb, self.r_b);
+        self.point_mass = point_k.invert().unwrap_or(cgmath::Matrix3::from_value(0.0));
+        self.point_bias = ((sb.pos + self.r_b) - (sa.pos + self.r_a)) * beta;
+
+        let axis_a = sa.rot * self.local_axis_a;
+        let axis_b = sb.rot * self.local_axis_b;
+        let mut rows = Vec::new();
+        self.angular_bias = Vector3D::new(0.0, 0.0, 0.0);
+        match self.kind {
+            JointKind::BallSocket { swing_limit } => {
+                if let Some(limit) = swing_limit {
+                    let cross = axis_a.cross(axis_b);
+                    if cross.length2() > 1e-10 {
+                        let angle = axis_a.dot(axis_b).max(-1.0).min(1.0).acos();
+                        rows.push(AngularRow::limit(cross.normalize(), sa, sb, angle - limit,
+                                self.limit_impulses[0], dt));
+                    }
+                }
+            },
+            JointKind::Hinge { limits, motor } => {
+                let tangents = perpendicular_axes(axis_a);
+                for i in 0..2 {
+                    let error = axis_a.cross(axis_b).dot(tangents[i]);
+                    rows.push(AngularRow::new(tangents[i], sa, sb, beta * error,
+                            self.row_impulses[i], ::std::f32::MIN, ::std::f32::MAX));
+                }
+                if let Some(motor) = motor {
+                    let max = motor.max_torque * dt;
+                    rows.push(AngularRow::new(axis_a, sa, sb, -motor.speed, self.motor_impulse,
+                            -max, max));
+                }
+                if let Some((lower, upper)) = limits {
+                    let angle = self.get_angle(sa.rot, sb.rot);
+                    rows.push(AngularRow::limit(-axis_a, sa, sb, lower - angle,
+                            self.limit_impulses[0], dt));
+                    rows.push(AngularRow::limit(axis_a, sa, sb, angle - upper,
+                            self.limit_impulses[1], dt));
+                }
+            },
+            JointKind::Fixed => {
+                let k = sa.get_inv_inertia_matrix() + sb.get_inv_inertia_matrix();
+                self.angular_mass = k.invert().unwrap_or(cgmath::Matrix3::from_value(0.0));
+                let delta = sa.rot.conjugate() * sb.rot * self.reference.conjugate();
+                let sign = if delta.s < 0.0 { -1.0 } else { 1.0 };
+                self.angular_bias = (sa.rot * delta.v) * (2.0 * sign * beta);
+            },
+        }
+        self.rows = rows;
+
+        // Warm start.
+        sa.apply_impulse(-self.point_impulse, self.r_a);
+        sb.apply_impulse(self.point_impulse, self.r_b);
+        if let JointKind::Fixed = self.kind {
+            let impulse = Vector3D::new(self.row_impulses[0], self.row_impulses[1],
+                    self.row_impulses[2]);
+            sa.apply_angular_impulse(-impulse);
+            sb.apply_angular_impulse(impulse);
+        }
+        for row in &self.rows {
+            row.warm_start(sa, sb);
+        }
+    }
+
+    // Runs a single iteration of the solver on the joint. This is called by the PhysicsWorld.
+    pub fn solve(&mut self, sa: &mut SolverBody, sb: &mut SolverBody) {
+        for row in &mut self.rows {
+            row.solve(sa, sb);
+        }
+        if let JointKind::Fixed = self.kind {
+            let speed = sb.angular_velocity - sa.angular_velocity;
+            let impulse = -(self.angular_mass * (speed + self.angular_bias));
+            self.row_impulses[0] += impulse.x;
+            self.row_impulses[1] += impulse.y;
+            self.row_impulses[2] += impulse.z;
+            sa.apply_angular_impulse(-impulse);
+            sb.apply_angular_impulse(impulse);
+        }
+        let speed = sb.get_velocity(self.r_b) - sa.get_velocity(self.r_a);
+        let impulse = -(self.point_mass * (speed + self.point_bias));
+        self.point_impulse = self.point_impulse + impulse;
+        sa.apply_impulse(-impulse, self.r_a);
+        sb.apply_impulse(impulse, self.r_b);
+    }
+
+    // Keeps the impulses of the rows for the next step. This is called by the PhysicsWorld.
+    pub fn finish(&mut self) {
+        let impulses: Vec<GLfloat> = self.rows.iter().map(|r| r.impulse).collect();
+        match self.kind {
+            JointKind::BallSocket { swing_limit } => {
+                self.limit_impulses[0] = if swing_limit.is_some() {
+                    impulses.get(0).cloned().unwrap_or(0.0)
+                } else { 0.0 };
+            },
+            JointKind::Hinge { limits, motor } => {
+                self.row_impulses[0] = impulses[0];
+                self.row_impulses[1] = impulses[1];
+                let mut next = 2;
+                self.motor_impulse = 0.0;
+                if motor.is_some() {
+                    self.motor_impulse = impulses[next];
+                    next += 1;
+                }
+                self.limit_impulses = [0.0; 2];
+                if limits.is_some() {
+                    self.limit_impulses = [impulses[next], impulses[next + 1]];
+                }
+            },
+            JointKind::Fixed => (),
+        }
+    }
+}
+
+// Gets how a body resists an impulse at an offset from its center, as a matrix.
+fn point_matrix(body: &SolverBody, r: Vector3D) -> cgmath::Matrix3<GLfloat> {
+    let skew = cgmath::Matrix3::new(0.0, r.z, -r.y, -r.z, 0.0, r.x, r.y, -r.x, 0.0);
+    cgmath::Matrix3::from_value(body.inv_mass) - skew * body.get_inv_inertia_matrix() * skew
+}
+
+// Gets two directions perpendicular to an axis and each other.
+fn perpendicular_axes(axis: Vector3D) -> [Vector3D; 2] {
+    let other = if axis.x.abs() < 0.57 { Vector3D::unit_x() } else { Vector3D::unit_y() };
+    let first = axis.cross(other).normalize();
+    [first, axis.cross(first)]
+}
@@ -0,0 +1,7 @@
+pub mod collision;
+pub mod collision_world;
+pub mod heightfield;
+pub mod joint;
+pub mod shape;
+pub mod trimesh;
+pub mod world;
@@ -0,0 +1,283 @@
+// Defines the collision shapes and the rigid transforms that place them in the world. Every shape
+// other than a triangle mesh or heightfield is convex and centered on its local origin, and is
+// described to the narrowphase by its support function, which gives the point of the shape
+// furthest in a direction. Spheres and capsules are
+// handled as a point or segment core that is rounded by a margin, which keeps their contacts exact
+// instead of approximating the curved surface.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::bounds::Aabb;
+use gfx::scene::Transform;
+use gfx::types::*;
+use physics::heightfield::Heightfield;
+use physics::trimesh::TriMesh;
+use self::cgmath::{EuclideanVector, Vector};
+use std::rc::Rc;
+
+// A rotation followed by a translation, which is a Transform without scale.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Isometry {
+    pub pos: Vector3D,
+    pub rot: Quaternion,
+}
+
+impl Isometry {
+    // Default constructor for an Isometry given its position and rotation.
+    pub fn new(pos: Vector3D, rot: Quaternion) -> Isometry {
+        Isometry { pos: pos, rot: rot }
+    }
+
+    // Creates an Isometry that doesn't move anything.
+    pub fn identity() -> Isometry {
+        Isometry::new(Vector3D::new(0.0, 0.0, 0.0), Quaternion::new(1.0, 0.0, 0.0, 0.0))
+    }
+
+    // Creates an Isometry from the position and rotation of a Transform, ignoring its scale.
+    pub fn from_transform(transform: &Transform) -> Isometry {
+        Isometry::new(transform.pos, transform.rot)
+    }
+
+    // Moves a point from local space to world space.
+    pub fn transform_point(&self, p: Vector3D) -> Vector3D {
+        self.rot * p + self.pos
+    }
+
+    // Rotates a direction from local space to world space.
+    pub fn transform_vector(&self, v: Vector3D) -> Vector3D {
+        self.rot * v
+    }
+
+    // Moves a point from world space to local space.
+    pub fn inverse_transform_point(&self, p: Vector3D) -> Vector3D {
+        self.rot.conjugate() * (p - self.pos)
+    }
+
+    // Rotates a direction from world space to local space.
+    pub fn inverse_transform_vector(&self, v: Vector3D) -> Vector3D {
+        self.rot.conjugate() * v
+    }
+
+    // Gets the model matrix.
+    pub fn to_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        cgmath::Matrix4::from(cgmath::Decomposed { scale: 1.0, rot: self.rot, disp: self.pos })
+    }
+}
+
+// The convex hull of a set of points. The points don't have to be on the hull, since only the ones
+// that are furthest in a direction are ever used.
+#[derive(Clone, Debug)]
+pub struct ConvexHull {
+    pub points: Vec<Vector3D>,
+}
+
+impl ConvexHull {
+    // Creates the hull of a set of points. Returns an Err if there are no points.
+    pub fn new(points: Vec<Vector3D>) -> Result<ConvexHull, String> {
+        if points.is_empty() {
+            return Err("A ConvexHull needs at least one point.".to_string());
+        }
+        Ok(ConvexHull { points: points })
+    }
+
+    // Creates the hull of a list of positions, such as the vertices of a ModelInfo.
+    pub fn from_positions(positions: &[GLfloat]) -> Result<ConvexHull, String> {
+        ConvexHull::new(positions.chunks(3).filter(|p| p.len() == 3)
+                .map(|p| Vector3D::new(p[0], p[1], p[2])).collect())
+    }
+
+    // Gets the point furthest in a direction.
+    pub fn support(&self, dir: Vector3D) -> Vector3D {
+        let mut best = self.points[0];
+        let mut best_dot = best.dot(dir);
+        for p in &self.points[1..] {
+            let d = p.dot(dir);
+            if d > best_dot {
+                best = *p;
+                best_dot = d;
+            }
+        }
+        best
+    }
+}
+
+// A collision shape in its local space. Boxes are given by their half extents and capsules stand
+// along their local Z axis, which is the engine's up, with the half height of the segment between
+// their two spheres. Triangle meshes and heightfields are the only shapes that aren't convex, and
+// are made of triangles that other shapes are tested against one at a time.
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Sphere { radius: GLfloat },
+    Box { half_extents: Vector3D },
+    Capsule { radius: GLfloat, half_height: GLfloat },
+    ConvexHull(Rc<ConvexHull>),
+    Triangle { points: [Vector3D; 3] },
+    TriMesh(Rc<TriMesh>),
+    Heightfield(Rc<Heightfield>),
+}
+
+impl Shape {
+    // Returns true if the shape is made of triangles that are tested one at a time.
+    pub fn is_concave(&self) -> bool {
+        match *self {
+            Shape::TriMesh(_) | Shape::Heightfield(_) => true,
+            _ => false,
+        }
+    }
+
+    // Calls a function with the corners of every triangle of a concave shape whose bounds overlap
+    // a box in local space. Convex shapes have no triangles.
+    pub fn query_triangles<F>(&self, bounds: &Aabb, callback: F) where F: FnMut([Vector3D; 3]) {
+        match *self {
+            Shape::TriMesh(ref mesh) => mesh.query(bounds, callback),
+            Shape::Heightfield(ref field) => field.query(bounds, callback),
+            _ => (),
+        }
+    }
+
+    // Gets the point of the core of the shape furthest in a direction in local space. The core is
+    // the shape without its margin, which is a point for a sphere and a segment for a capsule.
+    // Concave shapes give the support of their bounds, since they are never tested whole.
+    pub fn core_support(&self, dir: Vector3D) -> Vector3D {
+        let sign = |x: GLfloat| if x < 0.0 { -1.0 } else { 1.0 };
+        match *self {
+            Shape::Sphere { .. } => Vector3D::new(0.0, 0.0, 0.0),
+            Shape::Box { half_extents: h } => {
+                Vector3D::new(sign(dir.x) * h.x, sign(dir.y) * h.y, sign(dir.z) * h.z)
+            },
+            Shape::Capsule { half_height, .. } => {
+                Vector3D::new(0.0, 0.0, sign(dir.z) * half_height)
+            },
+            Shape::ConvexHull(ref hull) => hull.support(dir),
+            Shape::Triangle { ref points } => {
+                let mut best = points[0];
+                for p in &points[1..] {
+                    if p.dot(dir) > best.dot(dir) { best = *p; }
+                }
+                best
+            },
+            Shape::TriMesh(_) | Shape::Heightfield(_) => {
+                let bounds = self.local_bounds();
+                Vector3D::new(if dir.x < 0.0 { bounds.min.x } else { bounds.max.x },
+                        if dir.y < 0.0 { bounds.min.y } else { bounds.max.y },
+                        if dir.z < 0.0 { bounds.min.z } else { bounds.max.z })
+            },
+        }
+    }
+
+    // Gets the radius that the core of the shape is rounded by.
+    pub fn margin(&self) -> GLfloat {
+        match *self {
+            Shape::Sphere { radius } | Shape::Capsule { radius, .. } => radius,
+            _ => 0.0,
+        }
+    }
+
+    // Gets the point of the shape furthest in a direction in local space.
+    pub fn support(&self, dir: Vector3D) -> Vector3D {
+        let core = self.core_support(dir);
+        if self.margin() > 0.0 && dir.length2() > 0.0 {
+            core + dir.normalize() * self.margin()
+        } else {
+            core
+        }
+    }
+
+    // Gets the bounds of the shape in local space.
+    pub fn local_bounds(&self) -> Aabb {
+        match *self {
+            Shape::Sphere { radius: r } => {
+                Aabb::new(Vector3D::new(-r, -r, -r), Vector3D::new(r, r, r))
+            },
+            Shape::Box { half_extents: h } => Aabb::new(-h, h),
+            Shape::Capsule { radius: r, half_height: h } => {
+                Aabb::new(Vector3D::new(-r, -r, -h - r), Vector3D::new(r, r, h + r))
+            },
+            Shape::ConvexHull(ref hull) => {
+                let mut bounds = Aabb::empty();
+                for p in &hull.points {
+                    bounds.add_point(*p);
+                }
+                bounds
+            },
+            Shape::Triangle { ref points } => {
+                let mut bounds = Aabb::empty();
+                for p in points {
+                    bounds.add_point(*p);
+                }
+                bounds
+            },
+            Shape::TriMesh(ref mesh) => mesh.get_bounds(),
+            Shape::Heightfield(ref field) => field.get_bounds(),
+        }
+    }
+
+    // Gets the diagonal of the inertia tensor of a solid shape with a mass, about its local axes.
+    // Every other shape is treated as its bounding box, which is close enough for a simulation.
+    pub fn compute_inertia(&self, mass: GLfloat) -> Vector3D {
+        match *self {
+            Shape::Sphere { radius: r } => {
+                let i = 0.4 * mass * r * r;
+                Vector3D::new(i, i, i)
+            },
+            Shape::Box { half_extents: h } => box_inertia(mass, h),
+            Shape::Capsule { radius: r, half_height: h } => {
+                // Split the mass between the cylinder and the two caps by their volumes.
+                let length = 2.0 * h;
+                let (cylinder, caps) = (length, 4.0 / 3.0 * r);
+                let mass_cylinder = mass * cylinder / (cylinder + caps);
+                let mass_caps = mass - mass_cylinder;
+                let axial = mass_cylinder * r * r * 0.5 + mass_caps * r * r * 0.4;
+                let side = mass_cylinder * (length * length / 12.0 + r * r * 0.25) +
+                        mass_caps * (r * r * 0.4 + length * length * 0.25 + length * r * 0.375);
+                Vector3D::new(side, side, axial)
+            },
+            _ => {
+                let bounds = self.local_bounds();
+                box_inertia(mass, (bounds.max - bounds.min) * 0.5)
+            },
+        }
+    }
+
+    // Gets the bounds of the shape placed in the world.
+    pub fn compute_bounds(&self, iso: &Isometry) -> Aabb {
+        match *self {
+            Shape::Sphere { radius: r } => {
+                let r = Vector3D::new(r, r, r);
+                Aabb::new(iso.pos - r, iso.pos + r)
+            },
+            Shape::Capsule { radius: r, half_height: h } => {
+                let axis = iso.transform_vector(Vector3D::new(0.0, 0.0, h));
+                let r = Vector3D::new(r, r, r);
+                let mut bounds = Aabb::empty();
+                bounds.add_point(iso.pos + axis);
+                bounds.add_point(iso.pos - axis);
+                Aabb::new(bounds.min - r, bounds.max + r)
+            },
+            Shape::ConvexHull(ref hull) => {
+                let mut bounds = Aabb::empty();
+                for p in &hull.points {
+                    bounds.add_point(iso.transform_point(*p));
+                }
+                bounds
+            },
+            Shape::Triangle { ref points } => {
+                let mut bounds = Aabb::empty();
+                for p in points {
+                    bounds.add_point(iso.transform_point(*p));
+                }
+                bounds
+            },
+            _ => self.local_bounds().transform(&iso.to_matrix()),
+        }
+    }
+}
+
+// Gets the diagonal of the inertia tensor of a solid box.
+fn box_inertia(mass: GLfloat, h: Vector3D) -> Vector3D {
+    let (x, y, z) = (h.x * h.x, h.y * h.y, h.z * h.z);
+    Vector3D::new(y + z, x + z, x + y) * (mass / 3.0)
+}
@@ -0,0 +1,95 @@
+// Defines a TriMesh, which is a collision shape made of the triangles of a mesh, such as level
+// geometry imported from a model. Unlike the other shapes, a TriMesh doesn't have to be convex, so
+// other shapes are tested against the triangles near them one at a time. The triangles are kept in
+// a Bvh that is built once when the mesh is created, so finding the ones near a shape only visits
+// the part of the mesh that it overlaps.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::bounds::Aabb;
+use gfx::bvh::Bvh;
+use gfx::model::ModelInfo;
+use gfx::types::*;
+use std::fmt;
+
+// A triangle mesh in its local space, with every triangle as three indices into the vertices.
+pub struct TriMesh {
+    pub vertices: Vec<Vector3D>,
+    pub triangles: Vec<[usize; 3]>,
+    bounds: Aabb,
+    bvh: Bvh<usize>,
+}
+
+impl TriMesh {
+    // Creates a mesh from its vertices and indices, where every three indices make a triangle.
+    // Returns an Err if there are no triangles or an index is out of range.
+    pub fn new(vertices: Vec<Vector3D>, indices: &[usize]) -> Result<TriMesh, String> {
+        if indices.len() < 3 || indices.len() % 3 != 0 {
+            return Err(format!("A TriMesh needs a multiple of 3 indices, but got {}.",
+                    indices.len()));
+        }
+        if let Some(index) = indices.iter().find(|&&i| i >= vertices.len()) {
+            return Err(format!("TriMesh index {} is out of range of {} vertices.", index,
+                    vertices.len()));
+        }
+        let triangles: Vec<[usize; 3]> = indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        // The mesh never moves, so the leaves don't need to be fattened.
+        let mut bvh = Bvh::new();
+        bvh.margin = 0.0;
+        let mut bounds = Aabb::empty();
+        for (i, t) in triangles.iter().enumerate() {
+            let mut triangle_bounds = Aabb::empty();
+            for &index in t {
+                triangle_bounds.add_point(vertices[index]);
+            }
+            bounds = bounds.union(&triangle_bounds);
+            bvh.insert(triangle_bounds, i);
+        }
+        Ok(TriMesh { vertices: vertices, triangles: triangles, bounds: bounds, bvh: bvh })
+    }
+
+    // Creates a mesh from the triangles of a model, scaled to match the node it's placed on since
+    // collision shapes can't be scaled.
+    pub fn from_model(info: &ModelInfo, scale: Vector3D) -> Result<TriMesh, String> {
+        let vertices = info.vertices.chunks(3).filter(|p| p.len() == 3)
+                .map(|p| Vector3D::new(p[0] * scale.x, p[1] * scale.y, p[2] * scale.z)).collect();
+        let indices: Vec<usize> = info.elements.iter().map(|&i| i as usize).collect();
+        TriMesh::new(vertices, &indices)
+    }
+
+    // Gets the number of triangles.
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    // Returns true if there are no triangles, which can't happen for a mesh made with new().
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    // Gets the bounds of the mesh in its local space.
+    pub fn get_bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    // Gets the corners of a triangle.
+    pub fn get_triangle(&self, index: usize) -> [Vector3D; 3] {
+        let t = self.triangles[index];
+        [self.vertices[t[0]], self.vertices[t[1]], self.vertices[t[2]]]
+    }
+
+    // Calls a function with the corners of every triangle whose bounds overlap a box in the mesh's
+    // local space.
+    pub fn query<F>(&self, bounds: &Aabb, mut callback: F) where F: FnMut([Vector3D; 3]) {
+        self.bvh.query(|b| b.intersects(bounds), |_, &index| callback(self.get_triangle(index)));
+    }
+}
+
+impl fmt::Debug for TriMesh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TriMesh {{ vertices: {}, triangles: {} }}", self.vertices.len(),
+                self.triangles.len())
+    }
+}
@@ -0,0 +1,1054 @@
+// Defines a PhysicsWorld, which simulates rigid bodies that fall under gravity, bounce, slide, and
+// come to rest on each other. Each step follows semi-implicit Euler integration: velocities are
+// updated from gravity and forces first, contacts are then resolved by sequential impulses, which
+// apply an impulse at each contact point in turn over several iterations until the bodies stop
+// moving into each other, and positions are moved last by the resolved velocities. Overlap that
+// builds up is pushed apart a little each step by biasing the contact velocities. Joints are
+// solved in the same iterations, before the contacts.
+//
+// The narrowphase gives a single point per pair each step, so each pair keeps a manifold of up to
+// four points in the bodies' local spaces that persists while the points stay close to touching.
+// That's enough to let a box rest flat on the ground, and the impulses from the last step are
+// applied up front to warm start the solver, which keeps stacks steady. Bodies that have been
+// still for a while are put to sleep along with everything touching or joined to them, and are
+// woken up when an awake body touches them.
+//
+// The world runs in fixed steps, so update() takes the frame time and runs as many steps as fit,
+// carrying the remainder over to the next frame, and then copies the poses of the bodies onto
+// their scene nodes. Between steps, rays, sphere casts, and overlap tests find the bodies on a set
+// of collision layers, such as for shooting, ground checks, and line of sight. The world also
+// keeps an event for each pair of bodies that starts or stops touching, for things like impact
+// sounds.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::layers::{self, LayerMask};
+use gfx::scene::{NodeId, Scene, Transform};
+use gfx::types::*;
+use physics::collision::{self, Contact};
+use physics::collision_world::{ColliderId, CollisionWorld};
+use physics::joint::{Joint, JointId, JointKind};
+use physics::shape::{Isometry, Shape};
+use self::cgmath::{EuclideanVector, Matrix, SquareMatrix, Vector};
+use std::collections::{BTreeMap, HashSet};
+use std::mem;
+
+// How far apart manifold points can drift, along or across the normal, before they're dropped.
+const CONTACT_BREAKING: GLfloat = 0.02;
+
+// How much overlap is allowed without being pushed apart, which keeps resting contacts touching
+// from one step to the next, and the fraction of the rest that is pushed apart each step.
+const PENETRATION_SLOP: GLfloat = 0.005;
+const BAUMGARTE: GLfloat = 0.2;
+
+// Bodies hitting slower than this don't bounce, which lets bouncy bodies come to rest.
+const RESTITUTION_THRESHOLD: GLfloat = 1.0;
+
+// How slow bodies have to move in meters and radians per second, and for how many seconds, before
+// they can sleep.
+const SLEEP_LINEAR_VELOCITY: GLfloat = 0.05;
+const SLEEP_ANGULAR_VELOCITY: GLfloat = 0.05;
+const TIME_TO_SLEEP: GLfloat = 0.5;
+
+// Handle to a body in a PhysicsWorld.
+pub type BodyId = usize;
+
+// How a body is moved by the simulation.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BodyType {
+    Static,
+    Dynamic,
+    Kinematic,
+}
+
+// A solid object in a PhysicsWorld. The restitution is how bouncy the body is from 0 to 1, the
+// friction is the coefficient of friction, and the damping is the fraction of velocity lost per
+// second. If the body has a node, the world copies the body's pose onto it after updating. The
+// layers are the collision layers that the body is on, which queries filter by.
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    pub body_type: BodyType,
+    pub iso: Isometry,
+    pub linear_velocity: Vector3D,
+    pub angular_velocity: Vector3D,
+    pub restitution: GLfloat,
+    pub friction: GLfloat,
+    pub linear_damping: GLfloat,
+    pub angular_damping: GLfloat,
+    pub gravity_scale: GLfloat,
+    pub can_sleep: bool,
+    pub node: Option<NodeId>,
+    pub layers: LayerMask,
+    mass: GLfloat,
+    inv_mass: GLfloat,
+    inv_inertia: Vector3D,
+    force: Vector3D,
+    torque: Vector3D,
+    sleeping: bool,
+    sleep_time: GLfloat,
+    collider: ColliderId,
+}
+
+impl RigidBody {
+    // Creates a body at a pose. Dynamic bodies have a mass of 1 until set_mass() is called.
+    pub fn new(body_type: BodyType, iso: Isometry) -> RigidBody {
+        let zero = Vector3D::new(0.0, 0.0, 0.0);
+        RigidBody {
+            body_type: body_type, iso: iso, linear_velocity: zero, angular_velocity: zero,
+            restitution: 0.0, friction: 0.5, linear_damping: 0.01, angular_damping: 0.05,
+            gravity_scale: 1.0, can_sleep: true, node: None, layers: layers::DEFAULT_LAYER,
+            mass: 1.0, inv_mass: 0.0, inv_inertia: zero, force: zero, torque: zero,
+            sleeping: false, sleep_time: 0.0, collider: 0,
+        }
+    }
+
+    // Creates a dynamic body with a mass.
+    pub fn new_dynamic(iso: Isometry, mass: GLfloat) -> RigidBody {
+        let mut body = RigidBody::new(BodyType::Dynamic, iso);
+        body.mass = mass;
+        body
+    }
+
+    // Creates a static body.
+    pub fn new_static(iso: Isometry) -> RigidBody {
+        RigidBody::new(BodyType::Static, iso)
+    }
+
+    // Creates a kinematic body.
+    pub fn new_kinematic(iso: Isometry) -> RigidBody {
+        RigidBody::new(BodyType::Kinematic, iso)
+    }
+
+    // Gets the mass of the body.
+    pub fn get_mass(&self) -> GLfloat {
+        self.mass
+    }
+
+    // Sets the mass of the body and recomputes its inertia from its shape. Only dynamic bodies
+    // with a positive mass are moved by forces.
+    pub fn set_mass(&mut self, mass: GLfloat, shape: &Shape) {
+        self.mass = mass;
+        if self.body_type != BodyType::Dynamic || mass <= 0.0 {
+            self.inv_mass = 0.0;
+            self.inv_inertia = Vector3D::new(0.0, 0.0, 0.0);
+            return;
+        }
+        let inertia = shape.compute_inertia(mass);
+        let invert = |i: GLfloat| if i > 0.0 { 1.0 / i } else { 0.0 };
+        self.inv_mass = 1.0 / mass;
+        self.inv_inertia = Vector3D::new(invert(inertia.x), invert(inertia.y), invert(inertia.z));
+    }
+
+    // Gets one over the mass, which is 0 for bodies that forces don't move.
+    pub fn get_inv_mass(&self) -> GLfloat {
+        self.inv_mass
+    }
+
+    // Multiplies a vector by the inverse inertia tensor in world space.
+    pub fn apply_inv_inertia(&self, v: Vector3D) -> Vector3D {
+        self.iso.transform_vector(self.inv_inertia * self.iso.inverse_transform_vector(v))
+    }
+
+    // Gets the velocity of a point on the body in world space.
+    pub fn get_point_velocity(&self, point: Vector3D) -> Vector3D {
+        self.linear_velocity + self.angular_velocity.cross(point - self.iso.pos)
+    }
+
+    // Returns true if the body is asleep, which means the world isn't moving it until something
+    // touches it.
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    // Returns true if the body is dynamic and awake, so the world moves it this step.
+    pub fn is_active(&self) -> bool {
+        self.body_type == BodyType::Dynamic && !self.sleeping
+    }
+
+    // Gets the collider of the body in the world's CollisionWorld.
+    pub fn get_collider(&self) -> ColliderId {
+        self.collider
+    }
+
+    // Adds a force through the center of mass for the next step.
+    pub fn add_force(&mut self, force: Vector3D) {
+        self.force = self.force + force;
+        self.wake();
+    }
+
+    // Adds a force at a point in world space for the next step, which also turns the body.
+    pub fn add_force_at(&mut self, force: Vector3D, point: Vector3D) {
+        self.force = self.force + force;
+        self.torque = self.torque + (point - self.iso.pos).cross(force);
+        self.wake();
+    }
+
+    // Adds a torque for the next step.
+    pub fn add_torque(&mut self, torque: Vector3D) {
+        self.torque = self.torque + torque;
+        self.wake();
+    }
+
+    // Changes the velocity of the body immediately by an impulse at a point in world space. Adding
+    // forces or impulses wakes the body up.
+    pub fn apply_impulse(&mut self, impulse: Vector3D, point: Vector3D) {
+        self.linear_velocity = self.linear_velocity + impulse * self.inv_mass;
+        let angular = self.apply_inv_inertia((point - self.iso.pos).cross(impulse));
+        self.angular_velocity = self.angular_velocity + angular;
+        self.wake();
+    }
+
+    // Wakes the body up.
+    pub fn wake(&mut self) {
+        self.sleeping = false;
+        self.sleep_time = 0.0;
+    }
+
+    // Puts the body to sleep and stops it.
+    pub fn sleep(&mut self) {
+        self.sleeping = true;
+        self.linear_velocity = Vector3D::new(0.0, 0.0, 0.0);
+        self.angular_velocity = Vector3D::new(0.0, 0.0, 0.0);
+    }
+
+    // Gets the forces added since the last step.
+    fn take_forces(&mut self) -> (Vector3D, Vector3D) {
+        let forces = (self.force, self.torque);
+        self.force = Vector3D::new(0.0, 0.0, 0.0);
+        self.torque = Vector3D::new(0.0, 0.0, 0.0);
+        forces
+    }
+}
+
+// A hit from a query against the bodies of a PhysicsWorld. The point and normal are on the surface
+// of the body that was hit, and the distance is how far the ray or shape travelled to reach it.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RaycastHit {
+    pub body: BodyId,
+    pub node: Option<NodeId>,
+    pub point: Vector3D,
+    pub normal: Vector3D,
+    pub distance: GLfloat,
+}
+
+// A change in whether two bodies touch, given with the lower body handle first. Events are kept
+// from each step until they are taken with take_collision_events().
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CollisionEvent {
+    Started(BodyId, BodyId),
+    Ended(BodyId, BodyId),
+}
+
+// A point of contact between two bodies kept in each body's local space, along with the impulses
+// applied at it last step.
+#[derive(Copy, Clone, Debug)]
+struct ManifoldPoint {
+    local_a: Vector3D,
+    local_b: Vector3D,
+    normal_impulse: GLfloat,
+    tangent_impulse: [GLfloat; 2],
+}
+
+// The points of contact between two bodies, with a normal pointing from the first to the second.
+#[derive(Clone, Debug)]
+struct Manifold {
+    normal: Vector3D,
+    points: Vec<ManifoldPoint>,
+}
+
+impl Manifold {
+    // Creates an empty manifold.
+    fn new() -> Manifold {
+        Manifold { normal: Vector3D::new(0.0, 0.0, 1.0), points: Vec::new() }
+    }
+
+    // Drops the points that the bodies have moved away from.
+    fn refresh(&mut self, iso_a: &Isometry, iso_b: &Isometry) {
+        let normal = self.normal;
+        self.points.retain(|p| {
+            let gap = iso_a.transform_point(p.local_a) - iso_b.transform_point(p.local_b);
+            let depth = gap.dot(normal);
+            depth > -CONTACT_BREAKING &&
+                    (gap - normal * depth).length2() < CONTACT_BREAKING * CONTACT_BREAKING
+        });
+    }
+
+    // Adds the contact found this step, replacing a point that is close to it.
+    fn add(&mut self, contact: &Contact, iso_a: &Isometry, iso_b: &Isometry) {
+        let half = contact.normal * (contact.depth * 0.5);
+        let (pa, pb) = (contact.point + half, contact.point - half);
+        let point = ManifoldPoint { local_a: iso_a.inverse_transform_point(pa),
+                local_b: iso_b.inverse_transform_point(pb), normal_impulse: 0.0,
+                tangent_impulse: [0.0, 0.0] };
+        self.normal = contact.normal;
+        for existing in &mut self.points {
+            let offset = iso_a.transform_point(existing.local_a) - pa;
+            if offset.length2() < CONTACT_BREAKING * CONTACT_BREAKING {
+                existing.local_a = point.local_a;
+                existing.local_b = point.local_b;
+                return;
+            }
+        }
+        self.points.push(point);
+        if self.points.len() > 4 {
+            self.reduce(iso_a, iso_b);
+        }
+    }
+
+    // Drops a point from a full manifold, keeping the deepest point and the rest that cover the
+    // most area.
+    fn reduce(&mut self, iso_a: &Isometry, iso_b: &Isometry) {
+        let normal = self.normal;
+        let depth = |p: &ManifoldPoint| {
+            (iso_a.transform_point(p.local_a) - iso_b.transform_point(p.local_b)).dot(normal)
+        };
+        let mut deepest = 0;
+        for i in 1..self.points.len() {
+            if depth(&self.points[i]) > depth(&self.points[deepest]) { deepest = i; }
+        }
+        let mut best = (0, -1.0);
+        for i in 0..self.points.len() {
+            if i == deepest { continue; }
+            let rest: Vec<Vector3D> = self.points.iter().enumerate().filter(|&(j, _)| j != i)
+                    .map(|(_, p)| p.local_a).collect();
+            let area = (rest[0] - rest[1]).cross(rest[2] - rest[3]).length2()
+                    .max((rest[0] - rest[2]).cross(rest[1] - rest[3]).length2())
+                    .max((rest[0] - rest[3]).cross(rest[1] - rest[2]).length2());
+            if area > best.1 { best = (i, area); }
+        }
+        self.points.remove(best.0);
+    }
+}
+
+// The velocity and mass of a body while contacts and joints are solved.
+#[derive(Copy, Clone, Debug)]
+pub struct SolverBody {
+    pub pos: Vector3D,
+    pub rot: Quaternion,
+    pub inv_mass: GLfloat,
+    pub inv_inertia: Vector3D,
+    pub linear_velocity: Vector3D,
+    pub angular_velocity: Vector3D,
+}
+
+impl SolverBody {
+    // Creates the solver's view of a body. Bodies that aren't moved by contacts have no inverse
+    // mass, but kinematic bodies keep their velocity so they push the bodies they touch.
+    pub fn new(body: &RigidBody) -> SolverBody {
+        let active = body.is_active();
+        let moving = active || body.body_type == BodyType::Kinematic;
+        let zero = Vector3D::new(0.0, 0.0, 0.0);
+        SolverBody {
+            pos: body.iso.pos, rot: body.iso.rot,
+            inv_mass: if active { body.inv_mass } else { 0.0 },
+            inv_inertia: if active { body.inv_inertia } else { zero },
+            linear_velocity: if moving { body.linear_velocity } else { zero },
+            angular_velocity: if moving { body.angular_velocity } else { zero },
+        }
+    }
+
+    // Multiplies a vector by the inverse inertia tensor in world space.
+    pub fn apply_inv_inertia(&self, v: Vector3D) -> Vector3D {
+        self.rot * (self.inv_inertia * (self.rot.conjugate() * v))
+    }
+
+    // Gets the inverse inertia tensor in world space.
+    pub fn get_inv_inertia_matrix(&self) -> cgmath::Matrix3<GLfloat> {
+        let rot = cgmath::Matrix3::from(self.rot);
+        rot * cgmath::Matrix3::from_diagonal(self.inv_inertia) * rot.transpose()
+    }
+
+    // Gets the velocity of a point at an offset from the center of the body.
+    pub fn get_velocity(&self, r: Vector3D) -> Vector3D {
+        self.linear_velocity + self.angular_velocity.cross(r)
+    }
+
+    // Applies an angular impulse.
+    pub fn apply_angular_impulse(&mut self, impulse: Vector3D) {
+        self.angular_velocity = self.angular_velocity + self.apply_inv_inertia(impulse);
+    }
+
+    // Applies an impulse at an offset from the center of the body.
+    pub fn apply_impulse(&mut self, impulse: Vector3D, r: Vector3D) {
+        self.linear_velocity = self.linear_velocity + impulse * self.inv_mass;
+        self.angular_velocity = self.angular_velocity + self.apply_inv_inertia(r.cross(impulse));
+    }
+
+    // Gets how much the body resists an impulse in a direction at an offset from its center.
+    pub fn get_inv_effective_mass(&self, r: Vector3D, dir: Vector3D) -> GLfloat {
+        self.inv_mass + self.apply_inv_inertia(r.cross(dir)).cross(r).dot(dir)
+    }
+}
+
+// A manifold point prepared for the solver.
+#[derive(Copy, Clone, Debug)]
+struct SolverPoint {
+    r_a: Vector3D,
+    r_b: Vector3D,
+    normal_mass: GLfloat,
+    tangent_mass: [GLfloat; 2],
+    bias: GLfloat,
+    normal_impulse: GLfloat,
+    tangent_impulse: [GLfloat; 2],
+}
+
+// A manifold prepared for the solver.
+#[derive(Clone, Debug)]
+struct SolverContact {
+    a: BodyId,
+    b: BodyId,
+    normal: Vector3D,
+    tangents: [Vector3D; 2],
+    friction: GLfloat,
+    points: Vec<SolverPoint>,
+}
+
+// A set of rigid bodies and the colliders that they touch each other with. Gravity points down the
+// Z axis by default, since Z is up in the engine, and the timestep is the length of each step in
+// seconds.
+pub struct PhysicsWorld {
+    pub gravity: Vector3D,
+    pub timestep: GLfloat,
+    pub iterations: usize,
+    pub max_steps: usize,
+    bodies: Vec<Option<RigidBody>>,
+    free: Vec<BodyId>,
+    colliders: CollisionWorld<BodyId>,
+    manifolds: BTreeMap<(BodyId, BodyId), Manifold>,
+    joints: Vec<Option<Joint>>,
+    free_joints: Vec<JointId>,
+    accumulator: GLfloat,
+    events: Vec<CollisionEvent>,
+}
+
+impl PhysicsWorld {
+    // Creates an empty world that steps 60 times a second.
+    pub fn new() -> PhysicsWorld {
+        PhysicsWorld {
+            gravity: Vector3D::new(0.0, 0.0, -9.81), timestep: 1.0 / 60.0, iterations: 10,
+            max_steps: 8, bodies: Vec::new(), free: Vec::new(), colliders: CollisionWorld::new(),
+            manifolds: BTreeMap::new(), joints: Vec::new(), free_joints: Vec::new(),
+            accumulator: 0.0, events: Vec::new(),
+        }
+    }
+
+    // Adds a body with a shape and returns its handle. The body's inertia is computed from the
+    // shape. Triangle meshes and heightfields have no volume to compute it from, so dynamic bodies
+    // with those shapes are made static.
+    pub fn add_body(&mut self, mut body: RigidBody, shape: Shape) -> BodyId {
+        if shape.is_concave() && body.body_type == BodyType::Dynamic {
+            body.body_type = BodyType::Static;
+        }
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => { self.bodies.push(None); self.bodies.len() - 1 },
+        };
+        let mass = body.mass;
+        body.set_mass(mass, &shape);
+        body.collider = self.colliders.add(shape, body.iso, id);
+        self.bodies[id] = Some(body);
+        id
+    }
+
+    // Removes a body and returns it, or None if the handle isn't in the world. Bodies that were
+    // resting on it are woken up, and the joints attached to it are removed.
+    pub fn remove_body(&mut self, id: BodyId) -> Option<RigidBody> {
+        let body = match self.bodies.get_mut(id).and_then(|b| b.take()) {
+            Some(body) => body,
+            None => { return None; },
+        };
+        let attached: Vec<JointId> = self.iter_joints().filter(|&(_, j)| j.a == id || j.b == id)
+                .map(|(joint_id, _)| joint_id).collect();
+        for joint_id in attached {
+            self.remove_joint(joint_id);
+        }
+        self.colliders.remove(body.collider);
+        let touching: Vec<(BodyId, BodyId)> = self.manifolds.keys()
+                .filter(|&&(a, b)| a == id || b == id).cloned().collect();
+        for (a, b) in touching {
+            self.manifolds.remove(&(a, b));
+            self.events.push(CollisionEvent::Ended(a, b));
+            let other = if a == id { b } else { a };
+            if let Some(other) = self.bodies[other].as_mut() { other.wake(); }
+        }
+        self.free.push(id);
+        Some(body)
+    }
+
+    // Gets a body.
+    pub fn get_body(&self, id: BodyId) -> Option<&RigidBody> {
+        self.bodies.get(id).and_then(|b| b.as_ref())
+    }
+
+    // Gets a mutable reference to a body. Use set_pose() to teleport the body, and wake() it up
+    // after changing its velocity.
+    pub fn get_body_mut(&mut self, id: BodyId) -> Option<&mut RigidBody> {
+        self.bodies.get_mut(id).and_then(|b| b.as_mut())
+    }
+
+    // Gets the shape of a body.
+    pub fn get_shape(&self, id: BodyId) -> Option<&Shape> {
+        self.get_body(id).and_then(|b| self.colliders.get(b.collider)).map(|c| &c.shape)
+    }
+
+    // Iterates over every body and its handle.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(BodyId, &'a RigidBody)> + 'a> {
+        Box::new(self.bodies.iter().enumerate().filter_map(|(id, b)| b.as_ref().map(|b| (id, b))))
+    }
+
+    // Gets the number of bodies.
+    pub fn len(&self) -> usize {
+        self.colliders.len()
+    }
+
+    // Returns true if there are no bodies.
+    pub fn is_empty(&self) -> bool {
+        self.colliders.is_empty()
+    }
+
+    // Gets the colliders of the bodies, whose data is the handle of their body.
+    pub fn get_collision_world(&self) -> &CollisionWorld<BodyId> {
+        &self.colliders
+    }
+
+    // Teleports a body to a pose and wakes it up. Returns an Err if the handle isn't in the world.
+    pub fn set_pose(&mut self, id: BodyId, iso: Isometry) -> Result<(), String> {
+        let collider = {
+            let body = try!(self.get_body_mut(id).ok_or(format!("Body {} doesn't exist.", id)));
+            body.iso = iso;
+            body.wake();
+            body.collider
+        };
+        self.colliders.set_pose(collider, iso)
+    }
+
+    // Sets the mass of a body, recomputing its inertia from its shape. Returns an Err if the
+    // handle isn't in the world.
+    pub fn set_mass(&mut self, id: BodyId, mass: GLfloat) -> Result<(), String> {
+        let shape = try!(self.get_shape(id).cloned().ok_or(format!("Body {} doesn't exist.", id)));
+        let body = self.bodies[id].as_mut().unwrap();
+        body.set_mass(mass, &shape);
+        body.wake();
+        Ok(())
+    }
+
+    // Wakes up a body. Returns an Err if the handle isn't in the world.
+    pub fn wake(&mut self, id: BodyId) -> Result<(), String> {
+        let body = try!(self.get_body_mut(id).ok_or(format!("Body {} doesn't exist.", id)));
+        body.wake();
+        Ok(())
+    }
+
+    // Adds a joint of a kind between two bodies in their current poses, which are the rest pose of
+    // the joint, and returns its handle. The anchor is the point in world space that the joint
+    // holds the bodies together at, and the axis is the direction in world space that a hinge
+    // turns around or that a ball-socket's swing is limited around. Returns an Err if either body
+    // isn't in the world or they are the same body.
+    pub fn add_joint(&mut self, kind: JointKind, a: BodyId, b: BodyId, anchor: Vector3D,
+            axis: Vector3D) -> Result<JointId, String> {
+        if a == b {
+            return Err(format!("Cannot join body {} to itself.", a));
+        }
+        let joint = {
+            let body_a = try!(self.get_body(a).ok_or(format!("Body {} doesn't exist.", a)));
+            let body_b = try!(self.get_body(b).ok_or(format!("Body {} doesn't exist.", b)));
+            Joint::new(kind, a, body_a, b, body_b, anchor, axis)
+        };
+        self.bodies[a].as_mut().unwrap().wake();
+        self.bodies[b].as_mut().unwrap().wake();
+        let id = match self.free_joints.pop() {
+            Some(id) => id,
+            None => { self.joints.push(None); self.joints.len() - 1 },
+        };
+        self.joints[id] = Some(joint);
+        Ok(id)
+    }
+
+    // Removes a joint and returns it, or None if the handle isn't in the world. The bodies it held
+    // together are woken up.
+    pub fn remove_joint(&mut self, id: JointId) -> Option<Joint> {
+        let joint = match self.joints.get_mut(id).and_then(|j| j.take()) {
+            Some(joint) => joint,
+            None => { return None; },
+        };
+        for &body in &[joint.a, joint.b] {
+            if let Some(body) = self.bodies[body].as_mut() { body.wake(); }
+        }
+        self.free_joints.push(id);
+        Some(joint)
+    }
+
+    // Gets a joint.
+    pub fn get_joint(&self, id: JointId) -> Option<&Joint> {
+        self.joints.get(id).and_then(|j| j.as_ref())
+    }
+
+    // Gets a mutable reference to a joint. Wake its bodies up after changing it.
+    pub fn get_joint_mut(&mut self, id: JointId) -> Option<&mut Joint> {
+        self.joints.get_mut(id).and_then(|j| j.as_mut())
+    }
+
+    // Iterates over every joint and its handle.
+    pub fn iter_joints<'a>(&'a self) -> Box<Iterator<Item=(JointId, &'a Joint)> + 'a> {
+        Box::new(self.joints.iter().enumerate().filter_map(|(id, j)| j.as_ref().map(|j| (id, j))))
+    }
+
+    // Gets how far a joint has turned around its axis from its rest pose, such as how far open a
+    // door is.
+    pub fn get_joint_angle(&self, id: JointId) -> Option<GLfloat> {
+        self.get_joint(id).map(|j| {
+            j.get_angle(self.bodies[j.a].as_ref().unwrap().iso.rot,
+                    self.bodies[j.b].as_ref().unwrap().iso.rot)
+        })
+    }
+
+    // Gets every point where two bodies touch as of the last step, with the normal pointing from
+    // the first body to the second.
+    pub fn get_contacts(&self) -> Vec<(BodyId, BodyId, Contact)> {
+        let mut contacts = Vec::new();
+        for (&(a, b), manifold) in &self.manifolds {
+            let (iso_a, iso_b) = (self.bodies[a].as_ref().unwrap().iso,
+                    self.bodies[b].as_ref().unwrap().iso);
+            for p in &manifold.points {
+                let (pa, pb) = (iso_a.transform_point(p.local_a), iso_b.transform_point(p.local_b));
+                contacts.push((a, b, Contact { point: (pa + pb) * 0.5, normal: manifold.normal,
+                        depth: (pa - pb).dot(manifold.normal) }));
+            }
+        }
+        contacts
+    }
+
+    // Takes the collision events from the steps since they were last taken.
+    pub fn take_collision_events(&mut self) -> Vec<CollisionEvent> {
+        mem::replace(&mut self.events, Vec::new())
+    }
+
+    // Gets the nearest body on a set of layers that a ray hits within a distance.
+    pub fn raycast(&self, origin: Vector3D, dir: Vector3D, max_dist: GLfloat, layers: LayerMask)
+            -> Option<RaycastHit> {
+        self.sphere_cast(origin, 0.0, dir, max_dist, layers)
+    }
+
+    // Gets every body on a set of layers that a ray hits within a distance, nearest first.
+    pub fn raycast_all(&self, origin: Vector3D, dir: Vector3D, max_dist: GLfloat,
+            layers: LayerMask) -> Vec<RaycastHit> {
+        self.sphere_cast_all(origin, 0.0, dir, max_dist, layers)
+    }
+
+    // Gets the nearest body on a set of layers that a sphere moving along a direction hits within
+    // a distance, such as to check for ground under a character.
+    pub fn sphere_cast(&self, origin: Vector3D, radius: GLfloat, dir: Vector3D,
+            max_dist: GLfloat, layers: LayerMask) -> Option<RaycastHit> {
+        self.cast(origin, radius, dir, max_dist, layers, false).into_iter().next()
+    }
+
+    // Gets every body on a set of layers that a sphere moving along a direction hits within a
+    // distance, nearest first.
+    pub fn sphere_cast_all(&self, origin: Vector3D, radius: GLfloat, dir: Vector3D,
+            max_dist: GLfloat, layers: LayerMask) -> Vec<RaycastHit> {
+        self.cast(origin, radius, dir, max_dist, layers, true)
+    }
+
+    // Gets the bodies on a set of layers that a placed shape overlaps.
+    pub fn overlap(&self, shape: &Shape, iso: &Isometry, layers: LayerMask) -> Vec<BodyId> {
+        let mut found: Vec<BodyId> = self.colliders.query_overlaps(shape, iso).into_iter()
+                .map(|c| self.colliders.get(c).unwrap().data)
+                .filter(|&id| self.bodies[id].as_ref().unwrap().layers & layers != 0).collect();
+        found.sort();
+        found
+    }
+
+    // Runs as many fixed steps as fit in the time since the last update, and then copies the poses
+    // of the bodies onto their scene nodes. At most max_steps are run, and time that doesn't fit is
+    // dropped so a slow frame doesn't make the next one slower. Returns the number of steps run.
+    pub fn update(&mut self, dt: GLfloat, scene: &mut Scene) -> usize {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= self.timestep && steps < self.max_steps {
+            let timestep = self.timestep;
+            self.step(timestep);
+            self.accumulator -= timestep;
+            steps += 1;
+        }
+        if steps == self.max_steps {
+            self.accumulator = self.accumulator.min(self.timestep);
+        }
+        self.sync_to_scene(scene);
+        steps
+    }
+
+    // Gets how far the time left over from the last update is towards the next step, from 0 to 1,
+    // which can be used to interpolate between poses.
+    pub fn get_alpha(&self) -> GLfloat {
+        (self.accumulator / self.timestep).min(1.0)
+    }
+
+    // Advances the simulation by a single step.
+    pub fn step(&mut self, dt: GLfloat) {
+        profile_scope!("World::step");
+        if dt <= 0.0 { return; }
+        self.update_colliders();
+        self.integrate_velocities(dt);
+        self.update_manifolds();
+        self.wake_touching();
+        self.solve(dt);
+        self.integrate_positions(dt);
+        self.update_sleep(dt);
+        self.update_colliders();
+    }
+
+    // Copies the poses of the bodies that move onto their scene nodes. Node poses are relative to
+    // their parent, so the scene should have been updated since the parents last moved, and the
+    // scene has to be updated again for the new poses to be drawn. Nodes keep their scale.
+    pub fn sync_to_scene(&self, scene: &mut Scene) {
+        for body in self.bodies.iter().filter_map(|b| b.as_ref()) {
+            let node_id = match body.node {
+                Some(node_id) if body.body_type != BodyType::Static => node_id,
+                _ => { continue; },
+            };
+            let parent = scene.get_node(node_id).and_then(|n| n.get_parent())
+                    .and_then(|p| scene.get_node(p)).map(|p| p.get_world_matrix());
+            let local = match parent.and_then(|m| m.invert()) {
+                Some(inverse) => Transform::from_matrix(&(inverse * body.iso.to_matrix())),
+                None => Transform::new(body.iso.pos, body.iso.rot, Vector3D::new(1.0, 1.0, 1.0)),
+            };
+            if let Some(node) = scene.get_node_mut(node_id) {
+                node.transform.pos = local.pos;
+                node.transform.rot = local.rot;
+            }
+        }
+    }
+
+    // Casts a sphere against the bodies on a set of layers.
+    fn cast(&self, origin: Vector3D, radius: GLfloat, dir: Vector3D, max_dist: GLfloat,
+            layers: LayerMask, all: bool) -> Vec<RaycastHit> {
+        if dir.length2() == 0.0 || max_dist < 0.0 { return Vec::new(); }
+        let shape = Shape::Sphere { radius: radius.max(0.0) };
+        let iso = Isometry::new(origin, Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        let hits = self.colliders.cast(&shape, &iso, dir.normalize(), max_dist, all, |_, &id| {
+            self.bodies[id].as_ref().unwrap().layers & layers != 0
+        });
+        hits.into_iter().map(|(collider, hit)| {
+            let body = self.colliders.get(collider).unwrap().data;
+            RaycastHit { body: body, node: self.bodies[body].as_ref().unwrap().node,
+                    point: hit.point, normal: hit.normal, distance: hit.distance }
+        }).collect()
+    }
+
+    // Moves the colliders of the bodies that move to their poses.
+    fn update_colliders(&mut self) {
+        for body in self.bodies.iter().filter_map(|b| b.as_ref()) {
+            if body.body_type != BodyType::Static && !body.sleeping {
+                self.colliders.set_pose(body.collider, body.iso).unwrap();
+            }
+        }
+    }
+
+    // Adds gravity and forces to the velocities of awake dynamic bodies and then damps them.
+    fn integrate_velocities(&mut self, dt: GLfloat) {
+        let gravity = self.gravity;
+        for body in self.bodies.iter_mut().filter_map(|b| b.as_mut()) {
+            let (force, torque) = body.take_forces();
+            if !body.is_active() { continue; }
+            let acceleration = gravity * body.gravity_scale + force * body.inv_mass;
+            body.linear_velocity = body.linear_velocity + acceleration * dt;
+            let angular = body.apply_inv_inertia(torque) * dt;
+            body.angular_velocity = body.angular_velocity + angular;
+            body.linear_velocity = body.linear_velocity / (1.0 + dt * body.linear_damping);
+            body.angular_velocity = body.angular_velocity / (1.0 + dt * body.angular_damping);
+        }
+    }
+
+    // Returns true if a body moves this step and can wake up the bodies it touches.
+    fn is_moving(&self, id: BodyId) -> bool {
+        let body = self.bodies[id].as_ref().unwrap();
+        body.is_active() || (body.body_type == BodyType::Kinematic &&
+                (body.linear_velocity.length2() > 0.0 || body.angular_velocity.length2() > 0.0))
+    }
+
+    // Finds the contacts for this step and merges them into the manifolds. Pairs where neither
+    // body moves keep their manifolds as they are, so sleeping stacks wake up warm started.
+    fn update_manifolds(&mut self) {
+        let joined: HashSet<(BodyId, BodyId)> = self.iter_joints()
+                .filter(|&(_, j)| !j.collide_connected)
+                .map(|(_, j)| if j.a < j.b { (j.a, j.b) } else { (j.b, j.a) }).collect();
+        let touching: HashSet<(BodyId, BodyId)> = self.manifolds.keys().cloned().collect();
+        let mut manifolds = BTreeMap::new();
+        for (ca, cb) in self.colliders.find_pairs() {
+            let (a, b) = (self.colliders.get(ca).unwrap().data,
+                    self.colliders.get(cb).unwrap().data);
+            let (a, b, ca, cb) = if a < b { (a, b, ca, cb) } else { (b, a, cb, ca) };
+            let dynamic = |id: BodyId| {
+                self.bodies[id].as_ref().unwrap().body_type == BodyType::Dynamic
+            };
+            if (!dynamic(a) && !dynamic(b)) || joined.contains(&(a, b)) { continue; }
+            let mut manifold = self.manifolds.remove(&(a, b)).unwrap_or(Manifold::new());
+            if self.is_moving(a) || self.is_moving(b) {
+                let (col_a, col_b) = (self.colliders.get(ca).unwrap(),
+                        self.colliders.get(cb).unwrap());
+                match collision::collide(&col_a.shape, &col_a.iso, &col_b.shape, &col_b.iso) {
+                    Some(contact) => {
+                        manifold.normal = contact.normal;
+                        manifold.refresh(&col_a.iso, &col_b.iso);
+                        manifold.add(&contact, &col_a.iso, &col_b.iso);
+                    },
+                    None => { manifold.points.clear(); },
+                }
+            }
+            if !manifold.points.is_empty() {
+                manifolds.insert((a, b), manifold);
+            }
+        }
+        for &pair in manifolds.keys().filter(|p| !touching.contains(p)) {
+            self.events.push(CollisionEvent::Started(pair.0, pair.1));
+        }
+        for &pair in touching.iter().filter(|p| !manifolds.contains_key(p)) {
+            self.events.push(CollisionEvent::Ended(pair.0, pair.1));
+        }
+        self.manifolds = manifolds;
+    }
+
+    // Gets every pair of bodies that touch or are joined.
+    fn get_links(&self) -> Vec<(BodyId, BodyId)> {
+        self.manifolds.keys().cloned().chain(self.iter_joints().map(|(_, j)| (j.a, j.b))).collect()
+    }
+
+    // Wakes up the sleeping bodies touched by or joined to moving ones, and everything that they
+    // touch or are joined to.
+    fn wake_touching(&mut self) {
+        let links = self.get_links();
+        let mut woke = true;
+        while woke {
+            woke = false;
+            for &(a, b) in &links {
+                for &(sleeper, other) in &[(a, b), (b, a)] {
+                    if self.bodies[sleeper].as_ref().unwrap().sleeping && self.is_moving(other) {
+                        self.bodies[sleeper].as_mut().unwrap().wake();
+                        woke = true;
+                    }
+                }
+            }
+        }
+    }
+
+    // Resolves the joints and contacts with sequential impulses.
+    fn solve(&mut self, dt: GLfloat) {
+        let mut solver_bodies: Vec<Option<SolverBody>> = self.bodies.iter()
+                .map(|b| b.as_ref().map(SolverBody::new)).collect();
+        let mut contacts = Vec::new();
+        for (&(a, b), manifold) in &self.manifolds {
+            if !self.bodies[a].as_ref().unwrap().is_active() &&
+                    !self.bodies[b].as_ref().unwrap().is_active() {
+                continue;
+            }
+            let (body_a, body_b) = (self.bodies[a].as_ref().unwrap(),
+                    self.bodies[b].as_ref().unwrap());
+            let (sa, sb) = (solver_bodies[a].unwrap(), solver_bodies[b].unwrap());
+            let normal = manifold.normal;
+            let tangents = tangent_basis(normal);
+            let restitution = body_a.restitution.max(body_b.restitution);
+            let mut points = Vec::with_capacity(manifold.points.len());
+            for p in &manifold.points {
+                let (pa, pb) = (body_a.iso.transform_point(p.local_a),
+                        body_b.iso.transform_point(p.local_b));
+                let point = (pa + pb) * 0.5;
+                let (r_a, r_b) = (point - sa.pos, point - sb.pos);
+                let inv_mass = |dir: Vector3D| {
+                    sa.get_inv_effective_mass(r_a, dir) + sb.get_inv_effective_mass(r_b, dir)
+                };
+                let invert = |k: GLfloat| if k > 0.0 { 1.0 / k } else { 0.0 };
+
+                // Push overlapping points apart, let separated ones close their gap, and bounce
+                // points that hit fast enough.
+                let depth = (pa - pb).dot(normal);
+                let approach = (sb.get_velocity(r_b) - sa.get_velocity(r_a)).dot(normal);
+                let bias = if depth < 0.0 { depth / dt } else {
+                    let push = BAUMGARTE / dt * (depth - PENETRATION_SLOP).max(0.0);
+                    if approach < -RESTITUTION_THRESHOLD {
+                        push.max(-restitution * approach)
+                    } else {
+                        push
+                    }
+                };
+                points.push(SolverPoint {
+                    r_a: r_a, r_b: r_b, normal_mass: invert(inv_mass(normal)),
+                    tangent_mass: [invert(inv_mass(tangents[0])), invert(inv_mass(tangents[1]))],
+                    bias: bias, normal_impulse: p.normal_impulse,
+                    tangent_impulse: p.tangent_impulse,
+                });
+            }
+            contacts.push(SolverContact { a: a, b: b, normal: normal, tangents: tangents,
+                    friction: (body_a.friction * body_b.friction).sqrt(), points: points });
+        }
+
+        // Warm start with the impulses from the last step, then iterate.
+        for contact in &contacts {
+            let (mut sa, mut sb) = (solver_bodies[contact.a].unwrap(),
+                    solver_bodies[contact.b].unwrap());
+            for p in &contact.points {
+                let impulse = contact.normal * p.normal_impulse +
+                        contact.tangents[0] * p.tangent_impulse[0] +
+                        contact.tangents[1] * p.tangent_impulse[1];
+                sa.apply_impulse(-impulse, p.r_a);
+                sb.apply_impulse(impulse, p.r_b);
+            }
+            solver_bodies[contact.a] = Some(sa);
+            solver_bodies[contact.b] = Some(sb);
+        }
+        let mut joints: Vec<&mut Joint> = self.joints.iter_mut().filter_map(|j| j.as_mut())
+                .filter(|j| {
+                    solver_bodies[j.a].map_or(false, |b| b.inv_mass > 0.0) ||
+                            solver_bodies[j.b].map_or(false, |b| b.inv_mass > 0.0)
+                }).collect();
+        for joint in &mut joints {
+            let (mut sa, mut sb) = (solver_bodies[joint.a].unwrap(),
+                    solver_bodies[joint.b].unwrap());
+            joint.prepare(&mut sa, &mut sb, dt);
+            solver_bodies[joint.a] = Some(sa);
+            solver_bodies[joint.b] = Some(sb);
+        }
+        for _ in 0..self.iterations {
+            for joint in &mut joints {
+                let (mut sa, mut sb) = (solver_bodies[joint.a].unwrap(),
+                        solver_bodies[joint.b].unwrap());
+                joint.solve(&mut sa, &mut sb);
+                solver_bodies[joint.a] = Some(sa);
+                solver_bodies[joint.b] = Some(sb);
+            }
+            for contact in &mut contacts {
+                let (mut sa, mut sb) = (solver_bodies[contact.a].unwrap(),
+                        solver_bodies[contact.b].unwrap());
+                solve_contact(contact, &mut sa, &mut sb);
+                solver_bodies[contact.a] = Some(sa);
+                solver_bodies[contact.b] = Some(sb);
+            }
+        }
+
+        // Keep the impulses for the next step and copy the velocities back onto the bodies.
+        for joint in &mut joints {
+            joint.finish();
+        }
+        for contact in &contacts {
+            let manifold = self.manifolds.get_mut(&(contact.a, contact.b)).unwrap();
+            for (p, solved) in manifold.points.iter_mut().zip(contact.points.iter()) {
+                p.normal_impulse = solved.normal_impulse;
+                p.tangent_impulse = solved.tangent_impulse;
+            }
+        }
+        for (body, solved) in self.bodies.iter_mut().zip(solver_bodies.iter()) {
+            if let (Some(body), Some(solved)) = (body.as_mut(), solved.as_ref()) {
+                if body.is_active() {
+                    body.linear_velocity = solved.linear_velocity;
+                    body.angular_velocity = solved.angular_velocity;
+                }
+            }
+        }
+    }
+
+    // Moves awake dynamic bodies and kinematic bodies by their velocities.
+    fn integrate_positions(&mut self, dt: GLfloat) {
+        for body in self.bodies.iter_mut().filter_map(|b| b.as_mut()) {
+            if !body.is_active() && body.body_type != BodyType::Kinematic { continue; }
+            body.iso.pos = body.iso.pos + body.linear_velocity * dt;
+            let w = body.angular_velocity * (0.5 * dt);
+            let spin = Quaternion::new(0.0, w.x, w.y, w.z) * body.iso.rot;
+            let rot = body.iso.rot;
+            body.iso.rot = Quaternion::new(rot.s + spin.s, rot.v.x + spin.v.x, rot.v.y + spin.v.y,
+                    rot.v.z + spin.v.z).normalize();
+        }
+    }
+
+    // Puts islands of touching dynamic bodies to sleep once every body in them has been still for
+    // long enough.
+    fn update_sleep(&mut self, dt: GLfloat) {
+        for body in self.bodies.iter_mut().filter_map(|b| b.as_mut()) {
+            if !body.is_active() { continue; }
+            let (linear, angular) = (SLEEP_LINEAR_VELOCITY, SLEEP_ANGULAR_VELOCITY);
+            let still = body.linear_velocity.length2() < linear * linear &&
+                    body.angular_velocity.length2() < angular * angular;
+            body.sleep_time = if body.can_sleep && still { body.sleep_time + dt } else { 0.0 };
+        }
+
+        // Bodies driven by a motor never stop.
+        for joint in self.joints.iter().filter_map(|j| j.as_ref()) {
+            if !joint.has_running_motor() { continue; }
+            for &id in &[joint.a, joint.b] {
+                self.bodies[id].as_mut().unwrap().sleep_time = 0.0;
+            }
+        }
+
+        // Join the bodies into islands, and find the least time that any body in each island has
+        // been still for.
+        let mut islands: Vec<usize> = (0..self.bodies.len()).collect();
+        for (a, b) in self.get_links() {
+            if self.bodies[a].as_ref().unwrap().is_active() &&
+                    self.bodies[b].as_ref().unwrap().is_active() {
+                let (root_a, root_b) = (find_root(&mut islands, a), find_root(&mut islands, b));
+                islands[root_a] = root_b;
+            }
+        }
+        let mut still_time = vec![::std::f32::MAX; self.bodies.len()];
+        for id in 0..self.bodies.len() {
+            let root = find_root(&mut islands, id);
+            if let Some(body) = self.bodies[id].as_ref() {
+                if body.is_active() { still_time[root] = still_time[root].min(body.sleep_time); }
+            }
+        }
+        for id in 0..self.bodies.len() {
+            let root = find_root(&mut islands, id);
+            if let Some(body) = self.bodies[id].as_mut() {
+                if body.is_active() && still_time[root] >= TIME_TO_SLEEP { body.sleep(); }
+            }
+        }
+    }
+}
+
+// Runs a single iteration of the solver on a contact.
+fn solve_contact(contact: &mut SolverContact, sa: &mut SolverBody, sb: &mut SolverBody) {
+    for p in &mut contact.points {
+        // Friction is limited by the normal impulse from the last iteration.
+        let limit = contact.friction * p.normal_impulse;
+        for i in 0..2 {
+            let tangent = contact.tangents[i];
+            let speed = (sb.get_velocity(p.r_b) - sa.get_velocity(p.r_a)).dot(tangent);
+            let total = (p.tangent_impulse[i] - speed * p.tangent_mass[i]).max(-limit).min(limit);
+            let impulse = tangent * (total - p.tangent_impulse[i]);
+            p.tangent_impulse[i] = total;
+            sa.apply_impulse(-impulse, p.r_a);
+            sb.apply_impulse(impulse, p.r_b);
+        }
+
+        // The total normal impulse can only push the bodies apart.
+        let speed = (sb.get_velocity(p.r_b) - sa.get_velocity(p.r_a)).dot(contact.normal);
+        let total = (p.normal_impulse + (p.bias - speed) * p.normal_mass).max(0.0);
+        let impulse = contact.normal * (total - p.normal_impulse);
+        p.normal_impulse = total;
+        sa.apply_impulse(-impulse, p.r_a);
+        sb.apply_impulse(impulse, p.r_b);
+    }
+}
+
+// Gets two directions perpendicular to a normal and each other.
+fn tangent_basis(normal: Vector3D) -> [Vector3D; 2] {
+    let axis = if normal.x.abs() < 0.57 { Vector3D::unit_x() } else { Vector3D::unit_y() };
+    let first = normal.cross(axis).normalize();
+    [first, normal.cross(first)]
+}
+
+// Finds the root of a set in a union-find forest, flattening the path to it on the way.
+fn find_root(parents: &mut Vec<usize>, id: usize) -> usize {
+    let mut root = id;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut current = id;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
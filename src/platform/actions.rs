@@ -0,0 +1,230 @@
+// Defines an ActionMap, which lets game code ask about named actions like "jump" or "move_x"
+// instead of particular keys and buttons. Each action is bound to any number of keys, mouse
+// buttons, gamepad buttons, and directions of gamepad axes, and the bindings can be changed at any
+// time so that players can remap their controls. An action's value is the strongest of its
+// bindings, from 0.0 to 1.0, and it counts as held down once its value passes the threshold, so an
+// axis can stand in for a button and the other way around. Bindings have names such as "key:Space",
+// "mouse:Left", "button:South", and "axis:LeftX+" so that they can be read from configuration
+// files.
+//
+// Usage of an ActionMap:
+// - Create it with new() and bind() each action to its defaults.
+// - Call update(&input, &gamepads) once per frame after updating the Input and Gamepads.
+// - Ask about actions with is_down(), was_pressed(), was_released(), and get_value(), or get_axis()
+//   for a pair of opposing actions.
+//
+// Brian Ho
+// brian@brkho.com
+
+use platform::gamepad::{GamepadAxis, GamepadButton, Gamepads};
+use platform::input::Input;
+use platform::window::{MouseButton, VirtualKeyCode};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+
+// The default value that an action has to pass to be held down.
+const DEFAULT_THRESHOLD: f32 = 0.5;
+
+// The keys that can be bound by name, which is the name of their VirtualKeyCode.
+static KEYS: [VirtualKeyCode; 96] = [VirtualKeyCode::Key1, VirtualKeyCode::Key2,
+        VirtualKeyCode::Key3, VirtualKeyCode::Key4, VirtualKeyCode::Key5, VirtualKeyCode::Key6,
+        VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9, VirtualKeyCode::Key0,
+        VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D,
+        VirtualKeyCode::E, VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H,
+        VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+        VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P,
+        VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+        VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+        VirtualKeyCode::Y, VirtualKeyCode::Z, VirtualKeyCode::Escape, VirtualKeyCode::F1,
+        VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4, VirtualKeyCode::F5,
+        VirtualKeyCode::F6, VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F9,
+        VirtualKeyCode::F10, VirtualKeyCode::F11, VirtualKeyCode::F12, VirtualKeyCode::Snapshot,
+        VirtualKeyCode::Pause, VirtualKeyCode::Insert, VirtualKeyCode::Home,
+        VirtualKeyCode::Delete, VirtualKeyCode::End, VirtualKeyCode::PageDown,
+        VirtualKeyCode::PageUp, VirtualKeyCode::Left, VirtualKeyCode::Up, VirtualKeyCode::Right,
+        VirtualKeyCode::Down, VirtualKeyCode::Back, VirtualKeyCode::Return, VirtualKeyCode::Space,
+        VirtualKeyCode::Numpad0, VirtualKeyCode::Numpad1, VirtualKeyCode::Numpad2,
+        VirtualKeyCode::Numpad3, VirtualKeyCode::Numpad4, VirtualKeyCode::Numpad5,
+        VirtualKeyCode::Numpad6, VirtualKeyCode::Numpad7, VirtualKeyCode::Numpad8,
+        VirtualKeyCode::Numpad9, VirtualKeyCode::Add, VirtualKeyCode::Subtract,
+        VirtualKeyCode::Multiply, VirtualKeyCode::Divide, VirtualKeyCode::Apostrophe,
+        VirtualKeyCode::Backslash, VirtualKeyCode::Comma, VirtualKeyCode::Equals,
+        VirtualKeyCode::Grave, VirtualKeyCode::LAlt, VirtualKeyCode::LBracket,
+        VirtualKeyCode::LControl, VirtualKeyCode::LShift, VirtualKeyCode::Minus,
+        VirtualKeyCode::Period, VirtualKeyCode::RAlt, VirtualKeyCode::RBracket,
+        VirtualKeyCode::RControl, VirtualKeyCode::RShift, VirtualKeyCode::Semicolon,
+        VirtualKeyCode::Slash, VirtualKeyCode::Tab];
+
+// The mouse buttons, gamepad buttons, and gamepad axes that can be bound by name.
+static MOUSE_BUTTONS: [MouseButton; 3] = [MouseButton::Left, MouseButton::Right,
+        MouseButton::Middle];
+static BUTTONS: [GamepadButton; 15] = [GamepadButton::South, GamepadButton::East,
+        GamepadButton::West, GamepadButton::North, GamepadButton::LeftBumper,
+        GamepadButton::RightBumper, GamepadButton::Select, GamepadButton::Start,
+        GamepadButton::Mode, GamepadButton::LeftStick, GamepadButton::RightStick,
+        GamepadButton::DPadUp, GamepadButton::DPadDown, GamepadButton::DPadLeft,
+        GamepadButton::DPadRight];
+static AXES: [GamepadAxis; 6] = [GamepadAxis::LeftX, GamepadAxis::LeftY, GamepadAxis::RightX,
+        GamepadAxis::RightY, GamepadAxis::LeftTrigger, GamepadAxis::RightTrigger];
+
+// Finds the item of a list whose debug name is a name.
+fn find_named<T: Copy + Debug>(items: &[T], name: &str) -> Option<T> {
+    items.iter().find(|item| format!("{:?}", item) == name).cloned()
+}
+
+// Something that an action can be bound to. Gamepad bindings are read from every connected
+// gamepad. An axis binding reads the axis in the direction of its sign, so (LeftX, 1.0) is the
+// left stick pushed right and (LeftX, -1.0) is it pushed left.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Button(GamepadButton),
+    Axis(GamepadAxis, f32),
+}
+
+impl Binding {
+    // Gets a binding from its name, such as "key:Space", "mouse:Left", "button:South", or
+    // "axis:LeftX+". Returns None if the name isn't a binding.
+    pub fn from_name(name: &str) -> Option<Binding> {
+        let mut parts = name.trim().splitn(2, ':');
+        let (kind, item) = match (parts.next(), parts.next()) {
+            (Some(kind), Some(item)) => (kind, item),
+            _ => return None,
+        };
+        match kind {
+            "key" => find_named(&KEYS, item).map(Binding::Key),
+            "mouse" => find_named(&MOUSE_BUTTONS, item).map(Binding::Mouse),
+            "button" => find_named(&BUTTONS, item).map(Binding::Button),
+            "axis" if item.ends_with("+") || item.ends_with("-") => {
+                let sign = if item.ends_with("+") { 1.0 } else { -1.0 };
+                find_named(&AXES, &item[..(item.len() - 1)]).map(|axis| Binding::Axis(axis, sign))
+            },
+            _ => None,
+        }
+    }
+
+    // Gets the name of the binding that from_name() reads.
+    pub fn get_name(&self) -> String {
+        match *self {
+            Binding::Key(key) => format!("key:{:?}", key),
+            Binding::Mouse(button) => format!("mouse:{:?}", button),
+            Binding::Button(button) => format!("button:{:?}", button),
+            Binding::Axis(axis, sign) => {
+                format!("axis:{:?}{}", axis, if sign < 0.0 { "-" } else { "+" })
+            },
+        }
+    }
+
+    // Gets the value of the binding from 0.0 to 1.0.
+    pub fn get_value(&self, input: &Input, gamepads: &Gamepads) -> f32 {
+        match *self {
+            Binding::Key(key) => if input.is_key_down(key) { 1.0 } else { 0.0 },
+            Binding::Mouse(button) => if input.is_button_down(button) { 1.0 } else { 0.0 },
+            Binding::Button(button) => {
+                if gamepads.iter().any(|p| p.is_button_down(button)) { 1.0 } else { 0.0 }
+            },
+            Binding::Axis(axis, sign) => {
+                gamepads.iter().map(|p| (p.get_axis(axis) * sign).max(0.0).min(1.0))
+                        .fold(0.0, f32::max)
+            },
+        }
+    }
+}
+
+// Named actions along with their bindings and their state as of the last update.
+pub struct ActionMap {
+    pub threshold: f32,
+    bindings: HashMap<String, Vec<Binding>>,
+    values: HashMap<String, f32>,
+    down: HashSet<String>,
+    pressed: HashSet<String>,
+    released: HashSet<String>,
+}
+
+impl ActionMap {
+    // Creates an ActionMap without any actions.
+    pub fn new() -> ActionMap {
+        ActionMap { threshold: DEFAULT_THRESHOLD, bindings: HashMap::new(),
+                values: HashMap::new(), down: HashSet::new(), pressed: HashSet::new(),
+                released: HashSet::new() }
+    }
+
+    // Binds an action to something, adding the action if it is new.
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.bindings.entry(action.to_string()).or_insert(Vec::new());
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    // Removes a binding from an action.
+    pub fn unbind(&mut self, action: &str, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    // Removes every binding of an action, which keeps the action so that it can be rebound.
+    pub fn clear(&mut self, action: &str) {
+        if let Some(bindings) = self.bindings.get_mut(action) {
+            bindings.clear();
+        }
+    }
+
+    // Replaces the bindings of an action.
+    pub fn rebind(&mut self, action: &str, bindings: &[Binding]) {
+        self.bindings.insert(action.to_string(), bindings.to_vec());
+    }
+
+    // Gets the bindings of an action.
+    pub fn get_bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], |b| &b[..])
+    }
+
+    // Gets the names of the actions.
+    pub fn get_actions(&self) -> Vec<&str> {
+        self.bindings.keys().map(|a| &a[..]).collect()
+    }
+
+    // Updates the value and state of every action from the keyboard, mouse, and gamepads.
+    pub fn update(&mut self, input: &Input, gamepads: &Gamepads) {
+        self.pressed.clear();
+        self.released.clear();
+        for (action, bindings) in &self.bindings {
+            let value = bindings.iter().map(|b| b.get_value(input, gamepads)).fold(0.0, f32::max);
+            self.values.insert(action.clone(), value);
+            if value >= self.threshold && value > 0.0 {
+                if self.down.insert(action.clone()) { self.pressed.insert(action.clone()); }
+            } else if self.down.remove(action) {
+                self.released.insert(action.clone());
+            }
+        }
+    }
+
+    // Gets the value of an action from 0.0 to 1.0.
+    pub fn get_value(&self, action: &str) -> f32 {
+        self.values.get(action).cloned().unwrap_or(0.0)
+    }
+
+    // Gets a value from -1.0 to 1.0 from a pair of opposing actions, like "move_left" and
+    // "move_right".
+    pub fn get_axis(&self, negative: &str, positive: &str) -> f32 {
+        self.get_value(positive) - self.get_value(negative)
+    }
+
+    // Returns true if an action is held down.
+    pub fn is_down(&self, action: &str) -> bool {
+        self.down.contains(action)
+    }
+
+    // Returns true if an action was pressed during the last update.
+    pub fn was_pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    // Returns true if an action was released during the last update.
+    pub fn was_released(&self, action: &str) -> bool {
+        self.released.contains(action)
+    }
+}
@@ -0,0 +1,169 @@
+// Defines a GameLoop, which drives a Game with a fixed timestep. The simulation runs in ticks of
+// the same length no matter how fast the machine draws frames, so gameplay plays out the same way
+// everywhere. Each frame, the time since the last frame is added to an accumulator and as many
+// ticks as fit are run, with the remainder carried over to the next frame. Rendering then happens
+// once per frame at whatever rate the display runs at, with an alpha that says how far the leftover
+// time is towards the next tick, so that the state of the last two ticks can be blended and motion
+// stays smooth when ticks and frames don't line up. Interpolated keeps both states for that.
+//
+// A frame that takes too long runs at most max_ticks, and the time that doesn't fit is dropped, so
+// a slow frame can't make the next one slower and spiral out of control.
+//
+// Usage of a GameLoop:
+// - Implement Game with fixed_update() for the simulation and render() for drawing, and
+//   optionally update() for per-frame work like input and is_running() to stop the loop.
+// - Create the loop with new(tick_rate) and call run(&mut game), or call frame(&mut game) from a
+//   loop of your own.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate time;
+
+use anim::clip::Keyframe;
+
+// The most ticks that are run in a single frame by default.
+const DEFAULT_MAX_TICKS: usize = 8;
+
+// The longest time in seconds that a frame can count for, which keeps a pause in a debugger from
+// turning into a burst of ticks.
+const MAX_FRAME_TIME: f64 = 0.25;
+
+// The hooks that a GameLoop calls. update() is called once per frame before the ticks with the
+// frame time, fixed_update() once per tick with the tick length, and render() once per frame after
+// the ticks with how far the frame is between the last tick and the next one, from 0.0 to 1.0.
+pub trait Game {
+    fn fixed_update(&mut self, dt: f32);
+
+    fn render(&mut self, alpha: f32);
+
+    fn update(&mut self, _: f32) {}
+
+    fn is_running(&self) -> bool {
+        true
+    }
+}
+
+// The timing of a fixed timestep loop.
+pub struct GameLoop {
+    pub tick_rate: f64,                     // Ticks per second.
+    pub max_ticks: usize,
+    accumulator: f64,
+    last_time: Option<f64>,
+    ticks: u64,
+    frames: u64,
+}
+
+impl GameLoop {
+    // Creates a loop that runs a number of ticks per second.
+    pub fn new(tick_rate: f64) -> GameLoop {
+        GameLoop { tick_rate: tick_rate, max_ticks: DEFAULT_MAX_TICKS, accumulator: 0.0,
+                last_time: None, ticks: 0, frames: 0 }
+    }
+
+    // Gets the length of a tick in seconds.
+    pub fn get_timestep(&self) -> f64 {
+        1.0 / self.tick_rate
+    }
+
+    // Gets how far the time left over from the last frame is towards the next tick, from 0.0 to
+    // 1.0.
+    pub fn get_alpha(&self) -> f32 {
+        (self.accumulator / self.get_timestep()).min(1.0) as f32
+    }
+
+    // Gets the number of ticks run so far.
+    pub fn get_tick_count(&self) -> u64 {
+        self.ticks
+    }
+
+    // Gets the number of frames run so far.
+    pub fn get_frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    // Gets the time of the simulation in seconds, which is the number of ticks times their length.
+    pub fn get_time(&self) -> f64 {
+        self.ticks as f64 * self.get_timestep()
+    }
+
+    // Runs frames until the game stops running.
+    pub fn run<G: Game>(&mut self, game: &mut G) {
+        while game.is_running() {
+            self.frame(game);
+        }
+    }
+
+    // Runs a frame with the time measured since the last one, which is zero for the first frame.
+    // Returns the number of ticks run.
+    pub fn frame<G: Game>(&mut self, game: &mut G) -> usize {
+        let now = time::precise_time_s();
+        let frame_time = self.last_time.map_or(0.0, |last| now - last);
+        self.last_time = Some(now);
+        self.advance(game, frame_time)
+    }
+
+    // Runs a frame that took a given time in seconds. This is what frame() does with the measured
+    // time, and can be called directly to drive the loop with a made up clock, such as in tests or
+    // when recording a video. Returns the number of ticks run.
+    pub fn advance<G: Game>(&mut self, game: &mut G, frame_time: f64) -> usize {
+        let frame_time = frame_time.max(0.0).min(MAX_FRAME_TIME);
+        let timestep = self.get_timestep();
+        profile_block!("Game::update", { game.update(frame_time as f32); });
+        self.accumulator += frame_time;
+        let mut ticks = 0;
+        while self.accumulator >= timestep && ticks < self.max_ticks {
+            profile_block!("Game::fixed_update", { game.fixed_update(timestep as f32); });
+            self.accumulator -= timestep;
+            ticks += 1;
+        }
+        if ticks == self.max_ticks {
+            log_debug!(Core, "Frame fell behind by {:.3}s, dropping time.", self.accumulator);
+            self.accumulator = self.accumulator.min(timestep);
+        }
+        self.ticks += ticks as u64;
+        self.frames += 1;
+        let alpha = self.get_alpha();
+        profile_block!("Game::render", { game.render(alpha); });
+        ticks
+    }
+
+    // Forgets the time of the last frame so that the next frame starts fresh, such as after
+    // loading a level or unpausing.
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+        self.last_time = None;
+    }
+}
+
+// A value that changes once per tick along with its value from the tick before, so that it can be
+// drawn between them.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Interpolated<T: Keyframe> {
+    pub previous: T,
+    pub current: T,
+}
+
+impl<T: Keyframe> Interpolated<T> {
+    // Creates a value that hasn't moved.
+    pub fn new(value: T) -> Interpolated<T> {
+        Interpolated { previous: value, current: value }
+    }
+
+    // Sets the value for a new tick, which makes the current value the previous one.
+    pub fn set(&mut self, value: T) {
+        self.previous = self.current;
+        self.current = value;
+    }
+
+    // Sets the value without blending from the old one, such as when teleporting.
+    pub fn reset(&mut self, value: T) {
+        self.previous = value;
+        self.current = value;
+    }
+
+    // Gets the value between the previous and current ticks by an alpha from a GameLoop.
+    pub fn get(&self, alpha: f32) -> T {
+        T::interpolate(self.previous, self.current, alpha)
+    }
+}
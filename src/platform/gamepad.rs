@@ -0,0 +1,454 @@
+// Defines Gamepads, which keeps the state of every connected gamepad from the events of a
+// GamepadBackend. Gamepads are numbered by the slot they were connected in, and keep their number
+// until they are disconnected. Sticks and triggers are read through a deadzone so that a stick at
+// rest reads exactly 0.0 instead of drifting. Sticks use a radial deadzone, which keeps diagonals
+// smooth, and the values past it are rescaled to start from 0.0.
+//
+// Buttons and axes are named by their position on an Xbox-style controller. Stick Y axes are
+// positive up and triggers go from 0.0 at rest to 1.0 when pulled all the way. On Linux, the
+// joystick devices at /dev/input/js* are read on a thread each, and other platforms have no
+// backend yet, so they never see a gamepad. Tests can feed events to handle_event() by hand.
+//
+// Usage of Gamepads:
+// - Create it with new() for the platform's backend, or with_backend() for another one.
+// - Call update() at the start of every frame, which returns the events so that connections can
+//   be noticed.
+// - Ask about a gamepad by its number with get(), or about the first connected one with first().
+//
+// Brian Ho
+// brian@brkho.com
+
+use platform::window::ElementState;
+use std::collections::{HashMap, HashSet};
+
+// The default radius of the deadzones of the sticks and triggers.
+const DEFAULT_DEADZONE: f32 = 0.15;
+
+// A button on a gamepad. The face buttons are named by where they are, so South is A on an Xbox
+// controller and cross on a PlayStation one.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadButton {
+    South,
+    East,
+    West,
+    North,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    Mode,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Other(u8),
+}
+
+// An analog axis on a gamepad.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum GamepadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+    Other(u8),
+}
+
+// Something that happened to a gamepad, given with the gamepad's number.
+#[derive(Clone, PartialEq, Debug)]
+pub enum GamepadEvent {
+    Connected(usize, String),
+    Disconnected(usize),
+    Button(usize, GamepadButton, ElementState),
+    Axis(usize, GamepadAxis, f32),
+}
+
+// A source of gamepad events.
+pub trait GamepadBackend {
+    // Gets every event that happened since the last poll without waiting for new ones.
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+// The state of a single gamepad as of the current frame. Axis values are the raw ones, and the
+// deadzone is applied when they are read.
+pub struct Gamepad {
+    pub id: usize,
+    pub name: String,
+    pub deadzone: f32,
+    buttons: HashSet<GamepadButton>,
+    pressed: HashSet<GamepadButton>,
+    released: HashSet<GamepadButton>,
+    axes: HashMap<GamepadAxis, f32>,
+}
+
+impl Gamepad {
+    // Creates the state of a gamepad with nothing held down.
+    pub fn new(id: usize, name: String) -> Gamepad {
+        Gamepad { id: id, name: name, deadzone: DEFAULT_DEADZONE, buttons: HashSet::new(),
+                pressed: HashSet::new(), released: HashSet::new(), axes: HashMap::new() }
+    }
+
+    // Returns true if a button is held down.
+    pub fn is_button_down(&self, button: GamepadButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    // Returns true if a button was pressed during this frame.
+    pub fn was_button_pressed(&self, button: GamepadButton) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    // Returns true if a button was released during this frame.
+    pub fn was_button_released(&self, button: GamepadButton) -> bool {
+        self.released.contains(&button)
+    }
+
+    // Gets the value of an axis without any deadzone.
+    pub fn get_raw_axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes.get(&axis).cloned().unwrap_or(0.0)
+    }
+
+    // Gets the value of an axis through the deadzone. Stick axes use the radial deadzone of the
+    // whole stick.
+    pub fn get_axis(&self, axis: GamepadAxis) -> f32 {
+        match axis {
+            GamepadAxis::LeftX => self.get_left_stick().0,
+            GamepadAxis::LeftY => self.get_left_stick().1,
+            GamepadAxis::RightX => self.get_right_stick().0,
+            GamepadAxis::RightY => self.get_right_stick().1,
+            _ => apply_deadzone(self.get_raw_axis(axis), self.deadzone),
+        }
+    }
+
+    // Gets the (x, y) position of the left stick through the deadzone.
+    pub fn get_left_stick(&self) -> (f32, f32) {
+        self.get_stick(GamepadAxis::LeftX, GamepadAxis::LeftY)
+    }
+
+    // Gets the (x, y) position of the right stick through the deadzone.
+    pub fn get_right_stick(&self) -> (f32, f32) {
+        self.get_stick(GamepadAxis::RightX, GamepadAxis::RightY)
+    }
+
+    // Updates the state from an event for this gamepad.
+    fn handle_event(&mut self, event: &GamepadEvent) {
+        match *event {
+            GamepadEvent::Button(_, button, ElementState::Pressed) => {
+                if self.buttons.insert(button) { self.pressed.insert(button); }
+            },
+            GamepadEvent::Button(_, button, ElementState::Released) => {
+                if self.buttons.remove(&button) { self.released.insert(button); }
+            },
+            GamepadEvent::Axis(_, axis, value) => {
+                self.axes.insert(axis, value.max(-1.0).min(1.0));
+            },
+            _ => {},
+        }
+    }
+
+    // Gets the position of a stick from its two axes through a radial deadzone.
+    fn get_stick(&self, x_axis: GamepadAxis, y_axis: GamepadAxis) -> (f32, f32) {
+        let (x, y) = (self.get_raw_axis(x_axis), self.get_raw_axis(y_axis));
+        let length = (x * x + y * y).sqrt();
+        if length <= self.deadzone { return (0.0, 0.0); }
+        let scale = apply_deadzone(length.min(1.0), self.deadzone) / length;
+        (x * scale, y * scale)
+    }
+}
+
+// The connected gamepads along with the backend that they are read from. Gamepads get the deadzone
+// when they are connected, and it can be changed for each of them after that.
+pub struct Gamepads {
+    pub deadzone: f32,
+    backend: Option<Box<GamepadBackend>>,
+    pads: Vec<Option<Gamepad>>,
+}
+
+impl Gamepads {
+    // Creates the gamepads for the platform's backend, if there is one.
+    pub fn new() -> Gamepads {
+        let backend = default_backend();
+        Gamepads { deadzone: DEFAULT_DEADZONE, backend: backend, pads: Vec::new() }
+    }
+
+    // Creates the gamepads for a backend.
+    pub fn with_backend(backend: Box<GamepadBackend>) -> Gamepads {
+        Gamepads { deadzone: DEFAULT_DEADZONE, backend: Some(backend), pads: Vec::new() }
+    }
+
+    // Creates the gamepads without a backend, which only change through handle_event().
+    pub fn without_backend() -> Gamepads {
+        Gamepads { deadzone: DEFAULT_DEADZONE, backend: None, pads: Vec::new() }
+    }
+
+    // Starts a new frame, polls the backend, and handles its events. The events are returned so
+    // that they can be passed on.
+    pub fn update(&mut self) -> Vec<GamepadEvent> {
+        self.begin_frame();
+        let events = match self.backend {
+            Some(ref mut backend) => backend.poll(),
+            None => Vec::new(),
+        };
+        for event in &events {
+            self.handle_event(event);
+        }
+        events
+    }
+
+    // Forgets the presses and releases of the last frame. This is done by update(), and is only
+    // needed when events are handled one at a time.
+    pub fn begin_frame(&mut self) {
+        for pad in self.pads.iter_mut().filter_map(|p| p.as_mut()) {
+            pad.pressed.clear();
+            pad.released.clear();
+        }
+    }
+
+    // Updates the state from a single event.
+    pub fn handle_event(&mut self, event: &GamepadEvent) {
+        match *event {
+            GamepadEvent::Connected(id, ref name) => {
+                while self.pads.len() <= id { self.pads.push(None); }
+                let mut pad = Gamepad::new(id, name.clone());
+                pad.deadzone = self.deadzone;
+                self.pads[id] = Some(pad);
+            },
+            GamepadEvent::Disconnected(id) => {
+                if id < self.pads.len() { self.pads[id] = None; }
+            },
+            GamepadEvent::Button(id, _, _) | GamepadEvent::Axis(id, _, _) => {
+                if let Some(&mut Some(ref mut pad)) = self.pads.get_mut(id) {
+                    pad.handle_event(event);
+                }
+            },
+        }
+    }
+
+    // Gets a connected gamepad by its number.
+    pub fn get(&self, id: usize) -> Option<&Gamepad> {
+        self.pads.get(id).and_then(|p| p.as_ref())
+    }
+
+    // Gets a connected gamepad by its number to change it.
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Gamepad> {
+        self.pads.get_mut(id).and_then(|p| p.as_mut())
+    }
+
+    // Gets the connected gamepad with the lowest number.
+    pub fn first(&self) -> Option<&Gamepad> {
+        self.iter().next()
+    }
+
+    // Gets an iterator over the connected gamepads.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item = &'a Gamepad> + 'a> {
+        Box::new(self.pads.iter().filter_map(|p| p.as_ref()))
+    }
+
+    // Gets the number of connected gamepads.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    // Returns true if no gamepads are connected.
+    pub fn is_empty(&self) -> bool {
+        self.first().is_none()
+    }
+}
+
+// Rescales a value so that everything within the deadzone reads 0.0 and the rest of the range
+// starts from there.
+fn apply_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value.abs() <= deadzone || deadzone >= 1.0 { return 0.0; }
+    value.signum() * (value.abs() - deadzone) / (1.0 - deadzone)
+}
+
+// Gets the backend for the platform, if there is one.
+#[cfg(target_os = "linux")]
+fn default_backend() -> Option<Box<GamepadBackend>> {
+    Some(Box::new(linux::JoystickBackend::new()))
+}
+
+// Gets the backend for the platform, if there is one.
+#[cfg(not(target_os = "linux"))]
+fn default_backend() -> Option<Box<GamepadBackend>> {
+    None
+}
+
+// A backend for the Linux joystick API. Each device is read by its own thread, since reads block,
+// and the threads send what they read back over a channel. Devices are looked for every so many
+// polls so that gamepads can be plugged in while the game runs.
+#[cfg(target_os = "linux")]
+pub mod linux {
+    use super::{GamepadAxis, GamepadBackend, GamepadButton, GamepadEvent};
+    use platform::window::ElementState;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    // Number of device slots that are looked at.
+    const MAX_DEVICES: usize = 16;
+
+    // Number of polls between looking for new devices.
+    const SCAN_INTERVAL: usize = 60;
+
+    // Kinds of joystick events. The init flag marks the events that give the starting state.
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    const JS_EVENT_INIT: u8 = 0x80;
+
+    // The largest raw value of an axis.
+    const AXIS_MAX: f32 = 32767.0;
+
+    // The state of a device slot. A slot whose device failed stays lost until the device goes
+    // away, so that a device that can't be read isn't opened over and over.
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    enum Slot {
+        Empty,
+        Open([i16; 2]),                     // The last value of the D-pad axes.
+        Lost,
+    }
+
+    // The backend over every joystick device.
+    pub struct JoystickBackend {
+        slots: Vec<Slot>,
+        sender: Sender<(usize, Option<[u8; 8]>)>,
+        receiver: Receiver<(usize, Option<[u8; 8]>)>,
+        polls: usize,
+    }
+
+    impl JoystickBackend {
+        // Creates the backend, which looks for devices on its first poll.
+        pub fn new() -> JoystickBackend {
+            let (sender, receiver) = channel();
+            JoystickBackend { slots: vec![Slot::Empty; MAX_DEVICES], sender: sender,
+                    receiver: receiver, polls: 0 }
+        }
+
+        // Opens the devices that appeared since the last scan and forgets lost devices that went
+        // away.
+        fn scan(&mut self, events: &mut Vec<GamepadEvent>) {
+            for id in 0..MAX_DEVICES {
+                let path = format!("/dev/input/js{}", id);
+                let exists = Path::new(&path).exists();
+                match self.slots[id] {
+                    Slot::Empty if exists => {
+                        let file = match File::open(&path) {
+                            Ok(file) => file,
+                            Err(_) => { self.slots[id] = Slot::Lost; continue; },
+                        };
+                        let sender = self.sender.clone();
+                        thread::spawn(move || read_device(id, file, sender));
+                        self.slots[id] = Slot::Open([0, 0]);
+                        events.push(GamepadEvent::Connected(id, path));
+                    },
+                    Slot::Lost if !exists => { self.slots[id] = Slot::Empty; },
+                    _ => {},
+                }
+            }
+        }
+
+        // Turns a raw joystick event into gamepad events.
+        fn decode(&mut self, id: usize, data: [u8; 8], events: &mut Vec<GamepadEvent>) {
+            let value = (data[4] as u16 | (data[5] as u16) << 8) as i16;
+            let (kind, number) = (data[6] & !JS_EVENT_INIT, data[7]);
+            if kind == JS_EVENT_BUTTON {
+                let state = if value != 0 { ElementState::Pressed } else { ElementState::Released };
+                events.push(GamepadEvent::Button(id, get_button(number), state));
+            } else if kind == JS_EVENT_AXIS {
+                if number == 6 || number == 7 {
+                    self.decode_dpad(id, number as usize - 6, value, events);
+                    return;
+                }
+                let raw = (value as f32 / AXIS_MAX).max(-1.0).min(1.0);
+                let (axis, value) = match number {
+                    0 => (GamepadAxis::LeftX, raw),
+                    1 => (GamepadAxis::LeftY, -raw),
+                    2 => (GamepadAxis::LeftTrigger, (raw + 1.0) * 0.5),
+                    3 => (GamepadAxis::RightX, raw),
+                    4 => (GamepadAxis::RightY, -raw),
+                    5 => (GamepadAxis::RightTrigger, (raw + 1.0) * 0.5),
+                    n => (GamepadAxis::Other(n), raw),
+                };
+                events.push(GamepadEvent::Axis(id, axis, value));
+            }
+        }
+
+        // Turns a D-pad axis into presses and releases of the D-pad buttons.
+        fn decode_dpad(&mut self, id: usize, index: usize, value: i16,
+                events: &mut Vec<GamepadEvent>) {
+            let mut hat = match self.slots[id] {
+                Slot::Open(hat) => hat,
+                _ => { return; },
+            };
+            let (negative, positive) = if index == 0 {
+                (GamepadButton::DPadLeft, GamepadButton::DPadRight)
+            } else {
+                (GamepadButton::DPadUp, GamepadButton::DPadDown)
+            };
+            let old = hat[index].signum();
+            let new = value.signum();
+            if old != new {
+                let mut push = |button, state| events.push(GamepadEvent::Button(id, button, state));
+                if old < 0 { push(negative, ElementState::Released); }
+                if old > 0 { push(positive, ElementState::Released); }
+                if new < 0 { push(negative, ElementState::Pressed); }
+                if new > 0 { push(positive, ElementState::Pressed); }
+            }
+            hat[index] = value;
+            self.slots[id] = Slot::Open(hat);
+        }
+    }
+
+    impl GamepadBackend for JoystickBackend {
+        fn poll(&mut self) -> Vec<GamepadEvent> {
+            let mut events = Vec::new();
+            if self.polls % SCAN_INTERVAL == 0 {
+                self.scan(&mut events);
+            }
+            self.polls += 1;
+            while let Ok((id, data)) = self.receiver.try_recv() {
+                match data {
+                    Some(data) => self.decode(id, data, &mut events),
+                    None => {
+                        self.slots[id] = Slot::Lost;
+                        events.push(GamepadEvent::Disconnected(id));
+                    },
+                }
+            }
+            events
+        }
+    }
+
+    // Reads events from a device until it fails, and then reports that it was lost.
+    fn read_device(id: usize, mut file: File, sender: Sender<(usize, Option<[u8; 8]>)>) {
+        let mut data = [0; 8];
+        while file.read_exact(&mut data).is_ok() {
+            if sender.send((id, Some(data))).is_err() { return; }
+        }
+        let _ = sender.send((id, None));
+    }
+
+    // Gets the button of a joystick button number in the layout of the xpad driver.
+    fn get_button(number: u8) -> GamepadButton {
+        match number {
+            0 => GamepadButton::South,
+            1 => GamepadButton::East,
+            2 => GamepadButton::West,
+            3 => GamepadButton::North,
+            4 => GamepadButton::LeftBumper,
+            5 => GamepadButton::RightBumper,
+            6 => GamepadButton::Select,
+            7 => GamepadButton::Start,
+            8 => GamepadButton::Mode,
+            9 => GamepadButton::LeftStick,
+            10 => GamepadButton::RightStick,
+            n => GamepadButton::Other(n),
+        }
+    }
+}
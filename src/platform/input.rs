@@ -0,0 +1,195 @@
+// Defines Input, which keeps the state of the keyboard and mouse from the events of a window. Keys
+// and mouse buttons are tracked both as held down and as pressed or released during the current
+// frame, which is what most game code wants to ask about instead of handling events itself. Mouse
+// movement, scrolling, and typed text are accumulated over the frame.
+//
+// Usage of Input:
+// - Create it with new().
+// - Call update(&mut window) at the start of every frame, which polls the window and returns the
+//   events so that they can still be passed on to anything else that handles events.
+// - Ask about the state with is_key_down(), was_key_pressed(), get_mouse_delta(), and so on.
+//
+// Brian Ho
+// brian@brkho.com
+
+use platform::window::{ElementState, Event, MouseButton, MouseScrollDelta, PlatformWindow,
+        VirtualKeyCode};
+use std::collections::HashSet;
+
+// Number of scroll lines a pixel delta from a touchpad counts as.
+const LINES_PER_PIXEL: f32 = 1.0 / 20.0;
+
+// The state of the keyboard and mouse as of the current frame.
+pub struct Input {
+    keys: HashSet<VirtualKeyCode>,
+    pressed_keys: HashSet<VirtualKeyCode>,
+    released_keys: HashSet<VirtualKeyCode>,
+    buttons: HashSet<MouseButton>,
+    pressed_buttons: HashSet<MouseButton>,
+    released_buttons: HashSet<MouseButton>,
+    cursor: Option<(i32, i32)>,
+    mouse_delta: (f32, f32),
+    scroll: f32,
+    text: String,
+    focused: bool,
+    resized: Option<(u32, u32)>,
+    closed: bool,
+}
+
+impl Input {
+    // Creates the state for a focused window with nothing held down.
+    pub fn new() -> Input {
+        Input { keys: HashSet::new(), pressed_keys: HashSet::new(), released_keys: HashSet::new(),
+                buttons: HashSet::new(), pressed_buttons: HashSet::new(),
+                released_buttons: HashSet::new(), cursor: None, mouse_delta: (0.0, 0.0),
+                scroll: 0.0, text: String::new(), focused: true, resized: None, closed: false }
+    }
+
+    // Starts a new frame, polls the window's events, and handles them. The events are returned so
+    // that they can be passed on.
+    pub fn update(&mut self, window: &mut PlatformWindow) -> Vec<Event> {
+        self.begin_frame();
+        let events = window.poll_events();
+        for event in &events {
+            self.handle_event(event);
+        }
+        events
+    }
+
+    // Forgets what happened during the last frame while keeping what is still held down. This is
+    // done by update(), and is only needed when events are handled one at a time.
+    pub fn begin_frame(&mut self) {
+        self.pressed_keys.clear();
+        self.released_keys.clear();
+        self.pressed_buttons.clear();
+        self.released_buttons.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll = 0.0;
+        self.text.clear();
+        self.resized = None;
+    }
+
+    // Updates the state from a single event.
+    pub fn handle_event(&mut self, event: &Event) {
+        match *event {
+            Event::KeyboardInput(state, _, Some(key)) => {
+                if state == ElementState::Pressed {
+                    // Held keys repeat their presses, which only count the first time.
+                    if self.keys.insert(key) { self.pressed_keys.insert(key); }
+                } else if self.keys.remove(&key) {
+                    self.released_keys.insert(key);
+                }
+            },
+            Event::MouseInput(state, button) => {
+                if state == ElementState::Pressed {
+                    if self.buttons.insert(button) { self.pressed_buttons.insert(button); }
+                } else if self.buttons.remove(&button) {
+                    self.released_buttons.insert(button);
+                }
+            },
+            Event::MouseMoved((x, y)) => {
+                if let Some((last_x, last_y)) = self.cursor {
+                    self.mouse_delta.0 += (x - last_x) as f32;
+                    self.mouse_delta.1 += (y - last_y) as f32;
+                }
+                self.cursor = Some((x, y));
+            },
+            Event::MouseWheel(MouseScrollDelta::LineDelta(_, lines)) => { self.scroll += lines; },
+            Event::MouseWheel(MouseScrollDelta::PixelDelta(_, pixels)) => {
+                self.scroll += pixels * LINES_PER_PIXEL;
+            },
+            Event::ReceivedCharacter(c) => {
+                if !c.is_control() { self.text.push(c); }
+            },
+            Event::Focused(focused) => {
+                self.focused = focused;
+                if !focused { self.release_all(); }
+            },
+            Event::Resized(width, height) => { self.resized = Some((width, height)); },
+            Event::Closed => { self.closed = true; },
+            _ => {},
+        }
+    }
+
+    // Returns true if a key is held down.
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool {
+        self.keys.contains(&key)
+    }
+
+    // Returns true if a key was pressed during this frame.
+    pub fn was_key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&key)
+    }
+
+    // Returns true if a key was released during this frame.
+    pub fn was_key_released(&self, key: VirtualKeyCode) -> bool {
+        self.released_keys.contains(&key)
+    }
+
+    // Returns true if a mouse button is held down.
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    // Returns true if a mouse button was pressed during this frame.
+    pub fn was_button_pressed(&self, button: MouseButton) -> bool {
+        self.pressed_buttons.contains(&button)
+    }
+
+    // Returns true if a mouse button was released during this frame.
+    pub fn was_button_released(&self, button: MouseButton) -> bool {
+        self.released_buttons.contains(&button)
+    }
+
+    // Gets a value from -1.0 to 1.0 from a pair of opposing keys.
+    pub fn get_axis(&self, negative: VirtualKeyCode, positive: VirtualKeyCode) -> f32 {
+        let mut value = 0.0;
+        if self.is_key_down(negative) { value -= 1.0; }
+        if self.is_key_down(positive) { value += 1.0; }
+        value
+    }
+
+    // Gets the position of the cursor in pixels from the top left of the window, or None if it
+    // hasn't moved over the window yet.
+    pub fn get_cursor(&self) -> Option<(i32, i32)> {
+        self.cursor
+    }
+
+    // Gets how far the mouse moved in pixels during this frame.
+    pub fn get_mouse_delta(&self) -> (f32, f32) {
+        self.mouse_delta
+    }
+
+    // Gets how many lines the mouse wheel scrolled during this frame, where up is positive.
+    pub fn get_scroll(&self) -> f32 {
+        self.scroll
+    }
+
+    // Gets the text that was typed during this frame.
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    // Returns true if the window has focus.
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    // Gets the new size of the window if it was resized during this frame.
+    pub fn get_resized(&self) -> Option<(u32, u32)> {
+        self.resized
+    }
+
+    // Returns true once the user has asked to close the window.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    // Releases every key and button, which happens when the window loses focus since their
+    // releases won't be seen.
+    fn release_all(&mut self) {
+        self.released_keys.extend(self.keys.drain());
+        self.released_buttons.extend(self.buttons.drain());
+        self.cursor = None;
+    }
+}
@@ -0,0 +1,5 @@
+pub mod actions;
+pub mod game_loop;
+pub mod gamepad;
+pub mod input;
+pub mod window;
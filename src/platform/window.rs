@@ -0,0 +1,472 @@
+// Defines the PlatformWindow trait, which is everything the engine needs from the operating system
+// window that it draws into: its size and title, the events from the keyboard and mouse, capturing
+// the cursor, and the OpenGL context and surface that frames are presented to. A NativeWindow
+// implements it with glutin, and a HeadlessWindow implements it without any window at all so that
+// code driven by window events can run in tests, where the events are queued by hand. An
+// OffscreenWindow sits between the two: it has a real OpenGL context but no window, so scenes can
+// be drawn and read back on machines without a display, such as CI servers.
+//
+// Events are glutin's Events for both implementations, since they are plain data and are what the
+// rest of the engine (such as the camera controllers) already handles.
+//
+// More windows can be opened with create_shared(), whose contexts share textures, buffers, and
+// programs with the window they are created from, so that everything loaded once can be drawn to
+// all of them.
+//
+// Usage of a PlatformWindow:
+// - Create a NativeWindow from a WindowConfig, or a HeadlessWindow for tests. create_window()
+//   creates an OffscreenWindow instead when the config's offscreen flag is set.
+// - Pass it to GameWindow::from_platform(), or drive the context yourself with make_current(),
+//   get_proc_address(), and swap_buffers().
+// - Call poll_events() once per frame, usually through Input::update().
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate glutin;
+
+pub use self::glutin::{ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+use self::glutin::{CursorState, GlRequest, HeadlessRendererBuilder, WindowBuilder};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::os::raw::c_void;
+use std::ptr;
+
+// How the cursor behaves over a window. A captured cursor is hidden and held in the window so that
+// mouse movement can be used for looking around without the cursor leaving it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CursorMode {
+    Normal,
+    Hidden,
+    Captured,
+}
+
+// The settings that a window is created with. The OpenGL version is the latest one if it is None,
+// and samples is the number of samples for multisampling, or 0 for none. An offscreen window has
+// an OpenGL context without a window, so the title, vsync, and visibility are ignored for it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    pub vsync: bool,
+    pub srgb: bool,
+    pub samples: u16,
+    pub depth_bits: u8,
+    pub gl_version: Option<(u8, u8)>,
+    pub visible: bool,
+    pub offscreen: bool,
+}
+
+impl WindowConfig {
+    // Creates the settings for a visible window of a size in pixels with a title, vsync, an sRGB
+    // surface, and a 24-bit depth buffer.
+    pub fn new(width: u32, height: u32, title: &str) -> WindowConfig {
+        WindowConfig { width: width, height: height, title: title.to_string(), vsync: true,
+                srgb: true, samples: 0, depth_bits: 24, gl_version: None, visible: true,
+                offscreen: false }
+    }
+
+    // Creates the settings for an offscreen window of a size in pixels.
+    pub fn new_offscreen(width: u32, height: u32) -> WindowConfig {
+        let mut config = WindowConfig::new(width, height, "");
+        config.vsync = false;
+        config.visible = false;
+        config.offscreen = true;
+        config
+    }
+}
+
+// Creates a NativeWindow, or an OffscreenWindow if the config asks for one. Either way, its
+// OpenGL context is made current.
+pub fn create_window(config: &WindowConfig) -> Result<Box<PlatformWindow>, String> {
+    if config.offscreen {
+        Ok(Box::new(try!(OffscreenWindow::new(config))))
+    } else {
+        Ok(Box::new(try!(NativeWindow::new(config))))
+    }
+}
+
+// An operating system window with an OpenGL surface, or a stand-in for one.
+pub trait PlatformWindow {
+    // Gets the size of the drawable surface in pixels.
+    fn get_size(&self) -> (u32, u32);
+
+    // Sets the size of the drawable surface in pixels.
+    fn set_size(&mut self, width: u32, height: u32);
+
+    // Gets the number of pixels per logical point of the screen that the window is on, such as 2.0
+    // on a high DPI screen. This can change when the window moves to another screen.
+    fn get_scale_factor(&self) -> f32;
+
+    // Sets the title of the window.
+    fn set_title(&mut self, title: &str);
+
+    // Gets every event that happened since the last poll without waiting for new ones.
+    fn poll_events(&mut self) -> Vec<Event>;
+
+    // Sets how the cursor behaves over the window.
+    fn set_cursor_mode(&mut self, mode: CursorMode) -> Result<(), String>;
+
+    // Gets how the cursor behaves over the window.
+    fn get_cursor_mode(&self) -> CursorMode;
+
+    // Makes the window's OpenGL context current on this thread.
+    fn make_current(&self) -> Result<(), String>;
+
+    // Gets the address of an OpenGL function for the window's context, or null if there is none.
+    fn get_proc_address(&self, symbol: &str) -> *const c_void;
+
+    // Presents the frame that was drawn to the surface.
+    fn swap_buffers(&self) -> Result<(), String>;
+
+    // Returns true once the user has asked to close the window.
+    fn is_closed(&self) -> bool;
+
+    // Creates another window whose OpenGL context shares textures, buffers, and programs with this
+    // one. Container objects such as vertex arrays and framebuffers are never shared and must be
+    // created in the context that uses them. This window's context stays current.
+    fn create_shared(&self, config: &WindowConfig) -> Result<Box<PlatformWindow>, String>;
+}
+
+// A window created by glutin. While the cursor is captured, it is moved back to the center of the
+// window after each poll, and MouseMoved events report a position that keeps moving past the
+// edges so that mouse movement is never cut off.
+pub struct NativeWindow {
+    window: glutin::Window,
+    cursor_mode: CursorMode,
+    cursor: (i32, i32),
+    closed: bool,
+}
+
+impl NativeWindow {
+    // Creates a window along with its OpenGL context, which is made current.
+    pub fn new(config: &WindowConfig) -> Result<NativeWindow, String> {
+        let window = try!(NativeWindow::build(config, None));
+        try!(window.make_current());
+        Ok(window)
+    }
+
+    // Creates a window with a context that optionally shares its objects with another window.
+    fn build(config: &WindowConfig, shared: Option<&glutin::Window>)
+            -> Result<NativeWindow, String> {
+        let mut builder = WindowBuilder::new().with_dimensions(config.width, config.height)
+                .with_title(config.title.clone()).with_srgb(Some(config.srgb))
+                .with_depth_buffer(config.depth_bits).with_visibility(config.visible);
+        if config.vsync {
+            builder = builder.with_vsync();
+        }
+        if config.samples > 0 {
+            builder = builder.with_multisampling(config.samples);
+        }
+        if let Some(version) = config.gl_version {
+            builder = builder.with_gl(GlRequest::Specific(glutin::Api::OpenGl, version));
+        }
+        if let Some(window) = shared {
+            builder = builder.with_shared_lists(window);
+        }
+        let window = try!(builder.build()
+                .map_err(|e| format!("Unable to create window: {:?}", e)));
+        Ok(NativeWindow { window: window, cursor_mode: CursorMode::Normal, cursor: (0, 0),
+                closed: false })
+    }
+
+    // Gets the glutin window for anything that isn't covered by PlatformWindow.
+    pub fn get_window(&self) -> &glutin::Window {
+        &self.window
+    }
+
+    // Gets the center of the window in pixels.
+    fn get_center(&self) -> (i32, i32) {
+        let (width, height) = self.get_size();
+        ((width / 2) as i32, (height / 2) as i32)
+    }
+}
+
+impl PlatformWindow for NativeWindow {
+    fn get_size(&self) -> (u32, u32) {
+        self.window.get_inner_size_pixels().unwrap_or((0, 0))
+    }
+
+    fn set_size(&mut self, width: u32, height: u32) {
+        self.window.set_inner_size(width, height);
+    }
+
+    fn get_scale_factor(&self) -> f32 {
+        self.window.hidpi_factor()
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        let center = self.get_center();
+        let mut last = center;
+        let mut events = Vec::new();
+        for event in self.window.poll_events() {
+            match event {
+                Event::MouseMoved(pos) if self.cursor_mode == CursorMode::Captured => {
+                    // Moving the cursor back to the center also sends an event, which is skipped
+                    // since it doesn't move the cursor from where it was last seen.
+                    if pos == last { continue; }
+                    self.cursor = (self.cursor.0 + pos.0 - last.0, self.cursor.1 + pos.1 - last.1);
+                    last = pos;
+                    events.push(Event::MouseMoved(self.cursor));
+                },
+                Event::Closed => {
+                    self.closed = true;
+                    events.push(event);
+                },
+                _ => { events.push(event); },
+            }
+        }
+        if last != center {
+            let _ = self.window.set_cursor_position(center.0, center.1);
+        }
+        events
+    }
+
+    fn set_cursor_mode(&mut self, mode: CursorMode) -> Result<(), String> {
+        let state = match mode {
+            CursorMode::Normal => CursorState::Normal,
+            CursorMode::Hidden => CursorState::Hide,
+            CursorMode::Captured => CursorState::Grab,
+        };
+        try!(self.window.set_cursor_state(state));
+        if mode == CursorMode::Captured && self.cursor_mode != CursorMode::Captured {
+            let center = self.get_center();
+            self.cursor = center;
+            let _ = self.window.set_cursor_position(center.0, center.1);
+        }
+        self.cursor_mode = mode;
+        Ok(())
+    }
+
+    fn get_cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    fn make_current(&self) -> Result<(), String> {
+        unsafe {
+            self.window.make_current().map_err(|e| format!("Unable to make current: {:?}", e))
+        }
+    }
+
+    fn get_proc_address(&self, symbol: &str) -> *const c_void {
+        self.window.get_proc_address(symbol) as *const c_void
+    }
+
+    fn swap_buffers(&self) -> Result<(), String> {
+        self.window.swap_buffers().map_err(|e| format!("Unable to swap buffers: {:?}", e))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn create_shared(&self, config: &WindowConfig) -> Result<Box<PlatformWindow>, String> {
+        let window = try!(NativeWindow::build(config, Some(&self.window)));
+        try!(self.make_current());
+        Ok(Box::new(window))
+    }
+}
+
+// A window that only exists in memory. It has no OpenGL context, so nothing can be drawn through
+// it, but events can be queued with push_event() and are given back by the next poll. Resizing it
+// queues a Resized event like a real window would, and the scale factor can be changed to stand in
+// for moving it to another screen.
+pub struct HeadlessWindow {
+    pub title: String,
+    pub scale_factor: f32,
+    width: u32,
+    height: u32,
+    events: VecDeque<Event>,
+    cursor_mode: CursorMode,
+    frames: Cell<usize>,
+    closed: bool,
+}
+
+impl HeadlessWindow {
+    // Creates a headless window with a size in pixels.
+    pub fn new(width: u32, height: u32) -> HeadlessWindow {
+        HeadlessWindow { title: String::new(), scale_factor: 1.0, width: width, height: height,
+                events: VecDeque::new(), cursor_mode: CursorMode::Normal, frames: Cell::new(0),
+                closed: false }
+    }
+
+    // Queues an event to be given back by the next poll.
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push_back(event);
+    }
+
+    // Gets the number of times that swap_buffers() has been called.
+    pub fn get_frame_count(&self) -> usize {
+        self.frames.get()
+    }
+}
+
+impl PlatformWindow for HeadlessWindow {
+    fn get_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_size(&mut self, width: u32, height: u32) {
+        if (width, height) == (self.width, self.height) { return; }
+        self.width = width;
+        self.height = height;
+        self.events.push_back(Event::Resized(width, height));
+    }
+
+    fn get_scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+    }
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        let events: Vec<Event> = self.events.drain(..).collect();
+        if events.iter().any(|e| match *e { Event::Closed => true, _ => false }) {
+            self.closed = true;
+        }
+        events
+    }
+
+    fn set_cursor_mode(&mut self, mode: CursorMode) -> Result<(), String> {
+        self.cursor_mode = mode;
+        Ok(())
+    }
+
+    fn get_cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    fn make_current(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn get_proc_address(&self, _: &str) -> *const c_void {
+        ptr::null()
+    }
+
+    fn swap_buffers(&self) -> Result<(), String> {
+        self.frames.set(self.frames.get() + 1);
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn create_shared(&self, config: &WindowConfig) -> Result<Box<PlatformWindow>, String> {
+        let mut window = HeadlessWindow::new(config.width, config.height);
+        window.title = config.title.clone();
+        window.scale_factor = self.scale_factor;
+        Ok(Box::new(window))
+    }
+}
+
+// An OpenGL context without a window. The context has no default framebuffer that is worth drawing
+// to, so frames should be drawn to a RenderTarget and read back from it. Events can be queued with
+// push_event() like a HeadlessWindow, and resizing only changes the reported size since any
+// RenderTargets are owned by whoever draws. Offscreen contexts can't share objects with another
+// context, so create_shared() always fails.
+pub struct OffscreenWindow {
+    context: glutin::HeadlessContext,
+    width: u32,
+    height: u32,
+    events: VecDeque<Event>,
+    cursor_mode: CursorMode,
+    frames: Cell<usize>,
+    closed: bool,
+}
+
+impl OffscreenWindow {
+    // Creates an offscreen context, which is made current. Only the size and the OpenGL version of
+    // the config are used.
+    pub fn new(config: &WindowConfig) -> Result<OffscreenWindow, String> {
+        let mut builder = HeadlessRendererBuilder::new(config.width, config.height);
+        if let Some(version) = config.gl_version {
+            builder = builder.with_gl(GlRequest::Specific(glutin::Api::OpenGl, version));
+        }
+        let context = try!(builder.build()
+                .map_err(|e| format!("Unable to create offscreen context: {:?}", e)));
+        let window = OffscreenWindow { context: context, width: config.width,
+                height: config.height, events: VecDeque::new(), cursor_mode: CursorMode::Normal,
+                frames: Cell::new(0), closed: false };
+        try!(window.make_current());
+        Ok(window)
+    }
+
+    // Queues an event to be given back by the next poll.
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push_back(event);
+    }
+
+    // Gets the number of times that swap_buffers() has been called.
+    pub fn get_frame_count(&self) -> usize {
+        self.frames.get()
+    }
+}
+
+impl PlatformWindow for OffscreenWindow {
+    fn get_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn set_size(&mut self, width: u32, height: u32) {
+        if (width, height) == (self.width, self.height) { return; }
+        self.width = width;
+        self.height = height;
+        self.events.push_back(Event::Resized(width, height));
+    }
+
+    fn get_scale_factor(&self) -> f32 {
+        1.0
+    }
+
+    fn set_title(&mut self, _: &str) {}
+
+    fn poll_events(&mut self) -> Vec<Event> {
+        let events: Vec<Event> = self.events.drain(..).collect();
+        if events.iter().any(|e| match *e { Event::Closed => true, _ => false }) {
+            self.closed = true;
+        }
+        events
+    }
+
+    fn set_cursor_mode(&mut self, mode: CursorMode) -> Result<(), String> {
+        self.cursor_mode = mode;
+        Ok(())
+    }
+
+    fn get_cursor_mode(&self) -> CursorMode {
+        self.cursor_mode
+    }
+
+    fn make_current(&self) -> Result<(), String> {
+        unsafe {
+            self.context.make_current().map_err(|e| format!("Unable to make current: {:?}", e))
+        }
+    }
+
+    fn get_proc_address(&self, symbol: &str) -> *const c_void {
+        self.context.get_proc_address(symbol) as *const c_void
+    }
+
+    fn swap_buffers(&self) -> Result<(), String> {
+        self.frames.set(self.frames.get() + 1);
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn create_shared(&self, _: &WindowConfig) -> Result<Box<PlatformWindow>, String> {
+        Err("Offscreen windows can't share their context.".to_string())
+    }
+}
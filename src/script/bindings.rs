@@ -0,0 +1,362 @@
+// Defines the functions that scripts use to work with the engine. Entities are the nodes of a
+// Scene, and scripts can spawn them from scratch or from prefabs, move them around, change the
+// materials of their meshes, and destroy them. Scripts can also emit and subscribe to named
+// events, and schedule functions to run after a delay or at an interval.
+//
+// Transforms are changed on the nodes directly, so the scene must be updated with Scene::update()
+// after the scripts run for the changes to be drawn. Positions and scales are vectors, rotations
+// are set with an axis vector and an angle in degrees, and colors are vectors of red, green, and
+// blue from 0 to 1.
+//
+// Functions for scripts:
+// - spawn_entity(name), spawn_entity(name, parent), spawn_prefab(prefab),
+//   spawn_prefab(prefab, parent), destroy(e)
+// - find(name or path), exists(e), get_name(e), get_parent(e), get_children(e)
+// - get_position(e), set_position(e, v), translate(e, v), get_world_position(e)
+// - get_scale(e), set_scale(e, v or number), set_rotation(e, axis, degrees),
+//   rotate(e, axis, degrees), get_forward(e)
+// - get_color(e), set_color(e, v), set_shininess(e, n), set_metallic(e, n), set_roughness(e, n)
+// - emit(event), emit(event, value), on(event, function), off(event, function)
+// - after(seconds, function), every(seconds, function), cancel(timer), time()
+//
+// Callbacks are named functions of the script, given as Fn("name") or "name". Entities are
+// spawned with spawn_entity() since spawn is a reserved word in Rhai.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate rhai;
+
+use gfx::color::Color;
+use gfx::game_window::GameWindow;
+use gfx::material::Material;
+use gfx::prefab::PrefabManager;
+use gfx::scene::{Node, NodeId, Scene};
+use gfx::scene_io::{ComponentRegistry, MeshCache};
+use gfx::types::*;
+use script::interpreter::{self, Entity, Host};
+use self::rhai::{Dynamic, FLOAT};
+use self::cgmath::{EuclideanVector, Rotation3};
+
+// What a PrefabManager needs to place prefabs, for scripts that spawn them.
+pub struct PrefabSpawner<'a> {
+    pub prefabs: &'a mut PrefabManager,
+    pub window: &'a mut GameWindow,
+    pub registry: &'a ComponentRegistry,
+    pub meshes: &'a mut MeshCache,
+}
+
+// The parts of the game that scripts can change during a frame.
+pub struct ScriptWorld<'a> {
+    pub scene: &'a mut Scene,
+    pub spawner: Option<PrefabSpawner<'a>>,
+}
+
+impl<'a> ScriptWorld<'a> {
+    // Creates a world for scripts that work with a scene but can't spawn prefabs.
+    pub fn new(scene: &'a mut Scene) -> ScriptWorld<'a> {
+        ScriptWorld { scene: scene, spawner: None }
+    }
+
+    // Creates a world for scripts that can also spawn prefabs.
+    pub fn with_prefabs(scene: &'a mut Scene, spawner: PrefabSpawner<'a>) -> ScriptWorld<'a> {
+        ScriptWorld { scene: scene, spawner: Some(spawner) }
+    }
+}
+
+// A function that a script asked to run later. Repeating timers run every interval seconds.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Timer {
+    pub id: usize,
+    pub function: String,
+    pub remaining: f64,
+    pub interval: Option<f64>,
+}
+
+// The event subscriptions, timers, and emitted events of a script. Callbacks are kept by name so
+// that they call the new version of a function after the script is reloaded.
+pub struct Schedule {
+    pub subscriptions: Vec<(String, String)>,
+    pub timers: Vec<Timer>,
+    pub emitted: Vec<(String, Dynamic)>,
+    pub time: f64,
+    next_timer: usize,
+}
+
+impl Schedule {
+    // Creates a Schedule without any subscriptions or timers.
+    pub fn new() -> Schedule {
+        Schedule { subscriptions: Vec::new(), timers: Vec::new(), emitted: Vec::new(), time: 0.0,
+                next_timer: 0 }
+    }
+
+    // Adds a timer and returns its id.
+    pub fn add_timer(&mut self, function: &str, delay: f64, interval: Option<f64>) -> usize {
+        let id = self.next_timer;
+        self.next_timer += 1;
+        self.timers.push(Timer { id: id, function: function.to_string(), remaining: delay,
+                interval: interval });
+        id
+    }
+
+    // Advances the timers by some seconds and returns the functions that are due in the order
+    // that they came due. Repeating timers that are more than one interval behind run once for
+    // each interval.
+    pub fn advance(&mut self, dt: f64) -> Vec<String> {
+        self.time += dt;
+        let mut due: Vec<(f64, String)> = Vec::new();
+        for timer in self.timers.iter_mut() {
+            timer.remaining -= dt;
+            while timer.remaining <= 0.0 {
+                due.push((timer.remaining, timer.function.clone()));
+                match timer.interval {
+                    Some(interval) => timer.remaining += interval,
+                    None => break,
+                }
+            }
+        }
+        self.timers.retain(|t| t.interval.is_some() || t.remaining > 0.0);
+        due.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        due.into_iter().map(|d| d.1).collect()
+    }
+}
+
+// The names of the functions that Bindings provides, which are registered with the interpreter.
+pub const FUNCTIONS: &'static [&'static str] = &["spawn_entity", "spawn_prefab", "destroy", "find",
+        "exists", "get_name", "get_parent", "get_children", "get_position", "get_world_position",
+        "set_position", "translate", "get_scale", "set_scale", "set_rotation", "rotate",
+        "get_forward", "get_color", "set_color", "set_shininess", "set_metallic", "set_roughness",
+        "emit", "on", "off", "after", "every", "cancel", "time"];
+
+// Gets an argument as an entity that exists in a scene.
+fn entity_arg(scene: &Scene, name: &str, args: &[Dynamic], index: usize)
+        -> Result<NodeId, String> {
+    let id = try!(interpreter::entity_arg(name, args, index));
+    match scene.get_node(id) {
+        Some(_) => Ok(id),
+        None => Err(format!("{}() was given entity {}, which was destroyed.", name, id)),
+    }
+}
+
+// Gets an optional parent entity argument.
+fn parent_arg(scene: &Scene, name: &str, args: &[Dynamic], index: usize)
+        -> Result<Option<NodeId>, String> {
+    match args.get(index) {
+        None => Ok(None),
+        Some(value) if value.is_unit() => Ok(None),
+        _ => entity_arg(scene, name, args, index).map(Some),
+    }
+}
+
+// Creates the script value of an entity.
+fn from_entity(id: NodeId) -> Dynamic {
+    Dynamic::from(Entity(id))
+}
+
+// Creates the script value of an optional entity, which is () if there isn't one.
+fn from_option(id: Option<NodeId>) -> Dynamic {
+    id.map_or(Dynamic::UNIT, from_entity)
+}
+
+// Gets a rotation from an axis and an angle in degrees.
+fn axis_angle(name: &str, args: &[Dynamic]) -> Result<Quaternion, String> {
+    let axis = try!(interpreter::vector_arg(name, args, 1));
+    if axis.length2() == 0.0 {
+        return Err(format!("{}() was given a zero axis.", name));
+    }
+    let degrees = try!(interpreter::number_arg(name, args, 2));
+    Ok(Quaternion::from_axis_angle(axis.normalize(), cgmath::rad(degrees.to_radians() as f32)))
+}
+
+// Gets the material of a node's mesh, or an Err if it has no mesh.
+fn material(node: &Node, name: &str) -> Result<Material, String> {
+    match node.mesh {
+        Some(ref mesh) => Ok(mesh.material.unwrap_or(mesh.info.mat)),
+        None => Err(format!("{}() was given entity {}, which has no mesh.", name, node.name)),
+    }
+}
+
+// Changes the material of a node's mesh, giving the instance its own copy of the material.
+fn set_material<F>(node: &mut Node, name: &str, change: F) -> Result<Dynamic, String>
+        where F: FnOnce(&mut Material) {
+    let mut mat = try!(material(node, name));
+    change(&mut mat);
+    node.mesh.as_mut().unwrap().material = Some(mat);
+    Ok(Dynamic::UNIT)
+}
+
+// The Host for a script that gives it a world and its schedule for one call.
+pub struct Bindings<'a, 'b: 'a> {
+    pub world: &'a mut ScriptWorld<'b>,
+    pub schedule: &'a mut Schedule,
+}
+
+impl<'a, 'b> Bindings<'a, 'b> {
+    // Calls an engine function, or returns None if there isn't one by that name.
+    fn call_engine(&mut self, name: &str, args: &[Dynamic]) -> Option<Result<Dynamic, String>> {
+        let scene = &mut *self.world.scene;
+        let spawner = &mut self.world.spawner;
+        let entity = |scene: &Scene, i| entity_arg(scene, name, args, i);
+        let number = |i| interpreter::number_arg(name, args, i);
+        let vector = |i| interpreter::vector_arg(name, args, i);
+        Some(match name {
+            "spawn_entity" => interpreter::str_arg(name, args, 0).and_then(|node| {
+                let parent = try!(parent_arg(scene, name, args, 1));
+                scene.add_node(&node, parent).map(from_entity)
+            }),
+            "spawn_prefab" => {
+                let prefab = match interpreter::str_arg(name, args, 0) {
+                    Ok(prefab) => prefab,
+                    Err(e) => return Some(Err(e)),
+                };
+                let parent = match parent_arg(scene, name, args, 1) {
+                    Ok(parent) => parent,
+                    Err(e) => return Some(Err(e)),
+                };
+                match *spawner {
+                    Some(ref mut spawner) => spawner.prefabs.instantiate(&prefab, scene, parent,
+                            spawner.window, spawner.registry, spawner.meshes).map(|instance| {
+                        from_entity(spawner.prefabs.get_instance(instance).unwrap().root)
+                    }),
+                    None => Err("Prefabs can't be spawned by this script.".to_string()),
+                }
+            },
+            "destroy" => entity(scene, 0).and_then(|id| {
+                if let Some(ref mut spawner) = *spawner {
+                    if let Some(instance) = spawner.prefabs.find_instance(id) {
+                        return spawner.prefabs.remove_instance(instance, scene, spawner.window)
+                                .map(|_| Dynamic::UNIT);
+                    }
+                }
+                scene.remove_node(id).map(|_| Dynamic::UNIT)
+            }),
+            "find" => interpreter::str_arg(name, args, 0).map(|path| {
+                from_option(if path.contains('/') { scene.find_path(&path) } else {
+                    scene.find(&path)
+                })
+            }),
+            "exists" => Ok(Dynamic::from(match interpreter::entity_arg(name, args, 0) {
+                Ok(id) => scene.get_node(id).is_some(),
+                Err(_) => false,
+            })),
+            "get_name" => entity(scene, 0).map(|id| {
+                Dynamic::from(scene.get_node(id).unwrap().name.clone())
+            }),
+            "get_parent" => entity(scene, 0).map(|id| {
+                from_option(scene.get_node(id).unwrap().get_parent())
+            }),
+            "get_children" => entity(scene, 0).map(|id| Dynamic::from_array(
+                    scene.get_node(id).unwrap().get_children().iter().map(|&c| from_entity(c))
+                    .collect())),
+            "get_position" => entity(scene, 0).map(|id| {
+                Dynamic::from(scene.get_node(id).unwrap().transform.pos)
+            }),
+            "get_world_position" => entity(scene, 0).map(|id| {
+                Dynamic::from(scene.get_node(id).unwrap().get_world_position())
+            }),
+            "set_position" | "translate" => entity(scene, 0).and_then(|id| {
+                let v = try!(vector(1));
+                let transform = &mut scene.get_node_mut(id).unwrap().transform;
+                transform.pos = if name == "translate" { transform.pos + v } else { v };
+                Ok(Dynamic::UNIT)
+            }),
+            "get_scale" => entity(scene, 0).map(|id| {
+                Dynamic::from(scene.get_node(id).unwrap().transform.scale)
+            }),
+            "set_scale" => entity(scene, 0).and_then(|id| {
+                let scale = match number(1) {
+                    Ok(n) => Vector3D::new(n as f32, n as f32, n as f32),
+                    Err(_) => try!(vector(1)),
+                };
+                scene.get_node_mut(id).unwrap().transform.scale = scale;
+                Ok(Dynamic::UNIT)
+            }),
+            "set_rotation" | "rotate" => entity(scene, 0).and_then(|id| {
+                let rotation = try!(axis_angle(name, args));
+                let transform = &mut scene.get_node_mut(id).unwrap().transform;
+                transform.rot = if name == "rotate" { rotation * transform.rot } else {
+                    rotation
+                };
+                Ok(Dynamic::UNIT)
+            }),
+            "get_forward" => entity(scene, 0).map(|id| {
+                let rot = scene.get_node(id).unwrap().transform.rot;
+                Dynamic::from(rot * Vector3D::new(0.0, 0.0, -1.0))
+            }),
+            "get_color" => entity(scene, 0).and_then(|id| {
+                let color = try!(material(scene.get_node(id).unwrap(), name)).color;
+                Ok(Dynamic::from(Vector3D::new(color.r, color.g, color.b)))
+            }),
+            "set_color" => entity(scene, 0).and_then(|id| {
+                let v = try!(vector(1));
+                set_material(scene.get_node_mut(id).unwrap(), name, |mat| {
+                    mat.color = Color::new(v.x, v.y, v.z, mat.color.a);
+                })
+            }),
+            "set_shininess" | "set_metallic" | "set_roughness" => entity(scene, 0).and_then(|id| {
+                let n = try!(number(1)) as f32;
+                set_material(scene.get_node_mut(id).unwrap(), name, |mat| match name {
+                    "set_shininess" => mat.shininess = n,
+                    "set_metallic" => mat.metallic = n,
+                    _ => mat.roughness = n,
+                })
+            }),
+            _ => return None,
+        })
+    }
+
+    // Calls an event or timer function, or returns None if there isn't one by that name.
+    fn call_schedule(&mut self, name: &str, args: &[Dynamic])
+            -> Option<Result<Dynamic, String>> {
+        let schedule = &mut *self.schedule;
+        Some(match name {
+            "emit" => interpreter::str_arg(name, args, 0).map(|event| {
+                schedule.emitted.push((event, args.get(1).cloned().unwrap_or(Dynamic::UNIT)));
+                Dynamic::UNIT
+            }),
+            "on" => interpreter::str_arg(name, args, 0).and_then(|event| {
+                let function = try!(interpreter::function_arg(name, args, 1));
+                let subscription = (event, function);
+                if !schedule.subscriptions.contains(&subscription) {
+                    schedule.subscriptions.push(subscription);
+                }
+                Ok(Dynamic::UNIT)
+            }),
+            "off" => interpreter::str_arg(name, args, 0).and_then(|event| {
+                let function = match args.get(1) {
+                    Some(_) => Some(try!(interpreter::function_arg(name, args, 1))),
+                    None => None,
+                };
+                schedule.subscriptions.retain(|s| {
+                    s.0 != event || function.as_ref().map_or(false, |f| *f != s.1)
+                });
+                Ok(Dynamic::UNIT)
+            }),
+            "after" | "every" => interpreter::number_arg(name, args, 0).and_then(|seconds| {
+                let function = try!(interpreter::function_arg(name, args, 1));
+                if name == "every" && seconds <= 0.0 {
+                    return Err("every() needs an interval above 0.".to_string());
+                }
+                let interval = if name == "every" { Some(seconds) } else { None };
+                Ok(Dynamic::from(schedule.add_timer(&function, seconds, interval) as FLOAT))
+            }),
+            "cancel" => interpreter::number_arg(name, args, 0).map(|id| {
+                let count = schedule.timers.len();
+                schedule.timers.retain(|t| t.id as f64 != id);
+                Dynamic::from(schedule.timers.len() < count)
+            }),
+            "time" => Ok(Dynamic::from(schedule.time as FLOAT)),
+            _ => return None,
+        })
+    }
+}
+
+impl<'a, 'b> Host for Bindings<'a, 'b> {
+    fn call(&mut self, name: &str, args: &[Dynamic]) -> Option<Result<Dynamic, String>> {
+        match self.call_engine(name, args) {
+            Some(result) => Some(result),
+            None => self.call_schedule(name, args),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// Defines Scripts, which run gameplay logic written in Rhai files, and a ScriptHost that runs
+// many of them and passes events between them. A script is loaded by running its top level and
+// then calling its on_start() function. Every frame the events posted to it are handed to the
+// functions subscribed to them with on(), its timers run, and on_update(dt) is called. The state
+// that a script keeps between calls goes in the properties of `this`, which every function sees.
+// Each script may have an owner entity, which it sees as this.owner.
+//
+// Scripts that were loaded from files are reloaded when their file changes. Reloading runs the
+// top level of the new version and then calls on_reload() if it exists instead of on_start().
+// `this` is kept, so the new version carries on with the state and entities of the old one.
+// Subscriptions and timers remember their functions by name and carry over to the new version. A
+// version that fails to compile or run is reported and the old one keeps running.
+//
+// Errors in callbacks are logged once until they change, so that a broken on_update() doesn't
+// flood the log, and the script keeps running.
+//
+// Usage of a ScriptHost:
+// - let mut scripts = ScriptHost::new();
+// - let id = try!(scripts.load("scripts/door.rhai", Some(door), &mut ScriptWorld::new(scene)));
+// - Forward engine events with post(), such as from an EventBus subscriber.
+// - scripts.update(dt, &mut ScriptWorld::new(&mut scene)) once per frame, then scene.update().
+// - Read the events that scripts emitted during the frame with get_events().
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::scene::NodeId;
+extern crate rhai;
+
+use script::bindings::{self, Bindings, Schedule, ScriptWorld};
+use script::interpreter::{Entity, Interpreter};
+use self::rhai::{Dynamic, FLOAT};
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::time::SystemTime;
+
+// Handle to a Script in a ScriptHost.
+pub type ScriptId = usize;
+
+// Gets the time that a file was last modified.
+fn get_modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Reads a script file.
+fn read_source(path: &str) -> Result<String, String> {
+    let mut file = try!(File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e)));
+    let mut source = String::new();
+    try!(file.read_to_string(&mut source).map_err(|e| e.to_string()));
+    Ok(source)
+}
+
+// A running script along with its subscriptions, timers, and the events waiting for it.
+pub struct Script {
+    pub interpreter: Interpreter,
+    pub schedule: Schedule,
+    pub owner: Option<NodeId>,
+    path: Option<String>,
+    modified: Option<SystemTime>,
+    events: Vec<(String, Dynamic)>,
+    last_error: Option<String>,
+}
+
+impl Script {
+    // Runs the source of a script and calls its on_start(). Returns an Err if the script can't be
+    // compiled or fails while starting.
+    pub fn from_source(source: &str, owner: Option<NodeId>, world: &mut ScriptWorld)
+            -> Result<Script, String> {
+        let mut interpreter = Interpreter::new(bindings::FUNCTIONS);
+        let owner_value = owner.map_or(Dynamic::UNIT, |o| Dynamic::from(Entity(o)));
+        interpreter.set_property("owner", owner_value);
+        let mut script = Script { interpreter: interpreter, schedule: Schedule::new(), owner: owner,
+                path: None, modified: None, events: Vec::new(), last_error: None };
+        try!(script.run(source, world));
+        try!(script.call_if_defined("on_start", &[], world));
+        Ok(script)
+    }
+
+    // Loads a script from a file, which is watched for changes by update().
+    pub fn load(path: &str, owner: Option<NodeId>, world: &mut ScriptWorld)
+            -> Result<Script, String> {
+        let modified = get_modified(path);
+        let source = try!(read_source(path));
+        let mut script = try!(Script::from_source(&source, owner, world)
+                .map_err(|e| format!("{}: {}", path, e)));
+        script.path = Some(path.to_string());
+        script.modified = modified;
+        Ok(script)
+    }
+
+    // Compiles a version of the script and runs its top level.
+    fn run(&mut self, source: &str, world: &mut ScriptWorld) -> Result<(), String> {
+        let mut bindings = Bindings { world: world, schedule: &mut self.schedule };
+        self.interpreter.load(source, &mut bindings)
+    }
+
+    // Gets the path of the file that the script was loaded from.
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|p| &p[..])
+    }
+
+    // Calls a function of the script with arguments.
+    pub fn call(&mut self, function: &str, args: &[Dynamic], world: &mut ScriptWorld)
+            -> Result<Dynamic, String> {
+        let mut bindings = Bindings { world: world, schedule: &mut self.schedule };
+        self.interpreter.call(function, args.to_vec(), &mut bindings)
+    }
+
+    // Calls a function of the script if it has one.
+    fn call_if_defined(&mut self, function: &str, args: &[Dynamic], world: &mut ScriptWorld)
+            -> Result<(), String> {
+        if self.interpreter.has_function(function) {
+            try!(self.call(function, args, world).map(|_| ()));
+        }
+        Ok(())
+    }
+
+    // Queues an event for the functions subscribed to it, which are called by the next update().
+    pub fn post(&mut self, event: &str, value: Dynamic) {
+        self.events.push((event.to_string(), value));
+    }
+
+    // Takes the events that the script has emitted since this was last called.
+    pub fn take_emitted(&mut self) -> Vec<(String, Dynamic)> {
+        mem::replace(&mut self.schedule.emitted, Vec::new())
+    }
+
+    // Reloads the script's file and calls on_reload(). Returns an Err and keeps the old version
+    // running if the new one can't be loaded.
+    pub fn reload(&mut self, world: &mut ScriptWorld) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+        self.modified = get_modified(&path);
+        let source = try!(read_source(&path));
+        try!(self.run(&source, world));
+        self.last_error = None;
+        self.call_if_defined("on_reload", &[], world)
+    }
+
+    // Logs an error unless it is the same as the last one.
+    fn report(&mut self, error: String) {
+        if self.last_error.as_ref() != Some(&error) {
+            log_error!(Game, "{}: {}", self.path.as_ref().map_or("Script", |p| &p[..]), error);
+            self.last_error = Some(error);
+        }
+    }
+
+    // Runs a frame of the script: reloads it if its file has changed, calls the functions
+    // subscribed to the events posted to it, runs the timers that are due, and calls on_update()
+    // with the frame time in seconds. Errors are logged.
+    pub fn update(&mut self, dt: f64, world: &mut ScriptWorld) {
+        let changed = self.path.as_ref().map_or(false, |p| get_modified(p) != self.modified);
+        if changed {
+            match self.reload(world) {
+                Ok(()) => log_info!(Game, "Reloaded {}.", self.path.as_ref().unwrap()),
+                Err(e) => {
+                    let path = self.path.clone().unwrap();
+                    self.report(format!("Unable to reload, so the old version is kept: {}", e));
+                    // Keep the new time so that a broken file is only reported once per edit.
+                    self.modified = get_modified(&path);
+                },
+            }
+        }
+
+        let events = mem::replace(&mut self.events, Vec::new());
+        for (event, value) in events {
+            let handlers: Vec<String> = self.schedule.subscriptions.iter()
+                    .filter(|s| s.0 == event).map(|s| s.1.clone()).collect();
+            for handler in handlers {
+                if let Err(e) = self.call(&handler, &[value.clone()], world) {
+                    self.report(e);
+                }
+            }
+        }
+        for function in self.schedule.advance(dt) {
+            if let Err(e) = self.call(&function, &[], world) {
+                self.report(e);
+            }
+        }
+        if let Err(e) = self.call_if_defined("on_update", &[Dynamic::from(dt as FLOAT)], world) {
+            self.report(e);
+        }
+    }
+}
+
+// Runs scripts and delivers the events that they emit to every script on the next frame.
+pub struct ScriptHost {
+    scripts: Vec<Option<Script>>,
+    events: Vec<(String, Dynamic)>,
+}
+
+impl ScriptHost {
+    // Creates a ScriptHost without any scripts.
+    pub fn new() -> ScriptHost {
+        ScriptHost { scripts: Vec::new(), events: Vec::new() }
+    }
+
+    // Adds a script that has already been loaded and returns a handle to it.
+    pub fn add(&mut self, script: Script) -> ScriptId {
+        match self.scripts.iter().position(|s| s.is_none()) {
+            Some(i) => { self.scripts[i] = Some(script); i },
+            None => { self.scripts.push(Some(script)); self.scripts.len() - 1 },
+        }
+    }
+
+    // Loads a script from a file and adds it.
+    pub fn load(&mut self, path: &str, owner: Option<NodeId>, world: &mut ScriptWorld)
+            -> Result<ScriptId, String> {
+        let script = try!(Script::load(path, owner, world));
+        Ok(self.add(script))
+    }
+
+    // Removes a script and returns it.
+    pub fn remove(&mut self, id: ScriptId) -> Option<Script> {
+        self.scripts.get_mut(id).and_then(|s| s.take())
+    }
+
+    // Removes every script owned by an entity, such as when the entity is destroyed.
+    pub fn remove_owned(&mut self, owner: NodeId) {
+        for script in self.scripts.iter_mut() {
+            if script.as_ref().map_or(false, |s| s.owner == Some(owner)) {
+                *script = None;
+            }
+        }
+    }
+
+    // Gets a script given its handle.
+    pub fn get(&self, id: ScriptId) -> Option<&Script> {
+        self.scripts.get(id).and_then(|s| s.as_ref())
+    }
+
+    // Gets a mutable script given its handle.
+    pub fn get_mut(&mut self, id: ScriptId) -> Option<&mut Script> {
+        self.scripts.get_mut(id).and_then(|s| s.as_mut())
+    }
+
+    // Posts an event to every script.
+    pub fn post(&mut self, event: &str, value: Dynamic) {
+        for script in self.scripts.iter_mut().filter_map(|s| s.as_mut()) {
+            script.post(event, value.clone());
+        }
+    }
+
+    // Gets the events that the scripts emitted during the last update().
+    pub fn get_events(&self) -> &[(String, Dynamic)] {
+        &self.events
+    }
+
+    // Runs a frame of every script and then posts the events that they emitted to every script
+    // for the next frame.
+    pub fn update(&mut self, dt: f64, world: &mut ScriptWorld) {
+        let mut events = Vec::new();
+        for script in self.scripts.iter_mut().filter_map(|s| s.as_mut()) {
+            script.update(dt, world);
+            events.extend(script.take_emitted());
+        }
+        for &(ref event, ref value) in events.iter() {
+            self.post(event, value.clone());
+        }
+        self.events = events;
+    }
+}
@@ -0,0 +1,332 @@
+// Runs scripts written in Rhai (https://rhai.rs), which is embedded as the scripting language of
+// the engine. An Interpreter holds the compiled functions of one script along with a state object
+// that the functions see as `this`, which is how a script keeps values between calls since Rhai
+// functions can't see the variables of the top level. Constants declared at the top level can be
+// read in functions as global::NAME.
+//
+// Anything a script calls that isn't a Rhai function or one of the script's own is asked of a
+// Host, which is how the engine gives scripts access to the scene. The host's functions are
+// registered by name when the Interpreter is created and take up to MAX_HOST_ARGS arguments of any
+// type, which the host checks itself. Scripts also get vectors, made with vec3(x, y, z), along with
+// entities and random numbers. Every call is limited to a number of operations so that a script
+// with an endless loop returns an error instead of freezing the game.
+//
+// Usage of an Interpreter:
+// - let mut interpreter = Interpreter::new(&["spawn_entity", "destroy", ...]);
+// - try!(interpreter.load(source, &mut host)) to compile the script and run its top level.
+// - try!(interpreter.call("on_update", vec![Dynamic::from(dt)], &mut host)) for each callback.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate rhai;
+
+use gfx::scene::NodeId;
+use gfx::types::*;
+use self::cgmath::EuclideanVector;
+use self::rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FnPtr, Map, Module,
+        NativeCallContext, Position, Scope, AST, FLOAT, INT};
+use std::any::TypeId;
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+use util::random::Random;
+
+// The default number of operations that a call may take.
+pub const DEFAULT_MAX_OPERATIONS: u64 = 1000000;
+
+// The most arguments that a host function can be called with.
+pub const MAX_HOST_ARGS: usize = 4;
+
+// Maximum depth of nested calls before a script is considered to have recursed forever.
+const MAX_CALL_LEVELS: usize = 64;
+
+// An entity of the scene as scripts see it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Entity(pub NodeId);
+
+// Provides the functions that a script can call beyond Rhai's and its own.
+pub trait Host {
+    // Calls a function by name. Returns None if the host doesn't have a function by that name.
+    fn call(&mut self, name: &str, args: &[Dynamic]) -> Option<Result<Dynamic, String>>;
+}
+
+// A Host without any functions, for scripts that only use the built in ones.
+pub struct NoHost;
+
+impl Host for NoHost {
+    fn call(&mut self, _: &str, _: &[Dynamic]) -> Option<Result<Dynamic, String>> {
+        None
+    }
+}
+
+// The host of the call that is running, which the registered host functions forward to. Rhai
+// only takes functions that live forever, so the host is kept as a pointer that is set for the
+// length of a call by HostGuard.
+type HostSlot = Rc<Cell<Option<*mut Host>>>;
+
+// Clears the host slot when a call returns, even by panicking, so that the pointer never outlives
+// the host.
+struct HostGuard<'a> {
+    slot: &'a Cell<Option<*mut Host>>,
+}
+
+impl<'a> HostGuard<'a> {
+    // Sets the host of a slot until the guard is dropped.
+    fn new(slot: &'a Cell<Option<*mut Host>>, host: &mut Host) -> HostGuard<'a> {
+        // The guard is dropped before the host's borrow ends, so the lifetime can be erased.
+        let host: *mut Host = unsafe { mem::transmute(host as *mut Host) };
+        slot.set(Some(host));
+        HostGuard { slot: slot }
+    }
+}
+
+impl<'a> Drop for HostGuard<'a> {
+    fn drop(&mut self) {
+        self.slot.set(None);
+    }
+}
+
+// Creates the error that a native function returns.
+fn runtime_error(message: String, position: Position) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(message.into(), position))
+}
+
+// Gets the name of a value's type for error messages.
+pub fn type_name(value: &Dynamic) -> &'static str {
+    if value.is_unit() { "()" }
+    else if value.is::<bool>() { "bool" }
+    else if value.is::<INT>() || value.is::<FLOAT>() { "number" }
+    else if value.is_string() { "string" }
+    else if value.is::<Vector3D>() { "vector" }
+    else if value.is::<Entity>() { "entity" }
+    else if value.is_array() { "array" }
+    else if value.is_map() { "map" }
+    else if value.is::<FnPtr>() { "function" }
+    else { value.type_name() }
+}
+
+// Gets a value as a number, which may be an integer or a float.
+fn to_number(value: &Dynamic) -> Option<f64> {
+    if let Ok(n) = value.as_float() { return Some(n); }
+    value.as_int().ok().map(|n| n as f64)
+}
+
+// Creates the error for an argument of the wrong type.
+fn arg_error(name: &str, args: &[Dynamic], index: usize, expected: &str) -> String {
+    match args.get(index) {
+        Some(other) => format!("{}() expects {} for argument {} but got {}.", name, expected,
+                index + 1, type_name(other)),
+        None => format!("{}() is missing argument {}.", name, index + 1),
+    }
+}
+
+// Gets an argument as a number.
+pub fn number_arg(name: &str, args: &[Dynamic], index: usize) -> Result<f64, String> {
+    args.get(index).and_then(to_number).ok_or_else(|| arg_error(name, args, index, "a number"))
+}
+
+// Gets an argument as a string.
+pub fn str_arg(name: &str, args: &[Dynamic], index: usize) -> Result<String, String> {
+    match args.get(index) {
+        Some(value) if value.is_string() => Ok(value.clone().into_string().unwrap()),
+        _ => Err(arg_error(name, args, index, "a string")),
+    }
+}
+
+// Gets an argument as a vector.
+pub fn vector_arg(name: &str, args: &[Dynamic], index: usize) -> Result<Vector3D, String> {
+    args.get(index).and_then(|v| v.clone().try_cast::<Vector3D>())
+            .ok_or_else(|| arg_error(name, args, index, "a vector"))
+}
+
+// Gets an argument as an entity, which may have been destroyed.
+pub fn entity_arg(name: &str, args: &[Dynamic], index: usize) -> Result<NodeId, String> {
+    args.get(index).and_then(|v| v.clone().try_cast::<Entity>()).map(|e| e.0)
+            .ok_or_else(|| arg_error(name, args, index, "an entity"))
+}
+
+// Gets an argument as the name of a function, which may be given as a function pointer such as
+// Fn("on_hit") or as a string. Closures can't be used since they are called again by name, which
+// loses what they captured.
+pub fn function_arg(name: &str, args: &[Dynamic], index: usize) -> Result<String, String> {
+    match args.get(index) {
+        Some(value) if value.is_string() => Ok(value.clone().into_string().unwrap()),
+        Some(value) if value.is::<FnPtr>() => {
+            let function = value.clone().cast::<FnPtr>();
+            if function.is_curried() || function.is_anonymous() {
+                return Err(format!("{}() needs a named function rather than a closure.", name));
+            }
+            Ok(function.fn_name().to_string())
+        },
+        _ => Err(arg_error(name, args, index, "a function")),
+    }
+}
+
+// Registers vectors, which are the engine's Vector3D with f32 components.
+fn register_vector(engine: &mut Engine) {
+    engine.register_type_with_name::<Vector3D>("vector");
+    engine.register_raw_fn("vec3", [TypeId::of::<Dynamic>(); 3],
+            |ctx: NativeCallContext, args: &mut [&mut Dynamic]| {
+        let args: Vec<Dynamic> = args.iter().map(|a| (**a).clone()).collect();
+        let component = |i| number_arg("vec3", &args, i)
+                .map_err(|e| runtime_error(e, ctx.call_position()));
+        Ok(Vector3D::new(try!(component(0)) as f32, try!(component(1)) as f32,
+                try!(component(2)) as f32))
+    });
+    engine.register_get_set("x", |v: &mut Vector3D| v.x as FLOAT,
+            |v: &mut Vector3D, x: FLOAT| v.x = x as f32);
+    engine.register_get_set("y", |v: &mut Vector3D| v.y as FLOAT,
+            |v: &mut Vector3D, y: FLOAT| v.y = y as f32);
+    engine.register_get_set("z", |v: &mut Vector3D| v.z as FLOAT,
+            |v: &mut Vector3D, z: FLOAT| v.z = z as f32);
+    engine.register_fn("+", |a: Vector3D, b: Vector3D| a + b);
+    engine.register_fn("-", |a: Vector3D, b: Vector3D| a - b);
+    engine.register_fn("-", |a: Vector3D| a * -1.0);
+    engine.register_fn("*", |a: Vector3D, s: FLOAT| a * s as f32);
+    engine.register_fn("*", |s: FLOAT, a: Vector3D| a * s as f32);
+    engine.register_fn("*", |a: Vector3D, s: INT| a * s as f32);
+    engine.register_fn("*", |s: INT, a: Vector3D| a * s as f32);
+    engine.register_fn("/", |a: Vector3D, s: FLOAT| a / s as f32);
+    engine.register_fn("/", |a: Vector3D, s: INT| a / s as f32);
+    engine.register_fn("==", |a: Vector3D, b: Vector3D| a == b);
+    engine.register_fn("!=", |a: Vector3D, b: Vector3D| a != b);
+    engine.register_fn("to_string", |v: &mut Vector3D| format!("vec3({}, {}, {})", v.x, v.y, v.z));
+    engine.register_fn("to_debug", |v: &mut Vector3D| format!("vec3({}, {}, {})", v.x, v.y, v.z));
+    engine.register_fn("length", |v: Vector3D| v.length() as FLOAT);
+    engine.register_fn("normalize", |v: Vector3D| {
+        if v.length2() == 0.0 { v } else { v.normalize() }
+    });
+    engine.register_fn("dot", |a: Vector3D, b: Vector3D| {
+        (a.x * b.x + a.y * b.y + a.z * b.z) as FLOAT
+    });
+    engine.register_fn("cross", |a: Vector3D, b: Vector3D| {
+        Vector3D::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x)
+    });
+    engine.register_fn("distance", |a: Vector3D, b: Vector3D| (b - a).length() as FLOAT);
+    engine.register_fn("lerp", |a: Vector3D, b: Vector3D, t: FLOAT| a + (b - a) * t as f32);
+}
+
+// Registers entities, which can be compared and printed but are otherwise only handed to the
+// host's functions.
+fn register_entity(engine: &mut Engine) {
+    engine.register_type_with_name::<Entity>("entity");
+    engine.register_fn("==", |a: Entity, b: Entity| a == b);
+    engine.register_fn("!=", |a: Entity, b: Entity| a != b);
+    engine.register_get("id", |e: &mut Entity| e.0 as INT);
+    engine.register_fn("to_string", |e: &mut Entity| format!("entity {}", e.0));
+    engine.register_fn("to_debug", |e: &mut Entity| format!("entity {}", e.0));
+}
+
+// Registers random() for a number from 0 to 1 and random(min, max) for one in a range.
+fn register_random(engine: &mut Engine, random: Rc<RefCell<Random>>) {
+    let unit = random.clone();
+    engine.register_fn("random", move || unit.borrow_mut().next_float() as FLOAT);
+    engine.register_raw_fn("random", [TypeId::of::<Dynamic>(); 2],
+            move |ctx: NativeCallContext, args: &mut [&mut Dynamic]| {
+        let args: Vec<Dynamic> = args.iter().map(|a| (**a).clone()).collect();
+        let bound = |i| number_arg("random", &args, i)
+                .map_err(|e| runtime_error(e, ctx.call_position()));
+        let (low, high) = (try!(bound(0)), try!(bound(1)));
+        Ok(low + (high - low) * random.borrow_mut().next_float() as FLOAT)
+    });
+}
+
+// Registers a host function by name for every number of arguments up to MAX_HOST_ARGS.
+fn register_host_function(engine: &mut Engine, name: &str, host: &HostSlot) {
+    for arity in 0..(MAX_HOST_ARGS + 1) {
+        let slot = host.clone();
+        let function = name.to_string();
+        engine.register_raw_fn(name, vec![TypeId::of::<Dynamic>(); arity],
+                move |ctx: NativeCallContext, args: &mut [&mut Dynamic]| {
+            let args: Vec<Dynamic> = args.iter().map(|a| (**a).clone()).collect();
+            let host = match slot.get() {
+                Some(host) => host,
+                None => return Err(runtime_error(format!("{}() can only be called by a script \
+                        that the engine is running.", function), ctx.call_position())),
+            };
+            // The slot is only set while the host is borrowed by a call.
+            match unsafe { (*host).call(&function, &args) } {
+                Some(result) => result.map_err(|e| runtime_error(e, ctx.call_position())),
+                None => Err(runtime_error(format!("{}() isn't available to this script.",
+                        function), ctx.call_position())),
+            }
+        });
+    }
+}
+
+// Runs the functions of a Rhai script with a Host.
+pub struct Interpreter {
+    pub engine: Engine,
+    pub state: Dynamic,
+    pub random: Rc<RefCell<Random>>,
+    scope: Scope<'static>,
+    ast: AST,
+    host: HostSlot,
+}
+
+impl Interpreter {
+    // Creates an Interpreter without a script that can call host functions with the given names.
+    pub fn new(host_functions: &[&str]) -> Interpreter {
+        let mut engine = Engine::new();
+        engine.set_max_operations(DEFAULT_MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.on_print(|s| log_info!(Game, "{}", s));
+        engine.on_debug(|s, _, position| log_debug!(Game, "{} at {}", s, position));
+        let random = Rc::new(RefCell::new(Random::new(0x5C41F7)));
+        let host: HostSlot = Rc::new(Cell::new(None));
+        register_vector(&mut engine);
+        register_entity(&mut engine);
+        register_random(&mut engine, random.clone());
+        for name in host_functions {
+            register_host_function(&mut engine, name, &host);
+        }
+        Interpreter { engine: engine, state: Dynamic::from_map(Map::new()), random: random,
+                scope: Scope::new(), ast: AST::empty(), host: host }
+    }
+
+    // Compiles a script and runs its top level, replacing the functions of the script that was
+    // loaded before but keeping the state. Returns an Err and keeps the old script if the new one
+    // doesn't compile or its top level fails.
+    pub fn load(&mut self, source: &str, host: &mut Host) -> Result<(), String> {
+        let ast = try!(self.engine.compile(source).map_err(|e| format!("Syntax error: {}", e)));
+        let mut scope = Scope::new();
+        {
+            let _guard = HostGuard::new(&self.host, host);
+            try!(self.engine.run_ast_with_scope(&mut scope, &ast)
+                    .map_err(|e| format!("Script error in the top level: {}", e)));
+        }
+        // Functions are called without running the top level again, so its constants are kept in a
+        // module for global::NAME to find.
+        let mut constants = Module::new();
+        for (name, constant, value) in scope.iter() {
+            if constant { constants.set_var(name, value); }
+        }
+        self.engine.register_static_module("global", constants.into());
+        self.ast = ast;
+        self.scope = scope;
+        Ok(())
+    }
+
+    // Sets a property of the state that functions see as `this`.
+    pub fn set_property(&mut self, name: &str, value: Dynamic) {
+        if let Some(mut map) = self.state.write_lock::<Map>() {
+            map.insert(name.into(), value);
+        }
+    }
+
+    // Returns true if the script has a function.
+    pub fn has_function(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    // Calls a function of the script with arguments and returns what it returns.
+    pub fn call(&mut self, name: &str, args: Vec<Dynamic>, host: &mut Host)
+            -> Result<Dynamic, String> {
+        let _guard = HostGuard::new(&self.host, host);
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.state);
+        self.engine.call_fn_with_options(options, &mut self.scope, &self.ast, name, args)
+                .map_err(|e| format!("Script error in {}(): {}", name, e))
+    }
+}
@@ -0,0 +1,328 @@
+// Defines allocators for data that is thrown away and rebuilt every frame, such as culling lists,
+// sort orders, and debug labels, so that it doesn't go through the global allocator each time.
+//
+// A FrameArena hands out memory by bumping an offset through a large chunk, and reset() takes all
+// of it back at once at the end of the frame. Only Copy types can be allocated since nothing in
+// the arena is ever dropped. When a chunk runs out a bigger one is added, and reset() replaces
+// the chunks with a single one that fits everything the frame used, so after the first few
+// frames a frame doesn't allocate at all. An ArenaVec is a growable list in a FrameArena for when
+// the number of items isn't known ahead of time.
+//
+// Allocations are handed out as an ArenaBox or an ArenaSlice, which own their piece of the arena
+// and deref to the value or slice in it. Each handle is the only way to reach its memory, so it can
+// be changed through the handle while the arena itself is only borrowed immutably.
+//
+// A Pool keeps objects such as Vecs and Strings once they are given back and hands them out again
+// emptied but with their memory intact, for data that isn't Copy or that outlives a frame.
+//
+// Usage of a FrameArena:
+// - Create it with new() or with_capacity() and keep it with the main loop.
+// - Allocate with alloc(), alloc_slice(), alloc_copy(), or new_vec() during the frame, and pass
+//   it to the _in() variants of the systems that take one.
+// - Call reset() once the frame is done, which needs every allocation to be gone.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cell::{Cell, UnsafeCell};
+use std::cmp;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::slice;
+
+// The size in bytes of the first chunk of an arena that is created without a capacity.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+// The capacity that an ArenaVec starts with once something is pushed to it.
+const MIN_VEC_CAPACITY: usize = 8;
+
+// The number of objects that a Pool keeps by default.
+const DEFAULT_MAX_FREE: usize = 64;
+
+// A bump allocator that is emptied all at once.
+pub struct FrameArena {
+    chunks: UnsafeCell<Vec<Vec<u8>>>,
+    used: Cell<usize>,                  // Bytes used of the last chunk.
+    allocated: Cell<usize>,             // Bytes handed out since the last reset, with padding.
+    peak: usize,
+}
+
+impl FrameArena {
+    // Creates an empty arena that allocates its first chunk when it is first used.
+    pub fn new() -> FrameArena {
+        FrameArena::with_capacity(0)
+    }
+
+    // Creates an arena with a chunk of some number of bytes.
+    pub fn with_capacity(bytes: usize) -> FrameArena {
+        let chunks = if bytes > 0 { vec![vec![0; bytes]] } else { Vec::new() };
+        FrameArena { chunks: UnsafeCell::new(chunks), used: Cell::new(0),
+                allocated: Cell::new(0), peak: 0 }
+    }
+
+    // Allocates a value.
+    pub fn alloc<'a, T: Copy>(&'a self, value: T) -> ArenaBox<'a, T> {
+        let data = self.alloc_array::<T>(1);
+        unsafe { ptr::write(data, value); }
+        ArenaBox { data: data, marker: PhantomData }
+    }
+
+    // Allocates a slice of some length with every item set to a value.
+    pub fn alloc_slice<'a, T: Copy>(&'a self, len: usize, value: T) -> ArenaSlice<'a, T> {
+        let data = self.alloc_array::<T>(len);
+        for i in 0..len {
+            unsafe { ptr::write(data.offset(i as isize), value); }
+        }
+        ArenaSlice { data: data, len: len, marker: PhantomData }
+    }
+
+    // Allocates a copy of a slice.
+    pub fn alloc_copy<'a, T: Copy>(&'a self, values: &[T]) -> ArenaSlice<'a, T> {
+        let data = self.alloc_array::<T>(values.len());
+        unsafe { ptr::copy_nonoverlapping(values.as_ptr(), data, values.len()); }
+        ArenaSlice { data: data, len: values.len(), marker: PhantomData }
+    }
+
+    // Creates an empty ArenaVec that allocates from the arena.
+    pub fn new_vec<'a, T: Copy>(&'a self) -> ArenaVec<'a, T> {
+        let data = ArenaSlice { data: self.alloc_array::<T>(0), len: 0, marker: PhantomData };
+        ArenaVec { arena: self, data: data, len: 0 }
+    }
+
+    // Takes back everything allocated since the last reset. If the frame needed more than one
+    // chunk, they are replaced with a single chunk large enough for all of it.
+    pub fn reset(&mut self) {
+        let chunks = unsafe { &mut *self.chunks.get() };
+        if chunks.len() > 1 {
+            let size = chunks.iter().fold(0, |size, chunk| size + chunk.len());
+            chunks.clear();
+            chunks.push(vec![0; size]);
+        }
+        self.peak = cmp::max(self.peak, self.allocated.get());
+        self.used.set(0);
+        self.allocated.set(0);
+    }
+
+    // Gets the number of bytes allocated since the last reset, including alignment padding.
+    pub fn get_used(&self) -> usize {
+        self.allocated.get()
+    }
+
+    // Gets the most bytes that were allocated between two resets.
+    pub fn get_peak(&self) -> usize {
+        cmp::max(self.peak, self.allocated.get())
+    }
+
+    // Gets the number of bytes held by the arena's chunks.
+    pub fn get_capacity(&self) -> usize {
+        let chunks = unsafe { &*self.chunks.get() };
+        chunks.iter().fold(0, |size, chunk| size + chunk.len())
+    }
+
+    // Allocates room for some number of values without setting them. The pointer is never null and
+    // is aligned for T even when nothing is allocated.
+    fn alloc_array<T>(&self, len: usize) -> *mut T {
+        let size = mem::size_of::<T>().checked_mul(len).expect("Arena allocation is too large.");
+        self.alloc_bytes(size, mem::align_of::<T>()) as *mut T
+    }
+
+    // Allocates some number of bytes at an alignment. Chunks are never freed or moved before a
+    // reset, so earlier allocations stay valid when a new chunk is added.
+    fn alloc_bytes(&self, size: usize, align: usize) -> *mut u8 {
+        if size == 0 { return align as *mut u8; }
+        let chunks = unsafe { &mut *self.chunks.get() };
+        if let Some(chunk) = chunks.last_mut() {
+            let base = chunk.as_mut_ptr() as usize;
+            let start = (base + self.used.get() + align - 1) / align * align - base;
+            if start + size <= chunk.len() {
+                self.allocated.set(self.allocated.get() + start - self.used.get() + size);
+                self.used.set(start + size);
+                return (base + start) as *mut u8;
+            }
+        }
+        let last = chunks.last().map_or(DEFAULT_CHUNK_SIZE / 2, |chunk| chunk.len());
+        let mut chunk = vec![0; cmp::max(last * 2, size + align)];
+        let base = chunk.as_mut_ptr() as usize;
+        let start = (base + align - 1) / align * align - base;
+        chunks.push(chunk);
+        self.allocated.set(self.allocated.get() + start + size);
+        self.used.set(start + size);
+        (base + start) as *mut u8
+    }
+}
+
+// A value allocated in a FrameArena. It is the only handle to its memory, which stays allocated
+// until the arena is reset.
+pub struct ArenaBox<'a, T: 'a + Copy> {
+    data: *mut T,
+    marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Copy> Deref for ArenaBox<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: Copy> DerefMut for ArenaBox<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+// A slice allocated in a FrameArena. Like an ArenaBox it is the only handle to its memory.
+pub struct ArenaSlice<'a, T: 'a + Copy> {
+    data: *mut T,
+    len: usize,
+    marker: PhantomData<&'a mut [T]>,
+}
+
+impl<'a, T: Copy> Deref for ArenaSlice<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl<'a, T: Copy> DerefMut for ArenaSlice<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.len) }
+    }
+}
+
+// A list in a FrameArena that grows by moving to a slice twice as large. The old slice stays
+// allocated until the arena is reset.
+pub struct ArenaVec<'a, T: 'a + Copy> {
+    arena: &'a FrameArena,
+    data: ArenaSlice<'a, T>,
+    len: usize,
+}
+
+impl<'a, T: Copy> ArenaVec<'a, T> {
+    // Adds an item to the end.
+    pub fn push(&mut self, value: T) {
+        if self.len == self.data.len() {
+            let capacity = cmp::max(self.data.len() * 2, MIN_VEC_CAPACITY);
+            let mut data = self.arena.alloc_slice(capacity, value);
+            data[..self.len].copy_from_slice(&self.data[..self.len]);
+            self.data = data;
+        }
+        self.data[self.len] = value;
+        self.len += 1;
+    }
+
+    // Adds every item of a slice to the end.
+    pub fn extend_from_slice(&mut self, values: &[T]) {
+        for value in values {
+            self.push(*value);
+        }
+    }
+
+    // Removes and returns the last item.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 { return None; }
+        self.len -= 1;
+        Some(self.data[self.len])
+    }
+
+    // Removes every item while keeping the slice.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    // Gets the number of items that fit before the list has to move.
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    // Turns the list into a slice that lives as long as the arena's allocations.
+    pub fn into_slice(self) -> ArenaSlice<'a, T> {
+        ArenaSlice { data: self.data.data, len: self.len, marker: PhantomData }
+    }
+}
+
+impl<'a, T: Copy> Deref for ArenaVec<'a, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data[..self.len]
+    }
+}
+
+impl<'a, T: Copy> DerefMut for ArenaVec<'a, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.data[..self.len]
+    }
+}
+
+// An object that can be emptied to be used again without giving up its memory.
+pub trait Recycle {
+    fn recycle(&mut self);
+}
+
+impl<T> Recycle for Vec<T> {
+    fn recycle(&mut self) {
+        self.clear();
+    }
+}
+
+impl Recycle for String {
+    fn recycle(&mut self) {
+        self.clear();
+    }
+}
+
+// Keeps objects that have been given back to hand them out again instead of creating new ones.
+// At most max_free objects are kept, and the rest are dropped when they are given back.
+pub struct Pool<T: Recycle + Default> {
+    pub max_free: usize,
+    free: Vec<T>,
+    created: usize,
+}
+
+impl<T: Recycle + Default> Pool<T> {
+    // Creates an empty pool that keeps up to a default number of objects.
+    pub fn new() -> Pool<T> {
+        Pool { max_free: DEFAULT_MAX_FREE, free: Vec::new(), created: 0 }
+    }
+
+    // Takes an empty object from the pool, or creates one if the pool is out.
+    pub fn take(&mut self) -> T {
+        match self.free.pop() {
+            Some(object) => object,
+            None => {
+                self.created += 1;
+                T::default()
+            },
+        }
+    }
+
+    // Gives an object back to the pool, which empties it.
+    pub fn give(&mut self, mut object: T) {
+        if self.free.len() < self.max_free {
+            object.recycle();
+            self.free.push(object);
+        }
+    }
+
+    // Gets the number of objects waiting in the pool.
+    pub fn get_free_count(&self) -> usize {
+        self.free.len()
+    }
+
+    // Gets the number of objects the pool has had to create, which stops growing once the pool
+    // holds enough objects.
+    pub fn get_created_count(&self) -> usize {
+        self.created
+    }
+
+    // Drops every object waiting in the pool.
+    pub fn clear(&mut self) {
+        self.free.clear();
+    }
+}
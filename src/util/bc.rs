@@ -0,0 +1,183 @@
+// Utility module that compresses images to the BC1, BC3, BC4, and BC5 block compression formats so
+// that they can be stored in DDS and KTX2 files and uploaded to the GPU as-is. Each 4x4 block of
+// color is fit with a line through the block's colors along their principal axis, and each block
+// of a single channel is fit with the channel's range. Blocks that hang off the edge of an image
+// repeat the image's edge texels. This favors speed over the quality of an exhaustive search but
+// is good enough for most textures.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use util::common;
+
+// The number of power iterations used to find the principal axis of a block's colors.
+const POWER_ITERATIONS: usize = 8;
+
+// Packs a color in 0.0-255.0 per channel to RGB 5:6:5.
+fn to_565(color: [f32; 3]) -> u16 {
+    let quantize = |v: f32, max: f32| (v / 255.0 * max + 0.5).max(0.0).min(max) as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+// Unpacks an RGB 5:6:5 color to 0.0-255.0 per channel.
+fn from_565(color: u16) -> [f32; 3] {
+    let r = (color >> 11) & 0x1F;
+    let g = (color >> 5) & 0x3F;
+    let b = color & 0x1F;
+    [(r << 3 | r >> 2) as f32, (g << 2 | g >> 4) as f32, (b << 3 | b >> 2) as f32]
+}
+
+// Gets the squared distance between two colors.
+fn distance2(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).fold(0.0, |sum, i| sum + (a[i] - b[i]) * (a[i] - b[i]))
+}
+
+// Gets the texels of the block at a block column and row, repeating the edge texels of the image.
+fn get_block<'a>(image: &'a common::Image, bx: u32, by: u32) -> [&'a common::Pixel; 16] {
+    let mut block = [&image.data[0]; 16];
+    for y in 0..4 {
+        for x in 0..4 {
+            let ix = cmp::min(bx * 4 + x, image.width - 1);
+            let iy = cmp::min(by * 4 + y, image.height - 1);
+            block[(y * 4 + x) as usize] = &image.data[(iy * image.width + ix) as usize];
+        }
+    }
+    block
+}
+
+// Compresses the colors of a block to an 8 byte BC1 color block in four color mode.
+fn encode_color_block(colors: &[[f32; 3]; 16], out: &mut Vec<u8>) {
+    let mut mean = [0.0f32; 3];
+    for color in colors.iter() {
+        for c in 0..3 {
+            mean[c] += color[c] / 16.0;
+        }
+    }
+    let mut covariance = [[0.0f32; 3]; 3];
+    for color in colors.iter() {
+        let d = [color[0] - mean[0], color[1] - mean[1], color[2] - mean[2]];
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[i][j] += d[i] * d[j];
+            }
+        }
+    }
+    let mut axis = [1.0f32, 1.0, 1.0];
+    for _ in 0..POWER_ITERATIONS {
+        let next = [0, 1, 2].iter().map(|&i| {
+            covariance[i][0] * axis[0] + covariance[i][1] * axis[1] + covariance[i][2] * axis[2]
+        }).collect::<Vec<f32>>();
+        let length = next.iter().fold(0.0f32, |sum, v| sum.max(v.abs()));
+        if length == 0.0 { break; }
+        axis = [next[0] / length, next[1] / length, next[2] / length];
+    }
+
+    // Use the colors furthest along the axis in each direction as the endpoints.
+    let project = |color: &[f32; 3]| {
+        (0..3).fold(0.0, |sum, i| sum + (color[i] - mean[i]) * axis[i])
+    };
+    let (mut low, mut high) = (colors[0], colors[0]);
+    let (mut low_t, mut high_t) = (project(&colors[0]), project(&colors[0]));
+    for color in colors.iter() {
+        let t = project(color);
+        if t < low_t { low_t = t; low = *color; }
+        if t > high_t { high_t = t; high = *color; }
+    }
+    let (mut color0, mut color1) = (to_565(high), to_565(low));
+    if color0 < color1 {
+        let swap = color0;
+        color0 = color1;
+        color1 = swap;
+    }
+
+    // Pick the closest of the four palette colors for every texel.
+    let mut indices = 0u32;
+    if color0 != color1 {
+        let (c0, c1) = (from_565(color0), from_565(color1));
+        let mut palette = [c0, c1, [0.0; 3], [0.0; 3]];
+        for c in 0..3 {
+            palette[2][c] = (2.0 * c0[c] + c1[c]) / 3.0;
+            palette[3][c] = (c0[c] + 2.0 * c1[c]) / 3.0;
+        }
+        for (i, color) in colors.iter().enumerate() {
+            let mut best = 0;
+            for p in 1..4 {
+                if distance2(*color, palette[p]) < distance2(*color, palette[best]) {
+                    best = p;
+                }
+            }
+            indices |= (best as u32) << (i * 2);
+        }
+    }
+    out.extend_from_slice(&[color0 as u8, (color0 >> 8) as u8, color1 as u8, (color1 >> 8) as u8,
+            indices as u8, (indices >> 8) as u8, (indices >> 16) as u8, (indices >> 24) as u8]);
+}
+
+// Compresses the values of a single channel of a block to an 8 byte BC4 block using the eight
+// value mode.
+fn encode_channel_block(values: &[u8; 16], out: &mut Vec<u8>) {
+    let high = values.iter().fold(0, |m, &v| cmp::max(m, v));
+    let low = values.iter().fold(255, |m, &v| cmp::min(m, v));
+    let mut bits = 0u64;
+    if high != low {
+        let mut palette = [high as u32, low as u32, 0, 0, 0, 0, 0, 0];
+        for k in 1..7 {
+            palette[k + 1] = ((7 - k as u32) * high as u32 + k as u32 * low as u32 + 3) / 7;
+        }
+        for (i, &value) in values.iter().enumerate() {
+            let mut best = 0;
+            for p in 1..8 {
+                let error = (palette[p] as i32 - value as i32).abs();
+                if error < (palette[best] as i32 - value as i32).abs() {
+                    best = p;
+                }
+            }
+            bits |= (best as u64) << (i * 3);
+        }
+    }
+    out.push(high);
+    out.push(low);
+    for i in 0..6 {
+        out.push((bits >> (i * 8)) as u8);
+    }
+}
+
+// Compresses an image to one of the BC1, BC3, BC4, or BC5 formats and returns the blocks. BC1
+// drops the alpha channel, BC4 keeps only the red channel, and BC5 keeps only the red and green
+// channels.
+pub fn encode_bc(image: &common::Image, format: common::CompressedFormat)
+        -> Result<Vec<u8>, String> {
+    if image.width == 0 || image.height == 0 ||
+            image.data.len() as u32 != image.width * image.height {
+        return Err("Image to compress has an incorrect size.".to_string());
+    }
+    let mut out = Vec::with_capacity(format.level_size(image.width, image.height));
+    for by in 0..((image.height + 3) / 4) {
+        for bx in 0..((image.width + 3) / 4) {
+            let block = get_block(image, bx, by);
+            let mut colors = [[0.0f32; 3]; 16];
+            let (mut reds, mut greens, mut alphas) = ([0u8; 16], [0u8; 16], [0u8; 16]);
+            for (i, pixel) in block.iter().enumerate() {
+                colors[i] = [pixel.red as f32, pixel.green as f32, pixel.blue as f32];
+                reds[i] = pixel.red;
+                greens[i] = pixel.green;
+                alphas[i] = pixel.alpha;
+            }
+            match format {
+                common::CompressedFormat::BC1 => encode_color_block(&colors, &mut out),
+                common::CompressedFormat::BC3 => {
+                    encode_channel_block(&alphas, &mut out);
+                    encode_color_block(&colors, &mut out);
+                },
+                common::CompressedFormat::BC4 => encode_channel_block(&reds, &mut out),
+                common::CompressedFormat::BC5 => {
+                    encode_channel_block(&reds, &mut out);
+                    encode_channel_block(&greens, &mut out);
+                },
+                _ => return Err(format!("Compressing to {:?} is not supported.", format)),
+            }
+        }
+    }
+    Ok(out)
+}
@@ -1,13 +1,14 @@
 // Utility module that allows for decoding of a BMP given a path to the file. This is only
 // implemented for a very strict subset of possible BMP formats (BITMAPINFOHEADER) without
-// compression. This is the format output by GIMP when exporting as BMP.
+// compression. This is the format output by GIMP when exporting as BMP. Images can also be
+// encoded to uncompressed 24-bit BMPs, which is fast enough to do every frame.
 //
 // Brian Ho
 // brian@brkho.com
 
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::mem;
 use util::common;
 
@@ -145,3 +146,45 @@ pub fn decode_bmp(fpath: &str) -> Result<DecodedBMP, String> {
     Ok(DecodedBMP { image: image })
 }
 
+
+// Appends a u32 to the data vector in little endian order.
+fn write_dword(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8,
+            (value >> 24) as u8]);
+}
+
+// Encodes an image to a 24-bit BMP file at a path with a BITMAPINFOHEADER. The alpha channel is
+// dropped. Rows are written from the bottom up and padded to 4 bytes as BMP requires.
+pub fn encode_bmp(fpath: &str, image: &common::Image) -> Result<(), String> {
+    if image.width == 0 || image.height == 0 ||
+            image.data.len() as u32 != image.width * image.height {
+        return Err("Image to encode has an incorrect size.".to_string());
+    }
+    let pad_bytes = (image.width % 4) as usize;
+    let row_size = image.width as usize * 3 + pad_bytes;
+    let array_size = (row_size * image.height as usize) as u32;
+    let mut data = Vec::with_capacity(54 + array_size as usize);
+    data.extend_from_slice(b"BM");
+    write_dword(&mut data, 54 + array_size);
+    write_dword(&mut data, 0);
+    write_dword(&mut data, 54);
+    write_dword(&mut data, 40);
+    write_dword(&mut data, image.width);
+    write_dword(&mut data, image.height);
+    data.extend_from_slice(&[1, 0, 24, 0]);
+    // No compression, the size of the pixel array, 72 DPI, and no palette.
+    for &value in [0, array_size, 2835, 2835, 0, 0].iter() {
+        write_dword(&mut data, value);
+    }
+    for row in image.data.chunks(image.width as usize).rev() {
+        for pixel in row {
+            data.extend_from_slice(&[pixel.blue, pixel.green, pixel.red]);
+        }
+        for _ in 0..pad_bytes {
+            data.push(0);
+        }
+    }
+
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
@@ -9,6 +9,7 @@ extern crate gl;
 
 use self::cgmath::*;
 use self::gl::types::*;
+use std::cmp;
 
 // Defines what is in a vertex.
 pub struct Vertex {
@@ -58,4 +59,89 @@ impl Image {
     pub fn get_rgba_vec(&self) -> Vec<u8> {
         self.get_vec_helper(true)
     }
-}
\ No newline at end of file
+}
+
+// Defines what is in a high dynamic range image. Each pixel holds linear RGB values which can
+// exceed 1.0.
+pub struct HDRImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<Vector3<GLfloat>>,
+}
+
+// The block compression formats that can be uploaded to the GPU without decoding. Every format
+// stores 4x4 blocks of texels. BC1 and BC4 use 8 bytes per block while the rest use 16.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CompressedFormat {
+    BC1,
+    BC1Alpha,
+    BC2,
+    BC3,
+    BC4,
+    BC5,
+    BC6HUnsigned,
+    BC6HSigned,
+    BC7,
+}
+
+impl CompressedFormat {
+    // Gets the number of bytes in a single 4x4 block.
+    pub fn block_size(&self) -> usize {
+        match *self {
+            CompressedFormat::BC1 | CompressedFormat::BC1Alpha | CompressedFormat::BC4 => 8,
+            _ => 16,
+        }
+    }
+
+    // Gets the number of bytes needed to store a single mip level of the given size. Sizes too
+    // large to address saturate so that they can be compared against the size of a file.
+    pub fn level_size(&self, width: u32, height: u32) -> usize {
+        let blocks_x = cmp::max((width as usize + 3) / 4, 1);
+        let blocks_y = cmp::max((height as usize + 3) / 4, 1);
+        blocks_x.saturating_mul(blocks_y).saturating_mul(self.block_size())
+    }
+}
+
+// Defines what is in a block compressed image. The levels hold the raw blocks of every mip level
+// starting with the full size image. The sRGB flag is set when the color data is encoded in sRGB
+// space.
+pub struct CompressedImage {
+    pub width: u32,
+    pub height: u32,
+    pub format: CompressedFormat,
+    pub srgb: bool,
+    pub levels: Vec<Vec<u8>>,
+}
+
+// Defines what is in a decoded sound. The samples are interleaved by channel, so a stereo sound
+// holds a left and a right sample for each frame, and are in the range -1.0 to 1.0.
+pub struct Sound {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+impl Sound {
+    // Gets the number of frames, which is the number of samples in each channel.
+    pub fn get_frame_count(&self) -> usize {
+        if self.channels == 0 { 0 } else { self.samples.len() / self.channels as usize }
+    }
+
+    // Gets the length of the sound in seconds.
+    pub fn get_duration(&self) -> f32 {
+        if self.sample_rate == 0 { return 0.0; }
+        self.get_frame_count() as f32 / self.sample_rate as f32
+    }
+}
+
+// Writes data to a file in the temporary directory and returns its path so that tests can run the
+// decoders, which read from files, on bytes built in the test.
+#[cfg(test)]
+pub fn write_test_file(name: &str, data: &[u8]) -> String {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    let path = env::temp_dir().join(format!("mmo-test-{}", name));
+    File::create(&path).and_then(|mut fd| fd.write_all(data)).unwrap();
+    path.to_str().unwrap().to_string()
+}
@@ -0,0 +1,573 @@
+// Defines the engine's Config, which holds the settings read from a TOML file at startup: the
+// window's resolution, vsync, and MSAA level, the paths that assets are loaded from, the bindings
+// of actions, and the quality tier along with any quality settings that override it. Every setting
+// has a default, so the file only needs the ones that differ. Any setting can also be overridden
+// for a single run with an environment variable named after its key, such as ENGINE_WINDOW_WIDTH
+// for window.width or ENGINE_BINDINGS_JUMP for bindings.jump. Environment values are read as TOML
+// values, or as plain strings if they aren't one.
+//
+// update() checks whether the file has changed and reloads it, and set() changes a setting while
+// the game is running. Both give back a ConfigChanged event with the keys whose values changed,
+// which can be published on an EventBus so that each system reacts to the settings it cares about,
+// such as the renderer turning bloom off or the ActionMap picking up new bindings. A file that
+// fails to load while it is being edited is logged and the settings stay as they were.
+//
+// Keys that the engine doesn't know about are kept, so games can put their own settings in the
+// same file and read them with get().
+//
+// Usage of a Config:
+// - let mut config = try!(Config::load("config.toml"));
+// - GameWindow::from_config(&config.settings.window.to_window_config("Game")) and
+//   config.settings.apply_bindings(&mut actions) at startup.
+// - if let Some(event) = config.update() { bus.publish(event); } once per frame.
+//
+// Brian Ho
+// brian@brkho.com
+
+use platform::actions::{ActionMap, Binding};
+use platform::window::WindowConfig;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use util::json::Value;
+use util::toml;
+
+// The prefix of the environment variables that override settings.
+const ENV_PREFIX: &'static str = "ENGINE_";
+
+// The settings of each quality tier that can be overridden one by one.
+static QUALITY_KEYS: [&'static str; 5] = ["render_scale", "bloom", "fxaa", "lod_bias",
+        "particle_scale"];
+
+// An event for when settings have changed, given by their keys such as "window.vsync".
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConfigChanged {
+    pub keys: Vec<String>,
+}
+
+impl ConfigChanged {
+    // Returns true if a key or any key under it changed, so "quality" is affected by a change to
+    // "quality.bloom".
+    pub fn affects(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| {
+            k == key || (k.starts_with(key) && k[key.len()..].starts_with("."))
+        })
+    }
+}
+
+// The size of the window in pixels, whether it waits for vertical sync, and the number of samples
+// for multisampling, or 0 for none.
+#[derive(Clone, PartialEq, Debug)]
+pub struct WindowSettings {
+    pub width: u32,
+    pub height: u32,
+    pub vsync: bool,
+    pub msaa: u16,
+}
+
+impl WindowSettings {
+    // Gets the settings for creating a window with a title.
+    pub fn to_window_config(&self, title: &str) -> WindowConfig {
+        let mut config = WindowConfig::new(self.width, self.height, title);
+        config.vsync = self.vsync;
+        config.samples = self.msaa;
+        config
+    }
+}
+
+// The directories that assets and shaders are loaded from and the cook manifest of the assets.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AssetSettings {
+    pub root: String,
+    pub shaders: String,
+    pub manifest: String,
+}
+
+impl AssetSettings {
+    // Gets the path of an asset relative to the asset directory.
+    pub fn get_path(&self, name: &str) -> String {
+        let mut path = PathBuf::from(&self.root);
+        path.push(name);
+        path.to_string_lossy().into_owned()
+    }
+}
+
+// How much work the renderer does for better looking frames.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum QualityTier {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl QualityTier {
+    // Gets a tier from its name in a configuration file.
+    pub fn from_name(name: &str) -> Option<QualityTier> {
+        match &name.to_lowercase()[..] {
+            "low" => Some(QualityTier::Low),
+            "medium" => Some(QualityTier::Medium),
+            "high" => Some(QualityTier::High),
+            "ultra" => Some(QualityTier::Ultra),
+            _ => None,
+        }
+    }
+
+    // Gets the name of the tier that from_name() reads.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            QualityTier::Low => "low",
+            QualityTier::Medium => "medium",
+            QualityTier::High => "high",
+            QualityTier::Ultra => "ultra",
+        }
+    }
+}
+
+// The settings picked by a quality tier. The render scale is the size of the scene's render
+// targets relative to the window, the LOD bias scales the distances of LodGroups so larger values
+// lower detail sooner, and the particle scale multiplies the particle budgets of emitters.
+#[derive(Clone, PartialEq, Debug)]
+pub struct QualitySettings {
+    pub tier: QualityTier,
+    pub render_scale: f32,
+    pub bloom: bool,
+    pub fxaa: bool,
+    pub lod_bias: f32,
+    pub particle_scale: f32,
+}
+
+impl QualitySettings {
+    // Gets the settings of a quality tier.
+    pub fn for_tier(tier: QualityTier) -> QualitySettings {
+        let (render_scale, bloom, fxaa, lod_bias, particle_scale) = match tier {
+            QualityTier::Low => (0.75, false, false, 2.0, 0.25),
+            QualityTier::Medium => (1.0, false, true, 1.5, 0.5),
+            QualityTier::High => (1.0, true, true, 1.0, 1.0),
+            QualityTier::Ultra => (1.0, true, true, 0.5, 1.0),
+        };
+        QualitySettings { tier: tier, render_scale: render_scale, bloom: bloom, fxaa: fxaa,
+                lod_bias: lod_bias, particle_scale: particle_scale }
+    }
+}
+
+// Every setting that the engine reads from a Config.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Settings {
+    pub window: WindowSettings,
+    pub assets: AssetSettings,
+    pub quality: QualitySettings,
+    pub bindings: Vec<(String, Vec<Binding>)>,
+}
+
+// Gets the value at a dotted key of a document.
+fn get_key<'a>(document: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').fold(Some(document), |value, part| value.and_then(|v| v.get(part)))
+}
+
+// Sets the value at a dotted key of a document, creating any tables on the way.
+fn set_key(document: &mut Value, key: &str, value: Value) {
+    let mut parts = key.splitn(2, '.');
+    let first = parts.next().unwrap_or("");
+    match parts.next() {
+        Some(rest) => {
+            match document.get(first) {
+                Some(&Value::Object(_)) => {},
+                _ => document.set(first, Value::new_object()),
+            }
+            if let Some(table) = document.get_mut(first) {
+                set_key(table, rest, value);
+            }
+        },
+        None => document.set(first, value),
+    }
+}
+
+// Merges a document into another, where tables are merged key by key and anything else replaces
+// what was there.
+fn merge(base: &mut Value, overlay: &Value) {
+    if let Value::Object(ref pairs) = *overlay {
+        for &(ref key, ref value) in pairs {
+            let merged = match (base.get_mut(key), value) {
+                (Some(existing @ &mut Value::Object(_)), &Value::Object(_)) => {
+                    merge(existing, value);
+                    true
+                },
+                _ => false,
+            };
+            if !merged {
+                base.set(key, value.clone());
+            }
+        }
+    }
+}
+
+// Collects the dotted keys of every value in a document that isn't a table.
+fn flatten(value: &Value, prefix: &str, out: &mut Vec<(String, Value)>) {
+    match *value {
+        Value::Object(ref pairs) => {
+            for &(ref key, ref item) in pairs {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(item, &key, out);
+            }
+        },
+        _ => out.push((prefix.to_string(), value.clone())),
+    }
+}
+
+// Gets the keys whose values differ between two documents.
+fn diff(before: &Value, after: &Value) -> Vec<String> {
+    let (mut old, mut new) = (Vec::new(), Vec::new());
+    flatten(before, "", &mut old);
+    flatten(after, "", &mut new);
+    let mut keys = Vec::new();
+    for &(ref key, ref value) in &new {
+        if old.iter().find(|&&(ref k, _)| k == key).map_or(true, |&(_, ref v)| v != value) {
+            keys.push(key.clone());
+        }
+    }
+    for &(ref key, _) in &old {
+        if !new.iter().any(|&(ref k, _)| k == key) {
+            keys.push(key.clone());
+        }
+    }
+    keys
+}
+
+// Reads a number setting.
+fn get_number(document: &Value, key: &str) -> Result<Option<f64>, String> {
+    match get_key(document, key) {
+        Some(value) => value.as_f64().map(Some).ok_or(format!("{} must be a number.", key)),
+        None => Ok(None),
+    }
+}
+
+// Reads a whole number setting that is at least a minimum.
+fn get_integer(document: &Value, key: &str, min: f64, default: u32) -> Result<u32, String> {
+    match try!(get_number(document, key)) {
+        Some(n) if n.fract() == 0.0 && n >= min && n <= u32::max_value() as f64 => Ok(n as u32),
+        Some(_) => Err(format!("{} must be a whole number of at least {}.", key, min)),
+        None => Ok(default),
+    }
+}
+
+// Reads a boolean setting.
+fn get_bool(document: &Value, key: &str, default: bool) -> Result<bool, String> {
+    match get_key(document, key) {
+        Some(value) => value.as_bool().ok_or(format!("{} must be true or false.", key)),
+        None => Ok(default),
+    }
+}
+
+// Reads a string setting.
+fn get_string(document: &Value, key: &str, default: &str) -> Result<String, String> {
+    match get_key(document, key) {
+        Some(value) => {
+            value.as_str().map(|s| s.to_string()).ok_or(format!("{} must be a string.", key))
+        },
+        None => Ok(default.to_string()),
+    }
+}
+
+impl Settings {
+    // Gets the settings that are used for anything that isn't configured.
+    pub fn new() -> Settings {
+        Settings {
+            window: WindowSettings { width: 1280, height: 720, vsync: true, msaa: 0 },
+            assets: AssetSettings { root: "assets".to_string(), shaders: "shaders".to_string(),
+                    manifest: "assets/cook.json".to_string() },
+            quality: QualitySettings::for_tier(QualityTier::High),
+            bindings: Vec::new(),
+        }
+    }
+
+    // Reads the settings from a document, using the defaults for anything that it doesn't have.
+    // Returns an Err if any setting has the wrong type or an invalid value.
+    pub fn from_value(document: &Value) -> Result<Settings, String> {
+        let defaults = Settings::new();
+        let window = WindowSettings {
+            width: try!(get_integer(document, "window.width", 1.0, defaults.window.width)),
+            height: try!(get_integer(document, "window.height", 1.0, defaults.window.height)),
+            vsync: try!(get_bool(document, "window.vsync", defaults.window.vsync)),
+            msaa: try!(get_integer(document, "window.msaa", 0.0, 0)) as u16,
+        };
+        let assets = AssetSettings {
+            root: try!(get_string(document, "assets.root", &defaults.assets.root)),
+            shaders: try!(get_string(document, "assets.shaders", &defaults.assets.shaders)),
+            manifest: try!(get_string(document, "assets.manifest", &defaults.assets.manifest)),
+        };
+
+        let tier_name = try!(get_string(
+                document, "quality.tier", defaults.quality.tier.get_name()));
+        let tier = match QualityTier::from_name(&tier_name) {
+            Some(tier) => tier,
+            None => return Err(format!("quality.tier has an unknown tier {}.", tier_name)),
+        };
+        let mut quality = QualitySettings::for_tier(tier);
+        quality.render_scale = try!(get_number(document, "quality.render_scale"))
+                .map_or(quality.render_scale, |n| n as f32);
+        quality.bloom = try!(get_bool(document, "quality.bloom", quality.bloom));
+        quality.fxaa = try!(get_bool(document, "quality.fxaa", quality.fxaa));
+        quality.lod_bias = try!(get_number(document, "quality.lod_bias"))
+                .map_or(quality.lod_bias, |n| n as f32);
+        quality.particle_scale = try!(get_number(document, "quality.particle_scale"))
+                .map_or(quality.particle_scale, |n| n as f32);
+        if quality.render_scale <= 0.0 {
+            return Err("quality.render_scale must be greater than 0.".to_string());
+        }
+
+        let mut bindings = Vec::new();
+        if let Some(actions) = get_key(document, "bindings") {
+            let actions = try!(actions.as_object().ok_or("bindings must be a table.".to_string()));
+            for &(ref action, ref value) in actions {
+                // A single binding can be given without an array around it.
+                let names = match *value {
+                    Value::Array(ref items) => items.clone(),
+                    ref other => vec![other.clone()],
+                };
+                let mut parsed = Vec::new();
+                for name in &names {
+                    let name = try!(name.as_str().ok_or(
+                            format!("bindings.{} must be a list of bindings.", action)));
+                    match Binding::from_name(name) {
+                        Some(binding) => parsed.push(binding),
+                        None => {
+                            return Err(format!("bindings.{} has an unknown binding {}.", action,
+                                    name));
+                        },
+                    }
+                }
+                bindings.push((action.clone(), parsed));
+            }
+        }
+        Ok(Settings { window: window, assets: assets, quality: quality, bindings: bindings })
+    }
+
+    // Writes the settings as a document that from_value() reads back. Quality settings are only
+    // written when they differ from their tier's.
+    pub fn to_value(&self) -> Value {
+        let mut document = Value::new_object();
+        set_key(&mut document, "window.width", Value::Number(self.window.width as f64));
+        set_key(&mut document, "window.height", Value::Number(self.window.height as f64));
+        set_key(&mut document, "window.vsync", Value::Bool(self.window.vsync));
+        set_key(&mut document, "window.msaa", Value::Number(self.window.msaa as f64));
+        set_key(&mut document, "assets.root", Value::String(self.assets.root.clone()));
+        set_key(&mut document, "assets.shaders", Value::String(self.assets.shaders.clone()));
+        set_key(&mut document, "assets.manifest", Value::String(self.assets.manifest.clone()));
+        set_key(&mut document, "quality.tier",
+                Value::String(self.quality.tier.get_name().to_string()));
+        let tier = QualitySettings::for_tier(self.quality.tier);
+        let quality = [
+            (tier.render_scale != self.quality.render_scale,
+                    Value::Number(self.quality.render_scale as f64)),
+            (tier.bloom != self.quality.bloom, Value::Bool(self.quality.bloom)),
+            (tier.fxaa != self.quality.fxaa, Value::Bool(self.quality.fxaa)),
+            (tier.lod_bias != self.quality.lod_bias, Value::Number(self.quality.lod_bias as f64)),
+            (tier.particle_scale != self.quality.particle_scale,
+                    Value::Number(self.quality.particle_scale as f64)),
+        ];
+        for (key, &(changed, ref value)) in QUALITY_KEYS.iter().zip(quality.iter()) {
+            if changed {
+                set_key(&mut document, &format!("quality.{}", key), value.clone());
+            }
+        }
+        document.set("bindings", Value::new_object());
+        for &(ref action, ref bindings) in &self.bindings {
+            let names = bindings.iter().map(|b| Value::String(b.get_name())).collect();
+            set_key(&mut document, &format!("bindings.{}", action), Value::Array(names));
+        }
+        document
+    }
+
+    // Replaces the bindings of every configured action in an ActionMap. Actions that aren't
+    // configured keep their bindings.
+    pub fn apply_bindings(&self, actions: &mut ActionMap) {
+        for &(ref action, ref bindings) in &self.bindings {
+            actions.rebind(action, bindings);
+        }
+    }
+}
+
+// Reads the environment variables that override settings. A variable overrides the known key
+// whose name it matches, or an action's bindings if it starts with ENGINE_BINDINGS_.
+fn read_environment(known: &Value) -> Value {
+    let mut keys = Vec::new();
+    flatten(known, "", &mut keys);
+    for key in QUALITY_KEYS.iter() {
+        keys.push((format!("quality.{}", key), Value::Null));
+    }
+    let mut overrides = Value::new_object();
+    for (name, text) in env::vars() {
+        if !name.starts_with(ENV_PREFIX) { continue; }
+        let name = &name[ENV_PREFIX.len()..];
+        let binding_prefix = "BINDINGS_";
+        let key = if name.starts_with(binding_prefix) {
+            Some(format!("bindings.{}", name[binding_prefix.len()..].to_lowercase()))
+        } else {
+            keys.iter().map(|&(ref k, _)| k.clone())
+                    .find(|k| k.replace(".", "_").to_uppercase() == name)
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                log_warn!(Core, "{}{} doesn't match any setting.", ENV_PREFIX, name);
+                continue;
+            },
+        };
+        let mut value = toml::parse_value(&text).unwrap_or(Value::String(text.clone()));
+        // Bindings are given as a list separated by commas.
+        if key.starts_with("bindings.") {
+            value = Value::Array(text.split(',').map(|b| Value::String(b.trim().to_string()))
+                    .collect());
+        }
+        log_info!(Core, "{} is overridden by the environment.", key);
+        set_key(&mut overrides, &key, value);
+    }
+    overrides
+}
+
+// Gets the time that a file was last modified.
+fn get_modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// The settings along with the layers of documents that they are read from. Each layer overrides
+// the ones before it: the defaults, the file, the environment, and changes made with set().
+pub struct Config {
+    pub settings: Settings,
+    path: Option<String>,
+    modified: Option<SystemTime>,
+    file: Value,
+    environment: Value,
+    runtime: Value,
+    document: Value,
+}
+
+impl Config {
+    // Creates a Config with the default settings and any overrides from the environment.
+    pub fn new() -> Config {
+        let defaults = Settings::new().to_value();
+        let environment = read_environment(&defaults);
+        let mut config = Config { settings: Settings::new(), path: None, modified: None,
+                file: Value::new_object(), environment: environment,
+                runtime: Value::new_object(), document: defaults };
+        if let Err(e) = config.rebuild() {
+            log_error!(Core, "Ignoring the environment's settings: {}", e);
+            config.environment = Value::new_object();
+            let _ = config.rebuild();
+        }
+        config
+    }
+
+    // Loads the settings from a TOML file, which is watched for changes by update(). A file that
+    // doesn't exist yet counts as empty so that every setting has its default. Returns an Err if
+    // the file can't be parsed or has an invalid setting.
+    pub fn load(path: &str) -> Result<Config, String> {
+        let mut config = Config::new();
+        config.path = Some(path.to_string());
+        config.modified = get_modified(path);
+        if config.modified.is_some() {
+            config.file = try!(toml::decode_toml(path)
+                    .map_err(|e| format!("Unable to load {}: {}", path, e)));
+        } else {
+            log_info!(Core, "{} doesn't exist, so the default settings are used.", path);
+        }
+        try!(config.rebuild());
+        Ok(config)
+    }
+
+    // Gets the path of the file that the settings are loaded from.
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_ref().map(|p| &p[..])
+    }
+
+    // Gets the value of a dotted key such as "window.width", including keys that the engine
+    // doesn't use.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        get_key(&self.document, key)
+    }
+
+    // Gets the merged document of every layer.
+    pub fn get_document(&self) -> &Value {
+        &self.document
+    }
+
+    // Changes the value of a dotted key until the game exits, overriding the file and the
+    // environment. Returns an Err and leaves the settings alone if the value is invalid for the
+    // key.
+    pub fn set(&mut self, key: &str, value: Value) -> Result<Option<ConfigChanged>, String> {
+        let previous = self.runtime.clone();
+        set_key(&mut self.runtime, key, value);
+        match self.rebuild() {
+            Ok(changed) => Ok(changed),
+            Err(e) => {
+                self.runtime = previous;
+                try!(self.rebuild());
+                Err(e)
+            },
+        }
+    }
+
+    // Reloads the file and returns the changes if any. Returns an Err and leaves the settings alone
+    // if the file can't be loaded.
+    pub fn reload(&mut self) -> Result<Option<ConfigChanged>, String> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => return Ok(None),
+        };
+        self.modified = get_modified(&path);
+        let previous = self.file.clone();
+        self.file = if self.modified.is_some() {
+            try!(toml::decode_toml(&path))
+        } else {
+            Value::new_object()
+        };
+        match self.rebuild() {
+            Ok(changed) => Ok(changed),
+            Err(e) => {
+                self.file = previous;
+                try!(self.rebuild());
+                Err(e)
+            },
+        }
+    }
+
+    // Reloads the file if it has changed since it was last loaded and returns the changes if
+    // any. Errors are logged. This is cheap enough to call every frame.
+    pub fn update(&mut self) -> Option<ConfigChanged> {
+        let modified = match self.path {
+            Some(ref path) => get_modified(path),
+            None => return None,
+        };
+        if modified == self.modified { return None; }
+        match self.reload() {
+            Ok(changed) => {
+                log_info!(Core, "Reloaded {}.", self.path.as_ref().unwrap());
+                changed
+            },
+            Err(e) => {
+                // Keep the new time so that a broken file is only reported once per edit.
+                self.modified = modified;
+                log_error!(Core, "Unable to reload {}: {}", self.path.as_ref().unwrap(), e);
+                None
+            },
+        }
+    }
+
+    // Merges the layers and reads the settings from them, returning the keys that changed.
+    fn rebuild(&mut self) -> Result<Option<ConfigChanged>, String> {
+        let mut document = Settings::new().to_value();
+        merge(&mut document, &self.file);
+        merge(&mut document, &self.environment);
+        merge(&mut document, &self.runtime);
+        self.settings = try!(Settings::from_value(&document));
+        let keys = diff(&self.document, &document);
+        self.document = document;
+        if keys.is_empty() { Ok(None) } else { Ok(Some(ConfigChanged { keys: keys })) }
+    }
+}
@@ -0,0 +1,384 @@
+// Converts source assets into the formats that the engine loads fastest, so that shipping builds
+// never have to parse PNGs or OBJs at runtime. Textures in BMP, PNG, or TGA files are given a mip
+// chain and block compressed into KTX2 files, which the TextureManager uploads as-is. Meshes in
+// OBJ, glTF, or GLB files are converted along with their texture maps into .rmod files.
+//
+// What to cook is described by a JSON manifest. Paths in the manifest are relative to it, and the
+// outputs go in its output directory with the name of their source unless an output is given:
+//
+//   {
+//     "output": "cooked",
+//     "textures": [
+//       { "source": "textures/brick.png", "format": "bc1", "color_space": "srgb" },
+//       { "source": "textures/brick_n.tga", "format": "bc5", "color_space": "linear" }
+//     ],
+//     "meshes": [
+//       { "source": "models/crate.gltf", "output": "crate.rmod", "diffuse": "textures/crate.png",
+//         "specular": "textures/crate_s.png", "normal": "textures/crate_n.png", "shininess": 32 }
+//     ]
+//   }
+//
+// The texture format is one of bc1, bc3, bc4, bc5, or auto, which picks bc3 for images with alpha
+// and bc1 otherwise. Mips are generated unless "mips" is false, and are filtered in linear space
+// for sRGB textures. Assets are cooked across the threads of a JobSystem, and an asset is skipped
+// if its output is newer than its sources and the manifest.
+//
+// Usage of the cooker:
+// - Load a manifest with Manifest::load(path).
+// - Call cook(&manifest, &jobs, force) to cook every asset that is out of date, or every asset if
+//   force is set, and read the CookReport for what was cooked and what failed.
+// - The cook binary does both from the command line: cargo run --bin cook -- manifest.json.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use util::{bc, bmp, common, gltf, ktx2, obj, png, rmod, tga};
+use util::jobs::JobSystem;
+use util::json::{self, Value};
+
+// The shininess of meshes that don't set one, which matches meshes loaded from OBJs at runtime.
+const DEFAULT_SHININESS: f32 = 10.0;
+
+// The block compression format to cook a texture to.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TextureFormat {
+    Auto,
+    BC1,
+    BC3,
+    BC4,
+    BC5,
+}
+
+// A texture to cook into a KTX2 file.
+#[derive(Clone, Debug)]
+pub struct TextureAsset {
+    pub source: String,
+    pub output: String,
+    pub format: TextureFormat,
+    pub srgb: bool,
+    pub mips: bool,
+}
+
+// A mesh to cook into a .rmod file along with the images of its material.
+#[derive(Clone, Debug)]
+pub struct MeshAsset {
+    pub source: String,
+    pub output: String,
+    pub diffuse: Option<String>,
+    pub specular: Option<String>,
+    pub normal: Option<String>,
+    pub shininess: f32,
+}
+
+// The assets described by a manifest file, with every path resolved.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub path: String,
+    pub textures: Vec<TextureAsset>,
+    pub meshes: Vec<MeshAsset>,
+}
+
+// The outcome of cooking a manifest. Outputs that were up to date are skipped, and failures hold
+// the output along with the error.
+#[derive(Clone, Debug, Default)]
+pub struct CookReport {
+    pub cooked: Vec<String>,
+    pub skipped: Vec<String>,
+    pub failed: Vec<(String, String)>,
+}
+
+// Helpers to read values from a manifest entry. Missing fields take a default value while fields
+// with the wrong type are an Err.
+fn get_string(object: &Value, key: &str) -> Result<Option<String>, String> {
+    match object.get(key) {
+        None => Ok(None),
+        Some(v) => v.as_str().map(|s| Some(s.to_string())).ok_or(format!("{} must be a string.",
+                key)),
+    }
+}
+
+fn get_bool(object: &Value, key: &str, default: bool) -> Result<bool, String> {
+    match object.get(key) {
+        None => Ok(default),
+        Some(v) => v.as_bool().ok_or(format!("{} must be true or false.", key)),
+    }
+}
+
+fn get_f32(object: &Value, key: &str, default: f32) -> Result<f32, String> {
+    match object.get(key) {
+        None => Ok(default),
+        Some(v) => v.as_f64().map(|n| n as f32).ok_or(format!("{} must be a number.", key)),
+    }
+}
+
+fn get_entries<'a>(object: &'a Value, key: &str) -> Result<&'a [Value], String> {
+    match object.get(key) {
+        None => Ok(&[]),
+        Some(v) => v.as_array().map(|a| &a[..]).ok_or(format!("{} must be an array.", key)),
+    }
+}
+
+// Resolves a path in the manifest against a directory.
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+// Gets the output path of an entry, which defaults to the name of its source with an extension.
+fn get_output(entry: &Value, source: &str, output_dir: &Path, extension: &str)
+        -> Result<String, String> {
+    match try!(get_string(entry, "output")) {
+        Some(output) => Ok(resolve(output_dir, &output)),
+        None => {
+            let stem = Path::new(source).file_stem().map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or(source.to_string());
+            Ok(resolve(output_dir, &format!("{}.{}", stem, extension)))
+        },
+    }
+}
+
+// Gets the source of an entry.
+fn get_source(entry: &Value, dir: &Path) -> Result<String, String> {
+    match try!(get_string(entry, "source")) {
+        Some(source) => Ok(resolve(dir, &source)),
+        None => Err("Every asset in a manifest needs a source.".to_string()),
+    }
+}
+
+impl Manifest {
+    // Reads a manifest from a JSON file.
+    pub fn load(fpath: &str) -> Result<Manifest, String> {
+        let doc = try!(json::decode_json(fpath));
+        let dir = Path::new(fpath).parent().map(|p| p.to_path_buf()).unwrap_or(PathBuf::new());
+        let output_dir = dir.join(try!(get_string(&doc, "output")).unwrap_or(String::new()));
+        let mut manifest = Manifest { path: fpath.to_string(), textures: Vec::new(),
+                meshes: Vec::new() };
+        for entry in try!(get_entries(&doc, "textures")) {
+            let source = try!(get_source(entry, &dir));
+            let format = try!(get_string(entry, "format")).unwrap_or("auto".to_string());
+            let format = match &format[..] {
+                "auto" => TextureFormat::Auto,
+                "bc1" => TextureFormat::BC1,
+                "bc3" => TextureFormat::BC3,
+                "bc4" => TextureFormat::BC4,
+                "bc5" => TextureFormat::BC5,
+                format => return Err(format!("Unknown texture format: {}", format)),
+            };
+            let srgb = match try!(get_string(entry, "color_space")) {
+                None => format != TextureFormat::BC4 && format != TextureFormat::BC5,
+                Some(ref space) if space == "srgb" => true,
+                Some(ref space) if space == "linear" => false,
+                Some(space) => return Err(format!("Unknown color space: {}", space)),
+            };
+            manifest.textures.push(TextureAsset {
+                    output: try!(get_output(entry, &source, &output_dir, "ktx2")),
+                    source: source, format: format, srgb: srgb,
+                    mips: try!(get_bool(entry, "mips", true)) });
+        }
+        for entry in try!(get_entries(&doc, "meshes")) {
+            let source = try!(get_source(entry, &dir));
+            let map = |key: &str| -> Result<Option<String>, String> {
+                Ok(try!(get_string(entry, key)).map(|path| resolve(&dir, &path)))
+            };
+            manifest.meshes.push(MeshAsset {
+                    output: try!(get_output(entry, &source, &output_dir, "rmod")),
+                    source: source, diffuse: try!(map("diffuse")),
+                    specular: try!(map("specular")), normal: try!(map("normal")),
+                    shininess: try!(get_f32(entry, "shininess", DEFAULT_SHININESS)) });
+        }
+        Ok(manifest)
+    }
+}
+
+// Decodes a BMP, PNG, or TGA file based on its extension.
+pub fn decode_image(path: &str) -> Result<common::Image, String> {
+    let extension = Path::new(path).extension().and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| &e[..]) {
+        Some("bmp") => Ok(try!(bmp::decode_bmp(path)).image),
+        Some("png") => Ok(try!(png::decode_png(path)).image),
+        Some("tga") => Ok(try!(tga::decode_tga(path)).image),
+        _ => Err(format!("Unsupported image file: {}.", path)),
+    }
+}
+
+// Converts an sRGB channel value in 0-255 to linear space in 0.0-1.0.
+fn to_linear(value: u8) -> f32 {
+    let c = value as f32 / 255.0;
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+// Converts a linear channel value in 0.0-1.0 to sRGB space in 0-255.
+fn to_srgb(value: f32) -> u8 {
+    let c = if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0 + 0.5).max(0.0).min(255.0) as u8
+}
+
+// Halves an image with a box filter. Color is averaged in linear space for sRGB images, and odd
+// edges fold into the last texel.
+fn downsample(image: &common::Image, srgb: bool) -> common::Image {
+    let width = if image.width > 1 { image.width / 2 } else { 1 };
+    let height = if image.height > 1 { image.height / 2 } else { 1 };
+    let mut data = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 4];
+            let mut count = 0.0;
+            for sy in (y * 2)..cmp::min(y * 2 + 2, image.height) {
+                for sx in (x * 2)..cmp::min(x * 2 + 2, image.width) {
+                    let p = &image.data[(sy * image.width + sx) as usize];
+                    let color = [p.red, p.green, p.blue];
+                    for c in 0..3 {
+                        sum[c] += if srgb { to_linear(color[c]) } else { color[c] as f32 / 255.0 };
+                    }
+                    sum[3] += p.alpha as f32 / 255.0;
+                    count += 1.0;
+                }
+            }
+            let channel = |c: usize| if srgb && c < 3 { to_srgb(sum[c] / count) } else {
+                (sum[c] / count * 255.0 + 0.5).min(255.0) as u8
+            };
+            data.push(common::Pixel { red: channel(0), green: channel(1), blue: channel(2),
+                    alpha: channel(3) });
+        }
+    }
+    common::Image { width: width, height: height, data: data }
+}
+
+// Returns true if an image uses its alpha channel. Images that are fully transparent are treated
+// as opaque since 24 bit BMPs decode with an alpha of 0.
+fn has_alpha(image: &common::Image) -> bool {
+    let first = image.data.first().map_or(255, |p| p.alpha);
+    image.data.iter().any(|p| p.alpha != first) || (first != 255 && first != 0)
+}
+
+// Cooks a texture into a KTX2 file.
+pub fn cook_texture(asset: &TextureAsset) -> Result<(), String> {
+    let image = try!(decode_image(&asset.source));
+    let format = match asset.format {
+        TextureFormat::Auto if has_alpha(&image) => common::CompressedFormat::BC3,
+        TextureFormat::Auto | TextureFormat::BC1 => common::CompressedFormat::BC1,
+        TextureFormat::BC3 => common::CompressedFormat::BC3,
+        TextureFormat::BC4 => common::CompressedFormat::BC4,
+        TextureFormat::BC5 => common::CompressedFormat::BC5,
+    };
+    let srgb = asset.srgb && format != common::CompressedFormat::BC4 &&
+            format != common::CompressedFormat::BC5;
+    let mut levels = vec![try!(bc::encode_bc(&image, format))];
+    if asset.mips {
+        let mut level = downsample(&image, srgb);
+        loop {
+            levels.push(try!(bc::encode_bc(&level, format)));
+            if level.width == 1 && level.height == 1 { break; }
+            level = downsample(&level, srgb);
+        }
+    }
+    let compressed = common::CompressedImage { width: image.width, height: image.height,
+            format: format, srgb: srgb, levels: levels };
+    ktx2::encode_ktx2(&asset.output, &compressed)
+}
+
+// Cooks a mesh and the images of its material into a .rmod file.
+pub fn cook_mesh(asset: &MeshAsset) -> Result<(), String> {
+    let extension = Path::new(&asset.source).extension().and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+    let (vertices, elements) = match extension.as_ref().map(|e| &e[..]) {
+        Some("obj") => {
+            let decoded = try!(obj::decode_obj(&asset.source));
+            let elements = decoded.elements.iter().flat_map(|&(a, b, c)| vec![a, b, c])
+                    .collect();
+            (decoded.vertices, elements)
+        },
+        Some("gltf") | Some("glb") => {
+            let decoded = try!(gltf::decode_gltf(&asset.source));
+            (decoded.vertices, decoded.elements)
+        },
+        _ => return Err(format!("Unsupported mesh file: {}.", asset.source)),
+    };
+    let map = |path: &Option<String>| -> Result<Option<common::Image>, String> {
+        match *path {
+            Some(ref path) => Ok(Some(try!(decode_image(path)))),
+            None => Ok(None),
+        }
+    };
+    let rmod = rmod::DecodedRMOD { diffuse: try!(map(&asset.diffuse)),
+            specular: try!(map(&asset.specular)), normal: try!(map(&asset.normal)),
+            vertices: vertices, elements: elements, shininess: asset.shininess };
+    rmod::encode_rmod(&asset.output, &rmod)
+}
+
+// Gets the time that a file was last modified.
+fn get_modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Returns true if an output is newer than every one of its inputs.
+fn is_up_to_date(output: &str, inputs: &[&str]) -> bool {
+    let modified = match get_modified(output) {
+        Some(modified) => modified,
+        None => return false,
+    };
+    inputs.iter().all(|input| get_modified(input).map_or(false, |m| m <= modified))
+}
+
+// An asset of either kind.
+enum Asset<'a> {
+    Texture(&'a TextureAsset),
+    Mesh(&'a MeshAsset),
+}
+
+// Cooks every asset in a manifest across the threads of a JobSystem. Assets whose outputs are
+// newer than their sources and the manifest are skipped unless force is set.
+pub fn cook(manifest: &Manifest, jobs: &JobSystem, force: bool) -> CookReport {
+    let mut assets: Vec<Asset> = manifest.textures.iter().map(|t| Asset::Texture(t)).collect();
+    assets.extend(manifest.meshes.iter().map(|m| Asset::Mesh(m)));
+    let results = jobs.map(&assets, |_, asset| {
+        let (output, mut inputs) = match *asset {
+            Asset::Texture(texture) => (&texture.output, vec![&texture.source[..]]),
+            Asset::Mesh(mesh) => {
+                let mut inputs = vec![&mesh.source[..]];
+                for map in [&mesh.diffuse, &mesh.specular, &mesh.normal].iter() {
+                    if let Some(ref path) = **map {
+                        inputs.push(&path[..]);
+                    }
+                }
+                (&mesh.output, inputs)
+            },
+        };
+        inputs.push(&manifest.path);
+        if !force && is_up_to_date(output, &inputs) {
+            return (output.clone(), None);
+        }
+        if let Some(dir) = Path::new(output).parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                return (output.clone(), Some(Err(e.to_string())));
+            }
+        }
+        let result = match *asset {
+            Asset::Texture(texture) => cook_texture(texture),
+            Asset::Mesh(mesh) => cook_mesh(mesh),
+        };
+        (output.clone(), Some(result))
+    });
+    let mut report = CookReport::default();
+    for (output, result) in results {
+        match result {
+            None => {
+                log_debug!(Assets, "{} is up to date.", output);
+                report.skipped.push(output);
+            },
+            Some(Ok(())) => {
+                log_info!(Assets, "Cooked {}.", output);
+                report.cooked.push(output);
+            },
+            Some(Err(e)) => {
+                log_error!(Assets, "Failed to cook {}: {}", output, e);
+                report.failed.push((output, e));
+            },
+        }
+    }
+    report
+}
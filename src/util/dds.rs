@@ -0,0 +1,236 @@
+// Utility module that allows for decoding of a DirectDraw Surface (.dds) file given a path to the
+// file. Only single 2D textures with block compressed data are supported. These can either use
+// the legacy DXT1, DXT3, DXT5, ATI1, and ATI2 FourCC codes or the DX10 extended header with one of
+// the BC1-BC7 DXGI formats. The blocks are not decoded so that they can be uploaded to the GPU
+// as-is.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::fs::File;
+use std::io::Read;
+use util::common;
+
+// Return value for a decoded DDS file. This contains the compressed blocks for every mip level.
+pub struct DecodedDDS {
+    pub image: common::CompressedImage,
+}
+
+// Size in bytes of the header following the magic number.
+const HEADER_SIZE: u32 = 124;
+
+// Size in bytes of the pixel format structure embedded in the header.
+const PIXEL_FORMAT_SIZE: u32 = 32;
+
+// Pixel format flag that indicates the FourCC field is valid.
+const DDPF_FOURCC: u32 = 0x4;
+
+// Caps2 flags for cubemaps and volume textures, neither of which are supported.
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x200000;
+
+// The resource dimension of a 2D texture in the DX10 header.
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+
+// Reads and consumes 4 bytes from the data vector as a little endian u32.
+fn read_dword(data: &Vec<u8>, cursor: &mut usize) -> Result<u32, String> {
+    if *cursor + 4 > data.len() {
+        return Err("DDS file is too small.".to_string());
+    }
+    let mut value = 0;
+    for i in 0..4 {
+        value |= (data[*cursor + i] as u32) << (8 * i);
+    }
+    *cursor += 4;
+    Ok(value)
+}
+
+// Converts a string of 4 characters into a FourCC code.
+fn fourcc(code: &str) -> u32 {
+    let bytes = code.as_bytes();
+    (bytes[0] as u32) | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+// Maps a legacy FourCC code to a compression format and whether it is in sRGB space. Legacy files
+// have no way of marking themselves as sRGB so they are assumed to be linear.
+fn format_from_fourcc(code: u32) -> Result<(common::CompressedFormat, bool), String> {
+    if code == fourcc("DXT1") {
+        Ok((common::CompressedFormat::BC1Alpha, false))
+    } else if code == fourcc("DXT3") {
+        Ok((common::CompressedFormat::BC2, false))
+    } else if code == fourcc("DXT5") {
+        Ok((common::CompressedFormat::BC3, false))
+    } else if code == fourcc("ATI1") || code == fourcc("BC4U") {
+        Ok((common::CompressedFormat::BC4, false))
+    } else if code == fourcc("ATI2") || code == fourcc("BC5U") {
+        Ok((common::CompressedFormat::BC5, false))
+    } else {
+        Err("Unsupported DDS FourCC code.".to_string())
+    }
+}
+
+// Maps a DXGI format from the DX10 header to a compression format and whether it is in sRGB
+// space.
+fn format_from_dxgi(format: u32) -> Result<(common::CompressedFormat, bool), String> {
+    match format {
+        70 | 71 => Ok((common::CompressedFormat::BC1Alpha, false)),
+        72 => Ok((common::CompressedFormat::BC1Alpha, true)),
+        73 | 74 => Ok((common::CompressedFormat::BC2, false)),
+        75 => Ok((common::CompressedFormat::BC2, true)),
+        76 | 77 => Ok((common::CompressedFormat::BC3, false)),
+        78 => Ok((common::CompressedFormat::BC3, true)),
+        79 | 80 => Ok((common::CompressedFormat::BC4, false)),
+        82 | 83 => Ok((common::CompressedFormat::BC5, false)),
+        94 | 95 => Ok((common::CompressedFormat::BC6HUnsigned, false)),
+        96 => Ok((common::CompressedFormat::BC6HSigned, false)),
+        97 | 98 => Ok((common::CompressedFormat::BC7, false)),
+        99 => Ok((common::CompressedFormat::BC7, true)),
+        _ => Err("Unsupported DDS DXGI format.".to_string()),
+    }
+}
+
+// Reads the DX10 extended header and returns the format it describes.
+fn read_dx10_header(data: &Vec<u8>, cursor: &mut usize)
+        -> Result<(common::CompressedFormat, bool), String> {
+    let dxgi_format = try!(read_dword(data, cursor));
+    let dimension = try!(read_dword(data, cursor));
+    try!(read_dword(data, cursor));
+    let array_size = try!(read_dword(data, cursor));
+    try!(read_dword(data, cursor));
+    if dimension != D3D10_RESOURCE_DIMENSION_TEXTURE2D || array_size > 1 {
+        return Err("Only single 2D DDS textures are supported.".to_string());
+    }
+    format_from_dxgi(dxgi_format)
+}
+
+// Decodes a DDS given a path to the file and returns a DecodedDDS struct containing the
+// compressed mip levels, format, width, and height of the image.
+pub fn decode_dds(fpath: &str) -> Result<DecodedDDS, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    let mut cursor = 0;
+    if try!(read_dword(&data, &mut cursor)) != fourcc("DDS ") {
+        return Err("DDS file header has incorrect magic values.".to_string());
+    }
+    if try!(read_dword(&data, &mut cursor)) != HEADER_SIZE {
+        return Err("DDS file header has an incorrect size.".to_string());
+    }
+    try!(read_dword(&data, &mut cursor));
+    let height = try!(read_dword(&data, &mut cursor));
+    let width = try!(read_dword(&data, &mut cursor));
+    try!(read_dword(&data, &mut cursor));
+    try!(read_dword(&data, &mut cursor));
+    let mip_count = try!(read_dword(&data, &mut cursor));
+    cursor += 11 * 4;
+    if try!(read_dword(&data, &mut cursor)) != PIXEL_FORMAT_SIZE {
+        return Err("DDS pixel format has an incorrect size.".to_string());
+    }
+    let pf_flags = try!(read_dword(&data, &mut cursor));
+    let code = try!(read_dword(&data, &mut cursor));
+    cursor += 5 * 4;
+    try!(read_dword(&data, &mut cursor));
+    let caps2 = try!(read_dword(&data, &mut cursor));
+    cursor += 3 * 4;
+    if pf_flags & DDPF_FOURCC == 0 {
+        return Err("Only block compressed DDS files are supported.".to_string());
+    }
+    if caps2 & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        return Err("Only single 2D DDS textures are supported.".to_string());
+    }
+    if width == 0 || height == 0 {
+        return Err("DDS image is empty.".to_string());
+    }
+    let (format, srgb) = if code == fourcc("DX10") {
+        try!(read_dx10_header(&data, &mut cursor))
+    } else {
+        try!(format_from_fourcc(code))
+    };
+
+    let mut levels = Vec::new();
+    let (mut level_width, mut level_height) = (width, height);
+    for _ in 0..(if mip_count == 0 { 1 } else { mip_count }) {
+        let size = format.level_size(level_width, level_height);
+        if size > data.len().saturating_sub(cursor) {
+            return Err("DDS file is too small.".to_string());
+        }
+        levels.push(data[cursor..(cursor + size)].to_vec());
+        cursor += size;
+        if level_width == 1 && level_height == 1 { break; }
+        level_width = if level_width > 1 { level_width / 2 } else { 1 };
+        level_height = if level_height > 1 { level_height / 2 } else { 1 };
+    }
+    let image = common::CompressedImage { width: width, height: height, format: format,
+            srgb: srgb, levels: levels };
+    Ok(DecodedDDS { image: image })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Builds a file with a legacy header for a FourCC code, which is followed by the DX10 header
+    // when the code is DX10.
+    fn dds_file(width: u32, height: u32, mip_count: u32, code: &str, blocks: &[u8]) -> Vec<u8> {
+        let mut header = vec![fourcc("DDS "), HEADER_SIZE, 0, height, width, 0, 0, mip_count];
+        header.extend_from_slice(&[0; 11]);
+        header.extend_from_slice(&[PIXEL_FORMAT_SIZE, DDPF_FOURCC, fourcc(code), 0, 0, 0, 0, 0]);
+        header.extend_from_slice(&[0; 5]);
+        let mut data = Vec::new();
+        for value in header {
+            for i in 0..4 {
+                data.push((value >> (8 * i)) as u8);
+            }
+        }
+        data.extend_from_slice(blocks);
+        data
+    }
+
+    #[test]
+    fn decodes_mip_levels() {
+        let data = dds_file(8, 8, 4, "DXT1", &[7; 32 + 8 + 8 + 8]);
+        let path = common::write_test_file("mips.dds", &data);
+        let image = decode_dds(&path).unwrap().image;
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!(image.format, common::CompressedFormat::BC1Alpha);
+        assert!(!image.srgb);
+        let sizes: Vec<usize> = image.levels.iter().map(|l| l.len()).collect();
+        assert_eq!(sizes, vec![32, 8, 8, 8]);
+    }
+
+    #[test]
+    fn decodes_dx10_header() {
+        let mut blocks = vec![99, 0, 0, 0, D3D10_RESOURCE_DIMENSION_TEXTURE2D as u8, 0, 0, 0];
+        blocks.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        blocks.extend_from_slice(&[5; 16]);
+        let path = common::write_test_file("dx10.dds", &dds_file(4, 4, 1, "DX10", &blocks));
+        let image = decode_dds(&path).unwrap().image;
+        assert_eq!(image.format, common::CompressedFormat::BC7);
+        assert!(image.srgb);
+        assert_eq!(image.levels, vec![vec![5; 16]]);
+    }
+
+    #[test]
+    fn rejects_truncated_blocks() {
+        let path = common::write_test_file("truncated.dds", &dds_file(8, 8, 1, "DXT1", &[0; 16]));
+        assert!(decode_dds(&path).is_err());
+        let data = dds_file(8, 8, 1, "DXT1", &[]);
+        let path = common::write_test_file("header.dds", &data[..100]);
+        assert!(decode_dds(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        let path = common::write_test_file("empty.dds", &dds_file(0, 4, 1, "DXT1", &[0; 8]));
+        assert!(decode_dds(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let data = dds_file(0xFFFFFFFF, 0xFFFFFFFF, 32, "DXT5", &[0; 64]);
+        let path = common::write_test_file("oversized.dds", &data);
+        assert!(decode_dds(&path).is_err());
+    }
+}
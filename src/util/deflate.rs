@@ -0,0 +1,161 @@
+// Utility module that compresses bytes to a DEFLATE stream (RFC 1951) wrapped in the zlib format
+// (RFC 1950), which is what PNG files store their pixels in. Repeated strings are found with hash
+// chains over the last 32KB of input and everything is written as a single block with the fixed
+// Huffman codes. Building dynamic codes would make files somewhat smaller, but this is mostly used
+// for screenshots and captured frames where writing quickly matters more.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use util::inflate;
+
+// How far back a repeated string can be found.
+const WINDOW_SIZE: usize = 32768;
+
+// The shortest and longest repeated strings that can be written.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// The number of bits in the hash of the next three bytes.
+const HASH_BITS: usize = 15;
+
+// The most earlier strings with the same hash that are checked for the longest match.
+const MAX_CHAIN: usize = 32;
+
+// Writes bits to a byte vector starting from the least significant bit of each byte.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: usize,
+}
+
+impl BitWriter {
+    // Writes the lowest n bits of a value with the least significant bit first. n is at most 16.
+    fn write_bits(&mut self, value: u32, n: usize) {
+        self.buffer |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Writes a Huffman code of some length, which is stored with its most significant bit first.
+    fn write_code(&mut self, code: u32, length: usize) {
+        let reversed = (0..length).fold(0, |r, i| r | ((code >> i) & 1) << (length - 1 - i));
+        self.write_bits(reversed, length);
+    }
+
+    // Pads the last byte with zeros and returns the bytes that were written.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+// Writes a literal/length symbol with its fixed Huffman code.
+fn write_symbol(writer: &mut BitWriter, symbol: usize) {
+    let symbol = symbol as u32;
+    if symbol < 144 {
+        writer.write_code(0x30 + symbol, 8);
+    } else if symbol < 256 {
+        writer.write_code(0x190 + symbol - 144, 9);
+    } else if symbol < 280 {
+        writer.write_code(symbol - 256, 7);
+    } else {
+        writer.write_code(0xC0 + symbol - 280, 8);
+    }
+}
+
+// Finds the index of the largest base in a table that is at most a value.
+fn find_code(bases: &[u16], value: usize) -> usize {
+    let mut index = 0;
+    while index + 1 < bases.len() && bases[index + 1] as usize <= value {
+        index += 1;
+    }
+    index
+}
+
+// Writes a repeated string as its length and distance codes along with their extra bits.
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = find_code(&inflate::LENGTH_BASE, length);
+    write_symbol(writer, 257 + code);
+    writer.write_bits((length - inflate::LENGTH_BASE[code] as usize) as u32,
+            inflate::LENGTH_EXTRA[code] as usize);
+    let code = find_code(&inflate::DISTANCE_BASE, distance);
+    writer.write_code(code as u32, 5);
+    writer.write_bits((distance - inflate::DISTANCE_BASE[code] as usize) as u32,
+            inflate::DISTANCE_EXTRA[code] as usize);
+}
+
+// Hashes the three bytes starting at an index.
+fn hash(data: &[u8], index: usize) -> usize {
+    ((data[index] as usize) << 10 ^ (data[index + 1] as usize) << 5 ^ data[index + 2] as usize) &
+            ((1 << HASH_BITS) - 1)
+}
+
+// Compresses bytes to a raw DEFLATE stream.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { out: Vec::with_capacity(data.len() / 2), buffer: 0, count: 0 };
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    // The chains hold one more than the index of each string so that 0 can mean the end.
+    let mut head = vec![0usize; 1 << HASH_BITS];
+    let mut previous = vec![0usize; WINDOW_SIZE];
+    let mut index = 0;
+    while index < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if index + MIN_MATCH <= data.len() {
+            let max_length = cmp::min(MAX_MATCH, data.len() - index);
+            let mut candidate = head[hash(data, index)];
+            let mut chain = 0;
+            while candidate > 0 && chain < MAX_CHAIN {
+                let start = candidate - 1;
+                if index - start > WINDOW_SIZE { break; }
+                let mut length = 0;
+                while length < max_length && data[start + length] == data[index + length] {
+                    length += 1;
+                }
+                if length > best_length {
+                    best_length = length;
+                    best_distance = index - start;
+                    if length == max_length { break; }
+                }
+                candidate = previous[start % WINDOW_SIZE];
+                chain += 1;
+            }
+        }
+        let advance = if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            best_length
+        } else {
+            write_symbol(&mut writer, data[index] as usize);
+            1
+        };
+        for i in index..(index + advance) {
+            if i + MIN_MATCH <= data.len() {
+                let h = hash(data, i);
+                previous[i % WINDOW_SIZE] = head[h];
+                head[h] = i + 1;
+            }
+        }
+        index += advance;
+    }
+    write_symbol(&mut writer, 256);
+    writer.finish()
+}
+
+// Compresses bytes to a zlib stream.
+pub fn encode_zlib(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9C];
+    out.extend(deflate(data));
+    let checksum = inflate::adler32(data);
+    out.extend_from_slice(&[(checksum >> 24) as u8, (checksum >> 16) as u8,
+            (checksum >> 8) as u8, checksum as u8]);
+    out
+}
@@ -0,0 +1,217 @@
+// Utility module with an EventBus, which passes events between subsystems that don't know about
+// each other. Any type can be an event, and events are kept in a separate queue for each type, so
+// consumers only see the types they ask for. Events are double buffered: what is published during
+// a frame waits in a pending queue, and update() moves it to the current queue once per frame and
+// hands it to the subscribers. Everyone sees the same events for the whole frame no matter when
+// they look, and nothing is changed out from under a consumer that is halfway through reading.
+//
+// Consumers either subscribe a handler for a type, which is called for each event by update(), or
+// read the current events of a type whenever they like. Handlers can publish events of their own
+// through the EventSender they are given, and those are delivered on the next frame.
+//
+// The engine's own events are published as their usual types, such as the window's Events, the
+// GamepadEvents from Gamepads, and the CollisionEvents from a PhysicsWorld, along with the
+// AssetLoaded event defined here.
+//
+// Usage of an EventBus:
+// - Create it with new() and subscribe() handlers for the types they care about.
+// - Publish events from anywhere with publish(), or a frame's worth at once with publish_all().
+// - Call update() once per frame, and read() the types that aren't handled by a subscriber.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+// An event for when an asset has finished loading, given by its path.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AssetLoaded {
+    pub path: String,
+}
+
+// A handle to a subscribed handler that can be used to unsubscribe it.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Subscription {
+    type_id: TypeId,
+    id: usize,
+}
+
+// Collects the events published by handlers while the bus is busy calling them.
+pub struct EventSender {
+    events: Vec<(TypeId, Box<Any>, fn() -> Box<AnyQueue>)>,
+}
+
+impl EventSender {
+    // Publishes an event to be delivered on the next frame.
+    pub fn publish<T: Any>(&mut self, event: T) {
+        self.events.push((TypeId::of::<T>(), Box::new(event), new_queue::<T>));
+    }
+}
+
+// The queues of events of a single type along with the handlers subscribed to them.
+struct Queue<T> {
+    pending: Vec<T>,
+    current: Vec<T>,
+    handlers: Vec<(usize, Box<FnMut(&T, &mut EventSender)>)>,
+}
+
+// The part of a Queue that doesn't depend on its type, so that queues of every type can be kept
+// together.
+trait AnyQueue {
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+    fn push_any(&mut self, event: Box<Any>);
+    fn swap(&mut self);
+    fn dispatch(&mut self, sender: &mut EventSender);
+    fn unsubscribe(&mut self, id: usize) -> bool;
+    fn clear(&mut self);
+}
+
+impl<T: Any> AnyQueue for Queue<T> {
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+
+    fn push_any(&mut self, event: Box<Any>) {
+        if let Ok(event) = event.downcast::<T>() {
+            self.pending.push(*event);
+        }
+    }
+
+    fn swap(&mut self) {
+        self.current.clear();
+        self.current.extend(self.pending.drain(..));
+    }
+
+    fn dispatch(&mut self, sender: &mut EventSender) {
+        for event in &self.current {
+            for &mut (_, ref mut handler) in &mut self.handlers {
+                handler(event, sender);
+            }
+        }
+    }
+
+    fn unsubscribe(&mut self, id: usize) -> bool {
+        let len = self.handlers.len();
+        self.handlers.retain(|&(i, _)| i != id);
+        self.handlers.len() != len
+    }
+
+    fn clear(&mut self) {
+        self.pending.clear();
+        self.current.clear();
+    }
+}
+
+// Creates an empty queue for a type.
+fn new_queue<T: Any>() -> Box<AnyQueue> {
+    Box::new(Queue::<T> { pending: Vec::new(), current: Vec::new(), handlers: Vec::new() })
+}
+
+// The queues of every type of event that has been published or subscribed to. Queues are kept in
+// the order that their types were first seen, so handlers are always called in the same order.
+pub struct EventBus {
+    queues: Vec<Box<AnyQueue>>,
+    indices: HashMap<TypeId, usize>,
+    next_id: usize,
+}
+
+impl EventBus {
+    // Creates an EventBus without any events or subscribers.
+    pub fn new() -> EventBus {
+        EventBus { queues: Vec::new(), indices: HashMap::new(), next_id: 0 }
+    }
+
+    // Publishes an event to be delivered on the next update.
+    pub fn publish<T: Any>(&mut self, event: T) {
+        self.get_queue_mut::<T>().pending.push(event);
+    }
+
+    // Publishes every event from an iterator, such as the events returned by Input::update().
+    pub fn publish_all<T: Any, I: IntoIterator<Item = T>>(&mut self, events: I) {
+        self.get_queue_mut::<T>().pending.extend(events);
+    }
+
+    // Subscribes a handler to the events of a type, which is called for each of them by update().
+    pub fn subscribe<T: Any, F>(&mut self, handler: F) -> Subscription
+            where F: FnMut(&T, &mut EventSender) + 'static {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.get_queue_mut::<T>().handlers.push((id, Box::new(handler)));
+        Subscription { type_id: TypeId::of::<T>(), id: id }
+    }
+
+    // Unsubscribes a handler. Returns false if it was already unsubscribed.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        match self.indices.get(&subscription.type_id) {
+            Some(&index) => self.queues[index].unsubscribe(subscription.id),
+            None => false,
+        }
+    }
+
+    // Starts a new frame by making the pending events current and calling the handlers for them.
+    // The events published by the handlers are pending for the next frame.
+    pub fn update(&mut self) {
+        let mut sender = EventSender { events: Vec::new() };
+        for queue in &mut self.queues {
+            queue.swap();
+        }
+        for queue in &mut self.queues {
+            queue.dispatch(&mut sender);
+        }
+        for (type_id, event, create) in sender.events {
+            let index = match self.indices.get(&type_id) {
+                Some(&index) => index,
+                None => {
+                    self.queues.push(create());
+                    self.queues.len() - 1
+                },
+            };
+            self.indices.insert(type_id, index);
+            self.queues[index].push_any(event);
+        }
+    }
+
+    // Gets the current events of a type, which are the ones published before the last update.
+    pub fn read<T: Any>(&self) -> &[T] {
+        let queue = self.indices.get(&TypeId::of::<T>())
+                .and_then(|&i| self.queues[i].as_any().downcast_ref::<Queue<T>>());
+        match queue {
+            Some(queue) => &queue.current,
+            None => &[],
+        }
+    }
+
+    // Gets the number of events of a type waiting for the next update.
+    pub fn get_pending_count<T: Any>(&self) -> usize {
+        self.indices.get(&TypeId::of::<T>())
+                .and_then(|&i| self.queues[i].as_any().downcast_ref::<Queue<T>>())
+                .map_or(0, |q| q.pending.len())
+    }
+
+    // Drops every pending and current event while keeping the subscribers.
+    pub fn clear(&mut self) {
+        for queue in &mut self.queues {
+            queue.clear();
+        }
+    }
+
+    // Gets the queue of a type, creating it if it doesn't exist yet.
+    fn get_queue_mut<T: Any>(&mut self) -> &mut Queue<T> {
+        let type_id = TypeId::of::<T>();
+        let index = match self.indices.get(&type_id) {
+            Some(&index) => index,
+            None => {
+                self.queues.push(new_queue::<T>());
+                self.queues.len() - 1
+            },
+        };
+        self.indices.insert(type_id, index);
+        self.queues[index].as_any_mut().downcast_mut::<Queue<T>>().unwrap()
+    }
+}
@@ -0,0 +1,173 @@
+// Utility module that allows for decoding of a bitmap font description given a path to the file.
+// This is only implemented for the text variant of the AngelCode BMFont format, which is what most
+// bitmap font generators export. The glyph images themselves are stored in separate page images
+// whose paths are resolved relative to the font description.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// The location of a single character in a page image along with how it is placed relative to the
+// pen. Offsets are measured from the top left of the line in pixels.
+pub struct FontChar {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub x_advance: i32,
+    pub page: usize,
+}
+
+// Return value for a decoded bitmap font. The line height is the distance between lines and the
+// base is the distance from the top of a line to the baseline, both in pixels.
+pub struct DecodedFNT {
+    pub size: i32,
+    pub line_height: u32,
+    pub base: u32,
+    pub pages: Vec<String>,
+    pub chars: HashMap<char, FontChar>,
+    pub kernings: HashMap<(char, char), i32>,
+}
+
+// Splits a line of the form `tag key=value key="quoted value"` into its tag and key value pairs.
+fn parse_line(line: &str) -> (String, HashMap<String, String>) {
+    let mut pairs = HashMap::new();
+    let mut tag = String::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() { break; }
+        tag.push(c);
+        chars.next();
+    }
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) { chars.next(); }
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c.is_whitespace() { break; }
+            key.push(c);
+            chars.next();
+        }
+        if key.is_empty() { break; }
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '"' { break; }
+                    value.push(c);
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() { break; }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        }
+        pairs.insert(key, value);
+    }
+    (tag, pairs)
+}
+
+// Gets an integer value of a key on a line.
+fn get_int(pairs: &HashMap<String, String>, key: &str) -> Result<i32, String> {
+    match pairs.get(key) {
+        Some(value) => value.parse::<i32>().map_err(|_| format!("Invalid value for {}.", key)),
+        None => Err(format!("Missing key {} in FNT file.", key)),
+    }
+}
+
+// Gets an unsigned integer value of a key on a line.
+fn get_uint(pairs: &HashMap<String, String>, key: &str) -> Result<u32, String> {
+    let value = try!(get_int(pairs, key));
+    if value < 0 {
+        return Err(format!("Negative value for {}.", key));
+    }
+    Ok(value as u32)
+}
+
+// Converts a character id into a char.
+fn get_char(pairs: &HashMap<String, String>, key: &str) -> Result<char, String> {
+    let id = try!(get_uint(pairs, key));
+    match ::std::char::from_u32(id) {
+        Some(c) => Ok(c),
+        None => Err(format!("Invalid character id {}.", id)),
+    }
+}
+
+// Decodes a text BMFont file given a path.
+pub fn decode_fnt(fpath: &str) -> Result<DecodedFNT, String> {
+    let mut file = match File::open(fpath) {
+        Ok(f) => f,
+        Err(_) => { return Err("Could not open FNT file.".to_string()); },
+    };
+    let mut contents = String::new();
+    if file.read_to_string(&mut contents).is_err() {
+        return Err("Could not read FNT file as text.".to_string());
+    }
+    let dir = Path::new(fpath).parent().unwrap_or(Path::new(""));
+
+    let mut font = DecodedFNT { size: 0, line_height: 0, base: 0, pages: Vec::new(),
+            chars: HashMap::new(), kernings: HashMap::new() };
+    let mut has_common = false;
+    for line in contents.lines() {
+        let (tag, pairs) = parse_line(line);
+        match tag.as_ref() {
+            "info" => { font.size = try!(get_int(&pairs, "size")).abs(); },
+            "common" => {
+                font.line_height = try!(get_uint(&pairs, "lineHeight"));
+                font.base = try!(get_uint(&pairs, "base"));
+                if pairs.get("packed").map_or(false, |p| p != "0") {
+                    return Err("Packed FNT channels are not supported.".to_string());
+                }
+                has_common = true;
+            },
+            "page" => {
+                let id = try!(get_uint(&pairs, "id")) as usize;
+                let file = match pairs.get("file") {
+                    Some(f) => f,
+                    None => { return Err("Missing file for FNT page.".to_string()); },
+                };
+                let path = dir.join(file).to_string_lossy().into_owned();
+                if font.pages.len() <= id {
+                    font.pages.resize(id + 1, String::new());
+                }
+                font.pages[id] = path;
+            },
+            "char" => {
+                let c = try!(get_char(&pairs, "id"));
+                font.chars.insert(c, FontChar {
+                        x: try!(get_uint(&pairs, "x")), y: try!(get_uint(&pairs, "y")),
+                        width: try!(get_uint(&pairs, "width")),
+                        height: try!(get_uint(&pairs, "height")),
+                        x_offset: try!(get_int(&pairs, "xoffset")),
+                        y_offset: try!(get_int(&pairs, "yoffset")),
+                        x_advance: try!(get_int(&pairs, "xadvance")),
+                        page: try!(get_uint(&pairs, "page")) as usize });
+            },
+            "kerning" => {
+                let first = try!(get_char(&pairs, "first"));
+                let second = try!(get_char(&pairs, "second"));
+                font.kernings.insert((first, second), try!(get_int(&pairs, "amount")));
+            },
+            _ => {},
+        }
+    }
+    if !has_common {
+        return Err("FNT file is missing the common line.".to_string());
+    }
+    for (_, c) in &font.chars {
+        if c.page >= font.pages.len() || font.pages[c.page].is_empty() {
+            return Err(format!("FNT character references missing page {}.", c.page));
+        }
+    }
+    Ok(font)
+}
@@ -0,0 +1,580 @@
+// Utility module that allows for decoding of the meshes of a glTF 2.0 file given a path to the
+// file. Both .gltf files, with their buffers in separate files or embedded as base64 data URIs,
+// and binary .glb files are supported. Every triangle primitive reachable from the default scene
+// is transformed by its node's world transform and merged into a single mesh, which is then
+// rotated from glTF's Y up to the engine's Z up the same way OBJ files are.
+//
+// Only positions, normals, and the first set of texture coordinates are read. Missing normals are
+// generated from the faces, and tangents and bitangents are always generated from the texture
+// coordinates like the OBJ decoder does so that normal maps behave the same for both. Materials,
+// skins, animations, morph targets, and sparse accessors are not supported.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::*;
+use self::gl::types::*;
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use util::common;
+use util::json::{self, Value};
+
+// The result of a glTF decoding. This holds information about the vertices and elements.
+pub struct DecodedGLTF {
+    pub vertices: Vec<common::Vertex>,
+    pub elements: Vec<u32>,
+}
+
+// The magic number and chunk types of a .glb file.
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BIN_CHUNK: u32 = 0x004E4942;
+
+// The glTF component types of accessors.
+const COMPONENT_BYTE: u64 = 5120;
+const COMPONENT_UNSIGNED_BYTE: u64 = 5121;
+const COMPONENT_SHORT: u64 = 5122;
+const COMPONENT_UNSIGNED_SHORT: u64 = 5123;
+const COMPONENT_UNSIGNED_INT: u64 = 5125;
+const COMPONENT_FLOAT: u64 = 5126;
+
+// The primitive mode for triangle lists.
+const MODE_TRIANGLES: u64 = 4;
+
+// The region of a buffer that an accessor reads from.
+struct Accessor<'a> {
+    data: &'a [u8],
+    count: usize,
+    components: usize,
+    component_type: u64,
+    normalized: bool,
+    stride: usize,
+}
+
+// Gets a field of a JSON object as an index.
+fn get_index(value: &Value, key: &str) -> Option<usize> {
+    value.get(key).and_then(|v| v.as_f64()).map(|v| v as usize)
+}
+
+// Gets an element of a top level array of the document, such as a mesh or an accessor.
+fn get_item<'a>(doc: &'a Value, key: &str, index: usize) -> Result<&'a Value, String> {
+    doc.get(key).and_then(|v| v.as_array()).and_then(|a| a.get(index))
+            .ok_or(format!("glTF file has no {} {}.", key, index))
+}
+
+// Reads a little endian u32 at an offset.
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    if offset + 4 > data.len() {
+        return Err("glTF file is too small.".to_string());
+    }
+    Ok(data[offset] as u32 | (data[offset + 1] as u32) << 8 | (data[offset + 2] as u32) << 16 |
+            (data[offset + 3] as u32) << 24)
+}
+
+// Decodes standard base64, ignoring padding and whitespace.
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c {
+            _ if c >= b'A' && c <= b'Z' => c - b'A',
+            _ if c >= b'a' && c <= b'z' => c - b'a' + 26,
+            _ if c >= b'0' && c <= b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' | b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err("glTF data URI has invalid base64.".to_string()),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Ok(out)
+}
+
+// Reads the JSON document of a .gltf or .glb file along with the binary chunk of a .glb file.
+fn read_document(fpath: &str) -> Result<(Value, Option<Vec<u8>>), String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+    if data.len() < 12 || try!(read_u32(&data, 0)) != GLB_MAGIC {
+        let text = try!(String::from_utf8(data).map_err(|e| e.to_string()));
+        return Ok((try!(json::parse(&text)), None));
+    }
+    if try!(read_u32(&data, 4)) != 2 {
+        return Err("Only glTF 2.0 files are supported.".to_string());
+    }
+    let mut cursor = 12;
+    let (mut doc, mut bin) = (None, None);
+    while cursor + 8 <= data.len() {
+        let length = try!(read_u32(&data, cursor)) as usize;
+        let kind = try!(read_u32(&data, cursor + 4));
+        if cursor + 8 + length > data.len() {
+            return Err("glTF file is too small.".to_string());
+        }
+        let chunk = &data[(cursor + 8)..(cursor + 8 + length)];
+        if kind == GLB_JSON_CHUNK && doc.is_none() {
+            let text = try!(String::from_utf8(chunk.to_vec()).map_err(|e| e.to_string()));
+            doc = Some(try!(json::parse(&text)));
+        } else if kind == GLB_BIN_CHUNK && bin.is_none() {
+            bin = Some(chunk.to_vec());
+        }
+        cursor += 8 + length;
+    }
+    match doc {
+        Some(doc) => Ok((doc, bin)),
+        None => Err("glTF binary file has no JSON chunk.".to_string()),
+    }
+}
+
+// Loads every buffer of a document. Buffers without a URI are the binary chunk of a .glb file.
+fn read_buffers(doc: &Value, fpath: &str, mut bin: Option<Vec<u8>>)
+        -> Result<Vec<Vec<u8>>, String> {
+    let mut buffers = Vec::new();
+    let empty = Vec::new();
+    for buffer in doc.get("buffers").and_then(|b| b.as_array()).unwrap_or(&empty) {
+        let data = match buffer.get("uri").and_then(|u| u.as_str()) {
+            Some(uri) if uri.starts_with("data:") => match uri.find(";base64,") {
+                Some(start) => try!(decode_base64(&uri[(start + 8)..])),
+                None => return Err("glTF data URIs must be base64.".to_string()),
+            },
+            Some(uri) => {
+                let path = Path::new(fpath).with_file_name(uri.replace("%20", " "));
+                let mut data = Vec::new();
+                let mut fd = try!(File::open(&path).map_err(|e| format!("{}: {}", uri, e)));
+                try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+                data
+            },
+            None => match bin.take() {
+                Some(data) => data,
+                None => return Err("glTF buffer has no data.".to_string()),
+            },
+        };
+        if data.len() < get_index(buffer, "byteLength").unwrap_or(0) {
+            return Err("glTF buffer is smaller than its byte length.".to_string());
+        }
+        buffers.push(data);
+    }
+    Ok(buffers)
+}
+
+// Gets the region of a buffer that an accessor reads from.
+fn get_accessor<'a>(doc: &Value, buffers: &'a [Vec<u8>], index: usize)
+        -> Result<Accessor<'a>, String> {
+    let accessor = try!(get_item(doc, "accessors", index));
+    if accessor.get("sparse").is_some() {
+        return Err("Sparse glTF accessors are not supported.".to_string());
+    }
+    let count = get_index(accessor, "count").unwrap_or(0);
+    let components = match accessor.get("type").and_then(|t| t.as_str()) {
+        Some("SCALAR") => 1,
+        Some("VEC2") => 2,
+        Some("VEC3") => 3,
+        Some("VEC4") => 4,
+        _ => return Err("Unsupported glTF accessor type.".to_string()),
+    };
+    let component_type = accessor.get("componentType").and_then(|t| t.as_f64()).unwrap_or(0.0)
+            as u64;
+    let component_size = match component_type {
+        COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => 1,
+        COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => 2,
+        COMPONENT_UNSIGNED_INT | COMPONENT_FLOAT => 4,
+        _ => return Err("Unsupported glTF component type.".to_string()),
+    };
+    let view = match get_index(accessor, "bufferView") {
+        Some(view) => try!(get_item(doc, "bufferViews", view)),
+        None => return Err("glTF accessors without buffer views are not supported.".to_string()),
+    };
+    let buffer = match get_index(view, "buffer").and_then(|b| buffers.get(b)) {
+        Some(buffer) => buffer,
+        None => return Err("glTF buffer view has an invalid buffer.".to_string()),
+    };
+    let element_size = components * component_size;
+    let stride = get_index(view, "byteStride").unwrap_or(element_size);
+    let start = get_index(view, "byteOffset").unwrap_or(0)
+            .checked_add(get_index(accessor, "byteOffset").unwrap_or(0));
+    let size = if count == 0 { Some(0) } else {
+        (count - 1).checked_mul(stride).and_then(|n| n.checked_add(element_size))
+    };
+    let end = match (start, size) { (Some(s), Some(n)) => s.checked_add(n), _ => None };
+    let (start, end) = match (start, end) {
+        (Some(start), Some(end)) if end <= buffer.len() => (start, end),
+        _ => return Err("glTF accessor reads past the end of its buffer.".to_string()),
+    };
+    Ok(Accessor { data: &buffer[start..end], count: count, components: components,
+            component_type: component_type,
+            normalized: accessor.get("normalized").and_then(|n| n.as_bool()).unwrap_or(false),
+            stride: stride })
+}
+
+// Reads a component of an accessor as an integer.
+fn read_integer(accessor: &Accessor, offset: usize) -> u32 {
+    let data = &accessor.data[offset..];
+    match accessor.component_type {
+        COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => data[0] as u32,
+        COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => data[0] as u32 | (data[1] as u32) << 8,
+        _ => data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 |
+                (data[3] as u32) << 24,
+    }
+}
+
+// Reads every element of an accessor as floats, normalizing integers if the accessor asks for it.
+fn read_floats(accessor: &Accessor) -> Vec<Vec<GLfloat>> {
+    let component_size = match accessor.component_type {
+        COMPONENT_BYTE | COMPONENT_UNSIGNED_BYTE => 1,
+        COMPONENT_SHORT | COMPONENT_UNSIGNED_SHORT => 2,
+        _ => 4,
+    };
+    (0..accessor.count).map(|i| (0..accessor.components).map(|c| {
+        let offset = i * accessor.stride + c * component_size;
+        let raw = read_integer(accessor, offset);
+        match accessor.component_type {
+            COMPONENT_FLOAT => f32::from_bits(raw),
+            COMPONENT_BYTE if accessor.normalized => ((raw as u8 as i8) as f32 / 127.0).max(-1.0),
+            COMPONENT_BYTE => (raw as u8 as i8) as f32,
+            COMPONENT_SHORT if accessor.normalized =>
+                ((raw as u16 as i16) as f32 / 32767.0).max(-1.0),
+            COMPONENT_SHORT => (raw as u16 as i16) as f32,
+            COMPONENT_UNSIGNED_BYTE if accessor.normalized => raw as f32 / 255.0,
+            COMPONENT_UNSIGNED_SHORT if accessor.normalized => raw as f32 / 65535.0,
+            _ => raw as f32,
+        }
+    }).collect()).collect()
+}
+
+// Reads an attribute of a primitive as floats, checking that each element has the number of
+// components that the attribute needs.
+fn read_attribute(doc: &Value, buffers: &[Vec<u8>], index: usize, name: &str, components: usize)
+        -> Result<Vec<Vec<GLfloat>>, String> {
+    let accessor = try!(get_accessor(doc, buffers, index));
+    if accessor.components != components {
+        return Err(format!("glTF {} accessor must have {} components.", name, components));
+    }
+    Ok(read_floats(&accessor))
+}
+
+// Reads every element of a scalar accessor as an index.
+fn read_indices(accessor: &Accessor) -> Result<Vec<u32>, String> {
+    if accessor.components != 1 {
+        return Err("glTF indices must be scalars.".to_string());
+    }
+    match accessor.component_type {
+        COMPONENT_UNSIGNED_BYTE | COMPONENT_UNSIGNED_SHORT | COMPONENT_UNSIGNED_INT => (),
+        _ => return Err("glTF indices must be unsigned integers.".to_string()),
+    }
+    Ok((0..accessor.count).map(|i| read_integer(accessor, i * accessor.stride)).collect())
+}
+
+// Gets the local transform of a node from its matrix or its translation, rotation, and scale.
+fn get_local_transform(node: &Value) -> Matrix4<GLfloat> {
+    let numbers = |key: &str| -> Option<Vec<GLfloat>> {
+        node.get(key).and_then(|v| v.as_array())
+                .map(|a| a.iter().map(|n| n.as_f64().unwrap_or(0.0) as GLfloat).collect())
+    };
+    if let Some(m) = numbers("matrix") {
+        if m.len() == 16 {
+            return Matrix4::new(m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10],
+                    m[11], m[12], m[13], m[14], m[15]);
+        }
+    }
+    let t = numbers("translation").unwrap_or(vec![0.0, 0.0, 0.0]);
+    let r = numbers("rotation").unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+    let s = numbers("scale").unwrap_or(vec![1.0, 1.0, 1.0]);
+    if t.len() != 3 || r.len() != 4 || s.len() != 3 {
+        return Matrix4::identity();
+    }
+    Matrix4::from_translation(Vector3::new(t[0], t[1], t[2])) *
+            Matrix4::from(Quaternion::new(r[3], r[0], r[1], r[2]).normalize()) *
+            Matrix4::from_nonuniform_scale(s[0], s[1], s[2])
+}
+
+// Converts a vector from glTF's Y up axes to the engine's Z up axes.
+fn to_engine_axes(v: Vector3<GLfloat>) -> Vector3<GLfloat> {
+    Vector3::new(v.z, v.x, v.y)
+}
+
+// Adds the triangles of a mesh to the output, transformed by a world transform.
+fn add_mesh(doc: &Value, buffers: &[Vec<u8>], mesh: usize, world: &Matrix4<GLfloat>,
+        out: &mut DecodedGLTF) -> Result<(), String> {
+    let mesh = try!(get_item(doc, "meshes", mesh));
+    let linear = Matrix3::new(world.x.x, world.x.y, world.x.z, world.y.x, world.y.y, world.y.z,
+            world.z.x, world.z.y, world.z.z);
+    let normal_matrix = linear.invert().map(|m| m.transpose()).unwrap_or(linear);
+    let flipped = linear.determinant() < 0.0;
+    let empty = Vec::new();
+    for primitive in mesh.get("primitives").and_then(|p| p.as_array()).unwrap_or(&empty) {
+        let mode = primitive.get("mode").and_then(|m| m.as_f64()).unwrap_or(4.0) as u64;
+        if mode != MODE_TRIANGLES {
+            return Err("Only glTF triangle lists are supported.".to_string());
+        }
+        let attributes = match primitive.get("attributes") {
+            Some(attributes) => attributes,
+            None => return Err("glTF primitive has no attributes.".to_string()),
+        };
+        let positions = match get_index(attributes, "POSITION") {
+            Some(index) => try!(read_attribute(doc, buffers, index, "POSITION", 3)),
+            None => return Err("glTF primitive has no positions.".to_string()),
+        };
+        let normals = match get_index(attributes, "NORMAL") {
+            Some(index) => Some(try!(read_attribute(doc, buffers, index, "NORMAL", 3))),
+            None => None,
+        };
+        let tcoords = match get_index(attributes, "TEXCOORD_0") {
+            Some(index) => Some(try!(read_attribute(doc, buffers, index, "TEXCOORD_0", 2))),
+            None => None,
+        };
+        let mut indices = match get_index(primitive, "indices") {
+            Some(index) => try!(read_indices(&try!(get_accessor(doc, buffers, index)))),
+            None => (0..(positions.len() as u32)).collect(),
+        };
+        if indices.len() % 3 != 0 || indices.iter().any(|&i| i as usize >= positions.len()) {
+            return Err("glTF primitive has invalid indices.".to_string());
+        }
+        if flipped {
+            for triangle in indices.chunks_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let base = out.vertices.len() as u32;
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        for (i, p) in positions.iter().enumerate() {
+            let position = world * Vector4::new(p[0], p[1], p[2], 1.0);
+            let normal = normals.as_ref().and_then(|n| n.get(i)).map_or(zero, |n| {
+                to_engine_axes((normal_matrix * Vector3::new(n[0], n[1], n[2])).normalize())
+            });
+            let tc = tcoords.as_ref().and_then(|t| t.get(i))
+                    .map_or(Vector2::new(0.0, 0.0), |t| Vector2::new(t[0], t[1]));
+            out.vertices.push(common::Vertex { pos: to_engine_axes(position.truncate()),
+                    norm: normal, tc: tc, tangent: zero, bitangent: zero });
+        }
+        let start = out.elements.len();
+        out.elements.extend(indices.iter().map(|&i| base + i));
+        if normals.is_none() {
+            generate_normals(&mut out.vertices, &out.elements[start..]);
+        }
+    }
+    Ok(())
+}
+
+// Generates normals for the vertices used by some triangles from the area weighted normals of the
+// faces around them.
+fn generate_normals(vertices: &mut [common::Vertex], elements: &[u32]) {
+    for triangle in elements.chunks(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let normal = (vertices[b].pos - vertices[a].pos).cross(vertices[c].pos - vertices[a].pos);
+        for &i in &[a, b, c] {
+            vertices[i].norm = vertices[i].norm + normal;
+        }
+    }
+    let first = elements.iter().fold(vertices.len(), |m, &i| cmp::min(m, i as usize));
+    for vertex in &mut vertices[first..] {
+        if vertex.norm.length2() > 0.0 {
+            vertex.norm = vertex.norm.normalize();
+        }
+    }
+}
+
+// Generates the tangents and bitangents of every vertex from the texture coordinates of the faces
+// around it, weighted by area like the OBJ decoder. Vertices without usable texture coordinates
+// get an arbitrary tangent perpendicular to their normal.
+fn generate_tangents(vertices: &mut [common::Vertex], elements: &[u32]) {
+    let zero = Vector3::new(0.0, 0.0, 0.0);
+    let mut sums = vec![(zero, zero); vertices.len()];
+    for triangle in elements.chunks(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let e1 = vertices[b].pos - vertices[a].pos;
+        let e2 = vertices[c].pos - vertices[a].pos;
+        let duv1 = vertices[b].tc - vertices[a].tc;
+        let duv2 = vertices[c].tc - vertices[a].tc;
+        let denominator = duv1.x * duv2.y - duv1.y * duv2.x;
+        if denominator.abs() < 1e-12 { continue; }
+        let tangent = ((e1 * duv2.y - e2 * duv1.y) / denominator).normalize();
+        let bitangent = ((e2 * duv1.x - e1 * duv2.x) / denominator).normalize();
+        let area = e1.cross(e2).length() * 0.5;
+        for &i in &[a, b, c] {
+            sums[i].0 = sums[i].0 + tangent * area;
+            sums[i].1 = sums[i].1 + bitangent * area;
+        }
+    }
+    for (vertex, &(tangent, bitangent)) in vertices.iter_mut().zip(sums.iter()) {
+        if tangent.length2() > 0.0 && bitangent.length2() > 0.0 {
+            vertex.tangent = tangent.normalize();
+            vertex.bitangent = bitangent.normalize();
+        } else {
+            let n = vertex.norm;
+            let other = if n.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+            vertex.tangent = n.cross(other).normalize();
+            vertex.bitangent = n.cross(vertex.tangent);
+        }
+    }
+}
+
+// Adds the meshes of a node and its children to the output.
+fn add_node(doc: &Value, buffers: &[Vec<u8>], node: usize, parent: &Matrix4<GLfloat>,
+        depth: usize, out: &mut DecodedGLTF) -> Result<(), String> {
+    let node_value = try!(get_item(doc, "nodes", node));
+    if depth > doc.get("nodes").and_then(|n| n.as_array()).map_or(0, |n| n.len()) {
+        return Err("glTF node hierarchy has a cycle.".to_string());
+    }
+    let world = parent * get_local_transform(node_value);
+    if let Some(mesh) = get_index(node_value, "mesh") {
+        try!(add_mesh(doc, buffers, mesh, &world, out));
+    }
+    let empty = Vec::new();
+    for child in node_value.get("children").and_then(|c| c.as_array()).unwrap_or(&empty) {
+        let child = child.as_f64().unwrap_or(-1.0);
+        if child < 0.0 { return Err("glTF node has an invalid child.".to_string()); }
+        try!(add_node(doc, buffers, child as usize, &world, depth + 1, out));
+    }
+    Ok(())
+}
+
+// Decodes a glTF or GLB file given a path to the file and returns a DecodedGLTF struct containing
+// the vertices and triangles of every mesh in its default scene. Files without scenes have every
+// mesh decoded without a transform.
+pub fn decode_gltf(fpath: &str) -> Result<DecodedGLTF, String> {
+    let (doc, bin) = try!(read_document(fpath));
+    let version = doc.get("asset").and_then(|a| a.get("version")).and_then(|v| v.as_str());
+    if !version.map_or(false, |v| v.starts_with("2.")) {
+        return Err("Only glTF 2.0 files are supported.".to_string());
+    }
+    let buffers = try!(read_buffers(&doc, fpath, bin));
+    let mut out = DecodedGLTF { vertices: Vec::new(), elements: Vec::new() };
+    let identity = Matrix4::identity();
+    let scene = get_index(&doc, "scene").unwrap_or(0);
+    match doc.get("scenes").and_then(|s| s.as_array()).and_then(|s| s.get(scene)) {
+        Some(scene) => {
+            let empty = Vec::new();
+            for node in scene.get("nodes").and_then(|n| n.as_array()).unwrap_or(&empty) {
+                let node = node.as_f64().unwrap_or(-1.0);
+                if node < 0.0 { return Err("glTF scene has an invalid node.".to_string()); }
+                try!(add_node(&doc, &buffers, node as usize, &identity, 0, &mut out));
+            }
+        },
+        None => {
+            let count = doc.get("meshes").and_then(|m| m.as_array()).map_or(0, |m| m.len());
+            for mesh in 0..count {
+                try!(add_mesh(&doc, &buffers, mesh, &identity, &mut out));
+            }
+        },
+    }
+    if out.elements.is_empty() {
+        return Err("glTF file has no triangles.".to_string());
+    }
+    let elements = out.elements.clone();
+    generate_tangents(&mut out.vertices, &elements);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // A buffer with the positions of a single triangle as base64.
+    static TRIANGLE: &'static str = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA";
+
+    // Builds a document with one mesh whose positions are read by an accessor.
+    fn document(uri: Option<&str>, accessor: &str) -> String {
+        let uri = uri.map_or(String::new(), |uri| format!("\"uri\": \"{}\", ", uri));
+        format!("{{\"asset\": {{\"version\": \"2.0\"}}, \"buffers\": [{{{}\"byteLength\": 36}}], \
+                \"bufferViews\": [{{\"buffer\": 0, \"byteLength\": 36}}], \"accessors\": [{}], \
+                \"meshes\": [{{\"primitives\": [{{\"attributes\": {{\"POSITION\": 0}}}}]}}]}}",
+                uri, accessor)
+    }
+
+    // An accessor for the three positions of the triangle.
+    static POSITIONS: &'static str =
+            "{\"bufferView\": 0, \"componentType\": 5126, \"count\": 3, \"type\": \"VEC3\"}";
+
+    // Builds a binary file from a document and the contents of its binary chunk.
+    fn glb_file(doc: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = doc.as_bytes().to_vec();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut words = vec![GLB_MAGIC, 2, (12 + 8 + json.len() + 8 + bin.len()) as u32];
+        words.extend_from_slice(&[json.len() as u32, GLB_JSON_CHUNK]);
+        let mut data = Vec::new();
+        let write_words = |data: &mut Vec<u8>, words: &[u32]| for value in words {
+            data.extend_from_slice(&[*value as u8, (*value >> 8) as u8, (*value >> 16) as u8,
+                    (*value >> 24) as u8]);
+        };
+        write_words(&mut data, &words);
+        data.extend_from_slice(&json);
+        write_words(&mut data, &[bin.len() as u32, GLB_BIN_CHUNK]);
+        data.extend_from_slice(bin);
+        data
+    }
+
+    #[test]
+    fn decodes_embedded_buffers() {
+        let uri = format!("data:application/octet-stream;base64,{}", TRIANGLE);
+        let doc = document(Some(&uri), POSITIONS);
+        let path = common::write_test_file("embedded.gltf", doc.as_bytes());
+        let decoded = decode_gltf(&path).unwrap();
+        assert_eq!(decoded.elements, vec![0, 1, 2]);
+        // The positions are rotated from Y up to Z up.
+        assert_eq!(decoded.vertices[1].pos, Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(decoded.vertices[2].pos, Vector3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn decodes_binary_files() {
+        let data = glb_file(&document(None, POSITIONS), &decode_base64(TRIANGLE).unwrap());
+        let path = common::write_test_file("triangle.glb", &data);
+        let decoded = decode_gltf(&path).unwrap();
+        assert_eq!(decoded.vertices.len(), 3);
+        assert_eq!(decoded.vertices[1].pos, Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = glb_file(&document(None, POSITIONS), &decode_base64(TRIANGLE).unwrap());
+        let path = common::write_test_file("truncated.glb", &data[..(data.len() - 8)]);
+        assert!(decode_gltf(&path).is_err());
+        let path = common::write_test_file("truncated-header.glb", &data[..14]);
+        assert!(decode_gltf(&path).is_err());
+        let doc = document(Some("data:;base64,AAAA"), POSITIONS);
+        let path = common::write_test_file("truncated.gltf", doc.as_bytes());
+        assert!(decode_gltf(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_component_counts() {
+        let accessor = POSITIONS.replace("VEC3", "VEC2");
+        let data = glb_file(&document(None, &accessor), &decode_base64(TRIANGLE).unwrap());
+        let path = common::write_test_file("components.glb", &data);
+        assert!(decode_gltf(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_meshes() {
+        let accessor = POSITIONS.replace("\"count\": 3", "\"count\": 0");
+        let data = glb_file(&document(None, &accessor), &decode_base64(TRIANGLE).unwrap());
+        let path = common::write_test_file("empty.glb", &data);
+        assert!(decode_gltf(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_accessors() {
+        // Counts and offsets this large overflow when the end of the accessor is worked out.
+        let bin = decode_base64(TRIANGLE).unwrap();
+        for accessor in [POSITIONS.replace("\"count\": 3", "\"count\": 1e30"),
+                POSITIONS.replace("\"count\": 3", "\"count\": 4"),
+                POSITIONS.replace("\"count\"", "\"byteOffset\": 1e30, \"count\"")].iter() {
+            let path = common::write_test_file("oversized.glb", &glb_file(&document(None,
+                    accessor), &bin));
+            assert!(decode_gltf(&path).is_err());
+        }
+    }
+}
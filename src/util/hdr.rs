@@ -0,0 +1,231 @@
+// Utility module that allows for decoding of a Radiance HDR (.hdr) file given a path to the file.
+// This only supports the 32-bit RGBE format with the standard "-Y height +X width" orientation,
+// stored either flat or with the newer per-channel run length encoding. This is the format output
+// by most HDR tools and the environment maps floating around on the internet.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::Vector3;
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+use util::common;
+
+// Return value for a decoded HDR file. This contains an image with linear floating point RGB data.
+pub struct DecodedHDR {
+    pub image: common::HDRImage,
+}
+
+// Every Radiance file starts with these two characters followed by the program name.
+static RADIANCE_MAGIC: &'static str = "#?";
+
+// The only pixel format supported by the decoder.
+static RGBE_FORMAT: &'static str = "FORMAT=32-bit_rle_rgbe";
+
+// Reads a single newline terminated ASCII line from the data vector and advances the cursor past
+// the newline.
+fn read_line(data: &Vec<u8>, cursor: &mut usize) -> Result<String, String> {
+    let orig = *cursor;
+    while *cursor < data.len() && data[*cursor] != ('\n' as u8) {
+        *cursor += 1;
+    }
+    if *cursor >= data.len() {
+        return Err("HDR file header is truncated.".to_string());
+    }
+    let line = String::from_utf8_lossy(&data[orig..*cursor]).into_owned();
+    *cursor += 1;
+    Ok(line)
+}
+
+// Reads a single byte from the data vector and advances the cursor.
+fn read_byte(data: &Vec<u8>, cursor: &mut usize) -> Result<u8, String> {
+    if *cursor >= data.len() {
+        return Err("HDR file is too small.".to_string());
+    }
+    *cursor += 1;
+    Ok(data[*cursor - 1])
+}
+
+// Reads and validates the header lines up to and including the resolution string and returns the
+// width and height of the image.
+fn read_header(data: &Vec<u8>, cursor: &mut usize) -> Result<(u32, u32), String> {
+    let magic = try!(read_line(data, cursor));
+    if !magic.starts_with(RADIANCE_MAGIC) {
+        return Err("HDR file header has incorrect magic values.".to_string());
+    }
+    loop {
+        let line = try!(read_line(data, cursor));
+        if line.is_empty() { break; }
+        if line.starts_with("FORMAT=") && line.trim() != RGBE_FORMAT {
+            return Err("Unsupported HDR pixel format.".to_string());
+        }
+    }
+    let resolution = try!(read_line(data, cursor));
+    let split: Vec<_> = resolution.split_whitespace().collect();
+    if split.len() != 4 || split[0] != "-Y" || split[2] != "+X" {
+        return Err("Unsupported HDR image orientation.".to_string());
+    }
+    let height = try!(u32::from_str(split[1]).map_err(|e| e.to_string()));
+    let width = try!(u32::from_str(split[3]).map_err(|e| e.to_string()));
+    if width == 0 || height == 0 {
+        return Err("HDR image has a zero width or height.".to_string());
+    }
+    Ok((width, height))
+}
+
+// Reads a single scanline of RGBE values into the scanline buffer. This handles both flat
+// scanlines and scanlines using the newer run length encoding where each channel is stored
+// separately.
+fn read_scanline(data: &Vec<u8>, cursor: &mut usize, scanline: &mut Vec<[u8; 4]>)
+        -> Result<(), String> {
+    let width = scanline.len();
+    if width == 0 {
+        return Err("HDR scanline has a zero width.".to_string());
+    }
+    let mut first = [0; 4];
+    for i in 0..4 {
+        first[i] = try!(read_byte(data, cursor));
+    }
+    let rle = first[0] == 2 && first[1] == 2 && (first[2] & 0x80) == 0 &&
+            width >= 8 && width < 0x8000;
+    if !rle {
+        scanline[0] = first;
+        for x in 1..width {
+            for i in 0..4 {
+                scanline[x][i] = try!(read_byte(data, cursor));
+            }
+        }
+        return Ok(());
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err("HDR scanline width does not match the image width.".to_string());
+    }
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = try!(read_byte(data, cursor)) as usize;
+            if count > 128 {
+                let run = count - 128;
+                let value = try!(read_byte(data, cursor));
+                if x + run > width { return Err("HDR run overflows scanline.".to_string()); }
+                for _ in 0..run {
+                    scanline[x][channel] = value;
+                    x += 1;
+                }
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("HDR run overflows scanline.".to_string());
+                }
+                for _ in 0..count {
+                    scanline[x][channel] = try!(read_byte(data, cursor));
+                    x += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Converts a shared exponent RGBE value into linear floating point RGB.
+fn rgbe_to_rgb(rgbe: &[u8; 4]) -> Vector3<f32> {
+    if rgbe[3] == 0 {
+        return Vector3::new(0.0, 0.0, 0.0);
+    }
+    let scale = (2.0 as f32).powi(rgbe[3] as i32 - (128 + 8));
+    Vector3::new(rgbe[0] as f32 * scale, rgbe[1] as f32 * scale, rgbe[2] as f32 * scale)
+}
+
+// Decodes a HDR given a path to the file and returns a DecodedHDR struct containing the linear
+// RGB pixel information, width, and height of the image. The first pixel is the top left corner.
+pub fn decode_hdr(fpath: &str) -> Result<DecodedHDR, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    let mut cursor = 0;
+    let (width, height) = try!(read_header(&data, &mut cursor));
+    // A run of up to 127 values of a channel takes 2 bytes, so no scanline packs more than 16
+    // pixels into a byte. That bounds the memory to set aside before the pixels are read.
+    let max_pixels = (data.len() - cursor).saturating_mul(16);
+    if width as usize > max_pixels {
+        return Err("HDR file is too small.".to_string());
+    }
+    let mut scanline = vec![[0; 4]; width as usize];
+    let count = try!(width.checked_mul(height).ok_or("HDR image is too large.".to_string()));
+    let mut pixels = Vec::with_capacity(cmp::min(count as usize, max_pixels));
+    for _ in 0..height {
+        try!(read_scanline(&data, &mut cursor, &mut scanline));
+        for rgbe in &scanline {
+            pixels.push(rgbe_to_rgb(rgbe));
+        }
+    }
+    let image = common::HDRImage { width: width, height: height, data: pixels };
+    Ok(DecodedHDR { image: image })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Builds a file with the standard header for an image of the given size.
+    fn hdr_file(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+        let mut data = format!("#?RADIANCE\n{}\n\n-Y {} +X {}\n", RGBE_FORMAT, height, width)
+                .into_bytes();
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    #[test]
+    fn decodes_flat_scanlines() {
+        let data = hdr_file(2, 1, &[128, 64, 0, 129, 0, 0, 0, 0]);
+        let path = common::write_test_file("flat.hdr", &data);
+        let image = decode_hdr(&path).unwrap().image;
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.data[0], Vector3::new(1.0, 0.5, 0.0));
+        assert_eq!(image.data[1], Vector3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn decodes_run_length_scanlines() {
+        // Each channel of the 8 pixels is a single run.
+        let data = hdr_file(8, 1, &[2, 2, 0, 8, 136, 128, 136, 64, 136, 0, 136, 129]);
+        let path = common::write_test_file("rle.hdr", &data);
+        let image = decode_hdr(&path).unwrap().image;
+        assert_eq!(image.data.len(), 8);
+        for pixel in &image.data {
+            assert_eq!(*pixel, Vector3::new(1.0, 0.5, 0.0));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_pixels() {
+        let data = hdr_file(2, 2, &[128, 64, 0, 129, 0, 0, 0, 0, 1, 2, 3]);
+        let path = common::write_test_file("truncated.hdr", &data);
+        assert!(decode_hdr(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let path = common::write_test_file("header.hdr", b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe");
+        assert!(decode_hdr(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        let path = common::write_test_file("empty.hdr", &hdr_file(0, 4, &[]));
+        assert!(decode_hdr(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        // The size in the header would need gigabytes if it were trusted for the pixel buffer.
+        let data = hdr_file(60000, 60000, &[2, 2, 0, 8, 136, 128, 136, 64, 136, 0, 136, 129]);
+        let path = common::write_test_file("oversized.hdr", &data);
+        assert!(decode_hdr(&path).is_err());
+    }
+}
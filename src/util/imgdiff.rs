@@ -0,0 +1,102 @@
+// Utility module that compares two images, such as a frame drawn by a HeadlessRenderer against a
+// reference image that is checked in with the tests. Rendering differs slightly between drivers,
+// so every channel of a pixel may be off by a tolerance before the pixel counts as different, and
+// a comparison passes as long as few enough pixels are different. Each comparison also makes a
+// diff image with the different pixels in red over a faded copy of the expected image, which is
+// worth saving when a comparison fails.
+//
+// Usage of imgdiff:
+// - let diff = try!(imgdiff::compare(&expected, &actual, 2));
+// - diff.passes(0.001) is true if at most 0.1% of the pixels are off by more than 2.
+// - imgdiff::compare_to_reference(&actual, "tests/reference/scene.png", 2) loads the expected
+//   image from a BMP, PNG, or TGA file first.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use util::common;
+use util::cook;
+
+// The result of comparing two images of the same size. The differences are the largest difference
+// of any channel of a pixel, so max_difference is the largest one in the image and
+// mean_difference is the average over every pixel.
+pub struct ImageDiff {
+    pub width: u32,
+    pub height: u32,
+    pub tolerance: u8,
+    pub max_difference: u8,
+    pub mean_difference: f32,
+    pub mismatched: usize,
+    pub image: common::Image,
+}
+
+impl ImageDiff {
+    // Gets the fraction of the pixels that are off by more than the tolerance.
+    pub fn get_mismatched_fraction(&self) -> f32 {
+        let count = (self.width * self.height) as f32;
+        if count == 0.0 { 0.0 } else { self.mismatched as f32 / count }
+    }
+
+    // Returns true if at most a fraction of the pixels are off by more than the tolerance.
+    pub fn passes(&self, max_fraction: f32) -> bool {
+        self.get_mismatched_fraction() <= max_fraction
+    }
+
+    // Describes the differences for a test failure message.
+    pub fn describe(&self) -> String {
+        format!("{} of {} pixels ({:.3}%) are off by more than {}, by at most {} and {:.3} on \
+                average.", self.mismatched, self.width * self.height,
+                self.get_mismatched_fraction() * 100.0, self.tolerance, self.max_difference,
+                self.mean_difference)
+    }
+}
+
+// Gets the largest difference of any channel between two pixels.
+fn pixel_difference(a: &common::Pixel, b: &common::Pixel) -> u8 {
+    let channel = |x: u8, y: u8| if x > y { x - y } else { y - x };
+    cmp::max(cmp::max(channel(a.red, b.red), channel(a.green, b.green)),
+            cmp::max(channel(a.blue, b.blue), channel(a.alpha, b.alpha)))
+}
+
+// Compares an image to the image that it is expected to match. Returns an Err if the images aren't
+// the same size.
+pub fn compare(expected: &common::Image, actual: &common::Image, tolerance: u8)
+        -> Result<ImageDiff, String> {
+    if expected.width != actual.width || expected.height != actual.height {
+        return Err(format!("Expected a {}x{} image but got a {}x{} one.", expected.width,
+                expected.height, actual.width, actual.height));
+    }
+    if expected.data.len() != actual.data.len() {
+        return Err("Compared images have an incorrect amount of pixels.".to_string());
+    }
+    let (mut max_difference, mut total, mut mismatched) = (0u8, 0u64, 0usize);
+    let mut data = Vec::with_capacity(expected.data.len());
+    for (a, b) in expected.data.iter().zip(actual.data.iter()) {
+        let difference = pixel_difference(a, b);
+        max_difference = cmp::max(max_difference, difference);
+        total += difference as u64;
+        if difference > tolerance {
+            mismatched += 1;
+            data.push(common::Pixel { red: 255, green: 0, blue: 0, alpha: 255 });
+        } else {
+            let fade = |v: u8| 192 + v / 4;
+            data.push(common::Pixel { red: fade(a.red), green: fade(a.green), blue: fade(a.blue),
+                    alpha: 255 });
+        }
+    }
+    let count = expected.data.len();
+    let mean_difference = if count == 0 { 0.0 } else { total as f32 / count as f32 };
+    Ok(ImageDiff { width: expected.width, height: expected.height, tolerance: tolerance,
+            max_difference: max_difference, mean_difference: mean_difference,
+            mismatched: mismatched,
+            image: common::Image { width: expected.width, height: expected.height, data: data } })
+}
+
+// Compares an image to a reference image in a BMP, PNG, or TGA file.
+pub fn compare_to_reference(actual: &common::Image, path: &str, tolerance: u8)
+        -> Result<ImageDiff, String> {
+    let expected = try!(cook::decode_image(path)
+            .map_err(|e| format!("Unable to load reference image {}: {}", path, e)));
+    compare(&expected, actual, tolerance)
+}
@@ -0,0 +1,285 @@
+// Utility module that decompresses DEFLATE streams (RFC 1951) and the zlib format that wraps them
+// (RFC 1950), which is what PNG files store their pixels in. Stored, fixed Huffman, and dynamic
+// Huffman blocks are all supported. Codes are decoded a bit at a time against the number of codes
+// of each length, which is slower than a table lookup but small and plenty fast for loading
+// assets. The length and distance tables are shared with the compressor in deflate.rs.
+//
+// Brian Ho
+// brian@brkho.com
+
+// The longest Huffman code allowed by DEFLATE.
+const MAX_BITS: usize = 15;
+
+// The number of literal/length and distance codes.
+const MAX_LITERAL_CODES: usize = 288;
+const MAX_DISTANCE_CODES: usize = 30;
+
+// The base lengths and extra bits of the length codes 257 to 285.
+pub static LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35,
+        43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+pub static LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4,
+        4, 4, 4, 5, 5, 5, 5, 0];
+
+// The base distances and extra bits of the distance codes 0 to 29.
+pub static DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+        257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+pub static DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8,
+        9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// The order that the code lengths of the code length code are stored in.
+static CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+        14, 1, 15];
+
+// Reads bits from a byte slice starting from the least significant bit of each byte.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: usize,
+}
+
+impl<'a> BitReader<'a> {
+    // Reads n bits as an unsigned integer with the first bit read as the least significant.
+    fn read_bits(&mut self, n: usize) -> Result<u32, String> {
+        while self.count < n {
+            if self.position >= self.data.len() {
+                return Err("Compressed data ends early.".to_string());
+            }
+            self.buffer |= (self.data[self.position] as u32) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << n) - 1) as u32;
+        self.buffer = if n == 32 { 0 } else { self.buffer >> n };
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Drops the bits left in the current byte.
+    fn align_to_byte(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// A canonical Huffman code given by the number of codes of each length and the symbols sorted by
+// code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // Builds a code from the length of the code of each symbol, where 0 means the symbol is
+    // unused. Incomplete codes are allowed since DEFLATE uses them for single distance codes.
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for length in 1..(MAX_BITS + 1) {
+            left = left * 2 - counts[length] as i32;
+            if left < 0 { return Err("Huffman code is oversubscribed.".to_string()); }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..(MAX_BITS + 1) {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts: counts, symbols: symbols })
+    }
+
+    // Decodes the next symbol by reading the code a bit at a time.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..(MAX_BITS + 1) {
+            code |= try!(reader.read_bits(1)) as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid Huffman code.".to_string())
+    }
+}
+
+// Copies a stored block to the output.
+fn inflate_stored(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), String> {
+    reader.align_to_byte();
+    let position = reader.position;
+    if position + 4 > reader.data.len() {
+        return Err("Compressed data ends early.".to_string());
+    }
+    let data = reader.data;
+    let length = data[position] as usize | (data[position + 1] as usize) << 8;
+    let complement = data[position + 2] as usize | (data[position + 3] as usize) << 8;
+    if length != !complement & 0xFFFF {
+        return Err("Stored block has an invalid length.".to_string());
+    }
+    if position + 4 + length > data.len() {
+        return Err("Compressed data ends early.".to_string());
+    }
+    out.extend_from_slice(&data[(position + 4)..(position + 4 + length)]);
+    reader.position = position + 4 + length;
+    Ok(())
+}
+
+// Decodes the symbols of a Huffman block to the output until the end of block code.
+fn inflate_codes(reader: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman,
+        distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = try!(literals.decode(reader)) as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err("Invalid length code.".to_string());
+            }
+            let length = LENGTH_BASE[symbol] as usize +
+                    try!(reader.read_bits(LENGTH_EXTRA[symbol] as usize)) as usize;
+            let symbol = try!(distances.decode(reader)) as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err("Invalid distance code.".to_string());
+            }
+            let distance = DISTANCE_BASE[symbol] as usize +
+                    try!(reader.read_bits(DISTANCE_EXTRA[symbol] as usize)) as usize;
+            if distance > out.len() {
+                return Err("Distance is farther back than the output.".to_string());
+            }
+            // The copy can overlap what it writes, which repeats the last few bytes.
+            let start = out.len() - distance;
+            for i in 0..length {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+    }
+}
+
+// Builds the fixed codes used by blocks of type 1.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; MAX_LITERAL_CODES];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = if symbol < 144 || symbol >= 280 { 8 } else if symbol < 256 { 9 } else { 7 };
+    }
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; MAX_DISTANCE_CODES]).unwrap())
+}
+
+// Reads the codes of a block of type 2 from the start of the block.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = try!(reader.read_bits(5)) as usize + 257;
+    let distance_count = try!(reader.read_bits(5)) as usize + 1;
+    let code_count = try!(reader.read_bits(4)) as usize + 4;
+    if literal_count > 286 || distance_count > MAX_DISTANCE_CODES {
+        return Err("Block has too many codes.".to_string());
+    }
+    let mut code_lengths = [0u8; 19];
+    for i in 0..code_count {
+        code_lengths[CODE_LENGTH_ORDER[i]] = try!(reader.read_bits(3)) as u8;
+    }
+    let code = try!(Huffman::new(&code_lengths));
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = try!(code.decode(reader));
+        let (value, repeat) = match symbol {
+            _ if symbol < 16 => (symbol as u8, 1),
+            16 => {
+                if index == 0 { return Err("Repeated length has nothing to repeat.".to_string()); }
+                (lengths[index - 1], 3 + try!(reader.read_bits(2)) as usize)
+            },
+            17 => (0, 3 + try!(reader.read_bits(3)) as usize),
+            _ => (0, 11 + try!(reader.read_bits(7)) as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err("Code lengths run past the end.".to_string());
+        }
+        for _ in 0..repeat {
+            lengths[index] = value;
+            index += 1;
+        }
+    }
+    if lengths[256] == 0 {
+        return Err("Block has no end of block code.".to_string());
+    }
+    let literals = try!(Huffman::new(&lengths[..literal_count]));
+    let distances = try!(Huffman::new(&lengths[literal_count..]));
+    Ok((literals, distances))
+}
+
+// Decompresses a raw DEFLATE stream and returns the decompressed bytes along with the number of
+// compressed bytes that were read.
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader { data: data, position: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = try!(reader.read_bits(1)) == 1;
+        match try!(reader.read_bits(2)) {
+            0 => try!(inflate_stored(&mut reader, &mut out)),
+            1 => {
+                let (literals, distances) = fixed_codes();
+                try!(inflate_codes(&mut reader, &mut out, &literals, &distances));
+            },
+            2 => {
+                let (literals, distances) = try!(dynamic_codes(&mut reader));
+                try!(inflate_codes(&mut reader, &mut out, &literals, &distances));
+            },
+            _ => return Err("Invalid block type.".to_string()),
+        }
+        if last { break; }
+    }
+    Ok((out, reader.position))
+}
+
+// Computes the Adler-32 checksum of some bytes.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+// Decompresses a zlib stream and checks its checksum.
+pub fn decode_zlib(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 {
+        return Err("Zlib stream is too small.".to_string());
+    }
+    let (cmf, flags) = (data[0], data[1]);
+    if cmf & 0x0F != 8 || (cmf as u16 * 256 + flags as u16) % 31 != 0 {
+        return Err("Zlib stream has an invalid header.".to_string());
+    }
+    if flags & 0x20 != 0 {
+        return Err("Zlib streams with preset dictionaries are not supported.".to_string());
+    }
+    let (out, read) = try!(inflate(&data[2..]));
+    let end = 2 + read;
+    if end + 4 > data.len() {
+        return Err("Zlib stream is missing its checksum.".to_string());
+    }
+    let expected = (data[end] as u32) << 24 | (data[end + 1] as u32) << 16 |
+            (data[end + 2] as u32) << 8 | data[end + 3] as u32;
+    if adler32(&out) != expected {
+        return Err("Zlib stream has an incorrect checksum.".to_string());
+    }
+    Ok(out)
+}
@@ -0,0 +1,458 @@
+// Defines a JobSystem, which runs jobs on a pool of worker threads so that work is spread across
+// every core. Each worker has a queue of its own that it takes its newest job from, and jobs that
+// are spawned from a worker go onto that worker's queue, so a job and the jobs it forks tend to
+// stay on the same core. A worker that runs out of jobs steals the oldest job from the other
+// workers, which is usually the largest piece of work left. Jobs spawned from other threads go
+// onto a shared queue that every worker takes from.
+//
+// Every job has a JobHandle that can be waited on, and jobs can be spawned to start only once
+// other jobs have finished, which builds a graph of jobs that runs without any thread waiting on
+// it. A thread that waits on a job runs other jobs in the meantime instead of blocking, so jobs
+// can fork jobs and wait on them without tying up the workers.
+//
+// Jobs from spawn() must own everything they use. Jobs in a scope() can borrow from the stack
+// instead since the scope waits for all of them before it returns, and join(), for_each_mut(), and
+// map() are built on scopes for fork-join parallelism. A job that panics counts as finished, and
+// the panic is reported by wait() or passed on by the scope that the job belongs to.
+//
+// Usage of a JobSystem:
+// - Create it with new() for a worker per core other than the calling thread's or with a number of
+//   workers with with_workers().
+// - Spawn jobs with spawn() or spawn_after() and wait() on their handles, or use scope(), join(),
+//   for_each_mut(), or map() to work on borrowed data.
+// - Drop it to finish the queued jobs and stop the workers.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+// The number of cores assumed when it can't be read from the system.
+const DEFAULT_CORE_COUNT: usize = 4;
+
+// How many pieces per thread for_each_mut() and map() split their items into, so that threads
+// which finish early can steal from the rest.
+const SPLITS_PER_THREAD: usize = 4;
+
+thread_local!(
+    // The pool and index of the worker that the current thread is, or a pool of 0 if it isn't one.
+    static WORKER: Cell<(usize, usize)> = Cell::new((0, 0))
+);
+
+// Work that runs once on some thread.
+trait Task: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Task for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+// A task along with the state that its handles share.
+struct Job {
+    task: Box<Task>,
+    state: Arc<JobState>,
+}
+
+// The state of a spawned job. The jobs that depend on it are released when it finishes.
+struct JobState {
+    done: AtomicBool,
+    panicked: AtomicBool,
+    dependents: Mutex<Vec<Arc<PendingJob>>>,
+}
+
+impl JobState {
+    fn new() -> JobState {
+        JobState { done: AtomicBool::new(false), panicked: AtomicBool::new(false),
+                dependents: Mutex::new(Vec::new()) }
+    }
+}
+
+// A job waiting on the jobs that it depends on, which is queued once the last of them finishes.
+struct PendingJob {
+    remaining: AtomicUsize,
+    job: Mutex<Option<Job>>,
+}
+
+// The queues and signals that the workers and waiting threads share.
+struct Shared {
+    queues: Vec<Mutex<VecDeque<Job>>>,
+    injector: Mutex<VecDeque<Job>>,
+    queued: AtomicUsize,                // At least the number of jobs in the queues.
+    waiting: AtomicUsize,               // Threads sleeping until a job finishes.
+    running: AtomicBool,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl Shared {
+    // Gets the ID of the pool that the workers mark themselves with.
+    fn get_id(&self) -> usize {
+        self as *const Shared as usize
+    }
+
+    // Gets the index of the worker that the current thread is if it belongs to this pool.
+    fn get_worker(&self) -> Option<usize> {
+        let (id, index) = WORKER.with(|w| w.get());
+        if id == self.get_id() { Some(index) } else { None }
+    }
+
+    // Queues a job on the current worker's queue, or on the shared queue from other threads, and
+    // wakes a thread to run it.
+    fn push(&self, job: Job) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        match self.get_worker() {
+            Some(index) => self.queues[index].lock().unwrap().push_back(job),
+            None => self.injector.lock().unwrap().push_back(job),
+        }
+        let _lock = self.sleep.lock().unwrap();
+        self.wake.notify_one();
+    }
+
+    // Takes the next job to run, which is the newest job of the current worker, then the oldest of
+    // the shared queue, and then the oldest of any other worker.
+    fn pop(&self) -> Option<Job> {
+        if self.queued.load(Ordering::SeqCst) == 0 { return None; }
+        let worker = self.get_worker();
+        let mut job = worker.and_then(|i| self.queues[i].lock().unwrap().pop_back());
+        if job.is_none() {
+            job = self.injector.lock().unwrap().pop_front();
+        }
+        if job.is_none() {
+            let count = self.queues.len();
+            let start = worker.map_or(0, |i| i + 1);
+            for offset in 0..count {
+                let victim = (start + offset) % count;
+                if Some(victim) == worker { continue; }
+                job = self.queues[victim].lock().unwrap().pop_front();
+                if job.is_some() { break; }
+            }
+        }
+        if job.is_some() {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        job
+    }
+
+    // Runs a job and marks it as finished even if it panics.
+    fn run(&self, job: Job) {
+        let Job { task, state } = job;
+        if panic::catch_unwind(AssertUnwindSafe(move || task.run())).is_err() {
+            state.panicked.store(true, Ordering::SeqCst);
+        }
+        self.finish(&state);
+    }
+
+    // Marks a job as finished, releases the jobs that depend on it, and wakes the threads waiting
+    // on jobs.
+    fn finish(&self, state: &JobState) {
+        let dependents = {
+            let mut dependents = state.dependents.lock().unwrap();
+            state.done.store(true, Ordering::SeqCst);
+            mem::replace(&mut *dependents, Vec::new())
+        };
+        for pending in dependents {
+            self.release(&pending);
+        }
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            let _lock = self.sleep.lock().unwrap();
+            self.wake.notify_all();
+        }
+    }
+
+    // Counts off one of the jobs that a pending job is waiting on and queues it after the last.
+    fn release(&self, pending: &PendingJob) {
+        if pending.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            if let Some(job) = pending.job.lock().unwrap().take() {
+                self.push(job);
+            }
+        }
+    }
+
+    // Runs other jobs until a job is finished, and sleeps while there are none to run.
+    fn wait_for(&self, state: &JobState) {
+        while !state.done.load(Ordering::SeqCst) {
+            if let Some(job) = self.pop() {
+                self.run(job);
+                continue;
+            }
+            self.waiting.fetch_add(1, Ordering::SeqCst);
+            {
+                let lock = self.sleep.lock().unwrap();
+                if !state.done.load(Ordering::SeqCst) && self.queued.load(Ordering::SeqCst) == 0 {
+                    let _lock = self.wake.wait(lock).unwrap();
+                }
+            }
+            self.waiting.fetch_sub(1, Ordering::SeqCst);
+        }
+        // The wakeup for a queued job may have gone to this thread, so it is passed on.
+        if self.queued.load(Ordering::SeqCst) > 0 {
+            let _lock = self.sleep.lock().unwrap();
+            self.wake.notify_one();
+        }
+    }
+}
+
+// Runs jobs on a worker thread until the JobSystem is dropped and the queues are empty.
+fn work(shared: Arc<Shared>, index: usize) {
+    WORKER.with(|w| w.set((shared.get_id(), index)));
+    loop {
+        if let Some(job) = shared.pop() {
+            shared.run(job);
+            continue;
+        }
+        let lock = shared.sleep.lock().unwrap();
+        let queued = shared.queued.load(Ordering::SeqCst);
+        if !shared.running.load(Ordering::SeqCst) {
+            if queued == 0 { break; }
+        } else if queued == 0 {
+            let _lock = shared.wake.wait(lock).unwrap();
+        }
+    }
+}
+
+// Gets the number of cores from /proc/cpuinfo, or a default number if it can't be read.
+pub fn get_core_count() -> usize {
+    let file = match File::open("/proc/cpuinfo") {
+        Ok(file) => file,
+        Err(_) => return DEFAULT_CORE_COUNT,
+    };
+    let count = BufReader::new(file).lines().filter_map(|line| line.ok())
+            .filter(|line| line.starts_with("processor")).count();
+    if count > 0 { count } else { DEFAULT_CORE_COUNT }
+}
+
+// A handle to a spawned job that can be waited on or depended on.
+#[derive(Clone)]
+pub struct JobHandle {
+    state: Arc<JobState>,
+}
+
+impl JobHandle {
+    // Returns true once the job has finished running.
+    pub fn is_done(&self) -> bool {
+        self.state.done.load(Ordering::SeqCst)
+    }
+
+    // Returns true if the job finished by panicking.
+    pub fn is_panicked(&self) -> bool {
+        self.state.panicked.load(Ordering::SeqCst)
+    }
+}
+
+// A pool of worker threads that run jobs.
+pub struct JobSystem {
+    shared: Arc<Shared>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl JobSystem {
+    // Creates a JobSystem with a worker for every core but one, which is left for the calling
+    // thread. There is always at least one worker.
+    pub fn new() -> JobSystem {
+        JobSystem::with_workers(cmp::max(get_core_count(), 2) - 1)
+    }
+
+    // Creates a JobSystem with some number of workers. Jobs only run on threads that wait on them
+    // if there are no workers.
+    pub fn with_workers(count: usize) -> JobSystem {
+        let shared = Arc::new(Shared { queues: (0..count).map(|_| Mutex::new(VecDeque::new()))
+                .collect(), injector: Mutex::new(VecDeque::new()), queued: AtomicUsize::new(0),
+                waiting: AtomicUsize::new(0), running: AtomicBool::new(true),
+                sleep: Mutex::new(()), wake: Condvar::new() });
+        let workers = (0..count).map(|index| {
+            let shared = shared.clone();
+            thread::Builder::new().name(format!("Job worker {}", index))
+                    .spawn(move || work(shared, index)).unwrap()
+        }).collect();
+        JobSystem { shared: shared, workers: workers }
+    }
+
+    // Gets the number of worker threads.
+    pub fn get_worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    // Spawns a job to run as soon as a thread is free.
+    pub fn spawn<F>(&self, f: F) -> JobHandle where F: FnOnce() + Send + 'static {
+        self.submit(&[], Box::new(f))
+    }
+
+    // Spawns a job to run once every job it depends on has finished, whether or not they panicked.
+    pub fn spawn_after<F>(&self, dependencies: &[JobHandle], f: F) -> JobHandle
+            where F: FnOnce() + Send + 'static {
+        self.submit(dependencies, Box::new(f))
+    }
+
+    // Runs other jobs on the calling thread until a job has finished. Returns an Err if the job
+    // panicked.
+    pub fn wait(&self, handle: &JobHandle) -> Result<(), String> {
+        self.shared.wait_for(&handle.state);
+        if handle.is_panicked() { Err("The job panicked.".to_string()) } else { Ok(()) }
+    }
+
+    // Waits on every job in a list. Returns an Err if any of them panicked.
+    pub fn wait_all(&self, handles: &[JobHandle]) -> Result<(), String> {
+        let mut result = Ok(());
+        for handle in handles {
+            if self.wait(handle).is_err() {
+                result = Err(format!("{} of {} jobs panicked.",
+                        handles.iter().filter(|h| h.is_panicked()).count(), handles.len()));
+            }
+        }
+        result
+    }
+
+    // Runs a function that can spawn jobs borrowing anything that outlives the call, and waits for
+    // every one of those jobs before returning what the function returns. Panics if the function
+    // or any of the jobs panicked once the jobs are done.
+    pub fn scope<'a, F, R>(&'a self, f: F) -> R where F: FnOnce(&Scope<'a>) -> R {
+        let scope = Scope { jobs: self, handles: RefCell::new(Vec::new()), marker: PhantomData };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        let panicked = scope.wait_all();
+        match result {
+            Ok(result) => {
+                if panicked { panic!("A job in the scope panicked."); }
+                result
+            },
+            Err(error) => panic::resume_unwind(error),
+        }
+    }
+
+    // Runs two functions at the same time, one of them on the calling thread, and returns both
+    // results.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+            where A: FnOnce() -> RA + Send, B: FnOnce() -> RB + Send, RA: Send, RB: Send {
+        let mut result_b = None;
+        let result_a = self.scope(|scope| {
+            scope.spawn(|| result_b = Some(b()));
+            a()
+        });
+        (result_a, result_b.unwrap())
+    }
+
+    // Calls a function with the index of every item in a slice and a mutable reference to it,
+    // spread across the workers and the calling thread.
+    pub fn for_each_mut<T, F>(&self, items: &mut [T], f: F)
+            where T: Send, F: Fn(usize, &mut T) + Sync {
+        let size = self.get_split_size(items.len());
+        let f = &f;
+        self.scope(|scope| {
+            for (i, chunk) in items.chunks_mut(size).enumerate() {
+                scope.spawn(move || {
+                    for (j, item) in chunk.iter_mut().enumerate() {
+                        f(i * size + j, item);
+                    }
+                });
+            }
+        });
+    }
+
+    // Calls a function with the index of every item in a slice and the item, spread across the
+    // workers and the calling thread, and returns the results in the same order.
+    pub fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+            where T: Sync, R: Send, F: Fn(usize, &T) -> R + Sync {
+        let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+        self.for_each_mut(&mut results, |i, result| *result = Some(f(i, &items[i])));
+        results.into_iter().map(|result| result.unwrap()).collect()
+    }
+
+    // Gets how many items go in each piece when splitting some number of items across threads.
+    fn get_split_size(&self, len: usize) -> usize {
+        let splits = (self.workers.len() + 1) * SPLITS_PER_THREAD;
+        cmp::max(1, (len + splits - 1) / splits)
+    }
+
+    // Queues a task once its dependencies have finished and returns its handle.
+    fn submit(&self, dependencies: &[JobHandle], task: Box<Task>) -> JobHandle {
+        let state = Arc::new(JobState::new());
+        let job = Job { task: task, state: state.clone() };
+        if dependencies.is_empty() {
+            self.shared.push(job);
+            return JobHandle { state: state };
+        }
+        // The extra count keeps the job from being queued before every dependency is counted.
+        let pending = Arc::new(PendingJob { remaining: AtomicUsize::new(dependencies.len() + 1),
+                job: Mutex::new(Some(job)) });
+        for dependency in dependencies {
+            let done = {
+                let mut dependents = dependency.state.dependents.lock().unwrap();
+                let done = dependency.state.done.load(Ordering::SeqCst);
+                if !done {
+                    dependents.push(pending.clone());
+                }
+                done
+            };
+            if done {
+                self.shared.release(&pending);
+            }
+        }
+        self.shared.release(&pending);
+        JobHandle { state: state }
+    }
+}
+
+impl Drop for JobSystem {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::SeqCst);
+        {
+            let _lock = self.shared.sleep.lock().unwrap();
+            self.shared.wake.notify_all();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Spawns jobs that can borrow anything that outlives a call to JobSystem::scope(). The lifetime
+// can't be shortened, which would let jobs borrow things that end before the scope waits on them.
+pub struct Scope<'a> {
+    jobs: &'a JobSystem,
+    handles: RefCell<Vec<JobHandle>>,
+    marker: PhantomData<Cell<&'a ()>>,
+}
+
+impl<'a> Scope<'a> {
+    // Spawns a job that the scope waits for.
+    pub fn spawn<F>(&self, f: F) -> JobHandle where F: FnOnce() + Send + 'a {
+        self.spawn_after(&[], f)
+    }
+
+    // Spawns a job that the scope waits for to run once every job it depends on has finished.
+    pub fn spawn_after<F>(&self, dependencies: &[JobHandle], f: F) -> JobHandle
+            where F: FnOnce() + Send + 'a {
+        let task: Box<Task + 'a> = Box::new(f);
+        // The scope waits for the job before anything that the job borrows can go away.
+        let task: Box<Task> = unsafe { mem::transmute(task) };
+        let handle = self.jobs.submit(dependencies, task);
+        self.handles.borrow_mut().push(handle.clone());
+        handle
+    }
+
+    // Gets the JobSystem that the scope spawns jobs on.
+    pub fn get_jobs(&self) -> &'a JobSystem {
+        self.jobs
+    }
+
+    // Waits for every job spawned in the scope. Returns true if any of them panicked.
+    fn wait_all(&self) -> bool {
+        let handles = mem::replace(&mut *self.handles.borrow_mut(), Vec::new());
+        handles.iter().fold(false, |panicked, handle| {
+            self.jobs.shared.wait_for(&handle.state);
+            panicked || handle.is_panicked()
+        })
+    }
+}
@@ -0,0 +1,390 @@
+// Utility module for reading and writing JSON documents. This is a small self-contained
+// implementation of RFC 8259 that is used for engine data such as saved scenes. Objects keep their
+// keys in insertion order so that written files are stable and easy to diff.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::char;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{Read, Write};
+
+// Maximum nesting of arrays and objects before a document is considered malformed.
+const MAX_DEPTH: usize = 256;
+
+// A JSON value. Objects are stored as a list of key value pairs in order.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    // Creates an empty object.
+    pub fn new_object() -> Value {
+        Value::Object(Vec::new())
+    }
+
+    // Sets a key of an object, replacing any value it already had. This does nothing if the value
+    // isn't an object.
+    pub fn set(&mut self, key: &str, value: Value) {
+        if let Value::Object(ref mut pairs) = *self {
+            match pairs.iter().position(|&(ref k, _)| k == key) {
+                Some(i) => { pairs[i].1 = value; },
+                None => { pairs.push((key.to_string(), value)); },
+            }
+        }
+    }
+
+    // Gets the value of a key if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref pairs) => pairs.iter().find(|&&(ref k, _)| k == key).map(|p| &p.1),
+            _ => None,
+        }
+    }
+
+    // Gets the mutable value of a key if this is an object that has it.
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match *self {
+            Value::Object(ref mut pairs) => {
+                pairs.iter_mut().find(|&&mut (ref k, _)| k == key).map(|p| &mut p.1)
+            },
+            _ => None,
+        }
+    }
+
+    // Removes a key from an object and returns its value if it had one.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        match *self {
+            Value::Object(ref mut pairs) => {
+                pairs.iter().position(|&(ref k, _)| k == key).map(|i| pairs.remove(i).1)
+            },
+            _ => None,
+        }
+    }
+
+    // Gets the value as a bool.
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self { Value::Bool(b) => Some(b), _ => None }
+    }
+
+    // Gets the value as a number.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self { Value::Number(n) => Some(n), _ => None }
+    }
+
+    // Gets the value as a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Value::String(ref s) => Some(s), _ => None }
+    }
+
+    // Gets the value as an array.
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match *self { Value::Array(ref a) => Some(a), _ => None }
+    }
+
+    // Gets the value as the key value pairs of an object.
+    pub fn as_object(&self) -> Option<&Vec<(String, Value)>> {
+        match *self { Value::Object(ref o) => Some(o), _ => None }
+    }
+
+    // Returns true if the value is null.
+    pub fn is_null(&self) -> bool {
+        *self == Value::Null
+    }
+
+    // Writes the value as indented JSON text.
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        write_value(self, &mut out, 0);
+        out
+    }
+}
+
+// Writes a string with the escapes required by JSON.
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => { let _ = write!(out, "\\u{:04x}", c as u32); },
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Writes a value indented by a number of levels. Arrays of numbers are kept on one line since they
+// are usually vectors and colors.
+fn write_value(value: &Value, out: &mut String, indent: usize) {
+    let pad = |out: &mut String, n: usize| for _ in 0..n { out.push_str("  "); };
+    match *value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if b { "true" } else { "false" }),
+        Value::Number(n) => {
+            if n.is_finite() { let _ = write!(out, "{}", n); } else { out.push_str("null"); }
+        },
+        Value::String(ref s) => write_string(s, out),
+        Value::Array(ref items) => {
+            if items.iter().all(|i| i.as_f64().is_some()) {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 { out.push_str(", "); }
+                    write_value(item, out, indent);
+                }
+                out.push(']');
+                return;
+            }
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                pad(out, indent + 1);
+                write_value(item, out, indent + 1);
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            pad(out, indent);
+            out.push(']');
+        },
+        Value::Object(ref pairs) => {
+            if pairs.is_empty() {
+                out.push_str("{}");
+                return;
+            }
+            out.push_str("{\n");
+            for (i, &(ref key, ref item)) in pairs.iter().enumerate() {
+                pad(out, indent + 1);
+                write_string(key, out);
+                out.push_str(": ");
+                write_value(item, out, indent + 1);
+                out.push_str(if i + 1 < pairs.len() { ",\n" } else { "\n" });
+            }
+            pad(out, indent);
+            out.push('}');
+        },
+    }
+}
+
+// A cursor over the characters of a document being parsed.
+struct Parser<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Creates an error message with the current line.
+    fn error(&self, message: &str) -> String {
+        format!("JSON error on line {}: {}", self.line, message)
+    }
+
+    // Consumes the next character.
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') { self.line += 1; }
+        c
+    }
+
+    // Skips any whitespace before the next token.
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c != ' ' && c != '\t' && c != '\n' && c != '\r' { break; }
+            self.next();
+        }
+    }
+
+    // Consumes a character and returns an Err if it isn't the expected one.
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("Expected '{}'.", expected))),
+        }
+    }
+
+    // Consumes a keyword such as true, false, or null.
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if self.next() != Some(expected) {
+                return Err(self.error("Invalid literal."));
+            }
+        }
+        Ok(value)
+    }
+
+    // Parses any value.
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Document is nested too deeply."));
+        }
+        self.skip_whitespace();
+        match self.chars.peek().map(|c| *c) {
+            Some('{') => self.object(depth),
+            Some('[') => self.array(depth),
+            Some('"') => Ok(Value::String(try!(self.string()))),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Null),
+            Some(c) if c == '-' || c.is_digit(10) => self.number(),
+            Some(_) => Err(self.error("Unexpected character.")),
+            None => Err(self.error("Unexpected end of document.")),
+        }
+    }
+
+    // Parses an object.
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        try!(self.expect('{'));
+        let mut pairs = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.next();
+            return Ok(Value::Object(pairs));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = try!(self.string());
+            self.skip_whitespace();
+            try!(self.expect(':'));
+            let value = try!(self.value(depth + 1));
+            pairs.push((key, value));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {},
+                Some('}') => { return Ok(Value::Object(pairs)); },
+                _ => { return Err(self.error("Expected ',' or '}'.")); },
+            }
+        }
+    }
+
+    // Parses an array.
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        try!(self.expect('['));
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.next();
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(try!(self.value(depth + 1)));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {},
+                Some(']') => { return Ok(Value::Array(items)); },
+                _ => { return Err(self.error("Expected ',' or ']'.")); },
+            }
+        }
+    }
+
+    // Parses four hex digits of a \u escape.
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = match self.next().and_then(|c| c.to_digit(16)) {
+                Some(d) => d,
+                None => { return Err(self.error("Invalid unicode escape.")); },
+            };
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    // Parses a string including escapes and UTF-16 surrogate pairs.
+    fn string(&mut self) -> Result<String, String> {
+        try!(self.expect('"'));
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => { return Ok(s); },
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let mut code = try!(self.hex4());
+                            if code >= 0xD800 && code < 0xDC00 {
+                                try!(self.expect('\\'));
+                                try!(self.expect('u'));
+                                let low = try!(self.hex4());
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00)
+                                        & 0x3FF);
+                            }
+                            match char::from_u32(code) {
+                                Some(c) => c,
+                                None => { return Err(self.error("Invalid unicode escape.")); },
+                            }
+                        },
+                        _ => { return Err(self.error("Invalid escape.")); },
+                    };
+                    s.push(c);
+                },
+                Some(c) => s.push(c),
+                None => { return Err(self.error("Unterminated string.")); },
+            }
+        }
+    }
+
+    // Parses a number.
+    fn number(&mut self) -> Result<Value, String> {
+        let mut text = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !(c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E') {
+                break;
+            }
+            text.push(c);
+            self.next();
+        }
+        match text.parse::<f64>() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => Err(self.error("Invalid number.")),
+        }
+    }
+}
+
+// Parses a JSON document from a string.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1 };
+    let value = try!(parser.value(0));
+    parser.skip_whitespace();
+    if parser.chars.peek().is_some() {
+        return Err(parser.error("Unexpected data after the document."));
+    }
+    Ok(value)
+}
+
+// Decodes a JSON file given a path.
+pub fn decode_json(fpath: &str) -> Result<Value, String> {
+    let mut file = match File::open(fpath) {
+        Ok(f) => f,
+        Err(_) => { return Err("Could not open JSON file.".to_string()); },
+    };
+    let mut contents = String::new();
+    if file.read_to_string(&mut contents).is_err() {
+        return Err("Could not read JSON file as text.".to_string());
+    }
+    parse(&contents)
+}
+
+// Encodes a value as indented JSON and writes it to a file given a path.
+pub fn encode_json(fpath: &str, value: &Value) -> Result<(), String> {
+    let mut file = match File::create(fpath) {
+        Ok(f) => f,
+        Err(_) => { return Err("Could not create JSON file.".to_string()); },
+    };
+    let mut text = value.to_pretty_string();
+    text.push('\n');
+    file.write_all(text.as_bytes()).map_err(|e| e.to_string())
+}
@@ -0,0 +1,290 @@
+// Utility module that allows for decoding of a Khronos KTX 2.0 (.ktx2) file given a path to the
+// file. Only single 2D textures without supercompression that use one of the BC1-BC7 Vulkan
+// formats are supported. Basis Universal and Zstandard supercompressed files are rejected. The
+// blocks are not decoded so that they can be uploaded to the GPU as-is. Block compressed images
+// can also be encoded as KTX2 files, which is how the asset cooker stores textures.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::fs::File;
+use std::io::{Read, Write};
+use util::common;
+
+// Return value for a decoded KTX2 file. This contains the compressed blocks for every mip level.
+pub struct DecodedKTX2 {
+    pub image: common::CompressedImage,
+}
+
+// Every KTX2 file starts with these 12 bytes.
+static KTX2_MAGIC: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A,
+        0x0A];
+
+// Reads and consumes 4 bytes from the data vector as a little endian u32.
+fn read_u32(data: &Vec<u8>, cursor: &mut usize) -> Result<u32, String> {
+    if *cursor + 4 > data.len() {
+        return Err("KTX2 file is too small.".to_string());
+    }
+    let mut value = 0;
+    for i in 0..4 {
+        value |= (data[*cursor + i] as u32) << (8 * i);
+    }
+    *cursor += 4;
+    Ok(value)
+}
+
+// Reads and consumes 8 bytes from the data vector as a little endian u64.
+fn read_u64(data: &Vec<u8>, cursor: &mut usize) -> Result<u64, String> {
+    let low = try!(read_u32(data, cursor)) as u64;
+    let high = try!(read_u32(data, cursor)) as u64;
+    Ok(low | high << 32)
+}
+
+// Maps a VkFormat to a compression format and whether it is in sRGB space.
+fn format_from_vk(format: u32) -> Result<(common::CompressedFormat, bool), String> {
+    match format {
+        131 => Ok((common::CompressedFormat::BC1, false)),
+        132 => Ok((common::CompressedFormat::BC1, true)),
+        133 => Ok((common::CompressedFormat::BC1Alpha, false)),
+        134 => Ok((common::CompressedFormat::BC1Alpha, true)),
+        135 => Ok((common::CompressedFormat::BC2, false)),
+        136 => Ok((common::CompressedFormat::BC2, true)),
+        137 => Ok((common::CompressedFormat::BC3, false)),
+        138 => Ok((common::CompressedFormat::BC3, true)),
+        139 => Ok((common::CompressedFormat::BC4, false)),
+        141 => Ok((common::CompressedFormat::BC5, false)),
+        143 => Ok((common::CompressedFormat::BC6HUnsigned, false)),
+        144 => Ok((common::CompressedFormat::BC6HSigned, false)),
+        145 => Ok((common::CompressedFormat::BC7, false)),
+        146 => Ok((common::CompressedFormat::BC7, true)),
+        _ => Err("Unsupported KTX2 format.".to_string()),
+    }
+}
+
+// Decodes a KTX2 given a path to the file and returns a DecodedKTX2 struct containing the
+// compressed mip levels, format, width, and height of the image.
+pub fn decode_ktx2(fpath: &str) -> Result<DecodedKTX2, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    if data.len() < KTX2_MAGIC.len() || data[0..KTX2_MAGIC.len()] != KTX2_MAGIC[..] {
+        return Err("KTX2 file header has incorrect magic values.".to_string());
+    }
+    let mut cursor = KTX2_MAGIC.len();
+    let (format, srgb) = try!(format_from_vk(try!(read_u32(&data, &mut cursor))));
+    try!(read_u32(&data, &mut cursor));
+    let width = try!(read_u32(&data, &mut cursor));
+    let height = try!(read_u32(&data, &mut cursor));
+    let depth = try!(read_u32(&data, &mut cursor));
+    let layers = try!(read_u32(&data, &mut cursor));
+    let faces = try!(read_u32(&data, &mut cursor));
+    let level_count = try!(read_u32(&data, &mut cursor));
+    let supercompression = try!(read_u32(&data, &mut cursor));
+    if depth != 0 || layers != 0 || faces != 1 {
+        return Err("Only single 2D KTX2 textures are supported.".to_string());
+    }
+    if supercompression != 0 {
+        return Err("Supercompressed KTX2 files are not supported.".to_string());
+    }
+    if width == 0 || height == 0 {
+        return Err("KTX2 image is empty.".to_string());
+    }
+    // Skip the data format descriptor, key/value data, and supercompression global data indices.
+    cursor += 4 * 4 + 2 * 8;
+
+    // A level count of 0 asks the loader to generate mips which can't be done for compressed
+    // data, so only the base level is used.
+    let mut levels = Vec::new();
+    let (mut level_width, mut level_height) = (width, height);
+    for _ in 0..(if level_count == 0 { 1 } else { level_count }) {
+        let offset = try!(read_u64(&data, &mut cursor)) as usize;
+        let length = try!(read_u64(&data, &mut cursor)) as usize;
+        try!(read_u64(&data, &mut cursor));
+        if length != format.level_size(level_width, level_height) {
+            return Err("KTX2 mip level has an incorrect size.".to_string());
+        }
+        let end = match offset.checked_add(length) {
+            Some(end) if end <= data.len() => end,
+            _ => return Err("KTX2 file is too small.".to_string()),
+        };
+        levels.push(data[offset..end].to_vec());
+        level_width = if level_width > 1 { level_width / 2 } else { 1 };
+        level_height = if level_height > 1 { level_height / 2 } else { 1 };
+    }
+    let image = common::CompressedImage { width: width, height: height, format: format,
+            srgb: srgb, levels: levels };
+    Ok(DecodedKTX2 { image: image })
+}
+
+// Maps a compression format to a VkFormat, along with the color model and channel IDs of its
+// samples for the data format descriptor.
+fn format_to_vk(format: common::CompressedFormat, srgb: bool)
+        -> Result<(u32, u32, Vec<u32>), String> {
+    match (format, srgb) {
+        (common::CompressedFormat::BC1, false) => Ok((131, 128, vec![0])),
+        (common::CompressedFormat::BC1, true) => Ok((132, 128, vec![0])),
+        (common::CompressedFormat::BC1Alpha, false) => Ok((133, 128, vec![15])),
+        (common::CompressedFormat::BC1Alpha, true) => Ok((134, 128, vec![15])),
+        (common::CompressedFormat::BC2, false) => Ok((135, 129, vec![15, 0])),
+        (common::CompressedFormat::BC2, true) => Ok((136, 129, vec![15, 0])),
+        (common::CompressedFormat::BC3, false) => Ok((137, 130, vec![15, 0])),
+        (common::CompressedFormat::BC3, true) => Ok((138, 130, vec![15, 0])),
+        (common::CompressedFormat::BC4, false) => Ok((139, 131, vec![0])),
+        (common::CompressedFormat::BC5, false) => Ok((141, 132, vec![0, 1])),
+        (common::CompressedFormat::BC7, false) => Ok((145, 134, vec![0])),
+        (common::CompressedFormat::BC7, true) => Ok((146, 134, vec![0])),
+        _ => Err("Unsupported KTX2 format.".to_string()),
+    }
+}
+
+// Appends a little endian u32 to the data vector.
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        data.push((value >> (8 * i)) as u8);
+    }
+}
+
+// Appends a little endian u64 to the data vector.
+fn write_u64(data: &mut Vec<u8>, value: u64) {
+    write_u32(data, value as u32);
+    write_u32(data, (value >> 32) as u32);
+}
+
+// Builds the data format descriptor of a block compressed format. Every sample covers a 64 bit
+// half of a block, and alpha is always linear even when the color is in sRGB space.
+fn build_dfd(model: u32, channels: &[u32], srgb: bool, block_size: usize) -> Vec<u8> {
+    let block_length = 24 + 16 * channels.len() as u32;
+    let mut dfd = Vec::new();
+    write_u32(&mut dfd, 4 + block_length);
+    write_u32(&mut dfd, 0);
+    write_u32(&mut dfd, 2 | block_length << 16);
+    write_u32(&mut dfd, model | 1 << 8 | (if srgb { 2 } else { 1 }) << 16);
+    write_u32(&mut dfd, 3 | 3 << 8);
+    write_u32(&mut dfd, block_size as u32);
+    write_u32(&mut dfd, 0);
+    for (i, &channel) in channels.iter().enumerate() {
+        let linear = if srgb && channel == 15 { 0x10 } else { 0 };
+        write_u32(&mut dfd, (i as u32 * 64) | 63 << 16 | (channel | linear) << 24);
+        write_u32(&mut dfd, 0);
+        write_u32(&mut dfd, 0);
+        write_u32(&mut dfd, 0xFFFFFFFF);
+    }
+    dfd
+}
+
+// Encodes a block compressed image as a KTX2 file at a path. The mip levels are stored from the
+// smallest to the largest as the format requires.
+pub fn encode_ktx2(fpath: &str, image: &common::CompressedImage) -> Result<(), String> {
+    let (vk_format, model, channels) = try!(format_to_vk(image.format, image.srgb));
+    if image.levels.is_empty() {
+        return Err("A KTX2 file needs at least one mip level.".to_string());
+    }
+    let block_size = image.format.block_size();
+    let dfd = build_dfd(model, &channels, image.srgb, block_size);
+    let mut data = Vec::new();
+    data.extend_from_slice(&KTX2_MAGIC);
+    for &value in [vk_format, 1, image.width, image.height, 0, 0, 1, image.levels.len() as u32,
+            0].iter() {
+        write_u32(&mut data, value);
+    }
+    let dfd_offset = data.len() + 4 * 4 + 2 * 8 + 3 * 8 * image.levels.len();
+    for &value in [dfd_offset as u32, dfd.len() as u32, 0, 0].iter() {
+        write_u32(&mut data, value);
+    }
+    write_u64(&mut data, 0);
+    write_u64(&mut data, 0);
+
+    // Lay out the levels after the descriptor, each aligned to the block size.
+    let mut offsets = vec![0; image.levels.len()];
+    let mut end = dfd_offset + dfd.len();
+    for (i, level) in image.levels.iter().enumerate().rev() {
+        end = (end + block_size - 1) / block_size * block_size;
+        offsets[i] = end;
+        end += level.len();
+    }
+    for (i, level) in image.levels.iter().enumerate() {
+        write_u64(&mut data, offsets[i] as u64);
+        write_u64(&mut data, level.len() as u64);
+        write_u64(&mut data, level.len() as u64);
+    }
+    data.extend_from_slice(&dfd);
+    for (i, level) in image.levels.iter().enumerate().rev() {
+        data.resize(offsets[i], 0);
+        data.extend_from_slice(level);
+    }
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Builds a file with a single mip level that starts right after the level index.
+    fn ktx2_file(vk_format: u32, width: u32, height: u32, blocks: &[u8]) -> Vec<u8> {
+        let mut data = KTX2_MAGIC.to_vec();
+        for &value in [vk_format, 1, width, height, 0, 0, 1, 1, 0, 0, 0, 0, 0].iter() {
+            for i in 0..4 {
+                data.push((value >> (8 * i)) as u8);
+            }
+        }
+        let offset = data.len() + 2 * 8 + 3 * 8;
+        for &value in [0, 0, offset as u64, blocks.len() as u64, blocks.len() as u64].iter() {
+            for i in 0..8 {
+                data.push((value >> (8 * i)) as u8);
+            }
+        }
+        data.extend_from_slice(blocks);
+        data
+    }
+
+    #[test]
+    fn decodes_single_level() {
+        let path = common::write_test_file("level.ktx2", &ktx2_file(132, 8, 4, &[3; 16]));
+        let image = decode_ktx2(&path).unwrap().image;
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!(image.format, common::CompressedFormat::BC1);
+        assert!(image.srgb);
+        assert_eq!(image.levels, vec![vec![3; 16]]);
+    }
+
+    #[test]
+    fn round_trips_encoded_images() {
+        let image = common::CompressedImage { width: 8, height: 8,
+                format: common::CompressedFormat::BC7, srgb: false,
+                levels: vec![vec![1; 64], vec![2; 16], vec![3; 16], vec![4; 16]] };
+        let path = common::write_test_file("round-trip.ktx2", &[]);
+        encode_ktx2(&path, &image).unwrap();
+        let decoded = decode_ktx2(&path).unwrap().image;
+        assert_eq!((decoded.width, decoded.height), (8, 8));
+        assert_eq!(decoded.format, common::CompressedFormat::BC7);
+        assert!(!decoded.srgb);
+        assert_eq!(decoded.levels, image.levels);
+    }
+
+    #[test]
+    fn rejects_truncated_levels() {
+        let mut data = ktx2_file(131, 8, 4, &[3; 16]);
+        let length = data.len();
+        data.truncate(length - 1);
+        let path = common::write_test_file("truncated.ktx2", &data);
+        assert!(decode_ktx2(&path).is_err());
+        let path = common::write_test_file("header.ktx2", &KTX2_MAGIC);
+        assert!(decode_ktx2(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        let path = common::write_test_file("empty.ktx2", &ktx2_file(131, 0, 0, &[0; 8]));
+        assert!(decode_ktx2(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        let path = common::write_test_file("oversized.ktx2",
+                &ktx2_file(137, 0xFFFFFFFF, 0xFFFFFFFF, &[0; 16]));
+        assert!(decode_ktx2(&path).is_err());
+    }
+}
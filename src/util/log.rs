@@ -0,0 +1,381 @@
+// Defines the engine's logger. Messages are logged with the log_error!(), log_warn!(),
+// log_info!(), log_debug!(), and log_trace!() macros under a category, such as
+// log_warn!(Assets, "Missing texture {}.", path), and each category has a level below which its
+// messages are dropped. The level is checked before the message is formatted, so a disabled
+// message costs no more than a load and a comparison, and an accepted message is formatted once
+// no matter how many sinks it goes to.
+//
+// Accepted messages become Records that are written to every LogSink, such as a ConsoleSink or a
+// FileSink, and kept in a ring buffer of the most recent records that an in-engine console can
+// read with get_history(). Every record has a sequence number, so a console can ask for only the
+// records that came in since the last one it showed. The logger is shared by every thread and
+// starts out with a ConsoleSink and every category at the Info level.
+//
+// Usage of the logger:
+// - Optionally set_level(category, level) or set_all_levels(level), and add_sink() a FileSink or
+//   clear_sinks() to replace the console.
+// - Log with the macros from anywhere.
+// - Read get_history(since) every frame to show the records that came in since the last call.
+// - Call flush() before exiting to make sure buffered sinks have written everything.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate time;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+// The number of records that the history keeps by default.
+const DEFAULT_HISTORY_CAPACITY: usize = 1024;
+
+// The number of categories.
+const CATEGORY_COUNT: usize = 8;
+
+#[macro_export]
+// Macro for logging a message under a category at a level, where the category and level are the
+// names of a Category and a Level.
+macro_rules! log_message { ($category:ident, $level:ident, $($arg:tt)+) => ({
+    let category = $crate::util::log::Category::$category;
+    let level = $crate::util::log::Level::$level;
+    if $crate::util::log::is_enabled(category, level) {
+        $crate::util::log::write(category, level, format_args!($($arg)+));
+    } }) }
+
+#[macro_export]
+// Macro for logging an error under a category.
+macro_rules! log_error { ($category:ident, $($arg:tt)+) =>
+        (log_message!($category, Error, $($arg)+)) }
+
+#[macro_export]
+// Macro for logging a warning under a category.
+macro_rules! log_warn { ($category:ident, $($arg:tt)+) =>
+        (log_message!($category, Warn, $($arg)+)) }
+
+#[macro_export]
+// Macro for logging information under a category.
+macro_rules! log_info { ($category:ident, $($arg:tt)+) =>
+        (log_message!($category, Info, $($arg)+)) }
+
+#[macro_export]
+// Macro for logging a debug message under a category.
+macro_rules! log_debug { ($category:ident, $($arg:tt)+) =>
+        (log_message!($category, Debug, $($arg)+)) }
+
+#[macro_export]
+// Macro for logging a trace message under a category.
+macro_rules! log_trace { ($category:ident, $($arg:tt)+) =>
+        (log_message!($category, Trace, $($arg)+)) }
+
+// How important a message is, from the least to the most. A category set to Off logs nothing.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Off,
+}
+
+impl Level {
+    // Gets the name of the level as it appears in logs.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Off => "OFF",
+        }
+    }
+
+    // Gets the level with a name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Level> {
+        match &name.to_lowercase()[..] {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" | "warning" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            "off" => Some(Level::Off),
+            _ => None,
+        }
+    }
+
+    // Gets the level stored at an index.
+    fn from_index(index: usize) -> Level {
+        match index {
+            0 => Level::Trace,
+            1 => Level::Debug,
+            2 => Level::Info,
+            3 => Level::Warn,
+            4 => Level::Error,
+            _ => Level::Off,
+        }
+    }
+}
+
+// The part of the engine that a message comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Category {
+    Core,
+    Renderer,
+    Assets,
+    Physics,
+    Audio,
+    Input,
+    Network,
+    Game,
+}
+
+impl Category {
+    // Gets every category.
+    pub fn get_all() -> [Category; CATEGORY_COUNT] {
+        [Category::Core, Category::Renderer, Category::Assets, Category::Physics, Category::Audio,
+                Category::Input, Category::Network, Category::Game]
+    }
+
+    // Gets the name of the category as it appears in logs.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            Category::Core => "core",
+            Category::Renderer => "renderer",
+            Category::Assets => "assets",
+            Category::Physics => "physics",
+            Category::Audio => "audio",
+            Category::Input => "input",
+            Category::Network => "network",
+            Category::Game => "game",
+        }
+    }
+
+    // Gets the category with a name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Category> {
+        let name = name.to_lowercase();
+        Category::get_all().iter().find(|c| c.get_name() == name).cloned()
+    }
+}
+
+// A logged message. The time is in seconds since the logger was first used, and the sequence
+// number counts up from 0 with every record.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub sequence: u64,
+    pub time: f64,
+    pub level: Level,
+    pub category: Category,
+    pub thread: String,
+    pub message: String,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:10.3}] {:<5} {}: {}", self.time, self.level.get_name(),
+                self.category.get_name(), self.message)
+    }
+}
+
+// A destination for records.
+pub trait LogSink: Send {
+    fn write(&mut self, record: &Record);
+    fn flush(&mut self) {}
+}
+
+// Writes records to the standard output, or to the standard error for warnings and errors.
+pub struct ConsoleSink {
+    pub level: Level,
+}
+
+impl ConsoleSink {
+    // Creates a ConsoleSink that writes every record it is given.
+    pub fn new() -> ConsoleSink {
+        ConsoleSink { level: Level::Trace }
+    }
+}
+
+impl LogSink for ConsoleSink {
+    fn write(&mut self, record: &Record) {
+        if record.level < self.level { return; }
+        if record.level >= Level::Warn {
+            let _ = writeln!(io::stderr(), "{}", record);
+        } else {
+            let _ = writeln!(io::stdout(), "{}", record);
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+// Writes records to a file through a buffer, which is flushed after every warning and error so
+// that they make it to disk even if the program crashes right after.
+pub struct FileSink {
+    pub level: Level,
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    // Creates a FileSink that writes every record it is given to a new file at a path.
+    pub fn create(fpath: &str) -> Result<FileSink, String> {
+        let file = try!(File::create(fpath).map_err(|e| format!("Unable to create {}: {}", fpath,
+                e)));
+        Ok(FileSink { level: Level::Trace, writer: BufWriter::new(file) })
+    }
+}
+
+impl LogSink for FileSink {
+    fn write(&mut self, record: &Record) {
+        if record.level < self.level { return; }
+        let _ = writeln!(self.writer, "{} ({})", record, record.thread);
+        if record.level >= Level::Warn {
+            let _ = self.writer.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+// The state of the logger that is changed by writing records.
+struct Output {
+    sinks: Vec<Box<LogSink>>,
+    history: VecDeque<Record>,
+    history_capacity: usize,
+    sequence: u64,
+}
+
+// The logger shared by every thread.
+struct Logger {
+    levels: Vec<AtomicUsize>,           // The level of each category by index.
+    epoch: f64,
+    output: Mutex<Output>,
+}
+
+static LOGGER_INIT: Once = Once::new();
+static mut LOGGER: *const Logger = 0 as *const Logger;
+
+// Gets the logger, creating it the first time. It is never freed.
+fn get_logger() -> &'static Logger {
+    unsafe {
+        LOGGER_INIT.call_once(|| {
+            let sinks: Vec<Box<LogSink>> = vec![Box::new(ConsoleSink::new())];
+            LOGGER = Box::into_raw(Box::new(Logger {
+                levels: (0..CATEGORY_COUNT).map(|_| AtomicUsize::new(Level::Info as usize))
+                        .collect(),
+                epoch: time::precise_time_s(),
+                output: Mutex::new(Output { sinks: sinks, history: VecDeque::new(),
+                        history_capacity: DEFAULT_HISTORY_CAPACITY, sequence: 0 }),
+            }));
+        });
+        &*LOGGER
+    }
+}
+
+// Locks the output of the logger. A thread that panicked while logging doesn't stop the others
+// from logging.
+fn lock_output() -> ::std::sync::MutexGuard<'static, Output> {
+    match get_logger().output.lock() {
+        Ok(output) => output,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// Sets the level below which the messages of a category are dropped.
+pub fn set_level(category: Category, level: Level) {
+    get_logger().levels[category as usize].store(level as usize, Ordering::Relaxed);
+}
+
+// Sets the level of every category.
+pub fn set_all_levels(level: Level) {
+    for category in Category::get_all().iter() {
+        set_level(*category, level);
+    }
+}
+
+// Gets the level of a category.
+pub fn get_level(category: Category) -> Level {
+    Level::from_index(get_logger().levels[category as usize].load(Ordering::Relaxed))
+}
+
+// Returns true if messages of a category at a level are logged.
+pub fn is_enabled(category: Category, level: Level) -> bool {
+    level != Level::Off && level as usize >= get_logger().levels[category as usize]
+            .load(Ordering::Relaxed)
+}
+
+// Adds a sink that every record from now on is written to.
+pub fn add_sink(sink: Box<LogSink>) {
+    lock_output().sinks.push(sink);
+}
+
+// Flushes and removes every sink, including the default ConsoleSink.
+pub fn clear_sinks() {
+    let mut output = lock_output();
+    for sink in &mut output.sinks {
+        sink.flush();
+    }
+    output.sinks.clear();
+}
+
+// Flushes every sink.
+pub fn flush() {
+    for sink in &mut lock_output().sinks {
+        sink.flush();
+    }
+}
+
+// Sets the number of records that the history keeps, dropping the oldest ones if it holds more.
+pub fn set_history_capacity(capacity: usize) {
+    let mut output = lock_output();
+    output.history_capacity = capacity;
+    while output.history.len() > capacity {
+        output.history.pop_front();
+    }
+}
+
+// Gets the records in the history with sequence numbers of at least some number, from oldest to
+// newest. Passing 0 gets the whole history.
+pub fn get_history(since: u64) -> Vec<Record> {
+    let output = lock_output();
+    output.history.iter().filter(|r| r.sequence >= since).cloned().collect()
+}
+
+// Gets the sequence number that the next record will have.
+pub fn get_next_sequence() -> u64 {
+    lock_output().sequence
+}
+
+// Empties the history.
+pub fn clear_history() {
+    lock_output().history.clear();
+}
+
+// Logs a message regardless of the level of its category. This is what the macros call once the
+// level has been checked.
+pub fn write(category: Category, level: Level, args: fmt::Arguments) {
+    let logger = get_logger();
+    let thread = thread::current();
+    let mut record = Record { sequence: 0, time: time::precise_time_s() - logger.epoch,
+            level: level, category: category,
+            thread: thread.name().unwrap_or("unnamed").to_string(), message: fmt::format(args) };
+    let mut output = lock_output();
+    record.sequence = output.sequence;
+    output.sequence += 1;
+    for sink in &mut output.sinks {
+        sink.write(&record);
+    }
+    if output.history_capacity == 0 { return; }
+    if output.history.len() >= output.history_capacity {
+        output.history.pop_front();
+    }
+    output.history.push_back(record);
+}
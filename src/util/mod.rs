@@ -1,5 +1,31 @@
+#[macro_use] pub mod log;
+#[macro_use] pub mod profiler;
+pub mod alloc;
+pub mod bc;
 pub mod bmp;
 pub mod common;
+pub mod config;
+pub mod cook;
+pub mod dds;
+pub mod deflate;
+pub mod events;
+pub mod fnt;
+pub mod gltf;
+pub mod hdr;
+pub mod imgdiff;
+pub mod inflate;
+pub mod jobs;
+pub mod json;
+pub mod ktx2;
+pub mod noise;
 pub mod obj;
+pub mod ogg;
+pub mod png;
+pub mod random;
 pub mod rmod;
 pub mod shader;
+pub mod tga;
+pub mod toml;
+pub mod ttf;
+pub mod vorbis;
+pub mod wav;
@@ -0,0 +1,314 @@
+// Utility module with coherent noise for procedural content such as terrain and clouds. Perlin and
+// simplex noise are both built from a permutation table that is shuffled from a seed, so the same
+// seed always gives the same noise. Simplex noise is a bit cheaper in 3D and has fewer directional
+// artifacts. A Fractal stacks octaves of another noise at rising frequencies for detail at every
+// scale.
+//
+// All of the noise gives values in about [-1, 1], and the bake functions remap them to [0, 1] to
+// build heights for a Terrain or grayscale Images.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use self::gl::types::*;
+use util::common::{Image, Pixel};
+use util::random::Random;
+
+// Specifies methods for evaluating noise in 2D and 3D.
+pub trait Noise {
+    fn noise2(&self, x: GLfloat, y: GLfloat) -> GLfloat;
+    fn noise3(&self, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat;
+}
+
+// Builds a permutation of 0 to 255 from a seed, repeated twice so that lookups of a hashed value
+// plus an offset never need to wrap.
+fn build_permutation(seed: u64) -> Vec<u8> {
+    let mut perm: Vec<u8> = (0..256).map(|i| i as u8).collect();
+    Random::new(seed).shuffle(&mut perm);
+    let copy = perm.clone();
+    perm.extend_from_slice(&copy);
+    perm
+}
+
+// Gets the cell of a coordinate wrapped to the size of the permutation table.
+fn wrap(x: GLfloat) -> usize {
+    (x as i32 & 255) as usize
+}
+
+// Eases a fraction so that Perlin noise has a smooth second derivative across cells.
+fn fade(t: GLfloat) -> GLfloat {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: GLfloat, b: GLfloat, t: GLfloat) -> GLfloat {
+    a + (b - a) * t
+}
+
+// Gets the dot product of an offset with one of eight gradients picked by a hash.
+fn grad2(hash: u8, x: GLfloat, y: GLfloat) -> GLfloat {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+// Gets the dot product of an offset with one of the twelve edge gradients of a cube picked by a
+// hash, with four of them repeated to make sixteen.
+fn grad3(hash: u8, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Improved Perlin noise, which blends the gradients at the corners of the grid cell around a point.
+#[derive(Clone, Debug)]
+pub struct Perlin {
+    perm: Vec<u8>,
+}
+
+impl Perlin {
+    // Creates Perlin noise from a seed.
+    pub fn new(seed: u64) -> Perlin {
+        Perlin { perm: build_permutation(seed) }
+    }
+}
+
+impl Noise for Perlin {
+    fn noise2(&self, x: GLfloat, y: GLfloat) -> GLfloat {
+        let p = &self.perm;
+        let (xi, yi) = (wrap(x.floor()), wrap(y.floor()));
+        let (x, y) = (x - x.floor(), y - y.floor());
+        let (u, v) = (fade(x), fade(y));
+        let (a, b) = (p[xi] as usize + yi, p[xi + 1] as usize + yi);
+        let bottom = lerp(grad2(p[a], x, y), grad2(p[b], x - 1.0, y), u);
+        let top = lerp(grad2(p[a + 1], x, y - 1.0), grad2(p[b + 1], x - 1.0, y - 1.0), u);
+        lerp(bottom, top, v)
+    }
+
+    fn noise3(&self, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat {
+        let p = &self.perm;
+        let (xi, yi, zi) = (wrap(x.floor()), wrap(y.floor()), wrap(z.floor()));
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let (a, b) = (p[xi] as usize + yi, p[xi + 1] as usize + yi);
+        let (aa, ab) = (p[a] as usize + zi, p[a + 1] as usize + zi);
+        let (ba, bb) = (p[b] as usize + zi, p[b + 1] as usize + zi);
+        let near = lerp(
+                lerp(grad3(p[aa], x, y, z), grad3(p[ba], x - 1.0, y, z), u),
+                lerp(grad3(p[ab], x, y - 1.0, z), grad3(p[bb], x - 1.0, y - 1.0, z), u), v);
+        let (x1, y1, z1) = (x - 1.0, y - 1.0, z - 1.0);
+        let far = lerp(
+                lerp(grad3(p[aa + 1], x, y, z1), grad3(p[ba + 1], x1, y, z1), u),
+                lerp(grad3(p[ab + 1], x, y1, z1), grad3(p[bb + 1], x1, y1, z1), u), v);
+        lerp(near, far, w)
+    }
+}
+
+// Simplex noise, which sums the gradients at the corners of the simplex around a point. A simplex
+// has one more corner than there are dimensions, which is fewer than a grid cell.
+#[derive(Clone, Debug)]
+pub struct Simplex {
+    perm: Vec<u8>,
+}
+
+impl Simplex {
+    // Creates simplex noise from a seed.
+    pub fn new(seed: u64) -> Simplex {
+        Simplex { perm: build_permutation(seed) }
+    }
+}
+
+// Gets the contribution of a corner of a 2D simplex at an offset from it.
+fn corner2(hash: u8, x: GLfloat, y: GLfloat) -> GLfloat {
+    let t = 0.5 - x * x - y * y;
+    if t < 0.0 { 0.0 } else { t * t * t * t * grad2(hash, x, y) }
+}
+
+// Gets the contribution of a corner of a 3D simplex at an offset from it.
+fn corner3(hash: u8, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat {
+    let t = 0.6 - x * x - y * y - z * z;
+    if t < 0.0 { 0.0 } else { t * t * t * t * grad3(hash, x, y, z) }
+}
+
+impl Noise for Simplex {
+    fn noise2(&self, x: GLfloat, y: GLfloat) -> GLfloat {
+        let f2 = 0.5 * (3.0f32.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0f32.sqrt()) / 6.0;
+
+        // Skew the point onto the grid of simplices and find which of the two in the cell it's in.
+        let s = (x + y) * f2;
+        let (i, j) = ((x + s).floor(), (y + s).floor());
+        let t = (i + j) * g2;
+        let (x0, y0) = (x - (i - t), y - (j - t));
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let (x1, y1) = (x0 - i1 as GLfloat + g2, y0 - j1 as GLfloat + g2);
+        let (x2, y2) = (x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2);
+
+        let p = &self.perm;
+        let (ii, jj) = (wrap(i), wrap(j));
+        let h0 = p[ii + p[jj] as usize];
+        let h1 = p[ii + i1 + p[jj + j1] as usize];
+        let h2 = p[ii + 1 + p[jj + 1] as usize];
+        70.0 * (corner2(h0, x0, y0) + corner2(h1, x1, y1) + corner2(h2, x2, y2))
+    }
+
+    fn noise3(&self, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat {
+        let (f3, g3) = (1.0 / 3.0, 1.0 / 6.0);
+
+        // Skew the point onto the grid of simplices and find which of the six in the cell it's in
+        // from the order of its coordinates.
+        let s = (x + y + z) * f3;
+        let (i, j, k) = ((x + s).floor(), (y + s).floor(), (z + s).floor());
+        let t = (i + j + k) * g3;
+        let (x0, y0, z0) = (x - (i - t), y - (j - t), z - (k - t));
+        let (o1, o2) = if x0 >= y0 {
+            if y0 >= z0 { ((1, 0, 0), (1, 1, 0)) }
+            else if x0 >= z0 { ((1, 0, 0), (1, 0, 1)) }
+            else { ((0, 0, 1), (1, 0, 1)) }
+        } else {
+            if y0 < z0 { ((0, 0, 1), (0, 1, 1)) }
+            else if x0 < z0 { ((0, 1, 0), (0, 1, 1)) }
+            else { ((0, 1, 0), (1, 1, 0)) }
+        };
+        let offset = |c: GLfloat, o: usize, n: GLfloat| c - o as GLfloat + n * g3;
+
+        let p = &self.perm;
+        let (ii, jj, kk) = (wrap(i), wrap(j), wrap(k));
+        let hash = |a: usize, b: usize, c: usize| {
+            p[ii + a + p[jj + b + p[kk + c] as usize] as usize]
+        };
+        corner3(hash(0, 0, 0), x0, y0, z0) * 32.0 +
+                corner3(hash(o1.0, o1.1, o1.2), offset(x0, o1.0, 1.0), offset(y0, o1.1, 1.0),
+                        offset(z0, o1.2, 1.0)) * 32.0 +
+                corner3(hash(o2.0, o2.1, o2.2), offset(x0, o2.0, 2.0), offset(y0, o2.1, 2.0),
+                        offset(z0, o2.2, 2.0)) * 32.0 +
+                corner3(hash(1, 1, 1), offset(x0, 1, 3.0), offset(y0, 1, 3.0),
+                        offset(z0, 1, 3.0)) * 32.0
+    }
+}
+
+// How a Fractal combines its octaves. Fbm sums them for rolling hills and soft clouds. Turbulence
+// sums their absolute values, which gives sharp creases that look like billowing smoke. Ridged
+// inverts the creases into sharp ridges like mountain ranges.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FractalKind {
+    Fbm,
+    Turbulence,
+    Ridged,
+}
+
+// Octaves of a noise stacked at rising frequencies. Each octave's frequency is the last one's
+// times the lacunarity, and its amplitude is the last one's times the gain.
+#[derive(Clone, Debug)]
+pub struct Fractal<N: Noise> {
+    pub source: N,
+    pub kind: FractalKind,
+    pub octaves: usize,
+    pub frequency: GLfloat,
+    pub lacunarity: GLfloat,
+    pub gain: GLfloat,
+}
+
+impl<N: Noise> Fractal<N> {
+    // Creates fBm from a noise and a number of octaves that each double the frequency and halve
+    // the amplitude.
+    pub fn new(source: N, octaves: usize) -> Fractal<N> {
+        Fractal { source: source, kind: FractalKind::Fbm, octaves: octaves, frequency: 1.0,
+                lacunarity: 2.0, gain: 0.5 }
+    }
+
+    // Creates a fractal of a kind from a noise and a number of octaves.
+    pub fn with_kind(source: N, kind: FractalKind, octaves: usize) -> Fractal<N> {
+        let mut fractal = Fractal::new(source, octaves);
+        fractal.kind = kind;
+        fractal
+    }
+
+    // Sums the octaves given a function that evaluates the source noise at a frequency, and
+    // rescales the sum back to about [-1, 1].
+    fn stack<F>(&self, sample: F) -> GLfloat where F: Fn(GLfloat) -> GLfloat {
+        let (mut total, mut max) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (self.frequency, 1.0);
+        for _ in 0..self.octaves {
+            let n = sample(frequency);
+            total += amplitude * match self.kind {
+                FractalKind::Fbm => n,
+                FractalKind::Turbulence => n.abs(),
+                FractalKind::Ridged => (1.0 - n.abs()) * (1.0 - n.abs()),
+            };
+            max += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+        if max <= 0.0 { return 0.0; }
+        match self.kind {
+            FractalKind::Fbm => total / max,
+            _ => total / max * 2.0 - 1.0,
+        }
+    }
+}
+
+impl<N: Noise> Noise for Fractal<N> {
+    fn noise2(&self, x: GLfloat, y: GLfloat) -> GLfloat {
+        self.stack(|f| self.source.noise2(x * f, y * f))
+    }
+
+    fn noise3(&self, x: GLfloat, y: GLfloat, z: GLfloat) -> GLfloat {
+        self.stack(|f| self.source.noise3(x * f, y * f, z * f))
+    }
+}
+
+// Remaps noise from [-1, 1] to [0, 1].
+fn to_unit(n: GLfloat) -> GLfloat {
+    (n * 0.5 + 0.5).max(0.0).min(1.0)
+}
+
+// Bakes 2D noise into a grid of heights in [0, 1], one row after another. The scale is the size of
+// a cell in noise space, so smaller scales give smoother heights.
+pub fn bake_heights<N: Noise>(noise: &N, columns: usize, rows: usize, scale: GLfloat)
+        -> Vec<GLfloat> {
+    let mut heights = Vec::with_capacity(columns * rows);
+    for row in 0..rows {
+        for column in 0..columns {
+            heights.push(to_unit(noise.noise2(column as GLfloat * scale, row as GLfloat * scale)));
+        }
+    }
+    heights
+}
+
+// Converts values in [0, 1] to an opaque grayscale Image.
+fn to_image(values: &[GLfloat], width: u32, height: u32) -> Image {
+    let data = values.iter().map(|v| {
+        let gray = (v * 255.0).round() as u8;
+        Pixel { red: gray, green: gray, blue: gray, alpha: 255 }
+    }).collect();
+    Image { width: width, height: height, data: data }
+}
+
+// Bakes 2D noise into a grayscale Image that can be used as a heightmap or a texture. The scale is
+// the size of a pixel in noise space.
+pub fn bake_image<N: Noise>(noise: &N, width: u32, height: u32, scale: GLfloat) -> Image {
+    to_image(&bake_heights(noise, width as usize, height as usize, scale), width, height)
+}
+
+// Bakes a slice of 3D noise at a depth into a grayscale Image. Moving the depth over time animates
+// the noise smoothly, such as for drifting clouds.
+pub fn bake_image_slice<N: Noise>(noise: &N, width: u32, height: u32, scale: GLfloat,
+        depth: GLfloat) -> Image {
+    let mut values = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            values.push(to_unit(noise.noise3(x as GLfloat * scale, y as GLfloat * scale, depth)));
+        }
+    }
+    to_image(&values, width, height)
+}
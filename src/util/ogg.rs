@@ -0,0 +1,536 @@
+// Utility module that allows for decoding of an Ogg Vorbis file given a path to the file, either
+// all at once with decode_ogg() or a little at a time with a VorbisStream, which is meant for long
+// tracks such as music that shouldn't be decoded into memory in full. The Vorbis packets are read
+// out of the pages of the Ogg container here and decoded by util/vorbis.rs. Only the first logical
+// stream of a file is played, so chained files stop after their first part.
+//
+// A VorbisStream can seek to any frame. The file's pages are indexed by their granule positions,
+// which count the frames decoded by the end of each page, when the stream is opened. A seek starts
+// decoding a few pages before the frame and throws the samples before it away, so playback picks up
+// at exactly that frame, which is what makes looping seamless.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use util::common;
+use util::vorbis::VorbisDecoder;
+
+// Flags in the header of a page.
+const PAGE_CONTINUED: u8 = 0x01;
+const PAGE_LAST: u8 = 0x04;
+
+// Size of a page header without its table of segment lengths.
+const PAGE_HEADER_SIZE: usize = 27;
+
+// Number of frames decoded at a time by decode_ogg().
+const DECODE_FRAMES: usize = 4096;
+
+// Return value for a decoded Ogg Vorbis file. This contains the sample rate, channel count, and
+// samples of the first logical stream.
+pub struct DecodedOGG {
+    pub sound: common::Sound,
+}
+
+// A page of an Ogg file. The body holds the segments listed in the lacing table back to back, where
+// a segment of fewer than 255 bytes ends a packet.
+pub struct OggPage {
+    pub flags: u8,
+    pub granule: i64,
+    pub serial: u32,
+    pub sequence: u32,
+    pub lacing: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+// The location of a page in a file along with its granule position, which is -1 if no packet ends
+// in the page.
+#[derive(Copy, Clone, Debug)]
+pub struct PageInfo {
+    pub offset: u64,
+    pub granule: i64,
+}
+
+// A packet read from an Ogg file. The granule position is only given for the last packet that ends
+// in a page.
+pub struct OggPacket {
+    pub data: Vec<u8>,
+    pub granule: Option<i64>,
+    pub last: bool,
+}
+
+// Builds the table for the CRC-32 of Ogg pages, which uses the polynomial 0x04C11DB7 without
+// reflection.
+fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = (i as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 { (crc << 1) ^ 0x04C11DB7 } else { crc << 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+// Reads until a buffer is full or the file ends, and returns the number of bytes read.
+fn read_fully<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, String> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(read)
+}
+
+// Reads a little endian integer of n bytes from the data vector.
+fn read_le(data: &[u8], cursor: usize, n: usize) -> u64 {
+    (0..n).fold(0, |value, i| value | (data[cursor + i] as u64) << (8 * i))
+}
+
+// Reads a page header along with its lacing table, or None at the end of the file. A page that is
+// cut off by the end of the file also counts as the end, since streams are often cut short.
+fn read_page_header<R: Read>(reader: &mut R) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let mut header = vec![0; PAGE_HEADER_SIZE];
+    if try!(read_fully(reader, &mut header)) < PAGE_HEADER_SIZE {
+        return Ok(None);
+    }
+    if &header[0..4] != b"OggS" || header[4] != 0 {
+        return Err("Ogg page has incorrect magic values.".to_string());
+    }
+    let segments = header[26] as usize;
+    header.resize(PAGE_HEADER_SIZE + segments, 0);
+    if try!(read_fully(reader, &mut header[PAGE_HEADER_SIZE..])) < segments {
+        return Ok(None);
+    }
+    let body_size = header[PAGE_HEADER_SIZE..].iter().fold(0, |sum, &l| sum + l as usize);
+    Ok(Some((header, body_size)))
+}
+
+// Reads the next page and checks its CRC, or returns None at the end of the file.
+pub fn read_page<R: Read>(reader: &mut R, table: &[u32; 256]) -> Result<Option<OggPage>, String> {
+    let (mut header, body_size) = match try!(read_page_header(reader)) {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let mut body = vec![0; body_size];
+    if try!(read_fully(reader, &mut body)) < body_size {
+        return Ok(None);
+    }
+    let crc = read_le(&header, 22, 4) as u32;
+    for byte in &mut header[22..26] {
+        *byte = 0;
+    }
+    let computed = header.iter().chain(&body).fold(0u32, |crc, &byte| {
+        (crc << 8) ^ table[((crc >> 24) as u8 ^ byte) as usize]
+    });
+    if crc != computed {
+        return Err("Ogg page has an incorrect checksum.".to_string());
+    }
+    Ok(Some(OggPage { flags: header[5], granule: read_le(&header, 6, 8) as i64,
+            serial: read_le(&header, 14, 4) as u32, sequence: read_le(&header, 18, 4) as u32,
+            lacing: header[PAGE_HEADER_SIZE..].to_vec(), body: body }))
+}
+
+// Finds every page of the first logical stream in a file without reading their bodies.
+pub fn scan_pages<R: Read + Seek>(reader: &mut R) -> Result<Vec<PageInfo>, String> {
+    let mut pages = Vec::new();
+    let mut serial = None;
+    let mut offset = try!(reader.seek(SeekFrom::Start(0)).map_err(|e| e.to_string()));
+    while let Some((header, body_size)) = try!(read_page_header(reader)) {
+        let page_serial = read_le(&header, 14, 4) as u32;
+        if serial.is_none() {
+            serial = Some(page_serial);
+        }
+        if serial == Some(page_serial) {
+            pages.push(PageInfo { offset: offset, granule: read_le(&header, 6, 8) as i64 });
+            if header[5] & PAGE_LAST != 0 { break; }
+        }
+        offset += (header.len() + body_size) as u64;
+        try!(reader.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string()));
+    }
+    Ok(pages)
+}
+
+// Puts the packets of the first logical stream of an Ogg file back together from its pages.
+pub struct PacketReader<R> {
+    reader: R,
+    crc_table: [u32; 256],
+    serial: Option<u32>,
+    partial: Vec<u8>,                   // The start of a packet that continues on the next page.
+    continuing: bool,
+    packets: VecDeque<OggPacket>,
+    ended: bool,
+}
+
+impl<R: Read + Seek> PacketReader<R> {
+    // Creates a reader that starts at the current position of a file.
+    pub fn new(reader: R) -> PacketReader<R> {
+        PacketReader { reader: reader, crc_table: crc_table(), serial: None, partial: Vec::new(),
+                continuing: false, packets: VecDeque::new(), ended: false }
+    }
+
+    // Reads the next packet, or returns None at the end of the stream.
+    pub fn next_packet(&mut self) -> Result<Option<OggPacket>, String> {
+        while self.packets.is_empty() && !self.ended {
+            let page = match try!(read_page(&mut self.reader, &self.crc_table)) {
+                Some(page) => page,
+                None => {
+                    self.ended = true;
+                    break;
+                },
+            };
+            if self.serial.is_none() {
+                self.serial = Some(page.serial);
+            }
+            if self.serial == Some(page.serial) {
+                self.add_page(page);
+            }
+        }
+        Ok(self.packets.pop_front())
+    }
+
+    // Moves to the page at an offset in the file. Any packet that continues from the page before
+    // it is skipped.
+    pub fn seek(&mut self, offset: u64) -> Result<(), String> {
+        try!(self.reader.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string()));
+        self.partial.clear();
+        self.continuing = false;
+        self.packets.clear();
+        self.ended = false;
+        Ok(())
+    }
+
+    // Splits a page into packets, joining the first one onto the end of the last page's packet.
+    fn add_page(&mut self, page: OggPage) {
+        let continued = page.flags & PAGE_CONTINUED != 0;
+        if !continued {
+            self.partial.clear();
+        }
+        // The rest of a packet whose start was never read can't be decoded, so it is skipped.
+        let mut skipping = continued && !self.continuing;
+        let first = self.packets.len();
+        let mut start = 0;
+        for &length in &page.lacing {
+            let end = start + length as usize;
+            if !skipping {
+                self.partial.extend_from_slice(&page.body[start..end]);
+            }
+            start = end;
+            if length < 255 {
+                if !skipping {
+                    let data = ::std::mem::replace(&mut self.partial, Vec::new());
+                    self.packets.push_back(OggPacket { data: data, granule: None, last: false });
+                }
+                skipping = false;
+            }
+        }
+        self.continuing = !skipping && page.lacing.last().map_or(false, |&l| l == 255);
+        if self.packets.len() > first {
+            let packet = self.packets.back_mut().unwrap();
+            if page.granule >= 0 {
+                packet.granule = Some(page.granule);
+            }
+            packet.last = page.flags & PAGE_LAST != 0;
+        }
+        if page.flags & PAGE_LAST != 0 {
+            self.ended = true;
+        }
+    }
+}
+
+// Decodes an Ogg Vorbis file a little at a time.
+pub struct VorbisStream {
+    packets: PacketReader<BufReader<File>>,
+    decoder: VorbisDecoder,
+    pages: Vec<PageInfo>,
+    frame_count: u64,
+    pending: Vec<f32>,                  // Decoded samples that haven't been read yet.
+    consumed: usize,
+    end: Option<u64>,                   // The frame at the end of the pending samples, if known.
+    seek_target: Option<u64>,
+    finished: bool,
+}
+
+impl VorbisStream {
+    // Opens an Ogg Vorbis file and reads its headers.
+    pub fn open(fpath: &str) -> Result<VorbisStream, String> {
+        let file = try!(File::open(fpath).map_err(|e| e.to_string()));
+        let mut reader = BufReader::new(file);
+        let pages = try!(scan_pages(&mut reader));
+        let frame_count = pages.iter().rev().find(|p| p.granule >= 0).map_or(0, |p| p.granule);
+        let mut packets = PacketReader::new(reader);
+        try!(packets.seek(0));
+        let decoder = try!(read_headers(&mut packets));
+        Ok(VorbisStream { packets: packets, decoder: decoder, pages: pages,
+                frame_count: frame_count as u64, pending: Vec::new(), consumed: 0, end: Some(0),
+                seek_target: None, finished: false })
+    }
+
+    // Gets the sample rate.
+    pub fn get_sample_rate(&self) -> u32 {
+        self.decoder.sample_rate
+    }
+
+    // Gets the number of channels.
+    pub fn get_channels(&self) -> u16 {
+        self.decoder.channels
+    }
+
+    // Gets the length of the stream in frames.
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    // Gets the decoder, such as to look at the comments of the file.
+    pub fn get_decoder(&self) -> &VorbisDecoder {
+        &self.decoder
+    }
+
+    // Gets the frame that the next read starts at.
+    pub fn get_position(&self) -> u64 {
+        match (self.seek_target, self.end) {
+            (Some(target), _) => target,
+            (None, Some(end)) => {
+                let channels = self.decoder.channels as usize;
+                end.saturating_sub(((self.pending.len() - self.consumed) / channels) as u64)
+            },
+            (None, None) => 0,
+        }
+    }
+
+    // Reads the next frames into interleaved samples and returns how many frames were read, which
+    // is only fewer than fit once the stream has ended.
+    pub fn read(&mut self, samples: &mut [f32]) -> Result<usize, String> {
+        let channels = self.decoder.channels as usize;
+        let wanted = samples.len() / channels * channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.consumed == self.pending.len() {
+                self.pending.clear();
+                self.consumed = 0;
+                if !try!(self.decode_next()) { break; }
+                continue;
+            }
+            let count = cmp::min(wanted - written, self.pending.len() - self.consumed);
+            samples[written..(written + count)].copy_from_slice(
+                    &self.pending[self.consumed..(self.consumed + count)]);
+            written += count;
+            self.consumed += count;
+        }
+        Ok(written / channels)
+    }
+
+    // Moves to a frame, which is clamped to the length of the stream.
+    pub fn seek(&mut self, frame: u64) -> Result<(), String> {
+        let frame = cmp::min(frame, self.frame_count);
+        self.decoder.reset();
+        self.pending.clear();
+        self.consumed = 0;
+        self.finished = false;
+        self.seek_target = Some(frame);
+
+        // Decoding starts on the page after the last one that ends at least two long blocks before
+        // the frame, which leaves room for the packets that are needed before the first sample
+        // comes out. The start has to be before the last page so that a granule position is seen
+        // before the end of the stream, or it couldn't be told how much of the end was cut off.
+        let preroll = self.decoder.get_long_block_size() as u64 * 2;
+        let page = self.pages.iter().rposition(|page| {
+            page.granule >= 0 && page.granule as u64 + preroll <= frame
+        });
+        let start = match page {
+            Some(page) => cmp::min(page + 1, self.pages.len().saturating_sub(2)),
+            None => 0,
+        };
+        if start == 0 {
+            try!(self.packets.seek(0));
+            try!(read_headers(&mut self.packets));
+            self.end = Some(0);
+        } else {
+            try!(self.packets.seek(self.pages[start].offset));
+            self.end = None;
+        }
+        Ok(())
+    }
+
+    // Decodes the next packet onto the pending samples, dropping any samples before a frame that
+    // was seeked to. Returns false at the end of the stream.
+    fn decode_next(&mut self) -> Result<bool, String> {
+        let channels = self.decoder.channels as usize;
+        loop {
+            if self.finished { return Ok(false); }
+            let packet = match try!(self.packets.next_packet()) {
+                Some(packet) => packet,
+                None => {
+                    self.finished = true;
+                    return Ok(false);
+                },
+            };
+            self.finished = packet.last;
+            let start = self.pending.len();
+            let frames = try!(self.decoder.decode(&packet.data, &mut self.pending)) as u64;
+
+            // The granule position of the last page can end the stream partway through the last
+            // block. After a seek, the first granule position also tells where the samples are.
+            self.end = match (self.end, packet.granule) {
+                (Some(end), Some(granule)) if packet.last && end + frames > granule as u64 => {
+                    let kept = (granule as u64).saturating_sub(end);
+                    self.pending.truncate(start + kept as usize * channels);
+                    Some(end + kept)
+                },
+                (Some(end), _) => Some(end + frames),
+                (None, granule) => granule.map(|granule| granule as u64),
+            };
+
+            let (end, target) = match (self.end, self.seek_target) {
+                (_, None) => return Ok(true),
+                (None, Some(_)) => continue,
+                (Some(end), Some(target)) => (end, target),
+            };
+            if end < target && !self.finished {
+                self.pending.clear();
+                self.consumed = 0;
+                continue;
+            }
+            let buffered = ((self.pending.len() - self.consumed) / channels) as u64;
+            let skipped = cmp::min(target.saturating_sub(end.saturating_sub(buffered)), buffered);
+            self.consumed += skipped as usize * channels;
+            self.seek_target = None;
+            return Ok(true);
+        }
+    }
+}
+
+// Reads the three header packets at the start of a stream and creates a decoder from them.
+fn read_headers<R: Read + Seek>(packets: &mut PacketReader<R>) -> Result<VorbisDecoder, String> {
+    let mut headers = Vec::new();
+    while headers.len() < 3 {
+        match try!(packets.next_packet()) {
+            Some(packet) => headers.push(packet.data),
+            None => return Err("Ogg file is missing its Vorbis headers.".to_string()),
+        }
+    }
+    VorbisDecoder::new(&headers[0], &headers[1], &headers[2])
+}
+
+// Decodes an Ogg Vorbis file given a path to the file and returns a DecodedOGG struct containing
+// the samples, sample rate, and number of channels of the sound.
+pub fn decode_ogg(fpath: &str) -> Result<DecodedOGG, String> {
+    let mut stream = try!(VorbisStream::open(fpath));
+    let channels = stream.get_channels() as usize;
+    // The frame count comes from the file, so the samples grow as they are decoded instead of
+    // trusting it for the size of the buffer.
+    let mut samples = Vec::new();
+    let mut buffer = vec![0.0; DECODE_FRAMES * channels];
+    loop {
+        let frames = try!(stream.read(&mut buffer));
+        if frames == 0 { break; }
+        samples.extend_from_slice(&buffer[..(frames * channels)]);
+    }
+    Ok(DecodedOGG { sound: common::Sound { sample_rate: stream.get_sample_rate(),
+            channels: stream.get_channels(), samples: samples } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use util::common;
+
+    // A stereo file with 600 frames at 8000 Hz of a 440 Hz sine on the left channel and an 880 Hz
+    // sine on the right, both with an amplitude of 0.5.
+    static TONE: &'static str = "assets/tone.ogg";
+
+    // Reads the tone file into memory.
+    fn read_tone() -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(TONE).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    // Changes a byte range of the page at an offset and updates the page's checksum.
+    fn patch_page(data: &mut Vec<u8>, page: usize, at: usize, bytes: &[u8]) {
+        data[(page + at)..(page + at + bytes.len())].copy_from_slice(bytes);
+        let segments = data[page + 26] as usize;
+        let header_size = PAGE_HEADER_SIZE + segments;
+        let body_size = data[(page + PAGE_HEADER_SIZE)..(page + header_size)].iter()
+                .fold(0, |sum, &l| sum + l as usize);
+        for byte in &mut data[(page + 22)..(page + 26)] {
+            *byte = 0;
+        }
+        let table = crc_table();
+        let crc = data[page..(page + header_size + body_size)].iter().fold(0u32, |crc, &byte| {
+            (crc << 8) ^ table[((crc >> 24) as u8 ^ byte) as usize]
+        });
+        for i in 0..4 {
+            data[page + 22 + i] = (crc >> (8 * i)) as u8;
+        }
+    }
+
+    #[test]
+    fn decodes_vorbis() {
+        let sound = decode_ogg(TONE).unwrap().sound;
+        assert_eq!((sound.sample_rate, sound.channels), (8000, 2));
+        assert_eq!(sound.get_frame_count(), 600);
+        for t in 0..600 {
+            let left = 0.5 * (2.0 * PI * 440.0 * t as f32 / 8000.0).sin();
+            let right = 0.5 * (2.0 * PI * 880.0 * t as f32 / 8000.0).sin();
+            assert!((sound.samples[2 * t] - left).abs() < 0.1);
+            assert!((sound.samples[2 * t + 1] - right).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn seeks_to_frames() {
+        let sound = decode_ogg(TONE).unwrap().sound;
+        let mut stream = VorbisStream::open(TONE).unwrap();
+        assert_eq!(stream.get_frame_count(), 600);
+        for &frame in [450, 0, 300].iter() {
+            stream.seek(frame).unwrap();
+            assert_eq!(stream.get_position(), frame);
+            let mut samples = vec![0.0; 20];
+            assert_eq!(stream.read(&mut samples).unwrap(), 10);
+            let start = frame as usize * 2;
+            assert_eq!(&samples[..], &sound.samples[start..(start + 20)]);
+        }
+    }
+
+    #[test]
+    fn stops_at_truncated_pages() {
+        let data = read_tone();
+        let path = common::write_test_file("truncated.ogg", &data[..(data.len() - 100)]);
+        let sound = decode_ogg(&path).unwrap().sound;
+        assert!(sound.get_frame_count() < 600);
+        let path = common::write_test_file("headers.ogg", &data[..200]);
+        assert!(decode_ogg(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_channels() {
+        // The channel count is the 12th byte of the identification packet on the first page.
+        let mut data = read_tone();
+        let segments = data[26] as usize;
+        patch_page(&mut data, 0, PAGE_HEADER_SIZE + segments + 11, &[0]);
+        let path = common::write_test_file("empty.ogg", &data);
+        assert!(decode_ogg(&path).is_err());
+    }
+
+    #[test]
+    fn ignores_oversized_granule() {
+        // The granule position of the last page would need terabytes if it were trusted for the
+        // size of the samples. Without it the end of the last block isn't cut off, so a little
+        // more than the 600 frames come out.
+        let mut data = read_tone();
+        let last = (0..(data.len() - 4)).rev().find(|&i| &data[i..(i + 4)] == b"OggS").unwrap();
+        patch_page(&mut data, last, 6, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00]);
+        let path = common::write_test_file("granule.ogg", &data);
+        let frames = decode_ogg(&path).unwrap().sound.get_frame_count();
+        assert!(frames >= 600 && frames < 1000);
+    }
+}
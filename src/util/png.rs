@@ -0,0 +1,485 @@
+// Utility module that allows for decoding of a PNG given a path to the file. Every color type and
+// bit depth is supported, along with palettes, tRNS transparency, and Adam7 interlacing. 16 bit
+// samples are reduced to 8 bits, and gamma and color profile chunks are ignored. Chunk CRCs are
+// not checked since the zlib checksum already covers the pixels. Images can also be encoded to
+// 8-bit RGB or RGBA PNGs, picking the filter for each row that is likely to compress best.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Write};
+use util::common;
+use util::deflate;
+use util::inflate;
+
+// Return value for a decoded PNG file. This contains a width, height, and an array of pixels with
+// color and alpha information.
+pub struct DecodedPNG {
+    pub image: common::Image,
+}
+
+// Every PNG file starts with these 8 bytes.
+static PNG_MAGIC: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+// The starting column, starting row, column step, and row step of each Adam7 pass.
+static ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8),
+        (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+// The fields of the IHDR chunk along with the palette and transparency chunks.
+struct Header {
+    width: u32,
+    height: u32,
+    pixels: usize,                      // The number of pixels, which fits in a u32.
+    depth: u8,
+    color_type: u8,
+    interlaced: bool,
+    palette: Vec<common::Pixel>,
+    transparent: Option<[u16; 3]>,      // The color key for gray and RGB images.
+}
+
+impl Header {
+    // Gets the number of samples in each pixel.
+    fn get_channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    // Gets the number of bytes in a row of some width, not counting the filter byte.
+    fn get_row_size(&self, width: u32) -> usize {
+        (width as usize * self.get_channels() * self.depth as usize + 7) / 8
+    }
+}
+
+// Reads a big endian u32 at an offset.
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, String> {
+    if offset + 4 > data.len() {
+        return Err("PNG file is too small.".to_string());
+    }
+    Ok((data[offset] as u32) << 24 | (data[offset + 1] as u32) << 16 |
+            (data[offset + 2] as u32) << 8 | data[offset + 3] as u32)
+}
+
+// Reads the IHDR chunk.
+fn read_header(chunk: &[u8]) -> Result<Header, String> {
+    if chunk.len() != 13 {
+        return Err("PNG header has an incorrect size.".to_string());
+    }
+    let (width, height) = (try!(read_u32(chunk, 0)), try!(read_u32(chunk, 4)));
+    let pixels = try!(width.checked_mul(height).ok_or("PNG image is too large.".to_string()));
+    let header = Header { width: width, height: height, pixels: pixels as usize,
+            depth: chunk[8], color_type: chunk[9], interlaced: chunk[12] == 1,
+            palette: Vec::new(), transparent: None };
+    let valid_depth = match header.color_type {
+        0 => [1, 2, 4, 8, 16].contains(&header.depth),
+        3 => [1, 2, 4, 8].contains(&header.depth),
+        2 | 4 | 6 => header.depth == 8 || header.depth == 16,
+        _ => return Err("Unsupported PNG color type.".to_string()),
+    };
+    if !valid_depth {
+        return Err("Unsupported PNG bit depth.".to_string());
+    }
+    if chunk[10] != 0 || chunk[11] != 0 || chunk[12] > 1 {
+        return Err("Unsupported PNG compression, filter, or interlace method.".to_string());
+    }
+    if header.width == 0 || header.height == 0 {
+        return Err("PNG image is empty.".to_string());
+    }
+    Ok(header)
+}
+
+// Gets the Paeth predictor of a byte from the bytes to its left, above, and above left.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// Undoes the filters of the rows of an image in place and returns the unfiltered rows. Each row
+// starts with its filter type.
+fn unfilter(data: &[u8], row_size: usize, rows: u32, bpp: usize) -> Result<Vec<u8>, String> {
+    let mut out = vec![0u8; row_size * rows as usize];
+    for row in 0..(rows as usize) {
+        let start = row * (row_size + 1);
+        let filter = data[start];
+        let source = &data[(start + 1)..(start + 1 + row_size)];
+        let (previous, current) = out.split_at_mut(row * row_size);
+        let current = &mut current[..row_size];
+        let above = if row == 0 { None } else { Some(&previous[((row - 1) * row_size)..]) };
+        for i in 0..row_size {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = above.map_or(0, |above| above[i]);
+            let c = if i >= bpp { above.map_or(0, |above| above[i - bpp]) } else { 0 };
+            current[i] = match filter {
+                0 => source[i],
+                1 => source[i].wrapping_add(a),
+                2 => source[i].wrapping_add(b),
+                3 => source[i].wrapping_add(((a as u16 + b as u16) / 2) as u8),
+                4 => source[i].wrapping_add(paeth(a, b, c)),
+                _ => return Err("Invalid PNG filter type.".to_string()),
+            };
+        }
+    }
+    Ok(out)
+}
+
+// Reads the raw value of a sample from an unfiltered row, which is what color keys compare to.
+fn read_sample(row: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => (row[index * 2] as u16) << 8 | row[index * 2 + 1] as u16,
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << depth) - 1)
+        },
+    }
+}
+
+// Converts a sample to 8 bits.
+fn to_byte(sample: u16, depth: u8) -> u8 {
+    match depth {
+        16 => (sample >> 8) as u8,
+        8 => sample as u8,
+        _ => (sample as u32 * 255 / ((1 << depth) - 1)) as u8,
+    }
+}
+
+// Converts an unfiltered row to pixels.
+fn read_row(header: &Header, row: &[u8], width: u32) -> Result<Vec<common::Pixel>, String> {
+    let channels = header.get_channels();
+    let mut pixels = Vec::with_capacity(width as usize);
+    for x in 0..(width as usize) {
+        let mut samples = [0u16; 4];
+        for c in 0..channels {
+            samples[c] = read_sample(row, x * channels + c, header.depth);
+        }
+        let keyed = match header.transparent {
+            Some(key) => match header.color_type {
+                0 => samples[0] == key[0],
+                2 => samples[..3] == key[..],
+                _ => false,
+            },
+            None => false,
+        };
+        let opaque = if keyed { 0 } else { 255 };
+        let depth = header.depth;
+        pixels.push(match header.color_type {
+            0 => {
+                let v = to_byte(samples[0], depth);
+                common::Pixel { red: v, green: v, blue: v, alpha: opaque }
+            },
+            2 => common::Pixel { red: to_byte(samples[0], depth), green: to_byte(samples[1], depth),
+                    blue: to_byte(samples[2], depth), alpha: opaque },
+            3 => match header.palette.get(samples[0] as usize) {
+                Some(p) => common::Pixel { red: p.red, green: p.green, blue: p.blue,
+                        alpha: p.alpha },
+                None => return Err("PNG palette index is out of range.".to_string()),
+            },
+            4 => {
+                let v = to_byte(samples[0], depth);
+                common::Pixel { red: v, green: v, blue: v, alpha: to_byte(samples[1], depth) }
+            },
+            _ => common::Pixel { red: to_byte(samples[0], depth), green: to_byte(samples[1], depth),
+                    blue: to_byte(samples[2], depth), alpha: to_byte(samples[3], depth) },
+        });
+    }
+    Ok(pixels)
+}
+
+// Decodes the pixels of a subimage at the start of some decompressed data and returns them along
+// with the number of bytes used.
+fn read_subimage(header: &Header, data: &[u8], width: u32, height: u32)
+        -> Result<(Vec<common::Pixel>, usize), String> {
+    let row_size = header.get_row_size(width);
+    let size = (row_size + 1) * height as usize;
+    if size > data.len() {
+        return Err("PNG image data is too small.".to_string());
+    }
+    let bpp = cmp::max((header.get_channels() * header.depth as usize + 7) / 8, 1);
+    let rows = try!(unfilter(&data[..size], row_size, height, bpp));
+    // A subimage is never larger than the image, so this can't overflow.
+    let mut pixels = Vec::with_capacity(width as usize * height as usize);
+    for row in rows.chunks(row_size) {
+        pixels.extend(try!(read_row(header, row, width)));
+    }
+    Ok((pixels, size))
+}
+
+// Gets the position, spacing, and size of the non-empty Adam7 passes of an image. The sizes are
+// worked out in u64 since the image can be as wide as a u32 allows.
+fn get_passes(width: u32, height: u32) -> Vec<(u32, u32, u32, u32, u32, u32)> {
+    ADAM7_PASSES.iter().filter_map(|&(x0, y0, dx, dy)| {
+        let pass_width = ((width as u64 + (dx - 1 - x0) as u64) / dx as u64) as u32;
+        let pass_height = ((height as u64 + (dy - 1 - y0) as u64) / dy as u64) as u32;
+        if pass_width == 0 || pass_height == 0 { return None; }
+        Some((x0, y0, dx, dy, pass_width, pass_height))
+    }).collect()
+}
+
+// Decodes the pixels of an image from the decompressed data of its IDAT chunks.
+fn read_pixels(header: &Header, data: &[u8]) -> Result<Vec<common::Pixel>, String> {
+    if !header.interlaced {
+        return Ok(try!(read_subimage(header, data, header.width, header.height)).0);
+    }
+    let passes = get_passes(header.width, header.height);
+    let size = passes.iter().fold(0, |size, &(_, _, _, _, width, height)| {
+        size + (header.get_row_size(width) as u64 + 1) * height as u64
+    });
+    if size > data.len() as u64 {
+        return Err("PNG image data is too small.".to_string());
+    }
+    let mut pixels: Vec<common::Pixel> = (0..header.pixels)
+            .map(|_| common::Pixel { red: 0, green: 0, blue: 0, alpha: 0 }).collect();
+    let mut offset = 0;
+    for &(x0, y0, dx, dy, width, height) in passes.iter() {
+        let (pass, size) = try!(read_subimage(header, &data[offset..], width, height));
+        offset += size;
+        for (i, pixel) in pass.into_iter().enumerate() {
+            let (x, y) = (x0 + (i as u32 % width) * dx, y0 + (i as u32 / width) * dy);
+            pixels[y as usize * header.width as usize + x as usize] = pixel;
+        }
+    }
+    Ok(pixels)
+}
+
+// Decodes a PNG given a path to the file and returns a DecodedPNG struct containing the pixel
+// information, width, and height of the image.
+pub fn decode_png(fpath: &str) -> Result<DecodedPNG, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    if data.len() < PNG_MAGIC.len() || data[0..PNG_MAGIC.len()] != PNG_MAGIC[..] {
+        return Err("PNG file header has incorrect magic values.".to_string());
+    }
+    let mut cursor = PNG_MAGIC.len();
+    let mut header: Option<Header> = None;
+    let mut compressed = Vec::new();
+    loop {
+        let length = try!(read_u32(&data, cursor)) as usize;
+        if cursor + 12 + length > data.len() {
+            return Err("PNG file is too small.".to_string());
+        }
+        let kind = &data[(cursor + 4)..(cursor + 8)];
+        let chunk = &data[(cursor + 8)..(cursor + 8 + length)];
+        cursor += 12 + length;
+        if kind == b"IHDR" {
+            header = Some(try!(read_header(chunk)));
+            continue;
+        }
+        let header = match header.as_mut() {
+            Some(header) => header,
+            None => return Err("PNG file doesn't start with a header.".to_string()),
+        };
+        match kind {
+            b"PLTE" => {
+                header.palette = chunk.chunks(3).filter(|c| c.len() == 3).map(|c| {
+                    common::Pixel { red: c[0], green: c[1], blue: c[2], alpha: 255 }
+                }).collect();
+            },
+            b"tRNS" => {
+                if header.color_type == 3 {
+                    for (pixel, &alpha) in header.palette.iter_mut().zip(chunk.iter()) {
+                        pixel.alpha = alpha;
+                    }
+                } else {
+                    let mut key = [0u16; 3];
+                    for (i, value) in chunk.chunks(2).take(3).enumerate() {
+                        if value.len() == 2 {
+                            key[i] = (value[0] as u16) << 8 | value[1] as u16;
+                        }
+                    }
+                    header.transparent = Some(key);
+                }
+            },
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => (),
+        }
+    }
+    let header = match header {
+        Some(header) => header,
+        None => return Err("PNG file has no header.".to_string()),
+    };
+    let decompressed = try!(inflate::decode_zlib(&compressed));
+    let pixels = try!(read_pixels(&header, &decompressed));
+    let image = common::Image { width: header.width, height: header.height, data: pixels };
+    Ok(DecodedPNG { image: image })
+}
+
+// Computes the CRC-32 of some bytes, which ends every chunk.
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for i in 0..256 {
+        table[i] = (0..8).fold(i as u32, |c, _| {
+            if c & 1 != 0 { 0xEDB88320 ^ c >> 1 } else { c >> 1 }
+        });
+    }
+    !data.iter().fold(!0u32, |c, &byte| table[((c ^ byte as u32) & 0xFF) as usize] ^ c >> 8)
+}
+
+// Writes a chunk with its length and CRC.
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8], chunk: &[u8]) {
+    let length = chunk.len() as u32;
+    out.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8,
+            length as u8]);
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(chunk);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+}
+
+// Filters the rows of an image and returns them with their filter types. Each row uses the filter
+// with the smallest sum of its bytes as signed values, which is the usual guess at which filter
+// compresses best.
+fn filter(data: &[u8], row_size: usize, bpp: usize) -> Vec<u8> {
+    let rows = data.len() / row_size;
+    let mut out = Vec::with_capacity((row_size + 1) * rows);
+    let mut candidates = vec![vec![0u8; row_size]; 5];
+    for row in 0..rows {
+        let current = &data[(row * row_size)..((row + 1) * row_size)];
+        let above = if row == 0 { None } else { Some(&data[((row - 1) * row_size)..]) };
+        for i in 0..row_size {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = above.map_or(0, |above| above[i]);
+            let c = if i >= bpp { above.map_or(0, |above| above[i - bpp]) } else { 0 };
+            candidates[0][i] = current[i];
+            candidates[1][i] = current[i].wrapping_sub(a);
+            candidates[2][i] = current[i].wrapping_sub(b);
+            candidates[3][i] = current[i].wrapping_sub(((a as u16 + b as u16) / 2) as u8);
+            candidates[4][i] = current[i].wrapping_sub(paeth(a, b, c));
+        }
+        let cost = |bytes: &Vec<u8>| bytes.iter().fold(0u64, |sum, &v| {
+            sum + (v as i8 as i64).abs() as u64
+        });
+        let best = (1..5).fold(0, |best, f| {
+            if cost(&candidates[f]) < cost(&candidates[best]) { f } else { best }
+        });
+        out.push(best as u8);
+        out.extend_from_slice(&candidates[best]);
+    }
+    out
+}
+
+// Encodes an image to a PNG file at a path. The alpha channel is dropped unless alpha is true,
+// which is what screenshots want since the alpha of a framebuffer is rarely meaningful.
+pub fn encode_png(fpath: &str, image: &common::Image, alpha: bool) -> Result<(), String> {
+    if image.width == 0 || image.height == 0 ||
+            image.width.checked_mul(image.height).map(|n| n as usize) != Some(image.data.len()) {
+        return Err("Image to encode has an incorrect size.".to_string());
+    }
+    let bpp = if alpha { 4 } else { 3 };
+    let pixels = if alpha { image.get_rgba_vec() } else { image.get_rgb_vec() };
+    let filtered = filter(&pixels, image.width as usize * bpp, bpp);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&PNG_MAGIC);
+    let mut header = Vec::new();
+    for &value in [image.width, image.height].iter() {
+        header.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                value as u8]);
+    }
+    header.extend_from_slice(&[8, if alpha { 6 } else { 2 }, 0, 0, 0]);
+    write_chunk(&mut data, b"IHDR", &header);
+    write_chunk(&mut data, b"IDAT", &deflate::encode_zlib(&filtered));
+    write_chunk(&mut data, b"IEND", &[]);
+
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Builds a file from the fields of its header and the filtered rows of its pixels.
+    fn png_file(width: u32, height: u32, depth: u8, color_type: u8, interlaced: bool,
+            rows: &[u8]) -> Vec<u8> {
+        let mut data = PNG_MAGIC.to_vec();
+        let mut header = Vec::new();
+        for &value in [width, height].iter() {
+            header.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8,
+                    (value >> 8) as u8, value as u8]);
+        }
+        header.extend_from_slice(&[depth, color_type, 0, 0, interlaced as u8]);
+        write_chunk(&mut data, b"IHDR", &header);
+        write_chunk(&mut data, b"IDAT", &deflate::encode_zlib(rows));
+        write_chunk(&mut data, b"IEND", &[]);
+        data
+    }
+
+    // Gets the channels of a pixel so that they can be compared.
+    fn channels(pixel: &common::Pixel) -> (u8, u8, u8, u8) {
+        (pixel.red, pixel.green, pixel.blue, pixel.alpha)
+    }
+
+    #[test]
+    fn round_trips_encoded_images() {
+        let data = (0..12).map(|i| {
+            common::Pixel { red: i * 20, green: 255 - i * 20, blue: i, alpha: 128 + i }
+        }).collect();
+        let image = common::Image { width: 4, height: 3, data: data };
+        let path = common::write_test_file("round-trip.png", &[]);
+        encode_png(&path, &image, true).unwrap();
+        let decoded = decode_png(&path).unwrap().image;
+        assert_eq!((decoded.width, decoded.height), (4, 3));
+        for (a, b) in image.data.iter().zip(decoded.data.iter()) {
+            assert_eq!(channels(a), channels(b));
+        }
+    }
+
+    #[test]
+    fn decodes_interlaced_images() {
+        // The three non-empty passes of a 2x2 image hold its pixels in the order 0, 1, then 2
+        // and 3 together.
+        let data = png_file(2, 2, 8, 0, true, &[0, 10, 0, 20, 0, 30, 40]);
+        let path = common::write_test_file("interlaced.png", &data);
+        let image = decode_png(&path).unwrap().image;
+        let values: Vec<_> = image.data.iter().map(channels).collect();
+        assert_eq!(values, vec![(10, 10, 10, 255), (20, 20, 20, 255), (30, 30, 30, 255),
+                (40, 40, 40, 255)]);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = png_file(2, 2, 8, 2, false, &[0; 14]);
+        let path = common::write_test_file("truncated.png", &data[..(data.len() - 20)]);
+        assert!(decode_png(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_pixels() {
+        let path = common::write_test_file("short.png", &png_file(2, 2, 8, 2, false, &[0; 10]));
+        assert!(decode_png(&path).is_err());
+        let path = common::write_test_file("short-adam7.png", &png_file(2, 2, 8, 0, true, &[0; 6]));
+        assert!(decode_png(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        let path = common::write_test_file("empty.png", &png_file(0, 2, 8, 0, false, &[0; 2]));
+        assert!(decode_png(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        // The pixel count doesn't fit in a u32.
+        let data = png_file(0x10000, 0x10000, 8, 0, false, &[0; 8]);
+        let path = common::write_test_file("overflow.png", &data);
+        assert!(decode_png(&path).is_err());
+        // The pixel count fits but the data is far too small for it, interlaced or not.
+        for &interlaced in [false, true].iter() {
+            let data = png_file(60000, 60000, 8, 6, interlaced, &[0; 8]);
+            let path = common::write_test_file("oversized.png", &data);
+            assert!(decode_png(&path).is_err());
+        }
+    }
+}
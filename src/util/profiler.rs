@@ -0,0 +1,395 @@
+// Defines a Profiler for measuring where the CPU time of each frame goes. Code is timed by scopes
+// opened with profile_scope!("name"), which time the rest of the enclosing block, or with
+// profile_block!("name", { ... }). Scopes can be nested and can be opened on any thread, including
+// from jobs on a JobSystem, and each thread records its scopes on its own before handing them over
+// to the Profiler once its outermost scope closes. Scopes cost next to nothing while no Profiler is
+// enabled.
+//
+// Every frame, the Profiler gathers the scopes that closed during it into a FrameCapture with the
+// start, length, and nesting depth of each scope per thread. It can also take the GPU pass timings
+// of a FrameProfiler's FrameStats, which come from a few frames earlier since GPU queries are read
+// back late. The last few seconds of frames are kept, along with the frames that took longer than
+// the hitch threshold, and either can be exported as a chrome://tracing JSON file to look at
+// offline.
+//
+// Usage of a Profiler:
+// - Create it with new(), which enables the scopes.
+// - Wrap code in profile_scope!() and profile_block!() where its time is of interest.
+// - Call begin_frame() at the start of each frame and end_frame(gpu) at the end, optionally with
+//   the stats of a FrameProfiler.
+// - Read get_last_frame() for the latest timings, or call export_chrome_trace(path) or
+//   export_hitches(path) and open the file in chrome://tracing.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate time;
+
+use gfx::stats::{FrameStats, PassTime};
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Mutex, Once};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use util::json::{self, Value};
+
+// The number of frames kept by default, which is a few seconds at 60 frames per second.
+const DEFAULT_MAX_FRAMES: usize = 300;
+
+// The number of hitch frames kept by default.
+const DEFAULT_MAX_HITCHES: usize = 16;
+
+// The length in milliseconds that a frame has to go over to count as a hitch by default.
+const DEFAULT_HITCH_THRESHOLD: f64 = 33.3;
+
+// The most scopes that a thread keeps before handing them over while an outer scope is open.
+const FLUSH_COUNT: usize = 256;
+
+// The thread IDs in exported traces of the frames and the GPU passes.
+const FRAME_TRACK: usize = 0;
+const GPU_TRACK: usize = 1;
+
+#[macro_export]
+// Macro for timing the rest of the enclosing block under a name.
+macro_rules! profile_scope { ($name:expr) =>
+        (let _profile_scope = $crate::util::profiler::ProfileScope::new($name);) }
+
+#[macro_export]
+// Macro for timing a block under a name and evaluating to the block's value.
+macro_rules! profile_block { ($name:expr, $body:block) =>
+        ({ let _profile_scope = $crate::util::profiler::ProfileScope::new($name); $body }) }
+
+// A scope as recorded by the thread that ran it, with times in nanoseconds.
+struct RawScope {
+    name: &'static str,
+    thread: usize,
+    depth: usize,
+    start: u64,
+    end: u64,
+}
+
+// The scopes that a thread has recorded but not handed over yet.
+struct ThreadLog {
+    thread: usize,
+    depth: usize,
+    scopes: Vec<RawScope>,
+}
+
+// Where threads hand over their scopes, shared by every thread.
+struct Sink {
+    enabled: AtomicBool,
+    next_thread: AtomicUsize,
+    scopes: Mutex<Vec<RawScope>>,
+    threads: Mutex<Vec<String>>,        // The name of every thread by index.
+}
+
+static SINK_INIT: Once = Once::new();
+static mut SINK: *const Sink = 0 as *const Sink;
+
+thread_local!(static LOG: RefCell<Option<ThreadLog>> = RefCell::new(None));
+
+// Gets the Sink, creating it the first time. It is never freed.
+fn get_sink() -> &'static Sink {
+    unsafe {
+        SINK_INIT.call_once(|| {
+            SINK = Box::into_raw(Box::new(Sink { enabled: AtomicBool::new(false),
+                    next_thread: AtomicUsize::new(0), scopes: Mutex::new(Vec::new()),
+                    threads: Mutex::new(Vec::new()) }));
+        });
+        &*SINK
+    }
+}
+
+// Calls a function with the current thread's log, creating and naming it the first time.
+fn with_log<F, R>(f: F) -> R where F: FnOnce(&mut ThreadLog) -> R {
+    LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        if log.is_none() {
+            let sink = get_sink();
+            let thread = sink.next_thread.fetch_add(1, Ordering::SeqCst);
+            let name = thread::current().name().map(|n| n.to_string())
+                    .unwrap_or_else(|| format!("Thread {}", thread));
+            let mut threads = sink.threads.lock().unwrap();
+            while threads.len() <= thread {
+                threads.push(String::new());
+            }
+            threads[thread] = name;
+            *log = Some(ThreadLog { thread: thread, depth: 0, scopes: Vec::new() });
+        }
+        f(log.as_mut().unwrap())
+    })
+}
+
+// Hands the scopes that the current thread has recorded over to the Sink.
+fn flush_log(log: &mut ThreadLog) {
+    if log.scopes.is_empty() { return; }
+    get_sink().scopes.lock().unwrap().extend(log.scopes.drain(..));
+}
+
+// Times the scope that it lives in and records it when it is dropped. Use profile_scope!() to
+// create one.
+pub struct ProfileScope {
+    name: &'static str,
+    start: Option<u64>,
+}
+
+impl ProfileScope {
+    // Starts timing a scope if a Profiler is enabled.
+    pub fn new(name: &'static str) -> ProfileScope {
+        if !get_sink().enabled.load(Ordering::Relaxed) {
+            return ProfileScope { name: name, start: None };
+        }
+        with_log(|log| log.depth += 1);
+        ProfileScope { name: name, start: Some(time::precise_time_ns()) }
+    }
+}
+
+impl Drop for ProfileScope {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        let end = time::precise_time_ns();
+        with_log(|log| {
+            log.depth -= 1;
+            log.scopes.push(RawScope { name: self.name, thread: log.thread, depth: log.depth,
+                    start: start, end: end });
+            if log.depth == 0 || log.scopes.len() >= FLUSH_COUNT {
+                flush_log(log);
+            }
+        });
+    }
+}
+
+// A scope that closed during a captured frame. Times are in milliseconds, and the start is
+// relative to the start of the frame, so scopes that started in an earlier frame start below 0.0.
+// The depth is how many scopes it was nested in on its thread.
+#[derive(Clone, Debug)]
+pub struct ScopeTime {
+    pub name: &'static str,
+    pub thread: usize,
+    pub depth: usize,
+    pub start: f64,
+    pub duration: f64,
+}
+
+// The timings of a frame. Scopes are ordered by thread and then by start, so that every scope
+// comes right before the scopes nested in it. The start of the frame is in milliseconds since the
+// Profiler was created, and the GPU passes are from the FrameStats given to end_frame().
+#[derive(Clone, Debug)]
+pub struct FrameCapture {
+    pub frame: u64,
+    pub start: f64,
+    pub duration: f64,
+    pub scopes: Vec<ScopeTime>,
+    pub passes: Vec<PassTime>,
+    pub gpu_frame: Option<u64>,
+}
+
+impl FrameCapture {
+    // Gets the total time in milliseconds of every scope with a name.
+    pub fn get_total(&self, name: &str) -> f64 {
+        self.scopes.iter().filter(|s| s.name == name).fold(0.0, |t, s| t + s.duration)
+    }
+
+    // Gets the indices of the scopes nested directly in a scope.
+    pub fn get_children(&self, index: usize) -> Vec<usize> {
+        let parent = &self.scopes[index];
+        let mut children = Vec::new();
+        for (i, scope) in self.scopes.iter().enumerate().skip(index + 1) {
+            if scope.thread != parent.thread || scope.depth <= parent.depth { break; }
+            if scope.depth == parent.depth + 1 {
+                children.push(i);
+            }
+        }
+        children
+    }
+
+    // Gets the time in milliseconds of a scope not spent in the scopes nested in it.
+    pub fn get_self_time(&self, index: usize) -> f64 {
+        self.get_children(index).iter()
+                .fold(self.scopes[index].duration, |t, &i| t - self.scopes[i].duration)
+    }
+}
+
+// Captures the scopes of every frame and keeps the recent ones and the hitches.
+pub struct Profiler {
+    pub max_frames: usize,
+    pub max_hitches: usize,
+    pub hitch_threshold: f64,
+    frames: VecDeque<FrameCapture>,
+    hitches: VecDeque<FrameCapture>,
+    frame: u64,
+    epoch: u64,
+    frame_start: u64,
+    in_frame: bool,
+}
+
+impl Profiler {
+    // Creates a Profiler with no frames and enables the scopes. Only one Profiler should exist at
+    // a time since they share the scopes.
+    pub fn new() -> Profiler {
+        let profiler = Profiler { max_frames: DEFAULT_MAX_FRAMES, max_hitches: DEFAULT_MAX_HITCHES,
+                hitch_threshold: DEFAULT_HITCH_THRESHOLD, frames: VecDeque::new(),
+                hitches: VecDeque::new(), frame: 0, epoch: time::precise_time_ns(),
+                frame_start: 0, in_frame: false };
+        profiler.set_enabled(true);
+        profiler
+    }
+
+    // Turns the scopes on or off. Scopes that are open when this changes are dropped.
+    pub fn set_enabled(&self, enabled: bool) {
+        get_sink().enabled.store(enabled, Ordering::SeqCst);
+    }
+
+    // Returns true if scopes are being timed.
+    pub fn is_enabled(&self) -> bool {
+        get_sink().enabled.load(Ordering::SeqCst)
+    }
+
+    // Starts capturing a frame. Scopes that closed since the last frame ended are dropped.
+    pub fn begin_frame(&mut self) {
+        if self.in_frame { return; }
+        with_log(|log| flush_log(log));
+        get_sink().scopes.lock().unwrap().clear();
+        self.frame_start = time::precise_time_ns();
+        self.in_frame = true;
+    }
+
+    // Stops capturing the frame and returns its capture. The GPU passes are taken from the stats
+    // of a FrameProfiler if there are any.
+    pub fn end_frame(&mut self, gpu: Option<&FrameStats>) -> &FrameCapture {
+        if !self.in_frame {
+            self.begin_frame();
+        }
+        let end = time::precise_time_ns();
+        with_log(|log| flush_log(log));
+        let raw = mem::replace(&mut *get_sink().scopes.lock().unwrap(), Vec::new());
+        let frame_start = self.frame_start;
+        let mut scopes: Vec<ScopeTime> = raw.into_iter().map(|s| {
+            ScopeTime { name: s.name, thread: s.thread, depth: s.depth,
+                    start: get_ms(frame_start, s.start), duration: get_ms(s.start, s.end) }
+        }).collect();
+        scopes.sort_by(|a, b| match a.thread.cmp(&b.thread) {
+            cmp::Ordering::Equal => match a.start.partial_cmp(&b.start) {
+                Some(cmp::Ordering::Equal) | None => a.depth.cmp(&b.depth),
+                Some(order) => order,
+            },
+            order => order,
+        });
+        let capture = FrameCapture { frame: self.frame, start: get_ms(self.epoch, frame_start),
+                duration: get_ms(frame_start, end), scopes: scopes,
+                passes: gpu.map_or(Vec::new(), |stats| stats.passes.clone()),
+                gpu_frame: gpu.map(|stats| stats.frame) };
+        if capture.duration > self.hitch_threshold && self.max_hitches > 0 {
+            if self.hitches.len() >= self.max_hitches {
+                self.hitches.pop_front();
+            }
+            self.hitches.push_back(capture.clone());
+        }
+        while self.frames.len() >= cmp::max(self.max_frames, 1) {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(capture);
+        self.frame += 1;
+        self.in_frame = false;
+        self.frames.back().unwrap()
+    }
+
+    // Gets the capture of the last frame.
+    pub fn get_last_frame(&self) -> Option<&FrameCapture> {
+        self.frames.back()
+    }
+
+    // Gets the captures of the recent frames from oldest to newest.
+    pub fn get_frames(&self) -> &VecDeque<FrameCapture> {
+        &self.frames
+    }
+
+    // Gets the captures of the frames that went over the hitch threshold from oldest to newest.
+    pub fn get_hitches(&self) -> &VecDeque<FrameCapture> {
+        &self.hitches
+    }
+
+    // Writes the recent frames to a chrome://tracing JSON file.
+    pub fn export_chrome_trace(&self, fpath: &str) -> Result<(), String> {
+        json::encode_json(fpath, &to_chrome_trace(self.frames.iter()))
+    }
+
+    // Writes the hitch frames to a chrome://tracing JSON file.
+    pub fn export_hitches(&self, fpath: &str) -> Result<(), String> {
+        json::encode_json(fpath, &to_chrome_trace(self.hitches.iter()))
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        self.set_enabled(false);
+    }
+}
+
+// Gets the time in milliseconds between two times in nanoseconds, which is negative if the end
+// comes first.
+fn get_ms(start: u64, end: u64) -> f64 {
+    (end as f64 - start as f64) / 1000000.0
+}
+
+// Builds a chrome://tracing document from some frames. Every thread gets a track of its own below
+// a track of the frames, and the GPU passes go in a process of their own lined up with the start
+// of the frame that they were reported in.
+fn to_chrome_trace<'a, I>(frames: I) -> Value where I: Iterator<Item=&'a FrameCapture> {
+    let mut events = Vec::new();
+    let names = get_sink().threads.lock().unwrap().clone();
+    events.push(metadata("process_name", 0, FRAME_TRACK, "CPU"));
+    events.push(metadata("process_name", 1, GPU_TRACK, "GPU"));
+    events.push(metadata("thread_name", 0, FRAME_TRACK, "Frames"));
+    events.push(metadata("thread_name", 1, GPU_TRACK, "Passes"));
+    for (i, name) in names.iter().enumerate() {
+        events.push(metadata("thread_name", 0, i + 1, name));
+    }
+    for frame in frames {
+        events.push(complete_event(&format!("Frame {}", frame.frame), 0, FRAME_TRACK, frame.start,
+                frame.duration));
+        for scope in &frame.scopes {
+            events.push(complete_event(scope.name, 0, scope.thread + 1,
+                    frame.start + scope.start, scope.duration));
+        }
+        for pass in &frame.passes {
+            events.push(complete_event(&pass.name, 1, GPU_TRACK, frame.start + pass.gpu_start,
+                    pass.gpu_time));
+        }
+    }
+    let mut doc = Value::new_object();
+    doc.set("traceEvents", Value::Array(events));
+    doc.set("displayTimeUnit", Value::String("ms".to_string()));
+    doc
+}
+
+// Builds a trace event that names a process or thread.
+fn metadata(kind: &str, pid: usize, tid: usize, name: &str) -> Value {
+    let mut args = Value::new_object();
+    args.set("name", Value::String(name.to_string()));
+    let mut event = Value::new_object();
+    event.set("name", Value::String(kind.to_string()));
+    event.set("ph", Value::String("M".to_string()));
+    event.set("pid", Value::Number(pid as f64));
+    event.set("tid", Value::Number(tid as f64));
+    event.set("args", args);
+    event
+}
+
+// Builds a trace event for a span of time given in milliseconds, which traces store in
+// microseconds.
+fn complete_event(name: &str, pid: usize, tid: usize, start: f64, duration: f64) -> Value {
+    let mut event = Value::new_object();
+    event.set("name", Value::String(name.to_string()));
+    event.set("ph", Value::String("X".to_string()));
+    event.set("pid", Value::Number(pid as f64));
+    event.set("tid", Value::Number(tid as f64));
+    event.set("ts", Value::Number(start * 1000.0));
+    event.set("dur", Value::Number(duration * 1000.0));
+    event
+}
@@ -0,0 +1,62 @@
+// Utility module with a small seedable random number generator. It uses xorshift64*, which is fast
+// and good enough for procedural content, but it should never be used for anything that needs to
+// be secure. The same seed always gives the same sequence, so generated content can be rebuilt
+// exactly from its seed.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate gl;
+
+use self::gl::types::*;
+
+// A random number generator with a 64-bit state.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    // Creates a generator from a seed. The seed is scrambled first since xorshift can't start from
+    // a state of 0 and gives poor numbers for a while after small states.
+    pub fn new(seed: u64) -> Random {
+        let mut state = seed.wrapping_add(0x9E3779B97F4A7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D049BB133111EB);
+        state ^= state >> 31;
+        Random { state: if state == 0 { 0x9E3779B97F4A7C15 } else { state } }
+    }
+
+    // Gets the next random 32-bit number.
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 32) as u32
+    }
+
+    // Gets a random number in [0, 1).
+    pub fn next_float(&mut self) -> GLfloat {
+        (self.next_u32() >> 8) as GLfloat / (1 << 24) as GLfloat
+    }
+
+    // Gets a random number in [min, max).
+    pub fn range(&mut self, min: GLfloat, max: GLfloat) -> GLfloat {
+        min + (max - min) * self.next_float()
+    }
+
+    // Gets a random index in [0, len). The len must not be 0.
+    pub fn next_index(&mut self, len: usize) -> usize {
+        (self.next_u32() as u64 * len as u64 >> 32) as usize
+    }
+
+    // Randomly reorders a slice.
+    pub fn shuffle<T>(&mut self, values: &mut [T]) {
+        for i in (1..values.len()).rev() {
+            let j = self.next_index(i + 1);
+            values.swap(i, j);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 // Utility module that allows for decoding of a .rmod file given a path to a file. The .rmod file
 // format is a binary file format native to the Rust game engine and can be created from a FBX file
-// and texture maps using the rmod_converter.py script.
+// and texture maps using the rmod_converter.py script, or from OBJ and glTF files with the cook
+// tool, which writes them with encode_rmod().
 //
 // Brian Ho
 // brian@brkho.com
@@ -11,7 +12,7 @@ extern crate gl;
 use self::cgmath::*;
 use self::gl::types::*;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use util::common;
 
 // Return value for a decoded BMP file. This contains a width, height, and an array of pixels with
@@ -189,3 +190,63 @@ pub fn decode_rmod(fpath: &str) -> Result<DecodedRMOD, String> {
     Ok(rmod_file)
 }
 
+
+// Appends a 32 bit unsigned integer to the byte vector with the most significant bit first.
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        data.push((value >> (24 - 8 * i)) as u8);
+    }
+}
+
+// Appends a 32 bit float (IEEE 754) to the byte vector with the sign bit first.
+fn write_f32(data: &mut Vec<u8>, value: f32) {
+    write_u32(data, value.to_bits());
+}
+
+// Appends an image to the byte vector, or an empty image if there is none.
+fn write_image(data: &mut Vec<u8>, image: &Option<common::Image>) {
+    match *image {
+        Some(ref image) => {
+            write_u32(data, image.width);
+            write_u32(data, image.height);
+            for pixel in &image.data {
+                data.extend_from_slice(&[pixel.red, pixel.green, pixel.blue, pixel.alpha]);
+            }
+        },
+        None => {
+            write_u32(data, 0);
+            write_u32(data, 0);
+        },
+    }
+}
+
+// Appends a vertex to the byte vector.
+fn write_vertex(data: &mut Vec<u8>, vertex: &common::Vertex) {
+    for v in [vertex.pos, vertex.norm, vertex.tangent, vertex.bitangent].iter() {
+        write_f32(data, v.x);
+        write_f32(data, v.y);
+        write_f32(data, v.z);
+    }
+    write_f32(data, vertex.tc.x);
+    write_f32(data, vertex.tc.y);
+}
+
+// Encodes a DecodedRMOD struct as a .rmod file at a path, which decode_rmod() reads back as-is.
+pub fn encode_rmod(fpath: &str, rmod: &DecodedRMOD) -> Result<(), String> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&RUSTGAME_MAGIC);
+    write_image(&mut data, &rmod.diffuse);
+    write_image(&mut data, &rmod.specular);
+    write_image(&mut data, &rmod.normal);
+    write_f32(&mut data, rmod.shininess);
+    write_u32(&mut data, rmod.vertices.len() as u32);
+    for vertex in &rmod.vertices {
+        write_vertex(&mut data, vertex);
+    }
+    write_u32(&mut data, rmod.elements.len() as u32);
+    for &element in &rmod.elements {
+        write_u32(&mut data, element);
+    }
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
@@ -13,6 +13,7 @@ use std::str;
 use std::ffi::CString;
 use std::fs::File;
 use std::io::Read;
+use std::path;
 
 
 // Compile the shader given a path to an external GLSL file. This is mostly
@@ -44,8 +45,9 @@ pub fn compile_shader(path: &str, ty: GLenum) -> GLuint {
             buf.set_len((len as usize) - 1);
             gl::GetShaderInfoLog(
                     shader, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            panic!("{}", str::from_utf8(&buf).ok().expect(
-                    "ShaderInfoLog not valid utf8"));
+            let info = str::from_utf8(&buf).ok().expect("ShaderInfoLog not valid utf8");
+            log_error!(Renderer, "Failed to compile {}: {}", path, info);
+            panic!("{}", info);
         }
     }
     shader
@@ -58,6 +60,38 @@ pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint { unsafe {
     gl::AttachShader(program, vs);
     gl::AttachShader(program, fs);
     gl::LinkProgram(program);
+    check_link_status(program);
+    program
+} }
+
+// Link a program given a vertex shader whose outputs are captured by transform feedback. The
+// varyings are written one after another into a single interleaved buffer in the order given.
+// There is no fragment shader, so rasterization should be disabled while the program is used.
+pub fn link_feedback_program(vs: GLuint, varyings: &[&str]) -> GLuint { unsafe {
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vs);
+    let names: Vec<CString> = varyings.iter().map(|v| CString::new(*v).unwrap()).collect();
+    let pointers: Vec<*const GLchar> = names.iter().map(|n| n.as_ptr()).collect();
+    gl::TransformFeedbackVaryings(
+            program, pointers.len() as GLsizei, pointers.as_ptr(), gl::INTERLEAVED_ATTRIBS);
+    gl::LinkProgram(program);
+    check_link_status(program);
+    program
+} }
+
+// Link a program given a vertex shader, a geometry shader, and a fragment shader.
+pub fn link_geometry_program(vs: GLuint, gs: GLuint, fs: GLuint) -> GLuint { unsafe {
+    let program = gl::CreateProgram();
+    gl::AttachShader(program, vs);
+    gl::AttachShader(program, gs);
+    gl::AttachShader(program, fs);
+    gl::LinkProgram(program);
+    check_link_status(program);
+    program
+} }
+
+// Panics with the info log of a program if it failed to link.
+unsafe fn check_link_status(program: GLuint) {
     // See if the shader compilation failed.
     let mut status = gl::FALSE as GLint;
     gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
@@ -71,8 +105,45 @@ pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint { unsafe {
         buf.set_len((len as usize) - 1);
         gl::GetProgramInfoLog(
                 program, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-        panic!("{}", str::from_utf8(&buf).ok().expect(
-                "ProgramInfoLog not valid utf8"));
+        let info = str::from_utf8(&buf).ok().expect("ProgramInfoLog not valid utf8");
+        log_error!(Renderer, "Failed to link program: {}", info);
+        panic!("{}", info);
     }
-    program
-} }
+}
+
+// Compiles and links a program given the directory holding the shaders and the names of the
+// vertex and fragment shaders.
+pub fn load_program(dir: &str, vertex_name: &str, fragment_name: &str) -> GLuint {
+    let mut vpath = path::PathBuf::from(dir);
+    vpath.push(vertex_name);
+    let mut fpath = path::PathBuf::from(dir);
+    fpath.push(fragment_name);
+    let vs = compile_shader(vpath.to_str().unwrap(), gl::VERTEX_SHADER);
+    let fs = compile_shader(fpath.to_str().unwrap(), gl::FRAGMENT_SHADER);
+    link_program(vs, fs)
+}
+
+// Compiles and links a program given the directory holding the shaders, the name of a vertex
+// shader, and the varyings that transform feedback captures from it.
+pub fn load_feedback_program(dir: &str, vertex_name: &str, varyings: &[&str]) -> GLuint {
+    let mut vpath = path::PathBuf::from(dir);
+    vpath.push(vertex_name);
+    let vs = compile_shader(vpath.to_str().unwrap(), gl::VERTEX_SHADER);
+    link_feedback_program(vs, varyings)
+}
+
+// Compiles and links a program given the directory holding the shaders and the names of the
+// vertex, geometry, and fragment shaders.
+pub fn load_geometry_program(dir: &str, vertex_name: &str, geometry_name: &str,
+        fragment_name: &str) -> GLuint {
+    let mut vpath = path::PathBuf::from(dir);
+    vpath.push(vertex_name);
+    let mut gpath = path::PathBuf::from(dir);
+    gpath.push(geometry_name);
+    let mut fpath = path::PathBuf::from(dir);
+    fpath.push(fragment_name);
+    let vs = compile_shader(vpath.to_str().unwrap(), gl::VERTEX_SHADER);
+    let gs = compile_shader(gpath.to_str().unwrap(), gl::GEOMETRY_SHADER);
+    let fs = compile_shader(fpath.to_str().unwrap(), gl::FRAGMENT_SHADER);
+    link_geometry_program(vs, gs, fs)
+}
@@ -0,0 +1,236 @@
+// Utility module that allows for decoding of a TGA given a path to the file. True color,
+// grayscale, and color mapped images are supported both raw and run length encoded, at 8, 15, 16,
+// 24, and 32 bits per pixel. Images are flipped as needed so that the first pixel is always the
+// top left one.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::fs::File;
+use std::io::Read;
+use util::common;
+
+// Return value for a decoded TGA file. This contains a width, height, and an array of pixels with
+// color and alpha information.
+pub struct DecodedTGA {
+    pub image: common::Image,
+}
+
+// The size of the header at the start of every TGA file.
+const HEADER_SIZE: usize = 18;
+
+// Reads a little endian u16 at an offset.
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+// Reads a pixel of some number of bits that stores its color directly. 8 bit pixels are gray, and
+// 16 bit pixels use 5 bits for each channel with the top bit as the alpha.
+fn read_color(data: &[u8], bits: u8) -> common::Pixel {
+    match bits {
+        8 => common::Pixel { red: data[0], green: data[0], blue: data[0], alpha: 255 },
+        15 | 16 => {
+            let value = read_u16(data, 0);
+            let expand = |v: u16| ((v & 0x1F) * 255 / 31) as u8;
+            let alpha = if bits == 16 && value & 0x8000 == 0 { 0 } else { 255 };
+            common::Pixel { red: expand(value >> 10), green: expand(value >> 5),
+                    blue: expand(value), alpha: alpha }
+        },
+        24 => common::Pixel { red: data[2], green: data[1], blue: data[0], alpha: 255 },
+        _ => common::Pixel { red: data[2], green: data[1], blue: data[0], alpha: data[3] },
+    }
+}
+
+// Copies a pixel.
+fn copy_pixel(pixel: &common::Pixel) -> common::Pixel {
+    common::Pixel { red: pixel.red, green: pixel.green, blue: pixel.blue, alpha: pixel.alpha }
+}
+
+// Decodes a TGA given a path to the file and returns a DecodedTGA struct containing the pixel
+// information, width, and height of the image.
+pub fn decode_tga(fpath: &str) -> Result<DecodedTGA, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    if data.len() < HEADER_SIZE {
+        return Err("TGA file is too small.".to_string());
+    }
+    let id_length = data[0] as usize;
+    let map_type = data[1];
+    let image_type = data[2];
+    let map_start = read_u16(&data, 3) as usize;
+    let map_length = read_u16(&data, 5) as usize;
+    let map_bits = data[7];
+    let width = read_u16(&data, 12) as u32;
+    let height = read_u16(&data, 14) as u32;
+    let bits = data[16];
+    let descriptor = data[17];
+    let mapped = image_type & 7 == 1;
+    let rle = image_type & 8 != 0;
+    if image_type & 7 == 0 || image_type & 7 > 3 || image_type & !0xB != 0 {
+        return Err("Unsupported TGA image type.".to_string());
+    }
+    let valid_bits = match image_type & 7 {
+        1 => bits == 8 && map_type == 1 && [15, 16, 24, 32].contains(&map_bits),
+        2 => [15, 16, 24, 32].contains(&bits),
+        _ => bits == 8,
+    };
+    if !valid_bits {
+        return Err("Unsupported TGA pixel depth.".to_string());
+    }
+    if width == 0 || height == 0 {
+        return Err("TGA image is empty.".to_string());
+    }
+
+    // Read the color map that comes after the image ID.
+    let mut cursor = HEADER_SIZE + id_length;
+    let mut palette = Vec::new();
+    if map_type == 1 {
+        let entry_size = (map_bits as usize + 7) / 8;
+        if cursor + map_length * entry_size > data.len() {
+            return Err("TGA file is too small.".to_string());
+        }
+        for i in 0..map_length {
+            palette.push(read_color(&data[(cursor + i * entry_size)..], map_bits));
+        }
+        cursor += map_length * entry_size;
+    }
+
+    // Read the pixels, expanding runs if the image is run length encoded.
+    let pixel_size = (bits as usize + 7) / 8;
+    let count = (width * height) as usize;
+    let too_small = "TGA file is too small.".to_string();
+    let remaining = data.len() - cmp::min(cursor, data.len());
+    if !rle && count * pixel_size > remaining {
+        return Err(too_small);
+    }
+    // A run length encoded packet takes at least a byte and a pixel for up to 128 pixels, which
+    // bounds how many pixels the rest of the file can hold.
+    let capacity = if rle { cmp::min(count, remaining / (pixel_size + 1) * 128) } else { count };
+    let mut pixels: Vec<common::Pixel> = Vec::with_capacity(capacity);
+    let read_pixel = |data: &[u8]| -> Result<common::Pixel, String> {
+        if !mapped { return Ok(read_color(data, bits)); }
+        match palette.get((data[0] as usize).wrapping_sub(map_start)) {
+            Some(pixel) => Ok(copy_pixel(pixel)),
+            None => Err("TGA color map index is out of range.".to_string()),
+        }
+    };
+    while pixels.len() < count {
+        let (run, repeated) = if rle {
+            if cursor >= data.len() { return Err(too_small); }
+            let packet = data[cursor];
+            cursor += 1;
+            ((packet & 0x7F) as usize + 1, packet & 0x80 != 0)
+        } else {
+            (count, false)
+        };
+        if run > count - pixels.len() {
+            return Err("TGA run goes past the end of the image.".to_string());
+        }
+        if repeated {
+            if cursor + pixel_size > data.len() { return Err(too_small); }
+            let pixel = try!(read_pixel(&data[cursor..]));
+            cursor += pixel_size;
+            for _ in 0..run {
+                pixels.push(copy_pixel(&pixel));
+            }
+        } else {
+            if cursor + run * pixel_size > data.len() { return Err(too_small); }
+            for _ in 0..run {
+                pixels.push(try!(read_pixel(&data[cursor..])));
+                cursor += pixel_size;
+            }
+        }
+    }
+
+    // Rows are stored from the bottom up unless bit 5 of the descriptor is set, and columns from
+    // left to right unless bit 4 is set.
+    let (width, height) = (width as usize, height as usize);
+    if descriptor & 0x10 != 0 {
+        for row in pixels.chunks_mut(width) {
+            row.reverse();
+        }
+    }
+    if descriptor & 0x20 == 0 {
+        for y in 0..(height / 2) {
+            for x in 0..width {
+                pixels.swap(y * width + x, (height - 1 - y) * width + x);
+            }
+        }
+    }
+    let image = common::Image { width: width as u32, height: height as u32, data: pixels };
+    Ok(DecodedTGA { image: image })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Builds a file with a header for a 24 bit true color image followed by some pixel data.
+    fn tga_file(image_type: u8, width: u16, height: u16, descriptor: u8, pixels: &[u8])
+            -> Vec<u8> {
+        let mut data = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0, width as u8,
+                (width >> 8) as u8, height as u8, (height >> 8) as u8, 24, descriptor];
+        data.extend_from_slice(pixels);
+        data
+    }
+
+    // Gets the channels of a pixel so that they can be compared.
+    fn channels(pixel: &common::Pixel) -> (u8, u8, u8, u8) {
+        (pixel.red, pixel.green, pixel.blue, pixel.alpha)
+    }
+
+    #[test]
+    fn decodes_raw_pixels() {
+        // The rows are stored from the bottom up and the pixels are BGR.
+        let data = tga_file(2, 2, 2, 0, &[3, 2, 1, 6, 5, 4, 9, 8, 7, 12, 11, 10]);
+        let path = common::write_test_file("raw.tga", &data);
+        let image = decode_tga(&path).unwrap().image;
+        assert_eq!((image.width, image.height), (2, 2));
+        let values: Vec<_> = image.data.iter().map(channels).collect();
+        assert_eq!(values, vec![(7, 8, 9, 255), (10, 11, 12, 255), (1, 2, 3, 255),
+                (4, 5, 6, 255)]);
+    }
+
+    #[test]
+    fn decodes_run_length_pixels() {
+        // A run of three pixels followed by a single raw pixel, stored from the top down.
+        let data = tga_file(10, 4, 1, 0x20, &[0x82, 3, 2, 1, 0x00, 6, 5, 4]);
+        let path = common::write_test_file("rle.tga", &data);
+        let image = decode_tga(&path).unwrap().image;
+        let values: Vec<_> = image.data.iter().map(channels).collect();
+        assert_eq!(values, vec![(1, 2, 3, 255), (1, 2, 3, 255), (1, 2, 3, 255),
+                (4, 5, 6, 255)]);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let path = common::write_test_file("header.tga", &tga_file(2, 2, 2, 0, &[])[..10]);
+        assert!(decode_tga(&path).is_err());
+        let path = common::write_test_file("truncated.tga", &tga_file(2, 2, 2, 0, &[0; 11]));
+        assert!(decode_tga(&path).is_err());
+        let path = common::write_test_file("truncated-rle.tga", &tga_file(10, 4, 1, 0, &[0x83]));
+        assert!(decode_tga(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_dimensions() {
+        // Mirroring the columns of an empty image used to divide the pixels into empty rows.
+        let path = common::write_test_file("empty.tga", &tga_file(2, 0, 5, 0x10, &[]));
+        assert!(decode_tga(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_header() {
+        // A run length encoded header can't reserve more pixels than the file could hold.
+        let data = tga_file(10, 0xFFFF, 0xFFFF, 0, &[0xFF, 1, 2, 3]);
+        let path = common::write_test_file("oversized.tga", &data);
+        assert!(decode_tga(&path).is_err());
+        let path = common::write_test_file("oversized-raw.tga", &tga_file(2, 0xFFFF, 0xFFFF, 0,
+                &[0; 12]));
+        assert!(decode_tga(&path).is_err());
+    }
+}
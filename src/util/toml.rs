@@ -0,0 +1,440 @@
+// Utility module for reading TOML documents, which is what the engine's configuration files are
+// written in since they are easier to edit by hand than JSON. Documents are read into the same
+// Values as JSON, with every number as a Number and every table as an Object that keeps its keys
+// in order. This covers the parts of TOML 1.0 that configuration files use: comments, tables,
+// arrays of tables, dotted and quoted keys, basic and literal strings, integers, floats, booleans,
+// arrays, and inline tables. Multi-line strings, dates, and hexadecimal, octal, and binary
+// integers are not supported.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::char;
+use std::fs::File;
+use std::io::Read;
+use util::json::Value;
+
+// Maximum nesting of arrays and inline tables before a document is considered malformed.
+const MAX_DEPTH: usize = 256;
+
+// Gets the table at a path of keys under a table, creating any tables that don't exist yet. An
+// array of tables on the path stands for its last table like in TOML.
+fn table_at<'a>(table: &'a mut Value, path: &[String]) -> Result<&'a mut Value, String> {
+    if path.is_empty() { return Ok(table); }
+    if table.get(&path[0]).is_none() {
+        table.set(&path[0], Value::new_object());
+    }
+    let next = match table.get_mut(&path[0]) {
+        Some(next) => next,
+        None => return Err(format!("{} is not a table.", path[0])),
+    };
+    let next = match *next {
+        Value::Array(ref mut items) => match items.last_mut() {
+            Some(last) => last,
+            None => return Err(format!("{} is an empty array.", path[0])),
+        },
+        ref mut other => other,
+    };
+    match *next {
+        Value::Object(_) => table_at(next, &path[1..]),
+        _ => Err(format!("{} is not a table.", path[0])),
+    }
+}
+
+// Sets a dotted key of a table to a value. Returns an Err if the key already has a value.
+fn insert(table: &mut Value, key: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = match key.split_last() {
+        Some(split) => split,
+        None => return Err("Key is empty.".to_string()),
+    };
+    let parent = try!(table_at(table, parents));
+    if parent.get(last).is_some() {
+        return Err(format!("{} is defined twice.", key.join(".")));
+    }
+    parent.set(last, value);
+    Ok(())
+}
+
+// Returns true if a character can be part of a bare key.
+fn is_bare(c: char) -> bool {
+    (c >= 'a' && c <= 'z') || (c >= 'A' && c <= 'Z') || c.is_digit(10) || c == '_' || c == '-'
+}
+
+// A cursor over the characters of a document being parsed.
+struct Parser<'a> {
+    chars: ::std::iter::Peekable<::std::str::Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Creates an error message with the current line.
+    fn error(&self, message: &str) -> String {
+        format!("TOML error on line {}: {}", self.line, message)
+    }
+
+    // Consumes the next character.
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') { self.line += 1; }
+        c
+    }
+
+    // Peeks at the next character.
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|c| *c)
+    }
+
+    // Skips spaces and tabs, but not newlines.
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(' ') || self.peek() == Some('\t') {
+            self.next();
+        }
+    }
+
+    // Skips whitespace, newlines, and comments, which can appear between values of an array.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => { self.next(); },
+                Some('#') => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    // Skips a comment up to the end of its line.
+    fn skip_comment(&mut self) {
+        while self.peek().map_or(false, |c| c != '\n') {
+            self.next();
+        }
+    }
+
+    // Consumes a character and returns an Err if it isn't the expected one.
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("Expected '{}'.", expected))),
+        }
+    }
+
+    // Consumes the rest of a line after a key/value pair or table header, which may only have a
+    // comment on it.
+    fn end_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        match self.next() {
+            Some('\n') | None => Ok(()),
+            _ => Err(self.error("Expected the end of the line.")),
+        }
+    }
+
+    // Parses a dotted key made of bare and quoted keys.
+    fn key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_spaces();
+            let part = match self.peek() {
+                Some('"') => try!(self.basic_string()),
+                Some('\'') => try!(self.literal_string()),
+                Some(c) if is_bare(c) => {
+                    let mut part = String::new();
+                    while self.peek().map_or(false, is_bare) {
+                        part.push(self.next().unwrap());
+                    }
+                    part
+                },
+                _ => return Err(self.error("Expected a key.")),
+            };
+            parts.push(part);
+            self.skip_spaces();
+            if self.peek() != Some('.') { return Ok(parts); }
+            self.next();
+        }
+    }
+
+    // Parses any value.
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Document is nested too deeply."));
+        }
+        match self.peek() {
+            Some('"') => Ok(Value::String(try!(self.basic_string()))),
+            Some('\'') => Ok(Value::String(try!(self.literal_string()))),
+            Some('[') => self.array(depth),
+            Some('{') => self.inline_table(depth),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some(c) if c == '-' || c == '+' || c.is_digit(10) => self.number(),
+            Some(_) => Err(self.error("Unexpected character.")),
+            None => Err(self.error("Unexpected end of document.")),
+        }
+    }
+
+    // Consumes a keyword such as true or false.
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if self.next() != Some(expected) {
+                return Err(self.error("Invalid literal."));
+            }
+        }
+        Ok(value)
+    }
+
+    // Parses an integer or float, which may have underscores between digits.
+    fn number(&mut self) -> Result<Value, String> {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_digit(10) || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' ||
+                    c == '_') {
+                break;
+            }
+            if c != '_' { text.push(c); }
+            self.next();
+        }
+        match text.parse::<f64>() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => Err(self.error("Invalid number.")),
+        }
+    }
+
+    // Parses an array, which may span lines and have comments and a trailing comma.
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        try!(self.expect('['));
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.next();
+                return Ok(Value::Array(items));
+            }
+            items.push(try!(self.value(depth + 1)));
+            self.skip_blank();
+            match self.next() {
+                Some(',') => {},
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("Expected ',' or ']'.")),
+            }
+        }
+    }
+
+    // Parses an inline table, which must be on a single line.
+    fn inline_table(&mut self, depth: usize) -> Result<Value, String> {
+        try!(self.expect('{'));
+        let mut table = Value::new_object();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(table);
+        }
+        loop {
+            let key = try!(self.key());
+            try!(self.expect('='));
+            self.skip_spaces();
+            let value = try!(self.value(depth + 1));
+            try!(insert(&mut table, &key, value).map_err(|e| self.error(&e)));
+            self.skip_spaces();
+            match self.next() {
+                Some(',') => {},
+                Some('}') => return Ok(table),
+                _ => return Err(self.error("Expected ',' or '}'.")),
+            }
+        }
+    }
+
+    // Parses the hex digits of a \u or \U escape.
+    fn unicode(&mut self, digits: usize) -> Result<char, String> {
+        let mut code = 0;
+        for _ in 0..digits {
+            match self.next().and_then(|c| c.to_digit(16)) {
+                Some(d) => code = code * 16 + d,
+                None => return Err(self.error("Invalid unicode escape.")),
+            }
+        }
+        char::from_u32(code).ok_or(self.error("Invalid unicode escape."))
+    }
+
+    // Parses a basic string including escapes.
+    fn basic_string(&mut self) -> Result<String, String> {
+        try!(self.expect('"'));
+        if self.peek() == Some('"') {
+            self.next();
+            if self.peek() == Some('"') {
+                return Err(self.error("Multi-line strings are not supported."));
+            }
+            return Ok(String::new());
+        }
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => try!(self.unicode(4)),
+                        Some('U') => try!(self.unicode(8)),
+                        _ => return Err(self.error("Invalid escape.")),
+                    };
+                    s.push(c);
+                },
+                Some('\n') | None => return Err(self.error("Unterminated string.")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    // Parses a literal string, which has no escapes.
+    fn literal_string(&mut self) -> Result<String, String> {
+        try!(self.expect('\''));
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(s),
+                Some('\n') | None => return Err(self.error("Unterminated string.")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    // Parses a table header and returns its key along with whether it is an array of tables.
+    fn header(&mut self) -> Result<(Vec<String>, bool), String> {
+        try!(self.expect('['));
+        let array = self.peek() == Some('[');
+        if array { self.next(); }
+        let key = try!(self.key());
+        try!(self.expect(']'));
+        if array { try!(self.expect(']')); }
+        Ok((key, array))
+    }
+}
+
+// Parses a TOML document from a string into an Object.
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1 };
+    let mut root = Value::new_object();
+    let mut current: Vec<String> = Vec::new();
+    loop {
+        parser.skip_blank();
+        match parser.peek() {
+            None => return Ok(root),
+            Some('[') => {
+                let (key, array) = try!(parser.header());
+                if array {
+                    let (last, parents) = key.split_last().unwrap();
+                    let parent = try!(table_at(&mut root, parents).map_err(|e| parser.error(&e)));
+                    if parent.get(last).is_none() {
+                        parent.set(last, Value::Array(Vec::new()));
+                    }
+                    match parent.get_mut(last) {
+                        Some(&mut Value::Array(ref mut items)) => items.push(Value::new_object()),
+                        _ => return Err(parser.error(&format!("{} is not an array.", last))),
+                    }
+                } else {
+                    try!(table_at(&mut root, &key).map_err(|e| parser.error(&e)));
+                }
+                current = key;
+            },
+            Some(_) => {
+                let key = try!(parser.key());
+                try!(parser.expect('='));
+                parser.skip_spaces();
+                let value = try!(parser.value(0));
+                let table = try!(table_at(&mut root, &current).map_err(|e| parser.error(&e)));
+                try!(insert(table, &key, value).map_err(|e| parser.error(&e)));
+            },
+        }
+        try!(parser.end_line());
+    }
+}
+
+// Parses a single value, such as one given on the command line or in an environment variable.
+pub fn parse_value(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { chars: text.trim().chars().peekable(), line: 1 };
+    let value = try!(parser.value(0));
+    if parser.peek().is_some() {
+        return Err(parser.error("Unexpected data after the value."));
+    }
+    Ok(value)
+}
+
+// Decodes a TOML file given a path.
+pub fn decode_toml(fpath: &str) -> Result<Value, String> {
+    let mut file = try!(File::open(fpath).map_err(|e| e.to_string()));
+    let mut contents = String::new();
+    try!(file.read_to_string(&mut contents).map_err(|e| e.to_string()));
+    parse(&contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    #[test]
+    fn parses_documents() {
+        let doc = parse("# Settings\ntitle = \"mmo\" # The name.\n\n[window]\nwidth = 1_280\n\
+                vsync = true\nsize.scale = 1.5\n\n[[bindings]]\nkey = 'W'\n\
+                mods = [\"ctrl\",\n  \"shift\", ]\n[[bindings]]\nkey = \"\\u0041\"\n\
+                point = { x = 1, y = -2e1 }\n").unwrap();
+        assert_eq!(doc.get("title").and_then(|v| v.as_str()), Some("mmo"));
+        let window = doc.get("window").unwrap();
+        assert_eq!(window.get("width").and_then(|v| v.as_f64()), Some(1280.0));
+        assert_eq!(window.get("vsync").and_then(|v| v.as_bool()), Some(true));
+        assert_eq!(window.get("size").and_then(|s| s.get("scale")).and_then(|v| v.as_f64()),
+                Some(1.5));
+        let bindings = doc.get("bindings").and_then(|v| v.as_array()).unwrap();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[0].get("mods"), Some(&Value::Array(vec![
+                Value::String("ctrl".to_string()), Value::String("shift".to_string())])));
+        assert_eq!(bindings[1].get("key").and_then(|v| v.as_str()), Some("A"));
+        let point = bindings[1].get("point").unwrap();
+        assert_eq!(point.get("y").and_then(|v| v.as_f64()), Some(-20.0));
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse_value(" 42 ").unwrap(), Value::Number(42.0));
+        assert_eq!(parse_value("'C:\\path'").unwrap(), Value::String("C:\\path".to_string()));
+        assert!(parse_value("42 43").is_err());
+        assert!(parse_value("").is_err());
+    }
+
+    #[test]
+    fn decodes_files() {
+        let path = common::write_test_file("config.toml", b"[audio]\nvolume = 0.5\r\n");
+        let doc = decode_toml(&path).unwrap();
+        assert_eq!(doc.get("audio").and_then(|a| a.get("volume")), Some(&Value::Number(0.5)));
+    }
+
+    #[test]
+    fn rejects_truncated_documents() {
+        for text in ["key = \"value", "key = [1, 2", "key = { a = 1", "[table", "key =",
+                "key = tru", "key = \"\\u00"].iter() {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        for text in ["a = 1\na = 2", "a = 1\n[a]", "a = 1 b = 2", "a = \"\"\"long\"\"\"",
+                "a = 1.2.3", "= 1"].iter() {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let text = format!("a = {}", (0..10000).map(|_| "[").collect::<String>());
+        assert!(parse(&text).is_err());
+    }
+}
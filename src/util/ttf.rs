@@ -0,0 +1,687 @@
+// Utility module that allows for loading a TrueType (.ttf) font given a path to the file and
+// rasterizing its glyphs into coverage bitmaps. This is only implemented for fonts with quadratic
+// TrueType outlines (not CFF based OpenType fonts) and a Unicode character map. Kerning is read
+// from the legacy kern table, so fonts that only have GPOS kerning are laid out without kerning.
+// Hinting instructions are ignored.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::char;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+// Number of line segments each quadratic curve in an outline is flattened into.
+const CURVE_SEGMENTS: usize = 8;
+
+// Maximum nesting of compound glyphs before the outline is considered malformed.
+const MAX_COMPOUND_DEPTH: u32 = 8;
+
+// Maximum width or height of a rasterized glyph in pixels. Outlines come from the file, so a
+// glyph that is much larger than its em would otherwise ask for an enormous bitmap.
+const MAX_GLYPH_SIZE: usize = 4096;
+
+// A rasterized glyph. The coverage is stored row by row from the top with one byte per pixel. The
+// left bearing is the horizontal distance from the pen to the left edge of the bitmap and the top
+// bearing is the vertical distance from the baseline up to the top edge, both in pixels.
+pub struct RasterizedGlyph {
+    pub width: u32,
+    pub height: u32,
+    pub left: i32,
+    pub top: i32,
+    pub advance: f32,
+    pub coverage: Vec<u8>,
+}
+
+// A loaded TrueType font. Metrics are stored in font units, which can be converted to pixels by
+// multiplying by pixel_size / units_per_em.
+pub struct TrueTypeFont {
+    pub units_per_em: u16,
+    pub ascender: i16,
+    pub descender: i16,
+    pub line_gap: i16,
+    data: Vec<u8>,
+    glyf: usize,
+    loca: usize,
+    hmtx: usize,
+    num_glyphs: u16,
+    num_h_metrics: u16,
+    long_loca: bool,
+    glyph_map: HashMap<char, u16>,
+    kernings: HashMap<(u16, u16), i16>,
+}
+
+// Reads a big endian u8 at an offset in the data vector with bounds checking.
+fn read_u8(data: &Vec<u8>, offset: usize) -> Result<u8, String> {
+    match data.get(offset) {
+        Some(v) => Ok(*v),
+        None => Err("TTF file is too small.".to_string()),
+    }
+}
+
+// Reads a big endian u16 at an offset in the data vector with bounds checking.
+fn read_u16(data: &Vec<u8>, offset: usize) -> Result<u16, String> {
+    let high = try!(read_u8(data, offset)) as u16;
+    let low = try!(read_u8(data, offset + 1)) as u16;
+    Ok((high << 8) | low)
+}
+
+// Reads a big endian i16 at an offset in the data vector with bounds checking.
+fn read_i16(data: &Vec<u8>, offset: usize) -> Result<i16, String> {
+    Ok(try!(read_u16(data, offset)) as i16)
+}
+
+// Reads a big endian u32 at an offset in the data vector with bounds checking.
+fn read_u32(data: &Vec<u8>, offset: usize) -> Result<u32, String> {
+    let high = try!(read_u16(data, offset)) as u32;
+    let low = try!(read_u16(data, offset + 2)) as u32;
+    Ok((high << 16) | low)
+}
+
+// Reads a 2.14 fixed point number used for compound glyph transforms.
+fn read_f2dot14(data: &Vec<u8>, offset: usize) -> Result<f32, String> {
+    Ok(try!(read_i16(data, offset)) as f32 / 16384.0)
+}
+
+// Finds the offset of a table in the file given its four character tag.
+fn find_table(data: &Vec<u8>, tag: &[u8]) -> Result<Option<usize>, String> {
+    let num_tables = try!(read_u16(data, 4)) as usize;
+    for i in 0..num_tables {
+        let record = 12 + 16 * i;
+        if record + 4 <= data.len() && &data[record..(record + 4)] == tag {
+            return Ok(Some(try!(read_u32(data, record + 8)) as usize));
+        }
+    }
+    Ok(None)
+}
+
+// Finds the offset of a table that the font cannot be used without.
+fn require_table(data: &Vec<u8>, tag: &str) -> Result<usize, String> {
+    match try!(find_table(data, tag.as_bytes())) {
+        Some(offset) => Ok(offset),
+        None => Err(format!("TTF file is missing the {} table.", tag)),
+    }
+}
+
+// Reads a format 4 (segmented 16-bit) character map subtable.
+fn read_cmap_format4(data: &Vec<u8>, subtable: usize, glyph_map: &mut HashMap<char, u16>)
+        -> Result<(), String> {
+    let seg_x2 = try!(read_u16(data, subtable + 6)) as usize;
+    let ends = subtable + 14;
+    let starts = ends + seg_x2 + 2;
+    let deltas = starts + seg_x2;
+    let ranges = deltas + seg_x2;
+    for i in 0..(seg_x2 / 2) {
+        let end = try!(read_u16(data, ends + 2 * i)) as u32;
+        let start = try!(read_u16(data, starts + 2 * i)) as u32;
+        let delta = try!(read_u16(data, deltas + 2 * i)) as u32;
+        let range = try!(read_u16(data, ranges + 2 * i)) as usize;
+        if start == 0xFFFF { break; }
+        for code in start..(end + 1) {
+            let glyph = if range == 0 {
+                (code + delta) & 0xFFFF
+            } else {
+                let addr = ranges + 2 * i + range + 2 * (code - start) as usize;
+                let glyph = try!(read_u16(data, addr)) as u32;
+                if glyph == 0 { 0 } else { (glyph + delta) & 0xFFFF }
+            };
+            if let (Some(c), true) = (char::from_u32(code), glyph != 0) {
+                glyph_map.insert(c, glyph as u16);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads a format 12 (segmented 32-bit) character map subtable.
+fn read_cmap_format12(data: &Vec<u8>, subtable: usize, glyph_map: &mut HashMap<char, u16>)
+        -> Result<(), String> {
+    let num_groups = try!(read_u32(data, subtable + 12)) as usize;
+    for i in 0..num_groups {
+        let group = subtable + 16 + 12 * i;
+        let start = try!(read_u32(data, group));
+        let end = try!(read_u32(data, group + 4));
+        let glyph = try!(read_u32(data, group + 8));
+        if end < start || end > 0x10FFFF {
+            return Err("TTF character map has an invalid group.".to_string());
+        }
+        for code in start..(end + 1) {
+            if let Some(c) = char::from_u32(code) {
+                glyph_map.insert(c, (glyph + code - start) as u16);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads the Unicode character map, preferring the full repertoire subtable if there is one.
+fn read_cmap(data: &Vec<u8>, cmap: usize) -> Result<HashMap<char, u16>, String> {
+    let num_subtables = try!(read_u16(data, cmap + 2)) as usize;
+    let mut bmp = None;
+    let mut full = None;
+    for i in 0..num_subtables {
+        let record = cmap + 4 + 8 * i;
+        let platform = try!(read_u16(data, record));
+        let encoding = try!(read_u16(data, record + 2));
+        let subtable = cmap + try!(read_u32(data, record + 4)) as usize;
+        let format = try!(read_u16(data, subtable));
+        let unicode = platform == 0 || (platform == 3 && (encoding == 1 || encoding == 10));
+        if !unicode { continue; }
+        if format == 12 { full = Some(subtable); }
+        if format == 4 { bmp = Some(subtable); }
+    }
+    let mut glyph_map = HashMap::new();
+    match (full, bmp) {
+        (Some(subtable), _) => try!(read_cmap_format12(data, subtable, &mut glyph_map)),
+        (None, Some(subtable)) => try!(read_cmap_format4(data, subtable, &mut glyph_map)),
+        (None, None) => { return Err("TTF file has no Unicode character map.".to_string()); },
+    }
+    Ok(glyph_map)
+}
+
+// Reads the horizontal pairs of the first subtable of a legacy kern table.
+fn read_kern(data: &Vec<u8>, kern: usize) -> Result<HashMap<(u16, u16), i16>, String> {
+    let mut kernings = HashMap::new();
+    let version = try!(read_u16(data, kern));
+    let num_tables = try!(read_u16(data, kern + 2));
+    if version != 0 || num_tables == 0 {
+        return Ok(kernings);
+    }
+    let subtable = kern + 4;
+    let coverage = try!(read_u16(data, subtable + 4));
+    if coverage >> 8 != 0 || coverage & 1 == 0 {
+        return Ok(kernings);
+    }
+    let num_pairs = try!(read_u16(data, subtable + 6)) as usize;
+    for i in 0..num_pairs {
+        let pair = subtable + 14 + 6 * i;
+        kernings.insert((try!(read_u16(data, pair)), try!(read_u16(data, pair + 2))),
+                try!(read_i16(data, pair + 4)));
+    }
+    Ok(kernings)
+}
+
+// Reads the x or y coordinates of a simple glyph. Coordinates are stored as deltas, either as a
+// byte with a sign flag or as an i16, and the same flag means the delta is zero when the short flag
+// is not set.
+fn read_coordinates(data: &Vec<u8>, cursor: &mut usize, flags: &Vec<u8>, short_bit: u8,
+        same_bit: u8) -> Result<Vec<f32>, String> {
+    let mut coordinates = Vec::with_capacity(flags.len());
+    let mut value = 0i32;
+    for flag in flags {
+        if flag & short_bit != 0 {
+            let delta = try!(read_u8(data, *cursor)) as i32;
+            *cursor += 1;
+            value += if flag & same_bit != 0 { delta } else { -delta };
+        } else if flag & same_bit == 0 {
+            value += try!(read_i16(data, *cursor)) as i32;
+            *cursor += 2;
+        }
+        coordinates.push(value as f32);
+    }
+    Ok(coordinates)
+}
+
+// Flattens a closed contour of on-curve and off-curve points into a polyline. Two consecutive
+// off-curve points have an implied on-curve point halfway between them.
+fn flatten_contour(points: &[(f32, f32, bool)]) -> Vec<(f32, f32)> {
+    let mut expanded = Vec::new();
+    for i in 0..points.len() {
+        let (x, y, on) = points[i];
+        let (nx, ny, next_on) = points[(i + 1) % points.len()];
+        expanded.push((x, y, on));
+        if !on && !next_on {
+            expanded.push(((x + nx) * 0.5, (y + ny) * 0.5, true));
+        }
+    }
+    let start = match expanded.iter().position(|p| p.2) {
+        Some(s) => s,
+        None => { return Vec::new(); },
+    };
+    let len = expanded.len();
+    let mut polyline = vec![(expanded[start].0, expanded[start].1)];
+    let mut k = 1;
+    while k <= len {
+        let (x, y, on) = expanded[(start + k) % len];
+        if on {
+            polyline.push((x, y));
+            k += 1;
+        } else {
+            let (x0, y0) = polyline[polyline.len() - 1];
+            let (x1, y1, _) = expanded[(start + k + 1) % len];
+            for s in 1..(CURVE_SEGMENTS + 1) {
+                let t = s as f32 / CURVE_SEGMENTS as f32;
+                let u = 1.0 - t;
+                polyline.push((u * u * x0 + 2.0 * u * t * x + t * t * x1,
+                        u * u * y0 + 2.0 * u * t * y + t * t * y1));
+            }
+            k += 2;
+        }
+    }
+    polyline
+}
+
+// An accumulation buffer that rasterizes lines by adding the signed area each line covers to the
+// pixels it crosses. A running sum over the buffer then gives the coverage of every pixel, which
+// works for the nonzero winding of font outlines as long as contours do not overlap.
+struct Rasterizer {
+    width: usize,
+    height: usize,
+    accumulation: Vec<f32>,
+}
+
+impl Rasterizer {
+    // Creates an empty rasterizer with some padding so that lines touching the right edge do not
+    // index out of bounds.
+    fn new(width: usize, height: usize) -> Rasterizer {
+        Rasterizer { width: width, height: height, accumulation: vec![0.0; width * height + 3] }
+    }
+
+    // Adds the signed area of a line given in pixel coordinates with y pointing down.
+    fn line(&mut self, p0: (f32, f32), p1: (f32, f32)) {
+        if (p0.1 - p1.1).abs() < 1e-6 { return; }
+        let (dir, p0, p1) = if p0.1 < p1.1 { (1.0, p0, p1) } else { (-1.0, p1, p0) };
+        let dxdy = (p1.0 - p0.0) / (p1.1 - p0.1);
+        let mut x = p0.0;
+        if p0.1 < 0.0 { x -= p0.1 * dxdy; }
+        let y_start = if p0.1 < 0.0 { 0 } else { p0.1 as usize };
+        let y_end = if (p1.1.ceil() as usize) < self.height { p1.1.ceil() as usize }
+                else { self.height };
+        for y in y_start..y_end {
+            let row = y * self.width;
+            let dy = (y as f32 + 1.0).min(p1.1) - (y as f32).max(p0.1);
+            let x_next = x + dxdy * dy;
+            let d = dy * dir;
+            let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+            let x0_floor = x0.floor();
+            let x0i = x0_floor as usize;
+            let x1_ceil = x1.ceil();
+            let x1i = x1_ceil as usize;
+            if x1i <= x0i + 1 {
+                // The line stays within a single pixel on this row.
+                let mid = 0.5 * (x + x_next) - x0_floor;
+                self.accumulation[row + x0i] += d - d * mid;
+                self.accumulation[row + x0i + 1] += d * mid;
+            } else {
+                let s = 1.0 / (x1 - x0);
+                let x0f = x0 - x0_floor;
+                let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+                let x1f = x1 - x1_ceil + 1.0;
+                let am = 0.5 * s * x1f * x1f;
+                self.accumulation[row + x0i] += d * a0;
+                if x1i == x0i + 2 {
+                    self.accumulation[row + x0i + 1] += d * (1.0 - a0 - am);
+                } else {
+                    let a1 = s * (1.5 - x0f);
+                    self.accumulation[row + x0i + 1] += d * (a1 - a0);
+                    for xi in (x0i + 2)..(x1i - 1) {
+                        self.accumulation[row + xi] += d * s;
+                    }
+                    let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                    self.accumulation[row + x1i - 1] += d * (1.0 - a2 - am);
+                }
+                self.accumulation[row + x1i] += d * am;
+            }
+            x = x_next;
+        }
+    }
+
+    // Sums the accumulation buffer into coverage values from 0 to 255.
+    fn coverage(&self) -> Vec<u8> {
+        let mut sum = 0.0f32;
+        let mut coverage = Vec::with_capacity(self.width * self.height);
+        for i in 0..(self.width * self.height) {
+            sum += self.accumulation[i];
+            coverage.push((sum.abs().min(1.0) * 255.0 + 0.5) as u8);
+        }
+        coverage
+    }
+}
+
+impl TrueTypeFont {
+    // Gets the glyph index of a character if the font has it.
+    pub fn get_glyph(&self, c: char) -> Option<u16> {
+        self.glyph_map.get(&c).map(|g| *g)
+    }
+
+    // Gets the horizontal advance of a glyph in font units.
+    pub fn get_advance(&self, glyph: u16) -> Result<u16, String> {
+        let metric = if glyph < self.num_h_metrics { glyph } else { self.num_h_metrics - 1 };
+        read_u16(&self.data, self.hmtx + 4 * metric as usize)
+    }
+
+    // Gets the kerning adjustment between two characters in font units.
+    pub fn get_kerning(&self, left: char, right: char) -> i16 {
+        match (self.get_glyph(left), self.get_glyph(right)) {
+            (Some(l), Some(r)) => *self.kernings.get(&(l, r)).unwrap_or(&0),
+            _ => 0,
+        }
+    }
+
+    // Gets the scale that converts font units to pixels for a given em size in pixels.
+    pub fn get_scale(&self, pixel_size: f32) -> f32 {
+        pixel_size / self.units_per_em as f32
+    }
+
+    // Gets the location and length of a glyph's outline in the glyf table.
+    fn get_glyph_range(&self, glyph: u16) -> Result<(usize, usize), String> {
+        if glyph >= self.num_glyphs {
+            return Err(format!("TTF glyph {} is out of range.", glyph));
+        }
+        let i = glyph as usize;
+        let (start, end) = if self.long_loca {
+            (try!(read_u32(&self.data, self.loca + 4 * i)) as usize,
+                    try!(read_u32(&self.data, self.loca + 4 * i + 4)) as usize)
+        } else {
+            (try!(read_u16(&self.data, self.loca + 2 * i)) as usize * 2,
+                    try!(read_u16(&self.data, self.loca + 2 * i + 2)) as usize * 2)
+        };
+        if end < start {
+            return Err("TTF glyph locations are out of order.".to_string());
+        }
+        Ok((self.glyf + start, end - start))
+    }
+
+    // Gets the outline of a glyph in font units as a list of closed polylines.
+    fn get_contours(&self, glyph: u16, depth: u32) -> Result<Vec<Vec<(f32, f32)>>, String> {
+        if depth > MAX_COMPOUND_DEPTH {
+            return Err("TTF compound glyphs are nested too deeply.".to_string());
+        }
+        let (offset, length) = try!(self.get_glyph_range(glyph));
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        let num_contours = try!(read_i16(&self.data, offset));
+        if num_contours < 0 {
+            self.get_compound_contours(offset, depth)
+        } else {
+            self.get_simple_contours(offset, num_contours as usize)
+        }
+    }
+
+    // Reads the outline of a simple glyph.
+    fn get_simple_contours(&self, offset: usize, num_contours: usize)
+            -> Result<Vec<Vec<(f32, f32)>>, String> {
+        let data = &self.data;
+        let mut end_points = Vec::with_capacity(num_contours);
+        for i in 0..num_contours {
+            end_points.push(try!(read_u16(data, offset + 10 + 2 * i)) as usize);
+        }
+        let num_points = match end_points.last() {
+            Some(last) => last + 1,
+            None => { return Ok(Vec::new()); },
+        };
+        let instructions = try!(read_u16(data, offset + 10 + 2 * num_contours)) as usize;
+        let mut cursor = offset + 12 + 2 * num_contours + instructions;
+
+        let mut flags = Vec::with_capacity(num_points);
+        while flags.len() < num_points {
+            let flag = try!(read_u8(data, cursor));
+            cursor += 1;
+            flags.push(flag);
+            if flag & 8 != 0 {
+                let repeat = try!(read_u8(data, cursor));
+                cursor += 1;
+                for _ in 0..repeat { flags.push(flag); }
+            }
+        }
+        flags.truncate(num_points);
+
+        let xs = try!(read_coordinates(data, &mut cursor, &flags, 2, 16));
+        let ys = try!(read_coordinates(data, &mut cursor, &flags, 4, 32));
+
+        let mut contours = Vec::with_capacity(num_contours);
+        let mut start = 0;
+        for end in end_points {
+            if end < start || end >= num_points {
+                return Err("TTF glyph has invalid contour end points.".to_string());
+            }
+            let points: Vec<_> = (start..(end + 1)).map(|i| (xs[i], ys[i], flags[i] & 1 != 0))
+                    .collect();
+            contours.push(flatten_contour(&points));
+            start = end + 1;
+        }
+        Ok(contours)
+    }
+
+    // Reads the outline of a compound glyph by transforming the outlines of its components. Only
+    // components positioned with x and y offsets are supported, so offsets given by matching point
+    // numbers are ignored.
+    fn get_compound_contours(&self, offset: usize, depth: u32)
+            -> Result<Vec<Vec<(f32, f32)>>, String> {
+        let data = &self.data;
+        let mut contours = Vec::new();
+        let mut cursor = offset + 10;
+        loop {
+            let flags = try!(read_u16(data, cursor));
+            let component = try!(read_u16(data, cursor + 2));
+            cursor += 4;
+            let (arg1, arg2) = if flags & 0x1 != 0 {
+                cursor += 4;
+                (try!(read_i16(data, cursor - 4)) as f32, try!(read_i16(data, cursor - 2)) as f32)
+            } else {
+                cursor += 2;
+                (try!(read_u8(data, cursor - 2)) as i8 as f32,
+                        try!(read_u8(data, cursor - 1)) as i8 as f32)
+            };
+            let (dx, dy) = if flags & 0x2 != 0 { (arg1, arg2) } else { (0.0, 0.0) };
+            let (mut a, mut b, mut c, mut d) = (1.0, 0.0, 0.0, 1.0);
+            if flags & 0x8 != 0 {
+                a = try!(read_f2dot14(data, cursor));
+                d = a;
+                cursor += 2;
+            } else if flags & 0x40 != 0 {
+                a = try!(read_f2dot14(data, cursor));
+                d = try!(read_f2dot14(data, cursor + 2));
+                cursor += 4;
+            } else if flags & 0x80 != 0 {
+                a = try!(read_f2dot14(data, cursor));
+                b = try!(read_f2dot14(data, cursor + 2));
+                c = try!(read_f2dot14(data, cursor + 4));
+                d = try!(read_f2dot14(data, cursor + 6));
+                cursor += 8;
+            }
+            for contour in try!(self.get_contours(component, depth + 1)) {
+                contours.push(contour.iter().map(|&(x, y)| (a * x + c * y + dx, b * x + d * y + dy))
+                        .collect());
+            }
+            if flags & 0x20 == 0 { break; }
+        }
+        Ok(contours)
+    }
+
+    // Rasterizes a character at an em size in pixels. Characters without an outline, such as
+    // spaces, give an empty bitmap with only an advance. Returns None if the font does not have
+    // the character.
+    pub fn rasterize(&self, c: char, pixel_size: f32) -> Result<Option<RasterizedGlyph>, String> {
+        let glyph = match self.get_glyph(c) {
+            Some(g) => g,
+            None => { return Ok(None); },
+        };
+        let scale = self.get_scale(pixel_size);
+        let advance = try!(self.get_advance(glyph)) as f32 * scale;
+        let contours = try!(self.get_contours(glyph, 0));
+        let points = contours.iter().flat_map(|c| c.iter());
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0f32, 0.0f32, 0.0f32, 0.0f32);
+        for (i, &(x, y)) in points.enumerate() {
+            if i == 0 {
+                min_x = x; max_x = x; min_y = y; max_y = y;
+            }
+            min_x = min_x.min(x); max_x = max_x.max(x);
+            min_y = min_y.min(y); max_y = max_y.max(y);
+        }
+        if contours.iter().all(|c| c.is_empty()) {
+            return Ok(Some(RasterizedGlyph { width: 0, height: 0, left: 0, top: 0,
+                    advance: advance, coverage: Vec::new() }));
+        }
+
+        // Pad the bitmap by a pixel on each side so that antialiased edges are not clipped.
+        let left = (min_x * scale).floor() as i32 - 1;
+        let top = (max_y * scale).ceil() as i32 + 1;
+        let width = ((max_x * scale).ceil() as i32 + 1 - left) as usize;
+        let height = (top - (min_y * scale).floor() as i32 + 1) as usize;
+        if width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+            return Err("TTF glyph is too large to rasterize.".to_string());
+        }
+        let mut rasterizer = Rasterizer::new(width, height);
+        for contour in &contours {
+            for i in 1..contour.len() {
+                let (x0, y0) = contour[i - 1];
+                let (x1, y1) = contour[i];
+                rasterizer.line((x0 * scale - left as f32, top as f32 - y0 * scale),
+                        (x1 * scale - left as f32, top as f32 - y1 * scale));
+            }
+        }
+        Ok(Some(RasterizedGlyph { width: width as u32, height: height as u32, left: left,
+                top: top, advance: advance, coverage: rasterizer.coverage() }))
+    }
+}
+
+// Loads a TrueType font given a path.
+pub fn decode_ttf(fpath: &str) -> Result<TrueTypeFont, String> {
+    let mut file = match File::open(fpath) {
+        Ok(f) => f,
+        Err(_) => { return Err("Could not open TTF file.".to_string()); },
+    };
+    let mut data = Vec::new();
+    if file.read_to_end(&mut data).is_err() {
+        return Err("Could not read TTF file.".to_string());
+    }
+    let version = try!(read_u32(&data, 0));
+    if version != 0x00010000 && version != 0x74727565 {
+        return Err("TTF file does not contain TrueType outlines.".to_string());
+    }
+
+    let head = try!(require_table(&data, "head"));
+    let hhea = try!(require_table(&data, "hhea"));
+    let maxp = try!(require_table(&data, "maxp"));
+    let cmap = try!(require_table(&data, "cmap"));
+    let units_per_em = try!(read_u16(&data, head + 18));
+    if units_per_em == 0 {
+        return Err("TTF file has zero units per em.".to_string());
+    }
+    let num_h_metrics = try!(read_u16(&data, hhea + 34));
+    if num_h_metrics == 0 {
+        return Err("TTF file has no horizontal metrics.".to_string());
+    }
+    let glyph_map = try!(read_cmap(&data, cmap));
+    let kernings = match try!(find_table(&data, b"kern")) {
+        Some(kern) => try!(read_kern(&data, kern)),
+        None => HashMap::new(),
+    };
+    Ok(TrueTypeFont {
+            units_per_em: units_per_em,
+            ascender: try!(read_i16(&data, hhea + 4)),
+            descender: try!(read_i16(&data, hhea + 6)),
+            line_gap: try!(read_i16(&data, hhea + 8)),
+            glyf: try!(require_table(&data, "glyf")),
+            loca: try!(require_table(&data, "loca")),
+            hmtx: try!(require_table(&data, "hmtx")),
+            num_glyphs: try!(read_u16(&data, maxp + 4)),
+            num_h_metrics: num_h_metrics,
+            long_loca: try!(read_i16(&data, head + 50)) != 0,
+            glyph_map: glyph_map,
+            kernings: kernings,
+            data: data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Appends big endian u16 values to the data vector.
+    fn write_u16s(data: &mut Vec<u8>, values: &[u16]) {
+        for value in values {
+            data.push((value >> 8) as u8);
+            data.push(*value as u8);
+        }
+    }
+
+    // Builds a font with a single glyph for 'A', which is a square with sides of a given length in
+    // font units, along with a kerning pair between 'A' and itself.
+    fn ttf_file(units_per_em: u16, size: i16) -> Vec<u8> {
+        let mut head = vec![0; 54];
+        head[18] = (units_per_em >> 8) as u8;
+        head[19] = units_per_em as u8;
+        let mut hhea = vec![0; 34];
+        write_u16s(&mut hhea, &[2]);
+        let mut maxp = Vec::new();
+        write_u16s(&mut maxp, &[0, 0, 2]);
+        let mut cmap = Vec::new();
+        write_u16s(&mut cmap, &[0, 1, 3, 1, 0, 12]);
+        write_u16s(&mut cmap, &[4, 32, 0, 4, 0, 0, 0, 'A' as u16, 0xFFFF, 0, 'A' as u16, 0xFFFF,
+                1u16.wrapping_sub('A' as u16), 1, 0, 0]);
+        let mut hmtx = Vec::new();
+        write_u16s(&mut hmtx, &[500, 0, 600, 0]);
+        let mut glyf = Vec::new();
+        let size = size as u16;
+        write_u16s(&mut glyf, &[1, 0, 0, size, size, 3, 0]);
+        glyf.extend_from_slice(&[1, 1, 1, 1]);
+        write_u16s(&mut glyf, &[0, size, 0, 0u16.wrapping_sub(size), 0, 0, size, 0]);
+        let mut loca = Vec::new();
+        write_u16s(&mut loca, &[0, 0, glyf.len() as u16 / 2]);
+        let mut kern = Vec::new();
+        write_u16s(&mut kern, &[0, 1, 0, 20, 1, 1, 6, 0, 0, 1, 1, 0u16.wrapping_sub(50)]);
+
+        let tables: Vec<(&[u8], Vec<u8>)> = vec![(b"head", head), (b"hhea", hhea),
+                (b"maxp", maxp), (b"cmap", cmap), (b"hmtx", hmtx), (b"loca", loca),
+                (b"glyf", glyf), (b"kern", kern)];
+        let mut data = Vec::new();
+        write_u16s(&mut data, &[1, 0, tables.len() as u16, 0, 0, 0]);
+        let mut offset = 12 + 16 * tables.len();
+        for &(tag, ref table) in &tables {
+            data.extend_from_slice(tag);
+            write_u16s(&mut data, &[0, 0, (offset >> 16) as u16, offset as u16, 0,
+                    table.len() as u16]);
+            offset += (table.len() + 3) / 4 * 4;
+        }
+        for &(_, ref table) in &tables {
+            data.extend_from_slice(table);
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn rasterizes_glyphs() {
+        let path = common::write_test_file("square.ttf", &ttf_file(1000, 1000));
+        let font = decode_ttf(&path).unwrap();
+        assert_eq!(font.units_per_em, 1000);
+        assert_eq!(font.get_glyph('A'), Some(1));
+        assert_eq!(font.get_glyph('B'), None);
+        assert_eq!(font.get_kerning('A', 'A'), -50);
+        let glyph = font.rasterize('A', 10.0).unwrap().unwrap();
+        assert_eq!((glyph.width, glyph.height, glyph.left, glyph.top), (12, 12, -1, 11));
+        assert_eq!(glyph.advance, 6.0);
+        assert_eq!(glyph.coverage[6 * 12 + 6], 255);
+        assert_eq!(glyph.coverage[0], 0);
+        assert!(font.rasterize('B', 10.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let data = ttf_file(1000, 1000);
+        for &length in [10, 100, data.len() - 20].iter() {
+            let path = common::write_test_file("truncated.ttf", &data[..length]);
+            assert!(decode_ttf(&path).and_then(|font| font.rasterize('A', 10.0)).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_zero_units_per_em() {
+        let path = common::write_test_file("empty.ttf", &ttf_file(0, 1000));
+        assert!(decode_ttf(&path).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_glyphs() {
+        let path = common::write_test_file("oversized.ttf", &ttf_file(16, 32000));
+        let font = decode_ttf(&path).unwrap();
+        assert!(font.rasterize('A', 64.0).is_err());
+    }
+}
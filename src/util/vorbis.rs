@@ -0,0 +1,1073 @@
+// Utility module that decodes Vorbis audio, the codec used by Ogg Vorbis files. A VorbisDecoder is
+// created from the three header packets at the start of a stream and then turns each audio packet
+// into samples. Getting the packets out of the file is left to util/ogg.rs. Only floor type 1 is
+// supported, since floor type 0 has not been used by any encoder in years.
+//
+// Each audio packet holds a block of the spectrum of the sound as a coarse curve (the floor) along
+// with the detail on top of it (the residue). The decoder rebuilds the spectrum, transforms it into
+// samples with an inverse MDCT, and overlaps the windowed block with the previous one, so a packet
+// only gives samples once there is a previous packet to overlap it with. After a seek, reset()
+// forgets the previous packet so that the next one is only used for the overlap.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::cmp;
+use std::f64::consts::PI;
+
+// Gains for each step of the floor curve, which covers 140 dB in 256 steps.
+const FLOOR1_DB_RANGE: f64 = 140.0;
+const FLOOR1_STEPS: usize = 256;
+
+// The range of floor 1 Y values for each multiplier.
+const FLOOR1_RANGES: [i32; 4] = [256, 128, 86, 64];
+
+// Limits from the specification.
+const MAX_FLOOR1_VALUES: usize = 65;
+const CODEBOOK_SYNC: u32 = 0x564342;
+
+// Reads bits from a packet starting from the least significant bit of each byte, the way Vorbis
+// packs them. Reading past the end of the packet gives zeros and sets the end of packet flag, which
+// ends decoding of an audio packet early without it being an error.
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+    eop: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data: data, bit: 0, eop: false }
+    }
+
+    // Reads an unsigned integer of up to 32 bits.
+    fn read(&mut self, bits: u32) -> u32 {
+        let mut value = 0;
+        for i in 0..bits {
+            let byte = self.bit >> 3;
+            if byte >= self.data.len() {
+                self.eop = true;
+                return 0;
+            }
+            value |= (((self.data[byte] >> (self.bit & 7)) & 1) as u32) << i;
+            self.bit += 1;
+        }
+        value
+    }
+
+    // Reads a single bit as a flag.
+    fn read_flag(&mut self) -> bool {
+        self.read(1) == 1
+    }
+}
+
+// Gets the number of bits needed to store a value.
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+// Unpacks the 32 bit floating point format of codebooks, which isn't IEEE 754.
+fn unpack_float(value: u32) -> f32 {
+    let mantissa = (value & 0x1fffff) as f64;
+    let exponent = ((value & 0x7fe00000) >> 21) as i32 - 788;
+    let unpacked = mantissa * 2f64.powi(exponent);
+    if value & 0x80000000 != 0 { -unpacked as f32 } else { unpacked as f32 }
+}
+
+// Gets the largest number of values per dimension whose vectors all fit in a number of entries.
+fn lookup1_values(entries: usize, dimensions: usize) -> usize {
+    let fits = |values: usize| {
+        let mut total: u64 = 1;
+        for _ in 0..dimensions {
+            total = total.saturating_mul(values as u64);
+        }
+        total <= entries as u64
+    };
+    let mut values = (entries as f64).powf(1.0 / dimensions as f64).floor() as usize;
+    while fits(values + 1) {
+        values += 1;
+    }
+    while values > 0 && !fits(values) {
+        values -= 1;
+    }
+    values
+}
+
+// Checks the type and the "vorbis" signature at the start of a header packet.
+fn read_header_start(reader: &mut BitReader, kind: u32) -> Result<(), String> {
+    let mut signature = [0; 6];
+    let found = reader.read(8);
+    for byte in signature.iter_mut() {
+        *byte = reader.read(8) as u8;
+    }
+    if found != kind || &signature != b"vorbis" {
+        return Err("Vorbis header has incorrect magic values.".to_string());
+    }
+    Ok(())
+}
+
+// A Huffman codebook, optionally with a vector of values for each entry. The tree is stored as
+// pairs of children for the 0 and 1 bits, where a positive child is the index of another node, a
+// negative child is the leaf for entry -(child + 1), and 0 is a codeword that doesn't exist.
+struct Codebook {
+    dimensions: usize,
+    tree: Vec<[i32; 2]>,
+    values: Vec<f32>,
+}
+
+impl Codebook {
+    // Reads a codebook from the setup header.
+    fn read(reader: &mut BitReader) -> Result<Codebook, String> {
+        if reader.read(24) != CODEBOOK_SYNC {
+            return Err("Vorbis codebook has an incorrect sync pattern.".to_string());
+        }
+        let dimensions = reader.read(16) as usize;
+        let entries = reader.read(24) as usize;
+        if reader.eop {
+            return Err("Vorbis setup header is too small.".to_string());
+        }
+
+        // Lengths of 0 mark entries that are never used.
+        let mut lengths = vec![0; entries];
+        if reader.read_flag() {
+            let mut entry = 0;
+            let mut length = reader.read(5) + 1;
+            while entry < entries {
+                let count = reader.read(ilog((entries - entry) as u32)) as usize;
+                if entry + count > entries || length > 32 || reader.eop {
+                    return Err("Vorbis codebook has invalid lengths.".to_string());
+                }
+                for l in &mut lengths[entry..(entry + count)] {
+                    *l = length;
+                }
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = reader.read_flag();
+            for length in lengths.iter_mut() {
+                if !sparse || reader.read_flag() {
+                    *length = reader.read(5) + 1;
+                }
+                if reader.eop {
+                    return Err("Vorbis setup header is too small.".to_string());
+                }
+            }
+        }
+
+        let lookup_type = reader.read(4);
+        let values = match lookup_type {
+            0 => Vec::new(),
+            1 | 2 => {
+                if dimensions == 0 {
+                    return Err("Vorbis codebook has vectors without dimensions.".to_string());
+                }
+                let minimum = unpack_float(reader.read(32));
+                let delta = unpack_float(reader.read(32));
+                let value_bits = reader.read(4) + 1;
+                let sequence = reader.read_flag();
+                let count = if lookup_type == 1 {
+                    lookup1_values(entries, dimensions)
+                } else {
+                    entries * dimensions
+                };
+                let mut multiplicands = Vec::with_capacity(count);
+                for _ in 0..count {
+                    multiplicands.push(reader.read(value_bits) as f32);
+                }
+                if reader.eop || count == 0 {
+                    return Err("Vorbis codebook has invalid vectors.".to_string());
+                }
+                unpack_vectors(&multiplicands, lookup_type, entries, dimensions, minimum, delta,
+                        sequence)
+            },
+            _ => return Err(format!("Unsupported Vorbis codebook lookup type: {}.", lookup_type)),
+        };
+        Ok(Codebook { dimensions: dimensions, tree: try!(build_tree(&lengths)), values: values })
+    }
+
+    // Reads a codeword and returns its entry, or None at the end of the packet or if the codeword
+    // doesn't exist.
+    fn decode(&self, reader: &mut BitReader) -> Option<usize> {
+        let mut node = 0;
+        loop {
+            let child = self.tree[node][reader.read(1) as usize];
+            if reader.eop || child == 0 {
+                return None;
+            } else if child < 0 {
+                return Some((-child - 1) as usize);
+            }
+            node = child as usize;
+        }
+    }
+
+    // Reads a codeword and returns the vector of its entry.
+    fn decode_vector(&self, reader: &mut BitReader) -> Option<&[f32]> {
+        let entry = match self.decode(reader) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        let start = entry * self.dimensions;
+        self.values.get(start..(start + self.dimensions))
+    }
+}
+
+// Works out the vector of every entry of a codebook from its multiplicands.
+fn unpack_vectors(multiplicands: &[f32], lookup_type: u32, entries: usize, dimensions: usize,
+        minimum: f32, delta: f32, sequence: bool) -> Vec<f32> {
+    let mut values = Vec::with_capacity(entries * dimensions);
+    for entry in 0..entries {
+        let mut last = 0.0;
+        let mut divisor = 1;
+        for i in 0..dimensions {
+            // Type 1 lookups treat the entry as a number in base multiplicands.len() whose digits
+            // pick the value of each dimension, while type 2 lookups list every value.
+            let offset = if lookup_type == 1 {
+                let offset = (entry / divisor) % multiplicands.len();
+                divisor = divisor.saturating_mul(multiplicands.len());
+                offset
+            } else {
+                entry * dimensions + i
+            };
+            let value = multiplicands[offset] * delta + minimum + last;
+            if sequence {
+                last = value;
+            }
+            values.push(value);
+        }
+    }
+    values
+}
+
+// Builds the Huffman tree for codeword lengths. Each entry takes the leftmost free codeword of its
+// length in order, which is how the encoder assigns them.
+fn build_tree(lengths: &[u32]) -> Result<Vec<[i32; 2]>, String> {
+    let mut tree = vec![[0, 0]];
+    let mut full = vec![false];
+    let mut used = 0;
+    for (entry, &length) in lengths.iter().enumerate() {
+        if length == 0 { continue; }
+        if !insert_codeword(&mut tree, &mut full, 0, 1, length, entry) {
+            return Err("Vorbis codebook has too many codewords.".to_string());
+        }
+        used += 1;
+    }
+    // A codebook with a single codeword decodes it from either bit.
+    if used == 1 && tree[0][0] < 0 {
+        tree[0][1] = tree[0][0];
+    }
+    Ok(tree)
+}
+
+// Places an entry in the leftmost free spot at a depth under a node. Returns false if there is no
+// free spot, and marks nodes that are full so that they aren't searched again.
+fn insert_codeword(tree: &mut Vec<[i32; 2]>, full: &mut Vec<bool>, node: usize, depth: u32,
+        length: u32, entry: usize) -> bool {
+    if full[node] { return false; }
+    for bit in 0..2 {
+        let child = tree[node][bit];
+        if depth == length {
+            if child != 0 { continue; }
+            tree[node][bit] = -(entry as i32) - 1;
+        } else {
+            if child < 0 { continue; }
+            let next = if child == 0 {
+                tree.push([0, 0]);
+                full.push(false);
+                tree[node][bit] = (tree.len() - 1) as i32;
+                tree.len() - 1
+            } else {
+                child as usize
+            };
+            if !insert_codeword(tree, full, next, depth + 1, length, entry) { continue; }
+        }
+        let is_full = |child: i32| child < 0 || (child > 0 && full[child as usize]);
+        full[node] = is_full(tree[node][0]) && is_full(tree[node][1]);
+        return true;
+    }
+    false
+}
+
+// A floor of type 1, which describes the curve as a line through points whose X positions are
+// fixed and whose Y positions are read from each packet.
+struct Floor {
+    partition_classes: Vec<usize>,
+    class_dimensions: Vec<usize>,
+    class_subclasses: Vec<u32>,
+    class_masterbooks: Vec<usize>,
+    subclass_books: Vec<Vec<i32>>,
+    multiplier: i32,
+    xs: Vec<i32>,
+    sorted: Vec<usize>,                 // Indices of the X positions from left to right.
+    neighbors: Vec<(usize, usize)>,     // The closest points on either side of each point.
+}
+
+impl Floor {
+    // Reads a floor from the setup header.
+    fn read(reader: &mut BitReader, codebooks: &[Codebook]) -> Result<Floor, String> {
+        let kind = reader.read(16);
+        if kind != 1 {
+            return Err(format!("Unsupported Vorbis floor type: {}.", kind));
+        }
+        let partitions = reader.read(5) as usize;
+        let mut partition_classes = Vec::with_capacity(partitions);
+        for _ in 0..partitions {
+            partition_classes.push(reader.read(4) as usize);
+        }
+        let classes = partition_classes.iter().map(|&c| c + 1).max().unwrap_or(0);
+        let read_book = |reader: &mut BitReader| -> Result<usize, String> {
+            let book = reader.read(8) as usize;
+            if book >= codebooks.len() {
+                return Err("Vorbis floor uses a codebook that doesn't exist.".to_string());
+            }
+            Ok(book)
+        };
+        let mut class_dimensions = Vec::with_capacity(classes);
+        let mut class_subclasses = Vec::with_capacity(classes);
+        let mut class_masterbooks = Vec::with_capacity(classes);
+        let mut subclass_books = Vec::with_capacity(classes);
+        for _ in 0..classes {
+            class_dimensions.push(reader.read(3) as usize + 1);
+            let subclasses = reader.read(2);
+            class_subclasses.push(subclasses);
+            class_masterbooks.push(if subclasses > 0 { try!(read_book(reader)) } else { 0 });
+            let mut books = Vec::with_capacity(1 << subclasses);
+            for _ in 0..(1 << subclasses) {
+                // Books are stored plus one, with 0 for values that are always zero.
+                let book = reader.read(8) as i32 - 1;
+                if book >= codebooks.len() as i32 {
+                    return Err("Vorbis floor uses a codebook that doesn't exist.".to_string());
+                }
+                books.push(book);
+            }
+            subclass_books.push(books);
+        }
+        let multiplier = reader.read(2) as i32 + 1;
+        let range_bits = reader.read(4);
+        let mut xs = vec![0, 1 << range_bits];
+        for &class in &partition_classes {
+            for _ in 0..class_dimensions[class] {
+                xs.push(reader.read(range_bits) as i32);
+            }
+        }
+        if reader.eop {
+            return Err("Vorbis setup header is too small.".to_string());
+        }
+        if xs.len() > MAX_FLOOR1_VALUES {
+            return Err("Vorbis floor has too many points.".to_string());
+        }
+
+        let mut sorted: Vec<usize> = (0..xs.len()).collect();
+        sorted.sort_by_key(|&i| xs[i]);
+        if sorted.windows(2).any(|pair| xs[pair[0]] == xs[pair[1]]) {
+            return Err("Vorbis floor has points in the same place.".to_string());
+        }
+        let mut neighbors = vec![(0, 0); xs.len()];
+        for i in 2..xs.len() {
+            let (mut low, mut high) = (0, 1);
+            for j in 0..i {
+                if xs[j] < xs[i] && xs[j] > xs[low] { low = j; }
+                if xs[j] > xs[i] && xs[j] < xs[high] { high = j; }
+            }
+            neighbors[i] = (low, high);
+        }
+        Ok(Floor { partition_classes: partition_classes, class_dimensions: class_dimensions,
+                class_subclasses: class_subclasses, class_masterbooks: class_masterbooks,
+                subclass_books: subclass_books, multiplier: multiplier, xs: xs, sorted: sorted,
+                neighbors: neighbors })
+    }
+
+    // Reads the Y values of the floor for a channel, or None if the channel is unused in this
+    // packet.
+    fn decode(&self, reader: &mut BitReader, codebooks: &[Codebook]) -> Option<Vec<i32>> {
+        if !reader.read_flag() {
+            return None;
+        }
+        let bits = ilog(FLOOR1_RANGES[self.multiplier as usize - 1] as u32 - 1);
+        let mut ys = Vec::with_capacity(self.xs.len());
+        ys.push(reader.read(bits) as i32);
+        ys.push(reader.read(bits) as i32);
+        for &class in &self.partition_classes {
+            let bits = self.class_subclasses[class];
+            let mask = (1 << bits) - 1;
+            let mut value = if bits > 0 {
+                match codebooks[self.class_masterbooks[class]].decode(reader) {
+                    Some(value) => value,
+                    None => return None,
+                }
+            } else {
+                0
+            };
+            for _ in 0..self.class_dimensions[class] {
+                let book = self.subclass_books[class][value & mask];
+                value >>= bits;
+                ys.push(if book < 0 {
+                    0
+                } else {
+                    match codebooks[book as usize].decode(reader) {
+                        Some(y) => y as i32,
+                        None => return None,
+                    }
+                });
+            }
+        }
+        if reader.eop { None } else { Some(ys) }
+    }
+
+    // Works out the gains of the floor curve for the first n values of the spectrum from its Y
+    // values. Each Y value after the first two is stored as an offset from the line between its
+    // neighbors.
+    fn render(&self, ys: &[i32], n: usize, gains: &[f32]) -> Vec<f32> {
+        let range = FLOOR1_RANGES[self.multiplier as usize - 1];
+        let mut final_ys = ys.to_vec();
+        let mut used = vec![true; ys.len()];
+        for i in 2..ys.len() {
+            let (low, high) = self.neighbors[i];
+            let predicted = render_point(self.xs[low], final_ys[low], self.xs[high],
+                    final_ys[high], self.xs[i]);
+            let (high_room, low_room) = (range - predicted, predicted);
+            let room = cmp::min(high_room, low_room) * 2;
+            let value = ys[i];
+            if value == 0 {
+                used[i] = false;
+                final_ys[i] = predicted;
+                continue;
+            }
+            used[low] = true;
+            used[high] = true;
+            final_ys[i] = if value >= room {
+                if high_room > low_room {
+                    value - low_room + predicted
+                } else {
+                    predicted - value + high_room - 1
+                }
+            } else if value & 1 == 1 {
+                predicted - (value + 1) / 2
+            } else {
+                predicted + value / 2
+            };
+        }
+
+        let mut curve = vec![0; n];
+        let (mut lx, mut ly) = (0, final_ys[0] * self.multiplier);
+        let (mut hx, mut hy) = (0, ly);
+        for &i in &self.sorted[1..] {
+            if !used[i] { continue; }
+            hx = self.xs[i];
+            hy = final_ys[i] * self.multiplier;
+            render_line(lx, ly, hx, hy, &mut curve);
+            lx = hx;
+            ly = hy;
+        }
+        if (hx as usize) < n {
+            render_line(hx, hy, n as i32, hy, &mut curve);
+        }
+        curve.iter().map(|&y| gains[cmp::max(0, cmp::min(y, FLOOR1_STEPS as i32 - 1)) as usize])
+                .collect()
+    }
+}
+
+// Gets the Y value at an X position on the line between two points.
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let offset = dy.abs() * (x - x0) / (x1 - x0);
+    if dy < 0 { y0 - offset } else { y0 + offset }
+}
+
+// Draws the integer line between two points into the curve, leaving out the last point.
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, curve: &mut [i32]) {
+    let dy = y1 - y0;
+    let dx = x1 - x0;
+    if dx <= 0 { return; }
+    let base = dy / dx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * dx;
+    let (mut y, mut error) = (y0, 0);
+    for x in x0..cmp::min(x1, curve.len() as i32) {
+        if x > x0 {
+            error += ady;
+            if error >= dx {
+                error -= dx;
+                y += step;
+            } else {
+                y += base;
+            }
+        }
+        if x >= 0 {
+            curve[x as usize] = y;
+        }
+    }
+}
+
+// A residue, which adds detail to the spectrum in partitions. Each partition is given a class,
+// and the class picks a codebook for each of up to 8 passes over the partitions.
+struct Residue {
+    kind: u16,
+    begin: usize,
+    end: usize,
+    partition_size: usize,
+    classifications: usize,
+    classbook: usize,
+    books: Vec<[i32; 8]>,
+}
+
+impl Residue {
+    // Reads a residue from the setup header.
+    fn read(reader: &mut BitReader, codebooks: &[Codebook]) -> Result<Residue, String> {
+        let kind = reader.read(16) as u16;
+        if kind > 2 {
+            return Err(format!("Unsupported Vorbis residue type: {}.", kind));
+        }
+        let begin = reader.read(24) as usize;
+        let end = reader.read(24) as usize;
+        let partition_size = reader.read(24) as usize + 1;
+        let classifications = reader.read(6) as usize + 1;
+        let classbook = reader.read(8) as usize;
+        let mut cascades = Vec::with_capacity(classifications);
+        for _ in 0..classifications {
+            let low = reader.read(3);
+            let high = if reader.read_flag() { reader.read(5) } else { 0 };
+            cascades.push(high * 8 + low);
+        }
+        let mut books = Vec::with_capacity(classifications);
+        for cascade in cascades {
+            let mut passes = [-1; 8];
+            for (pass, book) in passes.iter_mut().enumerate() {
+                if cascade & (1 << pass) == 0 { continue; }
+                let index = reader.read(8) as usize;
+                match codebooks.get(index) {
+                    Some(codebook) if !codebook.values.is_empty() => *book = index as i32,
+                    _ => return Err("Vorbis residue uses an invalid codebook.".to_string()),
+                }
+            }
+            books.push(passes);
+        }
+        if reader.eop {
+            return Err("Vorbis setup header is too small.".to_string());
+        }
+        match codebooks.get(classbook) {
+            Some(codebook) if codebook.dimensions > 0 => {},
+            _ => return Err("Vorbis residue uses an invalid codebook.".to_string()),
+        }
+        Ok(Residue { kind: kind, begin: begin, end: end, partition_size: partition_size,
+                classifications: classifications, classbook: classbook, books: books })
+    }
+
+    // Adds the residue to the spectra of the channels of a submap, skipping the channels that
+    // aren't used. Type 2 residues are coded as one vector with the channels interleaved.
+    fn decode(&self, reader: &mut BitReader, codebooks: &[Codebook], spectra: &mut [Vec<f32>],
+            skip: &[bool]) {
+        if self.kind != 2 {
+            self.decode_vectors(reader, codebooks, spectra, skip);
+            return;
+        }
+        if skip.iter().all(|&skip| skip) { return; }
+        let channels = spectra.len();
+        let mut interleaved = vec![vec![0.0; spectra[0].len() * channels]];
+        self.decode_vectors(reader, codebooks, &mut interleaved, &[false]);
+        for (i, &value) in interleaved[0].iter().enumerate() {
+            spectra[i % channels][i / channels] = value;
+        }
+    }
+
+    // Adds the residue to each vector that isn't skipped. Reading stops at the end of the packet,
+    // which leaves the rest of the residue as zero.
+    fn decode_vectors(&self, reader: &mut BitReader, codebooks: &[Codebook],
+            vectors: &mut [Vec<f32>], skip: &[bool]) {
+        let size = match vectors.first() {
+            Some(vector) => vector.len(),
+            None => return,
+        };
+        let begin = cmp::min(self.begin, size);
+        let end = cmp::max(begin, cmp::min(self.end, size));
+        let partitions = (end - begin) / self.partition_size;
+        if partitions == 0 { return; }
+        let classbook = &codebooks[self.classbook];
+        let per_word = classbook.dimensions;
+        let mut classes = vec![vec![0; partitions + per_word]; vectors.len()];
+
+        for pass in 0..8 {
+            let mut partition = 0;
+            while partition < partitions {
+                // The first pass reads the classes of the next few partitions from one codeword.
+                if pass == 0 {
+                    for (j, classes) in classes.iter_mut().enumerate() {
+                        if skip[j] { continue; }
+                        let mut word = match classbook.decode(reader) {
+                            Some(word) => word,
+                            None => return,
+                        };
+                        for i in (0..per_word).rev() {
+                            classes[partition + i] = word % self.classifications;
+                            word /= self.classifications;
+                        }
+                    }
+                }
+                for _ in 0..per_word {
+                    if partition >= partitions { break; }
+                    for (j, vector) in vectors.iter_mut().enumerate() {
+                        if skip[j] { continue; }
+                        let book = self.books[classes[j][partition]][pass];
+                        if book < 0 { continue; }
+                        let offset = begin + partition * self.partition_size;
+                        let partition = &mut vector[offset..(offset + self.partition_size)];
+                        if !self.decode_partition(reader, &codebooks[book as usize], partition) {
+                            return;
+                        }
+                    }
+                    partition += 1;
+                }
+            }
+        }
+    }
+
+    // Adds the vectors of a partition. Type 0 residues interleave the values of each vector across
+    // the partition, while the others lay them out in order. Returns false at the end of the
+    // packet.
+    fn decode_partition(&self, reader: &mut BitReader, book: &Codebook, partition: &mut [f32])
+            -> bool {
+        let dimensions = book.dimensions;
+        if self.kind == 0 {
+            let step = partition.len() / dimensions;
+            for i in 0..step {
+                let vector = match book.decode_vector(reader) {
+                    Some(vector) => vector,
+                    None => return false,
+                };
+                for (j, &value) in vector.iter().enumerate() {
+                    partition[i + j * step] += value;
+                }
+            }
+        } else {
+            let mut i = 0;
+            while i < partition.len() {
+                let vector = match book.decode_vector(reader) {
+                    Some(vector) => vector,
+                    None => return false,
+                };
+                for &value in vector {
+                    if i >= partition.len() { break; }
+                    partition[i] += value;
+                    i += 1;
+                }
+            }
+        }
+        true
+    }
+}
+
+// A mapping, which says which floor and residue each channel uses and which pairs of channels are
+// coupled together.
+struct Mapping {
+    couplings: Vec<(usize, usize)>,     // Pairs of magnitude and angle channels.
+    mux: Vec<usize>,                    // The submap of each channel.
+    submaps: Vec<(usize, usize)>,       // The floor and residue of each submap.
+}
+
+impl Mapping {
+    // Reads a mapping from the setup header.
+    fn read(reader: &mut BitReader, channels: usize, floors: usize, residues: usize)
+            -> Result<Mapping, String> {
+        if reader.read(16) != 0 {
+            return Err("Unsupported Vorbis mapping type.".to_string());
+        }
+        let submap_count = if reader.read_flag() { reader.read(4) as usize + 1 } else { 1 };
+        let mut couplings = Vec::new();
+        if reader.read_flag() {
+            let steps = reader.read(8) as usize + 1;
+            let bits = ilog(channels as u32 - 1);
+            for _ in 0..steps {
+                let (magnitude, angle) = (reader.read(bits) as usize, reader.read(bits) as usize);
+                if magnitude == angle || magnitude >= channels || angle >= channels {
+                    return Err("Vorbis mapping couples invalid channels.".to_string());
+                }
+                couplings.push((magnitude, angle));
+            }
+        }
+        if reader.read(2) != 0 {
+            return Err("Vorbis mapping has reserved bits set.".to_string());
+        }
+        let mut mux = vec![0; channels];
+        if submap_count > 1 {
+            for submap in mux.iter_mut() {
+                *submap = reader.read(4) as usize;
+                if *submap >= submap_count {
+                    return Err("Vorbis mapping uses a submap that doesn't exist.".to_string());
+                }
+            }
+        }
+        let mut submaps = Vec::with_capacity(submap_count);
+        for _ in 0..submap_count {
+            reader.read(8);
+            let (floor, residue) = (reader.read(8) as usize, reader.read(8) as usize);
+            if floor >= floors || residue >= residues {
+                return Err("Vorbis mapping uses a missing floor or residue.".to_string());
+            }
+            submaps.push((floor, residue));
+        }
+        Ok(Mapping { couplings: couplings, mux: mux, submaps: submaps })
+    }
+}
+
+// A mode, which picks the block size and mapping of a packet.
+#[derive(Copy, Clone)]
+struct Mode {
+    long: bool,
+    mapping: usize,
+}
+
+// Decodes the packets of a Vorbis stream.
+pub struct VorbisDecoder {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub vendor: String,
+    pub comments: Vec<String>,
+    block_sizes: [usize; 2],
+    codebooks: Vec<Codebook>,
+    floors: Vec<Floor>,
+    residues: Vec<Residue>,
+    mappings: Vec<Mapping>,
+    modes: Vec<Mode>,
+    slopes: [Vec<f32>; 2],              // The rising halves of the short and long windows.
+    floor_gains: Vec<f32>,
+    previous: Vec<Vec<f32>>,            // The windowed previous block of each channel.
+}
+
+impl VorbisDecoder {
+    // Creates a decoder from the identification, comment, and setup header packets.
+    pub fn new(identification: &[u8], comment: &[u8], setup: &[u8])
+            -> Result<VorbisDecoder, String> {
+        let mut reader = BitReader::new(identification);
+        try!(read_header_start(&mut reader, 1));
+        let version = reader.read(32);
+        let channels = reader.read(8) as u16;
+        let sample_rate = reader.read(32);
+        reader.read(32);
+        reader.read(32);
+        reader.read(32);
+        let block_sizes = [1 << reader.read(4), 1 << reader.read(4)];
+        if !reader.read_flag() || reader.eop || version != 0 {
+            return Err("Vorbis identification header is invalid.".to_string());
+        }
+        if channels == 0 || sample_rate == 0 {
+            return Err("Vorbis stream has no channels or sample rate.".to_string());
+        }
+        if block_sizes[0] < 64 || block_sizes[1] > 8192 || block_sizes[0] > block_sizes[1] {
+            return Err("Vorbis stream has invalid block sizes.".to_string());
+        }
+
+        let mut reader = BitReader::new(comment);
+        try!(read_header_start(&mut reader, 3));
+        let read_string = |reader: &mut BitReader| {
+            let length = reader.read(32) as usize;
+            let mut bytes = Vec::new();
+            while bytes.len() < length && !reader.eop {
+                bytes.push(reader.read(8) as u8);
+            }
+            String::from_utf8_lossy(&bytes).into_owned()
+        };
+        let vendor = read_string(&mut reader);
+        let mut comments = Vec::new();
+        for _ in 0..reader.read(32) {
+            if reader.eop { break; }
+            comments.push(read_string(&mut reader));
+        }
+        if reader.eop {
+            return Err("Vorbis comment header is too small.".to_string());
+        }
+
+        let mut reader = BitReader::new(setup);
+        try!(read_header_start(&mut reader, 5));
+        let mut codebooks = Vec::new();
+        for _ in 0..(reader.read(8) + 1) {
+            codebooks.push(try!(Codebook::read(&mut reader)));
+        }
+        for _ in 0..(reader.read(6) + 1) {
+            if reader.read(16) != 0 {
+                return Err("Vorbis setup header has an invalid time domain type.".to_string());
+            }
+        }
+        let mut floors = Vec::new();
+        for _ in 0..(reader.read(6) + 1) {
+            floors.push(try!(Floor::read(&mut reader, &codebooks)));
+        }
+        let mut residues = Vec::new();
+        for _ in 0..(reader.read(6) + 1) {
+            residues.push(try!(Residue::read(&mut reader, &codebooks)));
+        }
+        let mut mappings = Vec::new();
+        for _ in 0..(reader.read(6) + 1) {
+            mappings.push(try!(Mapping::read(&mut reader, channels as usize, floors.len(),
+                    residues.len())));
+        }
+        let mut modes = Vec::new();
+        for _ in 0..(reader.read(6) + 1) {
+            let long = reader.read_flag();
+            if reader.read(16) != 0 || reader.read(16) != 0 {
+                return Err("Vorbis mode has an invalid window or transform type.".to_string());
+            }
+            let mapping = reader.read(8) as usize;
+            if mapping >= mappings.len() {
+                return Err("Vorbis mode uses a mapping that doesn't exist.".to_string());
+            }
+            modes.push(Mode { long: long, mapping: mapping });
+        }
+        if !reader.read_flag() || reader.eop {
+            return Err("Vorbis setup header is invalid.".to_string());
+        }
+
+        let floor_gains = (0..FLOOR1_STEPS).map(|i| {
+            let db = (i as f64 - (FLOOR1_STEPS - 1) as f64) * FLOOR1_DB_RANGE / FLOOR1_STEPS as f64;
+            10f64.powf(db / 20.0) as f32
+        }).collect();
+        Ok(VorbisDecoder { sample_rate: sample_rate, channels: channels, vendor: vendor,
+                comments: comments, block_sizes: block_sizes, codebooks: codebooks,
+                floors: floors, residues: residues, mappings: mappings, modes: modes,
+                slopes: [get_slope(block_sizes[0] / 2), get_slope(block_sizes[1] / 2)],
+                floor_gains: floor_gains, previous: Vec::new() })
+    }
+
+    // Gets the value of a comment such as "TITLE=..." by its case insensitive name.
+    pub fn get_comment(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.comments.iter().find(|c| {
+            c.find('=').map_or(false, |i| c[..i].to_lowercase() == name)
+        }).map(|c| &c[(c.find('=').unwrap() + 1)..])
+    }
+
+    // Gets the size of long blocks in frames. Decoding has to start at least this far before a
+    // frame for the frame to come out right.
+    pub fn get_long_block_size(&self) -> usize {
+        self.block_sizes[1]
+    }
+
+    // Forgets the previous packet, such as after a seek.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+    }
+
+    // Decodes an audio packet and adds its interleaved samples to the end of a vector. Returns the
+    // number of frames added, which is 0 for the first packet and for packets that aren't audio.
+    pub fn decode(&mut self, packet: &[u8], samples: &mut Vec<f32>) -> Result<usize, String> {
+        let mut reader = BitReader::new(packet);
+        if reader.read_flag() || reader.eop {
+            return Ok(0);
+        }
+        let mode = match self.modes.get(reader.read(ilog(self.modes.len() as u32 - 1)) as usize) {
+            Some(mode) => *mode,
+            None => return Err("Vorbis packet uses a mode that doesn't exist.".to_string()),
+        };
+        let (previous_long, next_long) = if mode.long {
+            (reader.read_flag(), reader.read_flag())
+        } else {
+            (false, false)
+        };
+        if reader.eop {
+            return Ok(0);
+        }
+        let size = self.block_sizes[mode.long as usize];
+        let channels = self.channels as usize;
+        let spectra = self.decode_spectra(&mut reader, mode.mapping, size / 2);
+
+        let window = self.get_window(mode.long, previous_long, next_long);
+        let blocks: Vec<Vec<f32>> = spectra.iter().map(|spectrum| {
+            inverse_mdct(spectrum).iter().zip(&window).map(|(&s, &w)| s * w).collect()
+        }).collect();
+
+        // Each packet gives the samples between the middles of the previous and current blocks,
+        // where the right half of the previous block overlaps the left half of the current one.
+        let previous_size = self.previous.first().map_or(0, |block| block.len());
+        let frames = if previous_size == 0 { 0 } else { previous_size / 4 + size / 4 };
+        samples.reserve(frames * channels);
+        for i in 0..frames {
+            for (previous, block) in self.previous.iter().zip(&blocks) {
+                let mut sample = 0.0;
+                if previous_size / 2 + i < previous_size {
+                    sample += previous[previous_size / 2 + i];
+                }
+                if size / 4 + i >= previous_size / 4 {
+                    sample += block[size / 4 + i - previous_size / 4];
+                }
+                samples.push(sample);
+            }
+        }
+        self.previous = blocks;
+        Ok(frames)
+    }
+
+    // Reads the floors and residues of a packet and combines them into the spectrum of each
+    // channel.
+    fn decode_spectra(&self, reader: &mut BitReader, mapping: usize, half: usize)
+            -> Vec<Vec<f32>> {
+        let mapping = &self.mappings[mapping];
+        let channels = self.channels as usize;
+        let floor_ys: Vec<Option<Vec<i32>>> = (0..channels).map(|channel| {
+            let floor = mapping.submaps[mapping.mux[channel]].0;
+            self.floors[floor].decode(reader, &self.codebooks)
+        }).collect();
+
+        // Coupled channels both need their residues if either of them is used.
+        let mut unused: Vec<bool> = floor_ys.iter().map(|ys| ys.is_none()).collect();
+        for &(magnitude, angle) in &mapping.couplings {
+            if !unused[magnitude] || !unused[angle] {
+                unused[magnitude] = false;
+                unused[angle] = false;
+            }
+        }
+
+        let mut spectra = vec![vec![0.0; half]; channels];
+        for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+            let members: Vec<usize> = (0..channels).filter(|&c| mapping.mux[c] == submap)
+                    .collect();
+            let mut vectors: Vec<Vec<f32>> = members.iter().map(|&c| {
+                ::std::mem::replace(&mut spectra[c], Vec::new())
+            }).collect();
+            let skip: Vec<bool> = members.iter().map(|&c| unused[c]).collect();
+            self.residues[residue].decode(reader, &self.codebooks, &mut vectors, &skip);
+            for (&c, vector) in members.iter().zip(vectors) {
+                spectra[c] = vector;
+            }
+        }
+
+        for &(magnitude, angle) in mapping.couplings.iter().rev() {
+            for i in 0..half {
+                let (m, a) = (spectra[magnitude][i], spectra[angle][i]);
+                let (m, a) = if m > 0.0 {
+                    if a > 0.0 { (m, m - a) } else { (m + a, m) }
+                } else {
+                    if a > 0.0 { (m, m + a) } else { (m - a, m) }
+                };
+                spectra[magnitude][i] = m;
+                spectra[angle][i] = a;
+            }
+        }
+
+        for (channel, spectrum) in spectra.iter_mut().enumerate() {
+            match floor_ys[channel] {
+                Some(ref ys) => {
+                    let floor = &self.floors[mapping.submaps[mapping.mux[channel]].0];
+                    let curve = floor.render(ys, half, &self.floor_gains);
+                    for (value, gain) in spectrum.iter_mut().zip(curve) {
+                        *value *= gain;
+                    }
+                },
+                None => {
+                    for value in spectrum.iter_mut() {
+                        *value = 0.0;
+                    }
+                },
+            }
+        }
+        spectra
+    }
+
+    // Gets the window of a block. Long blocks next to short ones only overlap them over the length
+    // of a short block.
+    fn get_window(&self, long: bool, previous_long: bool, next_long: bool) -> Vec<f32> {
+        let size = self.block_sizes[long as usize];
+        let short = self.block_sizes[0];
+        let (left_start, left) = if long && !previous_long {
+            (size / 4 - short / 4, &self.slopes[0])
+        } else {
+            (0, &self.slopes[long as usize])
+        };
+        let (right_start, right) = if long && !next_long {
+            (size * 3 / 4 - short / 4, &self.slopes[0])
+        } else {
+            (size / 2, &self.slopes[long as usize])
+        };
+        let mut window = vec![0.0; size];
+        for (i, &value) in left.iter().enumerate() {
+            window[left_start + i] = value;
+        }
+        for value in &mut window[(left_start + left.len())..right_start] {
+            *value = 1.0;
+        }
+        for (i, &value) in right.iter().rev().enumerate() {
+            window[right_start + i] = value;
+        }
+        window
+    }
+}
+
+// Gets the rising half of the Vorbis window over a length.
+fn get_slope(length: usize) -> Vec<f32> {
+    (0..length).map(|i| {
+        let x = (i as f64 + 0.5) / length as f64 * PI / 2.0;
+        (PI / 2.0 * x.sin() * x.sin()).sin() as f32
+    }).collect()
+}
+
+// Transforms complex values in place with a radix 2 FFT. The length has to be a power of two.
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut size = 2;
+    while size <= n {
+        let half = size / 2;
+        for k in 0..half {
+            let angle = -2.0 * PI * k as f64 / size as f64;
+            let (wr, wi) = (angle.cos() as f32, angle.sin() as f32);
+            let mut a = k;
+            while a < n {
+                let b = a + half;
+                let (tr, ti) = (re[b] * wr - im[b] * wi, re[b] * wi + im[b] * wr);
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+                a += size;
+            }
+        }
+        size *= 2;
+    }
+}
+
+// Transforms a spectrum of n/2 values into a block of n samples. The transform is done as a DCT-IV
+// of the spectrum, which is computed with an FFT of a quarter of the size, and the block is then
+// unfolded from the DCT-IV's output.
+fn inverse_mdct(spectrum: &[f32]) -> Vec<f32> {
+    let m = spectrum.len();
+    let quarter = m / 2;
+    let mut re = vec![0.0; quarter];
+    let mut im = vec![0.0; quarter];
+    for k in 0..quarter {
+        let (a, b) = (spectrum[2 * k], spectrum[m - 1 - 2 * k]);
+        let angle = -PI * (4 * k + 1) as f64 / (4 * m) as f64;
+        let (c, s) = (angle.cos() as f32, angle.sin() as f32);
+        re[k] = a * c - b * s;
+        im[k] = a * s + b * c;
+    }
+    fft(&mut re, &mut im);
+    let mut dct = vec![0.0; m];
+    for j in 0..quarter {
+        let angle = -PI * j as f64 / m as f64;
+        let (c, s) = (angle.cos() as f32, angle.sin() as f32);
+        dct[2 * j] = re[j] * c - im[j] * s;
+        dct[m - 1 - 2 * j] = -(re[j] * s + im[j] * c);
+    }
+    let mut block = vec![0.0; 2 * m];
+    for (n, sample) in block.iter_mut().enumerate() {
+        let i = n + m / 2;
+        *sample = if i < m {
+            dct[i]
+        } else if i < 2 * m {
+            -dct[2 * m - 1 - i]
+        } else {
+            -dct[i - 2 * m]
+        };
+    }
+    block
+}
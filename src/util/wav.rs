@@ -0,0 +1,249 @@
+// Utility module that allows for decoding of a WAV file given a path to the file. Only
+// uncompressed PCM data is supported, as 8-bit unsigned, 16, 24, or 32-bit signed integer, or 32
+// or 64-bit floating point samples, with either a plain or an extensible format chunk. Compressed
+// formats such as ADPCM are rejected. Chunks other than the format and data chunks are skipped.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::fs::File;
+use std::io::Read;
+use util::common;
+
+// Return value for a decoded WAV file. This contains the sample rate, channel count, and samples
+// converted to floating point.
+pub struct DecodedWAV {
+    pub sound: common::Sound,
+}
+
+// Format codes for the sample formats that are supported.
+const FORMAT_PCM: u16 = 0x0001;
+const FORMAT_FLOAT: u16 = 0x0003;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// Reads n bytes from the data vector as a little endian unsigned integer without consuming them.
+fn peek_le(data: &[u8], cursor: usize, n: usize) -> Result<u64, String> {
+    if cursor + n > data.len() {
+        return Err("WAV file is too small.".to_string());
+    }
+    let mut value = 0;
+    for i in 0..n {
+        value |= (data[cursor + i] as u64) << (8 * i);
+    }
+    Ok(value)
+}
+
+// Reads and consumes 2 bytes from the data vector as a little endian u16.
+fn read_u16(data: &[u8], cursor: &mut usize) -> Result<u16, String> {
+    let value = try!(peek_le(data, *cursor, 2)) as u16;
+    *cursor += 2;
+    Ok(value)
+}
+
+// Reads and consumes 4 bytes from the data vector as a little endian u32.
+fn read_u32(data: &[u8], cursor: &mut usize) -> Result<u32, String> {
+    let value = try!(peek_le(data, *cursor, 4)) as u32;
+    *cursor += 4;
+    Ok(value)
+}
+
+// The contents of the format chunk that matter for decoding.
+struct Format {
+    code: u16,
+    channels: u16,
+    sample_rate: u32,
+    bits: u16,
+}
+
+// Parses the format chunk. An extensible format gives the real format code at the start of its
+// subformat GUID.
+fn read_format(chunk: &[u8]) -> Result<Format, String> {
+    let mut cursor = 0;
+    let mut code = try!(read_u16(chunk, &mut cursor));
+    let channels = try!(read_u16(chunk, &mut cursor));
+    let sample_rate = try!(read_u32(chunk, &mut cursor));
+    try!(read_u32(chunk, &mut cursor));
+    try!(read_u16(chunk, &mut cursor));
+    let bits = try!(read_u16(chunk, &mut cursor));
+    if code == FORMAT_EXTENSIBLE {
+        cursor += 8;
+        code = try!(read_u16(chunk, &mut cursor));
+    }
+    Ok(Format { code: code, channels: channels, sample_rate: sample_rate, bits: bits })
+}
+
+// Converts the sample data to floating point given its format.
+fn convert_samples(data: &[u8], format: &Format) -> Result<Vec<f32>, String> {
+    let bytes = (format.bits as usize + 7) / 8;
+    if bytes == 0 {
+        return Err("WAV file has no sample size.".to_string());
+    }
+    let count = data.len() / bytes;
+    let mut samples = Vec::with_capacity(count);
+    match (format.code, format.bits) {
+        (FORMAT_PCM, 8) => {
+            for &byte in data {
+                samples.push((byte as f32 - 128.0) / 128.0);
+            }
+        },
+        (FORMAT_PCM, 16) | (FORMAT_PCM, 24) | (FORMAT_PCM, 32) => {
+            // The samples are shifted up to fill an i32 so that the sign comes along.
+            let shift = 32 - format.bits as u32;
+            let scale = 1.0 / 2147483648.0;
+            for i in 0..count {
+                let raw = try!(peek_le(data, i * bytes, bytes)) as u32;
+                samples.push(((raw << shift) as i32) as f32 * scale);
+            }
+        },
+        (FORMAT_FLOAT, 32) => {
+            for i in 0..count {
+                let raw = try!(peek_le(data, i * bytes, bytes)) as u32;
+                samples.push(f32::from_bits(raw));
+            }
+        },
+        (FORMAT_FLOAT, 64) => {
+            for i in 0..count {
+                let raw = try!(peek_le(data, i * bytes, bytes));
+                samples.push(f64::from_bits(raw) as f32);
+            }
+        },
+        (FORMAT_PCM, _) | (FORMAT_FLOAT, _) => {
+            return Err(format!("Unsupported WAV sample size: {} bits.", format.bits));
+        },
+        _ => return Err(format!("Unsupported WAV format: 0x{:X}.", format.code)),
+    }
+    Ok(samples)
+}
+
+// Decodes a WAV given a path to the file and returns a DecodedWAV struct containing the samples,
+// sample rate, and number of channels of the sound.
+pub fn decode_wav(fpath: &str) -> Result<DecodedWAV, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+        return Err("WAV file header has incorrect magic values.".to_string());
+    }
+    let mut cursor = 12;
+    let mut format = None;
+    let mut samples = None;
+    while cursor + 8 <= data.len() && samples.is_none() {
+        let id = &data[cursor..(cursor + 4)];
+        cursor += 4;
+        let size = try!(read_u32(&data, &mut cursor)) as usize;
+        // Some writers leave the size of the data chunk unset when streaming, so a chunk that
+        // runs past the end is cut short instead of rejected.
+        let end = if size > data.len() - cursor { data.len() } else { cursor + size };
+        let chunk = &data[cursor..end];
+        if id == b"fmt " {
+            format = Some(try!(read_format(chunk)));
+        } else if id == b"data" {
+            let format = match format {
+                Some(ref format) => format,
+                None => return Err("WAV data chunk comes before the format chunk.".to_string()),
+            };
+            samples = Some(try!(convert_samples(chunk, format)));
+        }
+        // Chunks are padded to an even size.
+        cursor = end + (size & 1);
+    }
+
+    let format = match format {
+        Some(format) => format,
+        None => return Err("WAV file has no format chunk.".to_string()),
+    };
+    if format.channels == 0 || format.sample_rate == 0 {
+        return Err("WAV file has no channels or sample rate.".to_string());
+    }
+    let mut samples = match samples {
+        Some(samples) => samples,
+        None => return Err("WAV file has no data chunk.".to_string()),
+    };
+    let frames = samples.len() / format.channels as usize;
+    samples.truncate(frames * format.channels as usize);
+    Ok(DecodedWAV { sound: common::Sound { sample_rate: format.sample_rate,
+            channels: format.channels, samples: samples } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common;
+
+    // Appends a little endian value of n bytes to the data vector.
+    fn write_le(data: &mut Vec<u8>, value: u32, n: usize) {
+        for i in 0..n {
+            data.push((value >> (8 * i)) as u8);
+        }
+    }
+
+    // Builds a file with a plain format chunk followed by a data chunk of a given size.
+    fn wav_file(code: u16, channels: u16, sample_rate: u32, bits: u16, size: u32, samples: &[u8])
+            -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WAVEfmt ".to_vec();
+        write_le(&mut data, 16, 4);
+        write_le(&mut data, code as u32, 2);
+        write_le(&mut data, channels as u32, 2);
+        write_le(&mut data, sample_rate, 4);
+        write_le(&mut data, 0, 4);
+        write_le(&mut data, 0, 2);
+        write_le(&mut data, bits as u32, 2);
+        data.extend_from_slice(b"data");
+        write_le(&mut data, size, 4);
+        data.extend_from_slice(samples);
+        data
+    }
+
+    #[test]
+    fn decodes_pcm_samples() {
+        let data = wav_file(FORMAT_PCM, 2, 22050, 16, 8, &[0, 0, 0, 0x40, 0, 0x80, 0xFF, 0x7F]);
+        let path = common::write_test_file("pcm16.wav", &data);
+        let sound = decode_wav(&path).unwrap().sound;
+        assert_eq!((sound.sample_rate, sound.channels), (22050, 2));
+        assert_eq!(sound.get_frame_count(), 2);
+        assert_eq!(&sound.samples[0..3], &[0.0, 0.5, -1.0]);
+        assert!(sound.samples[3] > 0.999);
+
+        let data = wav_file(FORMAT_PCM, 1, 8000, 8, 2, &[128, 0]);
+        let path = common::write_test_file("pcm8.wav", &data);
+        assert_eq!(decode_wav(&path).unwrap().sound.samples, vec![0.0, -1.0]);
+    }
+
+    #[test]
+    fn decodes_float_samples() {
+        let mut samples = Vec::new();
+        write_le(&mut samples, (0.25f32).to_bits(), 4);
+        let data = wav_file(FORMAT_FLOAT, 1, 44100, 32, 4, &samples);
+        let path = common::write_test_file("float.wav", &data);
+        assert_eq!(decode_wav(&path).unwrap().sound.samples, vec![0.25]);
+    }
+
+    #[test]
+    fn cuts_short_truncated_data() {
+        // The data chunk claims far more than the file holds, and the last frame is incomplete.
+        let data = wav_file(FORMAT_PCM, 2, 22050, 16, 0xFFFFFFFF, &[0, 0x40, 0, 0x40, 0, 0x40]);
+        let path = common::write_test_file("truncated.wav", &data);
+        let sound = decode_wav(&path).unwrap().sound;
+        assert_eq!(sound.samples, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = wav_file(FORMAT_PCM, 2, 22050, 16, 0, &[]);
+        for &length in [4, 12, 30].iter() {
+            let path = common::write_test_file("header.wav", &data[..length]);
+            assert!(decode_wav(&path).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_zero_channels() {
+        let path = common::write_test_file("empty.wav", &wav_file(FORMAT_PCM, 0, 22050, 16, 2,
+                &[0, 0]));
+        assert!(decode_wav(&path).is_err());
+        let path = common::write_test_file("unsized.wav", &wav_file(FORMAT_PCM, 1, 22050, 0, 2,
+                &[0, 0]));
+        assert!(decode_wav(&path).is_err());
+    }
+}